@@ -0,0 +1,304 @@
+use crate::dns::{record_tag, DnsResolver};
+use crate::export::value_string;
+use crate::psl::organizational_domain;
+use crate::report::{DkimResultType, Report, SpfResultType};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Maximum number of failing sources with a suggestion per domain
+const MAX_FAILING_SOURCES: usize = 5;
+
+/// Kind of a suggestion, stable for filtering in clients
+#[derive(Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AdviceKind {
+    PublishRecord,
+    AddReportingAddress,
+    RaisePolicy,
+    RaisePercentage,
+    AddSpfInclude,
+    AlignDkim,
+    AuthorizeSource,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Suggestion {
+    pub kind: AdviceKind,
+    pub message: String,
+}
+
+/// Suggestions for the DMARC setup of a domain
+#[derive(Serialize, ToSchema)]
+pub struct DomainAdvice {
+    pub domain: String,
+
+    /// DMARC record in DNS, empty if none is published
+    pub record: Option<String>,
+
+    /// Messages within the window the suggestions are based on
+    pub messages: usize,
+    pub passed: usize,
+
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// Messages of a domain within the window, collected from the reports
+#[derive(Default)]
+pub struct DomainData {
+    messages: usize,
+    passed: usize,
+    sources: HashSet<IpAddr>,
+
+    /// Published policy of the newest report, used without DMARC record in DNS
+    policy: String,
+    policy_begin: u64,
+
+    /// Messages failing DMARC per source IP that could not be assigned to a provider
+    failing: HashMap<IpAddr, usize>,
+
+    /// Messages failing DMARC per unaligned domain with passing SPF
+    spf_providers: BTreeMap<String, usize>,
+
+    /// Messages failing DMARC per unaligned domain with passing DKIM signature
+    dkim_providers: BTreeMap<String, usize>,
+}
+
+/// Collect the messages of all reports ending within the window before now per domain
+pub fn collect(reports: &[Report], now: u64, window: u64) -> BTreeMap<String, DomainData> {
+    let begin = now.saturating_sub(window);
+    let mut domains: BTreeMap<String, DomainData> = BTreeMap::new();
+    for report in reports {
+        let range = &report.report_metadata.date_range;
+        if range.end <= begin || range.end > now {
+            continue;
+        }
+        let domain = report.policy_published.domain.to_lowercase();
+        let org_domain = organizational_domain(&domain);
+        let data = domains.entry(domain).or_default();
+        if range.begin >= data.policy_begin {
+            data.policy = format!("p={}", value_string(&report.policy_published.p));
+            if let Some(pct) = report.policy_published.pct {
+                data.policy.push_str(&format!("; pct={pct}"));
+            }
+            data.policy_begin = range.begin;
+        }
+        for record in &report.record {
+            let count = record.row.count;
+            data.messages += count;
+            data.sources.insert(record.row.source_ip);
+            if record.is_dmarc_pass() {
+                data.passed += count;
+                continue;
+            }
+            let unaligned = |d: &str| organizational_domain(d) != org_domain;
+            let auth = &record.auth_results;
+            let spf: HashSet<String> = auth
+                .spf
+                .iter()
+                .filter(|r| r.result == SpfResultType::Pass && unaligned(&r.domain))
+                .map(|r| r.domain.to_lowercase())
+                .collect();
+            let dkim: HashSet<String> = auth
+                .dkim
+                .iter()
+                .flatten()
+                .filter(|r| r.result == DkimResultType::Pass && unaligned(&r.domain))
+                .map(|r| r.domain.to_lowercase())
+                .collect();
+            if spf.is_empty() && dkim.is_empty() {
+                *data.failing.entry(record.row.source_ip).or_default() += count;
+            }
+            for provider in spf {
+                *data.spf_providers.entry(provider).or_default() += count;
+            }
+            for provider in dkim {
+                *data.dkim_providers.entry(provider).or_default() += count;
+            }
+        }
+    }
+    domains
+}
+
+/// Check the DMARC record in DNS and name the failing sources to create the advice for a domain
+pub async fn domain_advice(
+    dns: &DnsResolver,
+    domain: String,
+    data: DomainData,
+    days: u64,
+) -> DomainAdvice {
+    let mut suggestions = Vec::new();
+    let record = match dns.txt(&format!("_dmarc.{domain}")).await {
+        Ok(records) => {
+            let record = records
+                .into_iter()
+                .find(|r| r.trim_start().starts_with("v=DMARC1"));
+            suggestions.extend(record_suggestion(&domain, record.as_deref()));
+            record
+        }
+        Err(err) => {
+            warn!("Failed to look up DMARC record for advice: {err:#}");
+            None
+        }
+    };
+    let mut failing: Vec<(IpAddr, usize)> = data.failing.iter().map(|(ip, n)| (*ip, *n)).collect();
+    failing.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    failing.truncate(MAX_FAILING_SOURCES);
+    let mut names = HashMap::new();
+    for (ip, _) in &failing {
+        if let Some(name) = dns.ptr(*ip).await.ok().and_then(|n| n.into_iter().next()) {
+            names.insert(*ip, name);
+        }
+    }
+    let policy = record.as_deref().unwrap_or(&data.policy);
+    suggestions.extend(report_suggestions(
+        &domain, &data, policy, days, &failing, &names,
+    ));
+    DomainAdvice {
+        suggestions,
+        domain,
+        record,
+        messages: data.messages,
+        passed: data.passed,
+    }
+}
+
+/// Missing DMARC record or reporting address
+fn record_suggestion(domain: &str, record: Option<&str>) -> Option<Suggestion> {
+    match record {
+        Some(record) if record_tag(record, "rua").is_none() => Some(Suggestion {
+            kind: AdviceKind::AddReportingAddress,
+            message: format!(
+                "The DMARC record of {domain} has no rua tag, aggregate reports will stop"
+            ),
+        }),
+        Some(_) => None,
+        None => Some(Suggestion {
+            kind: AdviceKind::PublishRecord,
+            message: format!(
+                "No DMARC record found at _dmarc.{domain}, \
+                publish one to keep the policy and the reports"
+            ),
+        }),
+    }
+}
+
+/// Suggestions based on the messages in the reports and the current policy in DMARC record notation
+fn report_suggestions(
+    domain: &str,
+    data: &DomainData,
+    policy: &str,
+    days: u64,
+    failing: &[(IpAddr, usize)],
+    names: &HashMap<IpAddr, String>,
+) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    let mut suggest = |kind, message| suggestions.push(Suggestion { kind, message });
+    if data.messages == 0 {
+        return suggestions;
+    }
+    let p = record_tag(policy, "p").unwrap_or_default().to_lowercase();
+    let pct = record_tag(policy, "pct").unwrap_or_else(|| "100".to_owned());
+    if data.passed == data.messages {
+        let passed = format!(
+            "All {} messages from {} sources passed DMARC in the last {days} days",
+            data.messages,
+            data.sources.len()
+        );
+        match (p.as_str(), pct.as_str()) {
+            ("none", _) => suggest(
+                AdviceKind::RaisePolicy,
+                format!("{passed}, safe to move from p=none to p=quarantine"),
+            ),
+            ("quarantine" | "reject", pct) if pct != "100" => suggest(
+                AdviceKind::RaisePercentage,
+                format!("{passed}, safe to raise pct={pct} to pct=100"),
+            ),
+            ("quarantine", _) => suggest(
+                AdviceKind::RaisePolicy,
+                format!("{passed}, safe to move from p=quarantine to p=reject"),
+            ),
+            _ => {}
+        }
+    }
+
+    for (provider, messages) in &data.spf_providers {
+        suggest(
+            AdviceKind::AddSpfInclude,
+            format!(
+                "{messages} messages failed DMARC although SPF passed for {provider}, \
+                add the SPF include of this provider to {domain} and use a return path of {domain}"
+            ),
+        );
+    }
+    for (provider, messages) in &data.dkim_providers {
+        suggest(
+            AdviceKind::AlignDkim,
+            format!(
+                "{messages} messages failed DMARC although DKIM passed for {provider}, \
+                set up DKIM signing with {domain} at this provider"
+            ),
+        );
+    }
+    for (ip, messages) in failing {
+        let name = names
+            .get(ip)
+            .map(|name| format!(" ({name})"))
+            .unwrap_or_default();
+        suggest(
+            AdviceKind::AuthorizeSource,
+            format!(
+                "{messages} messages from {ip}{name} failed DMARC, \
+                authorize the source with SPF or DKIM if it sends on behalf of {domain}"
+            ),
+        );
+    }
+    suggestions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use crate::report::DmarcResultType;
+    use crate::timeseries::DAY;
+    use std::fs;
+
+    #[test]
+    fn policy_and_provider_suggestions() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let mut report = parse_xml_file(&xml).unwrap();
+        report.record.truncate(1);
+        report.report_metadata.date_range.end = 10 * DAY;
+        let now = 12 * DAY;
+        let record = "v=DMARC1; p=none; rua=mailto:dmarc@example.com";
+
+        let domains = collect(&[report.clone()], now, 30 * DAY);
+        let advice = report_suggestions(
+            "example.com",
+            &domains["example.com"],
+            record,
+            30,
+            &[],
+            &HashMap::new(),
+        );
+        assert_eq!(advice.len(), 1);
+        assert_eq!(advice[0].kind, AdviceKind::RaisePolicy);
+
+        let failing = &mut report.record[0];
+        failing.row.policy_evaluated.spf = Some(DmarcResultType::Fail);
+        failing.auth_results.spf[0].domain = "bounce.provider.net".to_owned();
+        let domains = collect(&[report], now, 30 * DAY);
+        let data = &domains["example.com"];
+        assert_eq!(data.passed, 0);
+        let advice =
+            report_suggestions("example.com", data, &data.policy, 30, &[], &HashMap::new());
+        assert_eq!(advice.len(), 1);
+        assert_eq!(advice[0].kind, AdviceKind::AddSpfInclude);
+        assert!(advice[0].message.contains("bounce.provider.net"));
+        let missing = record_suggestion("example.com", None).unwrap();
+        assert_eq!(missing.kind, AdviceKind::PublishRecord);
+    }
+}
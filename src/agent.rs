@@ -0,0 +1,100 @@
+use crate::config::AgentConfiguration;
+use crate::ingest::{sign, unix_timestamp, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use anyhow::{Context, Result};
+use reqwest::{Client, StatusCode};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tokio::signal;
+use tracing::{error, info, warn};
+
+/// Subdirectory of the watched directory for successfully forwarded files
+const SENT_DIR: &str = "sent";
+
+/// Subdirectory of the watched directory for files rejected by the central instance
+const FAILED_DIR: &str = "failed";
+
+/// Watch a local directory for report files and forward them
+/// to the ingestion API of a central instance until stopped.
+pub async fn run_agent(config: &AgentConfiguration) -> Result<()> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(config.agent_timeout))
+        .build()
+        .context("Failed to create HTTP client")?;
+    for dir in [SENT_DIR, FAILED_DIR] {
+        fs::create_dir_all(Path::new(&config.watch_dir).join(dir))
+            .with_context(|| format!("Failed to create directory {dir} in watch directory"))?;
+    }
+
+    info!(
+        "Started agent with check interval of {} secs",
+        config.agent_interval
+    );
+    loop {
+        match forward_files(config, &client).await {
+            Ok(0) => info!("No new report files found"),
+            Ok(count) => info!("Forwarded {count} report files"),
+            Err(err) => error!("Failed to forward report files: {err:#}"),
+        }
+        let duration = Duration::from_secs(config.agent_interval);
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => {},
+            _ = signal::ctrl_c() => { break; },
+        }
+    }
+    info!("Agent stopped");
+    Ok(())
+}
+
+async fn forward_files(config: &AgentConfiguration, client: &Client) -> Result<usize> {
+    let watch_dir = Path::new(&config.watch_dir);
+    let entries = fs::read_dir(watch_dir).context("Failed to read watch directory")?;
+    let mut forwarded = 0;
+    for entry in entries {
+        let entry = entry.context("Failed to read directory entry")?;
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let file_name = entry.file_name();
+        let data = fs::read(&path).with_context(|| format!("Failed to read file {path:?}"))?;
+
+        let timestamp = unix_timestamp()?;
+        let response = client
+            .post(&config.ingest_url)
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(
+                SIGNATURE_HEADER,
+                sign(&config.ingest_secret, timestamp, &data),
+            )
+            .body(data)
+            .send()
+            .await
+            .context("Failed to send report file")?;
+
+        let status = response.status();
+        let target_dir = if status.is_success() {
+            forwarded += 1;
+            SENT_DIR
+        } else if rejected(status) {
+            let reason = response.text().await.unwrap_or_default();
+            warn!("Report file {file_name:?} was rejected with HTTP status {status}: {reason}");
+            FAILED_DIR
+        } else {
+            // Keep file for next attempt, central instance might be temporarily unavailable
+            warn!("Failed to forward report file {file_name:?}: HTTP status {status}");
+            continue;
+        };
+        fs::rename(&path, watch_dir.join(target_dir).join(&file_name))
+            .with_context(|| format!("Failed to move file {path:?} to {target_dir}"))?;
+    }
+    Ok(forwarded)
+}
+
+/// Client errors like invalid or too large files will not change with another attempt,
+/// except for an invalid signature, for example because of clock skew, and rate limits
+fn rejected(status: StatusCode) -> bool {
+    status.is_client_error()
+        && status != StatusCode::UNAUTHORIZED
+        && status != StatusCode::TOO_MANY_REQUESTS
+}
@@ -0,0 +1,138 @@
+use crate::cidr::IpNetwork;
+use crate::report::{DkimResultType, RecordType, Report, SpfResultType};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use utoipa::ToSchema;
+
+/// Service that is authorized to send mails for a domain
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct AllowedSender {
+    /// Name of the service, like a newsletter provider or the own mail servers
+    pub name: String,
+
+    /// IP ranges of the service in CIDR notation
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    pub networks: Vec<IpNetwork>,
+
+    /// Domains of the service with passing DKIM signatures or SPF checks, including subdomains
+    #[serde(default)]
+    pub domains: Vec<String>,
+}
+
+/// Expected senders per domain of the published policy.
+/// Records of domains with an allowlist from other sources are flagged as unexpected.
+#[derive(Serialize, Deserialize, Default, Clone, ToSchema)]
+pub struct Allowlist {
+    pub domains: HashMap<String, Vec<AllowedSender>>,
+}
+
+impl Allowlist {
+    /// Reads the allowlist from a JSON file
+    pub fn load(path: &str) -> Result<Self> {
+        let json = fs::read(path).with_context(|| format!("Failed to read allowlist {path}"))?;
+        let mut allowlist = Self::default();
+        allowlist.merge(
+            serde_json::from_slice(&json)
+                .with_context(|| format!("Failed to parse allowlist {path}"))?,
+        );
+        Ok(allowlist)
+    }
+
+    /// Replaces the senders of all domains in the other allowlist
+    pub fn merge(&mut self, other: Allowlist) {
+        self.domains.extend(
+            other
+                .domains
+                .into_iter()
+                .map(|(domain, senders)| (domain.to_lowercase(), senders)),
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    /// Checks if the record comes from an allowed sender of the domain.
+    /// Domains without allowlist accept all senders.
+    pub fn is_expected(&self, domain: &str, record: &RecordType) -> bool {
+        !self.domains.contains_key(&domain.to_lowercase()) || self.is_listed(domain, record)
+    }
+
+    /// Checks if the record comes from a sender on the allowlist of the domain
+    pub fn is_listed(&self, domain: &str, record: &RecordType) -> bool {
+        let Some(senders) = self.domains.get(&domain.to_lowercase()) else {
+            return false;
+        };
+        let auth = &record.auth_results;
+        let passed_domains: Vec<String> = auth
+            .dkim
+            .iter()
+            .flatten()
+            .filter(|r| r.result == DkimResultType::Pass)
+            .map(|r| r.domain.to_lowercase())
+            .chain(
+                auth.spf
+                    .iter()
+                    .filter(|r| r.result == SpfResultType::Pass)
+                    .map(|r| r.domain.to_lowercase()),
+            )
+            .collect();
+        senders.iter().any(|sender| {
+            sender
+                .networks
+                .iter()
+                .any(|n| n.contains(&record.row.source_ip))
+                || sender.domains.iter().any(|allowed| {
+                    let allowed = allowed.to_lowercase();
+                    passed_domains
+                        .iter()
+                        .any(|d| d == &allowed || d.ends_with(&format!(".{allowed}")))
+                })
+        })
+    }
+
+    /// Sets the unexpected flag of all records of the reports
+    pub fn mark(&self, reports: &mut [Report]) {
+        for report in reports {
+            let domain = &report.policy_published.domain;
+            for record in &mut report.record {
+                record.unexpected = !self.is_expected(domain, record);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn unexpected_records() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let mut reports = vec![parse_xml_file(&xml).unwrap()];
+        let mut allowlist = Allowlist::default();
+        allowlist.mark(&mut reports);
+        assert!(reports[0].record.iter().all(|r| !r.unexpected));
+
+        allowlist.merge(
+            serde_json::from_str(
+                r#"{"domains": {"Example.com": [{"name": "Office", "networks": ["10.0.0.0/8"]}]}}"#,
+            )
+            .unwrap(),
+        );
+        allowlist.mark(&mut reports);
+        assert!(reports[0].record[0].unexpected);
+
+        let source = reports[0].record[0].row.source_ip;
+        allowlist.domains.get_mut("example.com").unwrap()[0]
+            .networks
+            .push(IpNetwork::new(source, 24));
+        allowlist.mark(&mut reports);
+        assert!(!reports[0].record[0].unexpected);
+    }
+}
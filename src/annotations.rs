@@ -0,0 +1,71 @@
+use crate::csv::parse_csv;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// Manually maintained knowledge about source IPs and domains
+/// that is applied to the DMARC records when they are listed or exported.
+#[derive(Serialize, Deserialize, Default, Clone, ToSchema)]
+pub struct Annotations {
+    /// Labels for source IPs, like the name of a newsletter provider
+    #[schema(value_type = HashMap<String, String>)]
+    pub ips: HashMap<IpAddr, String>,
+
+    /// Owners of domains, like a team or department
+    pub domains: HashMap<String, String>,
+}
+
+impl Annotations {
+    /// Parses annotations from CSV lines with the format `type,value,label`.
+    /// Type must be `ip` or `domain`. An optional header line is skipped.
+    pub fn from_csv(csv: &str) -> Result<Self> {
+        let mut annotations = Self::default();
+        for (index, row) in parse_csv(csv).iter().enumerate() {
+            let [kind, value, label] = row.as_slice() else {
+                bail!("Line {} does not have exactly three fields", index + 1);
+            };
+            match kind.trim().to_lowercase().as_str() {
+                "ip" => {
+                    let ip = value
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("Invalid IP in line {}", index + 1))?;
+                    annotations.ips.insert(ip, label.trim().to_string());
+                }
+                "domain" => {
+                    annotations
+                        .domains
+                        .insert(value.trim().to_lowercase(), label.trim().to_string());
+                }
+                "type" if index == 0 => continue,
+                _ => bail!("Unknown annotation type '{kind}' in line {}", index + 1),
+            }
+        }
+        Ok(annotations)
+    }
+
+    /// Adds or replaces all annotations from the other instance
+    pub fn merge(&mut self, other: Annotations) {
+        self.ips.extend(other.ips);
+        self.domains.extend(
+            other
+                .domains
+                .into_iter()
+                .map(|(domain, owner)| (domain.to_lowercase(), owner)),
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.ips.len() + self.domains.len()
+    }
+
+    pub fn ip_label(&self, ip: &IpAddr) -> Option<&str> {
+        self.ips.get(ip).map(|l| l.as_str())
+    }
+
+    pub fn domain_owner(&self, domain: &str) -> Option<&str> {
+        self.domains.get(&domain.to_lowercase()).map(|o| o.as_str())
+    }
+}
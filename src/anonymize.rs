@@ -0,0 +1,260 @@
+use crate::config::Configuration;
+use crate::duplicate::DuplicateReport;
+use crate::mail::Mail;
+use crate::report::Report;
+use crate::state::AppState;
+use crate::summary::Summary;
+use crate::xml_error::XmlError;
+use ring::hmac;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+/// Number of letters of the pseudonyms for domain labels, local parts and organizations
+const PSEUDONYM_LENGTH: usize = 8;
+
+/// Replaces source IPs, domains and organization names with deterministic pseudonyms,
+/// so screenshots and demo instances can be shared without leaking infrastructure details.
+/// The same value always gets the same pseudonym with the same key,
+/// which keeps subdomains, networks and DMARC alignment recognizable.
+pub struct Anonymizer {
+    key: hmac::Key,
+}
+
+impl Anonymizer {
+    /// Returns nothing if anonymization is not enabled
+    pub fn new(config: &Configuration) -> Option<Self> {
+        let key = config.anonymize_key.as_ref()?;
+        Some(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+        })
+    }
+
+    fn digest(&self, kind: &str, value: &[u8]) -> hmac::Tag {
+        let mut context = hmac::Context::with_key(&self.key);
+        context.update(kind.as_bytes());
+        context.update(b"\n");
+        context.update(value);
+        context.sign()
+    }
+
+    fn letters(&self, kind: &str, value: &str) -> String {
+        self.digest(kind, value.to_lowercase().as_bytes())
+            .as_ref()
+            .iter()
+            .take(PSEUDONYM_LENGTH)
+            .map(|b| char::from(b'a' + b % 26))
+            .collect()
+    }
+
+    /// Keeps the top level domain and the number of labels.
+    /// Each label depends on its parent domain, so subdomains stay below their pseudonymous parents.
+    pub fn domain(&self, domain: &str) -> String {
+        let labels: Vec<&str> = domain.split('.').collect();
+        let Some((tld, names)) = labels.split_last() else {
+            return String::new();
+        };
+        if names.is_empty() {
+            return domain.to_lowercase();
+        }
+        let mut pseudonyms: Vec<String> = (0..names.len())
+            .map(|i| self.letters("domain", &labels[i..].join(".")))
+            .collect();
+        pseudonyms.push(tld.to_lowercase());
+        pseudonyms.join(".")
+    }
+
+    /// Addresses keep their format with pseudonyms for the local part and the domain
+    pub fn email(&self, address: &str) -> String {
+        match address.trim_matches(['<', '>', ' ']).rsplit_once('@') {
+            Some((local, domain)) => {
+                format!("{}@{}", self.letters("local", local), self.domain(domain))
+            }
+            None => self.org(address),
+        }
+    }
+
+    /// Organizations named like domains get pseudonymous domains
+    pub fn org(&self, org: &str) -> String {
+        if org.contains('.') && !org.contains(char::is_whitespace) {
+            self.domain(org)
+        } else {
+            format!("Org {}", self.letters("org", org))
+        }
+    }
+
+    /// Opaque identifiers like report IDs that may contain domains
+    pub fn id(&self, id: &str) -> String {
+        hex::encode(&self.digest("id", id.as_bytes()).as_ref()[..8])
+    }
+
+    /// Each byte depends on all bytes before it, so addresses in the same network
+    /// get pseudonyms in the same network for prefixes at byte boundaries
+    pub fn ip(&self, ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(self.prefix_bytes(ip.octets()))),
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(self.prefix_bytes(ip.octets()))),
+        }
+    }
+
+    fn prefix_bytes<const N: usize>(&self, bytes: [u8; N]) -> [u8; N] {
+        let mut pseudonym = [0; N];
+        for (i, byte) in pseudonym.iter_mut().enumerate() {
+            *byte = self.digest("ip", &bytes[..=i]).as_ref()[0];
+        }
+        pseudonym
+    }
+
+    /// Report with pseudonyms and without free text fields that might contain names
+    pub fn report(&self, report: &Report) -> Report {
+        let mut report = report.clone();
+        let metadata = &mut report.report_metadata;
+        metadata.org_name = self.org(&metadata.org_name);
+        metadata.email = self.email(&metadata.email);
+        metadata.extra_contact_info = None;
+        metadata.report_id = self.id(&metadata.report_id);
+        metadata.error = None;
+        metadata.generator = None;
+        report.policy_published.domain = self.domain(&report.policy_published.domain);
+        report.attachment_name = None;
+        for record in &mut report.record {
+            record.row.source_ip = self.ip(record.row.source_ip);
+            for reason in record.row.policy_evaluated.reason.iter_mut().flatten() {
+                reason.comment = None;
+            }
+            let identifiers = &mut record.identifiers;
+            identifiers.header_from = self.domain(&identifiers.header_from);
+            identifiers.envelope_from =
+                identifiers.envelope_from.as_deref().map(|d| self.domain(d));
+            identifiers.envelope_to = identifiers.envelope_to.as_deref().map(|d| self.domain(d));
+            for dkim in record.auth_results.dkim.iter_mut().flatten() {
+                dkim.domain = self.domain(&dkim.domain);
+                dkim.human_result = None;
+            }
+            for spf in &mut record.auth_results.spf {
+                spf.domain = self.domain(&spf.domain);
+            }
+            for arc in &mut record.auth_results.arc {
+                arc.domain = arc.domain.as_deref().map(|d| self.domain(d));
+            }
+            for other in &mut record.auth_results.other {
+                other.details.clear();
+            }
+        }
+        report
+    }
+
+    /// State as served to readers. Annotations, expected senders and the trackers for alerts
+    /// are left out since they cannot be mapped reliably and only matter to the operator.
+    pub fn state(&self, state: &AppState) -> AppState {
+        let reports: Vec<Report> = state.reports.iter().map(|r| self.report(r)).collect();
+        let mut summary = Summary::new(
+            state.summary.mails,
+            state.summary.xml_files,
+            &reports,
+            state.summary.duplicates,
+            state.summary.last_update,
+        );
+        summary.since = state.summary.since;
+        summary.until = state.summary.until;
+        summary.last_cycle = state.summary.last_cycle.clone();
+        summary.compliance = state
+            .summary
+            .compliance
+            .iter()
+            .map(|score| {
+                let mut score = score.clone();
+                score.domain = self.domain(&score.domain);
+                score
+            })
+            .collect();
+        AppState {
+            mails: Arc::new(
+                state
+                    .mails
+                    .iter()
+                    .map(|(uid, mail)| (*uid, self.mail(mail)))
+                    .collect(),
+            ),
+            xml_files: state.xml_files,
+            reports: Arc::new(reports),
+            summary,
+            last_update: state.last_update,
+            xml_errors: Arc::new(
+                state
+                    .xml_errors
+                    .iter()
+                    .map(|error| XmlError {
+                        xml: String::new(),
+                        attachment_name: None,
+                        attachment_path: None,
+                        ..error.clone()
+                    })
+                    .collect(),
+            ),
+            duplicates: Arc::new(
+                state
+                    .duplicates
+                    .iter()
+                    .map(|duplicate| DuplicateReport {
+                        mail_uid: duplicate.mail_uid,
+                        org_name: self.org(&duplicate.org_name),
+                        report_id: self.id(&duplicate.report_id),
+                    })
+                    .collect(),
+            ),
+            evicted_uids: state.evicted_uids.clone(),
+            quarantine: state.quarantine.clone(),
+            source_history: Arc::new(state.source_history.anonymized(self)),
+            policy_history: Arc::new(state.policy_history.anonymized(self)),
+            rollups: Arc::new(state.rollups.anonymized(self)),
+            revision: state.revision,
+            ready: state.ready,
+            ..Default::default()
+        }
+    }
+
+    fn mail(&self, mail: &Mail) -> Mail {
+        Mail {
+            subject: String::from("DMARC Aggregate Report"),
+            sender: self.email(&mail.sender),
+            to: self.email(&mail.to),
+            message_id: None,
+            ..mail.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterministic_pseudonyms() {
+        let anonymizer = Anonymizer {
+            key: hmac::Key::new(hmac::HMAC_SHA256, b"secret"),
+        };
+        let domain = anonymizer.domain("example.com");
+        assert_ne!(domain, "example.com");
+        assert!(domain.ends_with(".com"));
+        assert_eq!(anonymizer.domain("Example.COM"), domain);
+        let subdomain = anonymizer.domain("mail.example.com");
+        assert!(subdomain.ends_with(&format!(".{domain}")));
+        assert_ne!(anonymizer.domain("example.org"), domain);
+
+        let email = anonymizer.email("dmarc@example.com");
+        assert!(email.ends_with(&format!("@{domain}")));
+        assert_eq!(anonymizer.org("example.com"), domain);
+        assert!(anonymizer.org("Mail.Ru Group").starts_with("Org "));
+
+        let ip: IpAddr = "192.0.2.10".parse().unwrap();
+        let other: IpAddr = "192.0.2.20".parse().unwrap();
+        let (IpAddr::V4(masked), IpAddr::V4(masked_other)) =
+            (anonymizer.ip(ip), anonymizer.ip(other))
+        else {
+            panic!("IPv4 addresses must stay IPv4 addresses");
+        };
+        assert_ne!(IpAddr::V4(masked), ip);
+        assert_eq!(masked.octets()[..3], masked_other.octets()[..3]);
+        assert!(anonymizer.ip("2001:db8::1".parse().unwrap()).is_ipv6());
+    }
+}
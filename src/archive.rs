@@ -0,0 +1,85 @@
+use crate::attachment::{attachment_path, Attachment};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Local directory preserving the raw report files.
+/// Files are named by the SHA256 hash of their content,
+/// so the same file is only stored once.
+pub struct Archive {
+    dir: PathBuf,
+}
+
+impl Archive {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    pub fn store_xml(&self, hash: &str, data: &[u8]) -> Result<bool> {
+        self.store(&xml_path(hash), data)
+    }
+
+    pub fn store_attachment(&self, attachment: &Attachment) -> Result<bool> {
+        self.store(&attachment_path(attachment), &attachment.data)
+    }
+
+    /// Store XML files given by hash and content together with their attachments.
+    /// Returns the number of newly written files.
+    /// Errors are only logged to not block the processing of reports.
+    pub fn store_all<'a>(
+        &self,
+        xml_files: impl Iterator<Item = (&'a str, &'a [u8])>,
+        attachments: &[Attachment],
+    ) -> usize {
+        let xml_results = xml_files.map(|(hash, data)| self.store_xml(hash, data));
+        let attachment_results = attachments.iter().map(|a| self.store_attachment(a));
+        let mut archived = 0;
+        for result in xml_results.chain(attachment_results) {
+            match result {
+                Ok(true) => archived += 1,
+                Ok(false) => {}
+                Err(err) => warn!("Failed to archive file: {err:#}"),
+            }
+        }
+        archived
+    }
+
+    /// Returns the content of an archived file or nothing if it was not archived
+    pub fn read(&self, relative_path: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.dir.join(relative_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(&path).with_context(|| format!("Failed to read file {path:?}"))?;
+        Ok(Some(data))
+    }
+
+    /// Returns false if the file already existed
+    fn store(&self, relative_path: &str, data: &[u8]) -> Result<bool> {
+        let path = self.dir.join(relative_path);
+        if path.exists() {
+            return Ok(false);
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create archive directory {dir:?}"))?;
+        }
+        write_file(&path, data)?;
+        Ok(true)
+    }
+}
+
+/// Relative archive path of an extracted XML file as `xml/<hash>.xml`
+pub fn xml_path(hash: &str) -> String {
+    format!("xml/{hash}.xml")
+}
+
+/// Write to temporary file first to never leave partially written files in the archive
+fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data).with_context(|| format!("Failed to write file {tmp_path:?}"))?;
+    fs::rename(&tmp_path, path).with_context(|| format!("Failed to rename file to {path:?}"))
+}
@@ -0,0 +1,13 @@
+/// Original compressed report file as delivered in a mail attachment
+pub struct Attachment {
+    pub data: Vec<u8>,
+    pub hash: String,
+
+    /// File extension matching the compression format, for example `zip`
+    pub extension: &'static str,
+}
+
+/// Relative archive path of an original attachment as `attachments/<hash>.<extension>`
+pub fn attachment_path(attachment: &Attachment) -> String {
+    format!("attachments/{}.{}", attachment.hash, attachment.extension)
+}
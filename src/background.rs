@@ -1,81 +1,771 @@
-use crate::config::Configuration;
-use crate::imap::get_mails;
-use crate::parser::{extract_xml_files, parse_xml_file};
-use crate::state::AppState;
+use crate::archive::{xml_path, Archive};
+use crate::attachment::attachment_path;
+use crate::compliance::compliance_scores;
+use crate::config::{Configuration, ExportConfiguration, ExportFormat};
+use crate::directory::DirectorySource;
+use crate::duplicate::DuplicateReport;
+use crate::events::{Event, Events};
+use crate::export::{write_export, write_filtered_export};
+use crate::filter::RecordFilter;
+use crate::mail::Mail;
+use crate::notifications::{Alert, Notifier};
+use crate::parser::{extract_xml_files, parse_report, ExtractLimits, ParseOptions};
+use crate::quarantine::Quarantine;
+use crate::report::Report;
+use crate::reporters::delivery_gaps;
+use crate::retention::Retention;
+use crate::s3::{S3Archive, S3Source};
+use crate::settings::{Settings, SharedSettings};
+use crate::siem::export_failures;
+use crate::source::{close_sessions, get_mails, keeps_mails};
+use crate::state::SharedState;
+use crate::status::{unix_time, BackfillProgress, BackgroundStatus, Phase, TaskPanic};
+use crate::storage::{state_store, StateStore};
 use crate::summary::Summary;
-use crate::xml_error::XmlError;
-use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
-use tokio::sync::mpsc::Receiver;
-use tokio::task::JoinHandle;
-use tracing::{error, info, warn};
+use crate::systemd::with_watchdog;
+use crate::thresholds::failed_conditions;
+use crate::timeseries::DAY;
+use crate::xml_error::{XmlError, XmlErrorKind};
+use crate::xml_file::XmlFile;
+use anyhow::{anyhow, ensure, Context, Result};
+use futures::{stream, FutureExt, StreamExt};
+use serde::Serialize;
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::task::{spawn_blocking, JoinHandle};
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+use utoipa::ToSchema;
+
+/// Result of an update cycle that was requested via HTTP
+#[derive(Clone, Serialize, ToSchema)]
+pub struct CycleStatus {
+    pub cycle_id: u64,
+    pub success: bool,
+    pub error: Option<String>,
+    pub reports: usize,
+    pub last_update: u64,
+}
+
+/// Requests an immediate update cycle and receives its status after it finished
+pub type RefreshSender = Sender<oneshot::Sender<CycleStatus>>;
+
+/// Channels to communicate with the background task from other parts of the application
+pub struct BgChannels {
+    pub refresh: Receiver<oneshot::Sender<CycleStatus>>,
+    pub stop: Receiver<()>,
+    pub events: Arc<Events>,
+}
 
 pub fn start_bg_task(
     config: Configuration,
-    state: Arc<Mutex<AppState>>,
-    mut stop_signal: Receiver<()>,
+    state: Arc<SharedState>,
+    notifier: Arc<Notifier>,
+    settings: Arc<SharedSettings>,
+    s3_archive: Option<Arc<S3Archive>>,
+    s3_source: Option<S3Source>,
+    mut channels: BgChannels,
 ) -> JoinHandle<()> {
-    tokio::spawn(async move {
+    // Keepalives come from the task itself, so systemd notices a blocked background task
+    tokio::spawn(with_watchdog(async move {
         info!(
             "Started background task with check interval of {} secs",
-            config.imap_check_interval
+            settings.get().imap_check_interval
         );
-        loop {
-            match bg_update(&config, &state).await {
-                Ok(..) => info!("Finished update cycle without errors"),
-                Err(err) => error!("Failed updated cycle: {err:#}"),
+        let store = state_store(&config);
+        let directory = DirectorySource::new(&config);
+        let mut replica_version = None;
+        let mut cycle_id: u64 = 0;
+        let mut waiting: Vec<oneshot::Sender<CycleStatus>> = Vec::new();
+        'cycles: loop {
+            cycle_id += 1;
+            let current_settings = settings.get();
+            channels.events.send(Event::CycleStarted { cycle_id });
+            set_status(&state, |s| s.start_cycle(cycle_id));
+            let started = Instant::now();
+            let cycle = async {
+                if config.read_replica {
+                    match replica_update(store.as_deref(), &state, &mut replica_version).await {
+                        Ok(..) => info!("Finished replica update without errors"),
+                        Err(err) => {
+                            error!("Failed replica update: {err:#}");
+                            return Err(err);
+                        }
+                    };
+                } else {
+                    if let Some(s3_source) = &s3_source {
+                        let archive = config.archive_dir.as_deref().map(Archive::new);
+                        match s3_source.ingest(&state, archive.as_ref()).await {
+                            Ok(count) => {
+                                info!("Ingested {count} new reports from S3");
+                                if count > 0 {
+                                    channels.events.send(Event::NewReports { count });
+                                }
+                            }
+                            Err(err) => error!("Failed to ingest reports from S3: {err:#}"),
+                        }
+                    }
+                    if let Some(directory) = &directory {
+                        let archive = config.archive_dir.as_deref().map(Archive::new);
+                        match directory.ingest(&state, archive.as_ref()) {
+                            Ok(count) => {
+                                info!("Ingested {count} new reports from directory");
+                                if count > 0 {
+                                    channels.events.send(Event::NewReports { count });
+                                }
+                            }
+                            Err(err) => error!("Failed to ingest reports from directory: {err:#}"),
+                        }
+                    }
+                    let update = bg_update(
+                        &config,
+                        &current_settings,
+                        &state,
+                        &notifier,
+                        &channels.events,
+                        store.as_deref(),
+                        &s3_archive,
+                    );
+                    match update.await {
+                        Ok(stopped_early) => {
+                            info!("Finished update cycle without errors");
+                            return Ok(stopped_early);
+                        }
+                        Err(err) => {
+                            error!("Failed updated cycle: {err:#}");
+                            return Err(err);
+                        }
+                    };
+                }
+                Ok(false)
             };
-            let duration = Duration::from_secs(config.imap_check_interval);
-            tokio::select! {
-                _ = tokio::time::sleep(duration) => {},
-                _ = stop_signal.recv() => { break; },
+            // A panic anywhere in the cycle fails only this cycle, the task keeps running
+            let cycle = AssertUnwindSafe(cycle.instrument(info_span!("cycle", cycle_id)));
+            let result = match cycle.catch_unwind().await {
+                Ok(result) => result,
+                Err(payload) => {
+                    let message = panic_message(&*payload);
+                    error!(cycle_id, "Update cycle panicked: {message}");
+                    let phase = state.status().phase;
+                    let panic = record_panic(&state, phase, None, message.clone());
+                    if let Some(alert) = Alert::panics(&[panic]) {
+                        notifier.send(&alert.with_state(&state)).await;
+                    }
+                    Err(anyhow!("Update cycle panicked: {message}"))
+                }
+            };
+            let stopped_early = result.as_ref().is_ok_and(|stopped_early| *stopped_early);
+            let error = result.err().map(|err| format!("{err:#}"));
+            // A cycle stopped at the time budget or by the backfill continues with the remaining mails right away
+            let duration = if stopped_early {
+                Duration::ZERO
+            } else {
+                Duration::from_secs(current_settings.imap_check_interval)
+            };
+            {
+                let snapshot = state.snapshot();
+                let mut status = state.status();
+                status.finish_cycle(error.clone(), unix_time() + duration.as_secs());
+                let metrics = status.cycle_metrics(
+                    started.elapsed(),
+                    error.clone(),
+                    snapshot.mails.len(),
+                    snapshot.reports.len(),
+                );
+                state.cycle_history().push(metrics);
+            }
+            channels.events.send(Event::CycleFinished {
+                cycle_id,
+                success: error.is_none(),
+                error: error.clone(),
+            });
+
+            // Answer all refresh requests that were waiting for this cycle
+            if !waiting.is_empty() {
+                let status = {
+                    let locked_state = state.snapshot();
+                    CycleStatus {
+                        cycle_id,
+                        success: error.is_none(),
+                        error,
+                        reports: locked_state.reports.len(),
+                        last_update: locked_state.last_update,
+                    }
+                };
+                for responder in waiting.drain(..) {
+                    responder.send(status.clone()).ok();
+                }
+            }
+
+            // Refresh requests that arrived during the cycle start the next one right away.
+            // All pending requests are combined into a single cycle.
+            // A changed check interval moves the next cycle, counted from the end of this one.
+            let finished = tokio::time::Instant::now();
+            let mut next_cycle = finished + duration;
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(next_cycle) => break,
+                    _ = settings.changed(), if !stopped_early => {
+                        let interval = Duration::from_secs(settings.get().imap_check_interval);
+                        if finished + interval != next_cycle {
+                            next_cycle = finished + interval;
+                            let remaining = next_cycle.saturating_duration_since(tokio::time::Instant::now());
+                            state.status().next_run = Some(unix_time() + remaining.as_secs());
+                            info!("Next update cycle in {} secs after change of check interval", remaining.as_secs());
+                        }
+                    },
+                    Some(responder) = channels.refresh.recv() => {
+                        info!("Starting update cycle requested via HTTP");
+                        waiting.push(responder);
+                        while let Ok(responder) = channels.refresh.try_recv() {
+                            waiting.push(responder);
+                        }
+                        break;
+                    },
+                    _ = channels.stop.recv() => break 'cycles,
+                }
             }
         }
-    })
+        close_sessions(&config).await;
+    }))
+}
+
+/// Run a single update cycle and write the optional export file
+pub async fn run_once(
+    config: &Configuration,
+    state: &Arc<SharedState>,
+    notifier: &Notifier,
+    settings: &SharedSettings,
+) -> Result<()> {
+    run_single_cycle(config, state, notifier, settings).await?;
+    if let Some(path) = &config.once_export {
+        let locked_state = state.snapshot();
+        write_export(&locked_state, path)?;
+        info!("Exported {} reports to {path}", locked_state.reports.len());
+    }
+    let failed = failed_conditions(&config.fail_on, &state.original());
+    for condition in &failed {
+        warn!("Condition met: {condition}");
+    }
+    ensure!(failed.is_empty(), "{} fail conditions met", failed.len());
+    Ok(())
+}
+
+/// Write filtered data to a file after a single update cycle
+/// or from the restored state without connecting to the mail source
+pub async fn run_export(
+    config: &Configuration,
+    export_config: &ExportConfiguration,
+    state: &Arc<SharedState>,
+    notifier: &Notifier,
+    settings: &SharedSettings,
+) -> Result<()> {
+    if export_config.snapshot {
+        ensure!(
+            config.import_file.is_some()
+                || config.state_file.is_some()
+                || config.state_database_url.is_some(),
+            "Exporting the snapshot requires STATE_FILE, STATE_DATABASE_URL or IMPORT_FILE"
+        );
+    } else {
+        run_single_cycle(config, state, notifier, settings).await?;
+    }
+    let count = write_filtered_export(&state.snapshot(), export_config)?;
+    let kind = match export_config.format {
+        ExportFormat::Csv => "records",
+        ExportFormat::Json => "reports",
+    };
+    info!("Exported {count} {kind} to {}", export_config.output);
+    Ok(())
+}
+
+async fn run_single_cycle(
+    config: &Configuration,
+    state: &Arc<SharedState>,
+    notifier: &Notifier,
+    settings: &SharedSettings,
+) -> Result<()> {
+    // Cycles stopped at the time budget or by the backfill are continued until all mails are processed
+    let mut cycle_id = 1;
+    while bg_update(
+        config,
+        &settings.get(),
+        state,
+        notifier,
+        &Events::new(),
+        state_store(config).as_deref(),
+        &None,
+    )
+    .instrument(info_span!("cycle", cycle_id))
+    .await
+    .context("Failed update cycle")?
+    {
+        cycle_id += 1;
+    }
+    close_sessions(config).await;
+    Ok(())
 }
 
-async fn bg_update(config: &Configuration, state: &Arc<Mutex<AppState>>) -> Result<()> {
+/// Runs an update cycle and applies its results to the state.
+/// Returns whether the cycle stopped at the time budget or after a backfill batch
+/// before all new mails were processed.
+async fn bg_update(
+    config: &Configuration,
+    settings: &Settings,
+    state: &Arc<SharedState>,
+    notifier: &Notifier,
+    events: &Events,
+    store: Option<&dyn StateStore>,
+    s3_archive: &Option<Arc<S3Archive>>,
+) -> Result<bool> {
     info!("Starting background update cycle");
-    let mut mails = get_mails(config).await.context("Failed to get mails")?;
-
-    let mut xml_files = HashMap::new();
-    for mail in &mut mails.values_mut() {
-        if mail.body.is_some() {
-            match extract_xml_files(mail) {
-                Ok(files) => {
-                    for xml_file in files {
-                        xml_files.insert(xml_file.hash.clone(), xml_file);
+    let started = Instant::now();
+    let budget = config.cycle_time_budget.map(Duration::from_secs);
+    let backfill = config.backfill && !state.status().backfill.as_ref().is_some_and(|b| b.finished);
+
+    // Take over results of mails processed in previous cycles
+    let (
+        known_uids,
+        mut deliveries,
+        previous_reports,
+        previous_xml_errors,
+        previous_duplicates,
+        maintenance,
+        mut quarantine,
+    ) = {
+        let locked_state = state.original();
+        // Quarantined mails are downloaded again until their retries are used up
+        let retry_uids = locked_state.quarantine.retry_uids();
+        let known_uids: HashSet<u32> = locked_state
+            .mails
+            .values()
+            .filter(|m| !m.oversized && !retry_uids.contains(&m.uid))
+            .map(|m| m.uid)
+            .chain(locked_state.evicted_uids.iter().copied())
+            .collect();
+        let deliveries: HashMap<String, u32> = locked_state
+            .mails
+            .values()
+            .filter(|m| m.duplicate_of.is_none())
+            .filter_map(|m| Some((m.delivery_key()?, m.uid)))
+            .collect();
+        (
+            known_uids,
+            deliveries,
+            locked_state.reports.clone(),
+            locked_state.xml_errors.clone(),
+            locked_state.duplicates.clone(),
+            locked_state.maintenance,
+            Quarantine::clone(&locked_state.quarantine),
+        )
+    };
+
+    // The update runs in stages connected by bounded channels: fetch -> extract -> parse -> aggregate.
+    // The stages overlap and only a few batches of mails and XML files are in memory at the same time.
+    // A failing extraction or parser worker only loses its mail or file, the cycle goes on.
+    // Panics of workers are caught and recorded like errors, the offending mail is skipped.
+    set_status(state, |s| s.phase = Phase::Fetching);
+    let archive = config.archive_dir.as_deref().map(Archive::new);
+    let limits = ExtractLimits::from(config);
+    let options = Arc::new(ParseOptions::from(config));
+    let workers = worker_count(config);
+    let (batch_sender, batch_receiver) = channel::<Vec<Mail>>(1);
+    let (xml_sender, xml_receiver) = channel::<XmlFile>(workers);
+    let (parsed_sender, parsed_receiver) = channel::<(XmlFile, Result<Report>)>(workers);
+    let pending = AtomicUsize::new(0);
+    let panics: Mutex<Vec<TaskPanic>> = Mutex::new(Vec::new());
+    let mut received = 0;
+
+    let fetch = async {
+        let fetch_started = Instant::now();
+        let mails = get_mails(config, &known_uids, batch_sender, &pending).await;
+        let elapsed = fetch_started.elapsed();
+        set_status(state, |s| {
+            s.phase = Phase::Extracting;
+            s.fetch_ms = elapsed.as_millis() as u64;
+        });
+        mails
+    };
+
+    // Extract XML files from every batch of downloaded mails while the next batch is downloaded.
+    // Identical files in multiple mails are only parsed once.
+    let mut archived = 0;
+    let mut s3_uploads = Vec::new();
+    let mut new_mails = Vec::new();
+    let mut attachment_counts: HashMap<u32, usize> = HashMap::new();
+    let mut extracted_files = 0;
+    let mut duplicate_mails = 0;
+    let mut stopped_early = false;
+    let extraction = async {
+        let (mut batch_receiver, xml_sender) = (batch_receiver, xml_sender);
+        let mut hashes = HashSet::new();
+        while let Some(mut batch) = batch_receiver.recv().await {
+            received += batch.len();
+            // Copies of the same mail, for example sent to multiple aliases, are only processed once
+            for mail in &mut batch {
+                let Some(key) = mail.delivery_key() else {
+                    continue;
+                };
+                match deliveries.get(&key) {
+                    Some(&original) if original != mail.uid => {
+                        debug!(
+                            mail_uid = mail.uid,
+                            original, "Skipped copy of already processed mail"
+                        );
+                        mail.duplicate_of = Some(original);
+                        mail.body = None;
+                        duplicate_mails += 1;
+                        set_status(state, |s| s.duplicate_mails += 1);
+                    }
+                    Some(_) => {}
+                    None => {
+                        deliveries.insert(key, mail.uid);
                     }
                 }
-                Err(err) => warn!("Failed to extract XML files from mail: {err:#}"),
+            }
+            // Take bodies out of the mails to not keep the no longer needed data in memory
+            let bodies: Vec<(u32, Vec<u8>)> = batch
+                .iter_mut()
+                .filter_map(|m| Some((m.uid, m.body.take()?)))
+                .collect();
+            new_mails.append(&mut batch);
+            set_status(state, |s| s.mails_total += bodies.len());
+            let mut extracted = stream::iter(bodies)
+                .map(|(uid, body)| {
+                    let span = Span::current();
+                    spawn_blocking(move || {
+                        let _span = span.entered();
+                        let started = Instant::now();
+                        let result = catch_panic(|| extract_xml_files(uid, &body, &limits));
+                        (uid, result, started.elapsed())
+                    })
+                })
+                .buffered(workers);
+            while let Some(result) = extracted.next().await {
+                let (uid, result, elapsed) = match result {
+                    Ok(extracted) => extracted,
+                    Err(err) => {
+                        error!("Failed to join extraction worker: {err:#}");
+                        set_status(state, |s| {
+                            s.extract_errors += 1;
+                            s.mails_processed += 1;
+                        });
+                        continue;
+                    }
+                };
+                set_status(state, |s| s.extract_ms += elapsed.as_millis() as u64);
+                // A panic would happen again, so the mail is not retried
+                let (result, retries) = match result {
+                    Ok(result) => (result, config.extract_retries),
+                    Err(message) => {
+                        error!(mail_uid = uid, "Extraction worker panicked: {message}");
+                        let panic = record_panic(state, Phase::Extracting, Some(uid), message);
+                        let error = anyhow!("Extraction panicked: {}", panic.message);
+                        panics
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .push(panic);
+                        (Err(error), 0)
+                    }
+                };
+                match result {
+                    Ok((files, attachments)) => {
+                        if let Some(released) = quarantine.release(uid) {
+                            info!(
+                                mail_uid = uid,
+                                "Extracted XML files from quarantined mail after {} failed attempts",
+                                released.attempts
+                            );
+                        }
+                        attachment_counts.insert(uid, attachments.len());
+                        if let Some(archive) = &archive {
+                            archived += archive.store_all(
+                                files.iter().map(|f| (f.hash.as_str(), f.data.as_slice())),
+                                &attachments,
+                            );
+                        }
+                        if s3_archive.is_some() {
+                            s3_uploads
+                                .extend(files.iter().map(|f| (xml_path(&f.hash), f.data.clone())));
+                            s3_uploads.extend(
+                                attachments
+                                    .into_iter()
+                                    .map(|a| (attachment_path(&a), a.data)),
+                            );
+                        }
+                        for xml_file in files {
+                            if hashes.insert(xml_file.hash.clone()) {
+                                extracted_files += 1;
+                                set_status(state, |s| s.xml_files_total += 1);
+                                xml_sender
+                                    .send(xml_file)
+                                    .await
+                                    .context("Parser stage stopped")?;
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        let error = format!("{err:#}");
+                        let mail = quarantine.failed(uid, error, retries, unix_time());
+                        if mail.exhausted {
+                            warn!(
+                                mail_uid = uid,
+                                "Failed to extract XML files from mail after {} attempts, giving up: {}",
+                                mail.attempts,
+                                mail.error
+                            );
+                        } else {
+                            info!(
+                                mail_uid = uid,
+                                "Failed to extract XML files from mail, retrying in the next cycle: {}",
+                                mail.error
+                            );
+                        }
+                        set_status(state, |s| s.extract_errors += 1);
+                    }
+                }
+                set_status(state, |s| s.mails_processed += 1);
+            }
+            // Closing the channel stops the download, the remaining mails are new again in the next cycle
+            let remaining = pending.load(Ordering::Relaxed).saturating_sub(received);
+            if backfill {
+                let progress = backfill_progress(known_uids.len(), received, &pending, false);
+                set_status(state, |s| s.backfill = Some(progress));
+                if remaining > 0 {
+                    info!(
+                        "Publishing backfill results of {} new mails, {remaining} mails remaining",
+                        new_mails.len()
+                    );
+                    stopped_early = true;
+                    break;
+                }
+            }
+            if let Some(budget) = budget.filter(|budget| started.elapsed() >= *budget) {
+                info!(
+                    "Exceeded cycle time budget of {} secs, publishing results of {} new mails",
+                    budget.as_secs(),
+                    new_mails.len()
+                );
+                stopped_early = true;
+                break;
             }
         }
-    }
-    info!("Extracted {} XML files from mails", xml_files.len());
-
-    let mut xml_errors = Vec::new();
-    let mut reports = Vec::new();
-    for xml_file in xml_files.values() {
-        match parse_xml_file(&xml_file.data) {
-            Ok(report) => reports.push(report),
-            Err(err) => {
-                let error = format!("{err:#}");
-                xml_errors.push(XmlError {
-                    mail_uid: xml_file.mail_uid,
-                    error,
-                    xml: String::from_utf8_lossy(&xml_file.data).to_string(),
-                });
+        drop(batch_receiver);
+        set_status(state, |s| s.phase = Phase::Parsing);
+        Ok::<_, anyhow::Error>(())
+    };
+
+    // Parse in parallel but pass on the results in order of the extracted files
+    let parsing = async {
+        let (mut xml_receiver, parsed_sender) = (xml_receiver, parsed_sender);
+        let mut parsed = stream::poll_fn(|cx| xml_receiver.poll_recv(cx))
+            .map(|xml_file| {
+                let options = options.clone();
+                spawn_blocking(move || {
+                    let started = Instant::now();
+                    let result = catch_panic(|| parse_report(&xml_file.data, &options));
+                    (xml_file, result, started.elapsed())
+                })
+            })
+            .buffered(workers);
+        while let Some(result) = parsed.next().await {
+            match result {
+                Ok((xml_file, result, elapsed)) => {
+                    set_status(state, |s| s.parse_ms += elapsed.as_millis() as u64);
+                    // The file of a panicking parser is kept as XML error
+                    let result = result.unwrap_or_else(|message| {
+                        let uid = xml_file.mail_uid;
+                        error!(mail_uid = uid, "Parser worker panicked: {message}");
+                        let panic = record_panic(state, Phase::Parsing, Some(uid), message);
+                        let error = anyhow!("Parser panicked: {}", panic.message);
+                        panics
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .push(panic);
+                        Err(error)
+                    });
+                    parsed_sender
+                        .send((xml_file, result))
+                        .await
+                        .context("Aggregation stage stopped")?;
+                }
+                Err(err) => {
+                    error!("Failed to join parser worker: {err:#}");
+                    set_status(state, |s| {
+                        s.parse_errors += 1;
+                        s.xml_files_parsed += 1;
+                    });
+                }
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    // Collect the new reports, older mails win if the same report was delivered multiple times
+    let mut parsed_reports: Vec<Report> = Vec::new();
+    let mut repeated_reports: Vec<DuplicateReport> = Vec::new();
+    let mut parse_failures: Vec<XmlError> = Vec::new();
+    let aggregation = async {
+        let mut parsed_receiver = parsed_receiver;
+        let mut positions: HashMap<(String, String), usize> = HashMap::new();
+        while let Some((xml_file, result)) = parsed_receiver.recv().await {
+            let _span = info_span!("xml_file", mail_uid = xml_file.mail_uid).entered();
+            match result {
+                Ok(mut report) => {
+                    let (org_name, report_id) = report.key();
+                    debug!(org_name, report_id, "Parsed DMARC report");
+                    let key = (org_name.to_owned(), report_id.to_owned());
+                    report.mail_uid = Some(xml_file.mail_uid);
+                    report.xml_hash = Some(xml_file.hash.clone());
+                    report.attachment_name = xml_file.attachment_name.clone();
+                    match positions.get(&key) {
+                        Some(&position) => {
+                            if parsed_reports[position].mail_uid > report.mail_uid {
+                                std::mem::swap(&mut parsed_reports[position], &mut report);
+                            }
+                            repeated_reports.push(DuplicateReport {
+                                mail_uid: report.mail_uid,
+                                org_name: key.0,
+                                report_id: key.1,
+                            });
+                        }
+                        None => {
+                            positions.insert(key, parsed_reports.len());
+                            parsed_reports.push(report);
+                        }
+                    }
+                }
+                Err(err) => {
+                    let error = format!("{err:#}");
+                    debug!(error, "Failed to parse XML file");
+                    parse_failures.push(XmlError {
+                        mail_uid: xml_file.mail_uid,
+                        error,
+                        xml: String::from_utf8_lossy(&xml_file.data).to_string(),
+                        kind: XmlErrorKind::classify(&xml_file.data),
+                        hash: xml_file.hash.clone(),
+                        attachment_name: xml_file.attachment_name.clone(),
+                        attachment_path: xml_file.attachment_path.clone(),
+                    });
+                    set_status(state, |s| s.parse_errors += 1);
+                }
             }
+            set_status(state, |s| s.xml_files_parsed += 1);
+        }
+    };
+
+    let (mails, extracted, parsed, ()) = tokio::join!(fetch, extraction, parsing, aggregation);
+    let mut mails = mails.context("Failed to get mails")?;
+    extracted?;
+    parsed?;
+    let panics = panics.into_inner().unwrap_or_else(PoisonError::into_inner);
+    for mail in &mut new_mails {
+        mail.attachments = attachment_counts
+            .get(&mail.uid)
+            .copied()
+            .unwrap_or_default();
+    }
+    mails.extend(new_mails.into_iter().map(|m| (m.uid, m)));
+    if !keeps_mails(config) {
+        // Mails deleted from the server after downloading them stay with their reports
+        let locked_state = state.original();
+        for (uid, mail) in locked_state.mails.iter() {
+            mails.entry(*uid).or_insert_with(|| mail.clone());
         }
     }
-    info!("Parsed {} DMARC reports successfully", reports.len());
-    if !xml_errors.is_empty() {
-        warn!(
-            "Failed to parse {} XML file as DMARC reports",
-            xml_errors.len()
-        );
+
+    // Forget evicted mails that were removed from the inbox
+    let evicted_uids: HashSet<u32> = {
+        let locked_state = state.original();
+        locked_state
+            .evicted_uids
+            .iter()
+            .filter(|uid| mails.contains_key(uid))
+            .copied()
+            .collect()
+    };
+    mails.retain(|uid, _| !evicted_uids.contains(uid));
+
+    // Copies of removed mails are processed again in the next cycle
+    let orphaned: Vec<u32> = mails
+        .values()
+        .filter(|m| m.duplicate_of.is_some_and(|uid| !mails.contains_key(&uid)))
+        .map(|m| m.uid)
+        .collect();
+    for uid in orphaned {
+        mails.remove(&uid);
+    }
+    if duplicate_mails > 0 {
+        info!("Skipped {duplicate_mails} copies of already processed mails");
+    }
+    info!("Extracted {extracted_files} new XML files from mails");
+    if archive.is_some() {
+        info!("Archived {archived} new files");
+    }
+
+    // Drop results of mails that were removed from the inbox.
+    // Reports without mail were ingested via HTTP and are always kept.
+    quarantine.retain(&mails);
+    let previous_ingested = previous_reports
+        .iter()
+        .filter(|r| r.mail_uid.is_none())
+        .count();
+    let mut reports: Vec<Report> = previous_reports
+        .iter()
+        .filter(|r| r.mail_uid.is_none_or(|uid| mails.contains_key(&uid)))
+        .cloned()
+        .collect();
+    let mut xml_errors: Vec<XmlError> = previous_xml_errors
+        .iter()
+        .filter(|e| mails.contains_key(&e.mail_uid))
+        .cloned()
+        .collect();
+    let mut duplicates: Vec<DuplicateReport> = previous_duplicates
+        .iter()
+        .filter(|d| d.mail_uid.is_some_and(|uid| mails.contains_key(&uid)))
+        .cloned()
+        .collect();
+
+    // Reports known from previous cycles win over the new ones
+    let mut known_reports: HashSet<(String, String)> = reports
+        .iter()
+        .map(|r| (r.key().0.to_owned(), r.key().1.to_owned()))
+        .collect();
+    let known_report_count = reports.len();
+    let known_error_count = xml_errors.len();
+    let new_xml_errors = parse_failures.len();
+    xml_errors.append(&mut parse_failures);
+    let mut new_duplicates = repeated_reports.len();
+    duplicates.append(&mut repeated_reports);
+    for report in parsed_reports {
+        let (org_name, report_id) = report.key();
+        if known_reports.insert((org_name.to_owned(), report_id.to_owned())) {
+            reports.push(report);
+        } else {
+            duplicates.push(DuplicateReport {
+                mail_uid: report.mail_uid,
+                org_name: org_name.to_owned(),
+                report_id: report_id.to_owned(),
+            });
+            new_duplicates += 1;
+        }
+    }
+
+    let new_reports = &reports[known_report_count..];
+    let new_report_count = new_reports.len();
+    info!("Parsed {new_report_count} new DMARC reports successfully");
+    if new_xml_errors > 0 {
+        warn!("Failed to parse {new_xml_errors} new XML files as DMARC reports");
+    }
+    if new_duplicates > 0 {
+        info!("Skipped {new_duplicates} new duplicate DMARC reports");
     }
 
     let timestamp = SystemTime::now()
@@ -83,18 +773,364 @@ async fn bg_update(config: &Configuration, state: &Arc<Mutex<AppState>>) -> Resu
         .context("Failed to get Unix time stamp")?
         .as_secs();
 
-    let summary = Summary::new(mails.len(), xml_files.len(), &reports, timestamp);
+    // All results of the cycle are applied at once, a cycle failing before leaves the state untouched
+    // and the data of the last successful cycle is served until the next one succeeds.
+    // The summary and the other analyses are computed without blocking updates of HTTP requests,
+    // so they are computed again if one of them changed the state in the meantime.
+    set_status(state, |s| s.phase = Phase::Saving);
+    let mails = Arc::new(mails);
+    let evicted_uids = Arc::new(evicted_uids);
+    let quarantine = Arc::new(quarantine);
+    let (new_sources, policy_changes, compliance_drops, reporter_gaps) =
+        state.update_concurrently(|locked_state| {
+            let (reports, xml_errors): (Vec<Report>, Vec<XmlError>) =
+                if locked_state.maintenance == maintenance {
+                    // Keep reports ingested via HTTP while this cycle was running
+                    let ingested = locked_state
+                        .reports
+                        .iter()
+                        .filter(|r| r.mail_uid.is_none())
+                        .skip(previous_ingested);
+                    (
+                        reports.iter().chain(ingested).cloned().collect(),
+                        xml_errors.clone(),
+                    )
+                } else {
+                    // Reports were purged or parsed again while this cycle was running,
+                    // so only the new results of this cycle are added to the changed ones
+                    (
+                        locked_state
+                            .reports
+                            .iter()
+                            .filter(|r| r.mail_uid.is_none_or(|uid| mails.contains_key(&uid)))
+                            .chain(&reports[known_report_count..])
+                            .cloned()
+                            .collect(),
+                        locked_state
+                            .xml_errors
+                            .iter()
+                            .filter(|e| mails.contains_key(&e.mail_uid))
+                            .chain(&xml_errors[known_error_count..])
+                            .cloned()
+                            .collect(),
+                    )
+                };
+            let duplicates = duplicates
+                .iter()
+                .chain(
+                    locked_state
+                        .duplicates
+                        .iter()
+                        .filter(|d| d.mail_uid.is_none()),
+                )
+                .cloned()
+                .collect();
 
-    {
-        let mut locked_state = state.lock().expect("Failed to lock app state");
-        locked_state.mails = mails;
-        locked_state.xml_files = xml_files.len();
-        locked_state.summary = summary;
-        locked_state.reports = reports;
-        locked_state.last_update = timestamp;
-        locked_state.xml_errors = xml_errors;
+            let new_sources =
+                Arc::make_mut(&mut locked_state.source_history).update(&reports, timestamp);
+            let policy_changes =
+                Arc::make_mut(&mut locked_state.policy_history).update(&reports, timestamp);
+
+            locked_state.mails = mails.clone();
+            locked_state.reports = Arc::new(reports);
+            locked_state.last_update = timestamp;
+            locked_state.revision += 1;
+            locked_state.xml_errors = Arc::new(xml_errors);
+            locked_state.duplicates = Arc::new(duplicates);
+            locked_state.evicted_uids = evicted_uids.clone();
+            locked_state.quarantine = quarantine.clone();
+            locked_state.ready = true;
+
+            // Rollups are updated first to include the reports removed by the retention limits
+            let added = Arc::make_mut(&mut locked_state.rollups).update(&locked_state.reports);
+            if added > 0 {
+                debug!("Added {added} daily rollups");
+            }
+            let retention = Retention::new(config);
+            if retention.is_enabled() {
+                let evicted = retention.apply(locked_state, timestamp);
+                if evicted.reports > 0 || evicted.mails > 0 {
+                    info!(
+                        "Removed {} reports and {} mails exceeding the retention limits",
+                        evicted.reports, evicted.mails
+                    );
+                }
+            }
+
+            let reports = Arc::make_mut(&mut locked_state.reports);
+            locked_state.allowlist.mark(reports);
+            locked_state.notes.mark(reports);
+
+            // Every XML file results either in a report, a duplicate or an error
+            let xml_file_count = locked_state.reports.len()
+                + locked_state.duplicates.len()
+                + locked_state.xml_errors.len();
+            locked_state.xml_files = xml_file_count;
+            locked_state.summary = Summary::new(
+                locked_state.unique_mail_count(),
+                xml_file_count,
+                &locked_state.reports,
+                locked_state.duplicates.len(),
+                timestamp,
+            );
+            let compliance = compliance_scores(
+                &locked_state.reports,
+                timestamp,
+                config.compliance_days * DAY,
+            );
+            let compliance_drops = match config.compliance_target {
+                Some(target) => locked_state.compliance.update(&compliance, target),
+                None => Vec::new(),
+            };
+            locked_state.summary.compliance = compliance;
+            let gaps = delivery_gaps(
+                &locked_state.reports,
+                &RecordFilter::default(),
+                timestamp,
+                config.reporter_gap_days,
+            );
+            let reporter_gaps = locked_state.reporter_gaps.update(&gaps);
+            if let Some(days) = config.trusted_learning_days {
+                let proposed = locked_state.trusted_learning.update(
+                    &locked_state.reports,
+                    &locked_state.allowlist,
+                    days,
+                );
+                if proposed > 0 {
+                    info!("Proposed {proposed} new trusted senders for the allowlist");
+                }
+            }
+            (new_sources, policy_changes, compliance_drops, reporter_gaps)
+        });
+
+    // The state is already updated at this point, so the cycle does not fail anymore
+    let state_json = if s3_archive.is_some() {
+        state
+            .original()
+            .to_json()
+            .inspect_err(|err| warn!("Failed to serialize state: {err:#}"))
+            .ok()
+    } else {
+        None
+    };
+
+    if new_report_count > 0 {
+        events.send(Event::NewReports {
+            count: new_report_count,
+        });
+    }
+    if new_xml_errors > 0 {
+        events.send(Event::ParseErrors {
+            count: new_xml_errors,
+        });
+    }
+
+    // Alerts are sent after publishing the new state, so slow channels do not delay the dashboard
+    if let Some(alert) = Alert::failures(new_reports, settings.failure_alert_threshold) {
+        notifier.send(&alert.with_state(state)).await;
+    }
+    if let Some(target) = &config.siem_target {
+        export_failures(target, config.siem_format, new_reports).await;
+    }
+    if let Some(alert) = Alert::parse_errors(&xml_errors[known_error_count..]) {
+        notifier.send(&alert.with_state(state)).await;
+    }
+    if let Some(alert) = Alert::panics(&panics) {
+        notifier.send(&alert.with_state(state)).await;
+    }
+    if let Some(alert) = Alert::new_sources(&new_sources) {
+        notifier.send(&alert.with_state(state)).await;
+    }
+    if let Some(alert) = Alert::policy_changes(&policy_changes) {
+        notifier.send(&alert.with_state(state)).await;
+    }
+    if let Some(target) = config.compliance_target {
+        if let Some(alert) = Alert::compliance_drops(&compliance_drops, target) {
+            notifier.send(&alert.with_state(state)).await;
+        }
+    }
+    if let Some(alert) = Alert::reporter_gaps(&reporter_gaps) {
+        notifier.send(&alert.with_state(state)).await;
+    }
+
+    if let Some(store) = store {
+        match store.save(state.original()).await {
+            Ok(..) => info!("Saved state to {}", store.name()),
+            Err(err) => warn!("Failed to save state to {}: {err:#}", store.name()),
+        }
     }
+
+    // Upload in background to not delay the next cycle with slow object storage
+    if let (Some(s3_archive), Some(json)) = (s3_archive, state_json) {
+        s3_uploads.push((String::from("dmarc-export.json"), json));
+        let s3_archive = s3_archive.clone();
+        tokio::spawn(async move {
+            match s3_archive.upload(s3_uploads).await {
+                Ok(count) => info!("Uploaded {count} files to S3 archive"),
+                Err(err) => error!("Failed to upload files to S3 archive: {err:#}"),
+            }
+        });
+    }
+
     info!("Finished updating shared state");
+    if backfill {
+        let progress = backfill_progress(known_uids.len(), received, &pending, !stopped_early);
+        if progress.finished {
+            info!("Finished backfill of {} mails", progress.total);
+        }
+        set_status(state, |s| s.backfill = Some(progress));
+    }
+
+    Ok(stopped_early)
+}
+
+/// Progress of the backfill with the mails known before and received in the cycle
+fn backfill_progress(
+    known: usize,
+    received: usize,
+    pending: &AtomicUsize,
+    finished: bool,
+) -> BackfillProgress {
+    BackfillProgress {
+        processed: known + received,
+        total: known + pending.load(Ordering::Relaxed).max(received),
+        finished,
+    }
+}
+
+/// Number of blocking tasks used to extract and parse files in parallel,
+/// the number of CPU cores if not configured
+fn worker_count(config: &Configuration) -> usize {
+    match config.parse_workers {
+        Some(workers) => usize::from(workers),
+        None => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+    }
+}
+
+/// Update the progress of the running cycle in the shared state
+fn set_status(state: &SharedState, update: impl FnOnce(&mut BackgroundStatus)) {
+    update(&mut state.status());
+}
+
+/// Run the work of a worker, a panic is returned as its message instead of unwinding
+fn catch_panic<T>(work: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(work)).map_err(|payload| panic_message(&*payload))
+}
+
+/// Message of a panic, formatted messages are strings and literal messages static strings
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<String>() {
+        Some(message) => message.clone(),
+        None => payload.downcast_ref::<&str>().map_or_else(
+            || String::from("Unknown panic"),
+            |message| message.to_string(),
+        ),
+    }
+}
+
+/// Record a caught panic in the background status
+fn record_panic(
+    state: &SharedState,
+    phase: Phase,
+    mail_uid: Option<u32>,
+    message: String,
+) -> TaskPanic {
+    let panic = TaskPanic {
+        phase,
+        mail_uid,
+        message,
+        time: unix_time(),
+    };
+    set_status(state, |s| s.record_panic(panic.clone()));
+    panic
+}
+
+/// Reload the shared state saved by the primary instance.
+/// The state is only loaded again if its version changed.
+async fn replica_update(
+    store: Option<&dyn StateStore>,
+    state: &Arc<SharedState>,
+    last_version: &mut Option<String>,
+) -> Result<()> {
+    let store = store.context("Read replica mode requires a state file or database")?;
+    let version = store
+        .version()
+        .await
+        .context("Failed to get version of stored state")?
+        .with_context(|| format!("No state found in {}", store.name()))?;
+    if last_version.as_ref() == Some(&version) {
+        info!("State in {} did not change since last update", store.name());
+        return Ok(());
+    }
+
+    let mut new_state = store
+        .load()
+        .await
+        .context("Failed to load state")?
+        .with_context(|| format!("No state found in {}", store.name()))?;
+    new_state.ready = true;
+    // The revision is not saved, it has to change for ETags and page cursors of the old state
+    new_state.revision = state.snapshot().revision + 1;
+    state.replace(new_state);
+    *last_version = Some(version);
+    info!("Loaded state from {}", store.name());
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use crate::state::AppState;
+    use clap::Parser;
+    use std::fs;
+
+    #[tokio::test]
+    async fn keep_imported_reports() {
+        let config =
+            Configuration::parse_from(["test", "--demo", "--http-server-password=password"]);
+
+        // Export of another instance with a mail of its own mailbox
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let mut report = parse_xml_file(&xml).unwrap();
+        report.report_metadata.report_id = String::from("imported");
+        report.report_metadata.date_range.end = unix_time();
+        report.report_metadata.date_range.begin = unix_time() - DAY;
+        report.mail_uid = Some(u32::MAX);
+        let mail = Mail {
+            uid: u32::MAX,
+            size: xml.len(),
+            oversized: false,
+            date: unix_time() as i64,
+            subject: String::from("Report of the other mailbox"),
+            sender: String::new(),
+            to: String::new(),
+            attachments: 1,
+            message_id: None,
+            body: None,
+            duplicate_of: None,
+        };
+        let exported = AppState {
+            mails: Arc::new(HashMap::from([(mail.uid, mail)])),
+            reports: Arc::new(vec![report]),
+            ..Default::default()
+        };
+
+        let state = Arc::new(SharedState::new(exported.imported()));
+        let notifier = Notifier::new(&config).unwrap();
+        let settings = SharedSettings::new(&config, None).unwrap();
+        run_once(&config, &state, &notifier, &settings)
+            .await
+            .unwrap();
+
+        let state = state.original();
+        assert!(!state.mails.contains_key(&u32::MAX));
+        let imported = state
+            .reports
+            .iter()
+            .find(|r| r.report_metadata.report_id == "imported")
+            .unwrap();
+        assert_eq!(imported.mail_uid, None);
+    }
+}
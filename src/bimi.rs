@@ -0,0 +1,169 @@
+use crate::dns::{record_tag, DnsResolver};
+use anyhow::{bail, ensure, Context, Result};
+use reqwest::{header, Client, Response, StatusCode};
+use serde::Serialize;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// Recommended maximum size of the logo in bytes
+const MAX_LOGO_SIZE: usize = 32 * 1024;
+
+/// Timeout for fetching the logo and the certificate in seconds
+const FETCH_TIMEOUT: u64 = 10;
+
+/// Result of checking the BIMI record of the default selector of a domain
+#[derive(Serialize, ToSchema)]
+pub struct BimiCheck {
+    /// TXT record at `default._bimi.<domain>`, empty if BIMI is not deployed
+    pub record: Option<String>,
+
+    /// URL of the SVG logo from the `l` tag
+    pub logo: Option<String>,
+
+    /// URL of the Verified Mark Certificate from the optional `a` tag
+    pub certificate: Option<String>,
+
+    /// Problems found in the record, the logo, the certificate or the DMARC policy
+    pub problems: Vec<String>,
+}
+
+pub fn client() -> Result<Client> {
+    Client::builder()
+        .timeout(Duration::from_secs(FETCH_TIMEOUT))
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+/// Check the BIMI record of the domain, the reachability of the logo and certificate
+/// and whether the DMARC policy is strict enough for mailbox providers to show the logo.
+pub async fn check_domain(dns: &DnsResolver, client: &Client, domain: &str) -> BimiCheck {
+    let mut check = BimiCheck {
+        record: None,
+        logo: None,
+        certificate: None,
+        problems: Vec::new(),
+    };
+    let name = format!("default._bimi.{domain}");
+    let mut records: Vec<String> = match dns.txt(&name).await {
+        Ok(records) => records
+            .into_iter()
+            .filter(|r| r.trim_start().starts_with("v=BIMI1"))
+            .collect(),
+        Err(err) => {
+            check.problems.push(format!("{err:#}"));
+            return check;
+        }
+    };
+    if records.len() > 1 {
+        check.problems.push(format!("Multiple records at {name}"));
+        return check;
+    }
+    let Some(record) = records.pop() else {
+        return check;
+    };
+    check.logo = record_tag(&record, "l");
+    check.certificate = record_tag(&record, "a");
+    check.record = Some(record);
+
+    if let Err(err) = check_dmarc_policy(dns, domain).await {
+        check.problems.push(format!("{err:#}"));
+    }
+    match &check.logo {
+        Some(logo) => {
+            if let Err(err) = check_logo(client, logo).await {
+                check.problems.push(format!("{err:#}"));
+            }
+        }
+        None => check
+            .problems
+            .push("Record has no logo, BIMI is declined for the domain".to_owned()),
+    }
+    if let Some(certificate) = &check.certificate {
+        if let Err(err) = check_certificate(client, certificate).await {
+            check.problems.push(format!("{err:#}"));
+        }
+    }
+    check
+}
+
+/// BIMI requires a DMARC policy of quarantine or reject applied to all messages
+async fn check_dmarc_policy(dns: &DnsResolver, domain: &str) -> Result<()> {
+    let record = dns
+        .txt(&format!("_dmarc.{domain}"))
+        .await?
+        .into_iter()
+        .find(|r| r.trim_start().starts_with("v=DMARC1"))
+        .context("No DMARC record, BIMI requires DMARC enforcement")?;
+    let policy = record_tag(&record, "p").unwrap_or_default().to_lowercase();
+    ensure!(
+        policy == "quarantine" || policy == "reject",
+        "DMARC policy p={policy} is not strict enough for BIMI, quarantine or reject is required"
+    );
+    if let Some(pct) = record_tag(&record, "pct") {
+        ensure!(
+            pct == "100",
+            "DMARC policy pct={pct} is not strict enough for BIMI, 100 is required"
+        );
+    }
+    Ok(())
+}
+
+async fn fetch(client: &Client, url: &str, kind: &str) -> Result<Response> {
+    ensure!(
+        url.starts_with("https://"),
+        "URL {url} of the {kind} is not HTTPS"
+    );
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch {kind} from {url}"))?;
+    if response.status() != StatusCode::OK {
+        bail!(
+            "Failed to fetch {kind} from {url}: HTTP status {}",
+            response.status()
+        );
+    }
+    Ok(response)
+}
+
+/// The logo has to be an SVG in the Tiny Portable/Secure profile
+async fn check_logo(client: &Client, url: &str) -> Result<()> {
+    let response = fetch(client, url, "logo").await?;
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    ensure!(
+        content_type.starts_with("image/svg+xml"),
+        "Logo is served with content type {content_type:?} instead of image/svg+xml"
+    );
+    let svg = response.bytes().await.context("Failed to read logo")?;
+    ensure!(
+        svg.len() <= MAX_LOGO_SIZE,
+        "Logo has {} bytes, more than the recommended {MAX_LOGO_SIZE} bytes",
+        svg.len()
+    );
+    let svg = String::from_utf8_lossy(&svg);
+    ensure!(svg.contains("<svg"), "Logo is not an SVG image");
+    ensure!(
+        svg.contains("tiny-ps"),
+        "Logo does not use the SVG Tiny PS profile"
+    );
+    Ok(())
+}
+
+async fn check_certificate(client: &Client, url: &str) -> Result<()> {
+    let response = fetch(client, url, "certificate").await?;
+    let pem = response
+        .text()
+        .await
+        .context("Failed to read certificate")?;
+    ensure!(
+        pem.contains("-----BEGIN CERTIFICATE-----"),
+        "Certificate at {url} is not in PEM format"
+    );
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use crate::explain::AlignmentMode;
+use crate::filter::RecordFilter;
+use crate::report::{DkimResultType, PolicyOverrideType, RecordType, Report, SpfResultType};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+/// Most likely reason why a record failed DMARC, each one with a different fix
+#[derive(Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCause {
+    /// Forwarded by a mailing list or forwarder that broke the authentication,
+    /// as told by the override reason of the receiver or an ARC result
+    Forwarded,
+
+    /// SPF passed for the envelope from domain of another organization, like the bounce domain of a mail service
+    SpfNotAligned,
+
+    /// DKIM passed for a signature of another organization, like the default domain of a mail service
+    DkimNotAligned,
+
+    /// The envelope from domain is aligned but SPF failed and the message was not signed at all
+    DkimMissing,
+
+    /// An aligned signature or envelope from domain was used, but DKIM and SPF failed
+    BothFailed,
+
+    /// No identifier of the header from domain was used and nothing passed
+    LikelySpoof,
+}
+
+/// Failing messages per cause
+#[derive(Serialize, Default, ToSchema)]
+pub struct FailureCauseCounts {
+    /// Messages failing DMARC
+    pub failed: usize,
+    pub forwarded: usize,
+    pub spf_not_aligned: usize,
+    pub dkim_not_aligned: usize,
+    pub dkim_missing: usize,
+    pub both_failed: usize,
+    pub likely_spoof: usize,
+}
+
+impl FailureCauseCounts {
+    fn add(&mut self, cause: FailureCause, count: usize) {
+        self.failed += count;
+        *match cause {
+            FailureCause::Forwarded => &mut self.forwarded,
+            FailureCause::SpfNotAligned => &mut self.spf_not_aligned,
+            FailureCause::DkimNotAligned => &mut self.dkim_not_aligned,
+            FailureCause::DkimMissing => &mut self.dkim_missing,
+            FailureCause::BothFailed => &mut self.both_failed,
+            FailureCause::LikelySpoof => &mut self.likely_spoof,
+        } += count;
+    }
+}
+
+/// Causes of failing messages in total and per domain
+#[derive(Serialize, Default, ToSchema)]
+pub struct FailureCauseSummary {
+    pub total: FailureCauseCounts,
+    pub domains: BTreeMap<String, FailureCauseCounts>,
+}
+
+/// Classify a record failing DMARC by its auth results and the override reasons of the receiver
+pub fn failure_cause(report: &Report, record: &RecordType) -> FailureCause {
+    let evaluated = &record.row.policy_evaluated;
+    let auth = &record.auth_results;
+    let forwarded = evaluated.reason.iter().flatten().any(|r| {
+        matches!(
+            r.kind,
+            PolicyOverrideType::Forwarded
+                | PolicyOverrideType::TrustedForwarder
+                | PolicyOverrideType::MailingList
+        )
+    });
+    if forwarded
+        || auth
+            .arc
+            .iter()
+            .any(|a| a.result.eq_ignore_ascii_case("pass"))
+    {
+        return FailureCause::Forwarded;
+    }
+
+    let policy = &report.policy_published;
+    let header_from = &record.identifiers.header_from;
+    let dkim_mode = AlignmentMode::new(policy.adkim.as_ref());
+    let spf_mode = AlignmentMode::new(policy.aspf.as_ref());
+    let signatures = auth.dkim.as_deref().unwrap_or_default();
+    if auth.spf.iter().any(|s| s.result == SpfResultType::Pass) {
+        return FailureCause::SpfNotAligned;
+    }
+    if signatures.iter().any(|d| d.result == DkimResultType::Pass) {
+        return FailureCause::DkimNotAligned;
+    }
+    let aligned_signature = signatures
+        .iter()
+        .any(|d| dkim_mode.aligns(&d.domain, header_from));
+    let aligned_spf = auth
+        .spf
+        .iter()
+        .any(|s| spf_mode.aligns(&s.domain, header_from));
+    if signatures.is_empty() && aligned_spf {
+        FailureCause::DkimMissing
+    } else if aligned_signature || aligned_spf {
+        FailureCause::BothFailed
+    } else {
+        FailureCause::LikelySpoof
+    }
+}
+
+/// Count the messages of all matching records failing DMARC per cause
+pub fn failure_cause_summary(reports: &[Report], filter: &RecordFilter) -> FailureCauseSummary {
+    let mut summary = FailureCauseSummary::default();
+    for (report, record) in filter.records(reports) {
+        if record.is_dmarc_pass() {
+            continue;
+        }
+        let cause = failure_cause(report, record);
+        let count = record.row.count;
+        let domain = filter.group_domain(&report.policy_published.domain);
+        summary.total.add(cause, count);
+        summary.domains.entry(domain).or_default().add(cause, count);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use crate::report::{DmarcResultType, PolicyOverrideReason};
+    use std::fs;
+
+    #[test]
+    fn classify_failures() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let mut report = parse_xml_file(&xml).unwrap();
+        let record = &mut report.record[0];
+        record.row.policy_evaluated.spf = Some(DmarcResultType::Fail);
+        record.auth_results.dkim.as_mut().unwrap()[0].domain = String::from("example.net");
+        record.auth_results.spf[0].domain = String::from("bounces.example.net");
+        let spf_not_aligned = record.clone();
+
+        record.auth_results.spf[0].result = SpfResultType::Fail;
+        let spoof = record.clone();
+
+        record.auth_results.spf[0].domain = String::from("example.com");
+        let both_failed = record.clone();
+
+        record.auth_results.dkim = None;
+        let dkim_missing = record.clone();
+
+        record.row.policy_evaluated.reason = Some(vec![PolicyOverrideReason {
+            kind: PolicyOverrideType::MailingList,
+            comment: None,
+        }]);
+        let forwarded = record.clone();
+
+        report.record = vec![spf_not_aligned, spoof, both_failed, dkim_missing, forwarded];
+        let causes: Vec<FailureCause> = report
+            .record
+            .iter()
+            .map(|r| failure_cause(&report, r))
+            .collect();
+        assert_eq!(
+            causes,
+            vec![
+                FailureCause::SpfNotAligned,
+                FailureCause::LikelySpoof,
+                FailureCause::BothFailed,
+                FailureCause::DkimMissing,
+                FailureCause::Forwarded,
+            ]
+        );
+        let summary = failure_cause_summary(&[report], &RecordFilter::default());
+        assert_eq!(summary.total.failed, 10);
+        assert_eq!(summary.domains["example.com"].likely_spoof, 2);
+    }
+}
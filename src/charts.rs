@@ -0,0 +1,117 @@
+use crate::filter::RecordFilter;
+use crate::report::{DispositionType, Report};
+use crate::timeseries::{time_series, Interval, Timezone};
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Labels and datasets in the structure expected by Chart.js
+#[derive(Serialize, ToSchema)]
+pub struct ChartData {
+    pub labels: Vec<String>,
+    pub datasets: Vec<Dataset>,
+}
+
+/// Values of a single series, one per label
+#[derive(Serialize, ToSchema)]
+pub struct Dataset {
+    pub label: String,
+    pub data: Vec<usize>,
+}
+
+impl Dataset {
+    fn new(label: &str, data: Vec<usize>) -> Self {
+        Self {
+            label: label.to_owned(),
+            data,
+        }
+    }
+}
+
+/// Passed and failed messages per local day for a stacked bar chart
+pub fn daily_chart(reports: &[Report], filter: &RecordFilter, timezone: Timezone) -> ChartData {
+    let buckets = time_series(reports, filter, Interval::Daily, timezone);
+    ChartData {
+        labels: buckets.iter().map(|b| timezone.date(b.start)).collect(),
+        datasets: vec![
+            Dataset::new("Passed", buckets.iter().map(|b| b.passed).collect()),
+            Dataset::new("Failed", buckets.iter().map(|b| b.failed).collect()),
+        ],
+    }
+}
+
+/// Messages per applied disposition for a pie chart
+pub fn disposition_chart(reports: &[Report], filter: &RecordFilter) -> ChartData {
+    let mut counts = [0; 3];
+    for (_, record) in filter.records(reports) {
+        let index = match record.row.policy_evaluated.disposition {
+            DispositionType::None => 0,
+            DispositionType::Quarantine => 1,
+            DispositionType::Reject => 2,
+            DispositionType::Unknown(..) => continue,
+        };
+        counts[index] += record.row.count;
+    }
+    ChartData {
+        labels: vec!["none".into(), "quarantine".into(), "reject".into()],
+        datasets: vec![Dataset::new("Messages", counts.to_vec())],
+    }
+}
+
+/// Passed and failed messages of the source IPs with the most messages for a bar chart
+pub fn top_ips_chart(reports: &[Report], filter: &RecordFilter, limit: usize) -> ChartData {
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+    for (_, record) in filter.records(reports) {
+        let entry = counts
+            .entry(filter.group_source(record.row.source_ip))
+            .or_default();
+        if record.is_dmarc_pass() {
+            entry.0 += record.row.count;
+        } else {
+            entry.1 += record.row.count;
+        }
+    }
+    let mut ips: Vec<(String, (usize, usize))> = counts.into_iter().collect();
+    ips.sort_by(|(a, (a_passed, a_failed)), (b, (b_passed, b_failed))| {
+        (b_passed + b_failed)
+            .cmp(&(a_passed + a_failed))
+            .then(a.cmp(b))
+    });
+    ips.truncate(limit);
+    ChartData {
+        datasets: vec![
+            Dataset::new("Passed", ips.iter().map(|(_, (p, _))| *p).collect()),
+            Dataset::new("Failed", ips.iter().map(|(_, (_, f))| *f).collect()),
+        ],
+        labels: ips.into_iter().map(|(ip, _)| ip).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn top_ips_by_messages() {
+        let reports: Vec<Report> = ["acme.xml", "aol.xml", "google.xml"]
+            .iter()
+            .map(|f| fs::read(format!("testdata/dmarc-reports/{f}")).unwrap())
+            .map(|xml| parse_xml_file(&xml).unwrap())
+            .collect();
+        let chart = top_ips_chart(&reports, &RecordFilter::default(), 2);
+        assert_eq!(chart.labels.len(), 2);
+        let totals: Vec<usize> = (0..2)
+            .map(|i| chart.datasets[0].data[i] + chart.datasets[1].data[i])
+            .collect();
+        assert!(totals[0] >= totals[1]);
+        let all: usize = reports
+            .iter()
+            .flat_map(|r| &r.record)
+            .map(|r| r.row.count)
+            .max()
+            .unwrap();
+        assert!(totals[0] >= all);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::notifications::{Alert, NotificationChannel, Templates};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Maximum message length accepted by Discord webhooks
+const DISCORD_MAX_LENGTH: usize = 2000;
+
+/// Sends alerts to a Slack incoming webhook
+pub struct SlackSender {
+    client: Client,
+    url: String,
+}
+
+impl SlackSender {
+    pub fn new(client: Client, url: &str) -> Self {
+        Self {
+            client,
+            url: url.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for SlackSender {
+    fn name(&self) -> &'static str {
+        "Slack"
+    }
+
+    async fn send(&self, templates: &Templates, alert: &Alert) -> Result<()> {
+        let message = templates.render("chat", alert, "message")?;
+        self.client
+            .post(&self.url)
+            .json(&json!({ "text": message.trim() }))
+            .send()
+            .await
+            .context("Failed to send Slack request")?
+            .error_for_status()
+            .context("Slack request failed")?;
+        Ok(())
+    }
+}
+
+/// Sends alerts to a Discord webhook
+pub struct DiscordSender {
+    client: Client,
+    url: String,
+}
+
+impl DiscordSender {
+    pub fn new(client: Client, url: &str) -> Self {
+        Self {
+            client,
+            url: url.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for DiscordSender {
+    fn name(&self) -> &'static str {
+        "Discord"
+    }
+
+    async fn send(&self, templates: &Templates, alert: &Alert) -> Result<()> {
+        let message = templates.render("chat", alert, "message")?;
+        let content: String = message.trim().chars().take(DISCORD_MAX_LENGTH).collect();
+        self.client
+            .post(&self.url)
+            .json(&json!({ "content": content }))
+            .send()
+            .await
+            .context("Failed to send Discord request")?
+            .error_for_status()
+            .context("Discord request failed")?;
+        Ok(())
+    }
+}
+
+/// Sends alerts as text messages to a Matrix room
+pub struct MatrixSender {
+    client: Client,
+    homeserver: Url,
+    room_id: String,
+    access_token: String,
+}
+
+impl MatrixSender {
+    pub fn new(
+        client: Client,
+        homeserver: &str,
+        room_id: &str,
+        access_token: &str,
+    ) -> Result<Self> {
+        let homeserver = Url::parse(homeserver).context("Failed to parse Matrix homeserver URL")?;
+        Ok(Self {
+            client,
+            homeserver,
+            room_id: room_id.to_owned(),
+            access_token: access_token.to_owned(),
+        })
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for MatrixSender {
+    fn name(&self) -> &'static str {
+        "Matrix"
+    }
+
+    async fn send(&self, templates: &Templates, alert: &Alert) -> Result<()> {
+        let message = templates.render("chat", alert, "message")?;
+        // Transaction ID must be unique per access token to avoid deduplication by the server
+        let txn_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("Failed to get Unix time stamp")?
+            .as_nanos()
+            .to_string();
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Matrix homeserver URL"))?
+            .pop_if_empty()
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                &self.room_id,
+                "send",
+                "m.room.message",
+                &txn_id,
+            ]);
+        self.client
+            .put(url)
+            .bearer_auth(&self.access_token)
+            .json(&json!({ "msgtype": "m.text", "body": message.trim() }))
+            .send()
+            .await
+            .context("Failed to send Matrix request")?
+            .error_for_status()
+            .context("Matrix request failed")?;
+        Ok(())
+    }
+}
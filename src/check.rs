@@ -0,0 +1,233 @@
+use crate::config::{AcmeChallenge, Configuration, MailProtocol};
+use crate::notifications::Notifier;
+use crate::s3::s3_store;
+use crate::settings::SharedSettings;
+use crate::source::{check_inbox, configured};
+use crate::state::AppState;
+use crate::storage::{PostgresStore, StateStore};
+use crate::users::Users;
+use anyhow::{bail, ensure, Context, Result};
+use reqwest::Client;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::path::Path;
+use std::time::Duration;
+
+/// Maximum time to wait for the health endpoint
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Validate the configuration and the connectivity to the IMAP server,
+/// print the result of every check and fail if any of them failed.
+pub async fn run_check(config: &Configuration) -> Result<()> {
+    let mut failed = 0;
+    let mut report = |name: &str, result: Result<String>| match result {
+        Ok(details) => println!("OK    {name}: {details}"),
+        Err(err) => {
+            println!("FAIL  {name}: {err:#}");
+            failed += 1;
+        }
+    };
+
+    if config.read_replica {
+        report("IMAP", Ok(String::from("Skipped for read replica")));
+    } else {
+        report("IMAP", check_imap(config).await);
+    }
+    match config.http_listeners() {
+        Ok(listeners) => {
+            for (addr, _) in &listeners {
+                report("HTTP", check_bind(*addr));
+            }
+            let tls = listeners.iter().find(|(_, tls)| *tls);
+            if let Some((addr, _)) = tls.filter(|_| {
+                config.https_auto_cert
+                    && matches!(config.https_auto_cert_challenge, AcmeChallenge::Http01)
+            }) {
+                let challenge = SocketAddr::new(addr.ip(), config.https_auto_cert_http_port);
+                report("ACME Challenge", check_bind(challenge));
+            }
+        }
+        Err(err) => report("HTTP", Err(err)),
+    }
+    report("HTTP Users", check_users(config));
+    report("State File", check_state_file(config));
+    report("State Database", check_state_database(config).await);
+    report("Settings", check_settings(config));
+    report("Notifications", check_notifications(config));
+    report("S3", check_s3(config));
+
+    if failed > 0 {
+        bail!("{failed} checks failed");
+    }
+    println!("All checks passed");
+    Ok(())
+}
+
+/// Request `/healthz` of the instance running with the same configuration.
+/// Plain HTTP listeners are preferred, the certificate of a TLS listener is not verified
+/// since it is issued for the public domain and not for the local address.
+pub async fn run_healthcheck(config: &Configuration) -> Result<()> {
+    let listeners = config.http_listeners()?;
+    let (addr, tls) = listeners
+        .iter()
+        .find(|(_, tls)| !tls)
+        .or(listeners.first())
+        .copied()
+        .context("No HTTP listener configured")?;
+    let url = healthcheck_url(addr, tls, &config.http_base_path);
+    let client = Client::builder()
+        .timeout(HEALTHCHECK_TIMEOUT)
+        .danger_accept_invalid_certs(tls)
+        .build()
+        .context("Failed to create HTTP client")?;
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to request {url}"))?;
+    let status = response.status();
+    ensure!(
+        status.is_success(),
+        "Health check {url} failed with status {status}"
+    );
+    println!("Health check {url} succeeded");
+    Ok(())
+}
+
+/// Health endpoint of a listener, unspecified addresses are reached via loopback
+fn healthcheck_url(addr: SocketAddr, tls: bool, base_path: &str) -> String {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    let scheme = if tls { "https" } else { "http" };
+    let addr = SocketAddr::new(ip, addr.port());
+    format!("{scheme}://{addr}{base_path}/healthz")
+}
+
+async fn check_imap(config: &Configuration) -> Result<String> {
+    if config.demo {
+        let mails = check_inbox(config).await?;
+        return Ok(format!("Demo mode, {mails} simulated mails"));
+    }
+    let port = match config.mail_protocol {
+        MailProtocol::Imap => config.imap_port,
+        MailProtocol::Pop3 => config.pop3_port,
+        MailProtocol::Graph => {
+            let mails = check_inbox(config).await?;
+            return Ok(format!(
+                "Got access to mailbox {}, {mails} mails in folder {}",
+                config.graph_mailbox.as_deref().unwrap_or_default(),
+                config.graph_folder
+            ));
+        }
+        MailProtocol::Gmail => {
+            let mails = check_inbox(config).await?;
+            return Ok(format!(
+                "Got access to Gmail user {}, {mails} mails with label {}",
+                config.gmail_user, config.gmail_label
+            ));
+        }
+    };
+    if !configured(config) && config.ingest_dir.is_some() {
+        return Ok(String::from(
+            "Not configured, reports are only read from the ingest directory",
+        ));
+    }
+    if config.imap_host.is_empty() || config.imap_user.is_empty() {
+        bail!("IMAP host and user must be configured");
+    }
+    let mails = check_inbox(config).await?;
+    Ok(format!(
+        "Logged in as {} at {}:{port}, {mails} mails in INBOX",
+        config.imap_user, config.imap_host
+    ))
+}
+
+/// Binds to the address and releases it again immediately
+fn check_bind(addr: SocketAddr) -> Result<String> {
+    TcpListener::bind(addr).with_context(|| format!("Failed to bind to {addr}"))?;
+    Ok(format!("Address {addr} is available"))
+}
+
+fn check_users(config: &Configuration) -> Result<String> {
+    let users = Users::from_config(config)?;
+    if users.auth_disabled() {
+        Ok(String::from("Authentication is disabled"))
+    } else {
+        Ok(format!("{} users configured", users.count()))
+    }
+}
+
+fn check_state_file(config: &Configuration) -> Result<String> {
+    let Some(path) = &config.state_file else {
+        return Ok(String::from("Not configured"));
+    };
+    if !Path::new(path).exists() {
+        if config.read_replica {
+            bail!("State file {path} does not exist");
+        }
+        return Ok(format!("State file {path} will be created"));
+    }
+    let state = AppState::load(path)?;
+    Ok(format!(
+        "Loaded {} reports from {path}",
+        state.reports.len()
+    ))
+}
+
+/// Connects to the database, which applies pending migrations
+async fn check_state_database(config: &Configuration) -> Result<String> {
+    let Some(database) = &config.state_database_url else {
+        return Ok(String::from("Not configured"));
+    };
+    let store = PostgresStore::new(database.clone());
+    match store.version().await? {
+        Some(version) => Ok(format!("State version {version} in {}", store.name())),
+        None if config.read_replica => bail!("No state saved in {}", store.name()),
+        None => Ok(format!("State will be saved in {}", store.name())),
+    }
+}
+
+fn check_settings(config: &Configuration) -> Result<String> {
+    let settings = SharedSettings::new(config, None)?.get();
+    Ok(format!(
+        "Check interval {} secs, log level {}",
+        settings.imap_check_interval, settings.log_level
+    ))
+}
+
+fn check_notifications(config: &Configuration) -> Result<String> {
+    let channels = Notifier::new(config)?.channel_names();
+    if channels.is_empty() {
+        Ok(String::from("No channels configured"))
+    } else {
+        Ok(format!("Channels {}", channels.join(", ")))
+    }
+}
+
+fn check_s3(config: &Configuration) -> Result<String> {
+    match s3_store(config)? {
+        Some(..) => Ok(String::from("Object storage client created")),
+        None => Ok(String::from("Not configured")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_health_url() {
+        let addr: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        assert_eq!(
+            healthcheck_url(addr, false, ""),
+            "http://127.0.0.1:8080/healthz"
+        );
+        let addr: SocketAddr = "[::]:8443".parse().unwrap();
+        assert_eq!(
+            healthcheck_url(addr, true, "/dmarc"),
+            "https://[::1]:8443/dmarc/healthz"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use anyhow::{ensure, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+/// Prefix lengths for grouping source IPs, large providers rotate addresses within such networks
+const GROUP_PREFIX_V4: u8 = 24;
+const GROUP_PREFIX_V6: u8 = 48;
+
+/// Prefix length of the IPv4-mapped IPv6 addresses `::ffff:0:0/96`
+const MAPPED_PREFIX: u8 = 96;
+
+/// IP network in CIDR notation like `192.0.2.0/24` or `2001:db8::/32`.
+/// Single IPs without prefix length are networks with only this address.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Network with the prefix length containing the IP, host bits are cleared.
+    /// IPv4-mapped IPv6 networks within the mapped range are networks of their IPv4 address,
+    /// with the prefix length reduced by the 96 bits of the mapping prefix.
+    pub fn new(ip: IpAddr, prefix: u8) -> Self {
+        let (ip, prefix) = match ip.to_canonical() {
+            IpAddr::V4(mapped) if ip.is_ipv6() && prefix >= MAPPED_PREFIX => {
+                (IpAddr::V4(mapped), prefix - MAPPED_PREFIX)
+            }
+            IpAddr::V4(..) if ip.is_ipv6() => (ip, prefix),
+            canonical => (canonical, prefix),
+        };
+        let prefix = prefix.min(max_prefix(&ip));
+        let addr = match ip {
+            IpAddr::V4(ip) => IpAddr::V4((u32::from(ip) & mask_v4(prefix)).into()),
+            IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & mask_v6(prefix)).into()),
+        };
+        Self { addr, prefix }
+    }
+
+    /// The /24 network of IPv4 sources or the network of IPv6 sources with the prefix length,
+    /// /48 if not set, used to aggregate source IPs
+    pub fn group(ip: IpAddr, prefix_v6: Option<u8>) -> Self {
+        let ip = ip.to_canonical();
+        match ip {
+            IpAddr::V4(_) => Self::new(ip, GROUP_PREFIX_V4),
+            IpAddr::V6(_) => Self::new(ip, prefix_v6.unwrap_or(GROUP_PREFIX_V6)),
+        }
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, &ip.to_canonical()) {
+            (IpAddr::V4(addr), IpAddr::V4(ip)) => {
+                u32::from(*ip) & mask_v4(self.prefix) == u32::from(addr)
+            }
+            (IpAddr::V6(addr), IpAddr::V6(ip)) => {
+                u128::from(*ip) & mask_v6(self.prefix) == u128::from(addr)
+            }
+            // Only IPv6 networks shorter than the mapping prefix contain IPv4-mapped addresses
+            (IpAddr::V6(addr), IpAddr::V4(ip)) => {
+                u128::from(ip.to_ipv6_mapped()) & mask_v6(self.prefix) == u128::from(addr)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn max_prefix(ip: &IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn mask_v4(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+}
+
+fn mask_v6(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0)
+}
+
+impl FromStr for IpNetwork {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (ip, prefix) = match value.trim().split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix)),
+            None => (value.trim(), None),
+        };
+        let ip: IpAddr = ip
+            .parse()
+            .with_context(|| format!("Invalid IP in network {value}"))?;
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .with_context(|| format!("Invalid prefix length in network {value}"))?,
+            None => max_prefix(&ip),
+        };
+        ensure!(
+            prefix <= max_prefix(&ip),
+            "Prefix length of network {value} is too long"
+        );
+        Ok(Self::new(ip, prefix))
+    }
+}
+
+impl Display for IpNetwork {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<IpNetwork> for String {
+    fn from(network: IpNetwork) -> Self {
+        network.to_string()
+    }
+}
+
+/// Comma separated list of networks of both address families, like `192.0.2.0/24,2001:db8::/32`
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNetworks(pub Vec<IpNetwork>);
+
+impl IpNetworks {
+    /// Checks if any of the networks contains the IP
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+}
+
+impl FromStr for IpNetworks {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let networks = value
+            .split(',')
+            .map(str::trim)
+            .filter(|network| !network.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<IpNetwork>>>()?;
+        ensure!(!networks.is_empty(), "No network in '{value}'");
+        Ok(Self(networks))
+    }
+}
+
+impl Display for IpNetworks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let networks: Vec<String> = self.0.iter().map(IpNetwork::to_string).collect();
+        write!(f, "{}", networks.join(","))
+    }
+}
+
+impl TryFrom<String> for IpNetworks {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<IpNetworks> for String {
+    fn from(networks: IpNetworks) -> Self {
+        networks.to_string()
+    }
+}
+
+/// Address family of source IPs, IPv4-mapped IPv6 addresses count as IPv4
+#[derive(Clone, Copy, PartialEq, Debug, Deserialize, ToSchema)]
+pub enum IpVersion {
+    #[serde(rename = "4", alias = "ipv4")]
+    V4,
+    #[serde(rename = "6", alias = "ipv6")]
+    V6,
+}
+
+impl IpVersion {
+    pub fn matches(self, ip: &IpAddr) -> bool {
+        match self {
+            Self::V4 => ip.to_canonical().is_ipv4(),
+            Self::V6 => ip.to_canonical().is_ipv6(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn networks() {
+        let network: IpNetwork = "192.0.2.77/24".parse().unwrap();
+        assert_eq!(network.to_string(), "192.0.2.0/24");
+        assert!(network.contains(&"192.0.2.1".parse().unwrap()));
+        assert!(!network.contains(&"192.0.3.1".parse().unwrap()));
+        assert!(!network.contains(&"::1".parse().unwrap()));
+
+        let network: IpNetwork = "2001:db8:1::/48".parse().unwrap();
+        assert!(network.contains(&"2001:db8:1:2::1".parse().unwrap()));
+        assert!(!network.contains(&"2001:db8:2::1".parse().unwrap()));
+
+        let host: IpNetwork = "198.51.100.1".parse().unwrap();
+        assert_eq!(host.to_string(), "198.51.100.1/32");
+        assert!("0.0.0.0/0"
+            .parse::<IpNetwork>()
+            .unwrap()
+            .contains(&"8.8.8.8".parse().unwrap()));
+        assert!("192.0.2.0/33".parse::<IpNetwork>().is_err());
+        let ip = "2001:db8:1:2::1".parse().unwrap();
+        assert_eq!(IpNetwork::group(ip, None).to_string(), "2001:db8:1::/48");
+        assert_eq!(
+            IpNetwork::group(ip, Some(64)).to_string(),
+            "2001:db8:1:2::/64"
+        );
+
+        // IPv4 sources reported as IPv4-mapped IPv6 addresses
+        let mapped = "::ffff:192.0.2.1".parse().unwrap();
+        assert!("192.0.2.0/24"
+            .parse::<IpNetwork>()
+            .unwrap()
+            .contains(&mapped));
+        assert_eq!(
+            IpNetwork::group(mapped, Some(64)).to_string(),
+            "192.0.2.0/24"
+        );
+        assert!(IpVersion::V4.matches(&mapped));
+        let network: IpNetwork = "::ffff:192.0.2.0/120".parse().unwrap();
+        assert_eq!(network.to_string(), "192.0.2.0/24");
+        assert!(network.contains(&mapped));
+        assert!(network.contains(&"192.0.2.200".parse().unwrap()));
+        assert!(!network.contains(&"192.0.3.1".parse().unwrap()));
+        let network: IpNetwork = "::ffff:0:0/96".parse().unwrap();
+        assert_eq!(network.to_string(), "0.0.0.0/0");
+        let network: IpNetwork = "::/64".parse().unwrap();
+        assert!(network.contains(&mapped));
+        assert!(!"2001:db8::/32"
+            .parse::<IpNetwork>()
+            .unwrap()
+            .contains(&mapped));
+
+        let networks: IpNetworks = "192.0.2.0/24, 2001:db8::/32".parse().unwrap();
+        assert_eq!(networks.to_string(), "192.0.2.0/24,2001:db8::/32");
+        assert!(networks.contains(&mapped));
+        assert!(networks.contains(&ip));
+        assert!(!networks.contains(&"198.51.100.1".parse().unwrap()));
+        assert!(",".parse::<IpNetworks>().is_err());
+    }
+}
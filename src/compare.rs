@@ -0,0 +1,142 @@
+use crate::domains::{domain_stats, DomainStats};
+use crate::filter::RecordFilter;
+use crate::report::Report;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// Time window with inclusive Unix timestamps
+#[derive(Serialize, Clone, Copy, Debug, ToSchema)]
+pub struct Window {
+    pub since: u64,
+    pub until: u64,
+}
+
+impl Window {
+    /// Window of the same length ending right before this one
+    pub fn previous(&self) -> Self {
+        let length = self.until.saturating_sub(self.since) + 1;
+        Self {
+            since: self.since.saturating_sub(length),
+            until: self.since.saturating_sub(1),
+        }
+    }
+}
+
+/// Message counts of a domain within one window
+#[derive(Serialize, Default, ToSchema)]
+pub struct PeriodStats {
+    pub messages: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub pass_rate: f64,
+    pub sources: usize,
+}
+
+/// Changes of a domain between the previous and the current window
+#[derive(Serialize, ToSchema)]
+pub struct DomainComparison {
+    pub domain: String,
+    pub current: PeriodStats,
+    pub previous: PeriodStats,
+    pub messages_change: i64,
+    pub failed_change: i64,
+
+    /// Difference of the pass rates in percentage points
+    pub pass_rate_change: f64,
+
+    /// Source IPs only seen in the current window
+    #[schema(value_type = Vec<String>)]
+    pub new_sources: Vec<IpAddr>,
+
+    /// Source IPs only seen in the previous window
+    #[schema(value_type = Vec<String>)]
+    pub disappeared_sources: Vec<IpAddr>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Comparison {
+    pub current: Window,
+    pub previous: Window,
+    pub domains: Vec<DomainComparison>,
+}
+
+/// Compare all domains with reports overlapping one of the windows.
+/// The result is sorted by domain name.
+pub fn compare(
+    reports: &[Report],
+    current: Window,
+    previous: Window,
+    domain: Option<String>,
+) -> Comparison {
+    let stats = |window: Window| -> BTreeMap<String, DomainStats> {
+        let filter = RecordFilter {
+            domain: domain.clone(),
+            since: Some(window.since),
+            until: Some(window.until),
+            ..Default::default()
+        };
+        domain_stats(reports, &filter)
+            .into_iter()
+            .map(|d| (d.domain.clone(), d))
+            .collect()
+    };
+    let mut current_stats = stats(current);
+    let mut previous_stats = stats(previous);
+    let names: BTreeSet<String> = current_stats
+        .keys()
+        .chain(previous_stats.keys())
+        .cloned()
+        .collect();
+    let domains = names
+        .into_iter()
+        .map(|name| {
+            let now = current_stats.remove(&name).unwrap_or_default();
+            let before = previous_stats.remove(&name).unwrap_or_default();
+            let mut new_sources: Vec<IpAddr> = now
+                .sources
+                .keys()
+                .filter(|ip| !before.sources.contains_key(ip))
+                .copied()
+                .collect();
+            new_sources.sort();
+            let mut disappeared_sources: Vec<IpAddr> = before
+                .sources
+                .keys()
+                .filter(|ip| !now.sources.contains_key(ip))
+                .copied()
+                .collect();
+            disappeared_sources.sort();
+            let now = PeriodStats::from(now);
+            let before = PeriodStats::from(before);
+            DomainComparison {
+                domain: name,
+                messages_change: now.messages as i64 - before.messages as i64,
+                failed_change: now.failed as i64 - before.failed as i64,
+                pass_rate_change: now.pass_rate - before.pass_rate,
+                current: now,
+                previous: before,
+                new_sources,
+                disappeared_sources,
+            }
+        })
+        .collect();
+    Comparison {
+        current,
+        previous,
+        domains,
+    }
+}
+
+impl From<DomainStats> for PeriodStats {
+    fn from(stats: DomainStats) -> Self {
+        Self {
+            messages: stats.messages,
+            passed: stats.passed,
+            failed: stats.failed,
+            pass_rate: stats.pass_rate(),
+            sources: stats.sources.len(),
+        }
+    }
+}
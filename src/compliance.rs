@@ -0,0 +1,151 @@
+use crate::report::Report;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use utoipa::ToSchema;
+
+/// Minimum difference in percentage points between two windows to be reported as trend
+const TREND_TOLERANCE: f64 = 1.0;
+
+/// Direction of the compliance compared to the previous window
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Trend {
+    Up,
+    Down,
+    Stable,
+}
+
+/// Share of messages passing DMARC with aligned DKIM or SPF for a domain
+/// in the current window, compared to the window before
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct ComplianceScore {
+    pub domain: String,
+    pub messages: usize,
+    pub passed: usize,
+
+    /// Percentage of passed messages in the current window
+    pub compliance: f64,
+
+    /// Percentage of passed messages in the previous window, empty without messages
+    pub previous: Option<f64>,
+
+    /// Empty without messages in the previous window
+    pub trend: Option<Trend>,
+}
+
+/// Compliance of all domains with reports ending within the window before now.
+/// The previous window has the same length and ends where the current one begins.
+pub fn compliance_scores(reports: &[Report], now: u64, window: u64) -> Vec<ComplianceScore> {
+    let begin = now.saturating_sub(window);
+    let previous_begin = begin.saturating_sub(window);
+    let mut current: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    let mut previous: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for report in reports {
+        let end = report.report_metadata.date_range.end;
+        let counts = if end > begin && end <= now {
+            &mut current
+        } else if end > previous_begin && end <= begin {
+            &mut previous
+        } else {
+            continue;
+        };
+        let domain = report.policy_published.domain.to_lowercase();
+        let (messages, passed) = counts.entry(domain).or_default();
+        for record in &report.record {
+            *messages += record.row.count;
+            if record.is_dmarc_pass() {
+                *passed += record.row.count;
+            }
+        }
+    }
+    current
+        .into_iter()
+        .filter(|(_, (messages, _))| *messages > 0)
+        .map(|(domain, (messages, passed))| {
+            let compliance = percentage(passed, messages);
+            let previous = previous
+                .get(&domain)
+                .filter(|(messages, _)| *messages > 0)
+                .map(|(messages, passed)| percentage(*passed, *messages));
+            let trend = previous.map(|previous| {
+                if compliance - previous >= TREND_TOLERANCE {
+                    Trend::Up
+                } else if previous - compliance >= TREND_TOLERANCE {
+                    Trend::Down
+                } else {
+                    Trend::Stable
+                }
+            });
+            ComplianceScore {
+                domain,
+                messages,
+                passed,
+                compliance,
+                previous,
+                trend,
+            }
+        })
+        .collect()
+}
+
+fn percentage(passed: usize, messages: usize) -> f64 {
+    passed as f64 * 100.0 / messages as f64
+}
+
+/// Domains currently below the compliance target.
+/// Remembered across update cycles to only alert when a domain drops below the target.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ComplianceTracker {
+    below_target: HashSet<String>,
+}
+
+impl ComplianceTracker {
+    /// Returns the scores of domains that dropped below the target since the last update
+    pub fn update(&mut self, scores: &[ComplianceScore], target: f64) -> Vec<ComplianceScore> {
+        let mut dropped = Vec::new();
+        let mut below_target = HashSet::new();
+        for score in scores.iter().filter(|s| s.compliance < target) {
+            if !self.below_target.contains(&score.domain) {
+                dropped.push(score.clone());
+            }
+            below_target.insert(score.domain.clone());
+        }
+        self.below_target = below_target;
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use crate::timeseries::DAY;
+    use std::fs;
+
+    #[test]
+    fn scores_and_drops() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let mut report = parse_xml_file(&xml).unwrap();
+        report.record.truncate(1);
+        report.record[0].row.count = 10;
+        let mut previous = report.clone();
+        previous.report_metadata.date_range.end = 5 * DAY;
+        report.report_metadata.date_range.end = 12 * DAY;
+        let pass = report.record[0].is_dmarc_pass();
+        let reports = vec![previous, report];
+
+        let scores = compliance_scores(&reports, 14 * DAY, 7 * DAY);
+        assert_eq!(scores.len(), 1);
+        assert_eq!(scores[0].messages, 10);
+        let expected = if pass { 100.0 } else { 0.0 };
+        assert_eq!(scores[0].compliance, expected);
+        assert_eq!(scores[0].previous, Some(expected));
+        assert_eq!(scores[0].trend, Some(Trend::Stable));
+
+        let mut tracker = ComplianceTracker::default();
+        assert_eq!(tracker.update(&scores, 101.0).len(), 1);
+        assert!(tracker.update(&scores, 101.0).is_empty());
+        assert!(tracker.update(&scores, -1.0).is_empty());
+        assert_eq!(tracker.update(&scores, 101.0).len(), 1);
+    }
+}
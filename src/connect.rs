@@ -0,0 +1,271 @@
+use crate::config::Configuration;
+use crate::proxy;
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::TcpStream as StdTcpStream;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, Error as TlsError, RootCertStore,
+    SignatureScheme,
+};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, warn};
+
+/// Makes ring the crypto provider of all TLS clients and servers.
+/// Only ring is compiled in, but rustls requires an explicit choice
+/// as soon as a dependency enables another provider.
+pub fn install_crypto_provider() {
+    // Fails only if a provider was already installed
+    let _ = ring::default_provider().install_default();
+}
+
+/// Connection to the mail server, encrypted unless plaintext was explicitly enabled
+pub trait MailStream: AsyncRead + AsyncWrite + Unpin + Send + Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Debug> MailStream for T {}
+
+/// Connects to the port of the configured mail server, directly or through the proxy.
+/// The connection is encrypted with the TLS settings of the configuration
+/// unless plaintext connections were enabled explicitly.
+pub async fn connect(config: &Configuration, port: u16) -> Result<Box<dyn MailStream>> {
+    let timeout = Duration::from_secs(config.imap_timeout);
+    let tcp_stream = match &config.imap_proxy {
+        Some(proxy) => proxy::connect(proxy, &config.imap_host, port, timeout).await?,
+        None => connect_direct(config, port, timeout)?,
+    };
+    if config.imap_insecure_plaintext {
+        warn!(
+            "Using unencrypted connection to mail server {}",
+            config.imap_host
+        );
+        Ok(Box::new(tcp_stream))
+    } else {
+        Ok(Box::new(tls_connect(config, tcp_stream).await?))
+    }
+}
+
+/// Opens a TCP connection to the mail server without proxy
+fn connect_direct(config: &Configuration, port: u16, timeout: Duration) -> Result<TcpStream> {
+    let host_port = format!("{}:{port}", config.imap_host);
+    debug!("Parsing mail server address {host_port} as socket address...");
+    let addrs = host_port
+        .to_socket_addrs()
+        .context("Failed to convert host name and port to socket address")?
+        .collect::<Vec<SocketAddr>>();
+    let addr = addrs.first().context("Unable get first resolved address")?;
+    debug!("Got address {addr}");
+
+    let std_tcp_stream =
+        StdTcpStream::connect_timeout(addr, timeout).context("Failed to connect to mail server")?;
+    debug!("Created TCP stream");
+
+    std_tcp_stream
+        .set_nonblocking(true)
+        .context("Failed to set TCP stream to non-blocking")?;
+    let tcp_stream = TcpStream::from_std(std_tcp_stream)
+        .context("Failed to create TCP stream to mail server")?;
+    debug!("Created async TCP stream");
+    Ok(tcp_stream)
+}
+
+/// Wraps the TCP stream in TLS. The server certificate is verified with the webpki roots
+/// and the configured CA certificates or must match the pinned fingerprint.
+/// A client certificate is presented if configured.
+async fn tls_connect(
+    config: &Configuration,
+    tcp_stream: TcpStream,
+) -> Result<TlsStream<TcpStream>> {
+    let builder = ClientConfig::builder();
+    let builder = if let Some(fingerprint) = &config.imap_tls_pinned_cert {
+        let verifier = PinnedCertVerifier::new(parse_fingerprint(fingerprint)?);
+        debug!("Pinned server certificate {fingerprint}");
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+    } else {
+        // Prepare cert store with webpki roots
+        let mut root_cert_store = RootCertStore::empty();
+        let certs = webpki_roots::TLS_SERVER_ROOTS.iter().cloned();
+        root_cert_store.extend(certs);
+        if let Some(path) = &config.imap_tls_ca_cert {
+            let (added, _) = root_cert_store.add_parsable_certificates(load_certs(path)?);
+            if added == 0 {
+                bail!("No valid CA certificates found in {path}");
+            }
+            debug!("Added {added} CA certificates from {path}");
+        }
+        debug!("Created Root CA cert store");
+        builder.with_root_certificates(root_cert_store)
+    };
+
+    // Create async TLS connection
+    let client_config = match (&config.imap_tls_client_cert, &config.imap_tls_client_key) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .context("Failed to use TLS client certificate")?,
+        _ => builder.with_no_client_auth(),
+    };
+    debug!("Created TLS client config");
+
+    let connector = TlsConnector::from(Arc::new(client_config));
+    debug!("Created TLS connector");
+
+    let dns_name = ServerName::try_from(config.imap_host.clone())
+        .context("Failed to get DNS name from mail server host")?;
+    debug!("Got DNS name: {dns_name:?}");
+
+    let tls_stream = connector
+        .connect(dns_name, tcp_stream)
+        .await
+        .context("Failed to create TLS stream with mail server")?;
+    debug!("Created TLS stream");
+    Ok(tls_stream)
+}
+
+/// Accepts only the server certificate with the pinned SHA-256 fingerprint.
+/// Issuer, validity and host name are ignored, but the handshake signatures are still verified.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    fingerprint: [u8; 32],
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedCertVerifier {
+    fn new(fingerprint: [u8; 32]) -> Self {
+        Self {
+            fingerprint,
+            provider: Arc::new(ring::default_provider()),
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, TlsError> {
+        let fingerprint: [u8; 32] = Sha256::digest(end_entity).into();
+        if fingerprint == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            warn!(
+                "Mail server certificate fingerprint {} does not match pinned fingerprint",
+                hex::encode(fingerprint)
+            );
+            Err(TlsError::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, TlsError> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Parses a SHA-256 fingerprint in hex, optionally separated with colons like `AB:CD:...`
+fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32]> {
+    let digits: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect();
+    let bytes = hex::decode(digits).context("Invalid hex encoding of certificate fingerprint")?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Certificate fingerprint must be a SHA-256 hash"))
+}
+
+/// Reads all certificates from a PEM file
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open certificate file {path}"))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse certificate file {path}"))?;
+    if certs.is_empty() {
+        bail!("No certificates found in {path}");
+    }
+    Ok(certs)
+}
+
+/// Reads the first private key from a PEM file
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("Failed to open key file {path}"))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse key file {path}"))?
+        .with_context(|| format!("No private key found in {path}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_formats() {
+        let hex = "3f".repeat(32);
+        let colons = vec!["3F"; 32].join(":");
+        assert_eq!(parse_fingerprint(&hex).unwrap(), [0x3f; 32]);
+        assert_eq!(parse_fingerprint(&colons).unwrap(), [0x3f; 32]);
+        assert!(parse_fingerprint("3f:3f").is_err());
+        assert!(parse_fingerprint("not hex").is_err());
+    }
+
+    #[test]
+    fn ring_provider_installed() {
+        install_crypto_provider();
+        install_crypto_provider();
+        assert!(CryptoProvider::get_default().is_some());
+        ClientConfig::builder()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+    }
+}
@@ -0,0 +1,126 @@
+/// Joins the fields to a CSV line terminated with CRLF as recommended by RFC 4180
+pub fn csv_line(fields: impl IntoIterator<Item = String>) -> String {
+    let mut line = fields
+        .into_iter()
+        .map(|f| csv_escape(&f))
+        .collect::<Vec<String>>()
+        .join(",");
+    line.push_str("\r\n");
+    line
+}
+
+/// Joins lines to chunks of at least the size, only the last chunk may be smaller
+pub fn csv_chunks(
+    mut lines: impl Iterator<Item = String>,
+    size: usize,
+) -> impl Iterator<Item = String> {
+    std::iter::from_fn(move || {
+        let mut chunk = lines.next()?;
+        while chunk.len() < size {
+            match lines.next() {
+                Some(line) => chunk.push_str(&line),
+                None => break,
+            }
+        }
+        Some(chunk)
+    })
+}
+
+/// Quotes fields with separators and prefixes fields that spreadsheets would evaluate as formula
+/// with an apostrophe, since most fields come from the untrusted reports.
+fn csv_escape(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{field}")
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// Parses CSV data into rows of fields.
+/// Supports quoted fields with escaped quotes and line breaks.
+/// Empty lines are skipped.
+pub fn parse_csv(data: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            } else {
+                field.push(c);
+            }
+        } else if c == '"' {
+            quoted = true;
+        } else if c == ',' {
+            row.push(std::mem::take(&mut field));
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            row.push(std::mem::take(&mut field));
+            if row.len() > 1 || !row[0].is_empty() {
+                rows.push(std::mem::take(&mut row));
+            } else {
+                row.clear();
+            }
+        } else {
+            field.push(c);
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_fields() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_line(["a".into(), "b c".into()]), "a,b c\r\n");
+    }
+
+    #[test]
+    fn neutralize_formulas() {
+        assert_eq!(
+            csv_escape("=HYPERLINK(\"x\")"),
+            "\"'=HYPERLINK(\"\"x\"\")\""
+        );
+        assert_eq!(csv_escape("+1"), "'+1");
+        assert_eq!(csv_escape("-2+3"), "'-2+3");
+        assert_eq!(csv_escape("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_escape("a=b"), "a=b");
+    }
+
+    #[test]
+    fn join_chunks() {
+        let lines = ["ab", "cd", "ef", "g"].map(String::from).into_iter();
+        let chunks: Vec<String> = csv_chunks(lines, 3).collect();
+        assert_eq!(chunks, vec!["abcd", "efg"]);
+    }
+
+    #[test]
+    fn parse_rows() {
+        let rows = parse_csv("a,b\r\n\r\n\"c,\"\"d\"\"\",e\nf");
+        assert_eq!(rows, vec![vec!["a", "b"], vec!["c,\"d\"", "e"], vec!["f"]]);
+    }
+}
@@ -0,0 +1,81 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::sync::LazyLock;
+
+/// Random per process, because the state revision starts at zero again after a restart
+static EPOCH: LazyLock<u64> = LazyLock::new(|| {
+    let mut bytes = [0; 8];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Failed to generate random cursor epoch");
+    u64::from_le_bytes(bytes)
+});
+
+/// Reason why a page cannot be continued from a cursor
+#[derive(Debug, PartialEq)]
+pub enum CursorError {
+    /// Not a cursor issued by this server
+    Invalid,
+
+    /// The reports changed since the cursor was issued, pagination has to start again
+    Expired,
+}
+
+/// Position in a paginated listing of one state revision.
+/// Pages of the same revision never shift, because the state is only replaced as a whole.
+#[derive(Debug, PartialEq)]
+pub struct PageCursor {
+    pub revision: u64,
+    pub offset: usize,
+}
+
+impl PageCursor {
+    /// Cursor of the page following the returned items, nothing after the last page
+    pub fn next(revision: u64, offset: usize, returned: usize, total: usize) -> Option<Self> {
+        let offset = offset.saturating_add(returned);
+        (returned > 0 && offset < total).then_some(Self { revision, offset })
+    }
+
+    /// Opaque token for the query parameter
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}:{}", *EPOCH, self.revision, self.offset))
+    }
+
+    /// Parse a token and check that it was issued for the current revision
+    pub fn decode(cursor: &str, revision: u64) -> Result<Self, CursorError> {
+        let decoded = URL_SAFE_NO_PAD
+            .decode(cursor.trim())
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or(CursorError::Invalid)?;
+        let mut parts = decoded.split(':').map(str::parse::<u64>);
+        let (Some(Ok(epoch)), Some(Ok(cursor_revision)), Some(Ok(offset)), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(CursorError::Invalid);
+        };
+        if epoch != *EPOCH || cursor_revision != revision {
+            return Err(CursorError::Expired);
+        }
+        let offset = usize::try_from(offset).map_err(|_| CursorError::Invalid)?;
+        Ok(Self { revision, offset })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continue_pages() {
+        let cursor = PageCursor::next(7, 0, 100, 250).unwrap();
+        assert_eq!(cursor.offset, 100);
+        let token = cursor.encode();
+        assert_eq!(PageCursor::decode(&token, 7), Ok(cursor));
+        assert_eq!(PageCursor::decode(&token, 8), Err(CursorError::Expired));
+        assert_eq!(PageCursor::decode("page-2", 7), Err(CursorError::Invalid));
+
+        assert!(PageCursor::next(7, 200, 50, 250).is_none());
+        assert!(PageCursor::next(7, 300, 0, 250).is_none());
+    }
+}
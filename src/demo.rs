@@ -0,0 +1,389 @@
+use crate::config::Configuration;
+use crate::mail::{hashed_uid, Mail};
+use crate::parser::{extract_xml_from_file, ExtractLimits};
+use crate::source::select_new_mails;
+use crate::status::unix_time;
+use crate::timeseries::DAY;
+use anyhow::{ensure, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::DateTime;
+use mailparse::{dateparse, parse_headers, MailHeaderMap};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::LazyLock;
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, info};
+
+/// Sample reports bundled with the binary
+const SAMPLES: &[(&str, &[u8])] = &[
+    (
+        "acme.xml",
+        include_bytes!("../testdata/dmarc-reports/acme.xml"),
+    ),
+    (
+        "aol.xml",
+        include_bytes!("../testdata/dmarc-reports/aol.xml"),
+    ),
+    (
+        "google.xml",
+        include_bytes!("../testdata/dmarc-reports/google.xml"),
+    ),
+    (
+        "mailru.xml",
+        include_bytes!("../testdata/dmarc-reports/mailru.xml"),
+    ),
+    (
+        "outlook.xml",
+        include_bytes!("../testdata/dmarc-reports/outlook.xml"),
+    ),
+    (
+        "solamora.xml",
+        include_bytes!("../testdata/dmarc-reports/solamora.xml"),
+    ),
+    (
+        "webde.xml",
+        include_bytes!("../testdata/dmarc-reports/webde.xml"),
+    ),
+    (
+        "yahoo.xml",
+        include_bytes!("../testdata/dmarc-reports/yahoo.xml"),
+    ),
+];
+
+/// Days with daily reports of every sample in the mailbox at the start
+const BACKLOG_DAYS: u64 = 7;
+
+/// Start of the simulation, the mailbox only grows while the process runs
+static STARTED: LazyLock<u64> = LazyLock::new(unix_time);
+
+/// Sample files the simulated mails are made of
+enum Sample {
+    /// XML file of a report that is sent again and again with new dates and IDs
+    Report { name: String, xml: String },
+
+    /// Recorded mail that is replayed once as it is
+    Mail { name: String, data: Vec<u8> },
+}
+
+/// Simulated mail with its complete body
+struct DemoMail {
+    uid: u32,
+    date: i64,
+    subject: String,
+    sender: String,
+    to: String,
+    message_id: String,
+    body: Vec<u8>,
+}
+
+/// Get metadata of all simulated mails, works like `imap::get_mails`.
+/// The mailbox contains the daily reports of the last week and the reports that arrived since the start,
+/// all made of the bundled sample reports or the files of the demo directory.
+pub async fn get_mails(
+    config: &Configuration,
+    known_uids: &HashSet<u32>,
+    batches: Sender<Vec<Mail>>,
+    pending: &AtomicUsize,
+) -> Result<HashMap<u32, Mail>> {
+    let samples = samples(config)?;
+    let max_size = config.max_mail_size as usize;
+    let mut mails = HashMap::new();
+    let mut new_mails = Vec::new();
+    for demo_mail in mailbox(config, &samples, *STARTED, unix_time()) {
+        let size = demo_mail.body.len();
+        if size > max_size || known_uids.contains(&demo_mail.uid) {
+            mails.insert(demo_mail.uid, demo_mail.into_mail(max_size, false));
+        } else {
+            new_mails.push(demo_mail);
+        }
+    }
+    info!(
+        "Simulated {} mails, {} of them are new",
+        mails.len() + new_mails.len(),
+        new_mails.len()
+    );
+
+    select_new_mails(config, &mut new_mails, pending);
+
+    let mut new_mails = new_mails.into_iter().peekable();
+    while new_mails.peek().is_some() {
+        let batch: Vec<Mail> = new_mails
+            .by_ref()
+            .take(config.imap_batch_size as usize)
+            .map(|m| m.into_mail(max_size, true))
+            .collect();
+        debug!("Simulated batch of {} mails", batch.len());
+        if batches.send(batch).await.is_err() {
+            info!("Processing stopped, the remaining mails follow in the next cycle");
+            break;
+        }
+    }
+    Ok(mails)
+}
+
+/// Create the simulated mail with the UID again.
+/// Returns nothing if the mail does not exist anymore.
+pub async fn get_mail_body(config: &Configuration, uid: u32) -> Result<Option<Vec<u8>>> {
+    let samples = samples(config)?;
+    Ok(mailbox(config, &samples, *STARTED, unix_time())
+        .into_iter()
+        .find(|m| m.uid == uid)
+        .map(|m| m.body))
+}
+
+/// Loads the samples to validate the demo directory.
+/// Returns the number of simulated mails.
+pub async fn check_inbox(config: &Configuration) -> Result<u32> {
+    let samples = samples(config)?;
+    Ok(mailbox(config, &samples, *STARTED, unix_time()).len() as u32)
+}
+
+/// Bundled sample reports or the report files and mails of the demo directory
+fn samples(config: &Configuration) -> Result<Vec<Sample>> {
+    let Some(dir) = &config.demo_dir else {
+        return Ok(SAMPLES
+            .iter()
+            .map(|(name, xml)| Sample::Report {
+                name: (*name).to_owned(),
+                xml: String::from_utf8_lossy(xml).into_owned(),
+            })
+            .collect());
+    };
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read demo directory {dir}"))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+    let limits = ExtractLimits::from(config);
+    let mut samples = Vec::new();
+    for path in paths {
+        let name = file_name(&path);
+        let data = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("eml"))
+        {
+            samples.push(Sample::Mail { name, data });
+            continue;
+        }
+        let xml_files = extract_xml_from_file(&data, &limits)
+            .with_context(|| format!("Failed to read sample {}", path.display()))?;
+        for (index, xml) in xml_files.into_iter().enumerate() {
+            samples.push(Sample::Report {
+                name: format!("{name}-{index}.xml"),
+                xml: String::from_utf8_lossy(&xml).into_owned(),
+            });
+        }
+    }
+    ensure!(
+        !samples.is_empty(),
+        "No samples found in demo directory {dir}"
+    );
+    Ok(samples)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// All mails that arrived until `now` in a simulation started at `started`, oldest first.
+/// Recorded mails are there from the start, every sample report arrives once per day of the backlog
+/// and after the start one of the sample reports arrives per interval, taking turns.
+fn mailbox(config: &Configuration, samples: &[Sample], started: u64, now: u64) -> Vec<DemoMail> {
+    let mut mails = Vec::new();
+    let mut reports = Vec::new();
+    for sample in samples {
+        match sample {
+            Sample::Mail { name, data } => mails.push(recorded_mail(name, data)),
+            Sample::Report { name, xml } => reports.push((name, xml)),
+        }
+    }
+    if reports.is_empty() {
+        return mails;
+    }
+
+    let today = started / DAY * DAY;
+    for day in (1..=BACKLOG_DAYS).rev() {
+        let begin = today - day * DAY;
+        for (index, (name, xml)) in reports.iter().enumerate() {
+            let arrival = begin + DAY + index as u64 * 60;
+            if arrival <= now {
+                mails.push(report_mail(name, xml, begin, arrival));
+            }
+        }
+    }
+    let mut arrival = started + config.demo_interval;
+    let mut turn = 0;
+    while arrival <= now {
+        let (name, xml) = reports[turn % reports.len()];
+        mails.push(report_mail(name, xml, arrival - DAY, arrival));
+        arrival += config.demo_interval;
+        turn += 1;
+    }
+    mails
+}
+
+fn recorded_mail(name: &str, data: &[u8]) -> DemoMail {
+    let headers = parse_headers(data).map(|(headers, _)| headers).ok();
+    let header = |name: &str| {
+        headers
+            .as_ref()
+            .and_then(|h| h.get_first_value(name))
+            .unwrap_or_default()
+    };
+    DemoMail {
+        uid: hashed_uid(&format!("demo-mail-{name}")),
+        date: dateparse(&header("Date")).unwrap_or_default(),
+        subject: header("Subject"),
+        sender: header("From"),
+        to: header("To"),
+        message_id: header("Message-ID").trim().to_owned(),
+        body: data.to_vec(),
+    }
+}
+
+/// Mail with the sample report for the day starting at `begin`, with a new report ID
+/// and message counts that vary between 50% and 150% of the sample
+fn report_mail(name: &str, xml: &str, begin: u64, arrival: u64) -> DemoMail {
+    let key = format!("demo-{name}-{begin}-{arrival}");
+    let uid = hashed_uid(&key);
+    let xml = simulated_report(xml, begin, 50 + u64::from(uid % 101));
+    let org = element(&xml, "org_name").unwrap_or("Demo Reporter");
+    let email = element(&xml, "email").unwrap_or("noreply-dmarc@example.org");
+    let domain = element(&xml, "domain").unwrap_or("example.com");
+    let report_id = element(&xml, "report_id").unwrap_or_default();
+    let subject = format!("Report Domain: {domain} Submitter: {org} Report-ID: <{report_id}>");
+    let sender = format!("{org} <{email}>");
+    let to = format!("dmarc-reports@{domain}");
+    let message_id = format!("<{uid}.demo@dmarc-report-viewer>");
+    let date = DateTime::from_timestamp(arrival as i64, 0)
+        .unwrap_or_default()
+        .to_rfc2822();
+    let encoded = STANDARD.encode(&xml);
+    let lines: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|line| std::str::from_utf8(line).unwrap_or_default())
+        .collect();
+    let body = format!(
+        "From: {sender}\r\nTo: {to}\r\nSubject: {subject}\r\nDate: {date}\r\n\
+        Message-ID: {message_id}\r\nMIME-Version: 1.0\r\n\
+        Content-Type: multipart/mixed; boundary=\"demo\"\r\n\r\n\
+        --demo\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n\
+        This is a simulated DMARC aggregate report of the demo mode.\r\n\
+        --demo\r\nContent-Type: application/xml; name=\"{name}\"\r\n\
+        Content-Disposition: attachment; filename=\"{name}\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\r\n{}\r\n--demo--\r\n",
+        lines.join("\r\n")
+    );
+    DemoMail {
+        uid,
+        date: arrival as i64,
+        subject,
+        sender,
+        to,
+        message_id,
+        body: body.into_bytes(),
+    }
+}
+
+/// Sample report for the day starting at `begin` with the message counts scaled by the factor in percent
+fn simulated_report(xml: &str, begin: u64, factor: u64) -> String {
+    let xml = replace_text(xml, "begin", |_| begin.to_string());
+    let xml = replace_text(&xml, "end", |_| (begin + DAY - 1).to_string());
+    let xml = replace_text(&xml, "report_id", |id| format!("{}-{begin}", id.trim()));
+    replace_text(&xml, "count", |count| {
+        let count: u64 = count.trim().parse().unwrap_or(1);
+        (count * factor / 100).max(1).to_string()
+    })
+}
+
+impl DemoMail {
+    fn into_mail(self, max_size: usize, with_body: bool) -> Mail {
+        Mail {
+            uid: self.uid,
+            size: self.body.len(),
+            oversized: self.body.len() > max_size,
+            date: self.date,
+            subject: self.subject,
+            sender: self.sender,
+            to: self.to,
+            attachments: 0,
+            message_id: Some(self.message_id).filter(|id| !id.is_empty()),
+            body: with_body.then_some(self.body),
+            duplicate_of: None,
+        }
+    }
+}
+
+/// Text of the first element with the name
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{name}>"))? + name.len() + 2;
+    let len = xml[start..].find(&format!("</{name}>"))?;
+    Some(xml[start..start + len].trim())
+}
+
+/// Replace the text of all elements with the name, elements with namespace prefixes are kept
+fn replace_text(xml: &str, name: &str, mut replace: impl FnMut(&str) -> String) -> String {
+    let (open, close) = (format!("<{name}>"), format!("</{name}>"));
+    let mut result = String::with_capacity(xml.len());
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let content = start + open.len();
+        let Some(len) = rest[content..].find(&close) else {
+            break;
+        };
+        result.push_str(&rest[..content]);
+        result.push_str(&replace(&rest[content..content + len]));
+        rest = &rest[content + len..];
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use clap::Parser;
+
+    #[test]
+    fn simulate_arrivals() {
+        let config = Configuration::parse_from([
+            "test",
+            "--demo",
+            "--demo-interval=600",
+            "--http-server-password=password",
+        ]);
+        let samples = samples(&config).unwrap();
+        let started = 100 * DAY + DAY / 2;
+        let mails = mailbox(&config, &samples, started, started + 1800);
+        assert_eq!(mails.len(), 7 * SAMPLES.len() + 3);
+        let uids: HashSet<u32> = mails.iter().map(|m| m.uid).collect();
+        assert_eq!(uids.len(), mails.len());
+
+        // The newest mail has the third sample with a report of the day before its arrival
+        let mail = mails.last().unwrap();
+        assert_eq!(mail.date, (started + 1800) as i64);
+        assert!(mail.subject.contains("Submitter: google.com"));
+        assert!(mail
+            .subject
+            .ends_with(&format!("-{}>", started + 1800 - DAY)));
+
+        let xml = simulated_report(&String::from_utf8_lossy(SAMPLES[0].1), DAY, 150);
+        let report = parse_xml_file(xml.as_bytes()).unwrap();
+        let range = &report.report_metadata.date_range;
+        assert_eq!((range.begin, range.end), (DAY, 2 * DAY - 1));
+        assert!(report
+            .report_metadata
+            .report_id
+            .ends_with(&format!("-{DAY}")));
+        assert_eq!(report.record[0].row.count, 3);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::compare::Window;
+use crate::config::Configuration;
+use crate::export::daily_exports;
+use crate::ingest::unix_timestamp;
+use crate::notifications::{Alert, Notifier};
+use crate::pdf::pdf_report;
+use crate::s3::S3Archive;
+use crate::state::SharedState;
+use crate::timeseries::{Interval, Timezone, DAY};
+use anyhow::Result;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Start the task sending a digest at the configured hour every day or week.
+/// Returns nothing if no digest interval is configured.
+pub fn start_digest_task(
+    config: &Configuration,
+    state: Arc<SharedState>,
+    notifier: Arc<Notifier>,
+) -> Option<JoinHandle<()>> {
+    let interval = config.digest_interval?;
+    let hour = config.digest_hour;
+    let timezone = config.timezone;
+    Some(tokio::spawn(async move {
+        info!("Started digest task with {interval:?} interval at {hour}:00 {timezone}");
+        loop {
+            let next = match next_digest_time(interval, hour, timezone) {
+                Ok(next) => next,
+                Err(err) => {
+                    error!("Failed to calculate time of next digest: {err:#}");
+                    return;
+                }
+            };
+            let now = unix_timestamp().unwrap_or(next);
+            tokio::time::sleep(Duration::from_secs(next.saturating_sub(now))).await;
+
+            let since = next - interval.duration();
+            let alert = {
+                let locked_state = state.snapshot();
+                Alert::digest(&locked_state.reports, since, next)
+            };
+            notifier.send(&alert.with_state(&state)).await;
+        }
+    }))
+}
+
+/// Start the task writing a PDF report of the last day or week into the configured directory.
+/// Returns nothing if no directory is configured.
+pub fn start_pdf_report_task(
+    config: &Configuration,
+    state: Arc<SharedState>,
+) -> Option<JoinHandle<()>> {
+    let dir = PathBuf::from(config.pdf_report_dir.as_ref()?);
+    let interval = config.pdf_report_interval;
+    let hour = config.digest_hour;
+    let timezone = config.timezone;
+    Some(tokio::spawn(async move {
+        info!("Started PDF report task with {interval:?} interval at {hour}:00 {timezone}");
+        loop {
+            let next = match next_digest_time(interval, hour, timezone) {
+                Ok(next) => next,
+                Err(err) => {
+                    error!("Failed to calculate time of next PDF report: {err:#}");
+                    return;
+                }
+            };
+            let now = unix_timestamp().unwrap_or(next);
+            tokio::time::sleep(Duration::from_secs(next.saturating_sub(now))).await;
+
+            // Whole local days before the current one
+            let until = Interval::Daily.bucket_start(next, timezone) - 1;
+            let window = Window {
+                since: until + 1 - interval.duration(),
+                until,
+            };
+            let pdf = {
+                let locked_state = state.snapshot();
+                pdf_report(&locked_state.reports, window, None, timezone)
+            };
+            let path = dir.join(format!("dmarc-report-{}.pdf", timezone.date(window.since)));
+            let result = fs::create_dir_all(&dir).and_then(|_| fs::write(&path, pdf));
+            match result {
+                Ok(()) => info!("Wrote PDF report {}", path.display()),
+                Err(err) => error!("Failed to write PDF report {}: {err}", path.display()),
+            }
+        }
+    }))
+}
+
+/// Start the task writing the exports of the last days per domain into the configured directory
+/// and S3 prefix every day. Returns nothing if neither is configured.
+pub fn start_export_task(
+    config: &Configuration,
+    state: Arc<SharedState>,
+    s3: Option<S3Archive>,
+) -> Option<JoinHandle<()>> {
+    let dir = config.export_dir.as_ref().map(PathBuf::from);
+    if dir.is_none() && s3.is_none() {
+        return None;
+    }
+    let format = config.export_format;
+    let hour = config.export_hour;
+    let days = u64::from(config.export_days);
+    let timezone = config.timezone;
+    Some(tokio::spawn(async move {
+        info!("Started export task with {format:?} files of {days} days at {hour}:00 {timezone}");
+        loop {
+            let next = match next_digest_time(Interval::Daily, hour, timezone) {
+                Ok(next) => next,
+                Err(err) => {
+                    error!("Failed to calculate time of next export: {err:#}");
+                    return;
+                }
+            };
+            let now = unix_timestamp().unwrap_or(next);
+            tokio::time::sleep(Duration::from_secs(next.saturating_sub(now))).await;
+
+            // Whole local days before the current one, the oldest ones again for late reports
+            let today = Interval::Daily.bucket_start(next, timezone);
+            let mut files = Vec::new();
+            for day in 1..=days {
+                let since = today - day * DAY;
+                let exports = {
+                    let locked_state = state.snapshot();
+                    daily_exports(
+                        &locked_state.reports,
+                        &locked_state.annotations,
+                        since,
+                        since + DAY - 1,
+                        &timezone.date(since),
+                        format,
+                    )
+                };
+                match exports {
+                    Ok(exports) => files.extend(exports),
+                    Err(err) => error!("Failed to export {}: {err:#}", timezone.date(since)),
+                }
+            }
+            if let Some(dir) = &dir {
+                let result = files.iter().try_for_each(|(name, data)| {
+                    let path = dir.join(name);
+                    fs::create_dir_all(path.parent().unwrap_or(dir))
+                        .and_then(|_| fs::write(&path, data))
+                });
+                match result {
+                    Ok(()) => info!("Wrote {} export files to {}", files.len(), dir.display()),
+                    Err(err) => error!("Failed to write exports to {}: {err}", dir.display()),
+                }
+            }
+            if let Some(s3) = &s3 {
+                match s3.upload(files).await {
+                    Ok(count) => info!("Uploaded {count} export files to S3"),
+                    Err(err) => error!("Failed to upload exports: {err:#}"),
+                }
+            }
+        }
+    }))
+}
+
+/// Next Unix timestamp at the hour of the day in the timezone, for weekly digests on Mondays
+fn next_digest_time(interval: Interval, hour: u32, timezone: Timezone) -> Result<u64> {
+    let now = unix_timestamp()?;
+    let mut next = interval.bucket_start(now, timezone) + u64::from(hour) * 60 * 60;
+    while next <= now {
+        next += interval.duration();
+    }
+    Ok(next)
+}
@@ -0,0 +1,85 @@
+use crate::archive::Archive;
+use crate::config::Configuration;
+use crate::ingest::ingest_file;
+use crate::parser::{ExtractLimits, ParseOptions};
+use crate::state::SharedState;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Subdirectory of the ingest directory for successfully ingested files
+const DONE_DIR: &str = "done";
+
+/// Subdirectory of the ingest directory for files that could not be ingested
+const FAILED_DIR: &str = "failed";
+
+/// File extensions of report files, other files are left untouched
+const EXTENSIONS: [&str; 5] = ["xml", "gz", "zip", "tar", "tgz"];
+
+/// Source of report files dropped into a local directory, for example by an MTA or a script
+pub struct DirectorySource {
+    dir: PathBuf,
+    parse_options: ParseOptions,
+    limits: ExtractLimits,
+}
+
+impl DirectorySource {
+    /// Returns nothing if ingesting from a directory is not configured
+    pub fn new(config: &Configuration) -> Option<Self> {
+        Some(Self {
+            dir: PathBuf::from(config.ingest_dir.as_ref()?),
+            parse_options: ParseOptions::from(config),
+            limits: ExtractLimits::from(config),
+        })
+    }
+
+    /// Add the reports of all report files in the directory to the shared state
+    /// and move the files to the subdirectory `done` or `failed`.
+    /// Returns the number of added reports.
+    pub fn ingest(&self, state: &Arc<SharedState>, archive: Option<&Archive>) -> Result<usize> {
+        for dir in [DONE_DIR, FAILED_DIR] {
+            fs::create_dir_all(self.dir.join(dir))
+                .with_context(|| format!("Failed to create directory {dir} in ingest directory"))?;
+        }
+        let entries = fs::read_dir(&self.dir).context("Failed to read ingest directory")?;
+        let mut reports = 0;
+        for entry in entries {
+            let path = entry.context("Failed to read directory entry")?.path();
+            if !path.is_file() || !is_report_file(&path) {
+                continue;
+            }
+            let data = fs::read(&path).with_context(|| format!("Failed to read file {path:?}"))?;
+            let target_dir =
+                match ingest_file(state, archive, &data, &self.parse_options, &self.limits) {
+                    Ok(count) => {
+                        reports += count;
+                        DONE_DIR
+                    }
+                    Err(err) => {
+                        warn!("Failed to ingest report file {path:?}: {err:#}");
+                        FAILED_DIR
+                    }
+                };
+            let file_name = path.file_name().context("File without name")?;
+            fs::rename(&path, self.dir.join(target_dir).join(file_name))
+                .with_context(|| format!("Failed to move file {path:?} to {target_dir}"))?;
+        }
+        Ok(reports)
+    }
+}
+
+/// Hidden files are skipped, so writers can create them under a temporary name first
+fn is_report_file(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_none_or(|n| n.starts_with('.'));
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    !hidden && EXTENSIONS.contains(&extension.as_str())
+}
@@ -0,0 +1,217 @@
+use crate::config::Configuration;
+use anyhow::{Context, Result};
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::system_conf::read_system_conf;
+use hickory_resolver::TokioAsyncResolver;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum CacheKey {
+    Txt(String),
+    Ptr(IpAddr),
+    Mx(String),
+}
+
+struct CacheEntry {
+    /// Empty for negative results
+    values: Vec<String>,
+    expires: Instant,
+}
+
+/// DNS resolver with a shared cache that respects the TTLs of the records.
+/// Negative results are cached for a configurable duration.
+pub struct DnsResolver {
+    resolver: TokioAsyncResolver,
+    cache: Mutex<HashMap<CacheKey, CacheEntry>>,
+    max_entries: usize,
+    negative_ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Metrics of the DNS cache
+#[derive(Serialize, ToSchema)]
+pub struct DnsCacheStats {
+    pub entries: usize,
+    pub max_entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
+
+/// Value of a tag in a TXT record like DMARC or BIMI, empty values are treated as missing
+pub fn record_tag(record: &str, name: &str) -> Option<String> {
+    record
+        .split(';')
+        .filter_map(|t| t.split_once('='))
+        .find(|(key, _)| key.trim() == name)
+        .map(|(_, value)| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+}
+
+impl DnsResolver {
+    pub fn new(config: &Configuration) -> Self {
+        let (resolver_config, mut options) = read_system_conf().unwrap_or_else(|err| {
+            warn!("Failed to read system DNS configuration, using defaults: {err}");
+            (ResolverConfig::default(), ResolverOpts::default())
+        });
+        // Caching is done by this wrapper to allow metrics and a custom negative TTL
+        options.cache_size = 0;
+        Self {
+            resolver: TokioAsyncResolver::tokio(resolver_config, options),
+            cache: Mutex::new(HashMap::new()),
+            max_entries: config.dns_cache_size,
+            negative_ttl: Duration::from_secs(config.dns_negative_ttl),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get all TXT records of a name.
+    /// Multiple strings of a single record are concatenated.
+    /// Returns an empty list if the name or the records do not exist.
+    pub async fn txt(&self, name: &str) -> Result<Vec<String>> {
+        let key = CacheKey::Txt(name.to_lowercase());
+        if let Some(values) = self.cached(&key) {
+            return Ok(values);
+        }
+        let result = self.resolver.txt_lookup(name).await;
+        let (values, expires) = match result {
+            Ok(lookup) => {
+                let values = lookup
+                    .iter()
+                    .map(|txt| {
+                        txt.txt_data()
+                            .iter()
+                            .map(|data| String::from_utf8_lossy(data))
+                            .collect::<String>()
+                    })
+                    .collect();
+                (values, lookup.valid_until())
+            }
+            Err(err) => match err.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => {
+                    (Vec::new(), Instant::now() + self.negative_ttl)
+                }
+                _ => return Err(err).context(format!("Failed to look up TXT of {name}")),
+            },
+        };
+        self.store(key, &values, expires);
+        Ok(values)
+    }
+
+    /// Get the host names of an IP using reverse lookup
+    pub async fn ptr(&self, ip: IpAddr) -> Result<Vec<String>> {
+        let key = CacheKey::Ptr(ip);
+        if let Some(values) = self.cached(&key) {
+            return Ok(values);
+        }
+        let result = self.resolver.reverse_lookup(ip).await;
+        let (values, expires) = match result {
+            Ok(lookup) => {
+                let values = lookup
+                    .iter()
+                    .map(|ptr| ptr.to_string().trim_end_matches('.').to_string())
+                    .collect();
+                (values, lookup.valid_until())
+            }
+            Err(err) => match err.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => {
+                    (Vec::new(), Instant::now() + self.negative_ttl)
+                }
+                _ => return Err(err).context(format!("Failed to look up PTR of {ip}")),
+            },
+        };
+        self.store(key, &values, expires);
+        Ok(values)
+    }
+
+    /// Get the mail exchanger host names of a domain, ordered by preference
+    pub async fn mx(&self, name: &str) -> Result<Vec<String>> {
+        let key = CacheKey::Mx(name.to_lowercase());
+        if let Some(values) = self.cached(&key) {
+            return Ok(values);
+        }
+        let result = self.resolver.mx_lookup(name).await;
+        let (values, expires) = match result {
+            Ok(lookup) => {
+                let mut records: Vec<_> = lookup.iter().collect();
+                records.sort_by_key(|mx| mx.preference());
+                let values = records
+                    .iter()
+                    .map(|mx| mx.exchange().to_string().trim_end_matches('.').to_string())
+                    .collect();
+                (values, lookup.valid_until())
+            }
+            Err(err) => match err.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => {
+                    (Vec::new(), Instant::now() + self.negative_ttl)
+                }
+                _ => return Err(err).context(format!("Failed to look up MX of {name}")),
+            },
+        };
+        self.store(key, &values, expires);
+        Ok(values)
+    }
+
+    pub fn stats(&self) -> DnsCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let total = hits + misses;
+        DnsCacheStats {
+            entries: self.cache.lock().expect("Failed to lock DNS cache").len(),
+            max_entries: self.max_entries,
+            hits,
+            misses,
+            hit_rate: if total > 0 {
+                hits as f64 / total as f64
+            } else {
+                0.0
+            },
+        }
+    }
+
+    fn cached(&self, key: &CacheKey) -> Option<Vec<String>> {
+        let cache = self.cache.lock().expect("Failed to lock DNS cache");
+        match cache.get(key) {
+            Some(entry) if entry.expires > Instant::now() => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.values.clone())
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn store(&self, key: CacheKey, values: &[String], expires: Instant) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut cache = self.cache.lock().expect("Failed to lock DNS cache");
+        if cache.len() >= self.max_entries {
+            let now = Instant::now();
+            cache.retain(|_, entry| entry.expires > now);
+        }
+        if cache.len() >= self.max_entries && !cache.contains_key(&key) {
+            debug!("DNS cache is full, skipping entry");
+            return;
+        }
+        cache.insert(
+            key,
+            CacheEntry {
+                values: values.to_vec(),
+                expires,
+            },
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use crate::filter::RecordFilter;
+use crate::report::{DispositionType, DmarcResultType, Report};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// Aggregated message counts of all records for a single domain
+#[derive(Serialize, Default, ToSchema)]
+pub struct DomainStats {
+    pub domain: String,
+    pub records: usize,
+    pub messages: usize,
+
+    /// Messages with passed DKIM or SPF policy evaluation
+    pub passed: usize,
+
+    /// Messages where DKIM and SPF policy evaluation did not pass
+    pub failed: usize,
+
+    pub dkim_failed: usize,
+    pub spf_failed: usize,
+    pub disposition_none: usize,
+    pub disposition_quarantine: usize,
+    pub disposition_reject: usize,
+
+    /// Message counts per reporting organization
+    pub orgs: HashMap<String, usize>,
+
+    /// Message counts per source IP
+    #[serde(skip)]
+    pub sources: HashMap<IpAddr, SourceStats>,
+}
+
+/// Serializable summary of a domain with derived values
+#[derive(Serialize, ToSchema)]
+pub struct DomainSummary {
+    #[serde(flatten)]
+    pub stats: DomainStats,
+    pub pass_rate: f64,
+    pub distinct_source_ips: usize,
+}
+
+impl From<DomainStats> for DomainSummary {
+    fn from(stats: DomainStats) -> Self {
+        Self {
+            pass_rate: stats.pass_rate(),
+            distinct_source_ips: stats.sources.len(),
+            stats,
+        }
+    }
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct SourceStats {
+    pub messages: usize,
+    pub passed: usize,
+    pub failed: usize,
+}
+
+impl DomainStats {
+    /// Percentage of passed messages
+    pub fn pass_rate(&self) -> f64 {
+        if self.messages == 0 {
+            0.0
+        } else {
+            self.passed as f64 * 100.0 / self.messages as f64
+        }
+    }
+
+    /// Sources sorted by descending message count
+    pub fn top_sources(&self, limit: usize) -> Vec<(IpAddr, SourceStats)> {
+        let mut sources: Vec<(IpAddr, SourceStats)> = self
+            .sources
+            .iter()
+            .map(|(ip, stats)| (*ip, stats.clone()))
+            .collect();
+        sources.sort_by(|a, b| b.1.messages.cmp(&a.1.messages).then(a.0.cmp(&b.0)));
+        sources.truncate(limit);
+        sources
+    }
+}
+
+/// Aggregate all matching records by the domain of the published policy.
+/// The result is sorted by domain name.
+pub fn domain_stats(reports: &[Report], filter: &RecordFilter) -> Vec<DomainStats> {
+    let mut domains: HashMap<String, DomainStats> = HashMap::new();
+    for (report, record) in filter.records(reports) {
+        let domain = filter.group_domain(&report.policy_published.domain);
+        let stats = domains
+            .entry(domain.clone())
+            .or_insert_with(|| DomainStats {
+                domain,
+                ..Default::default()
+            });
+        let count = record.row.count;
+        let evaluated = &record.row.policy_evaluated;
+        let passed = record.is_dmarc_pass();
+
+        stats.records += 1;
+        stats.messages += count;
+        *stats
+            .orgs
+            .entry(report.report_metadata.org_name.clone())
+            .or_default() += count;
+        let source = stats.sources.entry(record.row.source_ip).or_default();
+        source.messages += count;
+        if passed {
+            stats.passed += count;
+            source.passed += count;
+        } else {
+            stats.failed += count;
+            source.failed += count;
+        }
+        if evaluated.dkim == Some(DmarcResultType::Fail) {
+            stats.dkim_failed += count;
+        }
+        if evaluated.spf == Some(DmarcResultType::Fail) {
+            stats.spf_failed += count;
+        }
+        match evaluated.disposition {
+            DispositionType::None => stats.disposition_none += count,
+            DispositionType::Quarantine => stats.disposition_quarantine += count,
+            DispositionType::Reject => stats.disposition_reject += count,
+            DispositionType::Unknown(..) => {}
+        }
+    }
+    let mut domains: Vec<DomainStats> = domains.into_values().collect();
+    domains.sort_by(|a, b| a.domain.cmp(&b.domain));
+    domains
+}
@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// Report that was skipped because a report with the same
+/// reporting organization and report ID was already known
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DuplicateReport {
+    /// UID of the mail containing the duplicate, empty for ingested reports
+    pub mail_uid: Option<u32>,
+    pub org_name: String,
+    pub report_id: String,
+}
@@ -0,0 +1,170 @@
+use crate::filter::RecordFilter;
+use crate::report::{DispositionType, Report, TestingType};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use utoipa::ToSchema;
+
+/// Failing messages by requested and applied disposition
+#[derive(Serialize, Default, ToSchema)]
+pub struct EnforcementCounts {
+    /// Messages failing DMARC
+    pub failed: usize,
+
+    /// Failing messages with a requested policy of quarantine or reject
+    pub enforceable: usize,
+
+    /// Enforceable messages handled as requested
+    pub enforced: usize,
+
+    /// Messages quarantined although reject was requested
+    pub downgraded: usize,
+
+    /// Enforceable messages delivered without quarantine or reject
+    pub not_enforced: usize,
+
+    /// Not enforced messages per override reason, `none` if the receiver gave no reason
+    pub reasons: BTreeMap<String, usize>,
+
+    /// Share of enforceable messages that were quarantined or rejected in percent
+    pub enforcement_rate: Option<f64>,
+
+    /// Share expected from the published percentage and testing mode in percent
+    pub expected_rate: Option<f64>,
+
+    /// Sum of the expected percentage per message, used for the expected rate
+    #[serde(skip)]
+    expected: f64,
+}
+
+impl EnforcementCounts {
+    fn finish(&mut self) {
+        if self.enforceable > 0 {
+            let enforceable = self.enforceable as f64;
+            let applied = (self.enforced + self.downgraded) as f64;
+            self.enforcement_rate = Some(applied / enforceable * 100.0);
+            self.expected_rate = Some(self.expected / enforceable);
+        }
+    }
+}
+
+/// Published policies compared with the applied dispositions, in total, per domain and per reporter
+#[derive(Serialize, Default, ToSchema)]
+pub struct EnforcementSummary {
+    pub total: EnforcementCounts,
+    pub domains: BTreeMap<String, EnforcementCounts>,
+    pub orgs: BTreeMap<String, EnforcementCounts>,
+}
+
+/// Aggregate the dispositions of all matching records failing DMARC.
+/// The requested policy is the subdomain policy for subdomains if published.
+/// Receivers only enforce the published percentage of failing messages
+/// and none of them in testing mode, which makes up the expected rate.
+pub fn enforcement_summary(reports: &[Report], filter: &RecordFilter) -> EnforcementSummary {
+    let mut summary = EnforcementSummary::default();
+    for (report, record) in filter.records(reports) {
+        if record.is_dmarc_pass() {
+            continue;
+        }
+        let policy = &report.policy_published;
+        let subdomain = !record
+            .identifiers
+            .header_from
+            .eq_ignore_ascii_case(&policy.domain);
+        let requested = match &policy.sp {
+            Some(sp) if subdomain => sp,
+            _ => &policy.p,
+        };
+        let enforceable = matches!(
+            requested,
+            DispositionType::Quarantine | DispositionType::Reject
+        );
+        let percentage = if policy.testing == Some(TestingType::Yes) {
+            0.0
+        } else {
+            f64::from(policy.pct.unwrap_or(100).min(100))
+        };
+        let evaluated = &record.row.policy_evaluated;
+        let applied = &evaluated.disposition;
+        let mut reasons: BTreeSet<String> = evaluated
+            .reason
+            .iter()
+            .flatten()
+            .map(|r| String::from(r.kind.clone()))
+            .collect();
+        if reasons.is_empty() {
+            reasons.insert(String::from("none"));
+        }
+
+        let count = record.row.count;
+        let domain = filter.group_domain(&policy.domain);
+        let org = report.report_metadata.org_name.clone();
+        for counts in [
+            &mut summary.total,
+            summary.domains.entry(domain).or_default(),
+            summary.orgs.entry(org).or_default(),
+        ] {
+            counts.failed += count;
+            if !enforceable {
+                continue;
+            }
+            counts.enforceable += count;
+            counts.expected += percentage * count as f64;
+            match applied {
+                DispositionType::None => {
+                    counts.not_enforced += count;
+                    for reason in &reasons {
+                        *counts.reasons.entry(reason.clone()).or_default() += count;
+                    }
+                }
+                DispositionType::Quarantine if *requested == DispositionType::Reject => {
+                    counts.downgraded += count;
+                }
+                _ => counts.enforced += count,
+            }
+        }
+    }
+    summary.total.finish();
+    for counts in summary
+        .domains
+        .values_mut()
+        .chain(summary.orgs.values_mut())
+    {
+        counts.finish();
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use crate::report::{PolicyOverrideReason, PolicyOverrideType};
+    use std::fs;
+
+    #[test]
+    fn sampled_out_failures() {
+        let xml = fs::read("testdata/dmarc-reports/mailru.xml").unwrap();
+        let enforced = parse_xml_file(&xml).unwrap();
+        let mut sampled = enforced.clone();
+        sampled.report_metadata.org_name = String::from("Other");
+        sampled.policy_published.pct = Some(50);
+        let evaluated = &mut sampled.record[0].row.policy_evaluated;
+        evaluated.disposition = DispositionType::None;
+        evaluated.reason = Some(vec![PolicyOverrideReason {
+            kind: PolicyOverrideType::SampledOut,
+            comment: None,
+        }]);
+
+        let summary = enforcement_summary(&[enforced, sampled], &RecordFilter::default());
+        let total = &summary.total;
+        assert_eq!(total.enforceable, 2);
+        assert_eq!(total.enforced, 1);
+        assert_eq!(total.not_enforced, 1);
+        assert_eq!(total.reasons["sampled_out"], 1);
+        assert_eq!(total.enforcement_rate, Some(50.0));
+        assert_eq!(total.expected_rate, Some(75.0));
+        assert_eq!(summary.orgs["Mail.Ru"].enforcement_rate, Some(100.0));
+        assert_eq!(summary.orgs["Other"].not_enforced, 1);
+        assert_eq!(summary.domains["foobar.de"].failed, 2);
+    }
+}
@@ -0,0 +1,58 @@
+use serde::Serialize;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+/// Number of events buffered for slow subscribers before they miss events
+const CAPACITY: usize = 100;
+
+/// Live update pushed to connected clients
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    CycleStarted {
+        cycle_id: u64,
+    },
+    CycleFinished {
+        cycle_id: u64,
+        success: bool,
+        error: Option<String>,
+    },
+    NewReports {
+        count: usize,
+    },
+    ParseErrors {
+        count: usize,
+    },
+}
+
+impl Event {
+    /// Name of the event as used in the SSE event field
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::CycleStarted { .. } => "cycle_started",
+            Event::CycleFinished { .. } => "cycle_finished",
+            Event::NewReports { .. } => "new_reports",
+            Event::ParseErrors { .. } => "parse_errors",
+        }
+    }
+}
+
+/// Distributes events to all current subscribers
+pub struct Events {
+    sender: Sender<Event>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+
+    /// Sends the event to all subscribers, events without subscribers are dropped
+    pub fn send(&self, event: Event) {
+        self.sender.send(event).ok();
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
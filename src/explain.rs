@@ -0,0 +1,351 @@
+use crate::psl::organizational_domain;
+use crate::report::{
+    find_report, AlignmentType, DispositionType, DkimResultType, DmarcResultType,
+    PolicyOverrideType, RecordType, Report, SpfResultType, TestingType,
+};
+use serde::Serialize;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// Alignment mode of an identifier, relaxed unless the policy requests strict alignment
+#[derive(Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AlignmentMode {
+    /// Same organizational domain as the header from domain
+    Relaxed,
+    /// Exactly the header from domain
+    Strict,
+}
+
+impl AlignmentMode {
+    pub fn new(alignment: Option<&AlignmentType>) -> Self {
+        match alignment {
+            Some(AlignmentType::Strict) => Self::Strict,
+            _ => Self::Relaxed,
+        }
+    }
+
+    pub fn aligns(self, domain: &str, header_from: &str) -> bool {
+        match self {
+            Self::Strict => {
+                domain.trim_end_matches('.').to_lowercase()
+                    == header_from.trim_end_matches('.').to_lowercase()
+            }
+            Self::Relaxed => organizational_domain(domain) == organizational_domain(header_from),
+        }
+    }
+}
+
+/// DKIM signature or SPF check of a record and its alignment with the header from domain
+#[derive(Serialize, Debug, ToSchema)]
+pub struct IdentifierCheck {
+    pub domain: String,
+    pub result: String,
+    pub mode: AlignmentMode,
+    pub aligned: bool,
+
+    /// Passed with an aligned domain, which is enough for DMARC to pass
+    pub passed: bool,
+}
+
+/// Step by step evaluation of a record as done by the receiver
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RecordExplanation {
+    /// Record ID as used by the explain endpoint
+    pub id: String,
+    pub report_id: String,
+    #[schema(value_type = String)]
+    pub source_ip: IpAddr,
+    pub count: usize,
+    pub header_from: String,
+    pub organizational_domain: String,
+    pub policy_domain: String,
+    pub dkim: Vec<IdentifierCheck>,
+    pub spf: Vec<IdentifierCheck>,
+
+    /// DMARC result according to the reported authentication results
+    pub dmarc_pass: bool,
+
+    /// Policy requested for failing messages, the subdomain policy for subdomains if published
+    pub requested: DispositionType,
+    pub disposition: DispositionType,
+
+    /// Human readable breakdown, one sentence per step
+    pub steps: Vec<String>,
+}
+
+/// ID of a record, made of the stable ID of the report and the index of the record
+pub fn record_id(report: &Report, index: usize) -> String {
+    format!("{}-{index}", report.stable_id())
+}
+
+/// Finds a record by the stable ID or the ID assigned by the reporter and the index of the record
+pub fn find_record<'a>(reports: &'a [Report], id: &str) -> Option<(&'a Report, usize)> {
+    let (report_id, index) = id.rsplit_once('-')?;
+    let index: usize = index.parse().ok()?;
+    let report = find_report(reports, report_id)?;
+    (index < report.record.len()).then_some((report, index))
+}
+
+/// Walks through the DMARC evaluation of a record of the report
+pub fn explain(report: &Report, index: usize) -> RecordExplanation {
+    let record = &report.record[index];
+    let policy = &report.policy_published;
+    let header_from = record.identifiers.header_from.to_lowercase();
+    let org_domain = organizational_domain(&header_from);
+    let mut steps = vec![format!(
+        "The header from domain {header_from} belongs to the organizational domain {org_domain} \
+         and the policy published for {} applies.",
+        policy.domain
+    )];
+
+    let dkim_mode = AlignmentMode::new(policy.adkim.as_ref());
+    let dkim: Vec<IdentifierCheck> = record
+        .auth_results
+        .dkim
+        .iter()
+        .flatten()
+        .map(|d| {
+            check(
+                &d.domain,
+                String::from(d.result.clone()),
+                d.result == DkimResultType::Pass,
+                dkim_mode,
+                &header_from,
+            )
+        })
+        .collect();
+    explain_checks("DKIM signature", &dkim, &header_from, &mut steps);
+
+    let spf_mode = AlignmentMode::new(policy.aspf.as_ref());
+    let spf: Vec<IdentifierCheck> = record
+        .auth_results
+        .spf
+        .iter()
+        .map(|s| {
+            check(
+                &s.domain,
+                String::from(s.result.clone()),
+                s.result == SpfResultType::Pass,
+                spf_mode,
+                &header_from,
+            )
+        })
+        .collect();
+    explain_checks("SPF check", &spf, &header_from, &mut steps);
+
+    let dkim_pass = dkim.iter().any(|c| c.passed);
+    let spf_pass = spf.iter().any(|c| c.passed);
+    let dmarc_pass = dkim_pass || spf_pass;
+    steps.push(match (dkim_pass, spf_pass) {
+        (true, true) => {
+            String::from("DMARC passed because DKIM and SPF passed with aligned domains.")
+        }
+        (true, false) => String::from("DMARC passed because DKIM passed with an aligned domain."),
+        (false, true) => String::from("DMARC passed because SPF passed with an aligned domain."),
+        (false, false) => {
+            String::from("DMARC failed because neither DKIM nor SPF passed with an aligned domain.")
+        }
+    });
+    explain_evaluation(record, dkim_pass, spf_pass, &mut steps);
+
+    let subdomain = header_from != policy.domain.to_lowercase();
+    let requested = match &policy.sp {
+        Some(sp) if subdomain => sp.clone(),
+        _ => policy.p.clone(),
+    };
+    let disposition = record.row.policy_evaluated.disposition.clone();
+    if policy.testing == Some(TestingType::Yes) {
+        steps.push(String::from(
+            "The policy is in testing mode, so receivers apply a disposition one level less strict than requested.",
+        ));
+    }
+    explain_disposition(record, &requested, policy.pct, &mut steps);
+
+    RecordExplanation {
+        id: record_id(report, index),
+        report_id: report.stable_id(),
+        source_ip: record.row.source_ip,
+        count: record.row.count,
+        header_from,
+        organizational_domain: org_domain,
+        policy_domain: policy.domain.clone(),
+        dkim,
+        spf,
+        dmarc_pass,
+        requested,
+        disposition,
+        steps,
+    }
+}
+
+/// Alignment of a DKIM signature or SPF check with the header from domain
+pub fn check(
+    domain: &str,
+    result: String,
+    pass: bool,
+    mode: AlignmentMode,
+    header_from: &str,
+) -> IdentifierCheck {
+    let aligned = mode.aligns(domain, header_from);
+    IdentifierCheck {
+        domain: domain.to_lowercase(),
+        result,
+        mode,
+        aligned,
+        passed: pass && aligned,
+    }
+}
+
+/// Adds one step per DKIM signature or SPF check explaining whether it passed and aligned
+pub fn explain_checks(
+    name: &str,
+    checks: &[IdentifierCheck],
+    header_from: &str,
+    steps: &mut Vec<String>,
+) {
+    if checks.is_empty() {
+        steps.push(format!(
+            "No {name} was reported, so it cannot contribute to DMARC."
+        ));
+    }
+    for check in checks {
+        let domain = &check.domain;
+        let step = match (check.result == "pass", check.aligned, check.mode) {
+            (true, true, AlignmentMode::Strict) => format!(
+                "{name} for {domain} passed and is aligned, because strict alignment requires exactly {header_from}."
+            ),
+            (true, true, AlignmentMode::Relaxed) => format!(
+                "{name} for {domain} passed and is aligned, because relaxed alignment only requires the organizational domain of {header_from}."
+            ),
+            (true, false, AlignmentMode::Strict) => format!(
+                "{name} for {domain} passed but is not aligned, because strict alignment requires exactly {header_from}."
+            ),
+            (true, false, AlignmentMode::Relaxed) => format!(
+                "{name} for {domain} passed but is not aligned, because its organizational domain {} differs from the one of {header_from}.",
+                organizational_domain(domain)
+            ),
+            (false, _, _) => format!(
+                "{name} for {domain} did not pass with result {}, so its alignment does not matter.",
+                check.result
+            ),
+        };
+        steps.push(step);
+    }
+}
+
+/// Compares the verdict of the receiver with the reported authentication results
+fn explain_evaluation(
+    record: &RecordType,
+    dkim_pass: bool,
+    spf_pass: bool,
+    steps: &mut Vec<String>,
+) {
+    let evaluated = &record.row.policy_evaluated;
+    let verdict = |result: &Option<DmarcResultType>| {
+        result.clone().map_or(String::from("missing"), String::from)
+    };
+    steps.push(format!(
+        "The receiver evaluated DKIM as {} and SPF as {}.",
+        verdict(&evaluated.dkim),
+        verdict(&evaluated.spf)
+    ));
+    let differs = |result: &Option<DmarcResultType>, pass: bool| {
+        result
+            .as_ref()
+            .is_some_and(|r| (*r == DmarcResultType::Pass) != pass)
+    };
+    if differs(&evaluated.dkim, dkim_pass) || differs(&evaluated.spf, spf_pass) {
+        steps.push(String::from(
+            "This differs from the reported authentication results, \
+             so the receiver based its verdict on results not included in the report.",
+        ));
+    }
+}
+
+fn explain_disposition(
+    record: &RecordType,
+    requested: &DispositionType,
+    pct: Option<u8>,
+    steps: &mut Vec<String>,
+) {
+    let evaluated = &record.row.policy_evaluated;
+    let disposition = String::from(evaluated.disposition.clone());
+    let requested_name = String::from(requested.clone());
+    if record.is_dmarc_pass() {
+        if evaluated.disposition == DispositionType::None {
+            steps.push(String::from("No policy was applied because DMARC passed."));
+        } else {
+            steps.push(format!(
+                "The receiver applied {disposition} although DMARC passed."
+            ));
+        }
+    } else if evaluated.disposition == *requested {
+        steps.push(format!(
+            "The receiver applied {disposition} as requested by the policy for failing messages."
+        ));
+    } else {
+        steps.push(format!(
+            "The receiver applied {disposition} instead of {requested_name} as requested by the policy for failing messages."
+        ));
+        if let Some(pct) = pct.filter(|pct| *pct < 100) {
+            steps.push(format!(
+                "The policy only applies to {pct}% of the failing messages, the others are handled one level less strict."
+            ));
+        }
+    }
+    for reason in evaluated.reason.iter().flatten() {
+        let kind = match &reason.kind {
+            PolicyOverrideType::Forwarded => "the message was forwarded",
+            PolicyOverrideType::SampledOut => {
+                "the message was sampled out by the percentage of the policy"
+            }
+            PolicyOverrideType::TrustedForwarder => "the message came from a trusted forwarder",
+            PolicyOverrideType::MailingList => "the message came from a mailing list",
+            PolicyOverrideType::LocalPolicy => "the local policy of the receiver",
+            PolicyOverrideType::Other | PolicyOverrideType::Unknown(_) => "another reason",
+        };
+        steps.push(match &reason.comment {
+            Some(comment) => {
+                format!("The receiver overrode the policy because of {kind}: {comment}")
+            }
+            None => format!("The receiver overrode the policy because of {kind}."),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn explain_spf_alignment() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let reports = vec![report];
+        let id = record_id(&reports[0], 0);
+        let (report, index) = find_record(&reports, &id).unwrap();
+        assert!(find_record(&reports, &format!("{}-1", report.stable_id())).is_none());
+        let via_report_id = format!("{}-0", report.report_metadata.report_id);
+        assert!(find_record(&reports, &via_report_id).is_some());
+
+        let explanation = explain(report, index);
+        assert_eq!(explanation.id, id);
+        assert!(explanation.dmarc_pass);
+        assert!(!explanation.dkim[0].passed);
+        assert!(explanation.dkim[0].aligned);
+        assert!(explanation.spf[0].passed);
+        assert_eq!(explanation.requested, DispositionType::None);
+        assert!(explanation
+            .steps
+            .iter()
+            .any(|s| s == "DMARC passed because SPF passed with an aligned domain."));
+        assert!(explanation
+            .steps
+            .last()
+            .unwrap()
+            .starts_with("The receiver overrode the policy because of another reason: "));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::annotations::Annotations;
+use crate::config::{ExportConfiguration, ExportFormat};
+use crate::csv::csv_line;
+use crate::filter::RecordFilter;
+use crate::report::{RecordType, Report};
+use crate::state::AppState;
+use crate::timeseries::DAY;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::Arc;
+
+const CSV_HEADER: &[&str] = &[
+    "report_id",
+    "org",
+    "domain",
+    "date_begin",
+    "date_end",
+    "source_ip",
+    "count",
+    "disposition",
+    "dkim",
+    "spf",
+    "header_from",
+    "envelope_from",
+    "envelope_to",
+    "dkim_auth",
+    "spf_auth",
+    "source_label",
+    "domain_owner",
+];
+
+/// Create CSV lines (including header) with one flattened line per DMARC record
+pub fn records_csv(
+    reports: &[Report],
+    annotations: &Annotations,
+    filter: &RecordFilter,
+) -> Vec<String> {
+    std::iter::once(csv_header())
+        .chain(
+            filter
+                .records(reports)
+                .map(|(report, record)| record_line(report, record, annotations)),
+        )
+        .collect()
+}
+
+/// Same lines as [`records_csv`], but created one at a time while iterating,
+/// so large exports of the state snapshot are never held in memory as a whole
+pub fn records_csv_lines(
+    state: Arc<AppState>,
+    filter: RecordFilter,
+) -> impl Iterator<Item = String> + Send + 'static {
+    let filter = Arc::new(filter);
+    let lines = (0..state.reports.len()).flat_map(move |index| {
+        let (state, filter) = (state.clone(), filter.clone());
+        let report = &state.reports[index];
+        let records = if filter.matches_report(report) {
+            report.record.len()
+        } else {
+            0
+        };
+        (0..records).filter_map(move |record| {
+            let report = &state.reports[index];
+            let record = &report.record[record];
+            filter
+                .matches_record(record)
+                .then(|| record_line(report, record, &state.annotations))
+        })
+    });
+    std::iter::once(csv_header()).chain(lines)
+}
+
+fn csv_header() -> String {
+    csv_line(CSV_HEADER.iter().map(|h| h.to_string()))
+}
+
+fn record_line(report: &Report, record: &RecordType, annotations: &Annotations) -> String {
+    let dkim_auth = record
+        .auth_results
+        .dkim
+        .iter()
+        .flatten()
+        .map(|r| format!("{}:{}", r.domain, value_string(&r.result)))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let spf_auth = record
+        .auth_results
+        .spf
+        .iter()
+        .map(|r| format!("{}:{}", r.domain, value_string(&r.result)))
+        .collect::<Vec<String>>()
+        .join(" ");
+    csv_line([
+        report.report_metadata.report_id.clone(),
+        report.report_metadata.org_name.clone(),
+        report.policy_published.domain.clone(),
+        report.report_metadata.date_range.begin.to_string(),
+        report.report_metadata.date_range.end.to_string(),
+        record.row.source_ip.to_string(),
+        record.row.count.to_string(),
+        value_string(&record.row.policy_evaluated.disposition),
+        value_string(&record.row.policy_evaluated.dkim),
+        value_string(&record.row.policy_evaluated.spf),
+        record.identifiers.header_from.clone(),
+        value_string(&record.identifiers.envelope_from),
+        value_string(&record.identifiers.envelope_to),
+        dkim_auth,
+        spf_auth,
+        annotations
+            .ip_label(&record.row.source_ip)
+            .unwrap_or_default()
+            .to_string(),
+        annotations
+            .domain_owner(&record.identifiers.header_from)
+            .unwrap_or_default()
+            .to_string(),
+    ])
+}
+
+/// Write all records as CSV or the complete state as JSON, depending on the file extension
+pub fn write_export(state: &AppState, path: &str) -> Result<()> {
+    let data = if path.to_lowercase().ends_with(".csv") {
+        records_csv(&state.reports, &state.annotations, &RecordFilter::default())
+            .concat()
+            .into_bytes()
+    } else {
+        state.to_json()?
+    };
+    fs::write(path, data).with_context(|| format!("Failed to write export file {path}"))
+}
+
+/// Write the records or reports matching the filters of the export subcommand.
+/// Returns the number of exported records or reports.
+pub fn write_filtered_export(state: &AppState, config: &ExportConfiguration) -> Result<usize> {
+    let filter = RecordFilter {
+        domain: config.domain.clone(),
+        org: config.org.clone(),
+        since: config.since,
+        // Include the whole last day
+        until: config.until.map(|until| until + DAY - 1),
+        only_failures: Some(config.only_failures),
+        ..Default::default()
+    };
+    let (data, count) = export_data(&state.reports, &state.annotations, &filter, config.format)?;
+    fs::write(&config.output, data)
+        .with_context(|| format!("Failed to write export file {}", config.output))?;
+    Ok(count)
+}
+
+/// Export files of the reports beginning within the time range, one per domain.
+/// Files are named like `2024-03-01/example.com.csv` by the given day.
+pub fn daily_exports(
+    reports: &[Report],
+    annotations: &Annotations,
+    since: u64,
+    until: u64,
+    day: &str,
+    format: ExportFormat,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    // Every report is part of exactly one day, even if its date range spans midnight
+    let reports: Vec<Report> = reports
+        .iter()
+        .filter(|r| (since..=until).contains(&r.report_metadata.date_range.begin))
+        .cloned()
+        .collect();
+    let domains: BTreeSet<String> = reports
+        .iter()
+        .map(|r| r.policy_published.domain.to_lowercase())
+        .collect();
+    let extension = match format {
+        ExportFormat::Csv => "csv",
+        ExportFormat::Json => "json",
+    };
+    let mut files = Vec::new();
+    for domain in domains {
+        let filter = RecordFilter {
+            domain: Some(domain.clone()),
+            ..Default::default()
+        };
+        let (data, _) = export_data(&reports, annotations, &filter, format)?;
+        files.push((format!("{day}/{domain}.{extension}"), data));
+    }
+    Ok(files)
+}
+
+/// CSV with the matching records or JSON with the matching reports, together with their number
+fn export_data(
+    reports: &[Report],
+    annotations: &Annotations,
+    filter: &RecordFilter,
+    format: ExportFormat,
+) -> Result<(Vec<u8>, usize)> {
+    Ok(match format {
+        ExportFormat::Csv => {
+            let lines = records_csv(reports, annotations, filter);
+            let count = lines.len() - 1;
+            (lines.concat().into_bytes(), count)
+        }
+        ExportFormat::Json => {
+            let reports = filtered_reports(reports, filter);
+            let json =
+                serde_json::to_vec_pretty(&reports).context("Failed to serialize reports")?;
+            (json, reports.len())
+        }
+    })
+}
+
+/// Matching reports with only their matching records
+fn filtered_reports(reports: &[Report], filter: &RecordFilter) -> Vec<Report> {
+    reports
+        .iter()
+        .filter(|r| filter.matches_report_records(r))
+        .map(|r| {
+            let mut report = r.clone();
+            report.record.retain(|record| filter.matches_record(record));
+            report
+        })
+        .collect()
+}
+
+/// Get the serialized string representation of enums and optional values
+pub fn value_string<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(string)) => string,
+        Ok(Value::Null) | Err(..) => String::new(),
+        Ok(other) => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+
+    #[test]
+    fn export_days_per_domain() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let begin = report.report_metadata.date_range.begin;
+        let mut other = report.clone();
+        other.policy_published.domain = String::from("Example.org");
+        let mut next_day = report.clone();
+        next_day.report_metadata.date_range.begin += DAY;
+        let reports = [report, other, next_day];
+        let annotations = Annotations::default();
+
+        let files = daily_exports(
+            &reports,
+            &annotations,
+            begin,
+            begin + DAY - 1,
+            "2024-03-01",
+            ExportFormat::Csv,
+        )
+        .unwrap();
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["2024-03-01/example.com.csv", "2024-03-01/example.org.csv"]
+        );
+        let csv = String::from_utf8(files[0].1.clone()).unwrap();
+        assert_eq!(csv.lines().count(), 1 + reports[0].record.len());
+    }
+
+    #[test]
+    fn stream_lines_of_snapshot() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let mut other = report.clone();
+        other.policy_published.domain = String::from("example.org");
+        let state = AppState {
+            reports: Arc::new(vec![report.clone(), other]),
+            ..Default::default()
+        };
+        let filter = RecordFilter {
+            domain: Some(report.policy_published.domain.clone()),
+            ..Default::default()
+        };
+
+        let expected = records_csv(&state.reports, &state.annotations, &filter);
+        assert_eq!(expected.len(), 1 + report.record.len());
+        let lines: Vec<String> = records_csv_lines(Arc::new(state), filter).collect();
+        assert_eq!(lines, expected);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::filter::RecordFilter;
+use crate::report::Report;
+use chrono::DateTime;
+use quick_xml::escape::escape;
+use std::fmt::Write;
+
+/// Atom feed with an entry per report, newest reports first.
+/// Only reports with matching records are included, so `only_failures`
+/// turns it into a feed of failure events.
+/// The links point to the report pages of the web UI below `base_url`.
+pub fn atom_feed(
+    reports: &[Report],
+    filter: &RecordFilter,
+    base_url: &str,
+    limit: usize,
+    updated: u64,
+) -> String {
+    let entries: Vec<&Report> = reports
+        .iter()
+        .rev()
+        .filter(|r| filter.matches_report_records(r))
+        .take(limit)
+        .collect();
+    let updated = entries
+        .iter()
+        .map(|r| r.report_metadata.date_range.end)
+        .max()
+        .unwrap_or_default()
+        .max(updated);
+
+    let mut feed = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    feed.push_str("  <title>DMARC Reports</title>\n");
+    let _ = writeln!(
+        feed,
+        "  <id>{}</id>",
+        escape(format!("{base_url}/feed.xml"))
+    );
+    let _ = writeln!(feed, "  <link href=\"{}/\"/>", escape(base_url));
+    let _ = writeln!(feed, "  <updated>{}</updated>", timestamp(updated));
+    feed.push_str("  <author><name>DMARC Report Viewer</name></author>\n");
+    for report in entries {
+        let metadata = &report.report_metadata;
+        let stable_id = report.stable_id();
+        let records: Vec<_> = report
+            .record
+            .iter()
+            .filter(|r| filter.matches_record(r))
+            .collect();
+        let messages: usize = records.iter().map(|r| r.row.count).sum();
+        let failed: usize = records
+            .iter()
+            .filter(|r| !r.is_dmarc_pass())
+            .map(|r| r.row.count)
+            .sum();
+        let mut title = format!(
+            "{} for {}: {messages} messages",
+            metadata.org_name, report.policy_published.domain
+        );
+        if failed > 0 {
+            let _ = write!(title, ", {failed} failed");
+        }
+        let summary = format!(
+            "Report {} from {} to {} with {} records, policy {}",
+            metadata.report_id,
+            timestamp(metadata.date_range.begin),
+            timestamp(metadata.date_range.end),
+            records.len(),
+            String::from(report.policy_published.p.clone()),
+        );
+        feed.push_str("  <entry>\n");
+        let _ = writeln!(feed, "    <title>{}</title>", escape(&title));
+        let _ = writeln!(feed, "    <id>urn:dmarc-report:{stable_id}</id>");
+        let _ = writeln!(
+            feed,
+            "    <link href=\"{}/#/reports/{stable_id}\"/>",
+            escape(base_url)
+        );
+        let _ = writeln!(
+            feed,
+            "    <updated>{}</updated>",
+            timestamp(metadata.date_range.end)
+        );
+        let _ = writeln!(feed, "    <summary>{}</summary>", escape(&summary));
+        feed.push_str("  </entry>\n");
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+/// RFC 3339 time as required by Atom
+fn timestamp(time: u64) -> String {
+    DateTime::from_timestamp(time as i64, 0)
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn newest_entries_first() {
+        let reports: Vec<Report> = ["acme.xml", "aol.xml", "google.xml"]
+            .iter()
+            .map(|f| fs::read(format!("testdata/dmarc-reports/{f}")).unwrap())
+            .map(|xml| parse_xml_file(&xml).unwrap())
+            .collect();
+        let feed = atom_feed(
+            &reports,
+            &RecordFilter::default(),
+            "https://dmarc.example.com",
+            2,
+            0,
+        );
+        assert_eq!(feed.matches("<entry>").count(), 2);
+        let newest = format!(
+            "<link href=\"https://dmarc.example.com/#/reports/{}\"/>",
+            reports[2].stable_id()
+        );
+        let second = feed.find(&reports[1].stable_id()).unwrap();
+        assert!(feed.find(&newest).unwrap() < second);
+        assert!(!feed.contains(&reports[0].stable_id()));
+    }
+}
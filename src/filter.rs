@@ -0,0 +1,208 @@
+use crate::cidr::{IpNetwork, IpNetworks, IpVersion};
+use crate::psl::organizational_domain;
+use crate::report::{RecordType, Report};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::Arc;
+use utoipa::IntoParams;
+
+/// Query parameters to filter DMARC records across all reports.
+/// Reports are matched by their metadata and records by their row data.
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RecordFilter {
+    /// Domain of the published policy
+    pub domain: Option<String>,
+
+    /// Name of the reporting organization
+    pub org: Option<String>,
+
+    /// Source IP of the record
+    #[param(value_type = Option<String>)]
+    pub source_ip: Option<IpAddr>,
+
+    /// Only records with a source IP in one of these comma separated networks in CIDR notation,
+    /// like `192.0.2.0/24` or `192.0.2.0/24,2001:db8::/32`
+    #[param(value_type = Option<String>)]
+    pub network: Option<IpNetworks>,
+
+    /// Only records with an IPv4 (`4`) or IPv6 (`6`) source IP
+    pub ip_version: Option<IpVersion>,
+
+    /// Only reports with a date range ending at or after this Unix timestamp
+    pub since: Option<u64>,
+
+    /// Only reports with a date range beginning at or before this Unix timestamp
+    pub until: Option<u64>,
+
+    /// Group subdomains under their organizational domain
+    #[serde(default)]
+    pub rollup: bool,
+
+    /// Group source IPs into /24 (IPv4) and /48 (IPv6) networks in rankings of sources
+    #[serde(default)]
+    pub group_networks: bool,
+
+    /// Prefix length of the IPv6 networks when grouping networks, like 56 or 64 instead of 48
+    #[param(maximum = 128)]
+    pub ipv6_prefix: Option<u8>,
+
+    /// Only records with failed DKIM or SPF or a disposition other than none,
+    /// the configured default is used if not set
+    pub only_failures: Option<bool>,
+
+    /// Keep failures acknowledged by a user when only failures are included
+    #[serde(default)]
+    pub acknowledged: bool,
+
+    /// Domains of the tenant of the caller, set by the server and not by the query
+    #[serde(skip)]
+    #[param(ignore)]
+    pub tenant: Option<TenantDomains>,
+}
+
+impl RecordFilter {
+    pub fn matches_report(&self, report: &Report) -> bool {
+        if !self.allows_domain(&report.policy_published.domain) {
+            return false;
+        }
+        if let Some(domain) = &self.domain {
+            if !self.matches_domain(&report.policy_published.domain, domain) {
+                return false;
+            }
+        }
+        if let Some(org) = &self.org {
+            if report.report_metadata.org_name != *org {
+                return false;
+            }
+        }
+        if let Some(since) = self.since {
+            if report.report_metadata.date_range.end < since {
+                return false;
+            }
+        }
+        if let Some(until) = self.until {
+            if report.report_metadata.date_range.begin > until {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Checks if all reports and records match
+    pub fn is_unfiltered(&self) -> bool {
+        self.domain.is_none()
+            && self.org.is_none()
+            && self.source_ip.is_none()
+            && self.network.is_none()
+            && self.ip_version.is_none()
+            && self.since.is_none()
+            && self.until.is_none()
+            && self.only_failures != Some(true)
+            && self.tenant.is_none()
+    }
+
+    /// Checks if the domain belongs to the tenant of the caller, all domains do without tenant
+    pub fn allows_domain(&self, domain: &str) -> bool {
+        self.tenant
+            .as_ref()
+            .is_none_or(|tenant| tenant.allows(domain))
+    }
+
+    /// Checks if the report matches and has matching records, reports without records match as well
+    pub fn matches_report_records(&self, report: &Report) -> bool {
+        self.matches_report(report)
+            && (report.record.is_empty() || report.record.iter().any(|r| self.matches_record(r)))
+    }
+
+    /// Domain as used for grouping, the organizational domain when rolling up subdomains
+    pub fn group_domain(&self, domain: &str) -> String {
+        if self.rollup {
+            organizational_domain(domain)
+        } else {
+            domain.to_lowercase()
+        }
+    }
+
+    /// Source IP as used for rankings, the network of the IP when grouping networks
+    pub fn group_source(&self, ip: IpAddr) -> String {
+        if self.group_networks {
+            IpNetwork::group(ip, self.ipv6_prefix).to_string()
+        } else {
+            ip.to_canonical().to_string()
+        }
+    }
+
+    /// Case insensitive comparison of the domains after grouping
+    pub fn matches_domain(&self, domain: &str, other: &str) -> bool {
+        self.group_domain(domain) == self.group_domain(other)
+    }
+
+    pub fn matches_record(&self, record: &RecordType) -> bool {
+        if self.only_failures == Some(true)
+            && (!record.is_failure() || (record.acknowledged && !self.acknowledged))
+        {
+            return false;
+        }
+        if let Some(source_ip) = &self.source_ip {
+            if record.row.source_ip.to_canonical() != source_ip.to_canonical() {
+                return false;
+            }
+        }
+        if let Some(version) = self.ip_version {
+            if !version.matches(&record.row.source_ip) {
+                return false;
+            }
+        }
+        if let Some(network) = &self.network {
+            if !network.contains(&record.row.source_ip) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Iterate over all matching records together with their report
+    pub fn records<'a>(
+        &'a self,
+        reports: &'a [Report],
+    ) -> impl Iterator<Item = (&'a Report, &'a RecordType)> + 'a {
+        reports
+            .iter()
+            .filter(|report| self.matches_report(report))
+            .flat_map(|report| report.record.iter().map(move |record| (report, record)))
+            .filter(|(_, record)| self.matches_record(record))
+    }
+}
+
+/// Domains visible to a user or API token, including their subdomains
+#[derive(Clone, Debug)]
+pub struct TenantDomains(Arc<HashSet<String>>);
+
+impl TenantDomains {
+    pub fn new(domains: HashSet<String>) -> Self {
+        Self(Arc::new(domains))
+    }
+
+    /// Domains in sorted order
+    pub fn domains(&self) -> Vec<&str> {
+        let mut domains: Vec<&str> = self.0.iter().map(String::as_str).collect();
+        domains.sort_unstable();
+        domains
+    }
+
+    pub fn allows(&self, domain: &str) -> bool {
+        let domain = domain.to_lowercase();
+        let mut rest = domain.as_str();
+        loop {
+            if self.0.contains(rest) {
+                return true;
+            }
+            match rest.split_once('.') {
+                Some((_, parent)) => rest = parent,
+                None => return false,
+            }
+        }
+    }
+}
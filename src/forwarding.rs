@@ -0,0 +1,87 @@
+use crate::filter::RecordFilter;
+use crate::report::{DmarcResultType, PolicyOverrideType, Report};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// Message counts for a recipient domain from the envelope to identifier.
+/// Forwarding usually breaks SPF while DKIM signatures survive,
+/// so a high share of `spf_fail_dkim_pass` hints at forwarded mails.
+#[derive(Serialize, Default, ToSchema)]
+pub struct ForwardingStats {
+    pub envelope_to: String,
+    pub messages: usize,
+    pub dkim_failed: usize,
+    pub spf_failed: usize,
+
+    /// Messages where SPF failed but DKIM passed
+    pub spf_fail_dkim_pass: usize,
+
+    /// Messages failing both DKIM and SPF
+    pub both_failed: usize,
+
+    /// Messages where the receiver overrode the policy because of forwarding
+    pub forwarded_override: usize,
+
+    /// Source IPs that delivered mails for the recipient domain
+    pub source_ips: usize,
+}
+
+/// Aggregate all matching records with envelope to identifier by that domain.
+/// Sorted by descending number of messages with SPF failures.
+pub fn forwarding_stats(reports: &[Report], filter: &RecordFilter) -> Vec<ForwardingStats> {
+    let mut domains: HashMap<String, (ForwardingStats, HashSet<IpAddr>)> = HashMap::new();
+    for (_, record) in filter.records(reports) {
+        let Some(envelope_to) = &record.identifiers.envelope_to else {
+            continue;
+        };
+        let domain = envelope_to.to_lowercase();
+        let (stats, sources) = domains.entry(domain.clone()).or_insert_with(|| {
+            let stats = ForwardingStats {
+                envelope_to: domain,
+                ..Default::default()
+            };
+            (stats, HashSet::new())
+        });
+        let count = record.row.count;
+        let evaluated = &record.row.policy_evaluated;
+        let dkim_failed = evaluated.dkim != Some(DmarcResultType::Pass);
+        let spf_failed = evaluated.spf != Some(DmarcResultType::Pass);
+        stats.messages += count;
+        if dkim_failed {
+            stats.dkim_failed += count;
+        }
+        if spf_failed {
+            stats.spf_failed += count;
+            if dkim_failed {
+                stats.both_failed += count;
+            } else {
+                stats.spf_fail_dkim_pass += count;
+            }
+        }
+        let forwarded = evaluated.reason.iter().flatten().any(|r| {
+            matches!(
+                r.kind,
+                PolicyOverrideType::Forwarded | PolicyOverrideType::TrustedForwarder
+            )
+        });
+        if forwarded {
+            stats.forwarded_override += count;
+        }
+        sources.insert(record.row.source_ip);
+    }
+    let mut domains: Vec<ForwardingStats> = domains
+        .into_values()
+        .map(|(mut stats, sources)| {
+            stats.source_ips = sources.len();
+            stats
+        })
+        .collect();
+    domains.sort_by(|a, b| {
+        b.spf_failed
+            .cmp(&a.spf_failed)
+            .then(a.envelope_to.cmp(&b.envelope_to))
+    });
+    domains
+}
@@ -0,0 +1,399 @@
+use crate::config::Configuration;
+use crate::mail::{hashed_uid, Mail};
+use crate::source::select_new_mails;
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use reqwest::{Client, RequestBuilder, Url};
+use ring::rand::SystemRandom;
+use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+use rustls_pemfile::Item;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::BufReader;
+use std::sync::atomic::AtomicUsize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, info, warn};
+
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const GMAIL_URL: &str = "https://gmail.googleapis.com/gmail/v1/users";
+const SCOPE: &str = "https://www.googleapis.com/auth/gmail.readonly";
+
+/// Headers requested for the metadata of the mails
+const HEADERS: [&str; 4] = ["Subject", "From", "To", "Message-ID"];
+
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+}
+
+/// Relevant fields of the JSON key file of a service account
+#[derive(Deserialize)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+    token_uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LabelList {
+    #[serde(default)]
+    labels: Vec<Label>,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MessagePage {
+    #[serde(default)]
+    messages: Vec<MessageRef>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MessageRef {
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Message {
+    id: String,
+    internal_date: Option<String>,
+    size_estimate: Option<usize>,
+    payload: Option<Payload>,
+    raw: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    #[serde(default)]
+    headers: Vec<Header>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    name: String,
+    value: String,
+}
+
+/// Get metadata of all mails with the configured label, works like `imap::get_mails`.
+/// The UIDs are derived from the Gmail message IDs.
+pub async fn get_mails(
+    config: &Configuration,
+    known_uids: &HashSet<u32>,
+    batches: Sender<Vec<Mail>>,
+    pending: &AtomicUsize,
+) -> Result<HashMap<u32, Mail>> {
+    let session = Session::login(config).await?;
+    let ids = session.message_ids().await?;
+    debug!("Number of mails with Gmail label: {}", ids.len());
+
+    let max_size = config.max_mail_size as usize;
+    let mut mails = HashMap::new();
+    let mut oversized = 0;
+    let mut new_mails = Vec::new();
+    for id in &ids {
+        let mail = mail_metadata(&session.metadata(id).await?, max_size);
+        if mail.oversized {
+            mails.insert(mail.uid, mail);
+            oversized += 1;
+        } else if known_uids.contains(&mail.uid) {
+            mails.insert(mail.uid, mail);
+        } else {
+            new_mails.push((id, mail));
+        }
+    }
+    if oversized > 0 {
+        warn!("Found {oversized} mails over size limit of {max_size} bytes");
+    }
+    info!("Downloaded metadata of {} mails", ids.len());
+
+    // Start with the oldest mails, or the newest ones during a backfill
+    new_mails.sort_by_key(|(_, mail)| mail.date);
+    select_new_mails(config, &mut new_mails, pending);
+
+    let mut downloaded = 0;
+    let mut new_mails = new_mails.into_iter().peekable();
+    while new_mails.peek().is_some() {
+        let mut batch = Vec::new();
+        for (id, mut mail) in new_mails.by_ref().take(config.imap_batch_size as usize) {
+            let body = session.raw(id).await?;
+            mail.size = body.len();
+            mail.body = Some(body);
+            batch.push(mail);
+            downloaded += 1;
+        }
+        debug!("Downloaded batch of {} mails", batch.len());
+        if batches.send(batch).await.is_err() {
+            info!("Processing stopped, the remaining mails follow in the next cycle");
+            break;
+        }
+    }
+    if downloaded > 0 {
+        info!("Downloaded {downloaded} mails");
+    }
+    Ok(mails)
+}
+
+/// Download the complete mail with the UID.
+/// Returns nothing if the mail does not exist or has no longer the label.
+pub async fn get_mail_body(config: &Configuration, uid: u32) -> Result<Option<Vec<u8>>> {
+    let session = Session::login(config).await?;
+    let ids = session.message_ids().await?;
+    let Some(id) = ids.iter().find(|id| hashed_uid(id) == uid) else {
+        return Ok(None);
+    };
+    Ok(Some(session.raw(id).await?))
+}
+
+/// Get a token and list the mails with the label to validate the configuration.
+/// Returns the number of mails with the label.
+pub async fn check_inbox(config: &Configuration) -> Result<u32> {
+    let session = Session::login(config).await?;
+    Ok(session.message_ids().await?.len() as u32)
+}
+
+fn mail_metadata(message: &Message, max_size: usize) -> Mail {
+    let header = |name: &str| {
+        message
+            .payload
+            .iter()
+            .flat_map(|p| &p.headers)
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value.clone())
+    };
+    let size = message.size_estimate.unwrap_or_default();
+    Mail {
+        uid: hashed_uid(&message.id),
+        size,
+        oversized: size > max_size,
+        date: message
+            .internal_date
+            .as_deref()
+            .and_then(|d| d.parse::<i64>().ok())
+            .map(|ms| ms / 1000)
+            .unwrap_or_default(),
+        subject: header("Subject").unwrap_or_default(),
+        sender: header("From").unwrap_or_default(),
+        to: header("To").unwrap_or_default(),
+        attachments: 0,
+        message_id: header("Message-ID"),
+        body: None,
+        duplicate_of: None,
+    }
+}
+
+/// Signed JWT to request an access token for the service account.
+/// The subject is the user whose mailbox is accessed with domain-wide delegation.
+fn service_account_assertion(account: &ServiceAccount, subject: &str, now: u64) -> Result<String> {
+    let mut claims = json!({
+        "iss": account.client_email,
+        "scope": SCOPE,
+        "aud": account.token_uri.as_deref().unwrap_or(TOKEN_URL),
+        "iat": now,
+        "exp": now + 3600,
+    });
+    if subject != "me" {
+        claims["sub"] = json!(subject);
+    }
+    let header = URL_SAFE_NO_PAD.encode(json!({"alg": "RS256", "typ": "JWT"}).to_string());
+    let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+    let message = format!("{header}.{claims}");
+
+    let key = match rustls_pemfile::read_one(&mut BufReader::new(account.private_key.as_bytes())) {
+        Ok(Some(Item::Pkcs8Key(key))) => key,
+        _ => bail!("Service account has no PKCS#8 private key"),
+    };
+    let key_pair = RsaKeyPair::from_pkcs8(key.secret_pkcs8_der())
+        .map_err(|e| anyhow::anyhow!("Invalid service account key: {e}"))?;
+    let mut signature = vec![0; key_pair.public().modulus_len()];
+    key_pair
+        .sign(
+            &RSA_PKCS1_SHA256,
+            &SystemRandom::new(),
+            message.as_bytes(),
+            &mut signature,
+        )
+        .map_err(|_| anyhow::anyhow!("Failed to sign service account token"))?;
+    Ok(format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature)))
+}
+
+/// Authenticated client for the mailbox of the Gmail user
+struct Session {
+    client: Client,
+    token: String,
+    user_url: Url,
+    label_id: String,
+}
+
+impl Session {
+    async fn login(config: &Configuration) -> Result<Self> {
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(config.imap_timeout))
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        let request = if let Some(path) = &config.gmail_service_account_file {
+            let file = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read service account file {path}"))?;
+            let account: ServiceAccount =
+                serde_json::from_str(&file).context("Failed to parse service account file")?;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .context("Failed to get Unix time stamp")?
+                .as_secs();
+            let assertion = service_account_assertion(&account, &config.gmail_user, now)?;
+            client
+                .post(account.token_uri.as_deref().unwrap_or(TOKEN_URL))
+                .form(&[
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                    ("assertion", &assertion),
+                ])
+        } else if let Some(refresh_token) = &config.gmail_refresh_token {
+            client.post(TOKEN_URL).form(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+                (
+                    "client_id",
+                    config.gmail_client_id.as_deref().unwrap_or_default(),
+                ),
+                (
+                    "client_secret",
+                    config.gmail_client_secret.as_deref().unwrap_or_default(),
+                ),
+            ])
+        } else {
+            bail!("Gmail requires a service account file or an OAuth refresh token");
+        };
+        let token: Token = request
+            .send()
+            .await
+            .context("Failed to request Gmail access token")?
+            .error_for_status()
+            .context("Failed to get Gmail access token")?
+            .json()
+            .await
+            .context("Failed to parse Gmail access token")?;
+        debug!("Got Gmail access token");
+
+        let mut user_url = Url::parse(GMAIL_URL).context("Invalid Gmail URL")?;
+        user_url
+            .path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Gmail URL"))?
+            .push(&config.gmail_user);
+        let mut session = Self {
+            client,
+            token: token.access_token,
+            user_url,
+            label_id: String::new(),
+        };
+        session.label_id = session.label_id(&config.gmail_label).await?;
+        Ok(session)
+    }
+
+    fn get(&self, path: &[&str]) -> Result<RequestBuilder> {
+        let mut url = self.user_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Gmail URL"))?
+            .extend(path);
+        Ok(self.client.get(url).bearer_auth(&self.token))
+    }
+
+    async fn send<T: for<'de> Deserialize<'de>>(request: RequestBuilder) -> Result<T> {
+        request
+            .send()
+            .await
+            .context("Failed to send Gmail API request")?
+            .error_for_status()
+            .context("Gmail API request failed")?
+            .json()
+            .await
+            .context("Failed to parse Gmail API response")
+    }
+
+    /// Labels are configured by name, but the API filters by their IDs
+    async fn label_id(&self, label: &str) -> Result<String> {
+        let list: LabelList = Self::send(self.get(&["labels"])?).await?;
+        list.labels
+            .into_iter()
+            .find(|l| l.id == label || l.name.eq_ignore_ascii_case(label))
+            .map(|l| l.id)
+            .with_context(|| format!("Gmail label {label} not found"))
+    }
+
+    /// IDs of all messages with the label, following the pages of the response
+    async fn message_ids(&self) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        let mut page_token = None;
+        loop {
+            let mut request = self
+                .get(&["messages"])?
+                .query(&[("labelIds", self.label_id.as_str()), ("maxResults", "500")]);
+            if let Some(page_token) = &page_token {
+                request = request.query(&[("pageToken", page_token)]);
+            }
+            let page: MessagePage = Self::send(request).await?;
+            ids.extend(page.messages.into_iter().map(|m| m.id));
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                return Ok(ids);
+            }
+        }
+    }
+
+    async fn metadata(&self, id: &str) -> Result<Message> {
+        let mut query = vec![("format", "metadata")];
+        query.extend(HEADERS.map(|h| ("metadataHeaders", h)));
+        Self::send(self.get(&["messages", id])?.query(&query)).await
+    }
+
+    /// Complete message in MIME format with all attachments
+    async fn raw(&self, id: &str) -> Result<Vec<u8>> {
+        let message: Message =
+            Self::send(self.get(&["messages", id])?.query(&[("format", "raw")])).await?;
+        let raw = message
+            .raw
+            .context("Gmail returned message without content")?;
+        URL_SAFE_NO_PAD
+            .decode(raw.trim_end_matches('='))
+            .context("Invalid encoding of Gmail message")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_metadata() {
+        let message: Message = serde_json::from_str(
+            r#"{
+                "id": "18e0a1b2c3d4e5f6",
+                "internalDate": "1709280900000",
+                "sizeEstimate": 4096,
+                "payload": {"headers": [
+                    {"name": "Subject", "value": "Report domain: example.com"},
+                    {"name": "From", "value": "noreply-dmarc-support@google.com"},
+                    {"name": "To", "value": "dmarc@example.com"},
+                    {"name": "Message-Id", "value": "<123@google.com>"}
+                ]}
+            }"#,
+        )
+        .unwrap();
+        let mail = mail_metadata(&message, 10000);
+        assert_eq!(mail.uid, hashed_uid("18e0a1b2c3d4e5f6"));
+        assert_eq!(mail.date, 1709280900);
+        assert_eq!(mail.subject, "Report domain: example.com");
+        assert_eq!(mail.sender, "noreply-dmarc-support@google.com");
+        assert_eq!(mail.message_id.as_deref(), Some("<123@google.com>"));
+        assert!(!mail.oversized);
+    }
+}
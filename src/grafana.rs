@@ -0,0 +1,180 @@
+use crate::domains::domain_stats;
+use crate::filter::RecordFilter;
+use crate::report::Report;
+use crate::reporters::reporters;
+use crate::timeseries::{time_series, Bucket, Interval, Timezone, DAY};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use utoipa::ToSchema;
+
+/// Metrics available as time series, optionally prefixed with a domain like `example.com:pass_rate`
+const METRICS: &[&str] = &[
+    "messages",
+    "passed",
+    "failed",
+    "pass_rate",
+    "disposition_none",
+    "disposition_quarantine",
+    "disposition_reject",
+];
+
+/// Tables available for table panels
+const TABLES: &[&str] = &["domains", "reporters"];
+
+/// Body of the search request of the SimpleJSON protocol
+#[derive(Deserialize, Default, ToSchema)]
+pub struct SearchRequest {
+    #[serde(default)]
+    pub target: String,
+}
+
+/// Body of the query request of the SimpleJSON protocol
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryRequest {
+    pub range: QueryRange,
+    #[serde(default)]
+    pub interval_ms: u64,
+    pub targets: Vec<QueryTarget>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct QueryRange {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct QueryTarget {
+    #[serde(default)]
+    pub target: String,
+}
+
+/// Time series in the SimpleJSON format with data points as value and Unix timestamp in milliseconds
+#[derive(Serialize)]
+pub struct Series {
+    pub target: String,
+    pub datapoints: Vec<(f64, u64)>,
+}
+
+/// Names of all metrics and tables containing the search text
+pub fn search(reports: &[Report], request: &SearchRequest) -> Vec<String> {
+    let domains: BTreeSet<String> = reports
+        .iter()
+        .map(|r| r.policy_published.domain.to_lowercase())
+        .collect();
+    let mut targets: Vec<String> = METRICS
+        .iter()
+        .chain(TABLES)
+        .map(|m| m.to_string())
+        .collect();
+    for domain in domains {
+        targets.extend(METRICS.iter().map(|m| format!("{domain}:{m}")));
+    }
+    targets.retain(|t| t.contains(&request.target));
+    targets
+}
+
+/// Results for all targets of the query, unknown targets are skipped.
+/// Returns an error message if the time range is invalid.
+pub fn query(
+    reports: &[Report],
+    request: &QueryRequest,
+    timezone: Timezone,
+) -> Result<Vec<Value>, String> {
+    let from = parse_time(&request.range.from)?;
+    let to = parse_time(&request.range.to)?;
+    let interval = if request.interval_ms >= 7 * DAY * 1000 {
+        Interval::Weekly
+    } else {
+        Interval::Daily
+    };
+    let mut results = Vec::new();
+    for target in &request.targets {
+        let (domain, metric) = match target.target.rsplit_once(':') {
+            Some((domain, metric)) => (Some(domain.to_owned()), metric),
+            None => (None, target.target.as_str()),
+        };
+        let filter = RecordFilter {
+            domain,
+            since: Some(from),
+            until: Some(to),
+            ..Default::default()
+        };
+        if TABLES.contains(&metric) {
+            results.push(table(reports, &filter, metric, to));
+        } else if METRICS.contains(&metric) {
+            let datapoints = time_series(reports, &filter, interval, timezone)
+                .iter()
+                .map(|bucket| (metric_value(bucket, metric), bucket.start * 1000))
+                .collect();
+            let series = Series {
+                target: target.target.clone(),
+                datapoints,
+            };
+            results.push(json!(series));
+        }
+    }
+    Ok(results)
+}
+
+fn metric_value(bucket: &Bucket, metric: &str) -> f64 {
+    let value = match metric {
+        "messages" => bucket.messages,
+        "passed" => bucket.passed,
+        "failed" => bucket.failed,
+        "disposition_none" => bucket.disposition_none,
+        "disposition_quarantine" => bucket.disposition_quarantine,
+        "disposition_reject" => bucket.disposition_reject,
+        "pass_rate" if bucket.messages > 0 => {
+            return bucket.passed as f64 * 100.0 / bucket.messages as f64
+        }
+        _ => 0,
+    };
+    value as f64
+}
+
+fn table(reports: &[Report], filter: &RecordFilter, name: &str, now: u64) -> Value {
+    let (columns, rows): (Vec<(&str, &str)>, Vec<Value>) = match name {
+        "domains" => (
+            vec![
+                ("Domain", "string"),
+                ("Messages", "number"),
+                ("Passed", "number"),
+                ("Failed", "number"),
+                ("Pass Rate", "number"),
+            ],
+            domain_stats(reports, filter)
+                .iter()
+                .map(|d| json!([d.domain, d.messages, d.passed, d.failed, d.pass_rate()]))
+                .collect(),
+        ),
+        _ => (
+            vec![
+                ("Reporter", "string"),
+                ("Reports", "number"),
+                ("Messages", "number"),
+                ("Failed", "number"),
+                ("Last Seen", "time"),
+            ],
+            reporters(reports, filter, now)
+                .iter()
+                .map(|r| json!([r.org, r.reports, r.messages, r.failed, r.last_seen * 1000]))
+                .collect(),
+        ),
+    };
+    let columns: Vec<Value> = columns
+        .into_iter()
+        .map(|(text, kind)| json!({ "text": text, "type": kind }))
+        .collect();
+    json!({ "type": "table", "columns": columns, "rows": rows })
+}
+
+/// Parses RFC 3339 timestamps as sent by Grafana to Unix timestamps
+fn parse_time(value: &str) -> Result<u64, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.timestamp().max(0) as u64)
+        .map_err(|err| format!("Invalid time {value}: {err}"))
+}
@@ -0,0 +1,330 @@
+use crate::config::Configuration;
+use crate::mail::{hashed_uid, Mail};
+use crate::source::select_new_mails;
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use reqwest::{Client, RequestBuilder, Url};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, info, warn};
+
+const LOGIN_URL: &str = "https://login.microsoftonline.com";
+const GRAPH_URL: &str = "https://graph.microsoft.com/v1.0";
+
+/// Extended MAPI property with the size of the message (PR_MESSAGE_SIZE)
+const SIZE_PROPERTY: &str = "Integer 0x0E08";
+
+#[derive(Deserialize)]
+struct Token {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct MessagePage {
+    value: Vec<Message>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Message {
+    id: String,
+    subject: Option<String>,
+    from: Option<Recipient>,
+    #[serde(default)]
+    to_recipients: Vec<Recipient>,
+    received_date_time: Option<String>,
+    internet_message_id: Option<String>,
+    #[serde(default)]
+    single_value_extended_properties: Vec<ExtendedProperty>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Recipient {
+    email_address: EmailAddress,
+}
+
+#[derive(Deserialize)]
+struct EmailAddress {
+    address: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExtendedProperty {
+    value: String,
+}
+
+/// Get metadata of all mails in the configured folder, works like `imap::get_mails`.
+/// Mails are identified by their immutable Graph IDs, the UIDs are derived from them.
+pub async fn get_mails(
+    config: &Configuration,
+    known_uids: &HashSet<u32>,
+    batches: Sender<Vec<Mail>>,
+    pending: &AtomicUsize,
+) -> Result<HashMap<u32, Mail>> {
+    let session = Session::login(config).await?;
+    let messages = session.messages().await?;
+    debug!("Number of mails in Graph mail folder: {}", messages.len());
+
+    let max_size = config.max_mail_size as usize;
+    let mut mails = HashMap::new();
+    let mut oversized = 0;
+    let mut new_mails = Vec::new();
+    for message in messages {
+        let mail = mail_metadata(&message, max_size);
+        if mail.oversized {
+            mails.insert(mail.uid, mail);
+            oversized += 1;
+        } else if known_uids.contains(&mail.uid) {
+            mails.insert(mail.uid, mail);
+        } else {
+            new_mails.push((message.id, mail));
+        }
+    }
+    if oversized > 0 {
+        warn!("Found {oversized} mails over size limit of {max_size} bytes");
+    }
+    info!(
+        "Downloaded metadata of {} mails",
+        mails.len() + new_mails.len()
+    );
+
+    // Start with the oldest mails, or the newest ones during a backfill
+    new_mails.sort_by_key(|(_, mail)| mail.date);
+    select_new_mails(config, &mut new_mails, pending);
+
+    let mut downloaded = 0;
+    let mut new_mails = new_mails.into_iter().peekable();
+    while new_mails.peek().is_some() {
+        let mut batch = Vec::new();
+        for (id, mut mail) in new_mails.by_ref().take(config.imap_batch_size as usize) {
+            let body = session.mime(&id).await?;
+            mail.size = body.len();
+            mail.body = Some(body);
+            batch.push(mail);
+            downloaded += 1;
+        }
+        debug!("Downloaded batch of {} mails", batch.len());
+        if batches.send(batch).await.is_err() {
+            info!("Processing stopped, the remaining mails follow in the next cycle");
+            break;
+        }
+    }
+    if downloaded > 0 {
+        info!("Downloaded {downloaded} mails");
+    }
+    Ok(mails)
+}
+
+/// Download the complete mail with the UID from the mail folder.
+/// Returns nothing if the mail does not exist anymore.
+pub async fn get_mail_body(config: &Configuration, uid: u32) -> Result<Option<Vec<u8>>> {
+    let session = Session::login(config).await?;
+    let Some(message) = session
+        .messages()
+        .await?
+        .into_iter()
+        .find(|m| hashed_uid(&m.id) == uid)
+    else {
+        return Ok(None);
+    };
+    Ok(Some(session.mime(&message.id).await?))
+}
+
+/// Get a token and list the mail folder to validate the configuration.
+/// Returns the number of mails in the folder.
+pub async fn check_inbox(config: &Configuration) -> Result<u32> {
+    let session = Session::login(config).await?;
+    Ok(session.messages().await?.len() as u32)
+}
+
+fn mail_metadata(message: &Message, max_size: usize) -> Mail {
+    let address = |r: &Recipient| r.email_address.address.clone().unwrap_or_default();
+    let size = message
+        .single_value_extended_properties
+        .first()
+        .and_then(|p| p.value.parse().ok())
+        .unwrap_or_default();
+    Mail {
+        uid: hashed_uid(&message.id),
+        size,
+        oversized: size > max_size,
+        date: message
+            .received_date_time
+            .as_deref()
+            .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+            .map(|d| d.timestamp())
+            .unwrap_or_default(),
+        subject: message.subject.clone().unwrap_or_default(),
+        sender: message.from.as_ref().map(address).unwrap_or_default(),
+        to: message
+            .to_recipients
+            .iter()
+            .map(address)
+            .collect::<Vec<String>>()
+            .join("; "),
+        attachments: 0,
+        message_id: message.internet_message_id.clone(),
+        body: None,
+        duplicate_of: None,
+    }
+}
+
+/// Authenticated client for the mailbox with an access token of the client credentials flow
+struct Session {
+    client: Client,
+    token: String,
+    folder_url: Url,
+    mailbox_url: Url,
+}
+
+impl Session {
+    async fn login(config: &Configuration) -> Result<Self> {
+        let tenant = config.graph_tenant_id.as_deref().unwrap_or_default();
+        let client_id = config.graph_client_id.as_deref().unwrap_or_default();
+        let secret = config.graph_client_secret.as_deref().unwrap_or_default();
+        let mailbox = config.graph_mailbox.as_deref().unwrap_or_default();
+
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(config.imap_timeout))
+            .build()
+            .context("Failed to create HTTP client")?;
+        let token: Token = client
+            .post(format!("{LOGIN_URL}/{tenant}/oauth2/v2.0/token"))
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id),
+                ("client_secret", secret),
+                ("scope", "https://graph.microsoft.com/.default"),
+            ])
+            .send()
+            .await
+            .context("Failed to request Graph access token")?
+            .error_for_status()
+            .context("Failed to get Graph access token")?
+            .json()
+            .await
+            .context("Failed to parse Graph access token")?;
+        debug!("Got Graph access token");
+
+        let mut mailbox_url = Url::parse(GRAPH_URL).context("Invalid Graph URL")?;
+        mailbox_url
+            .path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Graph URL"))?
+            .extend(["users", mailbox]);
+        let mut folder_url = mailbox_url.clone();
+        folder_url
+            .path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Graph URL"))?
+            .extend(["mailFolders", &config.graph_folder, "messages"]);
+        Ok(Self {
+            client,
+            token: token.access_token,
+            folder_url,
+            mailbox_url,
+        })
+    }
+
+    /// Requests with immutable IDs that do not change when mails are moved
+    fn get(&self, url: Url) -> RequestBuilder {
+        self.client
+            .get(url)
+            .bearer_auth(&self.token)
+            .header("Prefer", "IdType=\"ImmutableId\"")
+    }
+
+    /// All messages of the folder, following the pages of the response
+    async fn messages(&self) -> Result<Vec<Message>> {
+        let mut url = self.folder_url.clone();
+        url.query_pairs_mut()
+            .append_pair(
+                "$select",
+                "id,subject,from,toRecipients,receivedDateTime,internetMessageId",
+            )
+            .append_pair(
+                "$expand",
+                &format!("singleValueExtendedProperties($filter=id eq '{SIZE_PROPERTY}')"),
+            )
+            .append_pair("$top", "100");
+        let mut messages = Vec::new();
+        let mut next = Some(url);
+        while let Some(url) = next {
+            let page: MessagePage = self
+                .get(url)
+                .send()
+                .await
+                .context("Failed to list Graph messages")?
+                .error_for_status()
+                .context("Failed to list Graph messages")?
+                .json()
+                .await
+                .context("Failed to parse Graph messages")?;
+            messages.extend(page.value);
+            next = page
+                .next_link
+                .map(|link| Url::parse(&link))
+                .transpose()
+                .context("Invalid next link in Graph response")?;
+        }
+        Ok(messages)
+    }
+
+    /// Complete message in MIME format with all attachments
+    async fn mime(&self, id: &str) -> Result<Vec<u8>> {
+        let mut url = self.mailbox_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid Graph URL"))?
+            .extend(["messages", id, "$value"]);
+        let body = self
+            .get(url)
+            .send()
+            .await
+            .context("Failed to download Graph message")?
+            .error_for_status()
+            .context("Failed to download Graph message")?
+            .bytes()
+            .await
+            .context("Failed to read Graph message")?;
+        Ok(body.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_metadata() {
+        let page: MessagePage = serde_json::from_str(
+            r#"{
+                "value": [{
+                    "id": "AAkALgAAAAAAHYQDEapmEc2byACqAC-EWg0A",
+                    "subject": "Report domain: example.com",
+                    "from": {"emailAddress": {"name": "DMARC", "address": "noreply@google.com"}},
+                    "toRecipients": [
+                        {"emailAddress": {"address": "dmarc@example.com"}},
+                        {"emailAddress": {"address": "admin@example.com"}}
+                    ],
+                    "receivedDateTime": "2024-03-01T08:15:00Z",
+                    "internetMessageId": "<123@google.com>",
+                    "singleValueExtendedProperties": [{"id": "Integer 0xe08", "value": "4096"}]
+                }],
+                "@odata.nextLink": "https://graph.microsoft.com/v1.0/next"
+            }"#,
+        )
+        .unwrap();
+        assert!(page.next_link.is_some());
+        let mail = mail_metadata(&page.value[0], 1024);
+        assert_eq!(mail.uid, hashed_uid("AAkALgAAAAAAHYQDEapmEc2byACqAC-EWg0A"));
+        assert_eq!(mail.sender, "noreply@google.com");
+        assert_eq!(mail.to, "dmarc@example.com; admin@example.com");
+        assert_eq!(mail.date, 1709280900);
+        assert_eq!(mail.size, 4096);
+        assert!(mail.oversized);
+    }
+}
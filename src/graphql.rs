@@ -0,0 +1,474 @@
+use crate::dns::DnsResolver;
+use crate::domains::{domain_stats, DomainSummary};
+use crate::filter::RecordFilter;
+use crate::report::{find_report, RecordType, Report};
+use crate::state::SharedState;
+use crate::summary::Summary;
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, InputObject, Json, Object, Result, Schema,
+    SimpleObject,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Maximum nesting of queries to limit the costs of a single request
+const MAX_DEPTH: usize = 10;
+
+pub type DmarcSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// Read-only schema with reports, records, summaries and enrichment data
+pub fn schema(state: Arc<SharedState>, dns: Arc<DnsResolver>) -> DmarcSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(state)
+        .data(dns)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+/// Same filter options as the query parameters of the JSON API
+#[derive(InputObject, Default)]
+pub struct Filter {
+    /// Domain of the published policy
+    domain: Option<String>,
+    /// Name of the reporting organization
+    org: Option<String>,
+    /// Source IP of the record
+    source_ip: Option<String>,
+    /// Comma separated source IP networks in CIDR notation like 192.0.2.0/24,2001:db8::/32
+    network: Option<String>,
+    /// Only reports with a date range ending at or after this Unix timestamp
+    since: Option<u64>,
+    /// Only reports with a date range beginning at or before this Unix timestamp
+    until: Option<u64>,
+    /// Group subdomains under their organizational domain
+    #[graphql(default)]
+    rollup: bool,
+    /// Only records with failed DKIM or SPF or a disposition other than none
+    only_failures: Option<bool>,
+}
+
+impl TryFrom<Filter> for RecordFilter {
+    type Error = async_graphql::Error;
+
+    fn try_from(filter: Filter) -> Result<Self> {
+        let source_ip = match filter.source_ip {
+            Some(ip) => Some(ip.parse().map_err(|_| format!("Invalid source IP: {ip}"))?),
+            None => None,
+        };
+        let network = match filter.network {
+            Some(network) => Some(
+                network
+                    .parse()
+                    .map_err(|_| format!("Invalid network: {network}"))?,
+            ),
+            None => None,
+        };
+        Ok(RecordFilter {
+            domain: filter.domain,
+            org: filter.org,
+            source_ip,
+            network,
+            since: filter.since,
+            until: filter.until,
+            rollup: filter.rollup,
+            only_failures: filter.only_failures,
+            ..Default::default()
+        })
+    }
+}
+
+fn record_filter(filter: Option<Filter>) -> Result<Arc<RecordFilter>> {
+    Ok(Arc::new(filter.unwrap_or_default().try_into()?))
+}
+
+fn app_state<'a>(ctx: &'a Context<'_>) -> &'a Arc<SharedState> {
+    ctx.data_unchecked::<Arc<SharedState>>()
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Reports with matching records in the order they were received
+    async fn reports(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<Filter>,
+        #[graphql(default)] offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<ReportObject>> {
+        let filter = record_filter(filter)?;
+        let state = app_state(ctx).snapshot();
+        Ok(state
+            .reports
+            .iter()
+            .filter(|r| filter.matches_report_records(r))
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|r| ReportObject::new(r, &filter))
+            .collect())
+    }
+
+    /// Single report by its stable ID or the ID assigned by the reporter
+    async fn report(&self, ctx: &Context<'_>, id: String) -> Option<ReportObject> {
+        let state = app_state(ctx).snapshot();
+        find_report(&state.reports, &id).map(|r| ReportObject::new(r, &Default::default()))
+    }
+
+    /// Matching records of all reports
+    async fn records(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<Filter>,
+        #[graphql(default)] offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<RecordObject>> {
+        let filter = record_filter(filter)?;
+        let state = app_state(ctx).snapshot();
+        Ok(state
+            .reports
+            .iter()
+            .filter(|r| filter.matches_report(r))
+            .flat_map(|r| ReportObject::new(r, &filter).record_objects())
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    /// Aggregated statistics like the summary of the JSON API
+    async fn summary(&self, ctx: &Context<'_>, filter: Option<Filter>) -> Result<Json<Summary>> {
+        let filter = record_filter(filter)?;
+        let state = app_state(ctx).snapshot();
+        if filter.is_unfiltered() {
+            return Ok(Json(state.summary.clone()));
+        }
+        Ok(Json(state.summary.filtered(&state.reports, &filter)))
+    }
+
+    /// Message counts per policy domain
+    async fn domains(&self, ctx: &Context<'_>, filter: Option<Filter>) -> Result<Vec<Domain>> {
+        let filter = record_filter(filter)?;
+        let state = app_state(ctx).snapshot();
+        Ok(domain_stats(&state.reports, &filter)
+            .into_iter()
+            .map(|stats| Domain(DomainSummary::from(stats)))
+            .collect())
+    }
+}
+
+/// Report with the records matching the filter of the query
+#[derive(Clone)]
+pub struct ReportObject {
+    report: Arc<Report>,
+}
+
+impl ReportObject {
+    fn new(report: &Report, filter: &RecordFilter) -> Self {
+        let mut report = report.clone();
+        report.record.retain(|r| filter.matches_record(r));
+        Self {
+            report: Arc::new(report),
+        }
+    }
+
+    fn record_objects(&self) -> Vec<RecordObject> {
+        self.report
+            .record
+            .iter()
+            .map(|record| RecordObject {
+                report: self.clone(),
+                record: record.clone(),
+            })
+            .collect()
+    }
+}
+
+#[Object(name = "Report")]
+impl ReportObject {
+    async fn id(&self) -> &str {
+        &self.report.report_metadata.report_id
+    }
+
+    async fn stable_id(&self) -> String {
+        self.report.stable_id()
+    }
+
+    async fn org(&self) -> &str {
+        &self.report.report_metadata.org_name
+    }
+
+    async fn email(&self) -> &str {
+        &self.report.report_metadata.email
+    }
+
+    async fn domain(&self) -> &str {
+        &self.report.policy_published.domain
+    }
+
+    /// Begin of the date range as Unix timestamp
+    async fn date_begin(&self) -> u64 {
+        self.report.report_metadata.date_range.begin
+    }
+
+    /// End of the date range as Unix timestamp
+    async fn date_end(&self) -> u64 {
+        self.report.report_metadata.date_range.end
+    }
+
+    async fn errors(&self) -> Vec<String> {
+        self.report
+            .report_metadata
+            .error
+            .clone()
+            .unwrap_or_default()
+    }
+
+    async fn policy(&self) -> Policy<'_> {
+        Policy(&self.report)
+    }
+
+    async fn failed_messages(&self) -> usize {
+        self.report.failed_messages()
+    }
+
+    async fn records(&self) -> Vec<RecordObject> {
+        self.record_objects()
+    }
+}
+
+/// Policy published by the domain owner
+pub struct Policy<'a>(&'a Report);
+
+#[Object]
+impl Policy<'_> {
+    async fn domain(&self) -> &str {
+        &self.0.policy_published.domain
+    }
+
+    async fn adkim(&self) -> Option<String> {
+        self.0.policy_published.adkim.clone().map(String::from)
+    }
+
+    async fn aspf(&self) -> Option<String> {
+        self.0.policy_published.aspf.clone().map(String::from)
+    }
+
+    async fn p(&self) -> String {
+        self.0.policy_published.p.clone().into()
+    }
+
+    async fn sp(&self) -> Option<String> {
+        self.0.policy_published.sp.clone().map(String::from)
+    }
+
+    async fn pct(&self) -> Option<u8> {
+        self.0.policy_published.pct
+    }
+
+    async fn np(&self) -> Option<String> {
+        self.0.policy_published.np.clone().map(String::from)
+    }
+
+    async fn testing(&self) -> Option<String> {
+        self.0.policy_published.testing.clone().map(String::from)
+    }
+
+    async fn discovery_method(&self) -> Option<String> {
+        self.0
+            .policy_published
+            .discovery_method
+            .clone()
+            .map(String::from)
+    }
+}
+
+pub struct RecordObject {
+    report: ReportObject,
+    record: RecordType,
+}
+
+#[Object(name = "Record")]
+impl RecordObject {
+    async fn report(&self) -> &ReportObject {
+        &self.report
+    }
+
+    async fn source_ip(&self) -> String {
+        self.record.row.source_ip.to_string()
+    }
+
+    async fn count(&self) -> usize {
+        self.record.row.count
+    }
+
+    async fn disposition(&self) -> String {
+        self.record.row.policy_evaluated.disposition.clone().into()
+    }
+
+    /// Result of the DKIM policy evaluation
+    async fn dkim(&self) -> Option<String> {
+        self.record
+            .row
+            .policy_evaluated
+            .dkim
+            .clone()
+            .map(String::from)
+    }
+
+    /// Result of the SPF policy evaluation
+    async fn spf(&self) -> Option<String> {
+        self.record
+            .row
+            .policy_evaluated
+            .spf
+            .clone()
+            .map(String::from)
+    }
+
+    async fn dmarc_pass(&self) -> bool {
+        self.record.is_dmarc_pass()
+    }
+
+    /// Policy override reasons as type and optional comment
+    async fn reasons(&self) -> Vec<Reason<'_>> {
+        self.record
+            .row
+            .policy_evaluated
+            .reason
+            .iter()
+            .flatten()
+            .map(|r| Reason {
+                kind: String::from(r.kind.clone()),
+                comment: r.comment.as_deref(),
+            })
+            .collect()
+    }
+
+    async fn header_from(&self) -> &str {
+        &self.record.identifiers.header_from
+    }
+
+    async fn envelope_from(&self) -> Option<&str> {
+        self.record.identifiers.envelope_from.as_deref()
+    }
+
+    async fn envelope_to(&self) -> Option<&str> {
+        self.record.identifiers.envelope_to.as_deref()
+    }
+
+    async fn dkim_results(&self) -> Vec<AuthResult<'_>> {
+        self.record
+            .auth_results
+            .dkim
+            .iter()
+            .flatten()
+            .map(|r| AuthResult {
+                domain: &r.domain,
+                selector: r.selector.as_deref(),
+                scope: None,
+                result: String::from(r.result.clone()),
+            })
+            .collect()
+    }
+
+    async fn spf_results(&self) -> Vec<AuthResult<'_>> {
+        self.record
+            .auth_results
+            .spf
+            .iter()
+            .map(|r| AuthResult {
+                domain: &r.domain,
+                selector: None,
+                scope: r.scope.clone().map(String::from),
+                result: String::from(r.result.clone()),
+            })
+            .collect()
+    }
+
+    /// Label of the source IP from the annotations
+    async fn label(&self, ctx: &Context<'_>) -> Option<String> {
+        let state = app_state(ctx).snapshot();
+        state
+            .annotations
+            .ip_label(&self.record.row.source_ip)
+            .map(String::from)
+    }
+
+    /// Owner of the header from domain from the annotations
+    async fn owner(&self, ctx: &Context<'_>) -> Option<String> {
+        let state = app_state(ctx).snapshot();
+        state
+            .annotations
+            .domain_owner(&self.record.identifiers.header_from)
+            .map(String::from)
+    }
+
+    /// Host names of the source IP from a reverse DNS lookup
+    async fn hostnames(&self, ctx: &Context<'_>) -> Result<Vec<String>> {
+        let dns = ctx.data_unchecked::<Arc<DnsResolver>>();
+        Ok(dns.ptr(self.record.row.source_ip).await?)
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Reason<'a> {
+    #[graphql(name = "type")]
+    kind: String,
+    comment: Option<&'a str>,
+}
+
+/// DKIM or SPF authentication result, the selector is only set for DKIM
+/// and the scope only for SPF
+#[derive(SimpleObject)]
+pub struct AuthResult<'a> {
+    domain: &'a str,
+    selector: Option<&'a str>,
+    scope: Option<String>,
+    result: String,
+}
+
+pub struct Domain(DomainSummary);
+
+#[Object]
+impl Domain {
+    async fn domain(&self) -> &str {
+        &self.0.stats.domain
+    }
+
+    async fn records(&self) -> usize {
+        self.0.stats.records
+    }
+
+    async fn messages(&self) -> usize {
+        self.0.stats.messages
+    }
+
+    async fn passed(&self) -> usize {
+        self.0.stats.passed
+    }
+
+    async fn failed(&self) -> usize {
+        self.0.stats.failed
+    }
+
+    async fn dkim_failed(&self) -> usize {
+        self.0.stats.dkim_failed
+    }
+
+    async fn spf_failed(&self) -> usize {
+        self.0.stats.spf_failed
+    }
+
+    async fn pass_rate(&self) -> f64 {
+        self.0.pass_rate
+    }
+
+    async fn distinct_source_ips(&self) -> usize {
+        self.0.distinct_source_ips
+    }
+
+    /// Message counts per reporting organization
+    async fn orgs(&self) -> Json<HashMap<String, usize>> {
+        Json(self.0.stats.orgs.clone())
+    }
+}
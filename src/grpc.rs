@@ -0,0 +1,332 @@
+// Status is the error type of all tonic APIs and cannot be made smaller
+#![allow(clippy::result_large_err)]
+
+use crate::config::Configuration;
+use crate::filter::RecordFilter;
+use crate::ratelimit::RateLimiter;
+use crate::report::{RecordType, Report};
+use crate::state::{AppState, SharedState};
+use crate::users::Users;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::Stream;
+use proto::dmarc_reports_server::{DmarcReports, DmarcReportsServer};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task::block_in_place;
+use tonic::metadata::MetadataMap;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+/// Generated from `proto/dmarc.proto`
+pub mod proto {
+    tonic::include_proto!("dmarc.v1");
+}
+
+/// Read-only gRPC service with the same data as the JSON API
+struct DmarcService {
+    state: Arc<SharedState>,
+}
+
+impl DmarcService {
+    fn snapshot(&self) -> Arc<AppState> {
+        self.state.snapshot()
+    }
+}
+
+#[tonic::async_trait]
+impl DmarcReports for DmarcService {
+    async fn list_reports(
+        &self,
+        request: Request<proto::ListReportsRequest>,
+    ) -> Result<Response<proto::ListReportsResponse>, Status> {
+        let request = request.into_inner();
+        let filter = record_filter(request.filter.unwrap_or_default())?;
+        let limit = match request.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+        let reports = self
+            .snapshot()
+            .reports
+            .iter()
+            .filter(|r| filter.matches_report_records(r))
+            .skip(request.offset as usize)
+            .take(limit)
+            .map(|r| report_message(r, &filter))
+            .collect();
+        Ok(Response::new(proto::ListReportsResponse { reports }))
+    }
+
+    type StreamRecordsStream = Pin<Box<dyn Stream<Item = Result<proto::Record, Status>> + Send>>;
+
+    async fn stream_records(
+        &self,
+        request: Request<proto::RecordFilter>,
+    ) -> Result<Response<Self::StreamRecordsStream>, Status> {
+        let filter = record_filter(request.into_inner())?;
+        // Convert all records first to not hold the lock while the client reads the stream
+        let records: Vec<Result<proto::Record, Status>> = filter
+            .records(&self.snapshot().reports)
+            .map(|(report, record)| Ok(record_message(&report.stable_id(), record)))
+            .collect();
+        Ok(Response::new(Box::pin(futures::stream::iter(records))))
+    }
+
+    async fn get_summary(
+        &self,
+        request: Request<proto::RecordFilter>,
+    ) -> Result<Response<proto::Summary>, Status> {
+        let filter = record_filter(request.into_inner())?;
+        let state = self.snapshot();
+        let summary = if filter.is_unfiltered() {
+            state.summary.clone()
+        } else {
+            state.summary.filtered(&state.reports, &filter)
+        };
+        let counts = |map: &HashMap<String, usize>| {
+            map.iter().map(|(k, v)| (k.clone(), *v as u64)).collect()
+        };
+        Ok(Response::new(proto::Summary {
+            mails: summary.mails as u64,
+            xml_files: summary.xml_files as u64,
+            reports: summary.reports as u64,
+            duplicates: summary.duplicates as u64,
+            last_update: summary.last_update,
+            since: summary.since,
+            until: summary.until,
+            orgs: counts(&summary.orgs),
+            domains: counts(&summary.domains),
+        }))
+    }
+}
+
+fn record_filter(filter: proto::RecordFilter) -> Result<RecordFilter, Status> {
+    let source_ip = match filter.source_ip {
+        Some(ip) => Some(
+            ip.parse()
+                .map_err(|_| Status::invalid_argument(format!("Invalid source IP: {ip}")))?,
+        ),
+        None => None,
+    };
+    let network = match filter.network {
+        Some(network) => Some(
+            network
+                .parse()
+                .map_err(|_| Status::invalid_argument(format!("Invalid network: {network}")))?,
+        ),
+        None => None,
+    };
+    Ok(RecordFilter {
+        domain: filter.domain,
+        org: filter.org,
+        source_ip,
+        network,
+        since: filter.since,
+        until: filter.until,
+        rollup: filter.rollup,
+        only_failures: filter.only_failures,
+        ..Default::default()
+    })
+}
+
+fn report_message(report: &Report, filter: &RecordFilter) -> proto::Report {
+    let metadata = &report.report_metadata;
+    let policy = &report.policy_published;
+    let stable_id = report.stable_id();
+    proto::Report {
+        id: metadata.report_id.clone(),
+        org: metadata.org_name.clone(),
+        email: metadata.email.clone(),
+        domain: policy.domain.clone(),
+        date_begin: metadata.date_range.begin,
+        date_end: metadata.date_range.end,
+        policy: Some(proto::Policy {
+            domain: policy.domain.clone(),
+            adkim: policy.adkim.clone().map(String::from),
+            aspf: policy.aspf.clone().map(String::from),
+            p: policy.p.clone().into(),
+            sp: policy.sp.clone().map(String::from),
+            pct: policy.pct.map(u32::from),
+            np: policy.np.clone().map(String::from),
+            testing: policy.testing.clone().map(String::from),
+            discovery_method: policy.discovery_method.clone().map(String::from),
+        }),
+        records: report
+            .record
+            .iter()
+            .filter(|r| filter.matches_record(r))
+            .map(|r| record_message(&stable_id, r))
+            .collect(),
+        stable_id,
+    }
+}
+
+fn record_message(report_id: &str, record: &RecordType) -> proto::Record {
+    let evaluated = &record.row.policy_evaluated;
+    proto::Record {
+        report_id: report_id.to_owned(),
+        source_ip: record.row.source_ip.to_string(),
+        count: record.row.count as u64,
+        disposition: evaluated.disposition.clone().into(),
+        dkim: evaluated.dkim.clone().map(String::from),
+        spf: evaluated.spf.clone().map(String::from),
+        header_from: record.identifiers.header_from.clone(),
+        envelope_from: record.identifiers.envelope_from.clone(),
+        envelope_to: record.identifiers.envelope_to.clone(),
+        reasons: evaluated
+            .reason
+            .iter()
+            .flatten()
+            .map(|r| proto::Reason {
+                r#type: r.kind.clone().into(),
+                comment: r.comment.clone(),
+            })
+            .collect(),
+        dkim_results: record
+            .auth_results
+            .dkim
+            .iter()
+            .flatten()
+            .map(|r| proto::DkimResult {
+                domain: r.domain.clone(),
+                selector: r.selector.clone(),
+                result: r.result.clone().into(),
+            })
+            .collect(),
+        spf_results: record
+            .auth_results
+            .spf
+            .iter()
+            .map(|r| proto::SpfResult {
+                domain: r.domain.clone(),
+                scope: r.scope.clone().map(String::from),
+                result: r.result.clone().into(),
+            })
+            .collect(),
+    }
+}
+
+/// Accepts API tokens and the credentials of all users, like the read-only HTTP endpoints.
+/// Tenant users are rejected since the filters of the service are not scoped to tenants.
+/// Failed logins count towards the same lockout as the ones of the HTTP server.
+fn authorized(
+    users: &Users,
+    limiter: &RateLimiter,
+    client: Option<IpAddr>,
+    metadata: &MetadataMap,
+) -> Result<(), Status> {
+    let invalid = || Status::unauthenticated("Invalid credentials");
+    if users.auth_disabled() {
+        return Ok(());
+    }
+    let Some(header) = metadata.get("authorization").and_then(|v| v.to_str().ok()) else {
+        return Err(invalid());
+    };
+    if let Some(token) = header.strip_prefix("Bearer ") {
+        return users.valid_token(token).then_some(()).ok_or_else(invalid);
+    }
+    let credentials = header
+        .strip_prefix("Basic ")
+        .and_then(|b| STANDARD.decode(b).ok())
+        .and_then(|d| String::from_utf8(d).ok())
+        .ok_or_else(invalid)?;
+    let (user, password) = credentials.split_once(':').ok_or_else(invalid)?;
+    if let Some(remaining) = client.and_then(|ip| limiter.locked(ip)) {
+        return Err(Status::resource_exhausted(format!(
+            "Too many failed logins, retry in {} seconds",
+            remaining.as_secs() + 1
+        )));
+    }
+    // The interceptor is synchronous, the runtime moves other tasks away while hashing
+    if block_in_place(|| users.authenticate_blocking(user, password)).is_none() {
+        if let Some(ip) = client {
+            warn!("Failed gRPC login of user {user} from {ip}");
+            limiter.login_failed(ip);
+        }
+        return Err(invalid());
+    }
+    if let Some(ip) = client {
+        limiter.login_succeeded(ip);
+    }
+    users
+        .user_domains(user)
+        .is_none()
+        .then_some(())
+        .ok_or_else(invalid)
+}
+
+/// Runs the gRPC server until the shutdown signal is received
+pub async fn run_grpc_server(
+    config: &Configuration,
+    port: u16,
+    state: Arc<SharedState>,
+    users: Arc<Users>,
+    limiter: Arc<RateLimiter>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let binding = format!("{}:{port}", config.http_server_binding);
+    let addr: SocketAddr = binding.parse().context("Failed to parse binding address")?;
+    let service =
+        DmarcReportsServer::with_interceptor(DmarcService { state }, move |request: Request<()>| {
+            let client = request.remote_addr().map(|addr| addr.ip().to_canonical());
+            match authorized(&users, &limiter, client, request.metadata()) {
+                Ok(()) => Ok(request),
+                Err(status) => {
+                    warn!("Rejected unauthorized gRPC request");
+                    Err(status)
+                }
+            }
+        });
+    info!("Binding gRPC server to {addr}...");
+    Server::builder()
+        .add_service(service)
+        .serve_with_shutdown(addr, async move {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        })
+        .await
+        .context("Failed to run gRPC server")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn metadata(user: &str, password: &str) -> MetadataMap {
+        let credentials = STANDARD.encode(format!("{user}:{password}"));
+        let mut metadata = MetadataMap::new();
+        metadata.insert(
+            "authorization",
+            format!("Basic {credentials}").parse().unwrap(),
+        );
+        metadata
+    }
+
+    #[test]
+    fn lock_out_after_failed_logins() {
+        let config = Configuration::parse_from([
+            "test",
+            "--demo",
+            "--http-server-password=password",
+            "--http-login-max-failures=2",
+        ]);
+        let users = Users::from_config(&config).unwrap();
+        let limiter = RateLimiter::new(&config);
+        let client = Some("192.0.2.1".parse().unwrap());
+        let valid = metadata("dmarc", "password");
+        assert!(authorized(&users, &limiter, client, &valid).is_ok());
+        let invalid = metadata("dmarc", "wrong");
+        for _ in 0..2 {
+            let status = authorized(&users, &limiter, client, &invalid).unwrap_err();
+            assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        }
+        let status = authorized(&users, &limiter, client, &valid).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(limiter.locked("192.0.2.1".parse().unwrap()).is_some());
+    }
+}
@@ -0,0 +1,452 @@
+use crate::dns::record_tag;
+use crate::explain::{check, explain_checks, record_id, AlignmentMode, IdentifierCheck};
+use crate::filter::TenantDomains;
+use crate::psl::organizational_domain;
+use crate::report::{DispositionType, PolicyPublishedType, Report};
+use anyhow::{ensure, Context, Result};
+use mailparse::parse_headers;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// DKIM signature added by the sender, verified or not
+#[derive(Serialize, Debug, PartialEq, ToSchema)]
+pub struct DkimSignature {
+    pub domain: String,
+    pub selector: String,
+}
+
+/// Record of the aggregate reports with the source IP and header from domain of the message
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MatchingRecord {
+    /// Record ID as used by the explain endpoint
+    pub id: String,
+    pub org_name: String,
+
+    /// Date range of the report as Unix timestamps
+    pub begin: u64,
+    pub end: u64,
+
+    pub count: usize,
+    pub dmarc_pass: bool,
+    pub disposition: DispositionType,
+}
+
+/// DMARC evaluation of a single message from the authentication headers added by the receiver
+#[derive(Serialize, Debug, ToSchema)]
+pub struct HeaderAnalysis {
+    pub header_from: Option<String>,
+    pub organizational_domain: Option<String>,
+
+    /// Sending IP from the SPF results
+    #[schema(value_type = Option<String>)]
+    pub source_ip: Option<IpAddr>,
+
+    pub signatures: Vec<DkimSignature>,
+    pub dkim: Vec<IdentifierCheck>,
+    pub spf: Vec<IdentifierCheck>,
+
+    /// DMARC result of the receiver from the Authentication-Results header
+    pub reported_dmarc: Option<String>,
+
+    /// DMARC result according to the DKIM and SPF results and their alignment
+    pub dmarc_pass: bool,
+
+    /// Domain of the policy in the latest aggregate report, which provides the alignment modes
+    pub policy_domain: Option<String>,
+
+    /// Policy requested for failing messages, the subdomain policy for subdomains if published
+    pub requested: Option<DispositionType>,
+
+    /// Records of the aggregate reports that probably contain the message, newest first
+    pub records: Vec<MatchingRecord>,
+
+    /// Human readable breakdown, one sentence per step
+    pub steps: Vec<String>,
+}
+
+/// Result of one method in an Authentication-Results or Received-SPF header,
+/// like `dkim=pass header.d=example.com`
+#[derive(Debug)]
+struct MethodResult {
+    method: String,
+    result: String,
+    properties: HashMap<String, String>,
+}
+
+impl MethodResult {
+    fn property(&self, names: &[&str]) -> Option<&str> {
+        names
+            .iter()
+            .find_map(|name| self.properties.get(*name))
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+}
+
+/// Parses the header block of a mail, everything after the first empty line is ignored
+pub fn analyze_header_block(
+    data: &[u8],
+    reports: &[Report],
+    tenant: Option<&TenantDomains>,
+) -> Result<HeaderAnalysis> {
+    let (headers, _) = parse_headers(data).context("Failed to parse headers")?;
+    let headers: Vec<(String, String)> = headers
+        .iter()
+        .map(|h| (h.get_key(), h.get_value()))
+        .collect();
+    ensure!(!headers.is_empty(), "No headers found");
+    Ok(analyze_headers(&headers, reports, tenant))
+}
+
+/// Evaluates DMARC for the message like the receiver did, based on the results of the receiver
+/// and the policy and records of the aggregate reports of the tenant
+pub fn analyze_headers(
+    headers: &[(String, String)],
+    reports: &[Report],
+    tenant: Option<&TenantDomains>,
+) -> HeaderAnalysis {
+    let values = |name: &'static str| {
+        headers
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+    let results: Vec<MethodResult> = values("Authentication-Results")
+        .flat_map(authentication_results)
+        .chain(values("Received-SPF").filter_map(received_spf))
+        .collect();
+    let signatures: Vec<DkimSignature> = values("DKIM-Signature")
+        .filter_map(|value| {
+            Some(DkimSignature {
+                domain: record_tag(value, "d")?.to_lowercase(),
+                selector: record_tag(value, "s").unwrap_or_default(),
+            })
+        })
+        .collect();
+
+    let reported_dmarc = results.iter().find(|r| r.method == "dmarc");
+    let header_from = values("From").next().and_then(address_domain).or_else(|| {
+        reported_dmarc?
+            .property(&["header.from"])
+            .map(str::to_lowercase)
+    });
+    let source_ip = results
+        .iter()
+        .find_map(|r| {
+            r.property(&["client-ip", "smtp.remote-ip", "smtp.client-ip"])?
+                .parse()
+                .ok()
+        })
+        .or_else(|| headers.iter().find_map(|(_, value)| designated_ip(value)))
+        .map(|ip: IpAddr| ip.to_canonical());
+    let allowed = |domain: &str| tenant.is_none_or(|t| t.allows(domain));
+
+    let Some(header_from) = header_from.filter(|d| allowed(d)) else {
+        return HeaderAnalysis {
+            header_from: None,
+            organizational_domain: None,
+            source_ip,
+            signatures,
+            dkim: Vec::new(),
+            spf: Vec::new(),
+            reported_dmarc: reported_dmarc.map(|r| r.result.clone()),
+            dmarc_pass: false,
+            policy_domain: None,
+            requested: None,
+            records: Vec::new(),
+            steps: vec![String::from(
+                "The headers contain no From address of your domains, so DMARC cannot be evaluated.",
+            )],
+        };
+    };
+    let org_domain = organizational_domain(&header_from);
+
+    // Receivers look up the policy of the header from domain and fall back to the organizational domain
+    let policy = [&header_from, &org_domain].into_iter().find_map(|domain| {
+        reports
+            .iter()
+            .filter(|r| r.policy_published.domain.eq_ignore_ascii_case(domain))
+            .max_by_key(|r| r.report_metadata.date_range.end)
+            .map(|r| &r.policy_published)
+    });
+    let mut steps = vec![match policy {
+        Some(policy) => format!(
+            "The header from domain {header_from} belongs to the organizational domain {org_domain} \
+             and the policy published for {} in the latest aggregate report applies.",
+            policy.domain
+        ),
+        None => format!(
+            "The header from domain {header_from} belongs to the organizational domain {org_domain}, \
+             but no aggregate report contains its policy, so relaxed alignment is assumed."
+        ),
+    }];
+
+    let dkim_mode = AlignmentMode::new(policy.and_then(|p| p.adkim.as_ref()));
+    let mut dkim: Vec<IdentifierCheck> = results
+        .iter()
+        .filter(|r| r.method == "dkim")
+        .filter_map(|r| {
+            let domain = r
+                .property(&["header.d"])
+                .map(str::to_owned)
+                .or_else(|| address_domain(r.property(&["header.i"])?))?;
+            Some(check(
+                &domain,
+                r.result.clone(),
+                r.result == "pass",
+                dkim_mode,
+                &header_from,
+            ))
+        })
+        .collect();
+    // Signatures the receiver did not report a result for were not verified
+    for signature in &signatures {
+        if !dkim.iter().any(|c| c.domain == signature.domain) {
+            let result = String::from("unverified");
+            dkim.push(check(
+                &signature.domain,
+                result,
+                false,
+                dkim_mode,
+                &header_from,
+            ));
+        }
+    }
+    explain_checks("DKIM signature", &dkim, &header_from, &mut steps);
+
+    let spf_mode = AlignmentMode::new(policy.and_then(|p| p.aspf.as_ref()));
+    let mut spf: Vec<IdentifierCheck> = Vec::new();
+    for result in results.iter().filter(|r| r.method == "spf") {
+        let domain = result
+            .property(&["smtp.mailfrom", "envelope-from"])
+            .or_else(|| result.property(&["smtp.helo", "helo"]))
+            .map(|value| address_domain(value).unwrap_or_else(|| value.to_lowercase()));
+        let Some(domain) = domain else {
+            continue;
+        };
+        let pass = result.result == "pass";
+        let check = check(&domain, result.result.clone(), pass, spf_mode, &header_from);
+        if !spf
+            .iter()
+            .any(|c| c.domain == check.domain && c.result == check.result)
+        {
+            spf.push(check);
+        }
+    }
+    explain_checks("SPF check", &spf, &header_from, &mut steps);
+
+    let dkim_pass = dkim.iter().any(|c| c.passed);
+    let spf_pass = spf.iter().any(|c| c.passed);
+    let dmarc_pass = dkim_pass || spf_pass;
+    steps.push(match (dkim_pass, spf_pass) {
+        (true, true) => {
+            String::from("DMARC passed because DKIM and SPF passed with aligned domains.")
+        }
+        (true, false) => String::from("DMARC passed because DKIM passed with an aligned domain."),
+        (false, true) => String::from("DMARC passed because SPF passed with an aligned domain."),
+        (false, false) => {
+            String::from("DMARC failed because neither DKIM nor SPF passed with an aligned domain.")
+        }
+    });
+    if let Some(reported) = reported_dmarc {
+        if (reported.result == "pass") != dmarc_pass {
+            steps.push(format!(
+                "The receiver reported DMARC as {}, so it based its verdict on other results, like an ARC chain of a forwarder.",
+                reported.result
+            ));
+        }
+    }
+    let requested = policy.map(|policy| requested_policy(policy, &header_from));
+    if let Some(requested) = requested.as_ref().filter(|_| !dmarc_pass) {
+        steps.push(format!(
+            "The policy requests the disposition {} for failing messages.",
+            String::from(requested.clone())
+        ));
+    }
+
+    let mut records = Vec::new();
+    if let Some(ip) = source_ip {
+        for report in reports
+            .iter()
+            .filter(|r| allowed(&r.policy_published.domain))
+        {
+            for (index, record) in report.record.iter().enumerate() {
+                if record.row.source_ip.to_canonical() == ip
+                    && record
+                        .identifiers
+                        .header_from
+                        .eq_ignore_ascii_case(&header_from)
+                {
+                    let range = &report.report_metadata.date_range;
+                    records.push(MatchingRecord {
+                        id: record_id(report, index),
+                        org_name: report.report_metadata.org_name.clone(),
+                        begin: range.begin,
+                        end: range.end,
+                        count: record.row.count,
+                        dmarc_pass: record.is_dmarc_pass(),
+                        disposition: record.row.policy_evaluated.disposition.clone(),
+                    });
+                }
+            }
+        }
+        records.sort_by(|a, b| b.end.cmp(&a.end).then(a.id.cmp(&b.id)));
+        steps.push(match records.len() {
+            0 => format!("No aggregate report contains messages from {ip} for {header_from}."),
+            count => format!(
+                "{count} records of the aggregate reports contain messages from {ip} for {header_from}."
+            ),
+        });
+    }
+
+    HeaderAnalysis {
+        header_from: Some(header_from),
+        organizational_domain: Some(org_domain),
+        source_ip,
+        signatures,
+        dkim,
+        spf,
+        reported_dmarc: reported_dmarc.map(|r| r.result.clone()),
+        dmarc_pass,
+        policy_domain: policy.map(|p| p.domain.clone()),
+        requested,
+        records,
+        steps,
+    }
+}
+
+fn requested_policy(policy: &PolicyPublishedType, header_from: &str) -> DispositionType {
+    match &policy.sp {
+        Some(sp) if !header_from.eq_ignore_ascii_case(&policy.domain) => sp.clone(),
+        _ => policy.p.clone(),
+    }
+}
+
+/// Results of an Authentication-Results header, the first part is the ID of the receiver
+fn authentication_results(value: &str) -> Vec<MethodResult> {
+    strip_comments(value)
+        .split(';')
+        .skip(1)
+        .filter_map(|part| {
+            let mut tokens = part.split_whitespace();
+            let (method, result) = tokens.next()?.split_once('=')?;
+            Some(MethodResult {
+                method: method.to_lowercase(),
+                result: result.to_lowercase(),
+                properties: properties(tokens),
+            })
+        })
+        .collect()
+}
+
+/// SPF result of a Received-SPF header like `pass (comment) client-ip=192.0.2.1; envelope-from=a@example.com`
+fn received_spf(value: &str) -> Option<MethodResult> {
+    let value = strip_comments(value);
+    let mut tokens = value.split(|c: char| c.is_whitespace() || c == ';');
+    let result = tokens.find(|t| !t.is_empty())?;
+    Some(MethodResult {
+        method: String::from("spf"),
+        result: result.to_lowercase(),
+        properties: properties(tokens),
+    })
+}
+
+fn properties<'a>(tokens: impl Iterator<Item = &'a str>) -> HashMap<String, String> {
+    tokens
+        .filter_map(|token| token.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim_end_matches(';').trim_matches('"');
+            (key.to_lowercase(), value.to_owned())
+        })
+        .collect()
+}
+
+/// Removes comments in parentheses, which may be nested
+fn strip_comments(value: &str) -> String {
+    let mut depth = 0usize;
+    value
+        .chars()
+        .filter(|c| {
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth = depth.saturating_sub(1);
+                    return false;
+                }
+                _ => {}
+            }
+            depth == 0
+        })
+        .collect()
+}
+
+/// IP in comments like `domain of a@example.com designates 192.0.2.1 as permitted sender`
+fn designated_ip(value: &str) -> Option<IpAddr> {
+    let (_, rest) = value.split_once(" designates ")?;
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+/// Domain of an address like `Name <a@example.com>` or `a@example.com`
+fn address_domain(value: &str) -> Option<String> {
+    let address = match (value.rfind('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value,
+    };
+    let (_, domain) = address.trim().rsplit_once('@')?;
+    let domain = domain.trim().trim_end_matches('.').to_lowercase();
+    (!domain.is_empty()).then_some(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn evaluate_received_message() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let record = &report.record[0];
+        let ip = record.row.source_ip;
+        let header_from = record.identifiers.header_from.clone();
+        let headers: Vec<(String, String)> = [
+            ("From", format!("Newsletter <news@{header_from}>")),
+            (
+                "Authentication-Results",
+                format!(
+                    "mx.example.net; dkim=pass (2048-bit key) header.d=mail.{header_from} header.s=s1; \
+                     spf=fail (sender not permitted) smtp.mailfrom=bounce@other.example; dmarc=pass header.from={header_from}"
+                ),
+            ),
+            (
+                "Received-SPF",
+                format!("fail (mx.example.net: domain of bounce@other.example does not designate {ip} as permitted sender) client-ip={ip}; envelope-from=\"bounce@other.example\";"),
+            ),
+            ("DKIM-Signature", format!("v=1; a=rsa-sha256; d=mail.{header_from}; s=s1; b=abc=")),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_owned(), value))
+        .collect();
+
+        let analysis = analyze_headers(&headers, std::slice::from_ref(&report), None);
+        assert_eq!(analysis.header_from.as_deref(), Some(header_from.as_str()));
+        assert_eq!(analysis.source_ip, Some(ip));
+        assert_eq!(analysis.signatures[0].selector, "s1");
+        assert_eq!(analysis.dkim.len(), 1);
+        assert!(analysis.dkim[0].passed);
+        assert_eq!(analysis.spf.len(), 1);
+        assert_eq!(analysis.spf[0].domain, "other.example");
+        assert!(!analysis.spf[0].aligned);
+        assert!(analysis.dmarc_pass);
+        assert_eq!(analysis.reported_dmarc.as_deref(), Some("pass"));
+        assert_eq!(
+            analysis.policy_domain,
+            Some(report.policy_published.domain.clone())
+        );
+        assert_eq!(analysis.records.len(), 1);
+        assert_eq!(analysis.records[0].id, record_id(&report, 0));
+    }
+}
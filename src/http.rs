@@ -1,91 +1,480 @@
-use crate::config::Configuration;
-use crate::mail::Mail;
-use crate::state::AppState;
+use crate::advice::{self, DomainAdvice};
+use crate::allowlist::Allowlist;
+use crate::annotations::Annotations;
+use crate::archive::{xml_path, Archive};
+use crate::background::{CycleStatus, RefreshSender};
+use crate::bimi::{self, BimiCheck};
+use crate::causes::{failure_cause_summary, FailureCauseSummary};
+use crate::charts::{daily_chart, disposition_chart, top_ips_chart, ChartData};
+use crate::compare::{compare, Comparison, Window};
+use crate::compliance::{compliance_scores, ComplianceScore};
+use crate::config::{AcmeChallenge, Configuration};
+use crate::csv::csv_chunks;
+use crate::cursor::{CursorError, PageCursor};
+use crate::dns::{DnsCacheStats, DnsResolver};
+use crate::domains::{domain_stats, DomainSummary};
+use crate::enforcement::{enforcement_summary, EnforcementSummary};
+use crate::events::{Event, Events};
+use crate::explain::{explain, find_record, RecordExplanation};
+use crate::export::records_csv_lines;
+use crate::feed::atom_feed;
+use crate::filter::{RecordFilter, TenantDomains};
+use crate::forwarding::{forwarding_stats, ForwardingStats};
+use crate::grafana::{self, QueryRequest, SearchRequest};
+use crate::graphql::{self, DmarcSchema};
+use crate::headers::{analyze_header_block, HeaderAnalysis};
+use crate::ingest::{add_reports, ingest_file, verify, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::instance::InstanceStats;
+use crate::ip_detail::{ip_detail, IpDetail};
+use crate::learning::SenderSuggestion;
+use crate::mail::{mail_entries, Mail, MailEntry, MailFilter, Source};
+use crate::maintenance::{purge, reparse, ReparseResult};
+use crate::mta_sts::{self, MtaStsCheck};
+use crate::notes::{NewNote, Note, NoteTarget, NoteUpdate};
+use crate::offenders::{top_offenders, TopOffenders};
+use crate::openapi::swagger_ui;
+use crate::overrides::{override_summary, OverrideSummary};
+use crate::parser::{extract_xml_files, ExtractLimits, ParseOptions};
+use crate::pdf::pdf_report;
+use crate::policy::PolicyEntry;
+use crate::quarantine::QuarantinedMail;
+use crate::ratelimit::{RateLimitStats, RateLimiter};
+use crate::rdap::{Rdap, RdapInfo};
+use crate::report::{find_report, PolicyPublishedType, Report};
+use crate::reporters::{delivery_gaps, reporters, DeliveryGap, Reporter};
+use crate::rollup::DailyRollup;
+use crate::rua::{self, RuaCheck};
+use crate::search::{SearchResult, SearchTerm};
+use crate::selectors::{selector_inventory, SelectorUsage};
+use crate::session::{session_cookie, set_cookie, SessionUser, Sessions};
+use crate::settings::{Settings, SettingsUpdate, SharedSettings};
+use crate::source::get_mail_body;
+use crate::sources::SourceEntry;
+use crate::spf::{self, SpfNode};
+use crate::state::{AppState, SharedState};
+use crate::status::{unix_time, BackgroundStatus};
+use crate::summary::{CycleMetrics, Summary, WindowSummary};
+use crate::sync::{sync_reports, SYNC_PATH};
+use crate::systemd;
+use crate::tenants::tenant_path;
+use crate::timeseries::{
+    source_timeline, time_series, Bucket, Interval, SourceBucket, Timezone, DAY,
+};
+use crate::user_store::{TokenInfo, UserInfo, UserUpdate};
+use crate::users::{Role, Users};
+use crate::xlsx::domains_workbook;
+use crate::xml_error::{XmlError, XmlErrorKind};
 use anyhow::{Context, Result};
-use axum::body::Body;
-use axum::extract::{Path, Request};
-use axum::http::header::{self, AUTHORIZATION, WWW_AUTHENTICATE};
-use axum::http::StatusCode;
+use async_graphql::http::GraphiQLSource;
+use axum::async_trait;
+use axum::body::{Body, Bytes};
+use axum::extract::connect_info::IntoMakeServiceWithConnectInfo;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{
+    ConnectInfo, DefaultBodyLimit, FromRef, FromRequestParts, Path, Query, Request,
+};
+use axum::http::header::{self, AUTHORIZATION, SET_COOKIE, WWW_AUTHENTICATE};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri};
 use axum::middleware::{self, Next};
-use axum::response::{IntoResponse, Response};
-use axum::routing::IntoMakeService;
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::Extension;
 use axum::Json;
-use axum::{extract::State, routing::get, Router};
+use axum::{
+    extract::State,
+    routing::{get, patch, post},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
 use axum_server::Handle;
 use base64::{engine::general_purpose::STANDARD, Engine};
-use futures::StreamExt;
+use chrono::DateTime;
+use futures::future::try_join_all;
+use futures::{stream, FutureExt, Stream, StreamExt};
 use rustls_acme::caches::DirCache;
-use rustls_acme::AcmeConfig;
-use serde::Serialize;
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-use tokio::signal;
-use tracing::{error, info, warn};
-
-pub async fn run_http_server(config: &Configuration, state: Arc<Mutex<AppState>>) -> Result<()> {
-    if config.http_server_password.is_empty() {
+use rustls_acme::{AcmeConfig, UseChallenge};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Component;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{oneshot, watch};
+use tokio::task::spawn_blocking;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{debug, error, info, warn};
+use utoipa::{IntoParams, ToSchema};
+
+/// Service factory that provides the peer address to the handlers
+type MakeService = IntoMakeServiceWithConnectInfo<Router, SocketAddr>;
+
+/// State of the HTTP server that is available to all handlers
+#[derive(Clone)]
+struct HttpState {
+    app: Arc<SharedState>,
+    config: Arc<Configuration>,
+    dns: Arc<DnsResolver>,
+    rdap: Arc<Rdap>,
+    users: Arc<Users>,
+    sessions: Arc<Sessions>,
+    limiter: Arc<RateLimiter>,
+    settings: Arc<SharedSettings>,
+    refresh: RefreshSender,
+    events: Arc<Events>,
+    graphql: DmarcSchema,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl FromRef<HttpState> for Arc<SharedState> {
+    fn from_ref(state: &HttpState) -> Self {
+        state.app.clone()
+    }
+}
+
+impl FromRef<HttpState> for Arc<Configuration> {
+    fn from_ref(state: &HttpState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<HttpState> for Arc<DnsResolver> {
+    fn from_ref(state: &HttpState) -> Self {
+        state.dns.clone()
+    }
+}
+
+impl FromRef<HttpState> for Arc<Rdap> {
+    fn from_ref(state: &HttpState) -> Self {
+        state.rdap.clone()
+    }
+}
+
+impl FromRef<HttpState> for Arc<Users> {
+    fn from_ref(state: &HttpState) -> Self {
+        state.users.clone()
+    }
+}
+
+impl FromRef<HttpState> for Arc<Sessions> {
+    fn from_ref(state: &HttpState) -> Self {
+        state.sessions.clone()
+    }
+}
+
+impl FromRef<HttpState> for Arc<RateLimiter> {
+    fn from_ref(state: &HttpState) -> Self {
+        state.limiter.clone()
+    }
+}
+
+impl FromRef<HttpState> for Arc<SharedSettings> {
+    fn from_ref(state: &HttpState) -> Self {
+        state.settings.clone()
+    }
+}
+
+impl FromRef<HttpState> for Arc<Events> {
+    fn from_ref(state: &HttpState) -> Self {
+        state.events.clone()
+    }
+}
+
+impl FromRef<HttpState> for DmarcSchema {
+    fn from_ref(state: &HttpState) -> Self {
+        state.graphql.clone()
+    }
+}
+
+impl FromRef<HttpState> for watch::Receiver<bool> {
+    fn from_ref(state: &HttpState) -> Self {
+        state.shutdown.clone()
+    }
+}
+
+impl FromRef<HttpState> for RefreshSender {
+    fn from_ref(state: &HttpState) -> Self {
+        state.refresh.clone()
+    }
+}
+
+/// Channels connecting the HTTP server with the background task and the shutdown
+pub struct HttpChannels {
+    pub refresh: RefreshSender,
+    pub events: Arc<Events>,
+    pub shutdown: watch::Receiver<bool>,
+}
+
+pub async fn run_http_server(
+    config: &Configuration,
+    state: Arc<SharedState>,
+    dns: Arc<DnsResolver>,
+    users: Arc<Users>,
+    limiter: Arc<RateLimiter>,
+    settings: Arc<SharedSettings>,
+    channels: HttpChannels,
+) -> Result<()> {
+    let HttpChannels {
+        refresh,
+        events,
+        mut shutdown,
+    } = channels;
+    if users.auth_disabled() {
         warn!("Detected empty password: Basic Authentication will be disabled")
+    } else {
+        info!("Loaded {} HTTP users", users.count());
+    }
+    if users.tenant_count() > 0 {
+        info!("Loaded {} tenants", users.tenant_count());
+    }
+    let http_state = HttpState {
+        app: state.clone(),
+        config: Arc::new(config.clone()),
+        graphql: graphql::schema(state.clone(), dns.clone()),
+        dns,
+        rdap: Arc::new(Rdap::new(config)?),
+        users,
+        sessions: Arc::new(Sessions::new(config)?),
+        limiter,
+        settings,
+        refresh,
+        events: events.clone(),
+        shutdown: shutdown.clone(),
+    };
+    let router = router(config, http_state)?;
+    let listeners = config.http_listeners()?;
+
+    // Stop accepting new connections on shutdown and give running requests some time
+    let handle = Handle::new();
+    let handle_clone = handle.clone();
+    let timeout = Duration::from_secs(config.shutdown_timeout);
+    tokio::spawn(async move {
+        if shutdown.wait_for(|stop| *stop).await.is_ok() {
+            handle_clone.graceful_shutdown(Some(timeout));
+        }
+    });
+    if systemd::enabled() {
+        tokio::spawn(systemd::notify_ready(handle.clone(), state.clone(), events));
+    }
+
+    // Every listener gets its own service to tell handlers whether the connection uses TLS
+    let mut servers = Vec::new();
+    let mut tls_bindings = Vec::new();
+    for (addr, tls) in listeners {
+        let scheme = ListenerScheme(if tls { "https" } else { "http" });
+        let make_service = router
+            .clone()
+            .layer(Extension(scheme))
+            .into_make_service_with_connect_info::<SocketAddr>();
+        if tls {
+            tls_bindings.push((addr, make_service));
+        } else {
+            info!("Binding HTTP server to {addr}...");
+            servers.push(start_http_server(addr, handle.clone(), make_service).boxed());
+        }
     }
-    let make_service = Router::new()
-        .route("/summary", get(summary))
-        .route("/reports", get(reports))
-        .route("/reports/:id", get(report))
+    if !tls_bindings.is_empty() {
+        if config.https_auto_cert {
+            let server = start_https_server(config, tls_bindings, handle)
+                .map(|result| result.context("Failed to start HTTPS server"));
+            servers.push(server.boxed());
+        } else if let (Some(cert), Some(key)) = (&config.http_tls_cert, &config.http_tls_key) {
+            let server = start_tls_server(tls_bindings, handle, cert, key).map(|result| {
+                result.context("Failed to start HTTPS server with certificate files")
+            });
+            servers.push(server.boxed());
+        }
+    }
+    try_join_all(servers).await?;
+    Ok(())
+}
+
+/// All routes with their authentication and the optional base path
+fn router(config: &Configuration, http_state: HttpState) -> Result<Router> {
+    let conditional =
+        middleware::from_fn_with_state(http_state.clone(), conditional_request_middleware);
+    // Uploaded report files are either compressed attachments or XML files extracted from mails,
+    // imported reports as JSON have about the size of their XML files
+    let ingest_limit = DefaultBodyLimit::max(config.max_attachment_size.max(config.max_xml_size));
+    let import_limit = DefaultBodyLimit::max(config.max_xml_size);
+    let router = Router::new()
+        .route("/summary", get(summary).layer(conditional.clone()))
+        .route("/api/summary/domains", get(domains_summary))
+        .route("/api/domains/:domain", get(domain_detail))
+        .route("/api/summary/windows", get(summary_windows))
+        .route("/api/compliance", get(compliance))
+        .route("/api/compare", get(comparison))
+        .route("/api/reporters", get(reporter_list))
+        .route("/api/reporters/gaps", get(reporter_gaps))
+        .route("/api/selectors", get(selectors))
+        .route("/api/forwarding", get(forwarding))
+        .route("/api/overrides", get(overrides))
+        .route("/api/enforcement", get(enforcement))
+        .route("/api/failure-causes", get(failure_causes))
+        .route("/api/grafana", get(grafana_health))
+        .route("/api/grafana/search", post(grafana_search))
+        .route("/api/grafana/query", post(grafana_query))
+        .route("/graphql", get(graphiql).post(graphql_query))
+        .route("/api/timeseries", get(timeseries))
+        .route("/api/timeseries/sources", get(source_timeseries))
+        .route("/api/rollups", get(rollups))
+        .route("/api/rollups/timeseries", get(rollup_timeseries))
+        .route("/api/top-offenders", get(offenders))
+        .route("/api/charts/daily", get(daily_chart_data))
+        .route("/api/charts/dispositions", get(disposition_chart_data))
+        .route("/api/charts/top-ips", get(top_ips_chart_data))
+        .route("/api/sources", get(sources))
+        .route("/api/policies", get(policies))
+        .route("/reports", get(reports).layer(conditional.clone()))
+        .route("/reports/:id", get(report).layer(conditional.clone()))
+        .route("/api/reports/:id/xml", get(report_xml).layer(conditional))
+        .route("/api/records/:id/explain", get(record_explanation))
+        .route("/api/analyze-headers", post(analyze_headers))
+        .route("/api/search", get(search))
+        .route(SYNC_PATH, get(sync))
         .route("/xml-errors", get(xml_errors))
+        .route("/api/xml-errors", get(xml_error_list))
+        .route("/api/xml-errors/:hash/xml", get(xml_error_file))
+        .route(
+            "/api/xml-errors/:hash/attachment",
+            get(xml_error_attachment),
+        )
         .route("/mails", get(mails))
+        .route("/api/mails", get(mail_list))
+        .route("/api/quarantine", get(quarantine))
+        .route("/feed.xml", get(feed))
+        .route("/api/export/csv", get(export_csv))
+        .route("/api/export/json", get(export_json))
+        .route("/api/export/xlsx", get(export_xlsx))
+        .route("/api/export/pdf", get(export_pdf))
+        .route("/api/instance", get(instance))
+        .route("/api/dns/stats", get(dns_stats))
+        .route("/api/dns/ptr/:ip", get(dns_ptr))
+        .route("/api/dns/txt/:name", get(dns_txt))
+        .route("/api/dns/health", get(dns_health))
+        .route("/api/dns/spf", get(dns_spf))
+        .route("/api/rdap/:ip", get(rdap_lookup))
+        .route("/api/ips/:ip", get(ip_lookup))
+        .route("/api/advice", get(advice))
+        .route(
+            "/api/reports",
+            get(report_list).post(import_reports).layer(import_limit),
+        )
+        .route("/api/annotations", get(annotations))
+        .route("/api/annotations", post(import_annotations))
+        .route("/api/notes", get(notes).post(create_note))
+        .route("/api/notes/:id", patch(update_note).delete(delete_note))
+        .route("/api/allowlist", get(allowlist))
+        .route("/api/allowlist", post(import_allowlist))
         .route("/", get(static_file)) // index.html
         .route("/*filepath", get(static_file)) // all other files
+        .route("/api/ratelimit/stats", get(ratelimit_stats))
+        .route(
+            "/api/admin/settings",
+            get(admin_settings).put(update_settings),
+        )
+        .route("/api/admin/reload", post(admin_reload))
+        .route("/api/admin/users", get(admin_users).post(create_user))
+        .route(
+            "/api/admin/users/:name",
+            patch(update_user).delete(delete_user),
+        )
+        .route("/api/admin/purge", post(admin_purge))
+        .route("/api/admin/reparse", post(admin_reparse))
+        .route("/api/admin/suggestions", get(sender_suggestions))
+        .route("/api/admin/suggestions/:id/accept", post(accept_suggestion))
+        .route("/api/admin/suggestions/:id/reject", post(reject_suggestion))
+        .route("/api/admin/tokens", get(admin_tokens))
+        .route(
+            "/api/admin/tokens/:name",
+            post(rotate_token).delete(delete_token),
+        )
+        .route("/api/refresh", post(refresh_reports))
+        .route("/api/events", get(events_stream))
+        .route("/api/status", get(status))
+        .route("/api/status/history", get(status_history))
+        .route("/api/session", get(session))
+        .merge(swagger_ui(&config.http_base_path))
         .route_layer(middleware::from_fn_with_state(
-            config.clone(),
+            http_state.clone(),
             basic_auth_middleware,
         ))
-        .with_state(state.clone())
-        .into_make_service();
-
-    let binding = format!("{}:{}", config.http_server_binding, config.http_server_port);
-    let addr: SocketAddr = binding.parse().context("Failed to parse binding address")?;
-    info!("Binding HTTP server to {addr}...");
-
-    if config.https_auto_cert {
-        start_https_server(config, addr, make_service)
-            .await
-            .context("Failed to start HTTPS server")
+        // Authenticated by request signature instead of basic auth
+        .route("/api/ingest", post(ingest).layer(ingest_limit))
+        // Login form and session handling available without login
+        .route("/login.html", get(static_file))
+        .route("/api/login", post(login))
+        .route("/api/logout", post(logout))
+        .route_layer(middleware::from_fn_with_state(
+            http_state.clone(),
+            rate_limit_middleware,
+        ))
+        // Probes for container orchestration without authentication
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(http_state);
+    let router = match cors_layer(config).context("Invalid CORS configuration")? {
+        Some(cors) => router.layer(cors),
+        None => router,
+    };
+    let base_path = &config.http_base_path;
+    Ok(if base_path.is_empty() {
+        router
     } else {
-        start_http_server(addr, make_service)
-            .await
-            .context("Failed to start HTTP server")
-    }
+        info!("Serving all routes below base path {base_path}/");
+        Router::new()
+            .fallback_service(router)
+            .layer(middleware::from_fn_with_state(
+                base_path.clone(),
+                strip_base_path,
+            ))
+    })
 }
 
 async fn start_http_server(
     addr: SocketAddr,
-    make_service: IntoMakeService<Router>,
+    handle: Handle,
+    make_service: MakeService,
 ) -> anyhow::Result<()> {
-    let handle = Handle::new();
-    let handle_clone = handle.clone();
-    tokio::spawn(async move {
-        shutdown_signal().await;
-        handle_clone.shutdown();
-    });
-
     axum_server::bind(addr)
         .handle(handle)
         .serve(make_service)
         .await
-        .context("Failed to create axum HTTP server")
+        .with_context(|| format!("Failed to create axum HTTP server on {addr}"))
 }
 
-async fn start_https_server(
-    config: &Configuration,
-    addr: SocketAddr,
-    make_service: IntoMakeService<Router>,
+async fn start_tls_server(
+    bindings: Vec<(SocketAddr, MakeService)>,
+    handle: Handle,
+    cert: &str,
+    key: &str,
 ) -> anyhow::Result<()> {
-    let handle = Handle::new();
-    let handle_clone = handle.clone();
-    tokio::spawn(async move {
-        shutdown_signal().await;
-        handle_clone.shutdown();
+    let rustls_config = RustlsConfig::from_pem_file(cert, key)
+        .await
+        .context("Failed to load TLS certificate and key")?;
+
+    let servers = bindings.into_iter().map(|(addr, make_service)| {
+        info!("Binding HTTPS server to {addr}...");
+        axum_server::bind_rustls(addr, rustls_config.clone())
+            .handle(handle.clone())
+            .serve(make_service)
+            .map(move |result| {
+                result.with_context(|| format!("Failed to create axum HTTPS server on {addr}"))
+            })
     });
+    try_join_all(servers).await?;
+    Ok(())
+}
 
+async fn start_https_server(
+    config: &Configuration,
+    bindings: Vec<(SocketAddr, MakeService)>,
+    handle: Handle,
+) -> anyhow::Result<()> {
     let acme_domain = config
         .https_auto_cert_domain
         .as_deref()
@@ -107,14 +496,38 @@ async fn start_https_server(
             .to_owned(),
     );
 
+    let challenge_type = match config.https_auto_cert_challenge {
+        AcmeChallenge::TlsAlpn01 => UseChallenge::TlsAlpn01,
+        AcmeChallenge::Http01 => UseChallenge::Http01,
+    };
     let mut acme_state = AcmeConfig::new([acme_domain])
         .contact([acme_contact])
         .cache_option(Some(acme_cache))
-        .directory_lets_encrypt(true)
+        .directory_lets_encrypt(!config.https_auto_cert_staging)
+        .challenge_type(challenge_type)
         .state();
     let rustls_config = acme_state.default_rustls_config();
     let acceptor = acme_state.axum_acceptor(rustls_config);
 
+    if matches!(config.https_auto_cert_challenge, AcmeChallenge::Http01) {
+        // The challenge server uses the address of the first HTTPS listener with the challenge port
+        let challenge_ip = bindings[0].0.ip();
+        let challenge_addr = SocketAddr::new(challenge_ip, config.https_auto_cert_http_port);
+        let challenge_app = Router::new().route_service(
+            "/.well-known/acme-challenge/:challenge_token",
+            acme_state.http01_challenge_tower_service(),
+        );
+        info!("Binding ACME HTTP-01 challenge server to {challenge_addr}...");
+        tokio::spawn(async move {
+            if let Err(err) = axum_server::bind(challenge_addr)
+                .serve(challenge_app.into_make_service())
+                .await
+            {
+                error!("Failed to run ACME challenge server: {err:#}");
+            }
+        });
+    }
+
     tokio::spawn(async move {
         loop {
             match acme_state
@@ -128,105 +541,394 @@ async fn start_https_server(
         }
     });
 
-    axum_server::bind(addr)
-        .handle(handle)
-        .acceptor(acceptor)
-        .serve(make_service)
-        .await
-        .context("Failed to create axum HTTPS server")
+    let servers = bindings.into_iter().map(|(addr, make_service)| {
+        info!("Binding HTTPS server to {addr}...");
+        axum_server::bind(addr)
+            .handle(handle.clone())
+            .acceptor(acceptor.clone())
+            .serve(make_service)
+            .map(move |result| {
+                result.with_context(|| format!("Failed to create axum HTTPS server on {addr}"))
+            })
+    });
+    try_join_all(servers).await?;
+    Ok(())
 }
 
-/// Promise will be fulfilled when a shutdown signal is received
-async fn shutdown_signal() {
-    let ctrlc = async {
-        signal::ctrl_c()
-            .await
-            .expect("Failed to install Ctrl + C handler");
-    };
+/// Record filter from the query parameters with the configured default for failures only,
+/// restricted to the domains of the tenant of the caller
+#[async_trait]
+impl FromRequestParts<HttpState> for RecordFilter {
+    type Rejection = QueryRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &HttpState,
+    ) -> Result<Self, Self::Rejection> {
+        let Query(mut filter) = Query::<RecordFilter>::from_request_parts(parts, state).await?;
+        filter
+            .only_failures
+            .get_or_insert(state.config.only_failures);
+        filter.tenant = parts.extensions.get::<TenantDomains>().cloned();
+        Ok(filter)
+    }
+}
+
+/// Middleware to limit the number of requests per client IP
+async fn rate_limit_middleware(
+    State(limiter): State<Arc<RateLimiter>>,
+    State(config): State<Arc<Configuration>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = client_ip(&config, peer.ip(), request.headers());
+    if limiter.allow_request(client) {
+        next.run(request).await
+    } else {
+        warn!("Rate limit exceeded by client {client}");
+        (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, "60")]).into_response()
+    }
+}
 
-    #[cfg(unix)]
-    let terminate = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())
-            .expect("Failed to install signal handler")
-            .recv()
-            .await;
+/// Middleware answering with 304 Not Modified if the client already has the response
+/// for the current state, based on an ETag derived from the last update and state revision.
+/// Responses only change with the state, so polling clients can revalidate cheaply.
+/// The tag also covers the URL and the tenant of the request, which select the content,
+/// and the current local day, since windows of days up to today move at midnight.
+async fn conditional_request_middleware(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let today = Interval::Daily.bucket_start(unix_time(), config.timezone);
+    let mut hasher = Sha256::new();
+    hasher.update(request.uri().path());
+    hasher.update([0]);
+    hasher.update(request.uri().query().unwrap_or_default());
+    if let Some(tenant) = request.extensions().get::<TenantDomains>() {
+        for domain in tenant.domains() {
+            hasher.update([0]);
+            hasher.update(domain);
+        }
+    }
+    let variant = hex::encode(&hasher.finalize()[..8]);
+    let (etag, last_update) = {
+        let lock = state.snapshot();
+        (
+            format!(
+                "W/\"{}-{}-{today}-{variant}\"",
+                lock.last_update, lock.revision
+            ),
+            lock.last_update.max(today),
+        )
     };
+    let last_modified = DateTime::from_timestamp(last_update as i64, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string();
+    let headers = request.headers();
+    // If-Modified-Since is only evaluated without If-None-Match as required by RFC 9110
+    let not_modified = match headers.get(header::IF_NONE_MATCH) {
+        Some(value) => value.to_str().is_ok_and(|tags| {
+            tags.split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == "*" || t == etag.trim_start_matches("W/"))
+        }),
+        None => headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+            .is_some_and(|since| last_update as i64 <= since.timestamp()),
+    };
+    // Responses depend on the user, shared caches must not serve them to others
+    let validators = [
+        (header::ETAG, etag),
+        (header::LAST_MODIFIED, last_modified),
+        (header::VARY, String::from("Authorization, Cookie")),
+        (header::CACHE_CONTROL, String::from("private")),
+    ];
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, validators).into_response();
+    }
+    let response = next.run(request).await;
+    if response.status().is_success() {
+        (validators, response).into_response()
+    } else {
+        response
+    }
+}
+
+/// Address of the client, taken from the `X-Forwarded-For` header
+/// if the request was forwarded by a trusted reverse proxy
+fn client_ip(config: &Configuration, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let peer = peer.to_canonical();
+    if !config.http_trusted_proxies.contains(&peer) {
+        return peer;
+    }
+    headers
+        .get("X-Forwarded-For")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or(peer)
+}
 
-    #[cfg(not(unix))]
-    let terminate = std::future::pending::<()>();
+/// Creates the CORS layer if any allowed origins are configured
+fn cors_layer(config: &Configuration) -> Result<Option<CorsLayer>> {
+    if config.http_cors_origins.is_empty() {
+        return Ok(None);
+    }
+    let origins = if config.http_cors_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins = config
+            .http_cors_origins
+            .iter()
+            .map(|o| HeaderValue::from_str(o).with_context(|| format!("Invalid origin {o}")))
+            .collect::<Result<Vec<_>>>()?;
+        AllowOrigin::list(origins)
+    };
+    let methods = config
+        .http_cors_methods
+        .iter()
+        .map(|m| Method::from_bytes(m.as_bytes()).with_context(|| format!("Invalid method {m}")))
+        .collect::<Result<Vec<_>>>()?;
+    let headers = config
+        .http_cors_headers
+        .iter()
+        .map(|h| {
+            HeaderName::from_bytes(h.as_bytes()).with_context(|| format!("Invalid header {h}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers),
+    ))
+}
 
-    tokio::select! {
-        _ = ctrlc => {},
-        _ = terminate => {},
+/// Middleware that removes the base path from the request URI before routing
+async fn strip_base_path(
+    State(base_path): State<String>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(path) = request.uri().path().strip_prefix(base_path.as_str()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    // Relative links in the UI only work if the base path ends with a slash
+    if path.is_empty() {
+        return Redirect::permanent(&format!("{base_path}/")).into_response();
     }
+    if !path.starts_with('/') {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_owned(),
+    };
+    let mut parts = request.uri().clone().into_parts();
+    let Ok(path_and_query) = path_and_query.parse() else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    parts.path_and_query = Some(path_and_query);
+    let Ok(uri) = Uri::from_parts(parts) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    *request.uri_mut() = uri;
+    next.run(request).await
 }
 
-/// Middleware to add basic auth password protection
+/// Middleware to add basic auth password protection.
+/// Read-only users and API tokens are only allowed to use GET requests.
+/// Admin endpoints are only available to admins.
+/// Users and API tokens of tenants are restricted to the domains of their tenants.
+/// Requests that modify data are logged with the user name for auditing.
 async fn basic_auth_middleware(
-    State(config): State<Configuration>,
-    request: Request,
+    State(users): State<Arc<Users>>,
+    State(sessions): State<Arc<Sessions>>,
+    State(limiter): State<Arc<RateLimiter>>,
+    State(config): State<Arc<Configuration>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
     next: Next,
 ) -> Response {
-    // Password empty means basic auth is disabled
-    if config.http_server_password.is_empty() {
+    // No users means basic auth is disabled
+    if users.auth_disabled() {
         return next.run(request).await;
     }
 
-    // Prepare error responses
-    let unauthorized = Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
-        .header(WWW_AUTHENTICATE, "Basic realm=\"Access\"")
-        .body(Body::empty())
-        .expect("Failed to create response");
+    // Prepare error responses, with session login the browser should not prompt for credentials
+    let unauthorized = if !config.http_session_login {
+        Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(WWW_AUTHENTICATE, "Basic realm=\"Access\"")
+            .body(Body::empty())
+    } else if request.uri().path() == "/" {
+        Response::builder()
+            .status(StatusCode::SEE_OTHER)
+            .header(
+                header::LOCATION,
+                format!("{}/login.html", config.http_base_path),
+            )
+            .body(Body::empty())
+    } else {
+        Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(Body::empty())
+    }
+    .expect("Failed to create response");
     let bad_request = Response::builder()
         .status(StatusCode::BAD_REQUEST)
         .body(Body::empty())
         .expect("Failed to create response");
+    let forbidden = Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(Body::empty())
+        .expect("Failed to create response");
 
-    let Some(header) = request.headers().get(AUTHORIZATION) else {
-        return unauthorized;
-    };
-    let Ok(header) = header.to_str() else {
-        return bad_request;
-    };
-    let Some(base64) = header.strip_prefix("Basic ") else {
-        return bad_request;
-    };
-    let Ok(decoded) = STANDARD.decode(base64) else {
-        return bad_request;
-    };
-    let Ok(string) = String::from_utf8(decoded) else {
-        return bad_request;
-    };
-    let Some((user, password)) = string.split_once(':') else {
-        return bad_request;
-    };
-    if user == config.http_server_user && password == config.http_server_password {
-        next.run(request).await
+    // Grafana and GraphQL queries are sent as POST requests but do not change anything
+    let path = request.uri().path();
+    let read_only = request.method() == Method::GET
+        || request.method() == Method::HEAD
+        || (request.method() == Method::POST
+            && (path.starts_with(GRAFANA_PREFIX) || path == GRAPHQL_PATH));
+    let api_path = path.starts_with("/api/") || path == GRAPHQL_PATH;
+    // Admin endpoints also reveal users, tokens and settings with GET requests
+    let admin_path = path.starts_with(ADMIN_PREFIX) || path == RATE_LIMIT_STATS_PATH;
+    // Notes are written by read-only users too
+    let notes_path = path == NOTES_PATH || path.starts_with(&format!("{NOTES_PATH}/"));
+    let session = session_cookie(request.headers())
+        .filter(|_| config.http_session_login)
+        .and_then(|cookie| sessions.verify(cookie))
+        .and_then(|user| users.role(&user).map(|role| (user, role)));
+    let (user, role) = if let Some((user, role)) = users.proxy_user(peer.ip(), request.headers()) {
+        (user.to_owned(), role)
+    } else if let Some((user, role)) = session {
+        request
+            .extensions_mut()
+            .insert(SessionUser { user: user.clone() });
+        (user, role)
     } else {
-        unauthorized
+        let Some(header) = request.headers().get(AUTHORIZATION) else {
+            return unauthorized;
+        };
+        let Ok(header) = header.to_str() else {
+            return bad_request;
+        };
+        // Guessed API tokens count as failed logins like wrong passwords
+        let client = client_ip(&config, peer.ip(), request.headers());
+        if let Some(remaining) = limiter.locked(client) {
+            let secs = remaining.as_secs() + 1;
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, secs.to_string())],
+            )
+                .into_response();
+        }
+        if let Some(token) = header.strip_prefix("Bearer ") {
+            if !read_only || !api_path {
+                return unauthorized;
+            }
+            if admin_path {
+                return forbidden;
+            }
+            if users.valid_token(token) {
+                return next.run(request).await;
+            }
+            let Some(domains) = users.token_domains(token) else {
+                warn!("Invalid API token from {client}");
+                limiter.login_failed(client);
+                return unauthorized;
+            };
+            if !tenant_path(path) {
+                return forbidden;
+            }
+            request.extensions_mut().insert(domains);
+            return next.run(request).await;
+        }
+        let Some(base64) = header.strip_prefix("Basic ") else {
+            return bad_request;
+        };
+        let Ok(decoded) = STANDARD.decode(base64) else {
+            return bad_request;
+        };
+        let Ok(string) = String::from_utf8(decoded) else {
+            return bad_request;
+        };
+        let Some((user, password)) = string.split_once(':') else {
+            return bad_request;
+        };
+        let Some(role) = users.authenticate(user, password).await else {
+            warn!("Failed login of user {user} from {client}");
+            limiter.login_failed(client);
+            return unauthorized;
+        };
+        limiter.login_succeeded(client);
+        (user.to_owned(), role)
+    };
+
+    if let Some(domains) = users.user_domains(&user) {
+        if !read_only || !tenant_path(request.uri().path()) {
+            warn!(
+                "Denied {} {} for tenant user {user}",
+                request.method(),
+                request.uri().path()
+            );
+            return forbidden;
+        }
+        request.extensions_mut().insert(domains);
+        return next.run(request).await;
+    }
+    request.extensions_mut().insert(UserName(user.clone()));
+    if admin_path && role != Role::Admin {
+        warn!(
+            "Denied {} {} for read-only user {user}",
+            request.method(),
+            request.uri().path()
+        );
+        return forbidden;
+    }
+    if read_only {
+        return next.run(request).await;
+    }
+    if role != Role::Admin && !notes_path {
+        warn!(
+            "Denied {} {} for read-only user {user}",
+            request.method(),
+            request.uri().path()
+        );
+        return forbidden;
     }
+    info!(
+        "User {user} requested {} {}",
+        request.method(),
+        request.uri().path()
+    );
+    next.run(request).await
 }
 
-async fn static_file(req: Request) -> impl IntoResponse {
+async fn static_file(State(config): State<Arc<Configuration>>, req: Request) -> Response {
     let path = req.uri().path();
+    if let Some(dir) = &config.ui_override_dir {
+        if let Some((data, mime_type)) = override_file(dir, path) {
+            return (StatusCode::OK, [(header::CONTENT_TYPE, mime_type)], data).into_response();
+        }
+    }
     for sf in STATIC_FILES {
         if sf.http_path == path {
-            let mut mime_type = "application/octet-stream";
-            for mt in MIME_TYPES {
-                if sf.file_path.ends_with(mt.ext) {
-                    mime_type = mt.mime_type;
-                    break;
-                }
-            }
             return (
                 StatusCode::OK,
-                [(header::CONTENT_TYPE, mime_type)],
+                [(header::CONTENT_TYPE, mime_type(sf.file_path))],
                 #[cfg(debug_assertions)]
                 std::fs::read(sf.file_path).expect("Failed to read file"),
                 #[cfg(not(debug_assertions))]
                 sf._data,
-            );
+            )
+                .into_response();
         }
     }
     (
@@ -237,90 +939,3074 @@ async fn static_file(req: Request) -> impl IntoResponse {
         #[cfg(not(debug_assertions))]
         b"File not found",
     )
+        .into_response()
 }
 
-async fn summary(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResponse {
-    Json(
-        state
-            .lock()
-            .expect("Failed to lock app state")
-            .summary
-            .clone(),
-    )
+fn mime_type(file_path: &str) -> &'static str {
+    MIME_TYPES
+        .iter()
+        .find(|mt| file_path.ends_with(mt.ext))
+        .map(|mt| mt.mime_type)
+        .unwrap_or("application/octet-stream")
 }
 
-#[derive(Serialize)]
-struct ReportHeader {
-    id: String,
-    org: String,
-    domain: String,
-    date_begin: u64,
-    date_end: u64,
-    records: usize,
+/// File from the UI override directory for the request path.
+/// Paths with components other than plain names are ignored to stay inside the directory.
+fn override_file(dir: &str, path: &str) -> Option<(Vec<u8>, &'static str)> {
+    let relative = match path.trim_start_matches('/') {
+        "" => "index.html",
+        relative => relative,
+    };
+    let relative = std::path::Path::new(relative);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    let file_path = std::path::Path::new(dir).join(relative);
+    if !file_path.is_file() {
+        return None;
+    }
+    match std::fs::read(&file_path) {
+        Ok(data) => Some((data, mime_type(&relative.to_string_lossy()))),
+        Err(err) => {
+            warn!(
+                "Failed to read UI override file {}: {err}",
+                file_path.display()
+            );
+            None
+        }
+    }
 }
 
-async fn reports(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResponse {
-    let reports: Vec<ReportHeader> = state
-        .lock()
-        .expect("Failed to lock app state")
-        .reports
+/// Time window of the summary, overrides the configured number of days.
+/// Use `days=0` to include all reports.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SummaryParams {
+    days: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/summary",
+    tag = "statistics",
+    params(RecordFilter, SummaryParams),
+    responses((status = 200, body = Summary)),
+)]
+async fn summary(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    mut filter: RecordFilter,
+    Query(params): Query<SummaryParams>,
+) -> impl IntoResponse {
+    if filter.since.is_none() {
+        // Window starts at the beginning of the local day
+        if let Some(days) = params.days.or(config.summary_days).filter(|d| *d > 0) {
+            let today = Interval::Daily.bucket_start(unix_time(), config.timezone);
+            filter.since = Some(today.saturating_sub((days - 1) * DAY));
+        }
+    }
+    let mut summary = filtered_summary(&state.snapshot(), &filter);
+    summary.last_cycle = state.cycle_history().last();
+    Json(summary)
+}
+
+/// Summaries of all configured windows ending now, each limited by the filter as well
+#[utoipa::path(
+    get,
+    path = "/api/summary/windows",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = Vec<WindowSummary>)),
+)]
+async fn summary_windows(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    mut filter: RecordFilter,
+) -> impl IntoResponse {
+    let now = unix_time();
+    let since = filter.since;
+    let last_cycle = state.cycle_history().last();
+    let locked_state = state.snapshot();
+    let windows: Vec<WindowSummary> = config
+        .summary_windows
         .iter()
-        .map(|r| ReportHeader {
-            id: r.report_metadata.report_id.clone(),
-            org: r.report_metadata.org_name.clone(),
-            domain: r.policy_published.domain.clone(),
-            date_begin: r.report_metadata.date_range.begin,
-            date_end: r.report_metadata.date_range.end,
-            records: r.record.len(),
+        .map(|window| {
+            filter.since = since.max(window.since(now, config.timezone));
+            let mut summary = filtered_summary(&locked_state, &filter);
+            summary.last_cycle = last_cycle.clone();
+            WindowSummary {
+                window: window.to_string(),
+                summary,
+            }
         })
         .collect();
-    Json(reports)
+    Json(windows)
 }
 
-async fn report(
-    State(state): State<Arc<Mutex<AppState>>>,
-    Path(id): Path<String>,
+/// Precalculated summary for all reports, otherwise aggregated for the filter
+fn filtered_summary(state: &AppState, filter: &RecordFilter) -> Summary {
+    if filter.is_unfiltered() {
+        return state.summary.clone();
+    }
+    let mut summary = state.summary.filtered(&state.reports, filter);
+    summary
+        .compliance
+        .retain(|c| filter.allows_domain(&c.domain));
+    summary
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/summary/domains",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = Vec<DomainSummary>)),
+)]
+async fn domains_summary(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
 ) -> impl IntoResponse {
-    let lock = state.lock().expect("Failed to lock app state");
-    if let Some(report) = lock
-        .reports
-        .iter()
-        .find(|r| *r.report_metadata.report_id == id)
-    {
-        let report_json = serde_json::to_string(report).expect("Failed to serialize JSON");
-        (
-            StatusCode::OK,
-            [(header::CONTENT_TYPE, "application/json")],
-            report_json,
-        )
-    } else {
-        (
-            StatusCode::NOT_FOUND,
-            [(header::CONTENT_TYPE, "text/plain")],
-            format!("Cannot find report with ID {id}"),
-        )
+    let domains: Vec<DomainSummary> = domain_stats(&state.snapshot().reports, &filter)
+        .into_iter()
+        .map(DomainSummary::from)
+        .collect();
+    Json(domains)
+}
+
+/// Length of the rolling window, overrides the configured number of days
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ComplianceParams {
+    days: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/compliance",
+    tag = "statistics",
+    params(ComplianceParams),
+    responses((status = 200, body = Vec<ComplianceScore>)),
+)]
+async fn compliance(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    tenant: Option<Extension<TenantDomains>>,
+    Query(params): Query<ComplianceParams>,
+) -> impl IntoResponse {
+    let days = params.days.unwrap_or(config.compliance_days).max(1);
+    let mut scores = compliance_scores(&state.snapshot().reports, unix_time(), days * DAY);
+    if let Some(Extension(tenant)) = tenant {
+        scores.retain(|s| tenant.allows(&s.domain));
     }
+    Json(scores)
 }
 
-async fn xml_errors(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResponse {
-    let lock = state.lock().expect("Failed to lock app state");
-    let errors_json = serde_json::to_string(&lock.xml_errors).expect("Failed to serialize JSON");
-    (
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "application/json")],
-        errors_json,
-    )
+/// Current window as number of days up to today or as explicit time range,
+/// the previous window defaults to the same length right before the current one
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CompareParams {
+    days: Option<u64>,
+    since: Option<u64>,
+    until: Option<u64>,
+    previous_since: Option<u64>,
+    previous_until: Option<u64>,
+    domain: Option<String>,
 }
 
-async fn mails(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResponse {
-    let lock = state.lock().expect("Failed to lock app state");
-    let mails: Vec<&Mail> = lock.mails.values().collect();
-    let mails_json = serde_json::to_string(&mails).expect("Failed to serialize JSON");
-    (
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "application/json")],
-        mails_json,
-    )
+#[utoipa::path(
+    get,
+    path = "/api/compare",
+    tag = "statistics",
+    params(CompareParams),
+    responses(
+        (status = 200, body = Comparison),
+        (status = 400, description = "Invalid time windows"),
+    ),
+)]
+async fn comparison(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Query(params): Query<CompareParams>,
+) -> Response {
+    let now = unix_time();
+    let current = match (params.since, params.until) {
+        (Some(since), until) => Window {
+            since,
+            until: until.unwrap_or(now),
+        },
+        (None, None) => recent_days(params.days.unwrap_or(7), now, config.timezone),
+        (None, Some(..)) => {
+            return (StatusCode::BAD_REQUEST, "Parameter until requires since").into_response()
+        }
+    };
+    let previous = match (params.previous_since, params.previous_until) {
+        (Some(since), Some(until)) => Window { since, until },
+        (None, None) => current.previous(),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Parameters previous_since and previous_until must be used together",
+            )
+                .into_response()
+        }
+    };
+    if current.since > current.until || previous.since > previous.until {
+        return (StatusCode::BAD_REQUEST, "Empty time window").into_response();
+    }
+    let reports = &state.snapshot().reports;
+    Json(compare(reports, current, previous, params.domain)).into_response()
+}
+
+/// Whole local days up to and including today,
+/// so the previous window is aligned with days as well
+fn recent_days(days: u64, now: u64, timezone: Timezone) -> Window {
+    let today = Interval::Daily.bucket_start(now, timezone);
+    Window {
+        since: today.saturating_sub((days.max(1) - 1) * DAY),
+        until: today + DAY - 1,
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ReporterParams {
+    /// Reporters without reports in this number of days are marked as missing
+    #[serde(default = "default_recent_days")]
+    recent_days: u64,
+}
+
+fn default_recent_days() -> u64 {
+    7
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/reporters",
+    tag = "statistics",
+    params(RecordFilter, ReporterParams),
+    responses((status = 200, body = Vec<Reporter>)),
+)]
+async fn reporter_list(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+    Query(params): Query<ReporterParams>,
+) -> impl IntoResponse {
+    let recent_since = unix_time().saturating_sub(params.recent_days * DAY);
+    Json(reporters(&state.snapshot().reports, &filter, recent_since))
+}
+
+/// Reporters that used to send daily reports for a domain but sent none for the configured number of days
+#[utoipa::path(
+    get,
+    path = "/api/reporters/gaps",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = Vec<DeliveryGap>)),
+)]
+async fn reporter_gaps(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    Json(delivery_gaps(
+        &state.snapshot().reports,
+        &filter,
+        unix_time(),
+        config.reporter_gap_days,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/selectors",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = Vec<SelectorUsage>)),
+)]
+async fn selectors(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    Json(selector_inventory(&state.snapshot().reports, &filter))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/forwarding",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = Vec<ForwardingStats>)),
+)]
+async fn forwarding(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    Json(forwarding_stats(&state.snapshot().reports, &filter))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/overrides",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = OverrideSummary)),
+)]
+async fn overrides(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    Json(override_summary(&state.snapshot().reports, &filter))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/enforcement",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = EnforcementSummary)),
+)]
+async fn enforcement(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    Json(enforcement_summary(&state.snapshot().reports, &filter))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/failure-causes",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = FailureCauseSummary)),
+)]
+async fn failure_causes(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    Json(failure_cause_summary(&state.snapshot().reports, &filter))
+}
+
+/// Path of the notes endpoints
+const NOTES_PATH: &str = "/api/notes";
+
+/// Path prefix of the endpoints only available to admins
+const ADMIN_PREFIX: &str = "/api/admin/";
+
+/// Statistics of the rate limiter, only available to admins
+const RATE_LIMIT_STATS_PATH: &str = "/api/ratelimit/stats";
+
+/// Path prefix of the endpoints implementing the SimpleJSON protocol for Grafana
+const GRAFANA_PREFIX: &str = "/api/grafana/";
+
+/// Connection test of the Grafana data source
+#[utoipa::path(get, path = "/api/grafana", tag = "grafana", responses((status = 200)))]
+async fn grafana_health() -> impl IntoResponse {
+    StatusCode::OK
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/grafana/search",
+    tag = "grafana",
+    request_body = SearchRequest,
+    responses((status = 200, body = Vec<String>)),
+)]
+async fn grafana_search(
+    State(state): State<Arc<SharedState>>,
+    request: Option<Json<SearchRequest>>,
+) -> impl IntoResponse {
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    Json(grafana::search(&state.snapshot().reports, &request))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/grafana/query",
+    tag = "grafana",
+    request_body = QueryRequest,
+    responses(
+        (status = 200, body = Vec<Object>, description = "Time series and tables"),
+        (status = 400, description = "Invalid time range"),
+    ),
+)]
+async fn grafana_query(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Json(request): Json<QueryRequest>,
+) -> Response {
+    let result = grafana::query(&state.snapshot().reports, &request, config.timezone);
+    match result {
+        Ok(result) => Json(result).into_response(),
+        Err(err) => (StatusCode::BAD_REQUEST, err).into_response(),
+    }
+}
+
+/// Path of the GraphQL endpoint, outside of the JSON API
+const GRAPHQL_PATH: &str = "/graphql";
+
+/// Minimum size of the body frames of CSV exports
+const CSV_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Interactive GraphQL editor that sends its queries to the same path
+async fn graphiql(State(config): State<Arc<Configuration>>) -> impl IntoResponse {
+    let endpoint = format!("{}{GRAPHQL_PATH}", config.http_base_path);
+    Html(GraphiQLSource::build().endpoint(&endpoint).finish())
+}
+
+async fn graphql_query(
+    State(schema): State<DmarcSchema>,
+    Json(request): Json<async_graphql::Request>,
+) -> impl IntoResponse {
+    Json(schema.execute(request).await)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TimeSeriesParams {
+    #[serde(default)]
+    interval: Interval,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/timeseries",
+    tag = "statistics",
+    params(RecordFilter, TimeSeriesParams),
+    responses((status = 200, body = Vec<Bucket>)),
+)]
+async fn timeseries(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    filter: RecordFilter,
+    Query(params): Query<TimeSeriesParams>,
+) -> impl IntoResponse {
+    Json(time_series(
+        &state.snapshot().reports,
+        &filter,
+        params.interval,
+        config.timezone,
+    ))
+}
+
+/// Distinct source IPs per day or week of the domain set in the filter
+#[utoipa::path(
+    get,
+    path = "/api/timeseries/sources",
+    tag = "statistics",
+    params(RecordFilter, TimeSeriesParams),
+    responses(
+        (status = 200, body = Vec<SourceBucket>),
+        (status = 400, description = "No domain selected"),
+    ),
+)]
+async fn source_timeseries(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    filter: RecordFilter,
+    Query(params): Query<TimeSeriesParams>,
+) -> Response {
+    if filter.domain.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Select a domain with the domain parameter",
+        )
+            .into_response();
+    }
+    Json(source_timeline(
+        &state.snapshot().reports,
+        &filter,
+        params.interval,
+        config.timezone,
+    ))
+    .into_response()
+}
+
+/// Daily counts per domain, including days whose reports were removed by the retention limits
+#[utoipa::path(
+    get,
+    path = "/api/rollups",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = Vec<DailyRollup>)),
+)]
+async fn rollups(State(state): State<Arc<SharedState>>, filter: RecordFilter) -> impl IntoResponse {
+    Json(state.snapshot().rollups.list(&filter))
+}
+
+/// Message counts per day or week in UTC from the daily rollups, for trends longer than the retention
+#[utoipa::path(
+    get,
+    path = "/api/rollups/timeseries",
+    tag = "statistics",
+    params(RecordFilter, TimeSeriesParams),
+    responses((status = 200, body = Vec<Bucket>)),
+)]
+async fn rollup_timeseries(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+    Query(params): Query<TimeSeriesParams>,
+) -> impl IntoResponse {
+    Json(
+        state
+            .snapshot()
+            .rollups
+            .time_series(&filter, params.interval),
+    )
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct OffendersParams {
+    #[serde(default = "default_offenders_limit")]
+    limit: usize,
+}
+
+fn default_offenders_limit() -> usize {
+    10
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/top-offenders",
+    tag = "statistics",
+    params(RecordFilter, OffendersParams),
+    responses((status = 200, body = TopOffenders)),
+)]
+async fn offenders(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+    Query(params): Query<OffendersParams>,
+) -> impl IntoResponse {
+    Json(top_offenders(
+        &state.snapshot().reports,
+        &filter,
+        params.limit,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/charts/daily",
+    tag = "charts",
+    params(RecordFilter),
+    responses((status = 200, body = ChartData)),
+)]
+async fn daily_chart_data(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    Json(daily_chart(
+        &state.snapshot().reports,
+        &filter,
+        config.timezone,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/charts/dispositions",
+    tag = "charts",
+    params(RecordFilter),
+    responses((status = 200, body = ChartData)),
+)]
+async fn disposition_chart_data(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    Json(disposition_chart(&state.snapshot().reports, &filter))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/charts/top-ips",
+    tag = "charts",
+    params(RecordFilter, OffendersParams),
+    responses((status = 200, body = ChartData)),
+)]
+async fn top_ips_chart_data(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+    Query(params): Query<OffendersParams>,
+) -> impl IntoResponse {
+    Json(top_ips_chart(
+        &state.snapshot().reports,
+        &filter,
+        params.limit,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/sources",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = Vec<SourceEntry>)),
+)]
+async fn sources(State(state): State<Arc<SharedState>>, filter: RecordFilter) -> impl IntoResponse {
+    Json(state.snapshot().source_history.list(&filter))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/policies",
+    tag = "statistics",
+    params(RecordFilter),
+    responses((status = 200, body = HashMap<String, Vec<PolicyEntry>>)),
+)]
+async fn policies(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    let mut policies = state
+        .snapshot()
+        .policy_history
+        .list(filter.domain.as_deref());
+    policies.retain(|domain, _| filter.allows_domain(domain));
+    Json(policies)
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReportHeader {
+    id: String,
+    stable_id: String,
+    org: String,
+    domain: String,
+    date_begin: u64,
+    date_end: u64,
+    records: usize,
+    failures: usize,
+}
+
+#[derive(Deserialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum ReportSort {
+    Date,
+    Org,
+    Records,
+    Failures,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ReportListParams {
+    sort: Option<ReportSort>,
+    #[serde(default)]
+    order: SortOrder,
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports",
+    tag = "reports",
+    params(RecordFilter, ReportListParams),
+    responses((status = 200, body = Vec<ReportHeader>)),
+)]
+async fn reports(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+    Query(params): Query<ReportListParams>,
+) -> impl IntoResponse {
+    Json(report_headers(&state.snapshot(), &filter, &params))
+}
+
+/// Headers of the matching reports in the requested order, the order of the state otherwise
+fn report_headers(
+    state: &AppState,
+    filter: &RecordFilter,
+    params: &ReportListParams,
+) -> Vec<ReportHeader> {
+    let mut reports: Vec<ReportHeader> = state
+        .reports
+        .iter()
+        .filter(|r| filter.matches_report_records(r))
+        .map(|r| ReportHeader {
+            id: r.report_metadata.report_id.clone(),
+            stable_id: r.stable_id(),
+            org: r.report_metadata.org_name.clone(),
+            domain: r.policy_published.domain.clone(),
+            date_begin: r.report_metadata.date_range.begin,
+            date_end: r.report_metadata.date_range.end,
+            records: r.record.len(),
+            failures: r.failed_messages(),
+        })
+        .collect();
+    if let Some(sort) = params.sort {
+        reports.sort_by(|a, b| match sort {
+            ReportSort::Date => (a.date_begin, a.date_end).cmp(&(b.date_begin, b.date_end)),
+            ReportSort::Org => a.org.to_lowercase().cmp(&b.org.to_lowercase()),
+            ReportSort::Records => a.records.cmp(&b.records),
+            ReportSort::Failures => a.failures.cmp(&b.failures),
+        });
+        if matches!(params.order, SortOrder::Desc) {
+            reports.reverse();
+        }
+    }
+    reports
+}
+
+/// Page of matching reports with the total number of matches
+#[derive(Serialize, ToSchema)]
+struct ReportPage {
+    total: usize,
+    offset: usize,
+
+    /// State revision the page belongs to
+    revision: u64,
+
+    /// Cursor of the next page, missing on the last page
+    next_cursor: Option<String>,
+    reports: Vec<ReportHeader>,
+}
+
+/// Same reports as `/reports` in pages. The cursor of the next page is only valid as long as
+/// the reports do not change, afterwards 410 Gone tells the client to start again.
+#[utoipa::path(
+    get,
+    path = "/api/reports",
+    tag = "reports",
+    params(RecordFilter, ReportListParams, PageParams),
+    responses(
+        (status = 200, body = ReportPage),
+        (status = 400, description = "Invalid cursor"),
+        (status = 410, description = "Reports changed since the cursor was issued"),
+    ),
+)]
+async fn report_list(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+    Query(params): Query<ReportListParams>,
+    Query(page): Query<PageParams>,
+) -> impl IntoResponse {
+    let lock = state.snapshot();
+    let offset = match page.offset(lock.revision) {
+        Ok(offset) => offset,
+        Err(error) => return error.into_response(),
+    };
+    let headers = report_headers(&lock, &filter, &params);
+    let total = headers.len();
+    let reports: Vec<ReportHeader> = headers.into_iter().skip(offset).take(page.limit).collect();
+    Json(ReportPage {
+        total,
+        offset,
+        revision: lock.revision,
+        next_cursor: PageCursor::next(lock.revision, offset, reports.len(), total)
+            .map(|c| c.encode()),
+        reports,
+    })
+    .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/reports/{id}",
+    tag = "reports",
+    params(
+        ("id" = String, Path, description = "Stable ID or ID assigned by the reporter"),
+        RecordFilter,
+    ),
+    responses((status = 200, body = ReportDetail), (status = 404, description = "Unknown report")),
+)]
+async fn report(
+    State(state): State<Arc<SharedState>>,
+    tenant: Option<Extension<TenantDomains>>,
+    filter: RecordFilter,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let lock = state.snapshot();
+    let report = find_report(&lock.reports, &id)
+        .filter(|r| filter.allows_domain(&r.policy_published.domain));
+    if let Some(report) = report {
+        let mut report = Cow::Borrowed(report);
+        if filter.only_failures == Some(true) {
+            report.to_mut().record.retain(|r| filter.matches_record(r));
+        }
+        // The mailbox of the operator is none of the business of tenants
+        if tenant.is_some() {
+            let report = report.to_mut();
+            report.mail_uid = None;
+            report.xml_hash = None;
+            report.attachment_name = None;
+        }
+        let source = tenant.is_none().then(|| {
+            Source::new(
+                &lock.mails,
+                report.mail_uid,
+                report.attachment_name.as_deref(),
+                report.xml_hash.as_deref(),
+            )
+        });
+        let detail = ReportDetail {
+            report: &report,
+            source,
+        };
+        let report_json = serde_json::to_string(&detail).expect("Failed to serialize JSON");
+        (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/json")],
+            report_json,
+        )
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("Cannot find report with ID {id}"),
+        )
+    }
+}
+
+/// Report with the mail it was extracted from
+#[derive(Serialize, ToSchema)]
+struct ReportDetail<'a> {
+    #[serde(flatten)]
+    report: &'a Report,
+
+    /// Not available to tenant users
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<Source<'a>>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchParams {
+    q: String,
+    limit: Option<usize>,
+}
+
+/// Search reports by names, IDs, domains, selectors and source IPs or networks
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    tag = "reports",
+    params(SearchParams),
+    responses(
+        (status = 200, body = Vec<SearchResult>),
+        (status = 400, description = "Empty search query"),
+    ),
+)]
+async fn search(
+    State(state): State<Arc<SharedState>>,
+    tenant: Option<Extension<TenantDomains>>,
+    Query(params): Query<SearchParams>,
+) -> Response {
+    if params.q.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "Empty search query").into_response();
+    }
+    let term = SearchTerm::parse(&params.q);
+    let lock = state.snapshot();
+    let results: Vec<SearchResult> = lock
+        .reports
+        .iter()
+        .filter(|r| {
+            tenant
+                .as_ref()
+                .is_none_or(|t| t.allows(&r.policy_published.domain))
+        })
+        .filter_map(|r| term.search(r))
+        .take(params.limit.unwrap_or(1000))
+        .collect();
+    Json(results).into_response()
+}
+
+/// Step by step explanation of the DMARC evaluation of a record:
+/// which identifiers aligned, why DKIM and SPF passed or failed and why the disposition was applied
+#[utoipa::path(
+    get,
+    path = "/api/records/{id}/explain",
+    tag = "reports",
+    params((
+        "id" = String,
+        Path,
+        description = "Stable ID or ID assigned by the reporter of the report, followed by a dash and the index of the record"
+    )),
+    responses(
+        (status = 200, body = RecordExplanation),
+        (status = 404, description = "Unknown record"),
+    ),
+)]
+async fn record_explanation(
+    State(state): State<Arc<SharedState>>,
+    tenant: Option<Extension<TenantDomains>>,
+    Path(id): Path<String>,
+) -> Response {
+    let lock = state.snapshot();
+    let record = find_record(&lock.reports, &id).filter(|(r, _)| {
+        tenant
+            .as_ref()
+            .is_none_or(|t| t.allows(&r.policy_published.domain))
+    });
+    match record {
+        Some((report, index)) => Json(explain(report, index)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            format!("Cannot find record with ID {id}"),
+        )
+            .into_response(),
+    }
+}
+
+/// DMARC evaluation of a single message from its raw header block,
+/// with the records of the aggregate reports from the same source IP for the same domain
+#[utoipa::path(
+    post,
+    path = "/api/analyze-headers",
+    tag = "reports",
+    request_body(content = String, content_type = "text/plain", description = "Header block of the message as received"),
+    responses(
+        (status = 200, body = HeaderAnalysis),
+        (status = 422, description = "Invalid header block"),
+    ),
+)]
+async fn analyze_headers(
+    State(state): State<Arc<SharedState>>,
+    tenant: Option<Extension<TenantDomains>>,
+    body: Bytes,
+) -> Response {
+    let tenant = tenant.map(|Extension(tenant)| tenant);
+    match analyze_header_block(&body, &state.snapshot().reports, tenant.as_ref()) {
+        Ok(analysis) => Json(analysis).into_response(),
+        Err(err) => (StatusCode::UNPROCESSABLE_ENTITY, format!("{err:#}")).into_response(),
+    }
+}
+
+/// Original XML file of a report from the archive.
+/// Without archive the file is extracted again from the mail in the IMAP inbox.
+#[utoipa::path(
+    get,
+    path = "/api/reports/{id}/xml",
+    tag = "reports",
+    params(("id" = String, Path, description = "Stable ID or ID assigned by the reporter")),
+    responses(
+        (status = 200, content_type = "application/xml", body = Vec<u8>),
+        (status = 404, description = "Unknown report or XML file not available"),
+        (status = 502, description = "Failed to get the mail from the IMAP server"),
+    ),
+)]
+async fn report_xml(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    tenant: Option<Extension<TenantDomains>>,
+    Path(id): Path<String>,
+) -> Response {
+    if config.anonymize_key.is_some() {
+        return (StatusCode::NOT_FOUND, ANONYMIZED_ORIGINALS).into_response();
+    }
+    let report = {
+        let lock = state.snapshot();
+        find_report(&lock.reports, &id)
+            .filter(|r| {
+                tenant
+                    .as_ref()
+                    .is_none_or(|t| t.allows(&r.policy_published.domain))
+            })
+            .map(|r| (r.xml_hash.clone(), r.mail_uid))
+    };
+    let Some((hash, mail_uid)) = report else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Cannot find report with ID {id}"),
+        )
+            .into_response();
+    };
+    let Some(hash) = hash else {
+        return (
+            StatusCode::NOT_FOUND,
+            "Report was parsed before XML files were tracked",
+        )
+            .into_response();
+    };
+    match original_xml(&config, &hash, mail_uid).await {
+        Ok(Some(data)) => (
+            [
+                (header::CONTENT_TYPE, String::from("application/xml")),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{hash}.xml\""),
+                ),
+            ],
+            data,
+        )
+            .into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Original XML file is not available").into_response(),
+        Err(err) => {
+            warn!("Failed to get original XML file of report {id}: {err:#}");
+            (StatusCode::BAD_GATEWAY, format!("{err:#}")).into_response()
+        }
+    }
+}
+
+/// Original files contain the real names and addresses
+const ANONYMIZED_ORIGINALS: &str = "Original files are not available with anonymization";
+
+/// Looks up the XML file in the archive first and then in the mail
+async fn original_xml(
+    config: &Configuration,
+    hash: &str,
+    mail_uid: Option<u32>,
+) -> Result<Option<Vec<u8>>> {
+    if let Some(dir) = &config.archive_dir {
+        if let Some(data) = Archive::new(dir).read(&xml_path(hash))? {
+            return Ok(Some(data));
+        }
+    }
+    let Some(uid) = mail_uid else {
+        return Ok(None);
+    };
+    if config.read_replica {
+        return Ok(None);
+    }
+    let Some(body) = get_mail_body(config, uid).await? else {
+        return Ok(None);
+    };
+    let limits = ExtractLimits::from(config);
+    let (xml_files, _) = spawn_blocking(move || extract_xml_files(uid, &body, &limits))
+        .await
+        .context("Failed to join extraction worker")??;
+    Ok(xml_files
+        .into_iter()
+        .find(|f| f.hash == hash)
+        .map(|f| f.data))
+}
+
+#[utoipa::path(
+    get,
+    path = "/xml-errors",
+    tag = "xml errors",
+    responses((status = 200, body = Vec<XmlError>)),
+)]
+async fn xml_errors(State(state): State<Arc<SharedState>>) -> impl IntoResponse {
+    let lock = state.snapshot();
+    let errors_json = serde_json::to_string(&lock.xml_errors).expect("Failed to serialize JSON");
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        errors_json,
+    )
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct XmlErrorFilter {
+    kind: Option<XmlErrorKind>,
+    mail_uid: Option<u32>,
+}
+
+/// XML error without the potentially large XML file
+#[derive(Serialize, ToSchema)]
+struct XmlErrorEntry<'a> {
+    mail_uid: u32,
+    error: &'a str,
+    kind: XmlErrorKind,
+    hash: &'a str,
+    attachment_name: Option<&'a str>,
+    attachment_archived: bool,
+    source: Source<'a>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/xml-errors",
+    tag = "xml errors",
+    params(XmlErrorFilter),
+    responses((status = 200, body = Vec<XmlErrorEntry>)),
+)]
+async fn xml_error_list(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Query(filter): Query<XmlErrorFilter>,
+) -> impl IntoResponse {
+    let lock = state.snapshot();
+    let entries: Vec<XmlErrorEntry> = lock
+        .xml_errors
+        .iter()
+        .filter(|e| filter.kind.is_none_or(|kind| e.kind == kind))
+        .filter(|e| filter.mail_uid.is_none_or(|uid| e.mail_uid == uid))
+        .map(|e| XmlErrorEntry {
+            mail_uid: e.mail_uid,
+            error: &e.error,
+            kind: e.kind,
+            hash: &e.hash,
+            attachment_name: e.attachment_name.as_deref(),
+            attachment_archived: config.archive_dir.is_some() && e.attachment_path.is_some(),
+            source: Source::new(
+                &lock.mails,
+                Some(e.mail_uid),
+                e.attachment_name.as_deref(),
+                Some(e.hash.as_str()).filter(|h| !h.is_empty()),
+            ),
+        })
+        .collect();
+    Json(entries).into_response()
+}
+
+/// Original XML file that failed to parse, taken from the archive if possible
+/// because the copy in the state was converted to UTF-8
+#[utoipa::path(
+    get,
+    path = "/api/xml-errors/{hash}/xml",
+    tag = "xml errors",
+    params(("hash" = String, Path, description = "Hash of the XML file")),
+    responses(
+        (status = 200, content_type = "application/xml", body = Vec<u8>),
+        (status = 404, description = "Unknown XML error"),
+    ),
+)]
+async fn xml_error_file(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Path(hash): Path<String>,
+) -> Response {
+    if config.anonymize_key.is_some() {
+        return (StatusCode::NOT_FOUND, ANONYMIZED_ORIGINALS).into_response();
+    }
+    let xml = {
+        let lock = state.snapshot();
+        match lock.xml_errors.iter().find(|e| e.hash == hash) {
+            Some(error) => error.xml.clone(),
+            None => return (StatusCode::NOT_FOUND, "XML error not found").into_response(),
+        }
+    };
+    let archived = config
+        .archive_dir
+        .as_deref()
+        .map(|dir| Archive::new(dir).read(&xml_path(&hash)));
+    let data = match archived {
+        Some(Ok(Some(data))) => data,
+        Some(Err(err)) => {
+            error!("Failed to read archived XML file: {err:#}");
+            xml.into_bytes()
+        }
+        _ => xml.into_bytes(),
+    };
+    let disposition = format!("attachment; filename=\"{hash}.xml\"");
+    (
+        [
+            (header::CONTENT_TYPE, String::from("application/xml")),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        data,
+    )
+        .into_response()
+}
+
+/// Original mail attachment of an XML file that failed to parse, requires the archive
+#[utoipa::path(
+    get,
+    path = "/api/xml-errors/{hash}/attachment",
+    tag = "xml errors",
+    params(("hash" = String, Path, description = "Hash of the XML file")),
+    responses(
+        (status = 200, content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 404, description = "Unknown XML error or attachment not archived"),
+    ),
+)]
+async fn xml_error_attachment(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Path(hash): Path<String>,
+) -> Response {
+    if config.anonymize_key.is_some() {
+        return (StatusCode::NOT_FOUND, ANONYMIZED_ORIGINALS).into_response();
+    }
+    let (path, name) = {
+        let lock = state.snapshot();
+        let Some(error) = lock.xml_errors.iter().find(|e| e.hash == hash) else {
+            return (StatusCode::NOT_FOUND, "XML error not found").into_response();
+        };
+        (error.attachment_path.clone(), error.attachment_name.clone())
+    };
+    let (Some(dir), Some(path)) = (&config.archive_dir, path) else {
+        return (StatusCode::NOT_FOUND, "Attachment was not archived").into_response();
+    };
+    let data = match Archive::new(dir).read(&path) {
+        Ok(Some(data)) => data,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Attachment was not archived").into_response(),
+        Err(err) => {
+            error!("Failed to read archived attachment: {err:#}");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read attachment",
+            )
+                .into_response();
+        }
+    };
+    let content_type = if path.ends_with(".zip") {
+        "application/zip"
+    } else {
+        "application/gzip"
+    };
+    let file_name = name
+        .unwrap_or_else(|| path.rsplit('/').next().unwrap_or_default().to_owned())
+        .replace('"', "");
+    (
+        [
+            (header::CONTENT_TYPE, String::from(content_type)),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file_name}\""),
+            ),
+        ],
+        data,
+    )
+        .into_response()
+}
+
+#[utoipa::path(get, path = "/mails", tag = "reports", responses((status = 200, body = Vec<Mail>)))]
+async fn mails(State(state): State<Arc<SharedState>>) -> impl IntoResponse {
+    let lock = state.snapshot();
+    let mails: Vec<&Mail> = lock.mails.values().collect();
+    let mails_json = serde_json::to_string(&mails).expect("Failed to serialize JSON");
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/json")],
+        mails_json,
+    )
+}
+
+/// Page of a paginated listing
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PageParams {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_page_limit")]
+    limit: usize,
+
+    /// Cursor of the next page from the previous response, used instead of the offset
+    cursor: Option<String>,
+}
+
+fn default_page_limit() -> usize {
+    100
+}
+
+impl PageParams {
+    /// Offset of the page, from the cursor if there is one.
+    /// The error response tells the client whether the cursor is broken or the state changed.
+    fn offset(&self, revision: u64) -> Result<usize, (StatusCode, &'static str)> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(self.offset);
+        };
+        match PageCursor::decode(cursor, revision) {
+            Ok(cursor) => Ok(cursor.offset),
+            Err(CursorError::Invalid) => Err((StatusCode::BAD_REQUEST, "Invalid cursor")),
+            Err(CursorError::Expired) => Err((
+                StatusCode::GONE,
+                "Reports changed since the cursor was issued, restart from the first page",
+            )),
+        }
+    }
+}
+
+/// Page of matching mails with the total number of matches
+#[derive(Serialize, ToSchema)]
+struct MailPage<'a> {
+    total: usize,
+    offset: usize,
+
+    /// State revision the page belongs to
+    revision: u64,
+
+    /// Cursor of the next page, missing on the last page
+    next_cursor: Option<String>,
+    mails: Vec<MailEntry<'a>>,
+}
+
+/// Mails whose XML files could not be extracted with the number of attempts and the last error
+#[utoipa::path(
+    get,
+    path = "/api/quarantine",
+    tag = "reports",
+    responses((status = 200, body = Vec<QuarantinedMail>)),
+)]
+async fn quarantine(State(state): State<Arc<SharedState>>) -> impl IntoResponse {
+    Json(state.snapshot().quarantine.list())
+}
+
+/// Mails with the results of the XML files found in them, newest first
+#[utoipa::path(
+    get,
+    path = "/api/mails",
+    tag = "reports",
+    params(MailFilter, PageParams),
+    responses(
+        (status = 200, body = MailPage),
+        (status = 400, description = "Invalid cursor"),
+        (status = 410, description = "Reports changed since the cursor was issued"),
+    ),
+)]
+async fn mail_list(
+    State(state): State<Arc<SharedState>>,
+    Query(filter): Query<MailFilter>,
+    Query(page): Query<PageParams>,
+) -> impl IntoResponse {
+    let lock = state.snapshot();
+    let offset = match page.offset(lock.revision) {
+        Ok(offset) => offset,
+        Err(error) => return error.into_response(),
+    };
+    let entries = mail_entries(&lock, &filter);
+    let total = entries.len();
+    let mails: Vec<MailEntry> = entries.into_iter().skip(offset).take(page.limit).collect();
+    Json(MailPage {
+        total,
+        offset,
+        revision: lock.revision,
+        next_cursor: PageCursor::next(lock.revision, offset, mails.len(), total)
+            .map(|c| c.encode()),
+        mails,
+    })
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct FeedParams {
+    #[serde(default = "default_feed_limit")]
+    limit: usize,
+}
+
+fn default_feed_limit() -> usize {
+    50
+}
+
+/// Atom feed of the newest reports for feed readers
+async fn feed(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    filter: RecordFilter,
+    Query(params): Query<FeedParams>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(listener): Extension<ListenerScheme>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let base_url = public_base_url(&config, peer.ip(), listener, &headers);
+    let lock = state.snapshot();
+    let xml = atom_feed(
+        &lock.reports,
+        &filter,
+        &base_url,
+        params.limit,
+        lock.summary.last_update,
+    );
+    (
+        [(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+        xml,
+    )
+}
+
+/// URL of the web UI as seen by the client, used for absolute links
+fn public_base_url(
+    config: &Configuration,
+    peer: IpAddr,
+    listener: ListenerScheme,
+    headers: &HeaderMap,
+) -> String {
+    let scheme = request_scheme(config, peer, listener, headers);
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("localhost");
+    format!("{scheme}://{host}{}", config.http_base_path)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/export/csv",
+    tag = "export",
+    params(RecordFilter),
+    responses((status = 200, content_type = "text/csv", body = String)),
+)]
+async fn export_csv(
+    State(state): State<Arc<SharedState>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    let lines = records_csv_lines(state.snapshot(), filter);
+    let chunks = csv_chunks(lines, CSV_CHUNK_SIZE);
+    let stream = futures::stream::iter(chunks.map(Ok::<String, Infallible>));
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "text/csv"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"dmarc-records.csv\"",
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/export/json",
+    tag = "export",
+    responses((status = 200, body = Object, description = "Complete application state")),
+)]
+async fn export_json(State(state): State<Arc<SharedState>>) -> impl IntoResponse {
+    let lock = state.snapshot();
+    let state_json = serde_json::to_string(&*lock).expect("Failed to serialize JSON");
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"dmarc-export.json\"",
+            ),
+        ],
+        state_json,
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/export/xlsx",
+    tag = "export",
+    params(RecordFilter),
+    responses(
+        (status = 200, content_type = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", body = Vec<u8>),
+    ),
+)]
+async fn export_xlsx(State(state): State<Arc<SharedState>>, filter: RecordFilter) -> Response {
+    let domains = domain_stats(&state.snapshot().reports, &filter);
+    match domains_workbook(&domains) {
+        Ok(xlsx) => (
+            StatusCode::OK,
+            [
+                (
+                    header::CONTENT_TYPE,
+                    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+                ),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"dmarc-domains.xlsx\"",
+                ),
+            ],
+            xlsx,
+        )
+            .into_response(),
+        Err(err) => {
+            error!("Failed to create XLSX export: {err:#}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::CONTENT_TYPE, "text/plain")],
+                "Failed to create XLSX export",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Period of the PDF report as number of days up to today or as explicit time range
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PdfParams {
+    days: Option<u64>,
+    since: Option<u64>,
+    until: Option<u64>,
+    domain: Option<String>,
+}
+
+/// PDF summary with compliance per domain, top sources of failures and daily trend
+#[utoipa::path(
+    get,
+    path = "/api/export/pdf",
+    tag = "export",
+    params(PdfParams),
+    responses(
+        (status = 200, content_type = "application/pdf", body = Vec<u8>),
+        (status = 400, description = "Empty time window"),
+    ),
+)]
+async fn export_pdf(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Query(params): Query<PdfParams>,
+) -> Response {
+    let now = unix_time();
+    let window = match params.since {
+        Some(since) => Window {
+            since,
+            until: params.until.unwrap_or(now),
+        },
+        None => recent_days(params.days.unwrap_or(7), now, config.timezone),
+    };
+    if window.since > window.until {
+        return (StatusCode::BAD_REQUEST, "Empty time window").into_response();
+    }
+    let pdf = {
+        let lock = state.snapshot();
+        pdf_report(&lock.reports, window, params.domain, config.timezone)
+    };
+    (
+        [
+            (header::CONTENT_TYPE, "application/pdf"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"dmarc-report.pdf\"",
+            ),
+        ],
+        pdf,
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/instance",
+    tag = "status",
+    responses((status = 200, body = InstanceStats)),
+)]
+async fn instance(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+) -> impl IntoResponse {
+    let lock = state.snapshot();
+    Json(InstanceStats::new(&lock, config.state_file.as_deref()))
+}
+
+/// The process is alive if it can answer HTTP requests
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "status",
+    security(()),
+    responses((status = 200, body = String)),
+)]
+async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, "OK")
+}
+
+/// Ready after the first update cycle completed successfully
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "status",
+    security(()),
+    responses(
+        (status = 200, body = String),
+        (status = 503, description = "Waiting for first update"),
+    ),
+)]
+async fn readyz(State(state): State<Arc<SharedState>>) -> impl IntoResponse {
+    if state.snapshot().ready {
+        (StatusCode::OK, "OK")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "Waiting for first update")
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/settings",
+    tag = "admin",
+    responses((status = 200, body = Settings)),
+)]
+async fn admin_settings(State(settings): State<Arc<SharedSettings>>) -> impl IntoResponse {
+    Json(settings.get())
+}
+
+/// Change settings of the running instance until the next reload of the settings file.
+/// A new check interval applies to the wait for the next update cycle right away.
+#[utoipa::path(
+    put,
+    path = "/api/admin/settings",
+    tag = "admin",
+    request_body = SettingsUpdate,
+    responses(
+        (status = 200, body = Settings),
+        (status = 422, description = "Invalid settings"),
+    ),
+)]
+async fn update_settings(
+    State(settings): State<Arc<SharedSettings>>,
+    Json(update): Json<SettingsUpdate>,
+) -> Response {
+    match settings.update(update) {
+        Ok(settings) => Json(settings).into_response(),
+        Err(err) => (StatusCode::UNPROCESSABLE_ENTITY, format!("{err:#}")).into_response(),
+    }
+}
+
+/// Reload the settings file, same as sending SIGHUP
+#[utoipa::path(
+    post,
+    path = "/api/admin/reload",
+    tag = "admin",
+    responses(
+        (status = 200, body = Settings),
+        (status = 422, description = "Invalid settings file"),
+    ),
+)]
+async fn admin_reload(State(settings): State<Arc<SharedSettings>>) -> Response {
+    match settings.reload() {
+        Ok(settings) => Json(settings).into_response(),
+        Err(err) => {
+            warn!("Failed to reload settings: {err:#}");
+            (StatusCode::UNPROCESSABLE_ENTITY, format!("{err:#}")).into_response()
+        }
+    }
+}
+
+/// Remove reports by domain, org or date range, at least one of them is required.
+/// Mails of removed reports are not downloaded again.
+#[utoipa::path(
+    post,
+    path = "/api/admin/purge",
+    tag = "admin",
+    params(RecordFilter),
+    responses(
+        (status = 200, body = Object),
+        (status = 400, description = "No domain, org or date range"),
+        (status = 409, description = "Read replicas cannot change reports"),
+    ),
+)]
+async fn admin_purge(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Query(filter): Query<RecordFilter>,
+) -> Response {
+    if config.read_replica {
+        return read_replica_maintenance();
+    }
+    match purge(&state, &filter) {
+        Ok(count) => {
+            info!("Purged {count} reports");
+            Json(serde_json::json!({ "reports": count })).into_response()
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, format!("{err:#}")).into_response(),
+    }
+}
+
+/// Parse the archived XML files of all reports and parse errors again, for example after an update
+/// with parser fixes. Parse errors without archive are parsed from their stored XML.
+#[utoipa::path(
+    post,
+    path = "/api/admin/reparse",
+    tag = "admin",
+    responses(
+        (status = 200, body = ReparseResult),
+        (status = 409, description = "Read replicas cannot change reports"),
+    ),
+)]
+async fn admin_reparse(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    State(events): State<Arc<Events>>,
+) -> Response {
+    if config.read_replica {
+        return read_replica_maintenance();
+    }
+    let result = spawn_blocking(move || {
+        let archive = config.archive_dir.as_deref().map(Archive::new);
+        reparse(
+            &state,
+            archive.as_ref(),
+            &ParseOptions::from(config.as_ref()),
+        )
+    })
+    .await
+    .context("Failed to join parser worker");
+    match result.and_then(|r| r) {
+        Ok(result) => {
+            info!(
+                "Parsed {} reports again, recovered {} reports from parse errors, {} files failed and {} reports have no archived file",
+                result.reparsed, result.recovered, result.failed, result.missing
+            );
+            if result.recovered > 0 {
+                events.send(Event::NewReports {
+                    count: result.recovered,
+                });
+            }
+            Json(result).into_response()
+        }
+        Err(err) => {
+            error!("Failed to parse reports again: {err:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/suggestions",
+    tag = "admin",
+    responses((status = 200, body = Vec<SenderSuggestion>)),
+)]
+async fn sender_suggestions(State(state): State<Arc<SharedState>>) -> impl IntoResponse {
+    Json(state.snapshot().trusted_learning.list())
+}
+
+/// Add the source of a suggestion to the allowlist of its domain and flag all records again
+#[utoipa::path(
+    post,
+    path = "/api/admin/suggestions/{id}/accept",
+    tag = "admin",
+    params(("id" = u64, Path, description = "ID of the suggestion")),
+    responses(
+        (status = 200, body = SenderSuggestion),
+        (status = 404, description = "Unknown suggestion"),
+        (status = 409, description = "Read replicas cannot change the allowlist"),
+    ),
+)]
+async fn accept_suggestion(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Path(id): Path<u64>,
+) -> Response {
+    if config.read_replica {
+        return read_replica_maintenance();
+    }
+    let accepted = state.update(|locked| {
+        let suggestion = locked.trusted_learning.accept(id, &mut locked.allowlist)?;
+        locked
+            .allowlist
+            .mark(Arc::make_mut(&mut locked.reports).as_mut_slice());
+        locked.summary.unexpected = locked
+            .reports
+            .iter()
+            .flat_map(|r| &r.record)
+            .filter(|r| r.unexpected)
+            .count();
+        locked.revision += 1;
+        Some(suggestion)
+    });
+    match accepted {
+        Some(suggestion) => {
+            info!(
+                "Added {} as {} to the allowlist of {}",
+                suggestion.source_ip, suggestion.provider, suggestion.domain
+            );
+            Json(suggestion).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, format!("Unknown suggestion {id}")).into_response(),
+    }
+}
+
+/// Dismiss a suggestion, the source is not proposed again for the domain
+#[utoipa::path(
+    post,
+    path = "/api/admin/suggestions/{id}/reject",
+    tag = "admin",
+    params(("id" = u64, Path, description = "ID of the suggestion")),
+    responses(
+        (status = 204, description = "Suggestion rejected"),
+        (status = 404, description = "Unknown suggestion"),
+        (status = 409, description = "Read replicas cannot change suggestions"),
+    ),
+)]
+async fn reject_suggestion(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Path(id): Path<u64>,
+) -> Response {
+    if config.read_replica {
+        return read_replica_maintenance();
+    }
+    match state.update(|locked| locked.trusted_learning.reject(id)) {
+        Some(..) => StatusCode::NO_CONTENT.into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Unknown suggestion {id}")).into_response(),
+    }
+}
+
+fn read_replica_maintenance() -> Response {
+    (
+        StatusCode::CONFLICT,
+        "Reports can only be changed on the primary instance",
+    )
+        .into_response()
+}
+
+/// New user managed at runtime
+#[derive(Deserialize, ToSchema)]
+struct NewUser {
+    name: String,
+    role: Role,
+    password: String,
+}
+
+/// Generated API token, only returned once
+#[derive(Serialize, ToSchema)]
+struct NewToken {
+    name: String,
+    token: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/users",
+    tag = "admin",
+    responses((status = 200, body = Vec<UserInfo>)),
+)]
+async fn admin_users(State(users): State<Arc<Users>>) -> impl IntoResponse {
+    Json(users.list())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/users",
+    tag = "admin",
+    request_body = NewUser,
+    responses(
+        (status = 201, description = "User created"),
+        (status = 400, description = "Empty name or password"),
+        (status = 404, description = "User management is disabled"),
+        (status = 409, description = "User already exists"),
+    ),
+)]
+async fn create_user(State(users): State<Arc<Users>>, Json(user): Json<NewUser>) -> Response {
+    if !users.store().enabled() {
+        return user_store_disabled();
+    }
+    if user.name.is_empty() || user.name.contains(':') || user.password.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Name and password must not be empty and the name must not contain a colon",
+        )
+            .into_response();
+    }
+    if users.is_configured(&user.name) {
+        return configured_user(&user.name);
+    }
+    match users
+        .store()
+        .create_user(&user.name, user.role, &user.password)
+    {
+        Ok(true) => {
+            info!("Created user {} with role {:?}", user.name, user.role);
+            StatusCode::CREATED.into_response()
+        }
+        Ok(false) => (
+            StatusCode::CONFLICT,
+            format!("User {} already exists", user.name),
+        )
+            .into_response(),
+        Err(err) => user_store_error(err),
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/admin/users/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "Name of a managed user")),
+    request_body = UserUpdate,
+    responses(
+        (status = 204, description = "User updated"),
+        (status = 404, description = "Unknown user or user management is disabled"),
+        (status = 409, description = "User is configured and cannot be changed"),
+    ),
+)]
+async fn update_user(
+    State(users): State<Arc<Users>>,
+    Path(name): Path<String>,
+    Json(update): Json<UserUpdate>,
+) -> Response {
+    if !users.store().enabled() {
+        return user_store_disabled();
+    }
+    if users.is_configured(&name) {
+        return configured_user(&name);
+    }
+    if update.password.as_deref() == Some("") {
+        return (StatusCode::BAD_REQUEST, "Password must not be empty").into_response();
+    }
+    match users.store().update_user(&name, update) {
+        Ok(true) => {
+            info!("Updated user {name}");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, format!("Unknown user {name}")).into_response(),
+        Err(err) => user_store_error(err),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/admin/users/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "Name of a managed user")),
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 404, description = "Unknown user or user management is disabled"),
+        (status = 409, description = "User is configured and cannot be deleted"),
+    ),
+)]
+async fn delete_user(State(users): State<Arc<Users>>, Path(name): Path<String>) -> Response {
+    if !users.store().enabled() {
+        return user_store_disabled();
+    }
+    if users.is_configured(&name) {
+        return configured_user(&name);
+    }
+    match users.store().delete_user(&name) {
+        Ok(true) => {
+            info!("Deleted user {name}");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, format!("Unknown user {name}")).into_response(),
+        Err(err) => user_store_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/tokens",
+    tag = "admin",
+    responses((status = 200, body = Vec<TokenInfo>)),
+)]
+async fn admin_tokens(State(users): State<Arc<Users>>) -> impl IntoResponse {
+    Json(users.store().tokens())
+}
+
+/// Create a new API token with the name, an existing token with the same name stops working
+#[utoipa::path(
+    post,
+    path = "/api/admin/tokens/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "Name of the token")),
+    responses(
+        (status = 200, body = NewToken),
+        (status = 404, description = "User management is disabled"),
+    ),
+)]
+async fn rotate_token(State(users): State<Arc<Users>>, Path(name): Path<String>) -> Response {
+    if !users.store().enabled() {
+        return user_store_disabled();
+    }
+    match users.store().rotate_token(&name) {
+        Ok(token) => {
+            info!("Created API token {name}");
+            Json(NewToken { name, token }).into_response()
+        }
+        Err(err) => user_store_error(err),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/admin/tokens/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "Name of the token")),
+    responses(
+        (status = 204, description = "Token deleted"),
+        (status = 404, description = "Unknown token or user management is disabled"),
+    ),
+)]
+async fn delete_token(State(users): State<Arc<Users>>, Path(name): Path<String>) -> Response {
+    if !users.store().enabled() {
+        return user_store_disabled();
+    }
+    match users.store().delete_token(&name) {
+        Ok(true) => {
+            info!("Deleted API token {name}");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, format!("Unknown token {name}")).into_response(),
+        Err(err) => user_store_error(err),
+    }
+}
+
+fn user_store_disabled() -> Response {
+    (StatusCode::NOT_FOUND, "User management is disabled").into_response()
+}
+
+fn configured_user(name: &str) -> Response {
+    (
+        StatusCode::CONFLICT,
+        format!("User {name} is configured and cannot be changed at runtime"),
+    )
+        .into_response()
+}
+
+fn user_store_error(err: anyhow::Error) -> Response {
+    error!("Failed to update user store: {err:#}");
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")).into_response()
+}
+
+/// Run an update cycle immediately and wait for its result.
+/// Requests during a running cycle are combined into the next cycle.
+#[utoipa::path(
+    post,
+    path = "/api/refresh",
+    tag = "admin",
+    responses(
+        (status = 200, body = CycleStatus),
+        (status = 429, description = "Too many pending refresh requests"),
+        (status = 500, body = CycleStatus, description = "Update cycle failed"),
+    ),
+)]
+async fn refresh_reports(State(refresh): State<RefreshSender>) -> Response {
+    let (sender, receiver) = oneshot::channel();
+    if refresh.try_send(sender).is_err() {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many pending refresh requests",
+        )
+            .into_response();
+    }
+    match receiver.await {
+        Ok(status) if status.success => Json(status).into_response(),
+        Ok(status) => (StatusCode::INTERNAL_SERVER_ERROR, Json(status)).into_response(),
+        Err(..) => (StatusCode::SERVICE_UNAVAILABLE, "Background task stopped").into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/status",
+    tag = "status",
+    responses((status = 200, body = BackgroundStatus)),
+)]
+async fn status(State(state): State<Arc<SharedState>>) -> impl IntoResponse {
+    Json(state.status().clone())
+}
+
+/// Durations and counts of the last update cycles, oldest first
+#[utoipa::path(
+    get,
+    path = "/api/status/history",
+    tag = "status",
+    responses((status = 200, body = [CycleMetrics])),
+)]
+async fn status_history(State(state): State<Arc<SharedState>>) -> impl IntoResponse {
+    Json(state.cycle_history().list())
+}
+
+/// Push live updates to the client as server-sent events.
+/// The stream ends when the server shuts down to not delay the graceful shutdown.
+#[utoipa::path(
+    get,
+    path = "/api/events",
+    tag = "status",
+    responses((status = 200, content_type = "text/event-stream", body = String)),
+)]
+async fn events_stream(
+    State(events): State<Arc<Events>>,
+    State(shutdown): State<watch::Receiver<bool>>,
+) -> Sse<impl Stream<Item = Result<SseEvent, axum::Error>>> {
+    let stream = stream::unfold(
+        (events.subscribe(), shutdown),
+        |(mut receiver, mut shutdown)| async move {
+            loop {
+                let event = tokio::select! {
+                    event = receiver.recv() => event,
+                    _ = shutdown.wait_for(|stop| *stop) => return None,
+                };
+                match event {
+                    Ok(event) => {
+                        let sse_event = SseEvent::default().event(event.name()).json_data(&event);
+                        return Some((sse_event, (receiver, shutdown)));
+                    }
+                    Err(RecvError::Lagged(count)) => {
+                        warn!("Event stream client missed {count} events")
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        },
+    );
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/ratelimit/stats",
+    tag = "status",
+    responses((status = 200, body = RateLimitStats)),
+)]
+async fn ratelimit_stats(State(limiter): State<Arc<RateLimiter>>) -> impl IntoResponse {
+    Json(limiter.stats())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/dns/stats",
+    tag = "dns",
+    responses((status = 200, body = DnsCacheStats)),
+)]
+async fn dns_stats(State(dns): State<Arc<DnsResolver>>) -> impl IntoResponse {
+    Json(dns.stats())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/dns/ptr/{ip}",
+    tag = "dns",
+    params(("ip" = String, Path, description = "IPv4 or IPv6 address")),
+    responses(
+        (status = 200, body = Vec<String>),
+        (status = 502, description = "DNS lookup failed"),
+    ),
+)]
+async fn dns_ptr(State(dns): State<Arc<DnsResolver>>, Path(ip): Path<IpAddr>) -> Response {
+    dns_response(dns.ptr(ip).await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/dns/txt/{name}",
+    tag = "dns",
+    params(("name" = String, Path, description = "Domain name")),
+    responses(
+        (status = 200, body = Vec<String>),
+        (status = 502, description = "DNS lookup failed"),
+    ),
+)]
+async fn dns_txt(State(dns): State<Arc<DnsResolver>>, Path(name): Path<String>) -> Response {
+    dns_response(dns.txt(&name).await)
+}
+
+/// Window in days for the reports the advice is based on, 30 by default, and an optional domain
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AdviceParams {
+    days: Option<u64>,
+    domain: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/advice",
+    tag = "dns",
+    params(AdviceParams),
+    responses((status = 200, body = Vec<DomainAdvice>)),
+)]
+async fn advice(
+    State(state): State<Arc<SharedState>>,
+    State(dns): State<Arc<DnsResolver>>,
+    tenant: Option<Extension<TenantDomains>>,
+    Query(params): Query<AdviceParams>,
+) -> impl IntoResponse {
+    let days = params.days.unwrap_or(30).max(1);
+    let domains = advice::collect(&state.snapshot().reports, unix_time(), days * DAY);
+    let advice = domains
+        .into_iter()
+        .filter(|(domain, _)| {
+            params
+                .domain
+                .as_deref()
+                .is_none_or(|d| d.eq_ignore_ascii_case(domain))
+                && tenant.as_ref().is_none_or(|t| t.allows(domain))
+        })
+        .map(|(domain, data)| advice::domain_advice(&dns, domain, data, days));
+    Json(futures::future::join_all(advice).await)
+}
+
+/// Results of the enabled DNS based checks of a reported domain
+#[derive(Serialize, ToSchema)]
+struct DomainHealth {
+    domain: String,
+    mta_sts: Option<MtaStsCheck>,
+    bimi: Option<BimiCheck>,
+    rua: Option<RuaCheck>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/dns/health",
+    tag = "dns",
+    params(RecordFilter),
+    responses(
+        (status = 200, body = Vec<DomainHealth>),
+        (status = 404, description = "All DNS health checks are disabled"),
+    ),
+)]
+async fn dns_health(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    State(dns): State<Arc<DnsResolver>>,
+    filter: RecordFilter,
+) -> Response {
+    if !config.mta_sts_check && !config.bimi_check && !config.rua_check {
+        return (StatusCode::NOT_FOUND, "All DNS health checks are disabled").into_response();
+    }
+    let domains = policy_domains(&state, &filter);
+    let clients = match health_clients() {
+        Ok(clients) => clients,
+        Err(err) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")).into_response(),
+    };
+    let checks = domains
+        .into_iter()
+        .map(|domain| domain_health(&config, &dns, &clients, domain));
+    Json(futures::future::join_all(checks).await).into_response()
+}
+
+/// Policy domains of all reports matching the domain of the filter and the tenant
+fn policy_domains(state: &SharedState, filter: &RecordFilter) -> BTreeSet<String> {
+    state
+        .snapshot()
+        .reports
+        .iter()
+        .map(|r| r.policy_published.domain.to_lowercase())
+        .filter(|d| {
+            filter
+                .domain
+                .as_deref()
+                .is_none_or(|f| f.eq_ignore_ascii_case(d))
+                && filter.allows_domain(d)
+        })
+        .collect()
+}
+
+/// SPF records of the reported domains with their include and redirect chains
+/// and the number of DNS lookups per mechanism
+#[utoipa::path(
+    get,
+    path = "/api/dns/spf",
+    tag = "dns",
+    params(RecordFilter),
+    responses((status = 200, body = Vec<SpfNode>)),
+)]
+async fn dns_spf(
+    State(state): State<Arc<SharedState>>,
+    State(dns): State<Arc<DnsResolver>>,
+    filter: RecordFilter,
+) -> impl IntoResponse {
+    let domains = policy_domains(&state, &filter);
+    let checks = domains.iter().map(|domain| spf::check_domain(&dns, domain));
+    Json(futures::future::join_all(checks).await)
+}
+
+/// HTTP clients for the MTA-STS and BIMI checks
+fn health_clients() -> Result<(reqwest::Client, reqwest::Client)> {
+    Ok((mta_sts::client()?, bimi::client()?))
+}
+
+/// Runs the enabled DNS health checks for the domain
+async fn domain_health(
+    config: &Configuration,
+    dns: &DnsResolver,
+    (mta_sts_client, bimi_client): &(reqwest::Client, reqwest::Client),
+    domain: String,
+) -> DomainHealth {
+    let mut health = DomainHealth {
+        domain,
+        mta_sts: None,
+        bimi: None,
+        rua: None,
+    };
+    if config.mta_sts_check {
+        health.mta_sts = Some(mta_sts::check_domain(dns, mta_sts_client, &health.domain).await);
+    }
+    if config.bimi_check {
+        health.bimi = Some(bimi::check_domain(dns, bimi_client, &health.domain).await);
+    }
+    if config.rua_check {
+        let watched = config.report_addresses();
+        health.rua = Some(rua::check_domain(dns, &health.domain, &watched).await);
+    }
+    health
+}
+
+/// Published policy of the newest report of a domain
+#[derive(Serialize, ToSchema)]
+struct ObservedPolicy {
+    #[serde(flatten)]
+    policy: PolicyPublishedType,
+
+    /// Reporting organization of the newest report
+    org: String,
+
+    /// Begin of the date range of the newest report as Unix timestamp
+    seen: u64,
+}
+
+/// Messages of a source IP for a domain
+#[derive(Serialize, ToSchema)]
+struct DomainSource {
+    #[schema(value_type = String)]
+    ip: IpAddr,
+    messages: usize,
+    passed: usize,
+    failed: usize,
+}
+
+/// Everything about a single domain for the domain detail view
+#[derive(Serialize, ToSchema)]
+struct DomainDetail {
+    domain: String,
+
+    /// Current policy as observed by the reporters
+    policy: Option<ObservedPolicy>,
+
+    /// Changes of the published policy, oldest first
+    policy_history: Vec<PolicyEntry>,
+
+    /// DMARC record in DNS, empty if none is published or the lookup failed
+    dns_record: Option<String>,
+
+    /// Statistics of the matching records, empty without records
+    summary: Option<DomainSummary>,
+
+    /// Compliance within the compliance window compared to the window before
+    compliance: Option<ComplianceScore>,
+
+    /// Message volume and failures over time
+    timeline: Vec<Bucket>,
+
+    /// Source IPs with the most messages
+    top_sources: Vec<DomainSource>,
+
+    /// Results of the enabled DNS health checks, empty if all are disabled
+    health: Option<DomainHealth>,
+}
+
+/// Window in days for the compliance trend, size of the timeline buckets and number of top sources
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DomainDetailParams {
+    days: Option<u64>,
+    #[serde(default)]
+    interval: Interval,
+    #[serde(default = "default_offenders_limit")]
+    limit: usize,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/domains/{domain}",
+    tag = "statistics",
+    params(
+        ("domain" = String, Path, description = "Domain of the published policy"),
+        RecordFilter,
+        DomainDetailParams,
+    ),
+    responses(
+        (status = 200, body = DomainDetail),
+        (status = 404, description = "Unknown domain"),
+    ),
+)]
+async fn domain_detail(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    State(dns): State<Arc<DnsResolver>>,
+    mut filter: RecordFilter,
+    Query(params): Query<DomainDetailParams>,
+    Path(domain): Path<String>,
+) -> Response {
+    let domain = domain.to_lowercase();
+    filter.domain = Some(domain.clone());
+    let mut detail = {
+        let lock = state.snapshot();
+        let newest = lock
+            .reports
+            .iter()
+            .filter(|r| r.policy_published.domain.eq_ignore_ascii_case(&domain))
+            .max_by_key(|r| r.report_metadata.date_range.begin);
+        let Some(newest) = newest.filter(|_| filter.allows_domain(&domain)) else {
+            return (
+                StatusCode::NOT_FOUND,
+                [(header::CONTENT_TYPE, "text/plain")],
+                format!("No reports for domain {domain}"),
+            )
+                .into_response();
+        };
+        let days = params.days.unwrap_or(config.compliance_days).max(1);
+        let stats = domain_stats(&lock.reports, &filter).into_iter().next();
+        DomainDetail {
+            policy: Some(ObservedPolicy {
+                policy: newest.policy_published.clone(),
+                org: newest.report_metadata.org_name.clone(),
+                seen: newest.report_metadata.date_range.begin,
+            }),
+            policy_history: lock
+                .policy_history
+                .list(Some(&domain))
+                .into_values()
+                .next()
+                .unwrap_or_default(),
+            dns_record: None,
+            top_sources: stats
+                .iter()
+                .flat_map(|s| s.top_sources(params.limit))
+                .map(|(ip, source)| DomainSource {
+                    ip,
+                    messages: source.messages,
+                    passed: source.passed,
+                    failed: source.failed,
+                })
+                .collect(),
+            summary: stats.map(DomainSummary::from),
+            compliance: compliance_scores(&lock.reports, unix_time(), days * DAY)
+                .into_iter()
+                .find(|s| s.domain == domain),
+            timeline: time_series(&lock.reports, &filter, params.interval, config.timezone),
+            health: None,
+            domain,
+        }
+    };
+
+    // Pseudonymous domains must not be resolved, the results would belong to unrelated domains
+    if config.anonymize_key.is_none() {
+        detail.dns_record = match dns.txt(&format!("_dmarc.{}", detail.domain)).await {
+            Ok(records) => records
+                .into_iter()
+                .find(|r| r.trim_start().starts_with("v=DMARC1")),
+            Err(err) => {
+                debug!(
+                    "Failed to look up DMARC record of {}: {err:#}",
+                    detail.domain
+                );
+                None
+            }
+        };
+        if config.mta_sts_check || config.bimi_check || config.rua_check {
+            match health_clients() {
+                Ok(clients) => {
+                    let domain = detail.domain.clone();
+                    detail.health = Some(domain_health(&config, &dns, &clients, domain).await);
+                }
+                Err(err) => warn!("Failed to create clients for DNS health checks: {err:#}"),
+            }
+        }
+    }
+    Json(detail).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/rdap/{ip}",
+    tag = "dns",
+    params(("ip" = String, Path, description = "IPv4 or IPv6 address")),
+    responses(
+        (status = 200, body = RdapInfo),
+        (status = 404, description = "IP is not registered"),
+        (status = 502, description = "RDAP lookup failed"),
+    ),
+)]
+async fn rdap_lookup(State(rdap): State<Arc<Rdap>>, Path(ip): Path<IpAddr>) -> Response {
+    match rdap.lookup(ip).await {
+        Ok(Some(info)) => Json(info).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("No registration data for {ip}"),
+        )
+            .into_response(),
+        Err(err) => (
+            StatusCode::BAD_GATEWAY,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("{err:#}"),
+        )
+            .into_response(),
+    }
+}
+
+/// Everything known about a source IP for investigations: message volumes over time,
+/// header from domains, authentication results, reverse DNS and the network owner from RDAP
+#[utoipa::path(
+    get,
+    path = "/api/ips/{ip}",
+    tag = "dns",
+    params(
+        ("ip" = String, Path, description = "IPv4 or IPv6 address"),
+        RecordFilter,
+        TimeSeriesParams,
+    ),
+    responses(
+        (status = 200, body = IpDetail),
+        (status = 404, description = "IP does not appear in any report"),
+    ),
+)]
+async fn ip_lookup(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    State(dns): State<Arc<DnsResolver>>,
+    State(rdap): State<Arc<Rdap>>,
+    mut filter: RecordFilter,
+    Query(params): Query<TimeSeriesParams>,
+    Path(ip): Path<IpAddr>,
+) -> Response {
+    let detail = ip_detail(
+        &state.snapshot().reports,
+        &mut filter,
+        ip,
+        params.interval,
+        config.timezone,
+    );
+    let Some(mut detail) = detail else {
+        return (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("No reports with source IP {ip}"),
+        )
+            .into_response();
+    };
+    // Pseudonymous IPs must not be resolved, the results would belong to unrelated hosts
+    if config.anonymize_key.is_none() {
+        let (ptr, owner) = tokio::join!(dns.ptr(ip), rdap.lookup(ip));
+        detail.ptr = ptr.unwrap_or_else(|err| {
+            debug!("Failed to look up PTR of {ip}: {err:#}");
+            Vec::new()
+        });
+        detail.rdap = owner.unwrap_or_else(|err| {
+            debug!("Failed to look up RDAP of {ip}: {err:#}");
+            None
+        });
+    }
+    Json(detail).into_response()
+}
+
+fn dns_response(result: Result<Vec<String>>) -> Response {
+    match result {
+        Ok(values) => Json(values).into_response(),
+        Err(err) => (
+            StatusCode::BAD_GATEWAY,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("{err:#}"),
+        )
+            .into_response(),
+    }
+}
+
+/// Scheme of the listener that accepted the connection of a request
+#[derive(Clone, Copy)]
+struct ListenerScheme(&'static str);
+
+/// Scheme of the request as seen by the client,
+/// taken from the `X-Forwarded-Proto` header if the request was forwarded by a trusted reverse proxy
+fn request_scheme<'a>(
+    config: &Configuration,
+    peer: IpAddr,
+    listener: ListenerScheme,
+    headers: &'a HeaderMap,
+) -> &'a str {
+    if !config.http_trusted_proxies.contains(&peer.to_canonical()) {
+        return listener.0;
+    }
+    headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or(listener.0)
+}
+
+#[derive(Deserialize, ToSchema)]
+struct Login {
+    user: String,
+    password: String,
+}
+
+/// Start a session for the login form, the session cookie is set on success
+#[utoipa::path(
+    post,
+    path = "/api/login",
+    tag = "auth",
+    request_body = Login,
+    responses(
+        (status = 204, description = "Logged in, the response sets the session cookie"),
+        (status = 401, description = "Invalid credentials"),
+        (status = 404, description = "Session login is disabled"),
+        (status = 429, description = "Client is locked out after too many failed logins"),
+    ),
+)]
+#[allow(clippy::too_many_arguments)]
+async fn login(
+    State(users): State<Arc<Users>>,
+    State(sessions): State<Arc<Sessions>>,
+    State(limiter): State<Arc<RateLimiter>>,
+    State(config): State<Arc<Configuration>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(listener): Extension<ListenerScheme>,
+    headers: HeaderMap,
+    Json(login): Json<Login>,
+) -> Response {
+    if !config.http_session_login {
+        return (StatusCode::NOT_FOUND, "Session login is disabled").into_response();
+    }
+    let client = client_ip(&config, peer.ip(), &headers);
+    if let Some(remaining) = limiter.locked(client) {
+        let secs = remaining.as_secs() + 1;
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, secs.to_string())],
+        )
+            .into_response();
+    }
+    if users
+        .authenticate(&login.user, &login.password)
+        .await
+        .is_none()
+    {
+        warn!("Failed login of user {} from {client}", login.user);
+        limiter.login_failed(client);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    limiter.login_succeeded(client);
+    match sessions.create(&login.user) {
+        Ok(session) => {
+            info!("User {} logged in from {client}", login.user);
+            let cookie = set_cookie(
+                &session,
+                sessions.lifetime(),
+                &cookie_path(&config),
+                request_scheme(&config, peer.ip(), listener, &headers) == "https",
+            );
+            (StatusCode::NO_CONTENT, [(SET_COOKIE, cookie)]).into_response()
+        }
+        Err(err) => {
+            error!("Failed to create session: {err:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")).into_response()
+        }
+    }
+}
+
+/// End the current session and remove the session cookie
+#[utoipa::path(
+    post,
+    path = "/api/logout",
+    tag = "auth",
+    responses((status = 204, description = "Logged out, the response removes the session cookie")),
+)]
+async fn logout(
+    State(sessions): State<Arc<Sessions>>,
+    State(config): State<Arc<Configuration>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(listener): Extension<ListenerScheme>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(session) = session_cookie(&headers) {
+        sessions.revoke(session);
+    }
+    let cookie = set_cookie(
+        "",
+        0,
+        &cookie_path(&config),
+        request_scheme(&config, peer.ip(), listener, &headers) == "https",
+    );
+    (StatusCode::NO_CONTENT, [(SET_COOKIE, cookie)]).into_response()
+}
+
+/// User of the current session, only available when logged in with the login form
+#[utoipa::path(
+    get,
+    path = "/api/session",
+    tag = "auth",
+    responses(
+        (status = 200, body = SessionUser),
+        (status = 404, description = "Not logged in with a session cookie"),
+    ),
+)]
+async fn session(user: Option<Extension<SessionUser>>) -> Response {
+    match user {
+        Some(Extension(user)) => Json(user).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Sessions are valid for all paths below the base path
+fn cookie_path(config: &Configuration) -> String {
+    if config.http_base_path.is_empty() {
+        String::from("/")
+    } else {
+        config.http_base_path.clone()
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SyncParams {
+    /// Only reports with a date range ending at or after this Unix timestamp
+    #[serde(default)]
+    since: u64,
+}
+
+/// Reports to be pulled by other instances configured with `SYNC_PEERS`
+#[utoipa::path(
+    get,
+    path = "/api/sync",
+    tag = "reports",
+    params(SyncParams),
+    responses((status = 200, body = Vec<Report>)),
+)]
+async fn sync(
+    State(state): State<Arc<SharedState>>,
+    Query(params): Query<SyncParams>,
+) -> impl IntoResponse {
+    Json(sync_reports(&state.snapshot().reports, params.since))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/ingest",
+    tag = "reports",
+    security(()),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream", description = "Report file signed with the ingest secret"),
+    responses(
+        (status = 200, body = Object),
+        (status = 401, description = "Invalid signature"),
+        (status = 404, description = "Ingestion API is disabled"),
+        (status = 413, description = "Report file is too large"),
+        (status = 422, description = "Invalid report file"),
+    ),
+)]
+async fn ingest(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    State(events): State<Arc<Events>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let Some(secret) = &config.ingest_secret else {
+        return (StatusCode::NOT_FOUND, "Ingestion API is disabled").into_response();
+    };
+    let timestamp = headers
+        .get(TIMESTAMP_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let signature = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if let Err(err) = verify(secret, timestamp, signature, &body) {
+        warn!("Rejected ingestion request: {err:#}");
+        return (StatusCode::UNAUTHORIZED, format!("{err:#}")).into_response();
+    }
+    // Decompression and parsing of large files would block other requests on this worker
+    let result = spawn_blocking(move || {
+        let archive = config.archive_dir.as_deref().map(Archive::new);
+        ingest_file(
+            &state,
+            archive.as_ref(),
+            &body,
+            &ParseOptions::from(config.as_ref()),
+            &ExtractLimits::from(config.as_ref()),
+        )
+    })
+    .await
+    .context("Failed to join parser worker");
+    match result.and_then(|r| r) {
+        Ok(count) => {
+            info!("Ingested {count} reports from agent");
+            if count > 0 {
+                events.send(Event::NewReports { count });
+            }
+            Json(serde_json::json!({ "reports": count })).into_response()
+        }
+        Err(err) => {
+            warn!("Failed to ingest report file: {err:#}");
+            (StatusCode::UNPROCESSABLE_ENTITY, format!("{err:#}")).into_response()
+        }
+    }
+}
+
+/// Reports parsed by another collector, either a single report or a list of reports
+#[derive(Deserialize, ToSchema)]
+#[serde(untagged)]
+enum ReportImport {
+    Single(Box<Report>),
+    List(Vec<Report>),
+}
+
+/// Adds reports parsed by other collectors like parsedmarc.
+/// All reports are validated first and rejected together if any of them is invalid.
+#[utoipa::path(
+    post,
+    path = "/api/reports",
+    tag = "reports",
+    request_body(content = ReportImport, description = "Report or list of reports"),
+    responses(
+        (status = 200, body = Object),
+        (status = 422, description = "Invalid report"),
+    ),
+)]
+async fn import_reports(
+    State(state): State<Arc<SharedState>>,
+    State(events): State<Arc<Events>>,
+    Json(import): Json<ReportImport>,
+) -> Response {
+    let mut reports = match import {
+        ReportImport::Single(report) => vec![*report],
+        ReportImport::List(reports) => reports,
+    };
+    if let Some(err) = reports.iter().find_map(|r| r.validate().err()) {
+        warn!("Rejected imported reports: {err:#}");
+        return (StatusCode::UNPROCESSABLE_ENTITY, format!("{err:#}")).into_response();
+    }
+    // Fields filled after parsing only have a meaning for reports from this instance
+    for report in &mut reports {
+        report.mail_uid = None;
+        report.xml_hash = None;
+        report.attachment_name = None;
+    }
+    let submitted = reports.len();
+    match add_reports(&state, reports) {
+        Ok(count) => {
+            info!("Imported {count} of {submitted} submitted reports");
+            if count > 0 {
+                events.send(Event::NewReports { count });
+            }
+            Json(serde_json::json!({ "reports": count })).into_response()
+        }
+        Err(err) => {
+            warn!("Failed to import reports: {err:#}");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{err:#}")).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/annotations",
+    tag = "reports",
+    responses((status = 200, body = Annotations)),
+)]
+async fn annotations(State(state): State<Arc<SharedState>>) -> impl IntoResponse {
+    Json(state.snapshot().annotations.clone())
+}
+
+/// Imports annotations as CSV or JSON depending on the content type of the request
+#[utoipa::path(
+    post,
+    path = "/api/annotations",
+    tag = "reports",
+    request_body(content = String, content_type = "text/csv", description = "Annotations as CSV or JSON"),
+    responses((status = 200, body = Object), (status = 422, description = "Invalid annotations")),
+)]
+async fn import_annotations(
+    State(state): State<Arc<SharedState>>,
+    headers: HeaderMap,
+    body: String,
+) -> impl IntoResponse {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("json"));
+    let parsed = if is_json {
+        serde_json::from_str::<Annotations>(&body).context("Failed to parse JSON")
+    } else {
+        Annotations::from_csv(&body)
+    };
+    match parsed {
+        Ok(annotations) => {
+            let count = annotations.len();
+            state.update(|state| state.annotations.merge(annotations));
+            info!("Imported {count} annotations");
+            (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "text/plain")],
+                format!("Imported {count} annotations"),
+            )
+        }
+        Err(err) => (
+            StatusCode::BAD_REQUEST,
+            [(header::CONTENT_TYPE, "text/plain")],
+            format!("Failed to import annotations: {err:#}"),
+        ),
+    }
+}
+
+/// Name of the user authenticated by basic auth, session or proxy header
+#[derive(Clone)]
+struct UserName(String);
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct NoteQuery {
+    /// Only notes about this source IP
+    #[param(value_type = Option<String>)]
+    ip: Option<IpAddr>,
+
+    /// Only notes about the report with this stable ID
+    report: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/notes",
+    tag = "reports",
+    params(NoteQuery),
+    responses((status = 200, body = Vec<Note>)),
+)]
+async fn notes(
+    State(state): State<Arc<SharedState>>,
+    Query(query): Query<NoteQuery>,
+) -> impl IntoResponse {
+    Json(
+        state
+            .snapshot()
+            .notes
+            .list(query.ip, query.report.as_deref()),
+    )
+}
+
+/// Add a note about a source IP or report, allowed for read-only users too.
+/// Acknowledged failures are hidden from results with only failures and from alerts.
+#[utoipa::path(
+    post,
+    path = "/api/notes",
+    tag = "reports",
+    request_body = NewNote,
+    responses(
+        (status = 201, body = Note),
+        (status = 404, description = "Unknown report"),
+        (status = 409, description = "Read replicas cannot change notes"),
+    ),
+)]
+async fn create_note(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    user: Option<Extension<UserName>>,
+    Json(mut note): Json<NewNote>,
+) -> Response {
+    if config.read_replica {
+        return read_replica_maintenance();
+    }
+    if let NoteTarget::Report { id } = &mut note.target {
+        // Reports can also be given by the ID assigned by the reporter
+        match find_report(&state.snapshot().reports, id) {
+            Some(report) => *id = report.stable_id(),
+            None => return (StatusCode::NOT_FOUND, format!("Unknown report {id}")).into_response(),
+        }
+    }
+    let author = user
+        .map(|Extension(UserName(name))| name)
+        .unwrap_or_default();
+    let timestamp = unix_time();
+    let note = state.update(|locked_state| {
+        let note = Arc::make_mut(&mut locked_state.notes).add(note, &author, timestamp);
+        locked_state
+            .notes
+            .mark(Arc::make_mut(&mut locked_state.reports).as_mut_slice());
+        locked_state.revision += 1;
+        note
+    });
+    info!("Added note {} by {author}", note.id);
+    (StatusCode::CREATED, Json(note)).into_response()
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/notes/{id}",
+    tag = "reports",
+    params(("id" = u64, Path, description = "ID of the note")),
+    request_body = NoteUpdate,
+    responses(
+        (status = 200, body = Note),
+        (status = 404, description = "Unknown note"),
+        (status = 409, description = "Read replicas cannot change notes"),
+    ),
+)]
+async fn update_note(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Path(id): Path<u64>,
+    Json(update): Json<NoteUpdate>,
+) -> Response {
+    if config.read_replica {
+        return read_replica_maintenance();
+    }
+    let timestamp = unix_time();
+    let note = state.update(|locked_state| {
+        let note = Arc::make_mut(&mut locked_state.notes).update(id, update, timestamp)?;
+        locked_state
+            .notes
+            .mark(Arc::make_mut(&mut locked_state.reports).as_mut_slice());
+        locked_state.revision += 1;
+        Some(note)
+    });
+    match note {
+        Some(note) => Json(note).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Unknown note {id}")).into_response(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/notes/{id}",
+    tag = "reports",
+    params(("id" = u64, Path, description = "ID of the note")),
+    responses(
+        (status = 204, description = "Note deleted"),
+        (status = 404, description = "Unknown note"),
+        (status = 409, description = "Read replicas cannot change notes"),
+    ),
+)]
+async fn delete_note(
+    State(state): State<Arc<SharedState>>,
+    State(config): State<Arc<Configuration>>,
+    Path(id): Path<u64>,
+) -> Response {
+    if config.read_replica {
+        return read_replica_maintenance();
+    }
+    let removed = state.update(|locked_state| {
+        let removed = Arc::make_mut(&mut locked_state.notes).remove(id)?;
+        locked_state
+            .notes
+            .mark(Arc::make_mut(&mut locked_state.reports).as_mut_slice());
+        locked_state.revision += 1;
+        Some(removed)
+    });
+    match removed {
+        Some(..) => StatusCode::NO_CONTENT.into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Unknown note {id}")).into_response(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/allowlist",
+    tag = "reports",
+    responses((status = 200, body = Allowlist)),
+)]
+async fn allowlist(State(state): State<Arc<SharedState>>) -> impl IntoResponse {
+    Json(state.snapshot().allowlist.clone())
+}
+
+/// Replaces the expected senders of the domains in the request and flags all records again
+#[utoipa::path(
+    post,
+    path = "/api/allowlist",
+    tag = "reports",
+    request_body(content = Allowlist, description = "Expected senders per domain"),
+    responses((status = 200, body = String), (status = 400, description = "Invalid allowlist")),
+)]
+async fn import_allowlist(State(state): State<Arc<SharedState>>, body: String) -> Response {
+    let allowlist: Allowlist = match serde_json::from_str(&body) {
+        Ok(allowlist) => allowlist,
+        Err(err) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                [(header::CONTENT_TYPE, "text/plain")],
+                format!("Failed to parse allowlist: {err}"),
+            )
+                .into_response()
+        }
+    };
+    let count = allowlist.len();
+    state.update(|locked| {
+        locked.allowlist.merge(allowlist);
+        locked
+            .allowlist
+            .mark(Arc::make_mut(&mut locked.reports).as_mut_slice());
+        locked.summary.unexpected = locked
+            .reports
+            .iter()
+            .flat_map(|r| &r.record)
+            .filter(|r| r.unexpected)
+            .count();
+        locked.revision += 1;
+    });
+    info!("Imported allowlist for {count} domains");
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain")],
+        format!("Imported allowlist for {count} domains"),
+    )
+        .into_response()
 }
 
 const STATIC_FILES: &[StaticFile] = &[
@@ -329,6 +4015,11 @@ const STATIC_FILES: &[StaticFile] = &[
         file_path: "ui/index.html",
         _data: include_bytes!("../ui/index.html"),
     },
+    StaticFile {
+        http_path: "/login.html",
+        file_path: "ui/login.html",
+        _data: include_bytes!("../ui/login.html"),
+    },
     StaticFile {
         http_path: "/chart.js",
         file_path: "ui/chart.umd.4.4.2.min.js",
@@ -385,6 +4076,22 @@ const MIME_TYPES: &[MimeType] = &[
         ext: ".js",
         mime_type: "text/javascript",
     },
+    MimeType {
+        ext: ".css",
+        mime_type: "text/css",
+    },
+    MimeType {
+        ext: ".svg",
+        mime_type: "image/svg+xml",
+    },
+    MimeType {
+        ext: ".png",
+        mime_type: "image/png",
+    },
+    MimeType {
+        ext: ".ico",
+        mime_type: "image/x-icon",
+    },
 ];
 
 struct MimeType {
@@ -397,3 +4104,171 @@ struct StaticFile {
     file_path: &'static str,
     _data: &'static [u8],
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::{env, fs};
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    fn test_router(args: &[&str]) -> Router {
+        test_router_with_state(args, AppState::default())
+    }
+
+    fn test_router_with_state(args: &[&str], state: AppState) -> Router {
+        let config = Configuration::parse_from(["test", "--demo"].iter().chain(args));
+        let state = Arc::new(SharedState::new(state));
+        let dns = Arc::new(DnsResolver::new(&config));
+        let (refresh, _) = mpsc::channel(1);
+        let (_, shutdown) = watch::channel(false);
+        let http_state = HttpState {
+            app: state.clone(),
+            config: Arc::new(config.clone()),
+            graphql: graphql::schema(state, dns.clone()),
+            dns,
+            rdap: Arc::new(Rdap::new(&config).unwrap()),
+            users: Arc::new(Users::from_config(&config).unwrap()),
+            sessions: Arc::new(Sessions::new(&config).unwrap()),
+            limiter: Arc::new(RateLimiter::new(&config)),
+            settings: Arc::new(SharedSettings::new(&config, None).unwrap()),
+            refresh,
+            events: Arc::new(Events::new()),
+            shutdown,
+        };
+        router(&config, http_state).unwrap()
+    }
+
+    async fn status(router: &Router, path: &str, authorization: &str) -> StatusCode {
+        response(router, path, authorization).await.status()
+    }
+
+    async fn response(router: &Router, path: &str, authorization: &str) -> Response {
+        let mut request = Request::get(path)
+            .header(AUTHORIZATION, authorization)
+            .body(Body::empty())
+            .unwrap();
+        let peer = SocketAddr::from(([127, 0, 0, 1], 12345));
+        request.extensions_mut().insert(ConnectInfo(peer));
+        request.extensions_mut().insert(ListenerScheme("http"));
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    fn basic(user: &str, password: &str) -> String {
+        format!("Basic {}", STANDARD.encode(format!("{user}:{password}")))
+    }
+
+    #[tokio::test]
+    async fn admin_paths_need_admin_role() {
+        let path = env::temp_dir().join(format!("dmarc-http-users-{}", std::process::id()));
+        fs::write(&path, "viewer:read-only:secret\n").unwrap();
+        let users_file = format!("--http-users-file={}", path.display());
+        let router = test_router(&[
+            "--http-server-password=password",
+            "--http-api-tokens=token",
+            &users_file,
+        ]);
+        fs::remove_file(&path).unwrap();
+
+        let viewer = basic("viewer", "secret");
+        assert_eq!(
+            status(&router, "/api/summary/domains", &viewer).await,
+            StatusCode::OK
+        );
+        for path in [
+            "/api/admin/users",
+            "/api/admin/tokens",
+            "/api/ratelimit/stats",
+        ] {
+            assert_eq!(status(&router, path, &viewer).await, StatusCode::FORBIDDEN);
+            assert_eq!(
+                status(&router, path, "Bearer token").await,
+                StatusCode::FORBIDDEN
+            );
+        }
+        let admin = basic("dmarc", "password");
+        assert_eq!(
+            status(&router, "/api/admin/users", &admin).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn lock_out_guessed_tokens() {
+        let router = test_router(&[
+            "--http-server-password=password",
+            "--http-api-tokens=token",
+            "--http-login-max-failures=3",
+        ]);
+        let path = "/api/summary/domains";
+        assert_eq!(status(&router, path, "Bearer token").await, StatusCode::OK);
+        for _ in 0..3 {
+            assert_eq!(
+                status(&router, path, "Bearer guess").await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+        for authorization in ["Bearer guess", "Bearer token"] {
+            assert_eq!(
+                status(&router, path, authorization).await,
+                StatusCode::TOO_MANY_REQUESTS
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn hide_report_source_from_tenants() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let mut report = crate::parser::parse_xml_file(&xml).unwrap();
+        report.mail_uid = Some(42);
+        report.attachment_name = Some("acme.xml.gz".to_owned());
+        let state = AppState {
+            reports: Arc::new(vec![report]),
+            ..Default::default()
+        };
+        let id = std::process::id();
+        let users_path = env::temp_dir().join(format!("dmarc-tenant-users-{id}"));
+        fs::write(&users_path, "alice:read-only:secret\n").unwrap();
+        let tenants_path = env::temp_dir().join(format!("dmarc-tenants-{id}.json"));
+        fs::write(
+            &tenants_path,
+            r#"{"example": {"domains": ["example.com"], "users": ["alice"]}}"#,
+        )
+        .unwrap();
+        let router = test_router_with_state(
+            &[
+                "--http-server-password=password",
+                &format!("--http-users-file={}", users_path.display()),
+                &format!("--http-tenants-file={}", tenants_path.display()),
+            ],
+            state,
+        );
+        fs::remove_file(&users_path).unwrap();
+        fs::remove_file(&tenants_path).unwrap();
+
+        let path = "/reports/9391651994964116463";
+        let detail = |authorization: String| {
+            let router = router.clone();
+            async move {
+                let response = response(&router, path, &authorization).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+        let operator = detail(basic("dmarc", "password")).await;
+        assert_eq!(operator["source"]["attachment_name"], "acme.xml.gz");
+        assert_eq!(operator["mail_uid"], 42);
+        let tenant = detail(basic("alice", "secret")).await;
+        assert!(tenant.get("source").is_none());
+        assert!(tenant["mail_uid"].is_null());
+        assert!(tenant["attachment_name"].is_null());
+        assert_eq!(
+            tenant["report_metadata"]["report_id"],
+            "9391651994964116463"
+        );
+    }
+}
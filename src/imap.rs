@@ -1,77 +1,41 @@
 use crate::config::Configuration;
+use crate::connect::{connect, MailStream};
 use crate::mail::{decode_subject, Mail};
+use crate::source::select_new_mails;
 use anyhow::{Context, Result};
 use async_imap::imap_proto::Address;
 use async_imap::types::Fetch;
-use async_imap::Client;
+use async_imap::{Authenticator, Client, Session};
 use futures::StreamExt;
-use std::collections::HashMap;
-use std::net::TcpStream as StdTcpStream;
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::Duration;
-use tokio::net::TcpStream;
-use tokio_rustls::rustls::pki_types::ServerName;
-use tokio_rustls::rustls::{ClientConfig, RootCertStore};
-use tokio_rustls::TlsConnector;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
 use tracing::{debug, info, warn};
 
-pub async fn get_mails(config: &Configuration) -> Result<HashMap<u32, Mail>> {
-    // Prepare cert store with webpki roots
-    let mut root_cert_store = RootCertStore::empty();
-    let certs = webpki_roots::TLS_SERVER_ROOTS.iter().cloned();
-    root_cert_store.extend(certs);
-    debug!("Created Root CA cert store");
-
-    // Create async TLS connection
-    let client_config = ClientConfig::builder()
-        .with_root_certificates(root_cert_store)
-        .with_no_client_auth();
-    debug!("Created TLS client config");
-
-    let connector = TlsConnector::from(Arc::new(client_config));
-    debug!("Created TLS connector");
-
-    let host_port = format!("{}:{}", config.imap_host.as_str(), config.imap_port);
-    debug!("Parsing IMAP address {host_port} as socket address...");
-    let addrs = host_port
-        .to_socket_addrs()
-        .context("Failed to convert host name and port to socket address")?
-        .collect::<Vec<SocketAddr>>();
-    let addr = addrs.first().context("Unable get first resolved address")?;
-    debug!("Got address {addr}");
-
-    let timeout = Duration::from_secs(config.imap_timeout);
-    let std_tcp_stream =
-        StdTcpStream::connect_timeout(addr, timeout).context("Failed to connect to IMAP server")?;
-    debug!("Created TCP stream");
-
-    std_tcp_stream
-        .set_nonblocking(true)
-        .context("Failed to set TCP stream to non-blocking")?;
-    let tcp_stream = TcpStream::from_std(std_tcp_stream)
-        .context("Failed to create TCP stream to IMAP server")?;
-    debug!("Created async TCP stream");
-
-    let dns_name = ServerName::try_from(config.imap_host.clone())
-        .context("Failed to get DNS name from IMAP host")?;
-    debug!("Got DNS name: {dns_name:?}");
-
-    let tls_stream = connector
-        .connect(dns_name, tcp_stream)
-        .await
-        .context("Failed to create TLS stream with IMAP server")?;
-    debug!("Created TLS stream");
+type ImapSession = Session<Box<dyn MailStream>>;
 
-    let client = Client::new(tls_stream);
-    debug!("Created IMAP client");
+/// Session kept open between update cycles, taken out while in use
+static IDLE_SESSION: Mutex<Option<ImapSession>> = Mutex::const_new(None);
 
-    let mut session = client
-        .login(&config.imap_user, &config.imap_password)
-        .await
-        .map_err(|e| e.0)
-        .context("Failed to log in and create IMAP session")?;
-    debug!("IMAP login successful");
+/// Set once the task sending keepalives for the idle session runs
+static KEEPALIVE_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Get metadata of all mails in the inbox.
+/// Mails with UIDs not yet in the set of known UIDs are downloaded with body
+/// and sent in batches to the channel instead of being part of the result.
+/// The bounded channel keeps only a few batches of mail bodies in memory at the same time.
+/// The number of downloaded mails can be limited in the configuration,
+/// in that case the remaining new mails are left out of the result completely.
+pub async fn get_mails(
+    config: &Configuration,
+    known_uids: &HashSet<u32>,
+    batches: Sender<Vec<Mail>>,
+    pending: &AtomicUsize,
+) -> Result<HashMap<u32, Mail>> {
+    let mut session = open_session(config).await?;
 
     let mailbox = session
         .select("INBOX")
@@ -81,6 +45,7 @@ pub async fn get_mails(config: &Configuration) -> Result<HashMap<u32, Mail>> {
 
     // Get metadata for all all mails and filter by size
     let mut mails = HashMap::new();
+    let mut oversized = 0;
     let mut size_filtered_uids = Vec::new();
     debug!("Number of mails in INBOX: {}", mailbox.exists);
     if mailbox.exists > 0 {
@@ -97,34 +62,45 @@ pub async fn get_mails(config: &Configuration) -> Result<HashMap<u32, Mail>> {
             if mail.oversized {
                 // Add oversized mails without body to result list
                 mails.insert(mail.uid, mail);
+                oversized += 1;
+            } else if known_uids.contains(&mail.uid) {
+                // Mail was already processed in previous cycle, no body needed
+                mails.insert(mail.uid, mail);
             } else {
                 // Get mails with body in next step
-                size_filtered_uids.push(mail.uid.to_string());
+                size_filtered_uids.push(mail.uid);
             }
         }
-        if !mails.is_empty() {
+        if oversized > 0 {
             warn!(
-                "Found {} mails over size limit of {} bytes",
-                mails.len(),
+                "Found {oversized} mails over size limit of {} bytes",
                 config.max_mail_size
             )
         }
         info!("Downloaded metadata of {} mails", mailbox.exists)
     }
 
+    // Start with the oldest mails, or the newest ones during a backfill
+    size_filtered_uids.sort_unstable();
+    select_new_mails(config, &mut size_filtered_uids, pending);
+
     // Get full mails for all selected UIDs
     if !size_filtered_uids.is_empty() {
         let mut downloaded = 0;
 
-        // We need to get the mails in chunks.
-        // It will fail silently if the requested sequences become too big!
-        const CHUNK_SIZE: usize = 5000;
-        for chunk in size_filtered_uids.chunks(CHUNK_SIZE) {
-            let sequence: String = chunk.join(",");
+        // Small chunks limit the memory usage.
+        // Requests also fail silently if the sequences become too big!
+        for chunk in size_filtered_uids.chunks(config.imap_batch_size as usize) {
+            let sequence: String = chunk
+                .iter()
+                .map(|uid| uid.to_string())
+                .collect::<Vec<String>>()
+                .join(",");
             let mut stream = session
                 .uid_fetch(sequence, "(RFC822 RFC822.SIZE UID ENVELOPE INTERNALDATE)")
                 .await
                 .context("Failed to fetch message stream from IMAP inbox")?;
+            let mut batch = Vec::with_capacity(chunk.len());
             while let Some(fetch_result) = stream.next().await {
                 let fetched = fetch_result
                     .context("Failed to get next mail header from IMAP fetch response")?;
@@ -133,22 +109,174 @@ pub async fn get_mails(config: &Configuration) -> Result<HashMap<u32, Mail>> {
                 if let Some(body) = fetched.body() {
                     mail.body = Some(body.to_vec());
                     mail.size = body.len();
-                    mails.insert(mail.uid, mail);
+                    batch.push(mail);
                     downloaded += 1;
                 } else {
                     warn!("Mail with UID {} has no body!", mail.uid);
                 }
             }
+            debug!("Downloaded batch of {} mails", batch.len());
+            if batches.send(batch).await.is_err() {
+                // The cycle stops taking batches when its time budget is exceeded
+                info!("Processing stopped, the remaining mails follow in the next cycle");
+                break;
+            }
         }
         info!("Downloaded {downloaded} mails")
     }
 
+    release_session(config, session).await?;
+    Ok(mails)
+}
+
+/// Download the complete mail with the UID from the inbox.
+/// Returns nothing if the mail does not exist anymore.
+pub async fn get_mail_body(config: &Configuration, uid: u32) -> Result<Option<Vec<u8>>> {
+    let mut session = open_session(config).await?;
+    session
+        .select("INBOX")
+        .await
+        .context("Failed to select inbox")?;
+    let body = {
+        let mut stream = session
+            .uid_fetch(uid.to_string(), "(RFC822 UID)")
+            .await
+            .context("Failed to fetch mail from IMAP inbox")?;
+        let mut body = None;
+        while let Some(fetch_result) = stream.next().await {
+            let fetched = fetch_result.context("Failed to get mail from IMAP fetch response")?;
+            if fetched.uid == Some(uid) {
+                body = fetched.body().map(|b| b.to_vec());
+            }
+        }
+        body
+    };
+    release_session(config, session).await?;
+    Ok(body)
+}
+
+/// Log in to the IMAP server and select the inbox to validate the configuration.
+/// Returns the number of mails in the inbox.
+pub async fn check_inbox(config: &Configuration) -> Result<u32> {
+    let mut session = login(config).await?;
+    let mailbox = session
+        .select("INBOX")
+        .await
+        .context("Failed to select inbox")?;
     session
         .logout()
         .await
         .context("Failed to log off from IMAP server")?;
+    Ok(mailbox.exists)
+}
 
-    Ok(mails)
+/// Take the session kept open since the last use or log in again.
+/// Sessions closed by the server in the meantime are detected with a NOOP and replaced.
+async fn open_session(config: &Configuration) -> Result<ImapSession> {
+    let idle = IDLE_SESSION.lock().await.take();
+    if let Some(mut session) = idle.filter(|_| config.imap_keepalive > 0) {
+        let noop = timeout(Duration::from_secs(config.imap_timeout), session.noop()).await;
+        match noop {
+            Ok(Ok(())) => {
+                debug!("Reusing open IMAP session");
+                return Ok(session);
+            }
+            Ok(Err(err)) => debug!("IMAP session was closed, logging in again: {err:#}"),
+            Err(..) => debug!("IMAP session timed out, logging in again"),
+        }
+    }
+    login(config).await
+}
+
+/// Keep the session open for the next use, or log out if sessions are not kept open.
+/// Only one session is kept, additional ones from concurrent requests are logged out.
+async fn release_session(config: &Configuration, mut session: ImapSession) -> Result<()> {
+    if config.imap_keepalive > 0 {
+        let mut idle = IDLE_SESSION.lock().await;
+        if idle.is_none() {
+            *idle = Some(session);
+            if !KEEPALIVE_STARTED.swap(true, Ordering::Relaxed) {
+                tokio::spawn(keep_alive(
+                    Duration::from_secs(config.imap_keepalive),
+                    Duration::from_secs(config.imap_timeout),
+                ));
+            }
+            return Ok(());
+        }
+    }
+    session
+        .logout()
+        .await
+        .context("Failed to log off from IMAP server")?;
+    Ok(())
+}
+
+/// Send NOOP commands on the idle session, so the server does not close it between update cycles.
+/// A session failing to answer is dropped and the next use logs in again.
+async fn keep_alive(interval: Duration, command_timeout: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let mut idle = IDLE_SESSION.lock().await;
+        let Some(session) = idle.as_mut() else {
+            continue;
+        };
+        match timeout(command_timeout, session.noop()).await {
+            Ok(Ok(())) => debug!("Sent keepalive on idle IMAP session"),
+            Ok(Err(err)) => {
+                debug!("Dropping closed IMAP session: {err:#}");
+                *idle = None;
+            }
+            Err(..) => {
+                debug!("Dropping IMAP session not answering the keepalive");
+                *idle = None;
+            }
+        }
+    }
+}
+
+/// Log out from the session kept open between update cycles, if there is one
+pub async fn close_session() {
+    let idle = IDLE_SESSION.lock().await.take();
+    if let Some(mut session) = idle {
+        match session.logout().await {
+            Ok(()) => debug!("Logged out from idle IMAP session"),
+            Err(err) => warn!("Failed to log off from IMAP server: {err:#}"),
+        }
+    }
+}
+
+/// Connect to the IMAP server and log in
+async fn login(config: &Configuration) -> Result<Session<Box<dyn MailStream>>> {
+    let stream = connect(config, config.imap_port).await?;
+    let client = Client::new(stream);
+    debug!("Created IMAP client");
+
+    let session = if config.imap_password.is_empty() && config.imap_tls_client_cert.is_some() {
+        debug!("Authenticating with client certificate...");
+        client.authenticate("EXTERNAL", External).await
+    } else {
+        client.login(&config.imap_user, &config.imap_password).await
+    };
+    let session = session
+        .map_err(|e| e.0)
+        .context("Failed to log in and create IMAP session")?;
+    debug!("IMAP login successful");
+
+    Ok(session)
+}
+
+/// SASL EXTERNAL authentication with the identity of the TLS client certificate
+struct External;
+
+impl Authenticator for External {
+    type Response = Vec<u8>;
+
+    fn process(&mut self, _challenge: &[u8]) -> Self::Response {
+        // An empty authorization identity lets the server derive it from the certificate
+        Vec::new()
+    }
 }
 
 fn extract_metadata(mail: &Fetch, max_size: usize) -> Result<Mail> {
@@ -175,6 +303,7 @@ fn extract_metadata(mail: &Fetch, max_size: usize) -> Result<Mail> {
     );
     Ok(Mail {
         body: None,
+        duplicate_of: None,
         uid,
         sender,
         to,
@@ -182,6 +311,11 @@ fn extract_metadata(mail: &Fetch, max_size: usize) -> Result<Mail> {
         date,
         size,
         oversized: size > max_size,
+        attachments: 0,
+        message_id: env
+            .message_id
+            .as_deref()
+            .map(|id| String::from_utf8_lossy(id).into_owned()),
     })
 }
 
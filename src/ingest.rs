@@ -0,0 +1,176 @@
+use crate::archive::Archive;
+use crate::attachment::Attachment;
+use crate::duplicate::DuplicateReport;
+use crate::parser::{
+    compression_extension, extract_xml_from_file, hash_data, parse_report, ExtractLimits,
+    ParseOptions,
+};
+use crate::report::Report;
+use crate::state::SharedState;
+use anyhow::{bail, ensure, Context, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Header with the Unix timestamp used for signing the request
+pub const TIMESTAMP_HEADER: &str = "X-Ingest-Timestamp";
+
+/// Header with the hex encoded HMAC-SHA256 signature of the request
+pub const SIGNATURE_HEADER: &str = "X-Ingest-Signature";
+
+/// Maximum accepted difference between the signed timestamp and the local clock in seconds
+const MAX_CLOCK_SKEW: u64 = 300;
+
+fn mac(secret: &str, timestamp: u64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+pub fn unix_timestamp() -> Result<u64> {
+    Ok(SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .context("Failed to get Unix time stamp")?
+        .as_secs())
+}
+
+/// Create the signature of a report file forwarded by an agent
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    hex::encode(mac(secret, timestamp, body).finalize().into_bytes())
+}
+
+/// Check the signature and timestamp of a request to the ingestion API
+pub fn verify(secret: &str, timestamp: &str, signature: &str, body: &[u8]) -> Result<()> {
+    let timestamp: u64 = timestamp.parse().context("Invalid timestamp")?;
+    let now = unix_timestamp()?;
+    ensure!(
+        now.abs_diff(timestamp) <= MAX_CLOCK_SKEW,
+        "Timestamp is outside of the accepted time window"
+    );
+    let signature = hex::decode(signature).context("Invalid signature encoding")?;
+    mac(secret, timestamp, body)
+        .verify_slice(&signature)
+        .context("Invalid signature")
+}
+
+/// Parse a forwarded report file and add all contained reports to the shared state.
+/// The file is either rejected completely or all of its reports are added,
+/// except for duplicates of already known reports.
+/// Returns the number of added reports.
+pub fn ingest_file(
+    state: &Arc<SharedState>,
+    archive: Option<&Archive>,
+    data: &[u8],
+    options: &ParseOptions,
+    limits: &ExtractLimits,
+) -> Result<usize> {
+    let xml_files = extract_xml_from_file(data, limits)?;
+    if xml_files.is_empty() {
+        bail!("File did not include any XML file");
+    }
+    let hashes: Vec<String> = xml_files.iter().map(|xml| hash_data(xml)).collect();
+    if let Some(archive) = archive {
+        let attachments: Vec<Attachment> = compression_extension(data)
+            .map(|extension| Attachment {
+                data: data.to_vec(),
+                hash: hash_data(data),
+                extension,
+            })
+            .into_iter()
+            .collect();
+        archive.store_all(
+            hashes
+                .iter()
+                .zip(&xml_files)
+                .map(|(hash, xml)| (hash.as_str(), xml.as_slice())),
+            &attachments,
+        );
+    }
+    let reports = xml_files
+        .iter()
+        .zip(hashes)
+        .map(|(xml, hash)| {
+            let mut report = parse_report(xml, options)?;
+            report.xml_hash = Some(hash);
+            Ok(report)
+        })
+        .collect::<Result<Vec<Report>>>()?;
+    add_reports(state, reports)
+}
+
+/// Add reports to the shared state and record duplicates of already known reports.
+/// Returns the number of added reports.
+pub fn add_reports(state: &Arc<SharedState>, mut reports: Vec<Report>) -> Result<usize> {
+    let timestamp = unix_timestamp()?;
+    let count = state.update(|locked_state| {
+        locked_state.allowlist.mark(&mut reports);
+        locked_state.notes.mark(&mut reports);
+        let mut known: HashSet<(String, String)> = locked_state
+            .reports
+            .iter()
+            .map(|r| (r.key().0.to_owned(), r.key().1.to_owned()))
+            .collect();
+        let mut count = 0;
+        for report in reports {
+            locked_state.xml_files += 1;
+            let (org_name, report_id) = report.key();
+            if known.insert((org_name.to_owned(), report_id.to_owned())) {
+                Arc::make_mut(&mut locked_state.reports).push(report);
+                count += 1;
+            } else {
+                Arc::make_mut(&mut locked_state.duplicates).push(DuplicateReport {
+                    mail_uid: None,
+                    org_name: org_name.to_owned(),
+                    report_id: report_id.to_owned(),
+                });
+            }
+        }
+        locked_state.refresh_summary(timestamp);
+        count
+    });
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_roundtrip() {
+        let now = unix_timestamp().unwrap();
+        let signature = sign("secret", now, b"<feedback/>");
+        assert!(verify("secret", &now.to_string(), &signature, b"<feedback/>").is_ok());
+        assert!(verify("other", &now.to_string(), &signature, b"<feedback/>").is_err());
+        assert!(verify("secret", &now.to_string(), &signature, b"<changed/>").is_err());
+        let old = now - 2 * MAX_CLOCK_SKEW;
+        let signature = sign("secret", old, b"<feedback/>");
+        assert!(verify("secret", &old.to_string(), &signature, b"<feedback/>").is_err());
+    }
+
+    #[test]
+    fn add_validated_reports() {
+        let xml = std::fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = crate::parser::parse_xml_file(&xml).unwrap();
+        let json = serde_json::to_string(&report).unwrap();
+        let imported: Report = serde_json::from_str(&json).unwrap();
+        assert!(imported.validate().is_ok());
+        let mut invalid = imported.clone();
+        invalid.report_metadata.report_id = String::new();
+        assert!(invalid.validate().is_err());
+        let mut invalid = imported.clone();
+        invalid.report_metadata.date_range.end = u64::MAX;
+        assert!(invalid.validate().is_err());
+
+        let state = Arc::new(SharedState::new(Default::default()));
+        assert_eq!(add_reports(&state, vec![imported.clone()]).unwrap(), 1);
+        assert_eq!(add_reports(&state, vec![imported]).unwrap(), 0);
+        let state = state.original();
+        assert_eq!(state.reports.len(), 1);
+        assert_eq!(state.duplicates.len(), 1);
+    }
+}
@@ -0,0 +1,63 @@
+use crate::state::AppState;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use utoipa::ToSchema;
+
+/// Totals of the stored data for capacity planning
+#[derive(Serialize, ToSchema)]
+pub struct InstanceStats {
+    pub mails: usize,
+    pub reports: usize,
+    pub records: usize,
+    pub xml_errors: usize,
+    pub distinct_source_ips: usize,
+
+    /// Size of the state file on disk in bytes
+    pub state_file_size: Option<u64>,
+
+    /// Rough estimate of the memory used by the shared state in bytes,
+    /// based on the size of its serialized JSON representation
+    pub memory_estimate: usize,
+
+    /// Begin of the oldest report date range as Unix timestamp
+    pub oldest_report: Option<u64>,
+
+    /// End of the newest report date range as Unix timestamp
+    pub newest_report: Option<u64>,
+}
+
+impl InstanceStats {
+    pub fn new(state: &AppState, state_file: Option<&str>) -> Self {
+        let records = state.reports.iter().map(|r| r.record.len()).sum();
+        let distinct_source_ips = state
+            .reports
+            .iter()
+            .flat_map(|r| r.record.iter().map(|rec| rec.row.source_ip))
+            .collect::<HashSet<_>>()
+            .len();
+        let state_file_size = state_file
+            .and_then(|path| fs::metadata(path).ok())
+            .map(|m| m.len());
+        let memory_estimate = state.to_json().map(|json| json.len()).unwrap_or(0);
+        Self {
+            mails: state.mails.len(),
+            reports: state.reports.len(),
+            records,
+            xml_errors: state.xml_errors.len(),
+            distinct_source_ips,
+            state_file_size,
+            memory_estimate,
+            oldest_report: state
+                .reports
+                .iter()
+                .map(|r| r.report_metadata.date_range.begin)
+                .min(),
+            newest_report: state
+                .reports
+                .iter()
+                .map(|r| r.report_metadata.date_range.end)
+                .max(),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::filter::RecordFilter;
+use crate::rdap::RdapInfo;
+use crate::report::Report;
+use crate::timeseries::{time_series, Bucket, Interval, Timezone};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// Messages of a source IP for one header from domain
+#[derive(Serialize, Debug, ToSchema)]
+pub struct IpDomain {
+    pub domain: String,
+    pub messages: usize,
+    pub passed: usize,
+    pub failed: usize,
+}
+
+/// Messages of a source IP with the same authentication result for a domain
+#[derive(Serialize, Debug, ToSchema)]
+pub struct IpAuthResult {
+    pub domain: String,
+    pub result: String,
+    pub messages: usize,
+}
+
+/// Everything known about a source IP across all matching reports
+#[derive(Serialize, Debug, ToSchema)]
+pub struct IpDetail {
+    #[schema(value_type = String)]
+    pub ip: IpAddr,
+    pub reports: usize,
+    pub messages: usize,
+    pub passed: usize,
+    pub failed: usize,
+
+    /// Begin of the date range of the first report with the IP as Unix timestamp
+    pub first_seen: u64,
+
+    /// End of the date range of the last report with the IP as Unix timestamp
+    pub last_seen: u64,
+
+    /// Header from domains the IP sent as, most messages first
+    pub domains: Vec<IpDomain>,
+
+    /// Messages per reporting organization
+    pub reporters: BTreeMap<String, usize>,
+
+    /// Messages per applied disposition
+    pub dispositions: BTreeMap<String, usize>,
+
+    /// Messages per DKIM signature domain and result, most messages first
+    pub dkim: Vec<IpAuthResult>,
+
+    /// Messages per SPF domain and result, most messages first
+    pub spf: Vec<IpAuthResult>,
+
+    /// Message volume over time
+    pub timeline: Vec<Bucket>,
+
+    /// Host names from reverse DNS, empty if the lookup failed
+    pub ptr: Vec<String>,
+
+    /// Network and owner from RDAP, if available
+    pub rdap: Option<RdapInfo>,
+}
+
+/// Aggregates all records of the IP matching the filter.
+/// Returns nothing if the IP does not appear in any matching report.
+/// The DNS and RDAP enrichment is left empty for the caller.
+pub fn ip_detail(
+    reports: &[Report],
+    filter: &mut RecordFilter,
+    ip: IpAddr,
+    interval: Interval,
+    timezone: Timezone,
+) -> Option<IpDetail> {
+    let ip = ip.to_canonical();
+    filter.source_ip = Some(ip);
+    let mut report_ids = HashSet::new();
+    let mut detail = IpDetail {
+        ip,
+        reports: 0,
+        messages: 0,
+        passed: 0,
+        failed: 0,
+        first_seen: u64::MAX,
+        last_seen: 0,
+        domains: Vec::new(),
+        reporters: BTreeMap::new(),
+        dispositions: BTreeMap::new(),
+        dkim: Vec::new(),
+        spf: Vec::new(),
+        timeline: Vec::new(),
+        ptr: Vec::new(),
+        rdap: None,
+    };
+    let mut domains: HashMap<String, IpDomain> = HashMap::new();
+    let mut dkim: HashMap<(String, String), usize> = HashMap::new();
+    let mut spf: HashMap<(String, String), usize> = HashMap::new();
+    for (report, record) in filter.records(reports) {
+        let metadata = &report.report_metadata;
+        report_ids.insert(report.key());
+        detail.first_seen = detail.first_seen.min(metadata.date_range.begin);
+        detail.last_seen = detail.last_seen.max(metadata.date_range.end);
+
+        let count = record.row.count;
+        let pass = record.is_dmarc_pass();
+        detail.messages += count;
+        let header_from = record.identifiers.header_from.to_lowercase();
+        let domain = domains.entry(header_from.clone()).or_insert(IpDomain {
+            domain: header_from,
+            messages: 0,
+            passed: 0,
+            failed: 0,
+        });
+        domain.messages += count;
+        if pass {
+            detail.passed += count;
+            domain.passed += count;
+        } else {
+            detail.failed += count;
+            domain.failed += count;
+        }
+        *detail
+            .reporters
+            .entry(metadata.org_name.clone())
+            .or_default() += count;
+        let disposition = String::from(record.row.policy_evaluated.disposition.clone());
+        *detail.dispositions.entry(disposition).or_default() += count;
+
+        for signature in record.auth_results.dkim.iter().flatten() {
+            let key = (
+                signature.domain.to_lowercase(),
+                String::from(signature.result.clone()),
+            );
+            *dkim.entry(key).or_default() += count;
+        }
+        for check in &record.auth_results.spf {
+            let key = (
+                check.domain.to_lowercase(),
+                String::from(check.result.clone()),
+            );
+            *spf.entry(key).or_default() += count;
+        }
+    }
+    if report_ids.is_empty() {
+        return None;
+    }
+    detail.reports = report_ids.len();
+
+    let mut domains: Vec<IpDomain> = domains.into_values().collect();
+    domains.sort_by(|a, b| b.messages.cmp(&a.messages).then(a.domain.cmp(&b.domain)));
+    detail.domains = domains;
+    detail.dkim = auth_results(dkim);
+    detail.spf = auth_results(spf);
+    detail.timeline = time_series(reports, filter, interval, timezone);
+    Some(detail)
+}
+
+fn auth_results(counts: HashMap<(String, String), usize>) -> Vec<IpAuthResult> {
+    let mut results: Vec<IpAuthResult> = counts
+        .into_iter()
+        .map(|((domain, result), messages)| IpAuthResult {
+            domain,
+            result,
+            messages,
+        })
+        .collect();
+    results.sort_by(|a, b| {
+        b.messages
+            .cmp(&a.messages)
+            .then_with(|| (&a.domain, &a.result).cmp(&(&b.domain, &b.result)))
+    });
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn aggregate_source_ip() {
+        let reports: Vec<Report> = ["acme", "aol", "google"]
+            .iter()
+            .map(|name| {
+                let xml = fs::read(format!("testdata/dmarc-reports/{name}.xml")).unwrap();
+                parse_xml_file(&xml).unwrap()
+            })
+            .collect();
+        let ip: IpAddr = "72.150.241.94".parse().unwrap();
+        let mut filter = RecordFilter::default();
+        let detail = ip_detail(
+            &reports,
+            &mut filter,
+            ip,
+            Interval::Daily,
+            Timezone::default(),
+        )
+        .unwrap();
+        assert_eq!(detail.reports, 1);
+        assert_eq!(detail.messages, 2);
+        assert_eq!(detail.passed, 2);
+        assert_eq!(detail.domains[0].domain, "example.com");
+        assert_eq!(detail.reporters["acme.com"], 2);
+        assert_eq!(detail.dkim[0].result, "fail");
+        assert_eq!(detail.spf[0].result, "pass");
+        assert_eq!(detail.timeline.iter().map(|b| b.messages).sum::<usize>(), 2);
+
+        let unknown: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut filter = RecordFilter::default();
+        assert!(ip_detail(
+            &reports,
+            &mut filter,
+            unknown,
+            Interval::Daily,
+            Timezone::default()
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::allowlist::{AllowedSender, Allowlist};
+use crate::cidr::IpNetwork;
+use crate::report::{DkimResultType, Report, SpfResultType};
+use crate::timeseries::DAY;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// Source that only sent mails passing DMARC for a domain on enough days to be proposed as trusted sender
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct SenderSuggestion {
+    pub id: u64,
+    pub domain: String,
+
+    #[schema(value_type = String)]
+    pub source_ip: IpAddr,
+
+    /// Most common domain of the passing DKIM signatures and SPF checks, used as name of the sender
+    pub provider: String,
+
+    /// Number of days with mails from the source
+    pub days: usize,
+    pub messages: usize,
+
+    /// Begin of the first report with the source as Unix timestamp
+    pub first_seen: u64,
+
+    /// End of the last report with the source as Unix timestamp
+    pub last_seen: u64,
+}
+
+/// Everything seen from a source for a domain
+#[derive(Default)]
+struct Observation {
+    days: HashSet<u64>,
+    messages: usize,
+    failed: bool,
+    first_seen: u64,
+    last_seen: u64,
+    providers: HashMap<String, usize>,
+}
+
+/// Suggestions of trusted senders waiting for an admin, remembered across update cycles
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct TrustedLearning {
+    pending: BTreeMap<u64, SenderSuggestion>,
+
+    /// Sources per domain that are never proposed again
+    rejected: HashSet<(String, IpAddr)>,
+
+    /// IDs of accepted or rejected suggestions are not used again
+    last_id: u64,
+}
+
+impl TrustedLearning {
+    /// Propose all sources that are not on the allowlist of their domain
+    /// and only sent mails passing DMARC on at least `days` days.
+    /// Suggestions that no longer qualify are removed.
+    /// Returns the number of new suggestions.
+    pub fn update(&mut self, reports: &[Report], allowlist: &Allowlist, days: u64) -> usize {
+        let mut observations: HashMap<(String, IpAddr), Observation> = HashMap::new();
+        for report in reports {
+            let domain = report.policy_published.domain.to_lowercase();
+            let range = &report.report_metadata.date_range;
+            for record in &report.record {
+                let key = (domain.clone(), record.row.source_ip);
+                if self.rejected.contains(&key) || allowlist.is_listed(&domain, record) {
+                    continue;
+                }
+                let observation = observations.entry(key).or_default();
+                if observation.days.is_empty() || range.begin < observation.first_seen {
+                    observation.first_seen = range.begin;
+                }
+                observation.last_seen = observation.last_seen.max(range.end);
+                observation.days.insert(range.begin / DAY);
+                observation.messages += record.row.count;
+                observation.failed |= !record.is_dmarc_pass();
+                let auth = &record.auth_results;
+                let passed = auth
+                    .dkim
+                    .iter()
+                    .flatten()
+                    .filter(|r| r.result == DkimResultType::Pass)
+                    .map(|r| &r.domain)
+                    .chain(
+                        auth.spf
+                            .iter()
+                            .filter(|r| r.result == SpfResultType::Pass)
+                            .map(|r| &r.domain),
+                    );
+                for provider in passed {
+                    *observation
+                        .providers
+                        .entry(provider.to_lowercase())
+                        .or_default() += record.row.count;
+                }
+            }
+        }
+        observations.retain(|_, o| !o.failed && o.days.len() as u64 >= days);
+
+        let mut pending: HashMap<(String, IpAddr), SenderSuggestion> =
+            std::mem::take(&mut self.pending)
+                .into_values()
+                .map(|s| ((s.domain.clone(), s.source_ip), s))
+                .collect();
+        let mut added = 0;
+        for ((domain, source_ip), observation) in observations {
+            let provider = observation
+                .providers
+                .into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+                .map_or_else(|| source_ip.to_string(), |(provider, _)| provider);
+            let id = match pending.remove(&(domain.clone(), source_ip)) {
+                Some(suggestion) => suggestion.id,
+                None => {
+                    added += 1;
+                    self.last_id += 1;
+                    self.last_id
+                }
+            };
+            let suggestion = SenderSuggestion {
+                id,
+                domain,
+                source_ip,
+                provider,
+                days: observation.days.len(),
+                messages: observation.messages,
+                first_seen: observation.first_seen,
+                last_seen: observation.last_seen,
+            };
+            self.pending.insert(id, suggestion);
+        }
+        added
+    }
+
+    pub fn list(&self) -> Vec<SenderSuggestion> {
+        self.pending.values().cloned().collect()
+    }
+
+    /// Add the source to the allowlist of its domain, to the sender with the name of the provider if there is one.
+    /// Returns nothing if the ID is unknown.
+    pub fn accept(&mut self, id: u64, allowlist: &mut Allowlist) -> Option<SenderSuggestion> {
+        let suggestion = self.pending.remove(&id)?;
+        let network = IpNetwork::new(suggestion.source_ip, 128);
+        let senders = allowlist
+            .domains
+            .entry(suggestion.domain.clone())
+            .or_default();
+        match senders.iter_mut().find(|s| s.name == suggestion.provider) {
+            Some(sender) => sender.networks.push(network),
+            None => senders.push(AllowedSender {
+                name: suggestion.provider.clone(),
+                networks: vec![network],
+                domains: Vec::new(),
+            }),
+        }
+        Some(suggestion)
+    }
+
+    /// Returns nothing if the ID is unknown
+    pub fn reject(&mut self, id: u64) -> Option<SenderSuggestion> {
+        let suggestion = self.pending.remove(&id)?;
+        self.rejected
+            .insert((suggestion.domain.clone(), suggestion.source_ip));
+        Some(suggestion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn propose_consistent_sources() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let mut reports: Vec<Report> = (0..3)
+            .map(|day| {
+                let mut report = report.clone();
+                report.report_metadata.date_range.begin += day * DAY;
+                report
+            })
+            .collect();
+        let mut learning = TrustedLearning::default();
+        let mut allowlist = Allowlist::default();
+        assert_eq!(learning.update(&reports, &allowlist, 4), 0);
+        assert_eq!(learning.update(&reports, &allowlist, 3), 1);
+        assert_eq!(learning.update(&reports, &allowlist, 3), 0);
+        let suggestion = &learning.list()[0];
+        assert_eq!(suggestion.days, 3);
+        assert_eq!(suggestion.provider, "example.com");
+
+        // Accepted sources are on the allowlist and not proposed again
+        learning.accept(suggestion.id, &mut allowlist).unwrap();
+        allowlist.mark(&mut reports);
+        assert!(!reports[0].record[0].unexpected);
+        assert_eq!(learning.update(&reports, &allowlist, 3), 0);
+        assert!(learning.list().is_empty());
+    }
+}
@@ -0,0 +1,44 @@
+//! Parsing and analysis of DMARC aggregate reports, as used by the DMARC report viewer.
+//!
+//! The library contains everything that works without a mailbox or HTTP server:
+//! extracting XML files from archives and mails, parsing them as DMARC reports
+//! (including the fixups for broken files of known reporters),
+//! filtering records and aggregating them into summaries, time series and explanations.
+//!
+//! ```no_run
+//! use dmarc_report_viewer::{parse_report_bytes, ParseOptions, Summary};
+//!
+//! let data = std::fs::read("report.xml.gz")?;
+//! let reports = parse_report_bytes(&data, &ParseOptions::default())?;
+//! let summary = Summary::from_reports(&reports);
+//! println!("{} reports for {} domains", summary.reports, summary.domains.len());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+#![forbid(unsafe_code)]
+
+pub mod attachment;
+pub mod causes;
+pub mod cidr;
+pub mod compliance;
+pub mod explain;
+pub mod filter;
+pub mod parser;
+pub mod psl;
+pub mod quirks;
+pub(crate) mod repair;
+pub mod report;
+pub mod summary;
+pub(crate) mod tar;
+pub mod timeseries;
+pub mod xml_file;
+
+pub use causes::{failure_cause, FailureCause};
+pub use explain::{explain, RecordExplanation};
+pub use filter::RecordFilter;
+pub use parser::{
+    extract_xml_files, extract_xml_from_file, parse_report, parse_report_bytes, ExtractLimits,
+    ParseOptions,
+};
+pub use psl::organizational_domain;
+pub use report::Report;
+pub use summary::Summary;
@@ -0,0 +1,90 @@
+use crate::config::{Configuration, LogFormat, LogRotation};
+use anyhow::{Context, Result};
+use rolling_file::{BasicRollingFileAppender, RollingConditionBasic};
+use std::io::{self, Write};
+use std::sync::{Mutex, MutexGuard};
+use tracing::Subscriber;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::reload;
+use tracing_subscriber::{Layer, Registry};
+
+/// Handle to change the log level at runtime
+pub type LogLevelHandle = reload::Handle<LevelFilter, Registry>;
+
+/// Set up logging to stdout and optionally to rotating log files in the configured format
+pub fn init_logging(config: &Configuration) -> Result<LogLevelHandle> {
+    let file_layer = match &config.log_file {
+        Some(path) => {
+            let mut condition = RollingConditionBasic::new().max_size(config.log_file_max_size);
+            condition = match config.log_file_rotation {
+                LogRotation::Never => condition,
+                LogRotation::Hourly => condition.hourly(),
+                LogRotation::Daily => condition.daily(),
+            };
+            let appender =
+                BasicRollingFileAppender::new(path, condition, config.log_file_max_files)
+                    .with_context(|| format!("Failed to open log file {path}"))?;
+            Some(format_layer(
+                config.log_format,
+                LogFile(Mutex::new(appender)),
+            ))
+        }
+        None => None,
+    };
+    let (level_filter, handle) = reload::Layer::new(LevelFilter::from_level(config.log_level));
+    let subscriber = Registry::default()
+        .with(level_filter)
+        .with(format_layer(config.log_format, io::stdout))
+        .with(file_layer);
+    tracing::subscriber::set_global_default(subscriber)
+        .context("Failed to set up default tracing subscriber")?;
+    Ok(handle)
+}
+
+fn format_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_target(false)
+        .with_ansi(false);
+    match format {
+        LogFormat::Text => layer.compact().boxed(),
+        // Span fields like the cycle ID and mail UID become separate JSON fields
+        LogFormat::Json => layer
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    }
+}
+
+/// Shared log file that is flushed after every log line
+struct LogFile(Mutex<BasicRollingFileAppender>);
+
+struct LogFileWriter<'a>(MutexGuard<'a, BasicRollingFileAppender>);
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = LogFileWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogFileWriter(self.0.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+}
+
+impl Write for LogFileWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.0.flush()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
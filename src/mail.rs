@@ -1,7 +1,12 @@
+use crate::state::AppState;
 use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct Mail {
     pub uid: u32,
     pub size: usize,
@@ -10,11 +15,192 @@ pub struct Mail {
     pub subject: String,
     pub sender: String,
     pub to: String,
+
+    /// Number of compressed attachments with XML files
+    #[serde(default)]
+    pub attachments: usize,
+
+    /// Message-ID header to find the mail in other mail clients
+    #[serde(default)]
+    pub message_id: Option<String>,
+
     #[serde(skip)]
     pub body: Option<Vec<u8>>,
+
+    /// UID of the mail with the same Message-ID or content that was processed instead of this copy,
+    /// for example if the report was sent to multiple aliases of the inbox
+    #[serde(default)]
+    pub duplicate_of: Option<u32>,
+}
+
+impl Mail {
+    /// Identifies copies of the same mail by their Message-ID or the hash of the downloaded body
+    pub fn delivery_key(&self) -> Option<String> {
+        match (self.message_id.as_deref().map(str::trim), &self.body) {
+            (Some(message_id), _) if !message_id.is_empty() => Some(message_id.to_owned()),
+            (_, Some(body)) => Some(hex::encode(Sha256::digest(body))),
+            _ => None,
+        }
+    }
+}
+
+/// Mail and attachment a report or XML file was extracted from
+#[derive(Serialize, ToSchema)]
+pub struct Source<'a> {
+    /// UID of the mail in the IMAP inbox, empty for reports not delivered by mail
+    pub mail_uid: Option<u32>,
+    pub message_id: Option<&'a str>,
+    pub subject: Option<&'a str>,
+    pub sender: Option<&'a str>,
+
+    /// Date of the mail as Unix timestamp
+    pub date: Option<i64>,
+
+    /// File name of the attachment containing the XML file
+    pub attachment_name: Option<&'a str>,
+
+    /// SHA256 hash of the XML file, also used as name in the archive
+    pub xml_hash: Option<&'a str>,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(
+        mails: &'a HashMap<u32, Mail>,
+        mail_uid: Option<u32>,
+        attachment_name: Option<&'a str>,
+        xml_hash: Option<&'a str>,
+    ) -> Self {
+        let mail = mail_uid.and_then(|uid| mails.get(&uid));
+        Self {
+            mail_uid,
+            message_id: mail.and_then(|m| m.message_id.as_deref()),
+            subject: mail.map(|m| m.subject.as_str()),
+            sender: mail.map(|m| m.sender.as_str()),
+            date: mail.map(|m| m.date),
+            attachment_name,
+            xml_hash,
+        }
+    }
+}
+
+/// Outcome of extracting and parsing the attachments of a mail
+#[derive(Serialize, Clone, Copy, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseResult {
+    /// All XML files were parsed as reports or skipped as duplicates
+    Parsed,
+
+    /// At least one XML file could not be parsed
+    Failed,
+
+    /// No XML file was found in the mail
+    NoReports,
+
+    /// The mail exceeded the size limit and was not downloaded
+    Oversized,
+
+    /// Copy of another mail that was processed instead
+    Duplicate,
+
+    /// The XML files could not be extracted from the mail, see `/api/quarantine`
+    Quarantined,
+}
+
+/// Mail metadata with the results of the XML files found in it
+#[derive(Serialize, ToSchema)]
+pub struct MailEntry<'a> {
+    #[serde(flatten)]
+    pub mail: &'a Mail,
+    pub reports: usize,
+    pub duplicates: usize,
+    pub xml_errors: usize,
+    pub result: ParseResult,
+}
+
+/// Filters for the mail listing
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MailFilter {
+    /// Domain of the sender address, including subdomains
+    pub sender_domain: Option<String>,
+
+    /// Only mails with (or without) XML parsing errors
+    pub has_errors: Option<bool>,
+}
+
+impl MailFilter {
+    fn matches(&self, entry: &MailEntry) -> bool {
+        if let Some(domain) = &self.sender_domain {
+            let domain = domain.to_lowercase();
+            let matching = entry.mail.sender.split("; ").any(|address| {
+                let host = address
+                    .rsplit('@')
+                    .next()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                host == domain || host.ends_with(&format!(".{domain}"))
+            });
+            if !matching {
+                return false;
+            }
+        }
+        self.has_errors
+            .is_none_or(|has_errors| has_errors == (entry.xml_errors > 0))
+    }
+}
+
+/// Matching mails with the newest first
+pub fn mail_entries<'a>(state: &'a AppState, filter: &MailFilter) -> Vec<MailEntry<'a>> {
+    let mut counts: HashMap<u32, (usize, usize, usize)> = HashMap::new();
+    for uid in state.reports.iter().filter_map(|r| r.mail_uid) {
+        counts.entry(uid).or_default().0 += 1;
+    }
+    for uid in state.duplicates.iter().filter_map(|d| d.mail_uid) {
+        counts.entry(uid).or_default().1 += 1;
+    }
+    for error in state.xml_errors.iter() {
+        counts.entry(error.mail_uid).or_default().2 += 1;
+    }
+    let mut entries: Vec<MailEntry> = state
+        .mails
+        .values()
+        .map(|mail| {
+            let (reports, duplicates, xml_errors) =
+                counts.get(&mail.uid).copied().unwrap_or_default();
+            let result = if mail.oversized {
+                ParseResult::Oversized
+            } else if mail.duplicate_of.is_some() {
+                ParseResult::Duplicate
+            } else if state.quarantine.get(mail.uid).is_some() {
+                ParseResult::Quarantined
+            } else if xml_errors > 0 {
+                ParseResult::Failed
+            } else if reports + duplicates == 0 {
+                ParseResult::NoReports
+            } else {
+                ParseResult::Parsed
+            };
+            MailEntry {
+                mail,
+                reports,
+                duplicates,
+                xml_errors,
+                result,
+            }
+        })
+        .filter(|entry| filter.matches(entry))
+        .collect();
+    entries.sort_by_key(|e| Reverse((e.mail.date, e.mail.uid)));
+    entries
 }
 
 /// Basic decoder for MIME Encoded Words with UTF8 and Base64
+/// UID for mail sources without numeric UIDs like IMAP, derived from their string IDs
+pub fn hashed_uid(id: &str) -> u32 {
+    let hash = Sha256::digest(id.as_bytes());
+    u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]])
+}
+
 pub fn decode_subject(value: String) -> String {
     const PREFIX: &str = "=?utf-8?B?";
     const SUFFIX: &str = "=?=";
@@ -1,41 +1,155 @@
 #![forbid(unsafe_code)]
 
+use dmarc_report_viewer::{
+    attachment, causes, cidr, compliance, explain, filter, parser, psl, quirks, report, summary,
+    timeseries, xml_file,
+};
+
+mod advice;
+mod agent;
+mod allowlist;
+mod annotations;
+mod anonymize;
+mod archive;
 mod background;
+mod bimi;
+mod charts;
+mod chat;
+mod check;
+mod compare;
 mod config;
+mod connect;
+mod csv;
+mod cursor;
+mod demo;
+mod digest;
+mod directory;
+mod dns;
+mod domains;
+mod duplicate;
+mod enforcement;
+mod events;
+mod export;
+mod feed;
+mod forwarding;
+mod gmail;
+mod grafana;
+mod graph;
+mod graphql;
+mod grpc;
+mod headers;
 mod http;
 mod imap;
+mod ingest;
+mod instance;
+mod ip_detail;
+mod learning;
+mod logging;
 mod mail;
-mod parser;
-mod report;
+mod maintenance;
+mod mta_sts;
+mod notes;
+mod notifications;
+mod offenders;
+mod offline;
+mod openapi;
+mod overrides;
+mod password;
+mod pdf;
+mod policy;
+mod pop3;
+mod proxy;
+mod push;
+mod quarantine;
+mod ratelimit;
+mod rdap;
+mod reporters;
+mod retention;
+mod rollup;
+mod rua;
+mod s3;
+mod search;
+mod selectors;
+mod session;
+mod settings;
+mod siem;
+mod smtp;
+mod source;
+mod sources;
+mod spf;
 mod state;
-mod summary;
+mod status;
+mod storage;
+mod sync;
+mod systemd;
+mod tenants;
+mod thresholds;
+mod throttle;
+mod user_store;
+mod users;
+mod webhook;
+mod xlsx;
 mod xml_error;
-mod xml_file;
 
-use crate::background::start_bg_task;
-use crate::http::run_http_server;
-use crate::state::AppState;
+use crate::agent::run_agent;
+use crate::allowlist::Allowlist;
+use crate::anonymize::Anonymizer;
+use crate::background::{run_export, run_once, start_bg_task, BgChannels};
+use crate::check::{run_check, run_healthcheck};
+use crate::connect::install_crypto_provider;
+use crate::digest::{start_digest_task, start_export_task, start_pdf_report_task};
+use crate::dns::DnsResolver;
+use crate::events::Events;
+use crate::grpc::run_grpc_server;
+use crate::http::{run_http_server, HttpChannels};
+use crate::logging::init_logging;
+use crate::notifications::Notifier;
+use crate::offline::{run_ingest, run_parse};
+use crate::ratelimit::RateLimiter;
+use crate::s3::{s3_store, S3Archive, S3Source};
+use crate::settings::SharedSettings;
+use crate::state::{AppState, SharedState};
+use crate::storage::state_store;
+use crate::sync::start_sync_task;
+use crate::users::Users;
 use anyhow::{Context, Result};
-use config::Configuration;
-use std::sync::{Arc, Mutex};
+use config::{Command, Configuration};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal;
 use tokio::sync::mpsc::channel;
-use tracing::info;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+fn main() -> Result<()> {
+    install_crypto_provider();
 
-#[tokio::main]
-async fn main() -> Result<()> {
     // Create config from args and ENV variables.
     // Will exit early in case of error or help and version command.
+    // Secrets are written to the environment, so this happens before the runtime starts its threads.
     let config = Configuration::new();
 
-    // Set up basic logging to stdout
-    let subscriber = tracing_subscriber::fmt()
-        .compact()
-        .with_max_level(config.log_level)
-        .with_target(false)
-        .with_ansi(false)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)
-        .expect("Failed to set up default tracing subscriber");
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("Failed to start async runtime")?
+        .block_on(run(config))
+}
+
+async fn run(config: Configuration) -> Result<()> {
+    // Parse local files and check the health without logging to keep stdout clean
+    if let Some(Command::Parse(parse_config)) = &config.command {
+        return run_parse(parse_config);
+    }
+    if let Some(Command::Ingest(ingest_config)) = &config.command {
+        return run_ingest(ingest_config).await;
+    }
+    if let Some(Command::Healthcheck) = &config.command {
+        return run_healthcheck(&config).await;
+    }
+
+    // Set up logging to stdout and optional log files
+    let log_level = init_logging(&config).context("Failed to set up logging")?;
 
     // Log app name and version
     let version = env!("CARGO_PKG_VERSION");
@@ -46,30 +160,228 @@ async fn main() -> Result<()> {
     let git_hash = option_env!("GITHUB_SHA").unwrap_or("n/a");
     info!("Git-Hash: {git_hash}");
 
+    // Run forwarding agent instead of the full application
+    if let Some(Command::Agent(agent_config)) = &config.command {
+        agent_config.log();
+        return run_agent(agent_config).await;
+    }
+
     // Make configuration visible in logs
     config.log();
 
+    // Validate configuration and connectivity instead of running the app
+    if let Some(Command::CheckConfig) = &config.command {
+        return run_check(&config).await;
+    }
+
+    // Settings that can be reloaded at runtime
+    let settings =
+        Arc::new(SharedSettings::new(&config, Some(log_level)).context("Failed to load settings")?);
+
     // Prepare shared application state
-    let state = Arc::new(Mutex::new(AppState::default()));
+    let mut initial_state = if let Some(import_file) = &config.import_file {
+        let imported = AppState::load(import_file)
+            .context("Failed to import state")?
+            .imported();
+        info!(
+            "Imported {} reports from file {import_file}",
+            imported.reports.len()
+        );
+        imported
+    } else if let Some(store) = state_store(&config).filter(|_| !config.read_replica) {
+        // Restore the state of the last run to serve it until the first update cycle finished
+        match store.load().await {
+            Ok(Some(restored)) => {
+                info!(
+                    "Restored {} reports from {}",
+                    restored.reports.len(),
+                    store.name()
+                );
+                restored
+            }
+            Ok(None) => AppState::default(),
+            Err(err) => {
+                warn!("Failed to restore state from {}: {err:#}", store.name());
+                AppState::default()
+            }
+        }
+    } else {
+        AppState::default()
+    };
+    if let Some(path) = &config.allowlist_file {
+        initial_state.allowlist = Allowlist::load(path).context("Failed to load allowlist")?;
+        initial_state
+            .allowlist
+            .mark(Arc::make_mut(&mut initial_state.reports).as_mut_slice());
+        info!(
+            "Loaded allowlist for {} domains",
+            initial_state.allowlist.len()
+        );
+    }
+    let state = Arc::new(SharedState::with_anonymizer(
+        initial_state,
+        Anonymizer::new(&config),
+    ));
+
+    // Prepare notification channels
+    let notifier = Arc::new(Notifier::new(&config).context("Failed to set up notifications")?);
+
+    // Prepare object storage
+    let s3 = s3_store(&config).context("Failed to set up S3 object storage")?;
+    let s3_archive = S3Archive::new(&config, &s3).map(Arc::new);
+    let s3_source = S3Source::new(&config, &s3);
+
+    // Single update cycle without HTTP server
+    if config.once {
+        return run_once(&config, &state, &notifier, &settings).await;
+    }
+    if let Some(Command::Export(export_config)) = &config.command {
+        return run_export(&config, export_config, &state, &notifier, &settings).await;
+    }
+
+    // Handle SIGINT and SIGTERM to shut down all parts of the app
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("Received shutdown signal, stopping HTTP server...");
+        systemd::notify("STOPPING=1");
+        shutdown_sender.send_replace(true);
+    });
+
+    // Reload settings on SIGHUP
+    #[cfg(unix)]
+    {
+        let settings = settings.clone();
+        let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+            .context("Failed to install SIGHUP handler")?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("Received SIGHUP, reloading settings...");
+                if let Err(err) = settings.reload() {
+                    error!("Failed to reload settings: {err:#}");
+                }
+            }
+        });
+    }
 
     // Start background task
     let (stop_sender, stop_receiver) = channel(1);
-    let bg_handle = start_bg_task(config.clone(), state.clone(), stop_receiver);
+    let (refresh_sender, refresh_receiver) = channel(16);
+    let events = Arc::new(Events::new());
+    let bg_handle = start_bg_task(
+        config.clone(),
+        state.clone(),
+        notifier.clone(),
+        settings.clone(),
+        s3_archive,
+        s3_source,
+        BgChannels {
+            refresh: refresh_receiver,
+            stop: stop_receiver,
+            events: events.clone(),
+        },
+    );
+
+    // Start scheduled digests
+    start_digest_task(&config, state.clone(), notifier);
+    start_pdf_report_task(&config, state.clone());
+    start_export_task(&config, state.clone(), S3Archive::exports(&config, &s3));
+
+    // Start pulling reports from other instances
+    start_sync_task(
+        &config,
+        state.clone(),
+        events.clone(),
+        shutdown_receiver.clone(),
+    );
+
+    // Shared DNS resolver with cache
+    let dns = Arc::new(DnsResolver::new(&config));
+
+    // Users of the HTTP and gRPC servers, managed users can change at runtime
+    let users = Arc::new(Users::from_config(&config).context("Failed to load HTTP users")?);
+
+    // Failed logins of the HTTP and gRPC servers lock out the client on both
+    let limiter = Arc::new(RateLimiter::new(&config));
+
+    // Optional gRPC server next to the HTTP server
+    if let Some(port) = config.grpc_port {
+        let config = config.clone();
+        let state = state.clone();
+        let users = users.clone();
+        let limiter = limiter.clone();
+        let shutdown = shutdown_receiver.clone();
+        tokio::spawn(async move {
+            if let Err(err) = run_grpc_server(&config, port, state, users, limiter, shutdown).await
+            {
+                error!("Failed to run gRPC server: {err:#}");
+            }
+        });
+    }
 
     // Starting HTTP server
-    run_http_server(&config, state.clone())
-        .await
-        .context("Failed to start HTTP server")?;
+    run_http_server(
+        &config,
+        state.clone(),
+        dns,
+        users,
+        limiter,
+        settings,
+        HttpChannels {
+            refresh: refresh_sender,
+            events,
+            shutdown: shutdown_receiver,
+        },
+    )
+    .await
+    .context("Failed to start HTTP server")?;
 
-    // Shutdown rest of app after HTTP server stopped
+    // Shutdown rest of app after HTTP server stopped.
+    // A running update cycle is allowed to finish to log out from IMAP cleanly.
     info!("HTTP server stopped");
     info!("Shutting down background task...");
     stop_sender
         .send(())
         .await
         .expect("Failed to send background task shutdown signal");
-    bg_handle.await.expect("Failed to join background task");
+    let timeout = Duration::from_secs(config.shutdown_timeout);
+    let abort_handle = bg_handle.abort_handle();
+    match tokio::time::timeout(timeout, bg_handle).await {
+        Ok(result) => result.expect("Failed to join background task"),
+        Err(..) => {
+            warn!(
+                "Background task did not stop within {} seconds, cancelling running update cycle",
+                config.shutdown_timeout
+            );
+            abort_handle.abort();
+        }
+    }
     info!("Background task stopped, application shutdown completed!");
 
     Ok(())
 }
+
+/// Promise will be fulfilled when a shutdown signal is received
+async fn shutdown_signal() {
+    let ctrlc = async {
+        signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl + C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrlc => {},
+        _ = terminate => {},
+    }
+}
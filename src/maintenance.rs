@@ -0,0 +1,222 @@
+use crate::archive::{xml_path, Archive};
+use crate::duplicate::DuplicateReport;
+use crate::filter::RecordFilter;
+use crate::ingest::unix_timestamp;
+use crate::parser::{parse_report, ParseOptions};
+use crate::report::Report;
+use crate::state::{AppState, SharedState};
+use crate::xml_error::{XmlError, XmlErrorKind};
+use anyhow::{ensure, Result};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Result of parsing the archived XML files of all reports and parse errors again
+#[derive(Serialize, Default, Debug, ToSchema)]
+pub struct ReparseResult {
+    /// Reports replaced by the result of the current parser
+    pub reparsed: usize,
+
+    /// Former parse errors that are reports now
+    pub recovered: usize,
+
+    /// Files that still fail to parse, reports failing now are kept unchanged
+    pub failed: usize,
+
+    /// Reports without archived XML file, like imported reports or with disabled archive
+    pub missing: usize,
+}
+
+/// Remove all reports matching the domain, org and date range of the filter.
+/// At least one of them is required, so a missing parameter never removes all reports.
+/// Returns the number of removed reports.
+pub fn purge(state: &SharedState, filter: &RecordFilter) -> Result<usize> {
+    ensure!(
+        filter.domain.is_some()
+            || filter.org.is_some()
+            || filter.since.is_some()
+            || filter.until.is_some(),
+        "Purging requires a domain, org, since or until"
+    );
+    let timestamp = unix_timestamp()?;
+    Ok(state.update(|locked_state| {
+        let (purged, reports): (Vec<Report>, Vec<Report>) =
+            Arc::unwrap_or_clone(std::mem::take(&mut locked_state.reports))
+                .into_iter()
+                .partition(|r| filter.matches_report(r));
+        locked_state.reports = Arc::new(reports);
+        if !purged.is_empty() {
+            Arc::make_mut(&mut locked_state.rollups).purge(filter, &purged, &locked_state.reports);
+            finish_maintenance(locked_state, timestamp);
+        }
+        purged.len()
+    }))
+}
+
+/// Parse all reports and parse errors again, for example after a fix of the parser.
+/// The XML files come from the archive, parse errors fall back to their stored XML.
+/// Mails are not downloaded again and reports keep their mail, hash and attachment.
+pub fn reparse(
+    state: &SharedState,
+    archive: Option<&Archive>,
+    options: &ParseOptions,
+) -> Result<ReparseResult> {
+    let mut result = ReparseResult::default();
+    let original = state.original();
+    let read_archive = |hash: &str| -> Option<Vec<u8>> {
+        let archive = archive.filter(|_| !hash.is_empty())?;
+        archive.read(&xml_path(hash)).unwrap_or_else(|err| {
+            warn!("{err:#}");
+            None
+        })
+    };
+
+    // Parsing happens without holding the writer lock, results are applied by hash afterwards
+    let mut reports: HashMap<String, Report> = HashMap::new();
+    for report in original.reports.iter() {
+        let Some(data) = report.xml_hash.as_deref().and_then(read_archive) else {
+            result.missing += 1;
+            continue;
+        };
+        match parse_report(&data, options) {
+            Ok(mut reparsed) => {
+                reparsed.mail_uid = report.mail_uid;
+                reparsed.xml_hash = report.xml_hash.clone();
+                reparsed.attachment_name = report.attachment_name.clone();
+                reports.insert(reparsed.xml_hash.clone().unwrap_or_default(), reparsed);
+            }
+            Err(err) => {
+                warn!(
+                    "Keeping report {} that fails to parse now: {err:#}",
+                    report.report_metadata.report_id
+                );
+                result.failed += 1;
+            }
+        }
+    }
+    let mut recovered: HashMap<String, Report> = HashMap::new();
+    let mut errors: HashMap<String, (String, XmlErrorKind)> = HashMap::new();
+    for error in original.xml_errors.iter().filter(|e| !e.hash.is_empty()) {
+        let data = read_archive(&error.hash).unwrap_or_else(|| error.xml.clone().into_bytes());
+        match parse_report(&data, options) {
+            Ok(mut report) => {
+                report.mail_uid = Some(error.mail_uid);
+                report.xml_hash = Some(error.hash.clone());
+                report.attachment_name = error.attachment_name.clone();
+                recovered.insert(error.hash.clone(), report);
+            }
+            Err(err) => {
+                errors.insert(
+                    error.hash.clone(),
+                    (format!("{err:#}"), XmlErrorKind::classify(&data)),
+                );
+            }
+        }
+    }
+    drop(original);
+
+    let timestamp = unix_timestamp()?;
+    state.update(|locked_state| {
+        for report in Arc::make_mut(&mut locked_state.reports) {
+            let reparsed = report.xml_hash.as_ref().and_then(|h| reports.remove(h));
+            if let Some(reparsed) = reparsed {
+                *report = reparsed;
+                result.reparsed += 1;
+            }
+        }
+        let mut known: HashSet<(String, String)> = locked_state
+            .reports
+            .iter()
+            .map(|r| (r.key().0.to_owned(), r.key().1.to_owned()))
+            .collect();
+        let mut xml_errors: Vec<XmlError> = Vec::new();
+        for mut error in Arc::unwrap_or_clone(std::mem::take(&mut locked_state.xml_errors)) {
+            if let Some((message, kind)) = errors.remove(&error.hash) {
+                error.error = message;
+                error.kind = kind;
+                result.failed += 1;
+            }
+            let Some(report) = recovered.remove(&error.hash) else {
+                xml_errors.push(error);
+                continue;
+            };
+            result.recovered += 1;
+            let (org_name, report_id) = report.key();
+            if known.insert((org_name.to_owned(), report_id.to_owned())) {
+                Arc::make_mut(&mut locked_state.reports).push(report);
+            } else {
+                Arc::make_mut(&mut locked_state.duplicates).push(DuplicateReport {
+                    mail_uid: report.mail_uid,
+                    org_name: org_name.to_owned(),
+                    report_id: report_id.to_owned(),
+                });
+            }
+        }
+        locked_state.xml_errors = Arc::new(xml_errors);
+        let reports = Arc::make_mut(&mut locked_state.reports);
+        locked_state.allowlist.mark(reports);
+        locked_state.notes.mark(reports);
+        finish_maintenance(locked_state, timestamp);
+    });
+    Ok(result)
+}
+
+/// Update the counters and summary after a maintenance change of the reports.
+/// Update cycles running at the same time notice the change by the maintenance counter.
+fn finish_maintenance(state: &mut AppState, timestamp: u64) {
+    state.maintenance += 1;
+    state.xml_files = state.reports.len() + state.duplicates.len() + state.xml_errors.len();
+    state.refresh_summary(timestamp);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{hash_data, parse_xml_file};
+    use std::fs;
+
+    #[test]
+    fn purge_and_recover() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let error = XmlError {
+            mail_uid: 1,
+            error: String::from("Failed to parse"),
+            xml: String::from_utf8(xml.clone()).unwrap(),
+            kind: XmlErrorKind::Schema,
+            hash: hash_data(&xml),
+            attachment_name: None,
+            attachment_path: None,
+        };
+        let state = SharedState::new(AppState {
+            reports: Arc::new(vec![report.clone()]),
+            ..Default::default()
+        });
+
+        let filter = RecordFilter {
+            domain: Some(String::from("example.org")),
+            ..Default::default()
+        };
+        assert_eq!(purge(&state, &filter).unwrap(), 0);
+        assert!(purge(&state, &RecordFilter::default()).is_err());
+        let filter = RecordFilter {
+            domain: Some(report.policy_published.domain.clone()),
+            ..Default::default()
+        };
+        assert_eq!(purge(&state, &filter).unwrap(), 1);
+        assert!(state.original().reports.is_empty());
+        assert_eq!(state.original().maintenance, 1);
+
+        // Without archive the XML stored with the parse error is parsed again
+        state.update(|s| Arc::make_mut(&mut s.xml_errors).push(error));
+        let result = reparse(&state, None, &ParseOptions::new(false, &[])).unwrap();
+        assert_eq!(result.recovered, 1);
+        assert_eq!(result.failed, 0);
+        let state = state.original();
+        assert!(state.xml_errors.is_empty());
+        assert_eq!(state.reports[0].mail_uid, Some(1));
+        assert_eq!(state.xml_files, 1);
+    }
+}
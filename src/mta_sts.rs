@@ -0,0 +1,249 @@
+use crate::dns::DnsResolver;
+use anyhow::{bail, ensure, Context, Result};
+use reqwest::{header, redirect, Client, StatusCode};
+use serde::Serialize;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// Upper limit for `max_age` of a policy as specified in RFC 8461
+const MAX_AGE_LIMIT: u64 = 31_557_600;
+
+/// Policies with a shorter `max_age` in seconds are likely left over from testing
+const MIN_RECOMMENDED_MAX_AGE: u64 = 86_400;
+
+/// Timeout for fetching a policy file in seconds
+const FETCH_TIMEOUT: u64 = 10;
+
+/// MTA-STS policy file served at `https://mta-sts.<domain>/.well-known/mta-sts.txt`
+#[derive(Serialize, PartialEq, Debug, ToSchema)]
+pub struct MtaStsPolicy {
+    /// One of `enforce`, `testing` or `none`
+    pub mode: String,
+
+    /// Patterns of permitted MX hosts, a leading `*.` matches a single label
+    pub mx: Vec<String>,
+
+    /// Duration in seconds for caching the policy
+    pub max_age: u64,
+}
+
+/// Result of checking the MTA-STS and TLS-RPT setup of a domain
+#[derive(Serialize, ToSchema)]
+pub struct MtaStsCheck {
+    /// TXT record at `_mta-sts.<domain>`, empty if MTA-STS is not deployed
+    pub record: Option<String>,
+
+    /// Policy file, empty if it could not be fetched or parsed
+    pub policy: Option<MtaStsPolicy>,
+
+    /// MX hosts of the domain, ordered by preference
+    pub mx: Vec<String>,
+
+    /// TXT record at `_smtp._tls.<domain>` with the addresses for TLS reports
+    pub tls_rpt: Option<String>,
+
+    /// Problems found in the records, the policy or its MX patterns
+    pub problems: Vec<String>,
+}
+
+/// Client for fetching policy files, redirects are not allowed by RFC 8461
+pub fn client() -> Result<Client> {
+    Client::builder()
+        .timeout(Duration::from_secs(FETCH_TIMEOUT))
+        .redirect(redirect::Policy::none())
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+/// Check the MTA-STS record, the policy file and the TLS-RPT record of the domain
+/// and validate the MX patterns of the policy against the published MX hosts.
+/// Domains without MTA-STS record only get their TLS-RPT record checked.
+pub async fn check_domain(dns: &DnsResolver, client: &Client, domain: &str) -> MtaStsCheck {
+    let mut check = MtaStsCheck {
+        record: None,
+        policy: None,
+        mx: Vec::new(),
+        tls_rpt: None,
+        problems: Vec::new(),
+    };
+
+    match versioned_record(dns, &format!("_smtp._tls.{domain}"), "v=TLSRPTv1").await {
+        Ok(record) => check.tls_rpt = record,
+        Err(err) => check.problems.push(format!("TLS-RPT: {err:#}")),
+    }
+
+    match versioned_record(dns, &format!("_mta-sts.{domain}"), "v=STSv1").await {
+        Ok(Some(record)) => {
+            if !record
+                .split(';')
+                .any(|tag| tag.trim().strip_prefix("id=").is_some_and(valid_id))
+            {
+                check
+                    .problems
+                    .push("MTA-STS record has no valid id".to_owned());
+            }
+            check.record = Some(record);
+        }
+        Ok(None) => return check,
+        Err(err) => {
+            check.problems.push(format!("MTA-STS: {err:#}"));
+            return check;
+        }
+    }
+    if check.tls_rpt.is_none() {
+        check
+            .problems
+            .push("No TLS-RPT record, failed TLS connections are not reported".to_owned());
+    }
+
+    match dns.mx(domain).await {
+        Ok(mx) => check.mx = mx,
+        Err(err) => check.problems.push(format!("{err:#}")),
+    }
+    let policy = match fetch_policy(client, domain).await {
+        Ok(policy) => policy,
+        Err(err) => {
+            check.problems.push(format!("{err:#}"));
+            return check;
+        }
+    };
+    if policy.mode != "none" {
+        for host in &check.mx {
+            if !policy.mx.iter().any(|pattern| mx_matches(pattern, host)) {
+                check
+                    .problems
+                    .push(format!("MX host {host} is not permitted by the policy"));
+            }
+        }
+    }
+    if policy.max_age < MIN_RECOMMENDED_MAX_AGE {
+        check.problems.push(format!(
+            "Policy max_age of {} seconds is less than a day",
+            policy.max_age
+        ));
+    }
+    check.policy = Some(policy);
+    check
+}
+
+/// The only TXT record of the name starting with the version tag.
+/// Returns nothing if there is no such record and fails for multiple records.
+async fn versioned_record(dns: &DnsResolver, name: &str, version: &str) -> Result<Option<String>> {
+    let mut records: Vec<String> = dns
+        .txt(name)
+        .await?
+        .into_iter()
+        .filter(|r| r.split(';').next().is_some_and(|v| v.trim() == version))
+        .collect();
+    ensure!(records.len() <= 1, "Multiple records at {name}");
+    Ok(records.pop())
+}
+
+/// Policy IDs are 1 to 32 alphanumeric characters
+fn valid_id(id: &str) -> bool {
+    (1..=32).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+async fn fetch_policy(client: &Client, domain: &str) -> Result<MtaStsPolicy> {
+    let url = format!("https://mta-sts.{domain}/.well-known/mta-sts.txt");
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch policy from {url}"))?;
+    if response.status() != StatusCode::OK {
+        bail!(
+            "Failed to fetch policy from {url}: HTTP status {}",
+            response.status()
+        );
+    }
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    ensure!(
+        content_type.starts_with("text/plain"),
+        "Policy is served with content type {content_type:?} instead of text/plain"
+    );
+    let text = response.text().await.context("Failed to read policy")?;
+    parse_policy(&text)
+}
+
+fn parse_policy(text: &str) -> Result<MtaStsPolicy> {
+    let mut version = None;
+    let mut mode = None;
+    let mut max_age = None;
+    let mut mx = Vec::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "version" => version = Some(value),
+            "mode" => mode = Some(value),
+            "max_age" => max_age = Some(value),
+            "mx" => mx.push(value.to_lowercase()),
+            _ => {}
+        }
+    }
+    ensure!(version == Some("STSv1"), "Policy has no version STSv1");
+    let mode = match mode {
+        Some(mode @ ("enforce" | "testing" | "none")) => mode.to_owned(),
+        Some(mode) => bail!("Policy has invalid mode {mode}"),
+        None => bail!("Policy has no mode"),
+    };
+    let max_age: u64 = max_age
+        .context("Policy has no max_age")?
+        .parse()
+        .context("Policy has invalid max_age")?;
+    ensure!(
+        max_age <= MAX_AGE_LIMIT,
+        "Policy max_age exceeds the limit of {MAX_AGE_LIMIT} seconds"
+    );
+    ensure!(
+        mode == "none" || !mx.is_empty(),
+        "Policy in mode {mode} has no mx entries"
+    );
+    Ok(MtaStsPolicy { mode, mx, max_age })
+}
+
+/// Match an MX host name against a pattern of the policy
+fn mx_matches(pattern: &str, host: &str) -> bool {
+    let host = host.to_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+        None => host == pattern,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_file() {
+        let policy = parse_policy(
+            "version: STSv1\r\nmode: enforce\r\nmx: mail.example.com\r\nmx: *.Example.net\r\nmax_age: 604800\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            policy,
+            MtaStsPolicy {
+                mode: "enforce".to_owned(),
+                mx: vec!["mail.example.com".to_owned(), "*.example.net".to_owned()],
+                max_age: 604800,
+            }
+        );
+        assert!(mx_matches(&policy.mx[0], "MAIL.example.com"));
+        assert!(mx_matches(&policy.mx[1], "mx1.example.net"));
+        assert!(!mx_matches(&policy.mx[1], "a.mx1.example.net"));
+        assert!(!mx_matches(&policy.mx[1], "example.net"));
+        assert!(parse_policy("version: STSv1\nmode: enforce\nmax_age: 86400\n").is_err());
+        assert!(parse_policy("version: STSv1\nmode: strict\nmx: a\nmax_age: 86400\n").is_err());
+    }
+}
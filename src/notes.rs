@@ -0,0 +1,198 @@
+use crate::report::{RecordType, Report};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// What a note is about
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NoteTarget {
+    /// Records of a source IP, for all domains or only one of them
+    SourceIp {
+        #[schema(value_type = String)]
+        ip: IpAddr,
+        domain: Option<String>,
+    },
+
+    /// All records of a report by its stable ID
+    Report { id: String },
+}
+
+impl NoteTarget {
+    fn matches(&self, report_id: &str, domain: &str, record: &RecordType) -> bool {
+        match self {
+            Self::SourceIp { ip, domain: only } => {
+                record.row.source_ip == *ip
+                    && only.as_ref().is_none_or(|d| d.eq_ignore_ascii_case(domain))
+            }
+            Self::Report { id } => id == report_id,
+        }
+    }
+}
+
+/// Comment of a user about a source IP or report, optionally acknowledging its failures
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct Note {
+    pub id: u64,
+    pub target: NoteTarget,
+
+    /// Free text like "This is our newsletter provider" or "Investigated, benign"
+    pub text: String,
+
+    /// Failures of the target are known and hidden from failure views and alerts
+    pub acknowledged: bool,
+
+    /// User who created the note, empty without authentication
+    pub author: String,
+
+    /// Time of the creation as Unix timestamp
+    pub created: u64,
+
+    /// Time of the last change as Unix timestamp
+    pub updated: u64,
+}
+
+/// New note sent by a user
+#[derive(Deserialize, ToSchema)]
+pub struct NewNote {
+    pub target: NoteTarget,
+    pub text: String,
+    #[serde(default)]
+    pub acknowledged: bool,
+}
+
+/// Changes of an existing note, missing fields are kept
+#[derive(Deserialize, ToSchema)]
+pub struct NoteUpdate {
+    pub text: Option<String>,
+    pub acknowledged: Option<bool>,
+}
+
+/// Notes of all users by their ID, persisted with the state
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Notes {
+    notes: BTreeMap<u64, Note>,
+
+    /// IDs of removed notes are not used again
+    last_id: u64,
+}
+
+impl Notes {
+    pub fn add(&mut self, note: NewNote, author: &str, timestamp: u64) -> Note {
+        self.last_id += 1;
+        let id = self.last_id;
+        let target = match note.target {
+            NoteTarget::SourceIp { ip, domain } => NoteTarget::SourceIp {
+                ip,
+                domain: domain.map(|d| d.to_lowercase()),
+            },
+            target => target,
+        };
+        let note = Note {
+            id,
+            target,
+            text: note.text,
+            acknowledged: note.acknowledged,
+            author: author.to_owned(),
+            created: timestamp,
+            updated: timestamp,
+        };
+        self.notes.insert(id, note.clone());
+        note
+    }
+
+    /// Returns the changed note or nothing if the ID is unknown
+    pub fn update(&mut self, id: u64, update: NoteUpdate, timestamp: u64) -> Option<Note> {
+        let note = self.notes.get_mut(&id)?;
+        if let Some(text) = update.text {
+            note.text = text;
+        }
+        if let Some(acknowledged) = update.acknowledged {
+            note.acknowledged = acknowledged;
+        }
+        note.updated = timestamp;
+        Some(note.clone())
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Note> {
+        self.notes.remove(&id)
+    }
+
+    /// All notes, or only the notes of a source IP or report
+    pub fn list(&self, ip: Option<IpAddr>, report: Option<&str>) -> Vec<Note> {
+        self.notes
+            .values()
+            .filter(|n| match &n.target {
+                NoteTarget::SourceIp { ip: note_ip, .. } => {
+                    report.is_none() && ip.is_none_or(|ip| ip == *note_ip)
+                }
+                NoteTarget::Report { id } => ip.is_none() && report.is_none_or(|r| r == id),
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Sets the acknowledged flag of all records of the reports
+    pub fn mark(&self, reports: &mut [Report]) {
+        let acknowledged: Vec<&NoteTarget> = self
+            .notes
+            .values()
+            .filter(|n| n.acknowledged)
+            .map(|n| &n.target)
+            .collect();
+        for report in reports {
+            let id = if acknowledged.is_empty() {
+                String::new()
+            } else {
+                report.stable_id()
+            };
+            let domain = &report.policy_published.domain;
+            for record in &mut report.record {
+                record.acknowledged = acknowledged.iter().any(|t| t.matches(&id, domain, record));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn acknowledge_failures() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let mut reports = vec![parse_xml_file(&xml).unwrap()];
+        let ip = reports[0].record[0].row.source_ip;
+        let mut notes = Notes::default();
+        let note = NewNote {
+            target: NoteTarget::SourceIp {
+                ip,
+                domain: Some(String::from("Example.com")),
+            },
+            text: String::from("Our newsletter provider"),
+            acknowledged: false,
+        };
+        let id = notes.add(note, "admin", 100).id;
+        notes.mark(&mut reports);
+        assert!(!reports[0].record[0].acknowledged);
+
+        let update = NoteUpdate {
+            text: None,
+            acknowledged: Some(true),
+        };
+        let note = notes.update(id, update, 200).unwrap();
+        assert_eq!(note.text, "Our newsletter provider");
+        assert_eq!(note.updated, 200);
+        notes.mark(&mut reports);
+        assert!(reports[0].record[0].acknowledged);
+        assert_eq!(notes.list(Some(ip), None).len(), 1);
+        assert!(notes.list(None, Some("unknown")).is_empty());
+
+        notes.remove(id);
+        notes.mark(&mut reports);
+        assert!(!reports[0].record[0].acknowledged);
+    }
+}
@@ -0,0 +1,654 @@
+use crate::chat::{DiscordSender, MatrixSender, SlackSender};
+use crate::compliance::ComplianceScore;
+use crate::config::Configuration;
+use crate::domains::domain_stats;
+use crate::export::value_string;
+use crate::filter::RecordFilter;
+use crate::policy::PolicyChange;
+use crate::push::{GotifySender, NtfySender};
+use crate::report::Report;
+use crate::reporters::DeliveryGap;
+use crate::smtp::SmtpSender;
+use crate::sources::NewSource;
+use crate::state::SharedState;
+use crate::status::{unix_time, TaskPanic};
+use crate::throttle::Throttle;
+use crate::webhook::WebhookSender;
+use crate::xml_error::XmlError;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tera::Tera;
+use tracing::{error, info};
+
+/// Embedded default templates, can be replaced with files in the template directory
+const DEFAULT_TEMPLATES: &[(&str, &str)] = &[
+    (
+        "email/failure_alert_subject.txt",
+        include_str!("../templates/email/failure_alert_subject.txt"),
+    ),
+    (
+        "email/failure_alert_body.txt",
+        include_str!("../templates/email/failure_alert_body.txt"),
+    ),
+    (
+        "email/digest_body.html",
+        include_str!("../templates/email/digest_body.html"),
+    ),
+    (
+        "email/digest_subject.txt",
+        include_str!("../templates/email/digest_subject.txt"),
+    ),
+    (
+        "email/digest_body.txt",
+        include_str!("../templates/email/digest_body.txt"),
+    ),
+    (
+        "email/policy_change_subject.txt",
+        include_str!("../templates/email/policy_change_subject.txt"),
+    ),
+    (
+        "email/policy_change_body.txt",
+        include_str!("../templates/email/policy_change_body.txt"),
+    ),
+    (
+        "email/parse_errors_subject.txt",
+        include_str!("../templates/email/parse_errors_subject.txt"),
+    ),
+    (
+        "email/parse_errors_body.txt",
+        include_str!("../templates/email/parse_errors_body.txt"),
+    ),
+    (
+        "email/new_sources_subject.txt",
+        include_str!("../templates/email/new_sources_subject.txt"),
+    ),
+    (
+        "email/new_sources_body.txt",
+        include_str!("../templates/email/new_sources_body.txt"),
+    ),
+    (
+        "email/compliance_drop_subject.txt",
+        include_str!("../templates/email/compliance_drop_subject.txt"),
+    ),
+    (
+        "email/compliance_drop_body.txt",
+        include_str!("../templates/email/compliance_drop_body.txt"),
+    ),
+    (
+        "email/reporter_gap_subject.txt",
+        include_str!("../templates/email/reporter_gap_subject.txt"),
+    ),
+    (
+        "email/reporter_gap_body.txt",
+        include_str!("../templates/email/reporter_gap_body.txt"),
+    ),
+    (
+        "email/task_panic_subject.txt",
+        include_str!("../templates/email/task_panic_subject.txt"),
+    ),
+    (
+        "email/task_panic_body.txt",
+        include_str!("../templates/email/task_panic_body.txt"),
+    ),
+    (
+        "chat/failure_alert_message.txt",
+        include_str!("../templates/chat/failure_alert_message.txt"),
+    ),
+    (
+        "chat/digest_message.txt",
+        include_str!("../templates/chat/digest_message.txt"),
+    ),
+    (
+        "chat/policy_change_message.txt",
+        include_str!("../templates/chat/policy_change_message.txt"),
+    ),
+    (
+        "chat/parse_errors_message.txt",
+        include_str!("../templates/chat/parse_errors_message.txt"),
+    ),
+    (
+        "chat/new_sources_message.txt",
+        include_str!("../templates/chat/new_sources_message.txt"),
+    ),
+    (
+        "chat/compliance_drop_message.txt",
+        include_str!("../templates/chat/compliance_drop_message.txt"),
+    ),
+    (
+        "chat/reporter_gap_message.txt",
+        include_str!("../templates/chat/reporter_gap_message.txt"),
+    ),
+    (
+        "chat/task_panic_message.txt",
+        include_str!("../templates/chat/task_panic_message.txt"),
+    ),
+];
+
+/// Required plain text templates per alert kind as channel and part
+const TEMPLATE_PARTS: &[(&str, &str)] =
+    &[("email", "subject"), ("email", "body"), ("chat", "message")];
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// New records with failed DKIM or SPF checks or applied policy
+    FailureAlert,
+    /// Periodic summary of DMARC activity
+    Digest,
+    /// Reporters observed a different published policy
+    PolicyChange,
+    /// New XML files that could not be parsed as DMARC reports
+    ParseErrors,
+    /// Source IPs sending mails for a domain for the first time
+    NewSources,
+    /// Compliance score of domains dropped below the target
+    ComplianceDrop,
+    /// Reporters that used to send daily reports went silent
+    ReporterGap,
+    /// The background task panicked while processing a mail or update cycle
+    TaskPanic,
+}
+
+impl AlertKind {
+    pub const ALL: [AlertKind; 8] = [
+        AlertKind::FailureAlert,
+        AlertKind::Digest,
+        AlertKind::PolicyChange,
+        AlertKind::ParseErrors,
+        AlertKind::NewSources,
+        AlertKind::ComplianceDrop,
+        AlertKind::ReporterGap,
+        AlertKind::TaskPanic,
+    ];
+
+    /// Name used as prefix for the template files
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertKind::FailureAlert => "failure_alert",
+            AlertKind::Digest => "digest",
+            AlertKind::PolicyChange => "policy_change",
+            AlertKind::ParseErrors => "parse_errors",
+            AlertKind::NewSources => "new_sources",
+            AlertKind::ComplianceDrop => "compliance_drop",
+            AlertKind::ReporterGap => "reporter_gap",
+            AlertKind::TaskPanic => "task_panic",
+        }
+    }
+}
+
+/// Notification with data that is made available to the templates.
+/// Serialized as JSON payload for webhooks.
+#[derive(Serialize, Clone)]
+pub struct Alert {
+    pub kind: AlertKind,
+    #[serde(flatten)]
+    pub data: Value,
+
+    /// Summary and cycle status for the templates, not part of the default webhook payload
+    #[serde(skip)]
+    pub context: Value,
+}
+
+/// Record as made available to the notification templates
+#[derive(Serialize)]
+pub struct AlertRecord {
+    pub domain: String,
+    pub org: String,
+    pub source_ip: String,
+    pub count: usize,
+    pub disposition: String,
+    pub dkim: String,
+    pub spf: String,
+}
+
+impl Alert {
+    /// Creates an alert for all failing records in the given reports.
+    /// Returns nothing if the failing records contain less messages than the threshold.
+    pub fn failures(reports: &[Report], threshold: usize) -> Option<Self> {
+        let records: Vec<AlertRecord> = failing_records(reports);
+        let total_count: usize = records.iter().map(|r| r.count).sum();
+        if records.is_empty() || total_count < threshold {
+            return None;
+        }
+        let data = serde_json::json!({
+            "records": records,
+            "total_count": total_count,
+        });
+        Some(Self {
+            kind: AlertKind::FailureAlert,
+            data,
+            context: Value::Null,
+        })
+    }
+
+    /// Creates an alert for XML files that failed to parse.
+    /// Returns nothing if there are no errors.
+    pub fn parse_errors(errors: &[XmlError]) -> Option<Self> {
+        if errors.is_empty() {
+            return None;
+        }
+        let errors: Vec<Value> = errors
+            .iter()
+            .map(|e| serde_json::json!({ "mail_uid": e.mail_uid, "error": e.error }))
+            .collect();
+        Some(Self {
+            kind: AlertKind::ParseErrors,
+            data: serde_json::json!({ "errors": errors }),
+            context: Value::Null,
+        })
+    }
+
+    /// Creates an alert for source IPs seen for the first time.
+    /// Returns nothing if there are no new sources.
+    pub fn new_sources(sources: &[NewSource]) -> Option<Self> {
+        if sources.is_empty() {
+            return None;
+        }
+        Some(Self {
+            kind: AlertKind::NewSources,
+            data: serde_json::json!({ "sources": sources }),
+            context: Value::Null,
+        })
+    }
+
+    /// Creates an alert for changed published policies.
+    /// Returns nothing if there are no changes.
+    pub fn policy_changes(changes: &[PolicyChange]) -> Option<Self> {
+        if changes.is_empty() {
+            return None;
+        }
+        Some(Self {
+            kind: AlertKind::PolicyChange,
+            data: serde_json::json!({ "changes": changes }),
+            context: Value::Null,
+        })
+    }
+
+    /// Creates an alert for domains with a compliance score below the target.
+    /// Returns nothing if there are no such domains.
+    pub fn compliance_drops(scores: &[ComplianceScore], target: f64) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+        let domains: Vec<Value> = scores
+            .iter()
+            .map(|s| {
+                serde_json::json!({
+                    "domain": s.domain,
+                    "messages": s.messages,
+                    "passed": s.passed,
+                    "compliance": format!("{:.1}", s.compliance),
+                })
+            })
+            .collect();
+        Some(Self {
+            kind: AlertKind::ComplianceDrop,
+            data: serde_json::json!({ "domains": domains, "target": target }),
+            context: Value::Null,
+        })
+    }
+
+    /// Creates an alert for reporters that stopped sending their daily reports.
+    /// Returns nothing if there are no new gaps.
+    pub fn reporter_gaps(gaps: &[DeliveryGap]) -> Option<Self> {
+        if gaps.is_empty() {
+            return None;
+        }
+        Some(Self {
+            kind: AlertKind::ReporterGap,
+            data: serde_json::json!({ "gaps": gaps }),
+            context: Value::Null,
+        })
+    }
+
+    /// Creates an alert for panics caught in the background task.
+    /// Returns nothing if there are no panics.
+    pub fn panics(panics: &[TaskPanic]) -> Option<Self> {
+        if panics.is_empty() {
+            return None;
+        }
+        Some(Self {
+            kind: AlertKind::TaskPanic,
+            data: serde_json::json!({ "panics": panics }),
+            context: Value::Null,
+        })
+    }
+
+    /// Creates a digest of all reports with a date range ending in the given period
+    pub fn digest(reports: &[Report], since: u64, until: u64) -> Self {
+        let reports: Vec<Report> = reports
+            .iter()
+            .filter(|r| {
+                let end = r.report_metadata.date_range.end;
+                end > since && end <= until
+            })
+            .cloned()
+            .collect();
+        let domains: Vec<DigestDomain> = domain_stats(&reports, &RecordFilter::default())
+            .into_iter()
+            .map(|d| DigestDomain {
+                pass_rate: format!("{:.1}", d.pass_rate()),
+                domain: d.domain,
+                passed: d.passed,
+                total: d.messages,
+            })
+            .collect();
+        let mut failures = failing_records(&reports);
+        failures.sort_by_key(|r| Reverse(r.count));
+        failures.truncate(DIGEST_FAILURES);
+        let data = serde_json::json!({
+            "reports": reports.len(),
+            "mails": domains.iter().map(|d| d.total).sum::<usize>(),
+            "domains": domains,
+            "failures": failures,
+            "since": since,
+            "until": until,
+        });
+        Self {
+            kind: AlertKind::Digest,
+            data,
+            context: Value::Null,
+        }
+    }
+}
+
+impl Alert {
+    /// Make the summary, the status of the running cycle and the metrics of the last finished cycle
+    /// available to the templates as `summary`, `cycle` and `last_cycle`
+    pub fn with_state(mut self, state: &SharedState) -> Self {
+        let cycle = state.status().clone();
+        self.context = serde_json::json!({
+            "summary": state.snapshot().summary,
+            "cycle": cycle,
+            "last_cycle": state.cycle_history().last(),
+        });
+        self
+    }
+}
+
+/// Maximum number of notable failures listed in a digest
+const DIGEST_FAILURES: usize = 10;
+
+/// Domain pass rate as made available to the digest templates
+#[derive(Serialize)]
+pub struct DigestDomain {
+    pub domain: String,
+    pub passed: usize,
+    pub total: usize,
+    pub pass_rate: String,
+}
+
+/// Collect all failing records of the reports in the template format
+pub fn failing_records(reports: &[Report]) -> Vec<AlertRecord> {
+    reports
+        .iter()
+        .flat_map(|report| {
+            report
+                .record
+                .iter()
+                .filter(|record| record.is_failure() && !record.acknowledged)
+                .map(|record| AlertRecord {
+                    domain: report.policy_published.domain.clone(),
+                    org: report.report_metadata.org_name.clone(),
+                    source_ip: record.row.source_ip.to_string(),
+                    count: record.row.count,
+                    disposition: value_string(&record.row.policy_evaluated.disposition),
+                    dkim: value_string(&record.row.policy_evaluated.dkim),
+                    spf: value_string(&record.row.policy_evaluated.spf),
+                })
+        })
+        .collect()
+}
+
+/// Renders the alert messages from embedded or custom templates
+pub struct Templates {
+    tera: Tera,
+}
+
+impl Templates {
+    pub fn new(override_dir: Option<&str>) -> Result<Self> {
+        let mut tera = Tera::default();
+        let custom_template = |name: &str| -> Result<Option<String>> {
+            let Some(path) = override_dir
+                .map(|dir| Path::new(dir).join(name))
+                .filter(|path| path.is_file())
+            else {
+                return Ok(None);
+            };
+            info!("Using custom template {}", path.display());
+            fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read template {}", path.display()))
+                .map(Some)
+        };
+        for (name, default) in DEFAULT_TEMPLATES {
+            let template = custom_template(name)?.unwrap_or_else(|| default.to_string());
+            tera.add_raw_template(name, &template)
+                .with_context(|| format!("Failed to parse template {name}"))?;
+        }
+        // Webhook payloads have no embedded default, the alert is sent as JSON without template
+        for kind in AlertKind::ALL {
+            let name = format!("webhook/{}_payload.json", kind.name());
+            if let Some(template) = custom_template(&name)? {
+                tera.add_raw_template(&name, &template)
+                    .with_context(|| format!("Failed to parse template {name}"))?;
+            }
+        }
+        for kind in AlertKind::ALL {
+            for (channel, part) in TEMPLATE_PARTS {
+                let name = format!("{channel}/{}_{part}.txt", kind.name());
+                tera.get_template(&name)
+                    .with_context(|| format!("Missing template {name}"))?;
+            }
+        }
+        Ok(Self { tera })
+    }
+
+    /// Render a part like the subject or body of an alert for a specific channel
+    pub fn render(&self, channel: &str, alert: &Alert, part: &str) -> Result<String> {
+        let name = format!("{channel}/{}_{part}.txt", alert.kind.name());
+        self.render_template(&name, alert)
+    }
+
+    /// Render the HTML variant of a part, returns nothing if there is no HTML template
+    pub fn render_html(&self, channel: &str, alert: &Alert, part: &str) -> Result<Option<String>> {
+        let name = format!("{channel}/{}_{part}.html", alert.kind.name());
+        if self.tera.get_template(&name).is_err() {
+            return Ok(None);
+        }
+        self.render_template(&name, alert).map(Some)
+    }
+
+    /// Render the custom webhook payload, returns nothing if there is no template for the alert
+    pub fn render_payload(&self, alert: &Alert) -> Result<Option<Value>> {
+        let name = format!("webhook/{}_payload.json", alert.kind.name());
+        if self.tera.get_template(&name).is_err() {
+            return Ok(None);
+        }
+        let payload = self.render_template(&name, alert)?;
+        serde_json::from_str(&payload)
+            .with_context(|| format!("Template {name} rendered invalid JSON"))
+            .map(Some)
+    }
+
+    fn render_template(&self, name: &str, alert: &Alert) -> Result<String> {
+        let mut context = tera::Context::from_serialize(&alert.data)
+            .context("Failed to create template context")?;
+        if let Value::Object(values) = &alert.context {
+            for (key, value) in values {
+                context.insert(key, value);
+            }
+        }
+        self.tera
+            .render(name, &context)
+            .with_context(|| format!("Failed to render template {name}"))
+    }
+}
+
+/// Backend delivering rendered alerts to a notification service
+#[async_trait]
+pub trait NotificationChannel: Send + Sync {
+    /// Name of the channel for logging
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, templates: &Templates, alert: &Alert) -> Result<()>;
+}
+
+/// Sends alerts to all configured notification channels
+pub struct Notifier {
+    templates: Templates,
+    channels: Vec<Box<dyn NotificationChannel>>,
+    throttle: Throttle,
+}
+
+impl Notifier {
+    pub fn new(config: &Configuration) -> Result<Self> {
+        let templates = Templates::new(config.notification_template_dir.as_deref())
+            .context("Failed to load notification templates")?;
+        let client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .context("Failed to create HTTP client")?;
+
+        let mut channels: Vec<Box<dyn NotificationChannel>> = Vec::new();
+        if config.smtp_host.is_some() {
+            let smtp = SmtpSender::new(config).context("Failed to create SMTP sender")?;
+            channels.push(Box::new(smtp));
+        }
+        if let Some(url) = &config.webhook_url {
+            channels.push(Box::new(WebhookSender::new(client.clone(), url)));
+        }
+        if let Some(url) = &config.slack_webhook_url {
+            channels.push(Box::new(SlackSender::new(client.clone(), url)));
+        }
+        if let Some(url) = &config.discord_webhook_url {
+            channels.push(Box::new(DiscordSender::new(client.clone(), url)));
+        }
+        if let (Some(homeserver), Some(room_id), Some(access_token)) = (
+            &config.matrix_homeserver,
+            &config.matrix_room_id,
+            &config.matrix_access_token,
+        ) {
+            let matrix = MatrixSender::new(client.clone(), homeserver, room_id, access_token)
+                .context("Failed to create Matrix sender")?;
+            channels.push(Box::new(matrix));
+        }
+        if let Some(topic) = &config.ntfy_topic {
+            let ntfy = NtfySender::new(
+                client.clone(),
+                &config.ntfy_server,
+                topic,
+                config.ntfy_token.as_deref(),
+            );
+            channels.push(Box::new(ntfy));
+        }
+        if let (Some(server), Some(token)) = (&config.gotify_server, &config.gotify_token) {
+            channels.push(Box::new(GotifySender::new(client.clone(), server, token)));
+        }
+        Ok(Self {
+            templates,
+            channels,
+            throttle: Throttle::new(&config.alert_throttle),
+        })
+    }
+
+    /// Names of all configured channels
+    pub fn channel_names(&self) -> Vec<&'static str> {
+        self.channels.iter().map(|c| c.name()).collect()
+    }
+
+    /// Send alert to all channels, errors are logged but not returned.
+    /// Items already sent within the throttle window are removed first.
+    pub async fn send(&self, alert: &Alert) {
+        if self.channels.is_empty() {
+            return;
+        }
+        let Some(alert) = self.throttle.apply(alert, unix_time()) else {
+            info!(
+                "Skipped {} notification already sent within the throttle window",
+                alert.kind.name()
+            );
+            return;
+        };
+        for channel in &self.channels {
+            match channel.send(&self.templates, &alert).await {
+                Ok(..) => info!(
+                    "Sent {} notification via {}",
+                    alert.kind.name(),
+                    channel.name()
+                ),
+                Err(err) => error!(
+                    "Failed to send notification via {}: {err:#}",
+                    channel.name()
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+
+    #[test]
+    fn render_default_failure_alert() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let alert = Alert::failures(&[report], 1).unwrap();
+        let templates = Templates::new(None).unwrap();
+        let subject = templates.render("email", &alert, "subject").unwrap();
+        assert!(subject.starts_with("DMARC Alert:"));
+        let body = templates.render("email", &alert, "body").unwrap();
+        assert!(body.contains("disposition"));
+    }
+
+    #[test]
+    fn render_default_digest() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let alert = Alert::digest(&[report], 0, u64::MAX);
+        let templates = Templates::new(None).unwrap();
+        let subject = templates.render("email", &alert, "subject").unwrap();
+        assert_eq!(subject.trim(), "DMARC Digest: 1 new reports");
+        let html = templates.render_html("email", &alert, "body").unwrap();
+        assert!(html.unwrap().contains("<table"));
+        assert!(templates
+            .render_html("email", &alert, "subject")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn render_custom_webhook_payload() {
+        let dir = std::env::temp_dir().join(format!("dmarc-templates-{}", std::process::id()));
+        fs::create_dir_all(dir.join("webhook")).unwrap();
+        fs::write(
+            dir.join("webhook/failure_alert_payload.json"),
+            r#"{"text": {{ "Fehler: " ~ total_count | json_encode() }}, "reports": {{ summary.reports }}}"#,
+        )
+        .unwrap();
+        let templates = Templates::new(dir.to_str()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let mut alert = Alert::failures(&[report], 1).unwrap();
+        alert.context = serde_json::json!({ "summary": { "reports": 3 } });
+        let payload = templates.render_payload(&alert).unwrap().unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({ "text": "Fehler: 2", "reports": 3 })
+        );
+        assert!(templates
+            .render_payload(&Alert::digest(&[], 0, 1))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::filter::RecordFilter;
+use crate::report::Report;
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Message counts of a single source IP, header from domain or reporter
+#[derive(Serialize, ToSchema)]
+pub struct Offender {
+    pub name: String,
+
+    /// Messages failing DMARC
+    pub failed: usize,
+
+    /// All messages
+    pub messages: usize,
+}
+
+/// Rankings of the sources of DMARC failures
+#[derive(Serialize, ToSchema)]
+pub struct TopOffenders {
+    pub source_ips: Vec<Offender>,
+    pub header_from: Vec<Offender>,
+    pub orgs: Vec<Offender>,
+}
+
+#[derive(Default)]
+struct Counts {
+    failed: usize,
+    messages: usize,
+}
+
+fn ranking(counts: HashMap<String, Counts>, limit: usize) -> Vec<Offender> {
+    let mut offenders: Vec<Offender> = counts
+        .into_iter()
+        .filter(|(_, counts)| counts.failed > 0)
+        .map(|(name, counts)| Offender {
+            name,
+            failed: counts.failed,
+            messages: counts.messages,
+        })
+        .collect();
+    offenders.sort_by(|a, b| b.failed.cmp(&a.failed).then(a.name.cmp(&b.name)));
+    offenders.truncate(limit);
+    offenders
+}
+
+/// Rank source IPs, header from domains and reporting organizations
+/// of all matching records by the number of messages failing DMARC.
+pub fn top_offenders(reports: &[Report], filter: &RecordFilter, limit: usize) -> TopOffenders {
+    let mut source_ips: HashMap<String, Counts> = HashMap::new();
+    let mut header_from: HashMap<String, Counts> = HashMap::new();
+    let mut orgs: HashMap<String, Counts> = HashMap::new();
+    for (report, record) in filter.records(reports) {
+        let count = record.row.count;
+        let failed = if record.is_dmarc_pass() { 0 } else { count };
+        for (map, key) in [
+            (&mut source_ips, filter.group_source(record.row.source_ip)),
+            (
+                &mut header_from,
+                filter.group_domain(&record.identifiers.header_from),
+            ),
+            (&mut orgs, report.report_metadata.org_name.clone()),
+        ] {
+            let counts = map.entry(key).or_default();
+            counts.failed += failed;
+            counts.messages += count;
+        }
+    }
+    TopOffenders {
+        source_ips: ranking(source_ips, limit),
+        header_from: ranking(header_from, limit),
+        orgs: ranking(orgs, limit),
+    }
+}
@@ -0,0 +1,170 @@
+use crate::config::{IngestConfiguration, ParseConfiguration};
+use crate::ingest::{sign, unix_timestamp, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::parser::{
+    compression_extension, extract_xml_files, extract_xml_from_file, is_xml, parse_report,
+    ExtractLimits, ParseOptions,
+};
+use crate::report::Report;
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Parse local report files without IMAP or HTTP and print the results to stdout.
+/// Problems with single files are printed to stderr and do not stop the other files.
+pub fn run_parse(config: &ParseConfiguration) -> Result<()> {
+    let mut files = Vec::new();
+    for path in &config.paths {
+        collect_files(Path::new(path), &mut files)?;
+    }
+
+    let mut reports = Vec::new();
+    let mut failed = 0;
+    for file in &files {
+        match parse_file(file, &ParseOptions::new(config.lenient, &[])) {
+            Ok(parsed) => reports.extend(parsed),
+            Err(err) => {
+                eprintln!("{}: {err:#}", file.display());
+                failed += 1;
+            }
+        }
+    }
+
+    if config.json {
+        let json = serde_json::to_string_pretty(&reports).context("Failed to serialize reports")?;
+        println!("{json}");
+    } else {
+        print_summary(&reports);
+    }
+
+    if failed > 0 {
+        bail!("Failed to parse {failed} of {} files", files.len());
+    }
+    Ok(())
+}
+
+/// Parse a single report file or raw mail from stdin or a file, for example from a procmail hook.
+/// The reports are printed to stdout or uploaded to the ingestion API of a running instance.
+pub async fn run_ingest(config: &IngestConfiguration) -> Result<()> {
+    let data = if config.input == "-" {
+        let mut data = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .context("Failed to read from stdin")?;
+        data
+    } else {
+        fs::read(&config.input).with_context(|| format!("Failed to read {}", config.input))?
+    };
+
+    // Report files are passed on as they are, raw mails are reduced to their XML files
+    let limits = ExtractLimits::default();
+    let files = if compression_extension(&data).is_some() || is_xml(&data) {
+        vec![data]
+    } else {
+        let (xml_files, _) = extract_xml_files(0, &data, &limits)?;
+        xml_files.into_iter().map(|xml| xml.data).collect()
+    };
+    if files.is_empty() {
+        bail!("Input did not include any report");
+    }
+
+    let (Some(url), Some(secret)) = (&config.ingest_url, &config.ingest_secret) else {
+        let options = ParseOptions::new(config.lenient, &[]);
+        let mut reports = Vec::new();
+        for file in &files {
+            for xml in extract_xml_from_file(file, &limits)? {
+                reports.push(parse_report(&xml, &options)?);
+            }
+        }
+        if config.json {
+            let json =
+                serde_json::to_string_pretty(&reports).context("Failed to serialize reports")?;
+            println!("{json}");
+        } else {
+            print_summary(&reports);
+        }
+        return Ok(());
+    };
+
+    let client = Client::new();
+    let mut uploaded = 0;
+    for file in files {
+        let timestamp = unix_timestamp()?;
+        let response = client
+            .post(url)
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, sign(secret, timestamp, &file))
+            .body(file)
+            .send()
+            .await
+            .context("Failed to upload report file")?;
+        let status = response.status();
+        if !status.is_success() {
+            let reason = response.text().await.unwrap_or_default();
+            bail!("Report file was rejected with HTTP status {status}: {reason}");
+        }
+        let result: serde_json::Value = response
+            .json()
+            .await
+            .context("Failed to parse response of ingestion API")?;
+        uploaded += result["reports"].as_u64().unwrap_or_default();
+    }
+    println!("Uploaded {uploaded} new reports");
+    Ok(())
+}
+
+/// Collect files from the path, directories are searched recursively
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)
+            .with_context(|| format!("Failed to read directory {}", path.display()))?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Failed to read directory {}", path.display()))?;
+        entries.sort();
+        for entry in entries {
+            collect_files(&entry, files)?;
+        }
+    } else if path.is_file() {
+        files.push(path.to_owned());
+    } else {
+        bail!("File or directory {} does not exist", path.display());
+    }
+    Ok(())
+}
+
+fn parse_file(path: &Path, options: &ParseOptions) -> Result<Vec<Report>> {
+    let data = fs::read(path).context("Failed to read file")?;
+    extract_xml_from_file(&data, &ExtractLimits::default())?
+        .iter()
+        .map(|xml| parse_report(xml, options))
+        .collect()
+}
+
+fn print_summary(reports: &[Report]) {
+    let mut total_messages = 0;
+    let mut total_failed = 0;
+    for report in reports {
+        let messages: usize = report.record.iter().map(|r| r.row.count).sum();
+        let failed: usize = report
+            .record
+            .iter()
+            .filter(|r| !r.is_dmarc_pass())
+            .map(|r| r.row.count)
+            .sum();
+        total_messages += messages;
+        total_failed += failed;
+        println!(
+            "{} {} {}: {} records, {messages} messages, {failed} failed DMARC",
+            report.report_metadata.org_name,
+            report.report_metadata.report_id,
+            report.policy_published.domain,
+            report.record.len(),
+        );
+    }
+    println!(
+        "Total: {} reports, {total_messages} messages, {total_failed} failed DMARC",
+        reports.len()
+    );
+}
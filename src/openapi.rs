@@ -0,0 +1,144 @@
+use crate::http;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityRequirement, SecurityScheme};
+use utoipa::openapi::server::Server;
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::{Config, SwaggerUi};
+
+/// Path of the Swagger UI, the OpenAPI document is served below it
+const DOCS_PATH: &str = "/api/docs";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "DMARC Report Viewer",
+        description = "JSON API of the DMARC report viewer. \
+            All endpoints require basic auth or an API token for read-only requests."
+    ),
+    paths(
+        http::summary,
+        http::domains_summary,
+        http::domain_detail,
+        http::summary_windows,
+        http::compliance,
+        http::comparison,
+        http::reporter_list,
+        http::reporter_gaps,
+        http::selectors,
+        http::forwarding,
+        http::overrides,
+        http::enforcement,
+        http::failure_causes,
+        http::grafana_health,
+        http::grafana_search,
+        http::grafana_query,
+        http::timeseries,
+        http::source_timeseries,
+        http::rollups,
+        http::rollup_timeseries,
+        http::offenders,
+        http::daily_chart_data,
+        http::disposition_chart_data,
+        http::top_ips_chart_data,
+        http::sources,
+        http::policies,
+        http::reports,
+        http::report_list,
+        http::report,
+        http::report_xml,
+        http::record_explanation,
+        http::analyze_headers,
+        http::search,
+        http::sync,
+        http::xml_errors,
+        http::xml_error_list,
+        http::xml_error_file,
+        http::xml_error_attachment,
+        http::mails,
+        http::mail_list,
+        http::quarantine,
+        http::export_csv,
+        http::export_json,
+        http::export_xlsx,
+        http::export_pdf,
+        http::instance,
+        http::dns_stats,
+        http::dns_ptr,
+        http::dns_txt,
+        http::dns_health,
+        http::dns_spf,
+        http::rdap_lookup,
+        http::ip_lookup,
+        http::advice,
+        http::annotations,
+        http::import_annotations,
+        http::notes,
+        http::create_note,
+        http::update_note,
+        http::delete_note,
+        http::allowlist,
+        http::import_allowlist,
+        http::ratelimit_stats,
+        http::admin_settings,
+        http::update_settings,
+        http::admin_reload,
+        http::admin_purge,
+        http::admin_reparse,
+        http::sender_suggestions,
+        http::accept_suggestion,
+        http::reject_suggestion,
+        http::admin_users,
+        http::create_user,
+        http::update_user,
+        http::delete_user,
+        http::admin_tokens,
+        http::rotate_token,
+        http::delete_token,
+        http::refresh_reports,
+        http::events_stream,
+        http::status,
+        http::status_history,
+        http::ingest,
+        http::import_reports,
+        http::login,
+        http::logout,
+        http::session,
+        http::healthz,
+        http::readyz,
+    ),
+    modifiers(&Authentication)
+)]
+struct ApiDoc;
+
+/// Adds the supported authentication methods to the document
+struct Authentication;
+
+impl Modify for Authentication {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "basic_auth",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+        );
+        components.add_security_scheme(
+            "api_token",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        openapi.security = Some(vec![
+            SecurityRequirement::new("basic_auth", Vec::<String>::new()),
+            SecurityRequirement::new("api_token", Vec::<String>::new()),
+        ]);
+    }
+}
+
+/// Swagger UI and OpenAPI document for all API routes.
+/// The base path is needed because the UI loads the document with an absolute path.
+pub fn swagger_ui(base_path: &str) -> SwaggerUi {
+    let mut doc = ApiDoc::openapi();
+    if !base_path.is_empty() {
+        doc.servers = Some(vec![Server::new(base_path)]);
+    }
+    let doc_path = format!("{DOCS_PATH}/openapi.json");
+    SwaggerUi::new(DOCS_PATH)
+        .url(doc_path.clone(), doc)
+        .config(Config::new([format!("{base_path}{doc_path}")]))
+}
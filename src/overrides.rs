@@ -0,0 +1,62 @@
+use crate::filter::RecordFilter;
+use crate::report::{DispositionType, Report};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use utoipa::ToSchema;
+
+/// Records and messages with a policy override reason
+#[derive(Serialize, Default, ToSchema)]
+pub struct ReasonCounts {
+    pub records: usize,
+    pub messages: usize,
+
+    /// Messages failing DMARC that were still delivered without quarantine or reject
+    pub delivered_failures: usize,
+}
+
+/// Policy override reasons by type, in total and per domain and reporting organization
+#[derive(Serialize, Default, ToSchema)]
+pub struct OverrideSummary {
+    pub reasons: BTreeMap<String, ReasonCounts>,
+    pub domains: BTreeMap<String, BTreeMap<String, ReasonCounts>>,
+    pub orgs: BTreeMap<String, BTreeMap<String, ReasonCounts>>,
+}
+
+/// Aggregate the override reasons of all matching records.
+/// Records with multiple reasons of the same type are counted once for that type.
+pub fn override_summary(reports: &[Report], filter: &RecordFilter) -> OverrideSummary {
+    let mut summary = OverrideSummary::default();
+    for (report, record) in filter.records(reports) {
+        let evaluated = &record.row.policy_evaluated;
+        let kinds: BTreeSet<String> = evaluated
+            .reason
+            .iter()
+            .flatten()
+            .map(|r| String::from(r.kind.clone()))
+            .collect();
+        if kinds.is_empty() {
+            continue;
+        }
+        let count = record.row.count;
+        let delivered_failure =
+            !record.is_dmarc_pass() && evaluated.disposition == DispositionType::None;
+        let domain = filter.group_domain(&report.policy_published.domain);
+        let org = &report.report_metadata.org_name;
+        for kind in kinds {
+            let domain_reasons = summary.domains.entry(domain.clone()).or_default();
+            let org_reasons = summary.orgs.entry(org.clone()).or_default();
+            for counts in [
+                summary.reasons.entry(kind.clone()).or_default(),
+                domain_reasons.entry(kind.clone()).or_default(),
+                org_reasons.entry(kind).or_default(),
+            ] {
+                counts.records += 1;
+                counts.messages += count;
+                if delivered_failure {
+                    counts.delivered_failures += count;
+                }
+            }
+        }
+    }
+    summary
+}
@@ -1,102 +1,523 @@
-use crate::mail::Mail;
-use crate::report::Report;
+use crate::attachment::attachment_path;
+use crate::attachment::Attachment;
+use crate::quirks::{self, Quirk};
+use crate::repair::repair_xml;
+use crate::report::{OtherAuthResultType, Report, XmlReport};
+use crate::tar::{is_tar, tar_files};
 use crate::xml_file::XmlFile;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use flate2::read::GzDecoder;
-use mailparse::MailHeaderMap;
+use quick_xml::events::Event;
+use quick_xml::Reader;
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::io::{Cursor, Read};
-use tracing::warn;
+use tracing::{debug, warn};
 use zip::ZipArchive;
 
-/// Get zero or more XML files from a ZIP archive
-fn get_xml_from_zip(zip_bytes: &[u8]) -> Result<Vec<Vec<u8>>> {
-    let cursor = Cursor::new(zip_bytes);
-    let mut archive = ZipArchive::new(cursor).context("Failed to binary data as ZIP")?;
+/// Default limits, also used for local files of the parse command
+pub const DEFAULT_MAX_ATTACHMENT_SIZE: usize = 10 * 1024 * 1024;
+pub const DEFAULT_MAX_XML_SIZE: usize = 50 * 1024 * 1024;
+pub const DEFAULT_MAX_COMPRESSION_RATIO: usize = 100;
 
-    let mut xml_files = Vec::new();
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i).context("Unable to get file from ZIP")?;
-        let file_name = file.name();
+/// Limits for extracting XML files from untrusted archives
+/// to prevent a single mail from exhausting the memory
+#[derive(Clone, Copy)]
+pub struct ExtractLimits {
+    /// Maximum size of a compressed attachment in bytes
+    pub max_attachment_size: usize,
 
-        if !file_name.ends_with(".xml") {
-            warn!("File {file_name} in ZIP is not an XML file, skipping...",);
-            continue;
+    /// Maximum size of all XML files extracted from an archive in bytes
+    pub max_xml_size: usize,
+
+    /// Maximum ratio of the extracted to the compressed size
+    pub max_compression_ratio: usize,
+}
+
+impl Default for ExtractLimits {
+    fn default() -> Self {
+        Self {
+            max_attachment_size: DEFAULT_MAX_ATTACHMENT_SIZE,
+            max_xml_size: DEFAULT_MAX_XML_SIZE,
+            max_compression_ratio: DEFAULT_MAX_COMPRESSION_RATIO,
         }
+    }
+}
+
+/// Options for parsing XML files as DMARC reports
+#[derive(Clone)]
+pub struct ParseOptions {
+    /// Parse invalid XML files again after repairing them
+    pub lenient: bool,
+
+    /// Fixups for reporters known to send broken XML files, including the built-in ones
+    pub quirks: Vec<Quirk>,
+}
+
+impl ParseOptions {
+    pub fn new(lenient: bool, quirks: &[Quirk]) -> Self {
+        Self {
+            lenient,
+            quirks: quirks::with_builtin(quirks),
+        }
+    }
+}
+
+/// Strict parsing with the built-in quirks
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self::new(false, &[])
+    }
+}
+
+impl ExtractLimits {
+    fn check_attachment(&self, compressed: &[u8]) -> Result<()> {
+        if compressed.len() > self.max_attachment_size {
+            bail!(
+                "Attachment with {} bytes exceeds the limit of {} bytes",
+                compressed.len(),
+                self.max_attachment_size
+            );
+        }
+        Ok(())
+    }
+
+    /// Maximum extracted size of an archive, which is also limited by the compression ratio
+    fn max_extracted_size(&self, compressed_size: usize) -> usize {
+        self.max_xml_size
+            .min(compressed_size.saturating_mul(self.max_compression_ratio))
+    }
+}
+
+/// Reads until the end or the maximum size, whatever comes first.
+/// Decompression stops at the limit, so decompression bombs are not extracted completely.
+fn read_limited(reader: impl Read, max_size: usize) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(max_size as u64 + 1).read_to_end(&mut data)?;
+    if data.len() > max_size {
+        bail!("Extracted data exceeds the size or compression ratio limit of {max_size} bytes");
+    }
+    Ok(data)
+}
+
+/// Archives inside archives are only extracted up to this depth
+const MAX_NESTING: usize = 1;
+
+/// Detects XML files regardless of the file name or declared content type
+pub fn is_xml(data: &[u8]) -> bool {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    let start = data
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(0);
+    let data = &data[start..];
+    data.starts_with(b"<?xml") || data.starts_with(b"<feedback")
+}
 
-        let mut xml_file = Vec::new();
-        file.read_to_end(&mut xml_file)
-            .context("Failed to read XML from ZIP")?;
-        xml_files.push(xml_file);
+/// Get zero or more XML files from a ZIP, GZ or TAR archive detected by its magic bytes.
+/// Archives nested in the archive are extracted as well, for example an `.xml.gz` inside a `.zip`.
+/// A TAR archive inside a GZ archive does not count as nesting, so `.tar.gz` files may contain archives.
+/// The budget is the remaining size for all extracted files of the outermost archive.
+fn get_xml_from_archive(data: &[u8], budget: &mut usize, depth: usize) -> Result<Vec<Vec<u8>>> {
+    let mut contents = Vec::new();
+    match compression_extension(data) {
+        Some("zip") => {
+            let cursor = Cursor::new(data);
+            let mut archive = ZipArchive::new(cursor).context("Failed to binary data as ZIP")?;
+            for i in 0..archive.len() {
+                let file = archive.by_index(i).context("Unable to get file from ZIP")?;
+                if file.is_dir() {
+                    continue;
+                }
+                let file_name = file.name().to_owned();
+                let content = read_limited(file, *budget)
+                    .with_context(|| format!("Failed to read {file_name} from ZIP"))?;
+                *budget -= content.len();
+                contents.push((file_name, content));
+            }
+        }
+        Some("tar") => {
+            // Uncompressed, so the size was already accounted for
+            for (file_name, content) in tar_files(data)? {
+                contents.push((file_name, content.to_vec()));
+            }
+        }
+        Some(_) => {
+            let content = read_limited(GzDecoder::new(data), *budget)
+                .context("Failed to read file from GZ archive")?;
+            *budget -= content.len();
+            if is_tar(&content) {
+                return get_xml_from_archive(&content, budget, depth);
+            }
+            contents.push((String::from("GZ content"), content));
+        }
+        None => return Ok(vec![data.to_vec()]),
     }
 
+    let mut xml_files = Vec::new();
+    for (file_name, content) in contents {
+        if compression_extension(&content).is_some() && depth < MAX_NESTING {
+            let nested = get_xml_from_archive(&content, budget, depth + 1)
+                .with_context(|| format!("Failed to extract nested archive {file_name}"))?;
+            xml_files.extend(nested);
+        } else if file_name.ends_with(".xml") || is_xml(&content) {
+            xml_files.push(content);
+        } else {
+            warn!("File {file_name} in archive is not an XML file, skipping...");
+        }
+    }
     Ok(xml_files)
 }
 
-/// Get a single XML file from a GZ archive
-fn get_xml_from_gz(gz_bytes: &[u8]) -> Result<Vec<u8>> {
-    let mut gz = GzDecoder::new(gz_bytes);
-    let mut xml_file = Vec::new();
-    gz.read_to_end(&mut xml_file)
-        .context("Failed to read file from GZ archive")?;
-    Ok(xml_file)
+/// Get zero or more XML files from a compressed attachment within the limits
+fn get_xml_from_attachment(data: &[u8], limits: &ExtractLimits) -> Result<Vec<Vec<u8>>> {
+    limits.check_attachment(data)?;
+    let mut budget = limits.max_extracted_size(data.len());
+    get_xml_from_archive(data, &mut budget, 0)
 }
 
-fn hash_data(data: &[u8]) -> String {
+pub fn hash_data(data: &[u8]) -> String {
     let mut hasher = Sha256::new();
     hasher.update(data);
-    let hash = hasher.finalize();
-    format!("{:x?}", hash)
+    hex::encode(hasher.finalize())
 }
 
-pub fn extract_xml_files(mail: &mut Mail) -> Result<Vec<XmlFile>> {
-    // Consume mail body to avoid keeping the longer needed data in memory
-    let body = mail.body.take().context("Missing mail body")?;
-
-    let parsed = mailparse::parse_mail(&body).context("Failed to parse mail body")?;
+/// Get all XML files and the original compressed attachments from the body of a mail
+pub fn extract_xml_files(
+    mail_uid: u32,
+    body: &[u8],
+    limits: &ExtractLimits,
+) -> Result<(Vec<XmlFile>, Vec<Attachment>)> {
+    let parsed = mailparse::parse_mail(body).context("Failed to parse mail body")?;
 
     let mut xml_files = Vec::new();
+    let mut attachments = Vec::new();
     for part in parsed.parts() {
-        let content_type = part
-            .get_headers()
-            .get_first_value("Content-Type")
-            .unwrap_or(String::new());
-        if content_type.contains("application/zip") {
-            let body = part
-                .get_body_raw()
-                .context("Failed to get raw body of attachment part")?;
-            let xml_files_zip =
-                get_xml_from_zip(&body).context("Failed to extract XML from ZIP attachment")?;
-            for xml in xml_files_zip {
-                let hash = hash_data(&xml);
+        let content_type = part.ctype.mimetype.to_lowercase();
+        let attachment_name = part
+            .get_content_disposition()
+            .params
+            .get("filename")
+            .or_else(|| part.ctype.params.get("name"))
+            .cloned();
+        // Reporters are not consistent with content types and file names,
+        // so archives are detected by their magic bytes for all attachments
+        let declared = ["zip", "tar", "xml"]
+            .iter()
+            .any(|t| content_type.contains(t));
+        if content_type.starts_with("multipart/") || !(declared || attachment_name.is_some()) {
+            continue;
+        }
+        let body = part
+            .get_body_raw()
+            .context("Failed to get raw body of attachment part")?;
+        let Some(extension) = compression_extension(&body) else {
+            if is_xml(&body) {
                 xml_files.push(XmlFile {
-                    data: xml,
-                    mail_uid: mail.uid,
-                    hash,
+                    hash: hash_data(&body),
+                    data: body,
+                    mail_uid,
+                    attachment_name,
+                    attachment_path: None,
                 });
+            } else if declared {
+                let name = attachment_name.as_deref().unwrap_or(&content_type);
+                warn!("Attachment {name} is neither an archive nor an XML file, skipping...");
             }
-        } else if content_type.contains("application/gzip") {
-            let body = part
-                .get_body_raw()
-                .context("Failed to get raw body of attachment part")?;
-            let xml = get_xml_from_gz(&body).context("Failed to extract XML from GZ attachment")?;
+            continue;
+        };
+        let xml_files_archive = get_xml_from_attachment(&body, limits).with_context(|| {
+            format!(
+                "Failed to extract XML from {} attachment",
+                extension.to_uppercase()
+            )
+        })?;
+        let attachment = Attachment {
+            hash: hash_data(&body),
+            data: body,
+            extension,
+        };
+        for xml in xml_files_archive {
             let hash = hash_data(&xml);
             xml_files.push(XmlFile {
                 data: xml,
-                mail_uid: mail.uid,
+                mail_uid,
                 hash,
+                attachment_name: attachment_name.clone(),
+                attachment_path: Some(attachment_path(&attachment)),
             });
         }
+        attachments.push(attachment);
+    }
+
+    // Some reporters put the report into the body instead of an attachment
+    if xml_files.is_empty() {
+        for part in parsed.parts().filter(|p| p.subparts.is_empty()) {
+            let body = part
+                .get_body_raw()
+                .context("Failed to get raw body of mail part")?;
+            if let Some(xml) = embedded_xml(&body) {
+                debug!("Found XML file in mail body");
+                xml_files.push(XmlFile {
+                    hash: hash_data(&xml),
+                    data: xml,
+                    mail_uid,
+                    attachment_name: None,
+                    attachment_path: None,
+                });
+            }
+        }
     }
 
     if xml_files.is_empty() {
         warn!("Mail did not include XML file");
     }
 
-    Ok(xml_files)
+    Ok((xml_files, attachments))
+}
+
+/// XML report inlined in the text of a mail body, either as plain XML
+/// surrounded by other text or Base64 encoded without a transfer encoding
+fn embedded_xml(body: &[u8]) -> Option<Vec<u8>> {
+    let find = |needle: &[u8]| body.windows(needle.len()).position(|w| w == needle);
+    let start = find(b"<?xml").or_else(|| find(b"<feedback"));
+    let end = body
+        .windows(b"</feedback>".len())
+        .rposition(|w| w == b"</feedback>")
+        .map(|i| i + b"</feedback>".len());
+    if let (Some(start), Some(end)) = (start, end) {
+        if start < end {
+            return Some(body[start..end].to_vec());
+        }
+    }
+    let encoded: Vec<u8> = body
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    STANDARD
+        .decode(encoded)
+        .ok()
+        .filter(|decoded| is_xml(decoded))
+}
+
+/// Detect ZIP, GZ and TAR archives by their magic bytes, returns the matching file extension
+pub fn compression_extension(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"PK\x03\x04") {
+        Some("zip")
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        Some("gz")
+    } else if is_tar(data) {
+        Some("tar")
+    } else {
+        None
+    }
+}
+
+/// Get zero or more XML files from a report file that was not delivered by mail.
+/// ZIP, GZ and TAR archives are detected by their magic bytes, anything else is treated as XML.
+pub fn extract_xml_from_file(data: &[u8], limits: &ExtractLimits) -> Result<Vec<Vec<u8>>> {
+    match compression_extension(data) {
+        Some(extension) => get_xml_from_attachment(data, limits).with_context(|| {
+            format!(
+                "Failed to extract XML from {} file",
+                extension.to_uppercase()
+            )
+        }),
+        None => Ok(vec![data.to_vec()]),
+    }
 }
 
 pub fn parse_xml_file(xml_file: &[u8]) -> Result<Report> {
-    let mut cursor = Cursor::new(xml_file);
-    serde_xml_rs::from_reader(&mut cursor).context("Failed to parse XML as DMARC report")
+    // Deserializing from a string borrows the text instead of copying it through a buffered reader
+    let xml = std::str::from_utf8(xml_file).context("XML is not valid UTF-8")?;
+    let report: XmlReport =
+        quick_xml::de::from_str(xml).context("Failed to parse XML as DMARC report")?;
+    let mut report = Report::try_from(report)?;
+    let other = other_auth_results(xml_file);
+    if other.len() == report.record.len() {
+        for (record, other) in report.record.iter_mut().zip(other) {
+            record.auth_results.other = other;
+        }
+    }
+    Ok(report)
+}
+
+/// Collects the auth results of unknown methods for every record,
+/// because they cannot be captured with the fixed schema of the report.
+fn other_auth_results(xml_file: &[u8]) -> Vec<Vec<OtherAuthResultType>> {
+    let mut reader = Reader::from_reader(xml_file);
+    reader.config_mut().trim_text(true);
+    let mut buffer = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    let mut records = Vec::new();
+    let mut current: Option<OtherAuthResultType> = None;
+    loop {
+        let in_auth_results =
+            path.len() >= 2 && path[path.len() - 2..] == ["record", "auth_results"];
+        match reader.read_event_into(&mut buffer) {
+            Ok(Event::Start(start)) => {
+                let name = String::from_utf8_lossy(start.local_name().as_ref()).to_string();
+                if name == "record" && path.len() == 1 {
+                    records.push(Vec::new());
+                }
+                if in_auth_results && !matches!(name.as_str(), "dkim" | "spf" | "arc") {
+                    current = Some(OtherAuthResultType {
+                        method: name.clone(),
+                        result: None,
+                        details: BTreeMap::new(),
+                    });
+                }
+                path.push(name);
+            }
+            Ok(Event::Text(text)) => {
+                if let (Some(other), Some(name)) = (&mut current, path.last()) {
+                    let text = text.unescape().unwrap_or_default().to_string();
+                    if *name == other.method {
+                        other.result = Some(text);
+                    } else {
+                        other.details.insert(name.clone(), text);
+                    }
+                }
+            }
+            Ok(Event::End(..)) => {
+                path.pop();
+                let in_auth_results =
+                    path.len() >= 2 && path[path.len() - 2..] == ["record", "auth_results"];
+                if in_auth_results {
+                    if let (Some(mut other), Some(record)) = (current.take(), records.last_mut()) {
+                        if other.result.is_none() {
+                            other.result = other.details.get("result").cloned();
+                        }
+                        record.push(other);
+                    }
+                }
+            }
+            Ok(Event::Eof) | Err(..) => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+    records
+}
+
+/// Parse the XML file after applying the quirks of its reporter and retry with repaired XML in lenient mode.
+/// The error of the first attempt is returned if the repaired XML fails as well.
+pub fn parse_report(xml_file: &[u8], options: &ParseOptions) -> Result<Report> {
+    let fixed = quirks::apply(xml_file, &options.quirks);
+    let xml_file = fixed.as_deref().unwrap_or(xml_file);
+    let err = match parse_xml_file(xml_file) {
+        Ok(report) => return Ok(report),
+        Err(err) if !options.lenient => return Err(err),
+        Err(err) => err,
+    };
+    let repaired = repair_xml(xml_file);
+    match parse_xml_file(repaired.as_bytes()) {
+        Ok(report) => {
+            debug!("Parsed DMARC report after repairing invalid XML");
+            Ok(report)
+        }
+        Err(..) => Err(err),
+    }
+}
+
+/// Parse all reports of a report file (XML, ZIP, GZ or TAR) or of the attachments of a raw mail.
+/// Fails if the data contains no report or one of the reports cannot be parsed.
+pub fn parse_report_bytes(data: &[u8], options: &ParseOptions) -> Result<Vec<Report>> {
+    let limits = ExtractLimits::default();
+    let xml_files = if compression_extension(data).is_some() || is_xml(data) {
+        extract_xml_from_file(data, &limits)?
+    } else {
+        let (xml_files, _) = extract_xml_files(0, data, &limits)?;
+        xml_files.into_iter().map(|xml| xml.data).collect()
+    };
+    if xml_files.is_empty() {
+        bail!("Data does not contain any report");
+    }
+    xml_files
+        .iter()
+        .map(|xml| parse_report(xml, options))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn reject_decompression_bomb() {
+        let limits = ExtractLimits::default();
+        let xml = std::fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let extracted = extract_xml_from_file(&gzip(&xml), &limits).unwrap();
+        assert_eq!(extracted, vec![xml.clone()]);
+        let reports = parse_report_bytes(&gzip(&xml), &ParseOptions::default()).unwrap();
+        assert_eq!(reports[0].report_metadata.org_name, "acme.com");
+
+        let bomb = gzip(&vec![b' '; 10 * 1024 * 1024]);
+        assert!(extract_xml_from_file(&bomb, &limits).is_err());
+        let small = ExtractLimits {
+            max_attachment_size: 100,
+            ..limits
+        };
+        assert!(extract_xml_from_file(&gzip(&xml), &small).is_err());
+    }
+
+    #[test]
+    fn xml_in_mail_body() {
+        let xml = std::fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let xml = xml.trim_ascii();
+        let plain = [b"Report:\r\n".as_slice(), xml, b"\r\n-- \r\nSignature"].concat();
+        assert_eq!(embedded_xml(&plain).unwrap(), xml);
+        let encoded = STANDARD.encode(xml);
+        let wrapped: Vec<&str> = encoded
+            .as_bytes()
+            .chunks(76)
+            .map(|c| std::str::from_utf8(c).unwrap())
+            .collect();
+        let decoded = embedded_xml(wrapped.join("\r\n").as_bytes()).unwrap();
+        assert_eq!(decoded, xml);
+        assert!(embedded_xml(b"Please find the report attached").is_none());
+    }
+
+    #[test]
+    fn nested_archives() {
+        let limits = ExtractLimits::default();
+        let xml = std::fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("report.xml.gz", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(&gzip(&xml)).unwrap();
+        zip.start_file("report.zip", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(&gzip(&xml)).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        let extracted = extract_xml_from_file(&zip, &limits).unwrap();
+        assert_eq!(extracted, vec![xml.clone(), xml.clone()]);
+
+        // Only one level of nesting is extracted
+        let twice = extract_xml_from_file(&gzip(&gzip(&xml)), &limits).unwrap();
+        assert_eq!(twice, vec![xml.clone()]);
+        let thrice = extract_xml_from_file(&gzip(&gzip(&gzip(&xml))), &limits).unwrap();
+        assert!(thrice.is_empty());
+    }
+
+    #[test]
+    fn reject_implausible_date_range() {
+        let xml = std::fs::read_to_string("testdata/dmarc-reports/acme.xml").unwrap();
+        assert!(parse_xml_file(xml.as_bytes()).is_ok());
+        let forever = xml.replace("<end>1335657599</end>", "<end>18446744073709551615</end>");
+        assert!(parse_xml_file(forever.as_bytes()).is_err());
+    }
 }
@@ -0,0 +1,153 @@
+use anyhow::{anyhow, Result};
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+/// How long a successful verification is remembered
+const VERIFIED_TTL: Duration = Duration::from_secs(60);
+
+/// Hash like the ones of managed users to verify passwords of unknown users against,
+/// only the time it takes matters
+pub static UNKNOWN_USER_HASH: LazyLock<String> =
+    LazyLock::new(|| hash_password("unknown user").unwrap_or_default());
+
+/// Check a password against the configured value.
+/// The configured value can be an Argon2 hash in PHC string format,
+/// a bcrypt hash or a clear-text password.
+pub fn verify_password(configured: &str, password: &str) -> bool {
+    if configured.starts_with("$argon2") {
+        match PasswordHash::new(configured) {
+            Ok(hash) => Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok(),
+            Err(err) => {
+                warn!("Invalid Argon2 password hash: {err}");
+                false
+            }
+        }
+    } else if is_bcrypt_hash(configured) {
+        match bcrypt::verify(password, configured) {
+            Ok(valid) => valid,
+            Err(err) => {
+                warn!("Invalid bcrypt password hash: {err}");
+                false
+            }
+        }
+    } else {
+        configured.as_bytes().ct_eq(password.as_bytes()).into()
+    }
+}
+
+/// Remembers successful verifications for a short time, so clients sending
+/// the credentials with every request do not pay for the hash every time.
+/// Entries are keyed by the SHA-256 of the configured value and the password,
+/// a changed password never matches an old entry.
+#[derive(Default)]
+pub struct VerifiedPasswords {
+    expiry: Mutex<HashMap<Vec<u8>, Instant>>,
+}
+
+impl VerifiedPasswords {
+    pub fn verify(&self, configured: &str, password: &str) -> bool {
+        let key = digest(&SHA256, format!("{configured}\0{password}").as_bytes())
+            .as_ref()
+            .to_vec();
+        let now = Instant::now();
+        {
+            let mut expiry = self.expiry.lock().unwrap_or_else(PoisonError::into_inner);
+            expiry.retain(|_, expires| *expires > now);
+            if expiry.contains_key(&key) {
+                return true;
+            }
+        }
+        let valid = verify_password(configured, password);
+        if valid {
+            self.expiry
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(key, now + VERIFIED_TTL);
+        }
+        valid
+    }
+}
+
+/// Hash a password with Argon2 and a random salt in PHC string format
+pub fn hash_password(password: &str) -> Result<String> {
+    let mut salt = [0; 16];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| anyhow!("Failed to generate salt"))?;
+    let salt = SaltString::encode_b64(&salt).map_err(|err| anyhow!("Invalid salt: {err}"))?;
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| anyhow!("Failed to hash password: {err}"))?;
+    Ok(hash.to_string())
+}
+
+/// Describes the kind of the configured password for logging
+pub fn password_kind(configured: &str) -> &'static str {
+    if configured.is_empty() {
+        "none"
+    } else if configured.starts_with("$argon2") {
+        "Argon2 hash"
+    } else if is_bcrypt_hash(configured) {
+        "bcrypt hash"
+    } else {
+        "clear text"
+    }
+}
+
+fn is_bcrypt_hash(value: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| value.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_hashed_and_plain_passwords() {
+        assert!(verify_password("secret", "secret"));
+        assert!(!verify_password("secret", "other"));
+
+        let bcrypt = bcrypt::hash("secret", 4).unwrap();
+        assert!(verify_password(&bcrypt, "secret"));
+        assert!(!verify_password(&bcrypt, "other"));
+
+        let salt = SaltString::from_b64("c29tZXNhbHQ").unwrap();
+        let argon2 = Argon2::default()
+            .hash_password(b"secret", &salt)
+            .unwrap()
+            .to_string();
+        assert!(verify_password(&argon2, "secret"));
+        assert!(!verify_password(&argon2, "other"));
+
+        let hashed = hash_password("secret").unwrap();
+        assert_eq!(password_kind(&hashed), "Argon2 hash");
+        assert!(verify_password(&hashed, "secret"));
+    }
+
+    #[test]
+    fn remember_verified_passwords() {
+        let verified = VerifiedPasswords::default();
+        let hashed = hash_password("secret").unwrap();
+        assert!(!verified.verify(&hashed, "other"));
+        assert!(verified.verify(&hashed, "secret"));
+        assert_eq!(verified.expiry.lock().unwrap().len(), 1);
+        assert!(verified.verify(&hashed, "secret"));
+
+        // A new hash of the same password is verified again
+        let changed = hash_password("secret").unwrap();
+        assert!(!verified.verify(&changed, "other"));
+        assert!(verified.verify(&changed, "secret"));
+        assert_eq!(verified.expiry.lock().unwrap().len(), 2);
+    }
+}
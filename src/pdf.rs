@@ -0,0 +1,304 @@
+use crate::compare::{compare, Window};
+use crate::filter::RecordFilter;
+use crate::offenders::top_offenders;
+use crate::report::Report;
+use crate::timeseries::{time_series, Interval, Timezone};
+use pdf_writer::{Content, Name, Pdf, Rect, Ref, Str};
+
+/// A4 page size in points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+
+/// Number of sources listed in the report
+const TOP_SOURCES: usize = 10;
+
+const REGULAR: Name = Name(b"F1");
+const BOLD: Name = Name(b"F2");
+
+/// Pages of a document with a cursor for the next line from top to bottom
+struct Document {
+    pages: Vec<Content>,
+    y: f32,
+}
+
+impl Document {
+    fn new() -> Self {
+        Self {
+            pages: vec![Content::new()],
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    fn page(&mut self) -> &mut Content {
+        self.pages.last_mut().expect("Document without pages")
+    }
+
+    /// Starts a new page if there is not enough space left for the height
+    fn reserve(&mut self, height: f32) {
+        if self.y - height < MARGIN {
+            self.pages.push(Content::new());
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    fn text(&mut self, x: f32, font: Name, size: f32, text: &str) {
+        let y = self.y;
+        let encoded = win_ansi(text);
+        self.page()
+            .begin_text()
+            .set_font(font, size)
+            .next_line(x, y)
+            .show(Str(&encoded))
+            .end_text();
+    }
+
+    fn line(&mut self, font: Name, size: f32, text: &str) {
+        self.reserve(size * 1.5);
+        self.y -= size * 1.5;
+        self.text(MARGIN, font, size, text);
+    }
+
+    fn heading(&mut self, text: &str) {
+        self.reserve(60.0);
+        self.y -= 12.0;
+        self.line(BOLD, 13.0, text);
+    }
+
+    /// Table with columns at the given x offsets, the header is repeated on new pages
+    fn table(&mut self, columns: &[(&str, f32)], rows: &[Vec<String>]) {
+        let header = |doc: &mut Self| {
+            doc.y -= 15.0;
+            for (title, x) in columns {
+                doc.text(MARGIN + x, BOLD, 9.0, title);
+            }
+        };
+        header(self);
+        for row in rows {
+            if self.y - 13.0 < MARGIN {
+                self.reserve(PAGE_HEIGHT);
+                header(self);
+            }
+            self.y -= 13.0;
+            for (value, (_, x)) in row.iter().zip(columns) {
+                self.text(MARGIN + x, REGULAR, 9.0, value);
+            }
+        }
+    }
+
+    /// Bar chart with passed messages in green stacked below failed messages in red
+    fn bar_chart(&mut self, bars: &[(String, usize, usize)]) {
+        const HEIGHT: f32 = 120.0;
+        self.reserve(HEIGHT + 30.0);
+        let max = bars.iter().map(|(_, p, f)| p + f).max().unwrap_or(0).max(1);
+        let width = (PAGE_WIDTH - 2.0 * MARGIN) / bars.len().max(1) as f32;
+        let base = self.y - HEIGHT - 10.0;
+        for (i, (label, passed, failed)) in bars.iter().enumerate() {
+            let x = MARGIN + i as f32 * width;
+            let passed_height = *passed as f32 / max as f32 * HEIGHT;
+            let failed_height = *failed as f32 / max as f32 * HEIGHT;
+            let page = self.page();
+            page.set_fill_rgb(0.30, 0.69, 0.31)
+                .rect(x + 1.0, base, width - 2.0, passed_height)
+                .fill_nonzero();
+            page.set_fill_rgb(0.90, 0.22, 0.21)
+                .rect(x + 1.0, base + passed_height, width - 2.0, failed_height)
+                .fill_nonzero();
+            page.set_fill_rgb(0.0, 0.0, 0.0);
+            // Labels of every bar only fit for short periods
+            let step = bars.len().div_ceil(14);
+            if i % step == 0 {
+                let y = self.y;
+                self.y = base - 10.0;
+                self.text(x + 1.0, REGULAR, 6.0, label);
+                self.y = y;
+            }
+        }
+        self.y = base - 15.0;
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut pdf = Pdf::new();
+        let catalog_id = Ref::new(1);
+        let tree_id = Ref::new(2);
+        let regular_id = Ref::new(3);
+        let bold_id = Ref::new(4);
+        let page_ids: Vec<Ref> = (0..self.pages.len())
+            .map(|i| Ref::new(5 + 2 * i as i32))
+            .collect();
+        pdf.catalog(catalog_id).pages(tree_id);
+        pdf.pages(tree_id)
+            .kids(page_ids.iter().copied())
+            .count(page_ids.len() as i32);
+        for (font_id, name) in [(regular_id, "Helvetica"), (bold_id, "Helvetica-Bold")] {
+            pdf.type1_font(font_id)
+                .base_font(Name(name.as_bytes()))
+                .encoding_predefined(Name(b"WinAnsiEncoding"));
+        }
+        for (content, page_id) in self.pages.into_iter().zip(page_ids) {
+            let content_id = Ref::new(page_id.get() + 1);
+            let mut page = pdf.page(page_id);
+            page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+                .parent(tree_id)
+                .contents(content_id);
+            page.resources()
+                .fonts()
+                .pair(REGULAR, regular_id)
+                .pair(BOLD, bold_id);
+            drop(page);
+            pdf.stream(content_id, &content.finish());
+        }
+        pdf.finish()
+    }
+}
+
+/// The standard fonts only support WinAnsi, which matches Latin-1 for most characters
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+        .collect()
+}
+
+/// Shortens values that would overlap the next column
+fn truncate(value: &str, max: usize) -> String {
+    if value.chars().count() <= max {
+        value.to_owned()
+    } else {
+        let shortened: String = value.chars().take(max - 3).collect();
+        format!("{shortened}...")
+    }
+}
+
+/// Renders a PDF summary of the window with the compliance per domain
+/// compared to the previous window, the top sources of failures and a daily trend.
+pub fn pdf_report(
+    reports: &[Report],
+    window: Window,
+    domain: Option<String>,
+    timezone: Timezone,
+) -> Vec<u8> {
+    let filter = RecordFilter {
+        domain: domain.clone(),
+        since: Some(window.since),
+        until: Some(window.until),
+        ..Default::default()
+    };
+    let comparison = compare(reports, window, window.previous(), domain);
+    let mut doc = Document::new();
+    doc.line(BOLD, 20.0, "DMARC Report");
+    doc.line(
+        REGULAR,
+        11.0,
+        &format!(
+            "{} to {} ({timezone})",
+            timezone.date(window.since),
+            timezone.date(window.until)
+        ),
+    );
+
+    let current = comparison.domains.iter().map(|d| &d.current);
+    let messages: usize = current.clone().map(|s| s.messages).sum();
+    let failed: usize = current.map(|s| s.failed).sum();
+    let pass_rate = if messages > 0 {
+        (messages - failed) as f64 * 100.0 / messages as f64
+    } else {
+        0.0
+    };
+    doc.heading("Overview");
+    doc.line(REGULAR, 10.0, &format!("Messages: {messages}"));
+    doc.line(REGULAR, 10.0, &format!("Failed DMARC: {failed}"));
+    doc.line(REGULAR, 10.0, &format!("Compliance: {pass_rate:.1}%"));
+
+    doc.heading("Compliance per Domain");
+    let rows: Vec<Vec<String>> = comparison
+        .domains
+        .iter()
+        .filter(|d| d.current.messages > 0)
+        .map(|d| {
+            vec![
+                truncate(&d.domain, 40),
+                d.current.messages.to_string(),
+                d.current.failed.to_string(),
+                format!("{:.1}%", d.current.pass_rate),
+                if d.previous.messages > 0 {
+                    format!("{:+.1}", d.pass_rate_change)
+                } else {
+                    String::from("-")
+                },
+            ]
+        })
+        .collect();
+    doc.table(
+        &[
+            ("Domain", 0.0),
+            ("Messages", 230.0),
+            ("Failed", 300.0),
+            ("Compliance", 360.0),
+            ("Change", 430.0),
+        ],
+        &rows,
+    );
+
+    doc.heading("Top Sources of Failures");
+    let offenders = top_offenders(reports, &filter, TOP_SOURCES);
+    let rows: Vec<Vec<String>> = offenders
+        .source_ips
+        .iter()
+        .map(|o| {
+            vec![
+                truncate(&o.name, 40),
+                o.messages.to_string(),
+                o.failed.to_string(),
+            ]
+        })
+        .collect();
+    if rows.is_empty() {
+        doc.line(REGULAR, 10.0, "No failures");
+    } else {
+        doc.table(
+            &[("Source IP", 0.0), ("Messages", 230.0), ("Failed", 300.0)],
+            &rows,
+        );
+    }
+
+    doc.heading("Daily Trend");
+    let bars: Vec<(String, usize, usize)> =
+        time_series(reports, &filter, Interval::Daily, timezone)
+            .into_iter()
+            .filter(|b| b.start >= Interval::Daily.bucket_start(window.since, timezone))
+            .filter(|b| b.start <= window.until)
+            .map(|b| (timezone.date(b.start)[5..].to_owned(), b.passed, b.failed))
+            .collect();
+    if bars.is_empty() {
+        doc.line(REGULAR, 10.0, "No messages");
+    } else {
+        doc.bar_chart(&bars);
+        doc.line(
+            REGULAR,
+            8.0,
+            "Passed messages in green, failed messages in red",
+        );
+    }
+    doc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn valid_pdf() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let range = &report.report_metadata.date_range;
+        let window = Window {
+            since: range.begin,
+            until: range.end,
+        };
+        let pdf = pdf_report(&[report], window, None, Timezone::default());
+        assert!(pdf.starts_with(b"%PDF-"));
+        assert!(pdf.windows(11).any(|w| w == b"example.com"));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::anonymize::Anonymizer;
+use crate::export::value_string;
+use crate::report::{PolicyPublishedType, Report};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Published policy as observed by reporters from a point in time on
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct PolicyEntry {
+    /// Policy in DNS record notation, for example `p=reject; pct=100; adkim=r; aspf=r`
+    pub policy: String,
+
+    /// Reporting organization that observed the policy first
+    pub org: String,
+
+    /// Begin of the date range of the first report with this policy as Unix timestamp
+    pub first_seen: u64,
+
+    /// Time of the update cycle that detected the policy as Unix timestamp
+    pub detected: u64,
+}
+
+/// Change of the published policy of a domain
+#[derive(Serialize)]
+pub struct PolicyChange {
+    pub domain: String,
+    pub org: String,
+    pub previous: String,
+    pub current: String,
+}
+
+/// History of the published policies per domain
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct PolicyHistory {
+    domains: HashMap<String, Vec<PolicyEntry>>,
+}
+
+/// Format the relevant fields of a published policy like a DMARC DNS record
+pub fn policy_string(policy: &PolicyPublishedType) -> String {
+    let mut parts = vec![format!("p={}", value_string(&policy.p))];
+    if let Some(sp) = &policy.sp {
+        parts.push(format!("sp={}", value_string(sp)));
+    }
+    if let Some(np) = &policy.np {
+        parts.push(format!("np={}", value_string(np)));
+    }
+    if let Some(pct) = policy.pct {
+        parts.push(format!("pct={pct}"));
+    }
+    if let Some(testing) = &policy.testing {
+        parts.push(format!("t={}", value_string(testing)));
+    }
+    if let Some(adkim) = &policy.adkim {
+        parts.push(format!("adkim={}", value_string(adkim)));
+    }
+    if let Some(aspf) = &policy.aspf {
+        parts.push(format!("aspf={}", value_string(aspf)));
+    }
+    parts.join("; ")
+}
+
+impl PolicyHistory {
+    /// Same history with pseudonyms for the domains and reporting organizations
+    pub fn anonymized(&self, anonymizer: &Anonymizer) -> Self {
+        let domains = self
+            .domains
+            .iter()
+            .map(|(domain, entries)| {
+                let entries = entries
+                    .iter()
+                    .map(|entry| PolicyEntry {
+                        org: anonymizer.org(&entry.org),
+                        ..entry.clone()
+                    })
+                    .collect();
+                (anonymizer.domain(domain), entries)
+            })
+            .collect();
+        Self { domains }
+    }
+
+    /// Add the published policies of the reports to the history and return all changes.
+    /// Reports older than the latest known policy of a domain are ignored,
+    /// the first policy seen for a domain is not treated as a change.
+    pub fn update(&mut self, reports: &[Report], now: u64) -> Vec<PolicyChange> {
+        let mut sorted: Vec<&Report> = reports.iter().collect();
+        sorted.sort_by_key(|r| r.report_metadata.date_range.begin);
+
+        let mut changes = Vec::new();
+        for report in sorted {
+            let domain = report.policy_published.domain.to_lowercase();
+            let begin = report.report_metadata.date_range.begin;
+            let policy = policy_string(&report.policy_published);
+            let entries = self.domains.entry(domain.clone()).or_default();
+            if let Some(latest) = entries.last() {
+                if latest.policy == policy || begin < latest.first_seen {
+                    continue;
+                }
+                changes.push(PolicyChange {
+                    domain,
+                    org: report.report_metadata.org_name.clone(),
+                    previous: latest.policy.clone(),
+                    current: policy.clone(),
+                });
+            }
+            entries.push(PolicyEntry {
+                policy,
+                org: report.report_metadata.org_name.clone(),
+                first_seen: begin,
+                detected: now,
+            });
+        }
+        changes
+    }
+
+    /// Policy history of all domains or a single domain, oldest entries first
+    pub fn list(&self, domain: Option<&str>) -> HashMap<String, Vec<PolicyEntry>> {
+        self.domains
+            .iter()
+            .filter(|(d, _)| domain.is_none_or(|domain| domain.eq_ignore_ascii_case(d)))
+            .map(|(d, entries)| (d.clone(), entries.clone()))
+            .collect()
+    }
+}
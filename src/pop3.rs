@@ -0,0 +1,278 @@
+use crate::config::Configuration;
+use crate::connect::{connect, MailStream};
+use crate::mail::{hashed_uid, Mail};
+use crate::source::select_new_mails;
+use anyhow::{bail, Context, Result};
+use mailparse::{dateparse, parse_headers, MailHeaderMap};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicUsize;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, info, warn};
+
+/// Get metadata of all mails in the POP3 mailbox, works like `imap::get_mails`.
+/// POP3 has no UIDs, so they are derived from the unique IDs of the server (UIDL).
+/// New mails are deleted from the server after downloading them if configured.
+/// The deletion is only committed when the session ends without errors.
+pub async fn get_mails(
+    config: &Configuration,
+    known_uids: &HashSet<u32>,
+    batches: Sender<Vec<Mail>>,
+    pending: &AtomicUsize,
+) -> Result<HashMap<u32, Mail>> {
+    let mut session = Session::login(config).await?;
+    let unique_ids = session.unique_ids().await?;
+    let sizes = session.sizes().await?;
+    debug!("Number of mails in POP3 mailbox: {}", unique_ids.len());
+
+    // Get headers of already known and oversized mails, all others are downloaded completely
+    let max_size = config.max_mail_size as usize;
+    let mut mails = HashMap::new();
+    let mut oversized = 0;
+    let mut new_mails = Vec::new();
+    for (number, unique_id) in &unique_ids {
+        let uid = hashed_uid(unique_id);
+        let size = sizes.get(number).copied().unwrap_or_default();
+        if size > max_size || known_uids.contains(&uid) {
+            let headers = session.headers(*number).await?;
+            let mail = mail_metadata(uid, size, max_size, &headers)?;
+            oversized += usize::from(mail.oversized);
+            mails.insert(uid, mail);
+        } else {
+            new_mails.push((*number, uid));
+        }
+    }
+    if oversized > 0 {
+        warn!("Found {oversized} mails over size limit of {max_size} bytes");
+    }
+    info!("Downloaded metadata of {} mails", unique_ids.len());
+
+    // Start with the oldest mails, or the newest ones during a backfill
+    select_new_mails(config, &mut new_mails, pending);
+
+    let mut downloaded = 0;
+    for chunk in new_mails.chunks(config.imap_batch_size as usize) {
+        let mut batch = Vec::with_capacity(chunk.len());
+        for (number, uid) in chunk {
+            let body = session.retrieve(*number).await?;
+            let mut mail = mail_metadata(*uid, body.len(), max_size, &body)?;
+            mail.body = Some(body);
+            batch.push(mail);
+            downloaded += 1;
+        }
+        debug!("Downloaded batch of {} mails", batch.len());
+        if batches.send(batch).await.is_err() {
+            info!("Processing stopped, the remaining mails follow in the next cycle");
+            break;
+        }
+        if config.pop3_delete {
+            for (number, _) in chunk {
+                session.delete(*number).await?;
+            }
+        }
+    }
+    if downloaded > 0 {
+        info!("Downloaded {downloaded} mails");
+    }
+
+    session.quit().await?;
+    Ok(mails)
+}
+
+/// Download the complete mail with the UID from the mailbox.
+/// Returns nothing if the mail does not exist anymore.
+pub async fn get_mail_body(config: &Configuration, uid: u32) -> Result<Option<Vec<u8>>> {
+    let mut session = Session::login(config).await?;
+    let number = session
+        .unique_ids()
+        .await?
+        .into_iter()
+        .find(|(_, unique_id)| hashed_uid(unique_id) == uid)
+        .map(|(number, _)| number);
+    let body = match number {
+        Some(number) => Some(session.retrieve(number).await?),
+        None => None,
+    };
+    session.quit().await?;
+    Ok(body)
+}
+
+/// Log in to the POP3 server to validate the configuration.
+/// Returns the number of mails in the mailbox.
+pub async fn check_inbox(config: &Configuration) -> Result<u32> {
+    let mut session = Session::login(config).await?;
+    let status = session.command("STAT").await?;
+    let count = status
+        .split_whitespace()
+        .nth(1)
+        .and_then(|c| c.parse().ok())
+        .context("Invalid STAT response of POP3 server")?;
+    session.quit().await?;
+    Ok(count)
+}
+
+fn mail_metadata(uid: u32, size: usize, max_size: usize, data: &[u8]) -> Result<Mail> {
+    let (headers, _) = parse_headers(data).context("Failed to parse mail headers")?;
+    let date = headers
+        .get_first_value("Date")
+        .and_then(|d| dateparse(&d).ok())
+        .unwrap_or_default();
+    Ok(Mail {
+        uid,
+        size,
+        oversized: size > max_size,
+        date,
+        subject: headers.get_first_value("Subject").unwrap_or_default(),
+        sender: headers.get_first_value("From").unwrap_or_default(),
+        to: headers.get_first_value("To").unwrap_or_default(),
+        attachments: 0,
+        message_id: headers
+            .get_first_value("Message-ID")
+            .map(|id| id.trim().to_owned()),
+        body: None,
+        duplicate_of: None,
+    })
+}
+
+/// POP3 session as specified in RFC 1939
+struct Session<S> {
+    stream: BufReader<S>,
+}
+
+impl Session<Box<dyn MailStream>> {
+    async fn login(config: &Configuration) -> Result<Self> {
+        let stream = connect(config, config.pop3_port).await?;
+        let mut session = Session {
+            stream: BufReader::new(stream),
+        };
+        session.response().await.context("No POP3 greeting")?;
+        session
+            .command(&format!("USER {}", config.imap_user))
+            .await?;
+        session
+            .command(&format!("PASS {}", config.imap_password))
+            .await
+            .context("Failed to log in to POP3 server")?;
+        debug!("POP3 login successful");
+        Ok(session)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Session<S> {
+    /// Sends the command and returns the positive status line
+    async fn command(&mut self, command: &str) -> Result<String> {
+        let stream = self.stream.get_mut();
+        stream.write_all(command.as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+        stream.flush().await?;
+        self.response().await
+    }
+
+    async fn response(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            bail!("POP3 server closed the connection");
+        }
+        let line = line.trim_end();
+        if !line.starts_with("+OK") {
+            bail!("POP3 server responded with error: {line}");
+        }
+        Ok(line.to_owned())
+    }
+
+    /// Sends the command and returns the data of the multi-line response
+    async fn multi_line(&mut self, command: &str) -> Result<Vec<u8>> {
+        self.command(command).await?;
+        read_multi_line(&mut self.stream).await
+    }
+
+    /// Numbers of the mails in this session with their unique IDs
+    async fn unique_ids(&mut self) -> Result<Vec<(usize, String)>> {
+        let data = self.multi_line("UIDL").await?;
+        Ok(listing(&data)
+            .map(|(number, unique_id)| (number, unique_id.to_owned()))
+            .collect())
+    }
+
+    /// Sizes of the mails by their numbers
+    async fn sizes(&mut self) -> Result<HashMap<usize, usize>> {
+        let data = self.multi_line("LIST").await?;
+        Ok(listing(&data)
+            .filter_map(|(number, size)| Some((number, size.parse().ok()?)))
+            .collect())
+    }
+
+    async fn headers(&mut self, number: usize) -> Result<Vec<u8>> {
+        self.multi_line(&format!("TOP {number} 0"))
+            .await
+            .with_context(|| format!("Failed to get headers of mail {number}"))
+    }
+
+    async fn retrieve(&mut self, number: usize) -> Result<Vec<u8>> {
+        self.multi_line(&format!("RETR {number}"))
+            .await
+            .with_context(|| format!("Failed to download mail {number}"))
+    }
+
+    async fn delete(&mut self, number: usize) -> Result<()> {
+        self.command(&format!("DELE {number}"))
+            .await
+            .with_context(|| format!("Failed to delete mail {number}"))?;
+        Ok(())
+    }
+
+    /// Ends the session, which also commits the deletions
+    async fn quit(mut self) -> Result<()> {
+        self.command("QUIT")
+            .await
+            .context("Failed to log off from POP3 server")?;
+        Ok(())
+    }
+}
+
+/// Lines of UIDL and LIST responses with the mail number and a value
+fn listing(data: &[u8]) -> impl Iterator<Item = (usize, &str)> {
+    std::str::from_utf8(data)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (number, value) = line.trim().split_once(' ')?;
+            Some((number.parse().ok()?, value))
+        })
+}
+
+/// Reads lines until the terminating dot and removes the byte-stuffing of lines starting with a dot
+async fn read_multi_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            bail!("POP3 server closed the connection");
+        }
+        if line == b".\r\n" || line == b".\n" {
+            return Ok(data);
+        }
+        let content = if line.starts_with(b"..") {
+            &line[1..]
+        } else {
+            &line[..]
+        };
+        data.extend_from_slice(content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn multi_line_response() {
+        let mut response = &b"1 abc\r\n..hidden\r\n2 def\r\n.\r\n+OK next"[..];
+        let data = read_multi_line(&mut response).await.unwrap();
+        assert_eq!(data, b"1 abc\r\n.hidden\r\n2 def\r\n");
+        assert_eq!(response, b"+OK next");
+        let entries: Vec<_> = listing(&data).collect();
+        assert_eq!(entries, [(1, "abc"), (2, "def")]);
+    }
+}
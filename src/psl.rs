@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+/// Public suffix list from https://publicsuffix.org/list/public_suffix_list.dat
+const LIST: &str = include_str!("../data/public_suffix_list.dat");
+
+static RULES: LazyLock<Rules> = LazyLock::new(|| Rules::parse(LIST));
+
+/// Parsed rules of the public suffix list
+struct Rules {
+    suffixes: HashSet<&'static str>,
+
+    /// Domains below which every label is a public suffix (`*.ck` stored as `ck`)
+    wildcards: HashSet<&'static str>,
+
+    /// Exceptions from wildcards (`!www.ck` stored as `www.ck`)
+    exceptions: HashSet<&'static str>,
+}
+
+impl Rules {
+    fn parse(list: &'static str) -> Self {
+        let mut rules = Self {
+            suffixes: HashSet::new(),
+            wildcards: HashSet::new(),
+            exceptions: HashSet::new(),
+        };
+        for line in list.lines() {
+            let Some(rule) = line.split_whitespace().next() else {
+                continue;
+            };
+            if rule.starts_with("//") {
+                continue;
+            }
+            if let Some(exception) = rule.strip_prefix('!') {
+                rules.exceptions.insert(exception);
+            } else if let Some(wildcard) = rule.strip_prefix("*.") {
+                rules.wildcards.insert(wildcard);
+            } else {
+                rules.suffixes.insert(rule);
+            }
+        }
+        rules
+    }
+
+    /// Number of labels of the longest matching public suffix
+    fn suffix_labels(&self, labels: &[&str]) -> usize {
+        for i in 0..labels.len() {
+            let candidate = labels[i..].join(".");
+            if self.exceptions.contains(candidate.as_str()) {
+                return labels.len() - i - 1;
+            }
+            if self.suffixes.contains(candidate.as_str())
+                || self.wildcards.contains(labels[i + 1..].join(".").as_str())
+            {
+                return labels.len() - i;
+            }
+        }
+        // Unlisted top level domains are public suffixes as well
+        1
+    }
+}
+
+/// Organizational domain as defined by DMARC: the public suffix with one more label.
+/// Public suffixes themselves are returned unchanged.
+pub fn organizational_domain(domain: &str) -> String {
+    let domain = domain.trim_end_matches('.').to_lowercase();
+    let labels: Vec<&str> = domain.split('.').collect();
+    let suffix = RULES.suffix_labels(&labels);
+    if labels.len() <= suffix {
+        return domain;
+    }
+    labels[labels.len() - suffix - 1..].join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn organizational_domains() {
+        assert_eq!(organizational_domain("example.com"), "example.com");
+        assert_eq!(organizational_domain("Mail.Example.com."), "example.com");
+        assert_eq!(organizational_domain("a.b.example.co.uk"), "example.co.uk");
+        assert_eq!(organizational_domain("co.uk"), "co.uk");
+        assert_eq!(
+            organizational_domain("news.example.unknowntld"),
+            "example.unknowntld"
+        );
+        // Wildcard with exception
+        assert_eq!(organizational_domain("a.b.c.ck"), "b.c.ck");
+        assert_eq!(organizational_domain("mail.www.ck"), "www.ck");
+    }
+}
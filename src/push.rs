@@ -0,0 +1,89 @@
+use crate::notifications::{Alert, NotificationChannel, Templates};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::json;
+
+/// Sends alerts as push notifications to a ntfy topic
+pub struct NtfySender {
+    client: Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl NtfySender {
+    pub fn new(client: Client, server: &str, topic: &str, token: Option<&str>) -> Self {
+        Self {
+            client,
+            url: format!("{}/{topic}", server.trim_end_matches('/')),
+            token: token.map(str::to_owned),
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for NtfySender {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    async fn send(&self, templates: &Templates, alert: &Alert) -> Result<()> {
+        let title = templates.render("email", alert, "subject")?;
+        let message = templates.render("chat", alert, "message")?;
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("Title", title.trim())
+            .header("Tags", "email")
+            .body(message.trim().to_owned());
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        request
+            .send()
+            .await
+            .context("Failed to send ntfy request")?
+            .error_for_status()
+            .context("ntfy request failed")?;
+        Ok(())
+    }
+}
+
+/// Sends alerts as push notifications to a Gotify server
+pub struct GotifySender {
+    client: Client,
+    url: String,
+    token: String,
+}
+
+impl GotifySender {
+    pub fn new(client: Client, server: &str, token: &str) -> Self {
+        Self {
+            client,
+            url: format!("{}/message", server.trim_end_matches('/')),
+            token: token.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for GotifySender {
+    fn name(&self) -> &'static str {
+        "Gotify"
+    }
+
+    async fn send(&self, templates: &Templates, alert: &Alert) -> Result<()> {
+        let title = templates.render("email", alert, "subject")?;
+        let message = templates.render("chat", alert, "message")?;
+        self.client
+            .post(&self.url)
+            .header("X-Gotify-Key", &self.token)
+            .json(&json!({ "title": title.trim(), "message": message.trim(), "priority": 5 }))
+            .send()
+            .await
+            .context("Failed to send Gotify request")?
+            .error_for_status()
+            .context("Gotify request failed")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use utoipa::ToSchema;
+
+/// Mail whose XML files could not be extracted
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct QuarantinedMail {
+    pub mail_uid: u32,
+
+    /// Error of the last attempt
+    pub error: String,
+
+    /// Number of failed attempts
+    pub attempts: u32,
+
+    /// Time of the first failed attempt as Unix timestamp
+    pub first_failure: u64,
+
+    /// Time of the last failed attempt as Unix timestamp
+    pub last_failure: u64,
+
+    /// All retries failed, the mail is not downloaded again
+    pub exhausted: bool,
+}
+
+/// Mails that failed the extraction, downloaded and extracted again in the following
+/// update cycles until the retries are used up, for example after an update with fixes
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Quarantine {
+    mails: BTreeMap<u32, QuarantinedMail>,
+}
+
+impl Quarantine {
+    pub fn get(&self, uid: u32) -> Option<&QuarantinedMail> {
+        self.mails.get(&uid)
+    }
+
+    /// UIDs of mails to download again in the next update cycle
+    pub fn retry_uids(&self) -> HashSet<u32> {
+        self.mails
+            .values()
+            .filter(|m| !m.exhausted)
+            .map(|m| m.mail_uid)
+            .collect()
+    }
+
+    /// Record a failed attempt, the mail is retried until it failed `retries` more times
+    pub fn failed(
+        &mut self,
+        uid: u32,
+        error: String,
+        retries: u32,
+        timestamp: u64,
+    ) -> &QuarantinedMail {
+        let mail = self.mails.entry(uid).or_insert_with(|| QuarantinedMail {
+            mail_uid: uid,
+            error: String::new(),
+            attempts: 0,
+            first_failure: timestamp,
+            last_failure: timestamp,
+            exhausted: false,
+        });
+        mail.error = error;
+        mail.attempts += 1;
+        mail.last_failure = timestamp;
+        mail.exhausted = mail.attempts > retries;
+        mail
+    }
+
+    /// Remove a mail that was extracted successfully, returns its entry if it was quarantined
+    pub fn release(&mut self, uid: u32) -> Option<QuarantinedMail> {
+        self.mails.remove(&uid)
+    }
+
+    /// Forget mails that were removed from the mailbox
+    pub fn retain<T>(&mut self, mails: &HashMap<u32, T>) {
+        self.mails.retain(|uid, _| mails.contains_key(uid));
+    }
+
+    pub fn list(&self) -> Vec<QuarantinedMail> {
+        self.mails.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_until_exhausted() {
+        let mut quarantine = Quarantine::default();
+        let error = || String::from("broken");
+        assert!(!quarantine.failed(1, error(), 2, 100).exhausted);
+        assert!(!quarantine.failed(1, error(), 2, 200).exhausted);
+        assert!(quarantine.retry_uids().contains(&1));
+        let mail = quarantine.failed(1, String::from("still broken"), 2, 300);
+        assert!(mail.exhausted);
+        assert_eq!(mail.attempts, 3);
+        assert_eq!(mail.first_failure, 100);
+        assert!(quarantine.retry_uids().is_empty());
+
+        // Without retries a mail is given up after the first failure
+        assert!(quarantine.failed(2, error(), 0, 100).exhausted);
+        assert_eq!(quarantine.release(2).unwrap().attempts, 1);
+        quarantine.retain(&HashMap::<u32, ()>::new());
+        assert!(quarantine.list().is_empty());
+    }
+}
@@ -0,0 +1,263 @@
+use encoding_rs::Encoding;
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use tracing::debug;
+
+/// Reporters known to send broken XML files with the fixups that make them parseable
+const BUILTIN: &[&str] = &[
+    "mimecast:empty_elements",
+    "qq.com:encoding=gb18030",
+    "163.com:encoding=gb18030",
+    "126.com:encoding=gb18030",
+];
+
+/// Change of the XML file applied before parsing
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fixup {
+    /// Remove elements without content like `<sp></sp>` or `<pct/>`
+    EmptyElements,
+
+    /// Decode files that are not valid UTF-8 with this encoding, regardless of their declaration
+    Encoding(&'static Encoding),
+}
+
+/// Fixup for the XML files of a reporter, like `mimecast:empty_elements`.
+/// The reporter matches if it is part of the org name or the domain of the report email.
+#[derive(Clone, Debug)]
+pub struct Quirk {
+    pub reporter: String,
+    pub fixup: Fixup,
+}
+
+impl FromStr for Quirk {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (reporter, fixup) = value
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid quirk '{value}', expected <reporter>:<fixup>"))?;
+        let reporter = reporter.trim().to_lowercase();
+        if reporter.is_empty() {
+            return Err(format!("Missing reporter in quirk '{value}'"));
+        }
+        let fixup = match fixup.trim().split_once('=') {
+            None if fixup.trim() == "empty_elements" => Fixup::EmptyElements,
+            Some(("encoding", label)) => Encoding::for_label(label.trim().as_bytes())
+                .map(Fixup::Encoding)
+                .ok_or_else(|| format!("Unknown encoding '{label}'"))?,
+            _ => {
+                return Err(format!(
+                    "Unknown fixup '{fixup}', expected empty_elements or encoding=<label>"
+                ))
+            }
+        };
+        Ok(Self { reporter, fixup })
+    }
+}
+
+impl Display for Quirk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.fixup {
+            Fixup::EmptyElements => write!(f, "{}:empty_elements", self.reporter),
+            Fixup::Encoding(encoding) => {
+                let label = encoding.name().to_lowercase();
+                write!(f, "{}:encoding={label}", self.reporter)
+            }
+        }
+    }
+}
+
+/// Built-in quirks followed by the configured ones
+pub fn with_builtin(quirks: &[Quirk]) -> Vec<Quirk> {
+    BUILTIN
+        .iter()
+        .map(|quirk| quirk.parse().expect("Invalid built-in quirk"))
+        .chain(quirks.iter().cloned())
+        .collect()
+}
+
+/// Apply the fixups of all quirks matching the reporter of the file.
+/// Returns nothing if no fixup changed the file.
+pub fn apply(data: &[u8], quirks: &[Quirk]) -> Option<Vec<u8>> {
+    if quirks.is_empty() {
+        return None;
+    }
+    let (org_name, email) = reporter(data);
+    let domain = email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain)
+        .unwrap_or_default();
+    let fixups: Vec<&Quirk> = quirks
+        .iter()
+        .filter(|q| {
+            org_name.contains(&q.reporter)
+                || domain == q.reporter
+                || domain.ends_with(&format!(".{}", q.reporter))
+        })
+        .collect();
+    if fixups.is_empty() {
+        return None;
+    }
+
+    // Encodings first, the other fixups need UTF-8
+    let mut text = match std::str::from_utf8(data) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(..) => {
+            let encoding = fixups.iter().find_map(|q| match q.fixup {
+                Fixup::Encoding(encoding) => Some(encoding),
+                _ => None,
+            })?;
+            debug!("Decoding XML file of {org_name} as {}", encoding.name());
+            let (text, _, _) = encoding.decode(data);
+            // The declaration might still name a wrong encoding
+            let text = match text.trim_start().strip_prefix("<?xml") {
+                Some(rest) => rest.split_once("?>").map_or("", |(_, rest)| rest),
+                None => &text,
+            };
+            Cow::Owned(text.to_owned())
+        }
+    };
+    if fixups.iter().any(|q| q.fixup == Fixup::EmptyElements) {
+        if let Some(removed) = remove_empty_elements(&text) {
+            debug!("Removed empty elements from XML file of {org_name}");
+            text = Cow::Owned(removed);
+        }
+    }
+    match text {
+        Cow::Owned(text) => Some(text.into_bytes()),
+        Cow::Borrowed(..) => None,
+    }
+}
+
+/// Lowercase org name and email of the report metadata, empty if missing
+fn reporter(data: &[u8]) -> (String, String) {
+    let mut reader = Reader::from_reader(data);
+    let mut buffer = Vec::new();
+    let mut current = None;
+    let (mut org_name, mut email) = (String::new(), String::new());
+    loop {
+        match reader.read_event_into(&mut buffer) {
+            Ok(Event::Start(start)) => {
+                current = match start.local_name().as_ref() {
+                    b"org_name" => Some(true),
+                    b"email" => Some(false),
+                    _ => None,
+                };
+            }
+            Ok(Event::Text(text)) => {
+                let text = String::from_utf8_lossy(&text).trim().to_lowercase();
+                match current.take() {
+                    Some(true) => org_name = text,
+                    Some(false) => email = text,
+                    None => {}
+                }
+            }
+            Ok(Event::End(end)) if end.local_name().as_ref() == b"report_metadata" => break,
+            Ok(Event::End(..)) => current = None,
+            Ok(Event::Eof) | Err(..) => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+    (org_name, email)
+}
+
+/// Writes the XML again without elements that have neither text nor child elements.
+/// Returns nothing if there were none or the XML is not well-formed.
+fn remove_empty_elements(text: &str) -> Option<String> {
+    let mut reader = Reader::from_str(text);
+    // Start tag, buffered content and whether the content is more than whitespace per open element
+    let mut open: Vec<(BytesStart, Vec<Event>, bool)> = Vec::new();
+    let mut events: Vec<Event> = Vec::new();
+    let mut removed = false;
+    loop {
+        let event = match reader.read_event().ok()? {
+            Event::Eof => break,
+            Event::Start(start) => {
+                open.push((start, Vec::new(), false));
+                continue;
+            }
+            Event::Empty(..) => {
+                removed = true;
+                continue;
+            }
+            Event::End(..) => {
+                let (start, content, filled) = open.pop()?;
+                if !filled {
+                    removed = true;
+                    continue;
+                }
+                let end =
+                    BytesEnd::new(String::from_utf8_lossy(start.name().as_ref()).into_owned());
+                let parent = match open.last_mut() {
+                    Some((_, parent, parent_filled)) => {
+                        *parent_filled = true;
+                        parent
+                    }
+                    None => &mut events,
+                };
+                parent.push(Event::Start(start));
+                parent.extend(content);
+                parent.push(Event::End(end));
+                continue;
+            }
+            event => event,
+        };
+        let filled = match &event {
+            Event::Text(text) => !text.iter().all(u8::is_ascii_whitespace),
+            Event::CData(..) => true,
+            _ => false,
+        };
+        match open.last_mut() {
+            Some((_, content, parent_filled)) => {
+                *parent_filled |= filled;
+                content.push(event);
+            }
+            None => events.push(event),
+        }
+    }
+    if !removed || !open.is_empty() {
+        return None;
+    }
+    let mut writer = Writer::new(Vec::new());
+    for event in events {
+        writer.write_event(event).ok()?;
+    }
+    String::from_utf8(writer.into_inner()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fix_known_reporters() {
+        let quirks = with_builtin(&[]);
+        assert_eq!(quirks[0].to_string(), "mimecast:empty_elements");
+        assert!("example.net:encoding=none".parse::<Quirk>().is_err());
+
+        let xml = "<feedback><report_metadata><org_name>Mimecast</org_name></report_metadata>\
+            <policy_published><domain>example.com</domain><sp></sp><pct/></policy_published>\
+            <reason><type> </type></reason></feedback>";
+        let fixed = apply(xml.as_bytes(), &quirks).unwrap();
+        assert_eq!(
+            String::from_utf8(fixed).unwrap(),
+            "<feedback><report_metadata><org_name>Mimecast</org_name></report_metadata>\
+            <policy_published><domain>example.com</domain></policy_published></feedback>"
+        );
+
+        // GBK encoded org name of a report declared as UTF-8
+        let mut xml =
+            b"<?xml version=\"1.0\" encoding=\"UTF-8\"?><feedback><report_metadata>".to_vec();
+        xml.extend(b"<org_name>\xcc\xda\xd1\xb6</org_name><email>dmarc@qq.com</email>");
+        xml.extend(b"</report_metadata></feedback>");
+        let fixed = apply(&xml, &quirks).unwrap();
+        assert!(String::from_utf8(fixed)
+            .unwrap()
+            .contains("<org_name>腾讯</org_name>"));
+        assert!(apply(&xml, &[]).is_none());
+    }
+}
@@ -0,0 +1,201 @@
+use crate::config::Configuration;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Longest possible lockout after repeated failed logins
+const MAX_LOCKOUT: Duration = Duration::from_secs(3600);
+
+/// Number of tracked clients that triggers the removal of idle entries
+const PRUNE_THRESHOLD: usize = 10000;
+
+/// Per-client request rate limiting and lockout after failed logins
+pub struct RateLimiter {
+    requests_per_minute: u32,
+    max_failures: u32,
+    lockout: Duration,
+    clients: Mutex<HashMap<IpAddr, Client>>,
+    limited_requests: AtomicU64,
+    failed_logins: AtomicU64,
+    lockouts: AtomicU64,
+}
+
+struct Client {
+    /// Remaining requests in the token bucket
+    tokens: f64,
+    last_request: Instant,
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Counters of the rate limiter for monitoring
+#[derive(Serialize, ToSchema)]
+pub struct RateLimitStats {
+    pub clients: usize,
+    pub locked_clients: usize,
+    pub limited_requests: u64,
+    pub failed_logins: u64,
+    pub lockouts: u64,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Configuration) -> Self {
+        Self {
+            requests_per_minute: config.http_rate_limit,
+            max_failures: config.http_login_max_failures,
+            lockout: Duration::from_secs(config.http_login_lockout),
+            clients: Mutex::new(HashMap::new()),
+            limited_requests: AtomicU64::new(0),
+            failed_logins: AtomicU64::new(0),
+            lockouts: AtomicU64::new(0),
+        }
+    }
+
+    /// Takes one request from the token bucket of the client.
+    /// Returns false if the client exceeded the configured rate.
+    pub fn allow_request(&self, ip: IpAddr) -> bool {
+        if self.requests_per_minute == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let capacity = f64::from(self.requests_per_minute);
+        let mut clients = self.clients.lock().expect("Failed to lock rate limiter");
+        self.prune(&mut clients, now);
+        let client = clients
+            .entry(ip)
+            .or_insert_with(|| Client::new(capacity, now));
+        let elapsed = now.duration_since(client.last_request).as_secs_f64();
+        client.tokens = (client.tokens + elapsed * capacity / 60.0).min(capacity);
+        client.last_request = now;
+        if client.tokens >= 1.0 {
+            client.tokens -= 1.0;
+            true
+        } else {
+            self.limited_requests.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Returns the remaining lockout time if the client is locked out
+    pub fn locked(&self, ip: IpAddr) -> Option<Duration> {
+        let clients = self.clients.lock().expect("Failed to lock rate limiter");
+        let locked_until = clients.get(&ip)?.locked_until?;
+        locked_until.checked_duration_since(Instant::now())
+    }
+
+    /// Records a failed login and locks the client out after too many failures.
+    /// The lockout duration doubles with every further failure.
+    pub fn login_failed(&self, ip: IpAddr) {
+        self.failed_logins.fetch_add(1, Ordering::Relaxed);
+        if self.max_failures == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let capacity = f64::from(self.requests_per_minute);
+        let mut clients = self.clients.lock().expect("Failed to lock rate limiter");
+        let client = clients
+            .entry(ip)
+            .or_insert_with(|| Client::new(capacity, now));
+        client.last_request = now;
+        client.failures += 1;
+        if client.failures >= self.max_failures {
+            let exponent = (client.failures - self.max_failures).min(16);
+            let duration = self.lockout.saturating_mul(1 << exponent).min(MAX_LOCKOUT);
+            client.locked_until = Some(now + duration);
+            self.lockouts.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Locked out client {ip} for {} secs after {} failed logins",
+                duration.as_secs(),
+                client.failures
+            );
+        }
+    }
+
+    /// Resets the failed login counter of the client
+    pub fn login_succeeded(&self, ip: IpAddr) {
+        let mut clients = self.clients.lock().expect("Failed to lock rate limiter");
+        if let Some(client) = clients.get_mut(&ip) {
+            client.failures = 0;
+            client.locked_until = None;
+        }
+    }
+
+    pub fn stats(&self) -> RateLimitStats {
+        let now = Instant::now();
+        let clients = self.clients.lock().expect("Failed to lock rate limiter");
+        RateLimitStats {
+            clients: clients.len(),
+            locked_clients: clients
+                .values()
+                .filter(|c| c.locked_until.is_some_and(|until| until > now))
+                .count(),
+            limited_requests: self.limited_requests.load(Ordering::Relaxed),
+            failed_logins: self.failed_logins.load(Ordering::Relaxed),
+            lockouts: self.lockouts.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Removes clients that are idle long enough to have a full bucket and no lockout
+    fn prune(&self, clients: &mut HashMap<IpAddr, Client>, now: Instant) {
+        if clients.len() < PRUNE_THRESHOLD {
+            return;
+        }
+        clients.retain(|_, c| {
+            now.duration_since(c.last_request) < Duration::from_secs(60)
+                || c.locked_until.is_some_and(|until| until > now)
+        });
+    }
+}
+
+impl Client {
+    fn new(tokens: f64, now: Instant) -> Self {
+        Self {
+            tokens,
+            last_request: now,
+            failures: 0,
+            locked_until: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn limit_requests_and_lock_out() {
+        let config = Configuration::parse_from([
+            "test",
+            "--imap-host=localhost",
+            "--imap-user=user",
+            "--imap-password=password",
+            "--http-server-password=password",
+            "--http-rate-limit=2",
+            "--http-login-max-failures=2",
+        ]);
+        let limiter = RateLimiter::new(&config);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(limiter.allow_request(ip));
+        assert!(limiter.allow_request(ip));
+        assert!(!limiter.allow_request(ip));
+        assert!(limiter.allow_request("192.0.2.2".parse().unwrap()));
+
+        limiter.login_failed(ip);
+        assert!(limiter.locked(ip).is_none());
+        limiter.login_failed(ip);
+        assert!(limiter.locked(ip).is_some());
+        limiter.login_succeeded(ip);
+        assert!(limiter.locked(ip).is_none());
+
+        let stats = limiter.stats();
+        assert_eq!(stats.limited_requests, 1);
+        assert_eq!(stats.failed_logins, 2);
+        assert_eq!(stats.lockouts, 1);
+    }
+}
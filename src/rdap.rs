@@ -0,0 +1,191 @@
+use crate::config::Configuration;
+use anyhow::{Context, Result};
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+/// Registration data rarely changes, so results are cached for a day
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Maximum number of cached results
+const MAX_ENTRIES: usize = 10000;
+
+/// Timeout for requests to the RDAP servers in seconds
+const TIMEOUT: u64 = 15;
+
+/// Owner of the network of an IP address from the registration data
+#[derive(Serialize, Clone, Default, Debug, ToSchema)]
+pub struct RdapInfo {
+    /// Name of the network, like `GOOGLE`
+    pub network: Option<String>,
+
+    /// First and last address of the network
+    pub range: Option<String>,
+
+    pub country: Option<String>,
+
+    /// Name of the registrant of the network
+    pub organization: Option<String>,
+
+    /// Email address for reporting abuse
+    pub abuse_email: Option<String>,
+
+    /// Phone number for reporting abuse
+    pub abuse_phone: Option<String>,
+}
+
+/// Client for RDAP lookups of IP networks with a cache
+pub struct Rdap {
+    client: Client,
+    base_url: String,
+    cache: Mutex<HashMap<IpAddr, (RdapInfo, Instant)>>,
+}
+
+impl Rdap {
+    pub fn new(config: &Configuration) -> Result<Self> {
+        Ok(Self {
+            client: Client::builder()
+                .timeout(Duration::from_secs(TIMEOUT))
+                .build()
+                .context("Failed to create HTTP client")?,
+            base_url: config.rdap_url.trim_end_matches('/').to_owned(),
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Look up the network of the IP, returns nothing if it is not registered
+    pub async fn lookup(&self, ip: IpAddr) -> Result<Option<RdapInfo>> {
+        if let Some((info, expires)) = self
+            .cache
+            .lock()
+            .expect("Failed to lock RDAP cache")
+            .get(&ip)
+        {
+            if *expires > Instant::now() {
+                return Ok(Some(info.clone()));
+            }
+        }
+        let url = format!("{}/ip/{ip}", self.base_url);
+        let response = self
+            .client
+            .get(&url)
+            .header("Accept", "application/rdap+json")
+            .send()
+            .await
+            .with_context(|| format!("Failed to request {url}"))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let json: Value = response
+            .error_for_status()
+            .with_context(|| format!("Failed to look up {ip} with RDAP"))?
+            .json()
+            .await
+            .context("Failed to parse RDAP response")?;
+        let info = parse_network(&json);
+
+        let mut cache = self.cache.lock().expect("Failed to lock RDAP cache");
+        if cache.len() >= MAX_ENTRIES {
+            let now = Instant::now();
+            cache.retain(|_, (_, expires)| *expires > now);
+        }
+        if cache.len() < MAX_ENTRIES {
+            cache.insert(ip, (info.clone(), Instant::now() + CACHE_TTL));
+        }
+        Ok(Some(info))
+    }
+}
+
+fn parse_network(json: &Value) -> RdapInfo {
+    let text = |value: &Value| value.as_str().map(str::to_owned);
+    let mut info = RdapInfo {
+        network: text(&json["name"]),
+        country: text(&json["country"]),
+        ..Default::default()
+    };
+    if let (Some(start), Some(end)) = (json["startAddress"].as_str(), json["endAddress"].as_str()) {
+        info.range = Some(format!("{start} - {end}"));
+    }
+    let mut entities = Vec::new();
+    collect_entities(json, &mut entities);
+    for entity in entities {
+        let roles: Vec<&str> = entity["roles"]
+            .as_array()
+            .map(|roles| roles.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        if roles.contains(&"registrant") && info.organization.is_none() {
+            info.organization = vcard_property(entity, "fn");
+        }
+        if roles.contains(&"abuse") {
+            if info.abuse_email.is_none() {
+                info.abuse_email = vcard_property(entity, "email");
+            }
+            if info.abuse_phone.is_none() {
+                info.abuse_phone = vcard_property(entity, "tel");
+            }
+        }
+    }
+    info
+}
+
+/// All entities of the object including nested ones, parents first
+fn collect_entities<'a>(object: &'a Value, entities: &mut Vec<&'a Value>) {
+    for entity in object["entities"].as_array().into_iter().flatten() {
+        entities.push(entity);
+        collect_entities(entity, entities);
+    }
+}
+
+/// Text value of the first property with the name in the jCard of the entity
+fn vcard_property(entity: &Value, name: &str) -> Option<String> {
+    entity["vcardArray"][1]
+        .as_array()?
+        .iter()
+        .find(|property| property[0] == name)
+        .and_then(|property| property[3].as_str())
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_owner() {
+        let json: Value = serde_json::from_str(
+            r#"{
+                "name": "GOOGLE",
+                "startAddress": "209.85.128.0",
+                "endAddress": "209.85.255.255",
+                "entities": [{
+                    "roles": ["registrant"],
+                    "vcardArray": ["vcard", [["version", {}, "text", "4.0"], ["fn", {}, "text", "Google LLC"]]],
+                    "entities": [{
+                        "roles": ["abuse"],
+                        "vcardArray": ["vcard", [
+                            ["fn", {}, "text", "Abuse"],
+                            ["tel", {"type": ["work", "voice"]}, "text", "+1-650-253-0000"],
+                            ["email", {}, "text", "network-abuse@google.com"]
+                        ]]
+                    }]
+                }]
+            }"#,
+        )
+        .unwrap();
+        let info = parse_network(&json);
+        assert_eq!(info.network.as_deref(), Some("GOOGLE"));
+        assert_eq!(info.range.as_deref(), Some("209.85.128.0 - 209.85.255.255"));
+        assert_eq!(info.organization.as_deref(), Some("Google LLC"));
+        assert_eq!(
+            info.abuse_email.as_deref(),
+            Some("network-abuse@google.com")
+        );
+        assert_eq!(info.abuse_phone.as_deref(), Some("+1-650-253-0000"));
+        assert_eq!(info.country, None);
+    }
+}
@@ -0,0 +1,99 @@
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use quick_xml::events::{BytesEnd, Event};
+use quick_xml::{Reader, Writer};
+use std::borrow::Cow;
+
+/// Best effort repair of slightly broken XML files from sloppy reporters.
+/// Converts the data to UTF-8, removes control characters
+/// and closes elements that were left open.
+pub fn repair_xml(data: &[u8]) -> String {
+    let text = decode(data);
+    let text: String = text
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    close_elements(&text)
+}
+
+/// Detects the encoding by BOM or XML declaration.
+/// Data that is not valid in the detected encoding is treated as Windows-1252,
+/// which accepts every byte and is a superset of the commonly used ISO-8859-1.
+fn decode(data: &[u8]) -> Cow<'_, str> {
+    let encoding = Encoding::for_bom(data)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| declared_encoding(data))
+        .unwrap_or(UTF_8);
+    let (text, _, malformed) = encoding.decode(data);
+    if malformed {
+        WINDOWS_1252.decode(data).0
+    } else {
+        text
+    }
+}
+
+/// Encoding from the XML declaration like `<?xml version="1.0" encoding="ISO-8859-1"?>`
+fn declared_encoding(data: &[u8]) -> Option<&'static Encoding> {
+    let end = data.windows(2).position(|w| w == b"?>")?;
+    let declaration = String::from_utf8_lossy(&data[..end]);
+    let (_, rest) = declaration.split_once("encoding=")?;
+    let quote = rest.chars().next()?;
+    let label = rest[1..].split(quote).next()?;
+    Encoding::for_label(label.as_bytes())
+}
+
+/// Writes the XML again and adds missing end tags.
+/// Unexpected end tags are dropped and everything after a syntax error is ignored.
+fn close_elements(text: &str) -> String {
+    let mut reader = Reader::from_str(text);
+    reader.config_mut().check_end_names = false;
+    let mut writer = Writer::new(Vec::new());
+    let mut open: Vec<String> = Vec::new();
+    loop {
+        let event = match reader.read_event() {
+            Ok(Event::Eof) | Err(..) => break,
+            Ok(event) => event,
+        };
+        let result = match event {
+            Event::Start(start) => {
+                open.push(String::from_utf8_lossy(start.name().as_ref()).to_string());
+                writer.write_event(Event::Start(start))
+            }
+            Event::End(end) => {
+                let name = String::from_utf8_lossy(end.name().as_ref()).to_string();
+                let Some(position) = open.iter().rposition(|n| *n == name) else {
+                    continue;
+                };
+                open.drain(position..)
+                    .rev()
+                    .try_for_each(|n| writer.write_event(Event::End(BytesEnd::new(n))))
+            }
+            // The declaration might still name the original encoding
+            Event::Decl(..) => Ok(()),
+            event => writer.write_event(event),
+        };
+        if result.is_err() {
+            break;
+        }
+    }
+    for name in open.into_iter().rev() {
+        if writer.write_event(Event::End(BytesEnd::new(name))).is_err() {
+            break;
+        }
+    }
+    String::from_utf8_lossy(&writer.into_inner()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repair_broken_xml() {
+        let mut data = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n".to_vec();
+        data.extend(b"<feedback><org>M\xfcller\x01</org><a><b>text</a><c>open");
+        assert_eq!(
+            repair_xml(&data),
+            "\n<feedback><org>M\u{fc}ller</org><a><b>text</b></a><c>open</c></feedback>"
+        );
+    }
+}
@@ -2,36 +2,77 @@
 // https://github.com/bbustin/dmarc_aggregate_parser/
 // Its based upon appendix C of the DMARC RFC:
 // https://tools.ietf.org/html/rfc7489#appendix-C
+// Extended with the optional elements of the DMARCbis aggregate reporting draft,
+// which reporters may already use with the version 2 namespace.
 
+use crate::parser::hash_data;
+use crate::timeseries::DAY;
+use anyhow::{ensure, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct DateRangeType {
     pub begin: u64,
     pub end: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ReportMetadataType {
     pub org_name: String,
+    #[serde(default)]
     pub email: String,
     pub extra_contact_info: Option<String>,
     pub report_id: String,
     pub date_range: DateRangeType,
     pub error: Option<Vec<String>>,
+    /// Software that created the report, added by DMARCbis
+    pub generator: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+/// Implements the conversion of report enums from and to their string values.
+/// Values are matched case insensitive and unknown values of nonstandard
+/// reports are kept instead of failing the whole report.
+macro_rules! string_enum {
+    ($name:ident { $($variant:ident => $value:literal),+ $(,)? }) => {
+        impl From<String> for $name {
+            fn from(value: String) -> Self {
+                match value.trim().to_lowercase().as_str() {
+                    $($value => $name::$variant,)+
+                    _ => $name::Unknown(value),
+                }
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => String::from($value),)+
+                    $name::Unknown(value) => value,
+                }
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(from = "String", into = "String")]
 pub enum AlignmentType {
-    #[serde(rename = "r")]
     Relaxed,
-    #[serde(rename = "s")]
     Strict,
+    Unknown(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "snake_case")]
+string_enum!(AlignmentType {
+    Relaxed => "r",
+    Strict => "s",
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(from = "String", into = "String")]
 pub enum DispositionType {
     /// There is no preference on how a failed DMARC should be handled.
     None,
@@ -39,9 +80,16 @@ pub enum DispositionType {
     Quarantine,
     /// The message should be rejected.
     Reject,
+    Unknown(String),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+string_enum!(DispositionType {
+    None => "none",
+    Quarantine => "quarantine",
+    Reject => "reject",
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PolicyPublishedType {
     pub domain: String,
     pub adkim: Option<AlignmentType>,
@@ -50,17 +98,57 @@ pub struct PolicyPublishedType {
     pub sp: Option<DispositionType>,
     pub pct: Option<u8>,
     pub fo: Option<String>,
+    /// Policy for non-existent subdomains, added by DMARCbis
+    pub np: Option<DispositionType>,
+    /// Set if the domain owner only tests the policy, added by DMARCbis
+    pub testing: Option<TestingType>,
+    /// How the receiver found the policy, added by DMARCbis
+    pub discovery_method: Option<DiscoveryMethodType>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(from = "String", into = "String")]
+pub enum TestingType {
+    No,
+    Yes,
+    Unknown(String),
+}
+
+string_enum!(TestingType {
+    No => "n",
+    Yes => "y",
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(from = "String", into = "String")]
+pub enum DiscoveryMethodType {
+    /// Public suffix list as in RFC 7489
+    Psl,
+    /// DNS tree walk of DMARCbis
+    TreeWalk,
+    Unknown(String),
+}
+
+string_enum!(DiscoveryMethodType {
+    Psl => "psl",
+    TreeWalk => "treewalk",
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq, ToSchema)]
+#[serde(from = "String", into = "String")]
 pub enum DmarcResultType {
     Pass,
     Fail,
+    Unknown(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "snake_case")]
+string_enum!(DmarcResultType {
+    Pass => "pass",
+    Fail => "fail",
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(from = "String", into = "String")]
 pub enum PolicyOverrideType {
     Forwarded,
     SampledOut,
@@ -68,16 +156,26 @@ pub enum PolicyOverrideType {
     MailingList,
     LocalPolicy,
     Other,
+    Unknown(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+string_enum!(PolicyOverrideType {
+    Forwarded => "forwarded",
+    SampledOut => "sampled_out",
+    TrustedForwarder => "trusted_forwarder",
+    MailingList => "mailing_list",
+    LocalPolicy => "local_policy",
+    Other => "other",
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 pub struct PolicyOverrideReason {
     #[serde(rename = "type")]
     pub kind: PolicyOverrideType,
     pub comment: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PolicyEvaluatedType {
     pub disposition: DispositionType,
     pub dkim: Option<DmarcResultType>,
@@ -85,35 +183,45 @@ pub struct PolicyEvaluatedType {
     pub reason: Option<Vec<PolicyOverrideReason>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct RowType {
+    #[schema(value_type = String)]
     pub source_ip: IpAddr,
     pub count: usize,
     pub policy_evaluated: PolicyEvaluatedType,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct IdentifierType {
     pub envelope_to: Option<String>,
     pub envelope_from: Option<String>,
     pub header_from: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq, ToSchema)]
+#[serde(from = "String", into = "String")]
 pub enum DkimResultType {
     None,
     Pass,
     Fail,
     Policy,
     Neutral,
-    #[serde(rename = "temperror")]
     TemporaryError,
-    #[serde(rename = "permerror")]
     PermanentError,
+    Unknown(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+string_enum!(DkimResultType {
+    None => "none",
+    Pass => "pass",
+    Fail => "fail",
+    Policy => "policy",
+    Neutral => "neutral",
+    TemporaryError => "temperror",
+    PermanentError => "permerror",
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 pub struct DkimAuthResultType {
     pub domain: String,
     pub selector: Option<String>,
@@ -121,66 +229,359 @@ pub struct DkimAuthResultType {
     pub human_result: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(from = "String", into = "String")]
 pub enum SpfDomainScope {
     Helo,
-    #[serde(rename = "mfrom")]
     MailForm,
+    Unknown(String),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
-#[serde(rename_all = "snake_case")]
+string_enum!(SpfDomainScope {
+    Helo => "helo",
+    MailForm => "mfrom",
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq, ToSchema)]
+#[serde(from = "String", into = "String")]
 pub enum SpfResultType {
     None,
     Neutral,
     Pass,
     Fail,
-    #[serde(rename = "softfail")]
     SoftFail,
-    #[serde(rename = "temperror")]
     TemporaryError,
-    #[serde(rename = "permerror")]
     PermanentError,
+    Unknown(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+string_enum!(SpfResultType {
+    None => "none",
+    Neutral => "neutral",
+    Pass => "pass",
+    Fail => "fail",
+    SoftFail => "softfail",
+    TemporaryError => "temperror",
+    PermanentError => "permerror",
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 pub struct SpfAuthResultType {
     pub domain: String,
     pub scope: Option<SpfDomainScope>,
     pub result: SpfResultType,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Result of the ARC validation, either from an `arc` element
+/// or from the override reason comment used by Google
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ArcAuthResultType {
+    pub result: String,
+    pub domain: Option<String>,
+    pub selector: Option<String>,
+}
+
+impl ArcAuthResultType {
+    /// Parses comments like `arc=pass as[2].d=google.com as[2].s=arc-20160816`
+    fn from_comment(comment: &str) -> Option<Self> {
+        let mut arc = None;
+        let mut domain = None;
+        let mut selector = None;
+        for (key, value) in comment.split_whitespace().filter_map(|t| t.split_once('=')) {
+            if key == "arc" {
+                arc = Some(value);
+            } else if key.starts_with("as[") && key.ends_with("].d") {
+                domain = Some(value.to_owned());
+            } else if key.starts_with("as[") && key.ends_with("].s") {
+                selector = Some(value.to_owned());
+            }
+        }
+        Some(Self {
+            result: arc?.to_owned(),
+            domain,
+            selector,
+        })
+    }
+}
+
+/// Result of an authentication method that is not part of the schema.
+/// Contains the text of all child elements.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct OtherAuthResultType {
+    pub method: String,
+    pub result: Option<String>,
+    pub details: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AuthResultType {
     pub dkim: Option<Vec<DkimAuthResultType>>,
+    #[serde(default)]
     pub spf: Vec<SpfAuthResultType>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arc: Vec<ArcAuthResultType>,
+    /// Filled after parsing, see `other_auth_results`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other: Vec<OtherAuthResultType>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct RecordType {
     pub row: RowType,
     pub identifiers: IdentifierType,
     pub auth_results: AuthResultType,
+
+    /// Set if the source is not on the allowlist of the domain, see `Allowlist::mark`
+    #[serde(default)]
+    pub unexpected: bool,
+
+    /// Set if the failures of the record were acknowledged by a user, see `Notes::mark`
+    #[serde(default)]
+    pub acknowledged: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl RecordType {
+    /// Checks if DMARC passed, which requires either DKIM or SPF policy evaluation to pass
+    pub fn is_dmarc_pass(&self) -> bool {
+        let evaluated = &self.row.policy_evaluated;
+        evaluated.dkim == Some(DmarcResultType::Pass)
+            || evaluated.spf == Some(DmarcResultType::Pass)
+    }
+
+    /// Checks if DKIM or SPF failed or the receiver applied a policy other than none
+    pub fn is_failure(&self) -> bool {
+        let evaluated = &self.row.policy_evaluated;
+        evaluated.dkim == Some(DmarcResultType::Fail)
+            || evaluated.spf == Some(DmarcResultType::Fail)
+            || evaluated.disposition != DispositionType::None
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Report {
     pub version: Option<String>,
     pub report_metadata: ReportMetadataType,
     pub policy_published: PolicyPublishedType,
     pub record: Vec<RecordType>,
+    /// UID of the mail containing the report.
+    /// This is not part of the DMARC XML schema and filled after parsing.
+    #[serde(default)]
+    pub mail_uid: Option<u32>,
+    /// SHA256 hash of the original XML file, also used as name in the archive.
+    /// This is not part of the DMARC XML schema and filled after parsing.
+    #[serde(default)]
+    pub xml_hash: Option<String>,
+    /// File name of the mail attachment containing the report.
+    /// This is not part of the DMARC XML schema and filled after parsing.
+    #[serde(default)]
+    pub attachment_name: Option<String>,
+}
+
+/// Finds a report by its stable ID or by the ID assigned by the reporter
+pub fn find_report<'a>(reports: &'a [Report], id: &str) -> Option<&'a Report> {
+    reports
+        .iter()
+        .find(|r| r.stable_id() == id)
+        .or_else(|| reports.iter().find(|r| r.report_metadata.report_id == id))
+}
+
+/// Report as found in XML files, which is less strict than the schema.
+/// Some reporters repeat the published policy and only the first one is used.
+pub struct XmlReport {
+    version: Option<String>,
+    report_metadata: ReportMetadataType,
+    policy_published: Option<PolicyPublishedType>,
+    record: Vec<RecordType>,
+}
+
+// Implemented by hand because a derived Vec for the repeated policy makes quick-xml
+// look ahead over all following records for every one of them, which is quadratic.
+impl<'de> Deserialize<'de> for XmlReport {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct XmlReportVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for XmlReportVisitor {
+            type Value = XmlReport;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a DMARC aggregate report")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<XmlReport, A::Error> {
+                let mut version = None;
+                let mut report_metadata = None;
+                let mut policy_published = None;
+                let mut record = Vec::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "version" => version = Some(map.next_value()?),
+                        "report_metadata" => report_metadata = Some(map.next_value()?),
+                        "policy_published" => {
+                            let policy: PolicyPublishedType = map.next_value()?;
+                            policy_published.get_or_insert(policy);
+                        }
+                        "record" => record.append(&mut map.next_value::<Vec<RecordType>>()?),
+                        _ => {
+                            map.next_value::<serde::de::IgnoredAny>()?;
+                        }
+                    }
+                }
+                Ok(XmlReport {
+                    version,
+                    report_metadata: report_metadata
+                        .ok_or_else(|| serde::de::Error::missing_field("report_metadata"))?,
+                    policy_published,
+                    record,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(XmlReportVisitor)
+    }
+}
+
+impl TryFrom<XmlReport> for Report {
+    type Error = anyhow::Error;
+
+    fn try_from(xml: XmlReport) -> Result<Self> {
+        let policy_published = xml
+            .policy_published
+            .context("Report has no published policy")?;
+        check_date_range(&xml.report_metadata)?;
+        let mut record = xml.record;
+        for record in &mut record {
+            // DMARCbis reports the disposition pass for messages that passed DMARC,
+            // which is the same as none in RFC 7489 reports
+            let disposition = &mut record.row.policy_evaluated.disposition;
+            if matches!(disposition, DispositionType::Unknown(value) if value.trim().eq_ignore_ascii_case("pass"))
+            {
+                *disposition = DispositionType::None;
+            }
+            // Some reporters send IPv4 sources as IPv4-mapped IPv6 addresses like ::ffff:192.0.2.1
+            record.row.source_ip = record.row.source_ip.to_canonical();
+            if record.auth_results.arc.is_empty() {
+                let reasons = record.row.policy_evaluated.reason.iter().flatten();
+                record.auth_results.arc = reasons
+                    .filter_map(|r| ArcAuthResultType::from_comment(r.comment.as_deref()?))
+                    .collect();
+            }
+        }
+        Ok(Self {
+            version: xml.version,
+            report_metadata: xml.report_metadata,
+            policy_published,
+            record,
+            mail_uid: None,
+            xml_hash: None,
+            attachment_name: None,
+        })
+    }
+}
+
+/// Longest date range accepted for a report, reporters usually send daily reports
+const MAX_REPORT_SPAN: u64 = 7 * DAY;
+
+/// Tolerated clock difference for date ranges ending in the future
+const FUTURE_MARGIN: u64 = DAY;
+
+/// Rejects date ranges that cannot come from a real report.
+/// Anyone can send reports and huge ranges would make bucketing them by day very expensive.
+fn check_date_range(metadata: &ReportMetadataType) -> Result<()> {
+    let range = &metadata.date_range;
+    ensure!(
+        range.begin <= range.end,
+        "Date range of report {} begins after its end",
+        metadata.report_id
+    );
+    ensure!(
+        range.end - range.begin <= MAX_REPORT_SPAN,
+        "Date range of report {} spans more than {} days",
+        metadata.report_id,
+        MAX_REPORT_SPAN / DAY
+    );
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    ensure!(
+        range.end <= now.saturating_add(FUTURE_MARGIN),
+        "Date range of report {} ends in the future",
+        metadata.report_id
+    );
+    Ok(())
+}
+
+impl Report {
+    /// Stable identifier derived from organization, report ID and date range,
+    /// because report IDs are only unique per reporting organization
+    pub fn stable_id(&self) -> String {
+        let metadata = &self.report_metadata;
+        let key = format!(
+            "{}\n{}\n{}\n{}",
+            metadata.org_name,
+            metadata.report_id,
+            metadata.date_range.begin,
+            metadata.date_range.end
+        );
+        hash_data(key.as_bytes())[..16].to_owned()
+    }
+
+    /// Number of messages that failed DMARC
+    pub fn failed_messages(&self) -> usize {
+        self.record
+            .iter()
+            .filter(|r| !r.is_dmarc_pass())
+            .map(|r| r.row.count)
+            .sum()
+    }
+
+    /// Checks the fields required for a meaningful report,
+    /// for reports that were not parsed from XML but submitted as JSON
+    pub fn validate(&self) -> Result<()> {
+        let metadata = &self.report_metadata;
+        ensure!(
+            !metadata.org_name.trim().is_empty(),
+            "Report has no organization name"
+        );
+        ensure!(!metadata.report_id.trim().is_empty(), "Report has no ID");
+        check_date_range(metadata)?;
+        ensure!(
+            !self.policy_published.domain.trim().is_empty(),
+            "Policy of report {} has no domain",
+            metadata.report_id
+        );
+        for (index, record) in self.record.iter().enumerate() {
+            ensure!(
+                !record.identifiers.header_from.trim().is_empty(),
+                "Record {index} of report {} has no header from domain",
+                metadata.report_id
+            );
+        }
+        Ok(())
+    }
+
+    /// Key identifying a report independent of how often it was delivered
+    pub fn key(&self) -> (&str, &str) {
+        (
+            &self.report_metadata.org_name,
+            &self.report_metadata.report_id,
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs::File;
+    use std::io::BufReader;
 
     #[test]
     fn mailru_report() {
-        let reader = File::open("testdata/dmarc-reports/mailru.xml").unwrap();
-        let report: Report = serde_xml_rs::from_reader(reader).unwrap();
+        let reader = BufReader::new(File::open("testdata/dmarc-reports/mailru.xml").unwrap());
+        let report: Report = quick_xml::de::from_reader(reader).unwrap();
 
         // Check metadata
         assert_eq!(report.report_metadata.org_name, "Mail.Ru");
@@ -232,8 +633,8 @@ mod tests {
 
     #[test]
     fn aol_report() {
-        let reader = File::open("testdata/dmarc-reports/aol.xml").unwrap();
-        let report: Report = serde_xml_rs::from_reader(reader).unwrap();
+        let reader = BufReader::new(File::open("testdata/dmarc-reports/aol.xml").unwrap());
+        let report: Report = quick_xml::de::from_reader(reader).unwrap();
 
         // Check metadata
         assert_eq!(report.report_metadata.org_name, "AOL");
@@ -286,8 +687,8 @@ mod tests {
 
     #[test]
     fn acme_report() {
-        let reader = File::open("testdata/dmarc-reports/acme.xml").unwrap();
-        let report: Report = serde_xml_rs::from_reader(reader).unwrap();
+        let reader = BufReader::new(File::open("testdata/dmarc-reports/acme.xml").unwrap());
+        let report: Report = quick_xml::de::from_reader(reader).unwrap();
 
         // Check metadata
         assert_eq!(report.report_metadata.org_name, "acme.com");
@@ -369,8 +770,8 @@ mod tests {
 
     #[test]
     fn solamora_report() {
-        let reader = File::open("testdata/dmarc-reports/solamora.xml").unwrap();
-        let report: Report = serde_xml_rs::from_reader(reader).unwrap();
+        let reader = BufReader::new(File::open("testdata/dmarc-reports/solamora.xml").unwrap());
+        let report: Report = quick_xml::de::from_reader(reader).unwrap();
 
         // Check metadata
         assert_eq!(report.report_metadata.org_name, "solarmora.com");
@@ -430,8 +831,8 @@ mod tests {
 
     #[test]
     fn yahoo_report() {
-        let reader = File::open("testdata/dmarc-reports/yahoo.xml").unwrap();
-        let report: Report = serde_xml_rs::from_reader(reader).unwrap();
+        let reader = BufReader::new(File::open("testdata/dmarc-reports/yahoo.xml").unwrap());
+        let report: Report = quick_xml::de::from_reader(reader).unwrap();
 
         // Check metadata
         assert_eq!(report.report_metadata.org_name, "Yahoo");
@@ -483,8 +884,8 @@ mod tests {
 
     #[test]
     fn google_report() {
-        let reader = File::open("testdata/dmarc-reports/google.xml").unwrap();
-        let report: Report = serde_xml_rs::from_reader(reader).unwrap();
+        let reader = BufReader::new(File::open("testdata/dmarc-reports/google.xml").unwrap());
+        let report: Report = quick_xml::de::from_reader(reader).unwrap();
 
         // Check metadata
         assert_eq!(report.report_metadata.org_name, "google.com");
@@ -544,8 +945,8 @@ mod tests {
 
     #[test]
     fn outlook_report() {
-        let reader = File::open("testdata/dmarc-reports/outlook.xml").unwrap();
-        let report: Report = serde_xml_rs::from_reader(reader).unwrap();
+        let reader = BufReader::new(File::open("testdata/dmarc-reports/outlook.xml").unwrap());
+        let report: Report = quick_xml::de::from_reader(reader).unwrap();
 
         // Check metadata
         assert_eq!(report.report_metadata.org_name, "Outlook.com");
@@ -650,8 +1051,8 @@ mod tests {
 
     #[test]
     fn web_de_report() {
-        let reader = File::open("testdata/dmarc-reports/webde.xml").unwrap();
-        let report: Report = serde_xml_rs::from_reader(reader).unwrap();
+        let reader = BufReader::new(File::open("testdata/dmarc-reports/webde.xml").unwrap());
+        let report: Report = quick_xml::de::from_reader(reader).unwrap();
 
         // Check metadata
         assert_eq!(report.report_metadata.org_name, "WEB.DE");
@@ -714,4 +1115,139 @@ mod tests {
             }]
         );
     }
+
+    #[test]
+    fn nonstandard_report() {
+        let xml = r#"<?xml version="1.0"?>
+            <feedback>
+                <report_metadata>
+                    <org_name>Vendor</org_name>
+                    <report_id>42</report_id>
+                    <date_range><begin>1700000000</begin><end>1700086400</end></date_range>
+                    <vendor_extension>ignored</vendor_extension>
+                </report_metadata>
+                <policy_published><domain>example.com</domain><p>Reject</p></policy_published>
+                <policy_published><domain>example.com</domain><p>none</p></policy_published>
+                <record>
+                    <row>
+                        <source_ip>192.0.2.1</source_ip>
+                        <count>3</count>
+                        <policy_evaluated>
+                            <disposition>block</disposition>
+                            <dkim>fail</dkim>
+                            <spf>FAIL</spf>
+                            <reason>
+                                <type>local_policy</type>
+                                <comment>arc=pass as[2].d=google.com as[2].s=arc-20160816</comment>
+                            </reason>
+                        </policy_evaluated>
+                    </row>
+                    <identifiers><header_from>example.com</header_from></identifiers>
+                    <auth_results>
+                        <dkim><domain>example.com</domain><result>hardfail</result></dkim>
+                        <smime><domain>example.com</domain><result>pass</result></smime>
+                    </auth_results>
+                </record>
+            </feedback>"#;
+        let report = crate::parser::parse_xml_file(xml.as_bytes()).unwrap();
+        assert_eq!(report.report_metadata.email, "");
+        assert_eq!(report.policy_published.p, DispositionType::Reject);
+        assert_eq!(report.policy_published.sp, None);
+        assert_eq!(report.policy_published.pct, None);
+
+        let record = &report.record[0];
+        assert_eq!(
+            record.row.policy_evaluated.disposition,
+            DispositionType::Unknown(String::from("block"))
+        );
+        assert_eq!(record.row.policy_evaluated.spf, Some(DmarcResultType::Fail));
+        assert_eq!(
+            record.auth_results.dkim.as_ref().unwrap()[0].result,
+            DkimResultType::Unknown(String::from("hardfail"))
+        );
+        assert!(record.auth_results.spf.is_empty());
+        assert_eq!(
+            record.auth_results.arc,
+            vec![ArcAuthResultType {
+                result: String::from("pass"),
+                domain: Some(String::from("google.com")),
+                selector: Some(String::from("arc-20160816")),
+            }]
+        );
+        let other = &record.auth_results.other[0];
+        assert_eq!(other.method, "smime");
+        assert_eq!(other.result.as_deref(), Some("pass"));
+        assert_eq!(other.details["domain"], "example.com");
+    }
+
+    #[test]
+    fn dmarcbis_report() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <feedback xmlns="urn:ietf:params:xml:ns:dmarc-2.0">
+                <version>1.0</version>
+                <report_metadata>
+                    <org_name>Sample Reporter</org_name>
+                    <email>report_sender@example-reporter.com</email>
+                    <report_id>3v98abbp8ya9n3va8yr8oa3ya</report_id>
+                    <date_range><begin>302832000</begin><end>302918399</end></date_range>
+                    <generator>Example DMARC Aggregate Reporter v1.2</generator>
+                </report_metadata>
+                <policy_published>
+                    <domain>example.com</domain>
+                    <p>quarantine</p>
+                    <sp>none</sp>
+                    <np>reject</np>
+                    <testing>n</testing>
+                    <discovery_method>treewalk</discovery_method>
+                </policy_published>
+                <extensions><ext:custom xmlns:ext="urn:example">ignored</ext:custom></extensions>
+                <record>
+                    <row>
+                        <source_ip>192.0.2.123</source_ip>
+                        <count>123</count>
+                        <policy_evaluated>
+                            <disposition>pass</disposition>
+                            <dkim>pass</dkim>
+                            <spf>fail</spf>
+                        </policy_evaluated>
+                    </row>
+                    <identifiers>
+                        <envelope_from>example.com</envelope_from>
+                        <header_from>example.com</header_from>
+                    </identifiers>
+                    <auth_results>
+                        <dkim>
+                            <domain>example.com</domain>
+                            <result>pass</result>
+                            <selector>abc123</selector>
+                        </dkim>
+                        <spf>
+                            <domain>example.com</domain>
+                            <result>fail</result>
+                            <human_result>not authorized</human_result>
+                        </spf>
+                    </auth_results>
+                </record>
+            </feedback>"#;
+        let report = crate::parser::parse_xml_file(xml.as_bytes()).unwrap();
+        assert_eq!(
+            report.report_metadata.generator.as_deref(),
+            Some("Example DMARC Aggregate Reporter v1.2")
+        );
+        let policy = &report.policy_published;
+        assert_eq!(policy.p, DispositionType::Quarantine);
+        assert_eq!(policy.np, Some(DispositionType::Reject));
+        assert_eq!(policy.testing, Some(TestingType::No));
+        assert_eq!(policy.discovery_method, Some(DiscoveryMethodType::TreeWalk));
+        assert_eq!(policy.pct, None);
+
+        let record = &report.record[0];
+        assert_eq!(
+            record.row.policy_evaluated.disposition,
+            DispositionType::None
+        );
+        assert!(record.is_dmarc_pass());
+        assert_eq!(record.auth_results.spf[0].result, SpfResultType::Fail);
+        assert!(record.auth_results.other.is_empty());
+    }
 }
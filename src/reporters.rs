@@ -0,0 +1,196 @@
+use crate::filter::RecordFilter;
+use crate::report::Report;
+use crate::timeseries::DAY;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use utoipa::ToSchema;
+
+/// Minimum number of reports of a reporter for a domain to know its cadence
+const MIN_CADENCE_REPORTS: usize = 3;
+
+/// Reports and messages contributed by a reporting organization
+#[derive(Serialize, Default, ToSchema)]
+pub struct Reporter {
+    pub org: String,
+    pub reports: usize,
+    pub messages: usize,
+
+    /// Messages failing DMARC
+    pub failed: usize,
+
+    /// Policy domains covered by the reports
+    pub domains: BTreeSet<String>,
+
+    /// Begin of the oldest and end of the newest report as Unix timestamps
+    pub first_seen: u64,
+    pub last_seen: u64,
+
+    /// No report was received recently although the reporter sent reports before
+    pub missing: bool,
+}
+
+/// Aggregate all matching reports by reporting organization.
+/// Reporters without reports ending after `recent_since` are marked as missing.
+/// Sorted by descending message count.
+pub fn reporters(reports: &[Report], filter: &RecordFilter, recent_since: u64) -> Vec<Reporter> {
+    let mut reporters: HashMap<&str, Reporter> = HashMap::new();
+    for report in reports.iter().filter(|r| filter.matches_report(r)) {
+        let metadata = &report.report_metadata;
+        let reporter = reporters
+            .entry(&metadata.org_name)
+            .or_insert_with(|| Reporter {
+                org: metadata.org_name.clone(),
+                first_seen: metadata.date_range.begin,
+                ..Default::default()
+            });
+        reporter.reports += 1;
+        reporter
+            .domains
+            .insert(filter.group_domain(&report.policy_published.domain));
+        reporter.first_seen = reporter.first_seen.min(metadata.date_range.begin);
+        reporter.last_seen = reporter.last_seen.max(metadata.date_range.end);
+        for record in report.record.iter().filter(|r| filter.matches_record(r)) {
+            reporter.messages += record.row.count;
+            if !record.is_dmarc_pass() {
+                reporter.failed += record.row.count;
+            }
+        }
+    }
+    let mut reporters: Vec<Reporter> = reporters
+        .into_values()
+        .map(|mut r| {
+            r.missing = r.last_seen < recent_since;
+            r
+        })
+        .collect();
+    reporters.sort_by(|a, b| b.messages.cmp(&a.messages).then(a.org.cmp(&b.org)));
+    reporters
+}
+
+/// Reporter that used to send daily reports for a domain but went silent
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct DeliveryGap {
+    pub org: String,
+    pub domain: String,
+    pub reports: usize,
+
+    /// Median time between the ends of consecutive reports in seconds
+    pub interval: u64,
+
+    /// End of the newest report as Unix timestamp
+    pub last_seen: u64,
+
+    /// Full days since the end of the newest report
+    pub silent_days: u64,
+}
+
+/// Reporters with a daily cadence per domain that sent no report for at least `gap_days`.
+/// A silent reporter that used to send reports every day often means a broken RUA address or DNS record.
+/// Sorted by the longest silence first.
+pub fn delivery_gaps(
+    reports: &[Report],
+    filter: &RecordFilter,
+    now: u64,
+    gap_days: u64,
+) -> Vec<DeliveryGap> {
+    let mut ends: HashMap<(&str, String), Vec<u64>> = HashMap::new();
+    for report in reports.iter().filter(|r| filter.matches_report(r)) {
+        let metadata = &report.report_metadata;
+        let domain = filter.group_domain(&report.policy_published.domain);
+        ends.entry((&metadata.org_name, domain))
+            .or_default()
+            .push(metadata.date_range.end);
+    }
+    let mut gaps: Vec<DeliveryGap> = ends
+        .into_iter()
+        .filter(|(_, ends)| ends.len() >= MIN_CADENCE_REPORTS)
+        .filter_map(|((org, domain), mut ends)| {
+            ends.sort_unstable();
+            let mut intervals: Vec<u64> = ends.windows(2).map(|w| w[1] - w[0]).collect();
+            intervals.sort_unstable();
+            let interval = intervals[intervals.len() / 2];
+            let last_seen = *ends.last()?;
+            let silent_days = now.saturating_sub(last_seen) / DAY;
+            (interval <= DAY && silent_days >= gap_days).then(|| DeliveryGap {
+                org: org.to_owned(),
+                domain,
+                reports: ends.len(),
+                interval,
+                last_seen,
+                silent_days,
+            })
+        })
+        .collect();
+    gaps.sort_by(|a, b| {
+        a.last_seen
+            .cmp(&b.last_seen)
+            .then_with(|| a.org.cmp(&b.org))
+            .then_with(|| a.domain.cmp(&b.domain))
+    });
+    gaps
+}
+
+/// Reporters currently silent per domain.
+/// Remembered across update cycles to only alert when a reporter goes silent.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct GapTracker {
+    silent: HashSet<(String, String)>,
+}
+
+impl GapTracker {
+    /// Returns the gaps that started since the last update
+    pub fn update(&mut self, gaps: &[DeliveryGap]) -> Vec<DeliveryGap> {
+        let silent: HashSet<(String, String)> = gaps
+            .iter()
+            .map(|g| (g.org.clone(), g.domain.clone()))
+            .collect();
+        let started = gaps
+            .iter()
+            .filter(|g| !self.silent.contains(&(g.org.clone(), g.domain.clone())))
+            .cloned()
+            .collect();
+        self.silent = silent;
+        started
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn silent_daily_reporter() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let daily: Vec<Report> = (1..=5)
+            .map(|day| {
+                let mut report = report.clone();
+                report.report_metadata.date_range.end = day * DAY;
+                report
+            })
+            .collect();
+        let filter = RecordFilter::default();
+        assert!(delivery_gaps(&daily, &filter, 7 * DAY, 3).is_empty());
+        let gaps = delivery_gaps(&daily, &filter, 8 * DAY + 1, 3);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].interval, DAY);
+        assert_eq!(gaps[0].silent_days, 3);
+
+        let weekly: Vec<Report> = daily
+            .into_iter()
+            .map(|mut report| {
+                report.report_metadata.date_range.end *= 7;
+                report
+            })
+            .collect();
+        assert!(delivery_gaps(&weekly, &filter, 60 * DAY, 3).is_empty());
+
+        let mut tracker = GapTracker::default();
+        assert_eq!(tracker.update(&gaps).len(), 1);
+        assert!(tracker.update(&gaps).is_empty());
+        assert!(tracker.update(&[]).is_empty());
+        assert_eq!(tracker.update(&gaps).len(), 1);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::config::Configuration;
+use crate::state::AppState;
+use crate::timeseries::DAY;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Limits for the reports and mails kept in memory
+pub struct Retention {
+    /// Maximum age of reports and mails in seconds
+    pub max_age: Option<u64>,
+    pub max_reports: Option<usize>,
+    pub max_mails: Option<usize>,
+}
+
+/// Number of reports and mails removed from the state
+#[derive(Default, Debug, PartialEq)]
+pub struct Evicted {
+    pub reports: usize,
+    pub mails: usize,
+}
+
+impl Retention {
+    pub fn new(config: &Configuration) -> Self {
+        Self {
+            max_age: config.max_report_age.map(|days| days * DAY),
+            max_reports: config.max_reports,
+            max_mails: config.max_mails,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_age.is_some() || self.max_reports.is_some() || self.max_mails.is_some()
+    }
+
+    /// Removes reports and mails that are too old or exceed the maximum count, oldest first.
+    /// Results of evicted mails are removed as well and their UIDs are remembered
+    /// to not download and parse them again in the next update cycle.
+    pub fn apply(&self, state: &mut AppState, now: u64) -> Evicted {
+        let mut evicted_uids = HashSet::new();
+
+        let mut mails: Vec<(u32, i64)> = state.mails.values().map(|m| (m.uid, m.date)).collect();
+        mails.sort_unstable_by_key(|(uid, date)| (*date, *uid));
+        if let Some(max_age) = self.max_age {
+            let min_date = now.saturating_sub(max_age) as i64;
+            evicted_uids.extend(
+                mails
+                    .iter()
+                    .take_while(|(_, date)| *date < min_date)
+                    .map(|(uid, _)| *uid),
+            );
+        }
+        if let Some(max_mails) = self.max_mails {
+            let excess = mails.len().saturating_sub(max_mails);
+            evicted_uids.extend(mails.iter().take(excess).map(|(uid, _)| *uid));
+        }
+        Arc::make_mut(&mut state.mails).retain(|uid, _| !evicted_uids.contains(uid));
+        Arc::make_mut(&mut state.xml_errors).retain(|e| !evicted_uids.contains(&e.mail_uid));
+        Arc::make_mut(&mut state.duplicates)
+            .retain(|d| d.mail_uid.is_none_or(|uid| !evicted_uids.contains(&uid)));
+
+        let report_count = state.reports.len();
+        let reports = Arc::make_mut(&mut state.reports);
+        reports.retain(|r| r.mail_uid.is_none_or(|uid| !evicted_uids.contains(&uid)));
+        if let Some(max_age) = self.max_age {
+            let min_end = now.saturating_sub(max_age);
+            reports.retain(|r| r.report_metadata.date_range.end >= min_end);
+        }
+        if let Some(max_reports) = self.max_reports {
+            if reports.len() > max_reports {
+                // Keep the order of the remaining reports
+                let mut indices: Vec<usize> = (0..reports.len()).collect();
+                indices.sort_by_key(|i| Reverse(reports[*i].report_metadata.date_range.end));
+                let keep: HashSet<usize> = indices.into_iter().take(max_reports).collect();
+                let mut index = 0;
+                reports.retain(|_| {
+                    index += 1;
+                    keep.contains(&(index - 1))
+                });
+            }
+        }
+
+        let evicted = Evicted {
+            reports: report_count - state.reports.len(),
+            mails: evicted_uids.len(),
+        };
+        Arc::make_mut(&mut state.evicted_uids).extend(evicted_uids);
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::Mail;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    fn mail(uid: u32, date: i64) -> Mail {
+        Mail {
+            uid,
+            size: 0,
+            oversized: false,
+            date,
+            subject: String::new(),
+            sender: String::new(),
+            to: String::new(),
+            attachments: 0,
+            message_id: None,
+            body: None,
+            duplicate_of: None,
+        }
+    }
+
+    #[test]
+    fn evict_old_reports_and_mails() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let template = parse_xml_file(&xml).unwrap();
+        let mut state = AppState::default();
+        for uid in 1..=3 {
+            Arc::make_mut(&mut state.mails)
+                .insert(uid, mail(uid, i64::from(uid) * 10 * DAY as i64));
+            let mut report = template.clone();
+            report.mail_uid = Some(uid);
+            report.report_metadata.report_id = uid.to_string();
+            report.report_metadata.date_range.end = u64::from(uid) * 10 * DAY;
+            Arc::make_mut(&mut state.reports).push(report);
+        }
+
+        let retention = Retention {
+            max_age: Some(15 * DAY),
+            max_reports: None,
+            max_mails: Some(1),
+        };
+        let evicted = retention.apply(&mut state, 30 * DAY);
+        assert_eq!(
+            evicted,
+            Evicted {
+                reports: 2,
+                mails: 2
+            }
+        );
+        assert_eq!(state.mails.keys().collect::<Vec<_>>(), vec![&3]);
+        assert_eq!(state.reports.len(), 1);
+        assert_eq!(state.reports[0].mail_uid, Some(3));
+        assert_eq!(*state.evicted_uids, HashSet::from([1, 2]));
+    }
+}
@@ -0,0 +1,257 @@
+use crate::anonymize::Anonymizer;
+use crate::filter::RecordFilter;
+use crate::report::{DispositionType, Report};
+use crate::timeseries::{Bucket, Interval, Timezone, DAY};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// Counts of all reports about a domain for one day
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug, ToSchema)]
+pub struct DailyRollup {
+    /// Unix timestamp of the start of the day in UTC
+    pub day: u64,
+
+    /// Domain of the published policy
+    pub domain: String,
+
+    pub reports: usize,
+    pub messages: usize,
+
+    /// Messages with passed DKIM or SPF policy evaluation
+    pub passed: usize,
+
+    /// Messages where DKIM and SPF policy evaluation did not pass
+    pub failed: usize,
+
+    pub disposition_none: usize,
+    pub disposition_quarantine: usize,
+    pub disposition_reject: usize,
+
+    /// Distinct source IPs
+    pub sources: usize,
+}
+
+impl DailyRollup {
+    fn matches(&self, filter: &RecordFilter) -> bool {
+        filter.allows_domain(&self.domain)
+            && filter
+                .domain
+                .as_ref()
+                .is_none_or(|d| filter.matches_domain(d, &self.domain))
+            && filter.since.is_none_or(|since| self.day + DAY > since)
+            && filter.until.is_none_or(|until| self.day <= until)
+    }
+}
+
+/// Rows per day and domain persisted with the state.
+/// They are kept when the retention limits remove their reports,
+/// so long-range trends are available without keeping every report.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Rollups {
+    /// Sorted by day and domain
+    rows: Vec<DailyRollup>,
+}
+
+impl Rollups {
+    /// Same rollups with pseudonyms for the domains
+    pub fn anonymized(&self, anonymizer: &Anonymizer) -> Self {
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| DailyRollup {
+                domain: anonymizer.domain(&row.domain),
+                ..row.clone()
+            })
+            .collect();
+        Self { rows }
+    }
+
+    /// Adds or replaces the rows of all days and domains with reports.
+    /// Rows with more reports than there are now keep their counts,
+    /// since the missing reports were removed by the retention limits.
+    /// Returns the number of added rows.
+    pub fn update(&mut self, reports: &[Report]) -> usize {
+        let mut rows = self.take_rows();
+        let mut added = 0;
+        for (key, row) in daily_rollups(reports) {
+            match rows.get(&key) {
+                Some(previous) if previous.reports > row.reports => {}
+                previous => {
+                    added += usize::from(previous.is_none());
+                    rows.insert(key, row);
+                }
+            }
+        }
+        self.rows = rows.into_values().collect();
+        added
+    }
+
+    /// Calculates the rows of purged reports again from the remaining ones.
+    /// Rows of expired reports are removed if they match the domain and date range of the filter,
+    /// unless the filter only purged the reports of a single reporter.
+    pub fn purge(&mut self, filter: &RecordFilter, purged: &[Report], reports: &[Report]) {
+        let mut rows = self.take_rows();
+        let mut keys: HashSet<(u64, String)> = daily_rollups(purged).into_keys().collect();
+        if filter.org.is_none() {
+            keys.extend(
+                rows.iter()
+                    .filter(|(_, r)| r.matches(filter))
+                    .map(|(k, _)| k.clone()),
+            );
+        }
+        let mut remaining = daily_rollups(reports);
+        for key in keys {
+            match remaining.remove(&key) {
+                Some(row) => rows.insert(key, row),
+                None => rows.remove(&key),
+            };
+        }
+        self.rows = rows.into_values().collect();
+    }
+
+    /// Rows matching the domain and date range of the filter, oldest first
+    pub fn list(&self, filter: &RecordFilter) -> Vec<DailyRollup> {
+        self.rows
+            .iter()
+            .filter(|r| r.matches(filter))
+            .cloned()
+            .collect()
+    }
+
+    /// Message counts of the matching rows per day or week in UTC.
+    /// Buckets without rows between the first and last bucket are included with zero counts.
+    pub fn time_series(&self, filter: &RecordFilter, interval: Interval) -> Vec<Bucket> {
+        let mut buckets: BTreeMap<u64, Bucket> = BTreeMap::new();
+        for row in self.rows.iter().filter(|r| r.matches(filter)) {
+            let start = interval.bucket_start(row.day, Timezone::default());
+            let bucket = buckets.entry(start).or_insert_with(|| Bucket {
+                start,
+                ..Default::default()
+            });
+            bucket.messages += row.messages;
+            bucket.passed += row.passed;
+            bucket.failed += row.failed;
+            bucket.disposition_none += row.disposition_none;
+            bucket.disposition_quarantine += row.disposition_quarantine;
+            bucket.disposition_reject += row.disposition_reject;
+        }
+
+        let (Some(first), Some(last)) = (
+            buckets.keys().next().copied(),
+            buckets.keys().last().copied(),
+        ) else {
+            return Vec::new();
+        };
+        (first..=last)
+            .step_by(interval.duration() as usize)
+            .map(|start| {
+                buckets.remove(&start).unwrap_or_else(|| Bucket {
+                    start,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn take_rows(&mut self) -> BTreeMap<(u64, String), DailyRollup> {
+        std::mem::take(&mut self.rows)
+            .into_iter()
+            .map(|r| ((r.day, r.domain.clone()), r))
+            .collect()
+    }
+}
+
+/// Rows of the reports by day and domain.
+/// A report counts for the UTC day containing the middle of its date range,
+/// so reporters with date ranges in their local time end up on the day they meant.
+fn daily_rollups(reports: &[Report]) -> BTreeMap<(u64, String), DailyRollup> {
+    let mut rows: BTreeMap<(u64, String), (DailyRollup, HashSet<IpAddr>)> = BTreeMap::new();
+    for report in reports {
+        let range = &report.report_metadata.date_range;
+        let middle = range.begin / 2 + range.end.max(range.begin) / 2;
+        let day = middle - middle % DAY;
+        let domain = report.policy_published.domain.to_lowercase();
+        let (row, sources) = rows.entry((day, domain.clone())).or_insert_with(|| {
+            let row = DailyRollup {
+                day,
+                domain,
+                ..Default::default()
+            };
+            (row, HashSet::new())
+        });
+        row.reports += 1;
+        for record in &report.record {
+            let count = record.row.count;
+            row.messages += count;
+            if record.is_dmarc_pass() {
+                row.passed += count;
+            } else {
+                row.failed += count;
+            }
+            match record.row.policy_evaluated.disposition {
+                DispositionType::None => row.disposition_none += count,
+                DispositionType::Quarantine => row.disposition_quarantine += count,
+                DispositionType::Reject => row.disposition_reject += count,
+                DispositionType::Unknown(..) => {}
+            }
+            sources.insert(record.row.source_ip);
+        }
+    }
+    rows.into_iter()
+        .map(|(key, (mut row, sources))| {
+            row.sources = sources.len();
+            (key, row)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn keep_rows_of_expired_reports() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let template = parse_xml_file(&xml).unwrap();
+        let mut reports: Vec<Report> = (0..3)
+            .map(|day| {
+                let mut report = template.clone();
+                report.report_metadata.report_id = day.to_string();
+                report.report_metadata.date_range.begin = day * DAY;
+                report.report_metadata.date_range.end = (day + 1) * DAY - 1;
+                report
+            })
+            .collect();
+        let mut rollups = Rollups::default();
+        assert_eq!(rollups.update(&reports), 3);
+        let filter = RecordFilter::default();
+        let rows = rollups.list(&filter);
+        assert_eq!(rows[1].day, DAY);
+        assert_eq!(rows[1].reports, 1);
+        assert_eq!(rows[1].messages, template.record[0].row.count);
+        assert_eq!(rows[1].sources, 1);
+
+        // Rows stay after the retention removed their reports
+        reports.remove(0);
+        assert_eq!(rollups.update(&reports), 0);
+        assert_eq!(rollups.list(&filter).len(), 3);
+        let series = rollups.time_series(&filter, Interval::Daily);
+        assert_eq!(series.len(), 3);
+        assert_eq!(series[0].messages, template.record[0].row.count);
+
+        // Purging removes the rows of the purged domain and day, including expired reports
+        let filter = RecordFilter {
+            until: Some(DAY),
+            ..Default::default()
+        };
+        let purged = vec![reports.remove(0)];
+        rollups.purge(&filter, &purged, &reports);
+        let rows = rollups.list(&RecordFilter::default());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].day, 2 * DAY);
+    }
+}
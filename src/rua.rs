@@ -0,0 +1,151 @@
+use crate::dns::{record_tag, DnsResolver};
+use crate::psl::organizational_domain;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Destination of aggregate reports from the rua tag of a DMARC record
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RuaTarget {
+    /// URI as published, including an optional size limit
+    pub uri: String,
+
+    /// Mail address of a `mailto` URI
+    pub address: Option<String>,
+
+    /// Address is one of the mailboxes read by this instance
+    pub watched: bool,
+
+    /// Address is outside of the organizational domain and needs an authorization record
+    pub external: bool,
+
+    /// Destination published the authorization record, empty for internal addresses
+    pub authorized: Option<bool>,
+}
+
+/// Result of checking where the aggregate reports of a domain are sent to
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RuaCheck {
+    /// DMARC record at `_dmarc.<domain>`, empty if none is published or the lookup failed
+    pub record: Option<String>,
+
+    pub targets: Vec<RuaTarget>,
+
+    /// Reports that are sent somewhere this instance never sees them and other problems
+    pub problems: Vec<String>,
+}
+
+/// Check the rua tag of the DMARC record of the domain against the addresses read by this instance.
+/// External destinations must confirm with a record at `<domain>._report._dmarc.<destination>`,
+/// otherwise reporters do not send the reports.
+pub async fn check_domain(dns: &DnsResolver, domain: &str, watched: &[String]) -> RuaCheck {
+    let mut check = RuaCheck {
+        record: None,
+        targets: Vec::new(),
+        problems: Vec::new(),
+    };
+    let name = format!("_dmarc.{domain}");
+    let record = match dns.txt(&name).await {
+        Ok(records) => records
+            .into_iter()
+            .find(|r| r.trim_start().starts_with("v=DMARC1")),
+        Err(err) => {
+            check.problems.push(format!("{err:#}"));
+            return check;
+        }
+    };
+    let Some(record) = record else {
+        check.problems.push(format!("No DMARC record at {name}"));
+        return check;
+    };
+    let rua = record_tag(&record, "rua");
+    check.record = Some(record);
+    let Some(rua) = rua else {
+        check
+            .problems
+            .push(format!("The DMARC record of {domain} has no rua tag"));
+        return check;
+    };
+
+    let org_domain = organizational_domain(domain);
+    for uri in parse_rua(&rua) {
+        let address = uri
+            .get(..7)
+            .filter(|scheme| scheme.eq_ignore_ascii_case("mailto:"))
+            .map(|_| mailto_address(&uri[7..]));
+        let mut target = RuaTarget {
+            watched: address
+                .as_ref()
+                .is_some_and(|a| watched.iter().any(|w| w.eq_ignore_ascii_case(a))),
+            uri,
+            address: None,
+            external: false,
+            authorized: None,
+        };
+        let Some((_, destination)) = address.as_deref().and_then(|a| a.rsplit_once('@')) else {
+            check
+                .problems
+                .push(format!("Unsupported reporting URI {}", target.uri));
+            check.targets.push(target);
+            continue;
+        };
+        target.external = organizational_domain(destination) != org_domain;
+        if target.external {
+            let name = format!("{domain}._report._dmarc.{destination}");
+            let authorized = match dns.txt(&name).await {
+                Ok(records) => records
+                    .iter()
+                    .any(|r| r.trim_start().starts_with("v=DMARC1")),
+                Err(err) => {
+                    check.problems.push(format!("{err:#}"));
+                    false
+                }
+            };
+            if !authorized {
+                check.problems.push(format!(
+                    "{destination} does not accept reports for {domain} without a DMARC1 record at {name}"
+                ));
+            }
+            target.authorized = Some(authorized);
+        }
+        target.address = address;
+        check.targets.push(target);
+    }
+    if !watched.is_empty() && !check.targets.iter().any(|t| t.watched) {
+        check.problems.push(format!(
+            "Reports for {domain} are sent to {rua} and never reach {}",
+            watched.join(", ")
+        ));
+    }
+    check
+}
+
+/// URIs of a rua tag, which are separated by commas
+fn parse_rua(rua: &str) -> Vec<String> {
+    rua.split(',')
+        .map(str::trim)
+        .filter(|uri| !uri.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Address of a mailto URI without the optional size limit after `!`
+fn mailto_address(address: &str) -> String {
+    let address = address.split('!').next().unwrap_or_default();
+    address.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reporting_uris() {
+        let uris = parse_rua("mailto:dmarc@example.com!10m, mailto:Reports@Example.NET,,");
+        assert_eq!(
+            uris,
+            vec!["mailto:dmarc@example.com!10m", "mailto:Reports@Example.NET"]
+        );
+        assert_eq!(mailto_address("dmarc@example.com!10m"), "dmarc@example.com");
+        assert_eq!(mailto_address("Reports@Example.NET"), "reports@example.net");
+    }
+}
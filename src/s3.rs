@@ -0,0 +1,151 @@
+use crate::archive::Archive;
+use crate::config::Configuration;
+use crate::ingest::ingest_file;
+use crate::parser::{ExtractLimits, ParseOptions};
+use crate::state::SharedState;
+use anyhow::{Context, Result};
+use futures::TryStreamExt;
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Create a client for the configured S3 compatible object storage.
+/// Returns nothing if no bucket is configured.
+pub fn s3_store(config: &Configuration) -> Result<Option<Arc<dyn ObjectStore>>> {
+    let Some(bucket) = &config.s3_bucket else {
+        return Ok(None);
+    };
+    let mut builder = AmazonS3Builder::new()
+        .with_bucket_name(bucket)
+        .with_region(&config.s3_region);
+    if let Some(endpoint) = &config.s3_endpoint {
+        builder = builder
+            .with_allow_http(endpoint.starts_with("http://"))
+            .with_endpoint(endpoint);
+    }
+    if let Some(access_key) = &config.s3_access_key {
+        builder = builder.with_access_key_id(access_key);
+    }
+    if let Some(secret_key) = &config.s3_secret_key {
+        builder = builder.with_secret_access_key(secret_key);
+    }
+    let store = builder.build().context("Failed to create S3 client")?;
+    Ok(Some(Arc::new(store)))
+}
+
+/// Join a prefix and a relative path to an object path
+pub fn object_path(prefix: &str, relative_path: &str) -> Path {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        Path::from(relative_path)
+    } else {
+        Path::from(format!("{prefix}/{relative_path}"))
+    }
+}
+
+/// Archive of raw report files and JSON exports or the scheduled exports in an S3 bucket
+pub struct S3Archive {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+}
+
+impl S3Archive {
+    /// Returns nothing if archiving to S3 is not configured
+    pub fn new(config: &Configuration, store: &Option<Arc<dyn ObjectStore>>) -> Option<Self> {
+        let prefix = config.s3_archive_prefix.as_ref()?;
+        Some(Self {
+            store: store.as_ref()?.clone(),
+            prefix: prefix.clone(),
+        })
+    }
+
+    /// Returns nothing if scheduled exports to S3 are not configured
+    pub fn exports(config: &Configuration, store: &Option<Arc<dyn ObjectStore>>) -> Option<Self> {
+        let prefix = config.s3_export_prefix.as_ref()?;
+        Some(Self {
+            store: store.as_ref()?.clone(),
+            prefix: prefix.clone(),
+        })
+    }
+
+    /// Upload files given by their relative archive path and content.
+    /// Existing objects with the same path are overwritten.
+    pub async fn upload(&self, files: Vec<(String, Vec<u8>)>) -> Result<usize> {
+        let count = files.len();
+        for (relative_path, data) in files {
+            let path = object_path(&self.prefix, &relative_path);
+            self.store
+                .put(&path, data.into())
+                .await
+                .with_context(|| format!("Failed to upload {path} to S3"))?;
+        }
+        Ok(count)
+    }
+}
+
+/// Source of report files delivered directly into an S3 bucket
+pub struct S3Source {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+    parse_options: ParseOptions,
+    limits: ExtractLimits,
+}
+
+impl S3Source {
+    /// Returns nothing if ingesting from S3 is not configured
+    pub fn new(config: &Configuration, store: &Option<Arc<dyn ObjectStore>>) -> Option<Self> {
+        let prefix = config.s3_ingest_prefix.as_ref()?;
+        Some(Self {
+            store: store.as_ref()?.clone(),
+            prefix: prefix.clone(),
+            parse_options: ParseOptions::from(config),
+            limits: ExtractLimits::from(config),
+        })
+    }
+
+    /// Download all objects below the prefix that were not processed before
+    /// and add their reports to the shared state.
+    /// Objects that fail to parse are skipped and not downloaded again.
+    /// Returns the number of added reports.
+    pub async fn ingest(
+        &self,
+        state: &Arc<SharedState>,
+        archive: Option<&Archive>,
+    ) -> Result<usize> {
+        let prefix = Path::from(self.prefix.trim_matches('/'));
+        let objects: Vec<_> = self
+            .store
+            .list(Some(&prefix))
+            .try_collect()
+            .await
+            .context("Failed to list S3 objects")?;
+        let new_objects: Vec<Path> = {
+            let locked_state = state.original();
+            objects
+                .into_iter()
+                .map(|o| o.location)
+                .filter(|l| !locked_state.s3_objects.contains(l.as_ref()))
+                .collect()
+        };
+
+        let mut reports = 0;
+        for location in new_objects {
+            let data = self
+                .store
+                .get(&location)
+                .await
+                .with_context(|| format!("Failed to download S3 object {location}"))?
+                .bytes()
+                .await
+                .with_context(|| format!("Failed to read S3 object {location}"))?;
+            match ingest_file(state, archive, &data, &self.parse_options, &self.limits) {
+                Ok(count) => reports += count,
+                Err(err) => warn!("Failed to ingest S3 object {location}: {err:#}"),
+            }
+            state.update(|state| Arc::make_mut(&mut state.s3_objects).insert(location.to_string()));
+        }
+        Ok(reports)
+    }
+}
@@ -0,0 +1,190 @@
+use crate::report::{RecordType, Report};
+use serde::Serialize;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// Search term for reports.
+/// IP addresses and networks in CIDR notation match source IPs,
+/// any other text matches names, IDs, domains and selectors
+/// as well as the beginning of source IPs.
+pub enum SearchTerm {
+    Network(IpAddr, u8),
+    Text(String),
+}
+
+/// Report with the fields and records that matched the search term
+#[derive(Serialize, ToSchema)]
+pub struct SearchResult {
+    pub id: String,
+    pub org: String,
+    pub domain: String,
+    pub date_begin: u64,
+    pub date_end: u64,
+    pub fields: Vec<&'static str>,
+    pub records: Vec<usize>,
+}
+
+impl SearchTerm {
+    pub fn parse(query: &str) -> Self {
+        let query = query.trim();
+        if let Ok(ip) = query.parse::<IpAddr>() {
+            let prefix = if ip.is_ipv4() { 32 } else { 128 };
+            return Self::Network(ip, prefix);
+        }
+        if let Some((ip, prefix)) = query.split_once('/') {
+            if let (Ok(ip), Ok(prefix)) = (ip.parse::<IpAddr>(), prefix.parse::<u8>()) {
+                if prefix <= if ip.is_ipv4() { 32 } else { 128 } {
+                    return Self::Network(ip, prefix);
+                }
+            }
+        }
+        Self::Text(query.to_lowercase())
+    }
+
+    /// Returns nothing if neither the report nor one of its records matched
+    pub fn search(&self, report: &Report) -> Option<SearchResult> {
+        let mut fields = Vec::new();
+        if let Self::Text(text) = self {
+            let metadata = &report.report_metadata;
+            if contains(&metadata.org_name, text) {
+                fields.push("org");
+            }
+            if contains(&metadata.report_id, text) {
+                fields.push("report_id");
+            }
+            if contains(&report.policy_published.domain, text) {
+                fields.push("domain");
+            }
+        }
+        let mut records = Vec::new();
+        for (index, record) in report.record.iter().enumerate() {
+            let record_fields = self.record_fields(record);
+            if !record_fields.is_empty() {
+                records.push(index);
+                for field in record_fields {
+                    if !fields.contains(&field) {
+                        fields.push(field);
+                    }
+                }
+            }
+        }
+        if fields.is_empty() {
+            return None;
+        }
+        Some(SearchResult {
+            id: report.report_metadata.report_id.clone(),
+            org: report.report_metadata.org_name.clone(),
+            domain: report.policy_published.domain.clone(),
+            date_begin: report.report_metadata.date_range.begin,
+            date_end: report.report_metadata.date_range.end,
+            fields,
+            records,
+        })
+    }
+
+    fn record_fields(&self, record: &RecordType) -> Vec<&'static str> {
+        let text = match self {
+            Self::Network(network, prefix) => {
+                return if in_network(record.row.source_ip, *network, *prefix) {
+                    vec!["source_ip"]
+                } else {
+                    Vec::new()
+                };
+            }
+            Self::Text(text) => text,
+        };
+        let mut fields = Vec::new();
+        if record.row.source_ip.to_string().starts_with(text.as_str()) {
+            fields.push("source_ip");
+        }
+        let identifiers = &record.identifiers;
+        if contains(&identifiers.header_from, text) {
+            fields.push("header_from");
+        }
+        if identifiers
+            .envelope_from
+            .as_deref()
+            .is_some_and(|d| contains(d, text))
+        {
+            fields.push("envelope_from");
+        }
+        if identifiers
+            .envelope_to
+            .as_deref()
+            .is_some_and(|d| contains(d, text))
+        {
+            fields.push("envelope_to");
+        }
+        let dkim = record.auth_results.dkim.iter().flatten();
+        if dkim.clone().any(|r| contains(&r.domain, text)) {
+            fields.push("dkim_domain");
+        }
+        if dkim
+            .filter_map(|r| r.selector.as_deref())
+            .any(|s| contains(s, text))
+        {
+            fields.push("selector");
+        }
+        if record
+            .auth_results
+            .spf
+            .iter()
+            .any(|r| contains(&r.domain, text))
+        {
+            fields.push("spf_domain");
+        }
+        fields
+    }
+}
+
+/// Case insensitive substring match with an already lowercase text
+fn contains(value: &str, text: &str) -> bool {
+    value.to_lowercase().contains(text)
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn search_reports() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let ip = report.record[0].row.source_ip;
+        let selector = report.record[0].auth_results.dkim.as_ref().unwrap()[0]
+            .selector
+            .clone()
+            .unwrap();
+
+        let result = SearchTerm::parse(&selector.to_uppercase())
+            .search(&report)
+            .unwrap();
+        assert!(result.fields.contains(&"selector"));
+        assert_eq!(result.records, vec![0]);
+
+        let network = match ip {
+            IpAddr::V4(..) => format!("{ip}/16"),
+            IpAddr::V6(..) => format!("{ip}/48"),
+        };
+        let result = SearchTerm::parse(&network).search(&report).unwrap();
+        assert_eq!(result.fields, vec!["source_ip"]);
+
+        assert!(SearchTerm::parse("no-such-thing").search(&report).is_none());
+    }
+}
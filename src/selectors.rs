@@ -0,0 +1,74 @@
+use crate::filter::RecordFilter;
+use crate::report::{DkimResultType, Report};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use utoipa::ToSchema;
+
+/// Usage of a DKIM selector of a signing domain observed in the auth results
+#[derive(Serialize, Default, ToSchema)]
+pub struct SelectorUsage {
+    pub domain: String,
+    pub selector: String,
+    pub messages: usize,
+
+    /// Messages with a passing DKIM signature using this selector
+    pub passed: usize,
+    pub pass_rate: f64,
+
+    /// Begin of the oldest and end of the newest report with the selector as Unix timestamps
+    pub first_seen: u64,
+    pub last_seen: u64,
+
+    /// Header from domains of the signed messages
+    pub header_from: BTreeSet<String>,
+
+    /// Reporting organizations that observed the selector
+    pub orgs: BTreeSet<String>,
+}
+
+/// Inventory of all DKIM selectors in matching records, sorted by domain and selector.
+/// Signatures without selector are listed with an empty selector.
+pub fn selector_inventory(reports: &[Report], filter: &RecordFilter) -> Vec<SelectorUsage> {
+    let mut selectors: BTreeMap<(String, String), SelectorUsage> = BTreeMap::new();
+    for (report, record) in filter.records(reports) {
+        let range = &report.report_metadata.date_range;
+        // Count each selector only once per record
+        let mut results: BTreeMap<(String, String), bool> = BTreeMap::new();
+        for result in record.auth_results.dkim.iter().flatten() {
+            let key = (
+                result.domain.to_lowercase(),
+                result.selector.clone().unwrap_or_default(),
+            );
+            *results.entry(key).or_default() |= result.result == DkimResultType::Pass;
+        }
+        for ((domain, selector), passed) in results {
+            let usage = selectors
+                .entry((domain.clone(), selector.clone()))
+                .or_insert_with(|| SelectorUsage {
+                    domain,
+                    selector,
+                    first_seen: range.begin,
+                    ..Default::default()
+                });
+            usage.messages += record.row.count;
+            if passed {
+                usage.passed += record.row.count;
+            }
+            usage.first_seen = usage.first_seen.min(range.begin);
+            usage.last_seen = usage.last_seen.max(range.end);
+            usage
+                .header_from
+                .insert(record.identifiers.header_from.to_lowercase());
+            usage.orgs.insert(report.report_metadata.org_name.clone());
+        }
+    }
+    selectors
+        .into_values()
+        .map(|mut usage| {
+            if usage.messages > 0 {
+                usage.pass_rate = usage.passed as f64 * 100.0 / usage.messages as f64;
+            }
+            usage
+        })
+        .collect()
+}
@@ -0,0 +1,165 @@
+use crate::config::Configuration;
+use crate::status::unix_time;
+use anyhow::{anyhow, Result};
+use axum::http::{header, HeaderMap};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use utoipa::ToSchema;
+
+/// Name of the cookie with the signed session
+const COOKIE_NAME: &str = "dmarc_session";
+
+/// User of the session cookie of a request, used by the UI to offer a logout
+#[derive(Clone, Serialize, ToSchema)]
+pub struct SessionUser {
+    pub user: String,
+}
+
+/// Signed session cookies issued by the login form.
+/// Sessions are stateless except for the ones ended by a logout before they expire.
+pub struct Sessions {
+    key: hmac::Key,
+    lifetime: u64,
+
+    /// Ended sessions with their expiration time
+    revoked: Mutex<HashMap<String, u64>>,
+}
+
+impl Sessions {
+    /// Uses the configured secret or a random one, which ends all sessions on restart
+    pub fn new(config: &Configuration) -> Result<Self> {
+        let key = match &config.http_session_secret {
+            Some(secret) => hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            None => hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+                .map_err(|_| anyhow!("Failed to generate session key"))?,
+        };
+        Ok(Self {
+            key,
+            lifetime: config.http_session_lifetime,
+            revoked: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn lifetime(&self) -> u64 {
+        self.lifetime
+    }
+
+    /// Cookie value for a new session of the user
+    pub fn create(&self, user: &str) -> Result<String> {
+        let mut nonce = [0; 16];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("Failed to generate session ID"))?;
+        let payload = format!(
+            "{}.{}.{}",
+            unix_time() + self.lifetime,
+            hex::encode(nonce),
+            URL_SAFE_NO_PAD.encode(user)
+        );
+        let tag = hmac::sign(&self.key, payload.as_bytes());
+        Ok(format!("{payload}.{}", hex::encode(tag)))
+    }
+
+    /// Returns the user of a valid session that is neither expired nor ended
+    pub fn verify(&self, value: &str) -> Option<String> {
+        let (expires, user) = self.signed(value)?;
+        if expires <= unix_time() || self.lock().contains_key(value) {
+            return None;
+        }
+        String::from_utf8(user).ok()
+    }
+
+    /// Ends the session, valid sessions are remembered until they would expire.
+    /// Cookies without a valid signature are ignored to not fill the list with forged values.
+    pub fn revoke(&self, value: &str) {
+        let Some((expires, _)) = self.signed(value) else {
+            return;
+        };
+        let now = unix_time();
+        let mut revoked = self.lock();
+        revoked.retain(|_, expires| *expires > now);
+        if expires > now {
+            revoked.insert(value.to_owned(), expires);
+        }
+    }
+
+    /// Expiration time and user of a cookie with a valid signature
+    fn signed(&self, value: &str) -> Option<(u64, Vec<u8>)> {
+        let (payload, tag) = value.rsplit_once('.')?;
+        let tag = hex::decode(tag).ok()?;
+        hmac::verify(&self.key, payload.as_bytes(), &tag).ok()?;
+        let mut parts = payload.splitn(3, '.');
+        let expires: u64 = parts.next()?.parse().ok()?;
+        let _nonce = parts.next()?;
+        let user = URL_SAFE_NO_PAD.decode(parts.next()?).ok()?;
+        Some((expires, user))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, u64>> {
+        self.revoked.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Session cookie sent by the client, if any
+pub fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .map(|(_, value)| value)
+}
+
+/// Set-Cookie header value, a max age of 0 removes the cookie
+pub fn set_cookie(value: &str, max_age: u64, path: &str, secure: bool) -> String {
+    let secure = if secure { "; Secure" } else { "" };
+    format!(
+        "{COOKIE_NAME}={value}; Max-Age={max_age}; Path={path}; HttpOnly; SameSite=Strict{secure}"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn signed_sessions() {
+        let sessions = Sessions {
+            key: hmac::Key::new(hmac::HMAC_SHA256, b"secret"),
+            lifetime: 3600,
+            revoked: Mutex::new(HashMap::new()),
+        };
+        let cookie = sessions.create("alice").unwrap();
+        assert_eq!(sessions.verify(&cookie).as_deref(), Some("alice"));
+
+        let forged = cookie.replacen('.', "9.", 1);
+        assert_eq!(sessions.verify(&forged), None);
+        let other = Sessions {
+            key: hmac::Key::new(hmac::HMAC_SHA256, b"other"),
+            ..sessions
+        };
+        assert_eq!(other.verify(&cookie), None);
+
+        let mut headers = HeaderMap::new();
+        let header = format!("theme=dark; {COOKIE_NAME}={cookie}");
+        headers.insert(header::COOKIE, HeaderValue::from_str(&header).unwrap());
+        assert_eq!(session_cookie(&headers), Some(cookie.as_str()));
+
+        let sessions = other;
+        let cookie = sessions.create("bob").unwrap();
+        assert!(sessions.verify(&cookie).is_some());
+        sessions.revoke(&cookie);
+        assert_eq!(sessions.verify(&cookie), None);
+
+        sessions.revoke("99999999999.x.eA.00");
+        assert_eq!(sessions.lock().len(), 1);
+    }
+}
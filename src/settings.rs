@@ -0,0 +1,148 @@
+use crate::config::Configuration;
+use crate::logging::LogLevelHandle;
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::str::FromStr;
+use std::sync::RwLock;
+use tokio::sync::Notify;
+use tracing::level_filters::LevelFilter;
+use tracing::{info, Level};
+use utoipa::ToSchema;
+
+/// Settings that can be changed without restarting the application
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct Settings {
+    /// Interval between checking for new reports in IMAP inbox in seconds
+    pub imap_check_interval: u64,
+
+    /// Minimum number of failing messages in new records of an update cycle to send a failure alert
+    pub failure_alert_threshold: usize,
+
+    /// Log level (trace, debug, info, warn, error)
+    pub log_level: String,
+}
+
+/// Optional overrides for the settings from the configuration
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SettingsFile {
+    imap_check_interval: Option<u64>,
+    failure_alert_threshold: Option<usize>,
+    log_level: Option<String>,
+}
+
+/// Changes of the settings at runtime by an admin, missing fields are kept
+#[derive(Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsUpdate {
+    pub imap_check_interval: Option<u64>,
+    pub failure_alert_threshold: Option<usize>,
+    pub log_level: Option<String>,
+}
+
+/// Current settings shared by all parts of the application.
+/// Reloading applies the settings file on top of the startup configuration.
+pub struct SharedSettings {
+    defaults: Settings,
+    file: Option<String>,
+    current: RwLock<Settings>,
+    log_level: Option<LogLevelHandle>,
+
+    /// Wakes up the background task waiting for the next cycle
+    changed: Notify,
+}
+
+impl SharedSettings {
+    pub fn new(config: &Configuration, log_level: Option<LogLevelHandle>) -> Result<Self> {
+        let defaults = Settings {
+            imap_check_interval: config.imap_check_interval,
+            failure_alert_threshold: config.failure_alert_threshold,
+            log_level: config.log_level.to_string(),
+        };
+        let settings = Self {
+            current: RwLock::new(defaults.clone()),
+            defaults,
+            file: config.settings_file.clone(),
+            log_level,
+            changed: Notify::new(),
+        };
+        if settings.file.is_some() {
+            settings.reload()?;
+        }
+        Ok(settings)
+    }
+
+    pub fn get(&self) -> Settings {
+        self.current
+            .read()
+            .expect("Failed to lock settings")
+            .clone()
+    }
+
+    /// Reads the settings file again and applies the new settings
+    pub fn reload(&self) -> Result<Settings> {
+        let overrides = match &self.file {
+            Some(path) => {
+                let json = fs::read(path)
+                    .with_context(|| format!("Failed to read settings file {path}"))?;
+                serde_json::from_slice(&json)
+                    .with_context(|| format!("Failed to parse settings file {path}"))?
+            }
+            None => SettingsFile::default(),
+        };
+        let settings = Settings {
+            imap_check_interval: overrides
+                .imap_check_interval
+                .unwrap_or(self.defaults.imap_check_interval),
+            failure_alert_threshold: overrides
+                .failure_alert_threshold
+                .unwrap_or(self.defaults.failure_alert_threshold),
+            log_level: overrides
+                .log_level
+                .unwrap_or_else(|| self.defaults.log_level.clone()),
+        };
+        self.apply(settings)
+    }
+
+    /// Changes the current settings until the next reload of the settings file
+    pub fn update(&self, update: SettingsUpdate) -> Result<Settings> {
+        ensure!(
+            update.imap_check_interval != Some(0),
+            "Check interval must be at least 1 second"
+        );
+        let current = self.get();
+        let settings = Settings {
+            imap_check_interval: update
+                .imap_check_interval
+                .unwrap_or(current.imap_check_interval),
+            failure_alert_threshold: update
+                .failure_alert_threshold
+                .unwrap_or(current.failure_alert_threshold),
+            log_level: update.log_level.unwrap_or(current.log_level),
+        };
+        self.apply(settings)
+    }
+
+    /// Completes when the settings change next time
+    pub async fn changed(&self) {
+        self.changed.notified().await;
+    }
+
+    fn apply(&self, settings: Settings) -> Result<Settings> {
+        let level = Level::from_str(&settings.log_level)
+            .with_context(|| format!("Invalid log level {}", settings.log_level))?;
+        if let Some(handle) = &self.log_level {
+            handle
+                .reload(LevelFilter::from_level(level))
+                .context("Failed to change log level")?;
+        }
+        info!(
+            "Applied settings: check interval {} secs, failure alert threshold {}, log level {}",
+            settings.imap_check_interval, settings.failure_alert_threshold, settings.log_level
+        );
+        *self.current.write().expect("Failed to lock settings") = settings.clone();
+        self.changed.notify_waiters();
+        Ok(settings)
+    }
+}
@@ -0,0 +1,243 @@
+use crate::causes::failure_cause;
+use crate::config::SiemFormat;
+use crate::export::value_string;
+use crate::report::{RecordType, Report};
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::time::timeout;
+use tracing::{info, warn};
+
+/// Syslog priority of the events, facility local0 with severity warning
+const PRIORITY: u8 = 16 * 8 + 4;
+
+/// Enterprise number reserved for documentation by RFC 5612, used as structured data ID
+const SD_ID: &str = "dmarc@32473";
+
+const APP_NAME: &str = "dmarc-report-viewer";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Transport to the syslog collector
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SiemProtocol {
+    /// One datagram per event (RFC 5426)
+    Udp,
+    /// Octet counted events on one connection per update cycle (RFC 6587)
+    Tcp,
+}
+
+/// Syslog collector like `udp://siem.example.com:514`, without scheme UDP is used
+#[derive(Clone, Debug)]
+pub struct SiemTarget {
+    pub protocol: SiemProtocol,
+    pub address: String,
+}
+
+impl FromStr for SiemTarget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let (protocol, address) = if let Some(address) = value.strip_prefix("tcp://") {
+            (SiemProtocol::Tcp, address)
+        } else if let Some(address) = value.strip_prefix("udp://") {
+            (SiemProtocol::Udp, address)
+        } else {
+            (SiemProtocol::Udp, value)
+        };
+        let address = address.trim_end_matches('/');
+        let valid_port = address
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+        if !valid_port {
+            return Err(format!(
+                "Invalid syslog collector '{value}', expected e.g. udp://siem.example.com:514"
+            ));
+        }
+        Ok(Self {
+            protocol,
+            address: address.to_owned(),
+        })
+    }
+}
+
+impl Display for SiemTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.protocol {
+            SiemProtocol::Udp => write!(f, "udp://{}", self.address),
+            SiemProtocol::Tcp => write!(f, "tcp://{}", self.address),
+        }
+    }
+}
+
+/// Send one event per record of the new reports that failed DMARC.
+/// Errors are logged but not returned, the collector being down never fails an update cycle.
+pub async fn export_failures(target: &SiemTarget, format: SiemFormat, reports: &[Report]) {
+    let events: Vec<String> = reports
+        .iter()
+        .flat_map(|report| {
+            report
+                .record
+                .iter()
+                .filter(|record| !record.is_dmarc_pass())
+                .map(move |record| event(format, report, record))
+        })
+        .collect();
+    if events.is_empty() {
+        return;
+    }
+    match send(target, &events).await {
+        Ok(..) => info!("Sent {} DMARC failure events to {target}", events.len()),
+        Err(err) => warn!("Failed to send DMARC failure events to {target}: {err:#}"),
+    }
+}
+
+async fn send(target: &SiemTarget, events: &[String]) -> Result<()> {
+    match target.protocol {
+        SiemProtocol::Udp => {
+            let addr = lookup_host(&target.address)
+                .await
+                .context("Failed to resolve syslog collector")?
+                .next()
+                .context("Syslog collector has no address")?;
+            let local = if addr.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(local)
+                .await
+                .context("Failed to bind UDP socket")?;
+            for event in events {
+                socket
+                    .send_to(event.as_bytes(), addr)
+                    .await
+                    .context("Failed to send UDP datagram")?;
+            }
+        }
+        SiemProtocol::Tcp => {
+            let mut stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(&target.address))
+                .await
+                .context("Timeout while connecting to syslog collector")?
+                .context("Failed to connect to syslog collector")?;
+            for event in events {
+                let frame = format!("{} {event}", event.len());
+                stream
+                    .write_all(frame.as_bytes())
+                    .await
+                    .context("Failed to write to syslog collector")?;
+            }
+            stream.shutdown().await.ok();
+        }
+    }
+    Ok(())
+}
+
+/// RFC 5424 syslog message of a failing record, the message is a CEF event for the CEF format
+fn event(format: SiemFormat, report: &Report, record: &RecordType) -> String {
+    let metadata = &report.report_metadata;
+    let evaluated = &record.row.policy_evaluated;
+    let time = metadata.date_range.end;
+    let domain = &report.policy_published.domain;
+    let cause = value_string(&failure_cause(report, record));
+    let fields = [
+        ("domain", domain.clone()),
+        ("header_from", record.identifiers.header_from.clone()),
+        ("source_ip", record.row.source_ip.to_string()),
+        ("count", record.row.count.to_string()),
+        ("disposition", value_string(&evaluated.disposition)),
+        ("dkim", value_string(&evaluated.dkim)),
+        ("spf", value_string(&evaluated.spf)),
+        ("cause", cause),
+        ("org_name", metadata.org_name.clone()),
+        ("report_id", metadata.report_id.clone()),
+    ];
+    let timestamp = DateTime::from_timestamp(time as i64, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true);
+    let header = format!("<{PRIORITY}>1 {timestamp} - {APP_NAME} - dmarc_failure");
+    match format {
+        SiemFormat::Syslog => {
+            let params: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| format!("{name}=\"{}\"", escape_param(value)))
+                .collect();
+            format!(
+                "{header} [{SD_ID} {}] DMARC failure of {domain} from {} ({} messages)",
+                params.join(" "),
+                record.row.source_ip,
+                record.row.count
+            )
+        }
+        SiemFormat::Cef => format!("{header} - {}", cef(time, &fields)),
+    }
+}
+
+/// CEF event with the standard keys where available and custom strings for the rest
+fn cef(time: u64, fields: &[(&str, String); 10]) -> String {
+    let [domain, header_from, source_ip, count, disposition, dkim, spf, cause, org_name, report_id] =
+        fields.each_ref().map(|(_, value)| escape_extension(value));
+    format!(
+        "CEF:0|{APP_NAME}|{APP_NAME}|{}|dmarc_failure|DMARC failure|5|rt={} src={source_ip} \
+        cnt={count} act={disposition} dhost={domain} cs1Label=headerFrom cs1={header_from} \
+        cs2Label=dkim cs2={dkim} cs3Label=spf cs3={spf} cs4Label=cause cs4={cause} \
+        cs5Label=reporter cs5={org_name} cs6Label=reportId cs6={report_id}",
+        env!("CARGO_PKG_VERSION"),
+        time * 1000
+    )
+}
+
+/// Escape a structured data parameter value as required by RFC 5424
+fn escape_param(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+/// Escape a CEF extension value, line breaks would end the event
+fn escape_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use crate::report::DmarcResultType;
+    use std::fs;
+
+    #[test]
+    fn format_events() {
+        assert!("siem.example.com".parse::<SiemTarget>().is_err());
+        let target: SiemTarget = "tcp://siem.example.com:6514".parse().unwrap();
+        assert_eq!(target.protocol, SiemProtocol::Tcp);
+        assert_eq!(target.to_string(), "tcp://siem.example.com:6514");
+
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let mut report = parse_xml_file(&xml).unwrap();
+        report.report_metadata.report_id = String::from("id=\"1\"]");
+        let record = &mut report.record[0];
+        record.row.policy_evaluated.dkim = Some(DmarcResultType::Fail);
+        record.row.policy_evaluated.spf = Some(DmarcResultType::Fail);
+        let record = &report.record[0];
+
+        let syslog = event(SiemFormat::Syslog, &report, record);
+        assert!(syslog.starts_with("<132>1 "));
+        assert!(syslog.contains("[dmarc@32473 domain=\"example.com\""));
+        assert!(syslog.contains("report_id=\"id=\\\"1\\\"\\]\"]"));
+
+        let cef = event(SiemFormat::Cef, &report, record);
+        assert!(cef.contains(" dmarc_failure - CEF:0|dmarc-report-viewer|"));
+        assert!(cef.contains("dhost=example.com "));
+        assert!(cef.ends_with("cs6=id\\=\"1\"]"));
+    }
+}
@@ -0,0 +1,83 @@
+use crate::config::{Configuration, SmtpSecurity};
+use crate::notifications::{Alert, NotificationChannel, Templates};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use lettre::message::header::ContentType;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+/// Sends plain text or HTML mails to the configured recipients
+pub struct SmtpSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl SmtpSender {
+    pub fn new(config: &Configuration) -> Result<Self> {
+        let host = config.smtp_host.as_deref().context("Missing SMTP host")?;
+        let mut builder = match config.smtp_security {
+            SmtpSecurity::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .context("Failed to create SMTP STARTTLS transport")?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+                .context("Failed to create SMTP TLS transport")?,
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+        builder = builder.port(config.smtp_port);
+        if let (Some(user), Some(password)) = (&config.smtp_user, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(user.clone(), password.clone()));
+        }
+
+        let from = config
+            .smtp_from
+            .as_deref()
+            .context("Missing SMTP sender address")?
+            .parse()
+            .context("Failed to parse SMTP sender address")?;
+        let to = config
+            .smtp_to
+            .iter()
+            .map(|to| to.parse().context("Failed to parse SMTP recipient address"))
+            .collect::<Result<Vec<Mailbox>>>()?;
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+            to,
+        })
+    }
+
+    /// Send a plain text mail, with HTML alternative if given
+    pub async fn send_mail(&self, subject: &str, body: String, html: Option<String>) -> Result<()> {
+        let mut builder = Message::builder().from(self.from.clone()).subject(subject);
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        let message = if let Some(html) = html {
+            builder.multipart(MultiPart::alternative_plain_html(body, html))
+        } else {
+            builder.header(ContentType::TEXT_PLAIN).body(body)
+        }
+        .context("Failed to build mail")?;
+        self.transport
+            .send(message)
+            .await
+            .context("Failed to send mail")?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for SmtpSender {
+    fn name(&self) -> &'static str {
+        "mail"
+    }
+
+    async fn send(&self, templates: &Templates, alert: &Alert) -> Result<()> {
+        let subject = templates.render("email", alert, "subject")?;
+        let body = templates.render("email", alert, "body")?;
+        let html = templates.render_html("email", alert, "body")?;
+        self.send_mail(subject.trim(), body, html).await
+    }
+}
@@ -0,0 +1,201 @@
+use crate::config::{Configuration, MailProtocol};
+use crate::mail::Mail;
+use crate::{demo, gmail, graph, imap, pop3};
+use anyhow::Result;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc::{channel, Sender};
+use tracing::{info, warn};
+
+/// Upper limit for the delay between retries
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Get metadata of all mails with the configured protocol,
+/// new mails are downloaded with body and sent in batches to the channel.
+/// See `imap::get_mails` for the details.
+///
+/// Failures like network errors or server restarts are retried with exponential backoff.
+/// Mails sent to the channel before a failure count as known for the retries,
+/// so they are not downloaded and processed twice.
+///
+/// The number of new mails found on the server is stored in `pending` before the download starts.
+pub async fn get_mails(
+    config: &Configuration,
+    known_uids: &HashSet<u32>,
+    batches: Sender<Vec<Mail>>,
+    pending: &AtomicUsize,
+) -> Result<HashMap<u32, Mail>> {
+    if !configured(config) {
+        return Ok(HashMap::new());
+    }
+    let mut known_uids = known_uids.clone();
+    let mut attempt = 0;
+    loop {
+        // Forward the batches to remember the UIDs of the mails that were already sent
+        let (sender, mut receiver) = channel::<Vec<Mail>>(1);
+        let forward = async {
+            let mut sent = Vec::new();
+            while let Some(batch) = receiver.recv().await {
+                sent.extend(batch.iter().map(|m| m.uid));
+                if batches.send(batch).await.is_err() {
+                    break;
+                }
+            }
+            sent
+        };
+        let (result, sent) =
+            tokio::join!(fetch_mails(config, &known_uids, sender, pending), forward);
+        known_uids.extend(sent);
+        match result {
+            Err(err) if attempt < config.imap_retries => {
+                attempt += 1;
+                let delay = retry_delay(config.imap_retry_delay, attempt);
+                warn!(
+                    "Failed to get mails, retry {attempt} of {} in {} secs: {err:#}",
+                    config.imap_retries,
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Exponential backoff for the retry with up to 50% random jitter,
+/// so many instances do not hit a restarted server at the same time
+fn retry_delay(base_secs: u64, attempt: u32) -> Duration {
+    let delay = Duration::from_secs(base_secs)
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(MAX_RETRY_DELAY);
+    let mut random = [0; 4];
+    let jitter = match SystemRandom::new().fill(&mut random) {
+        Ok(()) => f64::from(u32::from_le_bytes(random)) / f64::from(u32::MAX) / 2.0,
+        Err(..) => 0.0,
+    };
+    delay.mul_f64(1.0 + jitter)
+}
+
+async fn fetch_mails(
+    config: &Configuration,
+    known_uids: &HashSet<u32>,
+    batches: Sender<Vec<Mail>>,
+    pending: &AtomicUsize,
+) -> Result<HashMap<u32, Mail>> {
+    if config.demo {
+        return demo::get_mails(config, known_uids, batches, pending).await;
+    }
+    match config.mail_protocol {
+        MailProtocol::Imap => imap::get_mails(config, known_uids, batches, pending).await,
+        MailProtocol::Pop3 => pop3::get_mails(config, known_uids, batches, pending).await,
+        MailProtocol::Graph => graph::get_mails(config, known_uids, batches, pending).await,
+        MailProtocol::Gmail => gmail::get_mails(config, known_uids, batches, pending).await,
+    }
+}
+
+/// Order and limit the new mails to download, which are expected oldest first.
+/// A backfill starts with the newest mails, so the recent reports are available first.
+/// Retries after sending some of the mails find less new mails, so the highest number is kept.
+pub fn select_new_mails<T>(config: &Configuration, new_mails: &mut Vec<T>, pending: &AtomicUsize) {
+    pending.fetch_max(new_mails.len(), Ordering::Relaxed);
+    if config.backfill {
+        new_mails.reverse();
+    }
+    if let Some(max_mails) = config.max_mails_per_cycle {
+        if new_mails.len() > max_mails {
+            info!(
+                "Limiting download to {max_mails} of {} new mails, the rest will follow in the next cycles",
+                new_mails.len()
+            );
+            new_mails.truncate(max_mails);
+        }
+    }
+}
+
+/// Download the complete mail with the UID again.
+/// Returns nothing if the mail does not exist anymore.
+pub async fn get_mail_body(config: &Configuration, uid: u32) -> Result<Option<Vec<u8>>> {
+    if config.demo {
+        return demo::get_mail_body(config, uid).await;
+    }
+    match config.mail_protocol {
+        MailProtocol::Imap => imap::get_mail_body(config, uid).await,
+        MailProtocol::Pop3 => pop3::get_mail_body(config, uid).await,
+        MailProtocol::Graph => graph::get_mail_body(config, uid).await,
+        MailProtocol::Gmail => gmail::get_mail_body(config, uid).await,
+    }
+}
+
+/// Log in to the mail server to validate the configuration.
+/// Returns the number of mails in the inbox.
+pub async fn check_inbox(config: &Configuration) -> Result<u32> {
+    if config.demo {
+        return demo::check_inbox(config).await;
+    }
+    match config.mail_protocol {
+        MailProtocol::Imap => imap::check_inbox(config).await,
+        MailProtocol::Pop3 => pop3::check_inbox(config).await,
+        MailProtocol::Graph => graph::check_inbox(config).await,
+        MailProtocol::Gmail => gmail::check_inbox(config).await,
+    }
+}
+
+/// Log out from mail server sessions kept open between update cycles
+pub async fn close_sessions(config: &Configuration) {
+    if config.mail_protocol == MailProtocol::Imap {
+        imap::close_session().await;
+    }
+}
+
+/// Mails are kept on the server unless POP3 is configured to delete them.
+/// Otherwise mails missing on the server were deleted by the user
+/// and their reports are removed as well.
+pub fn keeps_mails(config: &Configuration) -> bool {
+    config.mail_protocol != MailProtocol::Pop3 || !config.pop3_delete
+}
+
+/// IMAP settings can be omitted if the reports are only read from a local directory
+/// or the mailbox is simulated
+pub fn configured(config: &Configuration) -> bool {
+    config.demo || config.mail_protocol != MailProtocol::Imap || !config.imap_host.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn exponential_backoff() {
+        for (attempt, secs) in [(1, 5), (2, 10), (3, 20), (10, 300)] {
+            let delay = retry_delay(5, attempt);
+            assert!(delay >= Duration::from_secs(secs));
+            assert!(delay <= Duration::from_secs(secs).mul_f64(1.5));
+        }
+        assert_eq!(retry_delay(0, 1), Duration::ZERO);
+    }
+
+    #[test]
+    fn backfill_newest_first() {
+        let config = Configuration::parse_from([
+            "test",
+            "--imap-host=localhost",
+            "--imap-user=user",
+            "--imap-password=password",
+            "--http-server-password=password",
+            "--max-mails-per-cycle=2",
+            "--backfill",
+        ]);
+        let pending = AtomicUsize::new(0);
+        let mut new_mails = vec![1, 2, 3];
+        select_new_mails(&config, &mut new_mails, &pending);
+        assert_eq!(new_mails, vec![3, 2]);
+        assert_eq!(pending.load(Ordering::Relaxed), 3);
+
+        // A retry after the first mails were sent keeps the number of the first attempt
+        select_new_mails(&config, &mut vec![1], &pending);
+        assert_eq!(pending.load(Ordering::Relaxed), 3);
+    }
+}
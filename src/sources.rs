@@ -0,0 +1,171 @@
+use crate::anonymize::Anonymizer;
+use crate::filter::RecordFilter;
+use crate::report::Report;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+/// Time when a source IP was seen for a domain for the first time
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
+pub struct FirstSeen {
+    /// Begin of the date range of the first report with this source IP as Unix timestamp
+    pub first_seen: u64,
+
+    /// Time of the update cycle that detected the source IP as Unix timestamp
+    pub detected: u64,
+}
+
+/// Source IP that was not seen before for a header from domain
+#[derive(Serialize)]
+pub struct NewSource {
+    pub domain: String,
+    pub source_ip: IpAddr,
+    pub org: String,
+    pub count: usize,
+    pub first_seen: u64,
+    pub detected: u64,
+}
+
+/// History of all source IPs sending mails per header from domain
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct SourceHistory {
+    domains: HashMap<String, HashMap<IpAddr, FirstSeen>>,
+}
+
+impl SourceHistory {
+    /// Same history with pseudonyms for the domains and source IPs
+    pub fn anonymized(&self, anonymizer: &Anonymizer) -> Self {
+        let domains = self
+            .domains
+            .iter()
+            .map(|(domain, sources)| {
+                let sources = sources
+                    .iter()
+                    .map(|(ip, seen)| (anonymizer.ip(*ip), seen.clone()))
+                    .collect();
+                (anonymizer.domain(domain), sources)
+            })
+            .collect();
+        Self { domains }
+    }
+
+    /// Add all source IPs of the reports to the history and return the ones not seen before.
+    /// Nothing is returned when the history was empty, to not treat all sources as new on the first run.
+    pub fn update(&mut self, reports: &[Report], now: u64) -> Vec<NewSource> {
+        let learning = self.domains.is_empty();
+        let mut new_sources: HashMap<(String, IpAddr), NewSource> = HashMap::new();
+        for report in reports {
+            for record in &report.record {
+                let domain = record.identifiers.header_from.to_lowercase();
+                let ip = record.row.source_ip;
+                let begin = report.report_metadata.date_range.begin;
+                let known = self.domains.entry(domain.clone()).or_default();
+                if let Some(seen) = known.get_mut(&ip) {
+                    seen.first_seen = seen.first_seen.min(begin);
+                    if let Some(new) = new_sources.get_mut(&(domain, ip)) {
+                        new.count += record.row.count;
+                        new.first_seen = seen.first_seen;
+                    }
+                    continue;
+                }
+                known.insert(
+                    ip,
+                    FirstSeen {
+                        first_seen: begin,
+                        detected: now,
+                    },
+                );
+                if !learning {
+                    new_sources.insert(
+                        (domain.clone(), ip),
+                        NewSource {
+                            domain,
+                            source_ip: ip,
+                            org: report.report_metadata.org_name.clone(),
+                            count: record.row.count,
+                            first_seen: begin,
+                            detected: now,
+                        },
+                    );
+                }
+            }
+        }
+        let mut new_sources: Vec<NewSource> = new_sources.into_values().collect();
+        new_sources.sort_by(|a, b| a.domain.cmp(&b.domain).then(a.source_ip.cmp(&b.source_ip)));
+        new_sources
+    }
+
+    /// List all known sources matching the domain and source IP of the filter.
+    /// The time range of the filter is applied to the detection time.
+    /// Sorted by descending detection time.
+    pub fn list(&self, filter: &RecordFilter) -> Vec<SourceEntry> {
+        let mut entries: Vec<SourceEntry> = self
+            .domains
+            .iter()
+            .filter(|(domain, _)| {
+                filter.allows_domain(domain)
+                    && filter
+                        .domain
+                        .as_ref()
+                        .is_none_or(|d| filter.matches_domain(d, domain))
+            })
+            .flat_map(|(domain, ips)| {
+                ips.iter().map(|(ip, seen)| SourceEntry {
+                    domain: domain.clone(),
+                    source_ip: *ip,
+                    seen: seen.clone(),
+                })
+            })
+            .filter(|e| filter.source_ip.is_none_or(|ip| ip == e.source_ip))
+            .filter(|e| filter.since.is_none_or(|since| e.seen.detected >= since))
+            .filter(|e| filter.until.is_none_or(|until| e.seen.detected <= until))
+            .collect();
+        entries.sort_by(|a, b| {
+            b.seen
+                .detected
+                .cmp(&a.seen.detected)
+                .then(a.domain.cmp(&b.domain))
+                .then(a.source_ip.cmp(&b.source_ip))
+        });
+        entries
+    }
+}
+
+/// Known source IP of a domain as returned by the API
+#[derive(Serialize, ToSchema)]
+pub struct SourceEntry {
+    pub domain: String,
+    #[schema(value_type = String)]
+    pub source_ip: IpAddr,
+    #[serde(flatten)]
+    pub seen: FirstSeen,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn detect_new_sources_after_learning() {
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let mut history = SourceHistory::default();
+        assert!(history
+            .update(std::slice::from_ref(&report), 100)
+            .is_empty());
+        assert!(history
+            .update(std::slice::from_ref(&report), 200)
+            .is_empty());
+
+        let mut spoofed = report;
+        spoofed.record.truncate(1);
+        spoofed.record[0].row.source_ip = "192.0.2.1".parse().unwrap();
+        let new_sources = history.update(&[spoofed], 300);
+        assert_eq!(new_sources.len(), 1);
+        assert_eq!(new_sources[0].detected, 300);
+        assert_eq!(history.list(&RecordFilter::default())[0].seen.detected, 300);
+    }
+}
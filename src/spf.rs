@@ -0,0 +1,203 @@
+use crate::dns::DnsResolver;
+use futures::future::{BoxFuture, FutureExt};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Maximum number of DNS lookups during an SPF evaluation, more result in a permanent error
+pub const LOOKUP_LIMIT: usize = 10;
+
+/// Maximum depth of followed includes and redirects
+const MAX_DEPTH: usize = 10;
+
+/// Mechanisms and modifiers that need a DNS lookup during the evaluation
+const LOOKUP_TERMS: &[&str] = &["include", "a", "mx", "ptr", "exists", "redirect"];
+
+/// Mechanism or modifier of an SPF record
+#[derive(Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct SpfTerm {
+    /// Qualifier of a mechanism like `-` for fail, empty for modifiers
+    pub qualifier: String,
+
+    /// Name of the mechanism or modifier like `include`, `ip4` or `redirect`
+    pub name: String,
+
+    /// Domain, network or other argument of the term
+    pub value: Option<String>,
+
+    /// DNS lookups caused by the term including all nested records
+    pub lookups: usize,
+
+    /// Record of the domain of an include or redirect
+    #[schema(no_recursion)]
+    pub include: Option<Box<SpfNode>>,
+}
+
+/// SPF record of a domain with all included records resolved
+#[derive(Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct SpfNode {
+    pub domain: String,
+
+    /// SPF record of the domain, empty if none is published or the lookup failed
+    pub record: Option<String>,
+
+    pub terms: Vec<SpfTerm>,
+
+    /// DNS lookups of the record including all nested records
+    pub lookups: usize,
+
+    /// Problems of this record like a failed lookup or multiple SPF records
+    pub problems: Vec<String>,
+}
+
+/// Resolve the SPF record of the domain with its include and redirect chain.
+/// Exceeding the lookup limit is reported as problem of the domain.
+pub async fn check_domain(dns: &DnsResolver, domain: &str) -> SpfNode {
+    let mut node = resolve(dns, domain.to_lowercase(), Vec::new()).await;
+    if node.lookups > LOOKUP_LIMIT {
+        node.problems.push(format!(
+            "Record needs {} DNS lookups, receivers fail SPF above {LOOKUP_LIMIT}",
+            node.lookups
+        ));
+    }
+    node
+}
+
+/// Resolve a record, `parents` are the domains already on the chain to detect loops
+fn resolve(dns: &DnsResolver, domain: String, parents: Vec<String>) -> BoxFuture<'_, SpfNode> {
+    async move {
+        let mut node = SpfNode {
+            domain,
+            record: None,
+            terms: Vec::new(),
+            lookups: 0,
+            problems: Vec::new(),
+        };
+        let mut records: Vec<String> = match dns.txt(&node.domain).await {
+            Ok(records) => records.into_iter().filter(|r| is_spf(r)).collect(),
+            Err(err) => {
+                node.problems.push(format!("{err:#}"));
+                return node;
+            }
+        };
+        if records.len() > 1 {
+            node.problems
+                .push(format!("Multiple SPF records at {}", node.domain));
+            return node;
+        }
+        let Some(record) = records.pop() else {
+            node.problems
+                .push(format!("No SPF record at {}", node.domain));
+            return node;
+        };
+        node.terms = parse_terms(&record);
+        node.record = Some(record);
+
+        // The redirect modifier is ignored if the record has an all mechanism
+        let has_all = node.terms.iter().any(|t| t.name == "all");
+        let mut parents = parents;
+        parents.push(node.domain.clone());
+        for term in &mut node.terms {
+            let follow = term.name == "include" || (term.name == "redirect" && !has_all);
+            let Some(target) = term.value.as_ref().filter(|_| follow) else {
+                continue;
+            };
+            let target = target.to_lowercase();
+            if target.contains('%') {
+                // Macros depend on the sender and cannot be resolved in advance
+                continue;
+            }
+            if parents.contains(&target) {
+                node.problems
+                    .push(format!("Loop at {} {target}", term.name));
+                continue;
+            }
+            if parents.len() > MAX_DEPTH {
+                node.problems
+                    .push(format!("Not following {target} nested too deep"));
+                continue;
+            }
+            let child = resolve(dns, target, parents.clone()).await;
+            term.lookups += child.lookups;
+            term.include = Some(Box::new(child));
+        }
+        node.lookups = node.terms.iter().map(|t| t.lookups).sum();
+        node
+    }
+    .boxed()
+}
+
+fn is_spf(record: &str) -> bool {
+    let version = record.split_whitespace().next().unwrap_or_default();
+    version.eq_ignore_ascii_case("v=spf1")
+}
+
+/// Terms of the record without resolving includes, each with its own lookup
+fn parse_terms(record: &str) -> Vec<SpfTerm> {
+    record
+        .split_whitespace()
+        .skip(1)
+        .map(|term| {
+            let (qualifier, name, value) = match term.split_once('=') {
+                Some((name, value)) if !name.contains(':') => ("", name, Some(value)),
+                _ => {
+                    let (qualifier, mechanism) = match term.chars().next() {
+                        Some(q @ ('+' | '-' | '~' | '?')) => term.split_at(q.len_utf8()),
+                        _ => ("", term),
+                    };
+                    match mechanism.find([':', '/']) {
+                        Some(i) => (
+                            qualifier,
+                            &mechanism[..i],
+                            Some(mechanism[i..].trim_start_matches(':')),
+                        ),
+                        None => (qualifier, mechanism, None),
+                    }
+                }
+            };
+            let name = name.to_lowercase();
+            SpfTerm {
+                qualifier: qualifier.to_owned(),
+                lookups: usize::from(LOOKUP_TERMS.contains(&name.as_str())),
+                name,
+                value: value.map(str::to_owned),
+                include: None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_terms() {
+        assert!(is_spf("V=SPF1 -all"));
+        assert!(!is_spf("v=spf10 -all"));
+        let terms = parse_terms(
+            "v=spf1 ip4:192.0.2.0/24 a/24 mx:mail.example.com -include:_spf.example.net redirect=_spf.example.org ~all",
+        );
+        let summary: Vec<(&str, &str, Option<&str>, usize)> = terms
+            .iter()
+            .map(|t| {
+                (
+                    t.qualifier.as_str(),
+                    t.name.as_str(),
+                    t.value.as_deref(),
+                    t.lookups,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("", "ip4", Some("192.0.2.0/24"), 0),
+                ("", "a", Some("/24"), 1),
+                ("", "mx", Some("mail.example.com"), 1),
+                ("-", "include", Some("_spf.example.net"), 1),
+                ("", "redirect", Some("_spf.example.org"), 1),
+                ("~", "all", None, 0),
+            ]
+        );
+    }
+}
@@ -1,31 +1,384 @@
-use std::collections::HashMap;
-
-use crate::mail::Mail;
-use crate::report::Report;
-use crate::summary::Summary;
-use crate::xml_error::XmlError;
-
-/// Shared state between the different parts of the application.
-/// Connects the background task that collects mails via IMAP,
-/// parses them, analyzes DMARC reports and makes them available for
-/// the web frontend running on to the embedded HTTP server.
-#[derive(Default)]
-pub struct AppState {
-    /// Number of emails in IMAP report inbox
-    pub mails: HashMap<u32, Mail>,
-
-    /// Number of XML files found in IMAP report inbox
-    pub xml_files: usize,
-
-    /// DMARC reports parsed from emails in inbox
-    pub reports: Vec<Report>,
-
-    /// Summary of report and other stats
-    pub summary: Summary,
-
-    /// Time of last update from IMAP inbox as Unix timestamp
-    pub last_update: u64,
-
-    /// XML parsing errors
-    pub xml_errors: Vec<XmlError>,
-}
+use crate::allowlist::Allowlist;
+use crate::annotations::Annotations;
+use crate::anonymize::Anonymizer;
+use crate::compliance::ComplianceTracker;
+use crate::duplicate::DuplicateReport;
+use crate::learning::TrustedLearning;
+use crate::mail::Mail;
+use crate::notes::Notes;
+use crate::policy::PolicyHistory;
+use crate::quarantine::Quarantine;
+use crate::report::Report;
+use crate::reporters::GapTracker;
+use crate::rollup::Rollups;
+use crate::sources::SourceHistory;
+use crate::status::{BackgroundStatus, CycleHistory};
+use crate::summary::Summary;
+use crate::xml_error::XmlError;
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Shared state between the different parts of the application.
+/// Connects the background task that collects mails via IMAP,
+/// parses them, analyzes DMARC reports and makes them available for
+/// the web frontend running on to the embedded HTTP server.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct AppState {
+    /// Number of emails in IMAP report inbox
+    pub mails: Arc<HashMap<u32, Mail>>,
+
+    /// Number of XML files found in IMAP report inbox
+    pub xml_files: usize,
+
+    /// DMARC reports parsed from emails in inbox
+    pub reports: Arc<Vec<Report>>,
+
+    /// Summary of report and other stats
+    pub summary: Summary,
+
+    /// Time of last update from IMAP inbox as Unix timestamp
+    pub last_update: u64,
+
+    /// XML parsing errors
+    pub xml_errors: Arc<Vec<XmlError>>,
+
+    /// Skipped reports that were delivered more than once
+    #[serde(default)]
+    pub duplicates: Arc<Vec<DuplicateReport>>,
+
+    /// UIDs of mails removed by the retention limits that are not downloaded again
+    #[serde(default)]
+    pub evicted_uids: Arc<HashSet<u32>>,
+
+    /// Keys of already processed objects from the S3 ingestion prefix
+    #[serde(default)]
+    pub s3_objects: Arc<HashSet<String>>,
+
+    /// Source IPs seen per header from domain across all update cycles
+    #[serde(default)]
+    pub source_history: Arc<SourceHistory>,
+
+    /// Published policies per domain across all update cycles
+    #[serde(default)]
+    pub policy_history: Arc<PolicyHistory>,
+
+    /// Domains below the compliance target
+    #[serde(default)]
+    pub compliance: ComplianceTracker,
+
+    /// Reporters that stopped sending their daily reports per domain
+    #[serde(default)]
+    pub reporter_gaps: GapTracker,
+
+    /// Counts per day and domain, kept after the retention limits removed the reports
+    #[serde(default)]
+    pub rollups: Arc<Rollups>,
+
+    /// Manual annotations for source IPs and domains
+    #[serde(default)]
+    pub annotations: Annotations,
+
+    /// Expected senders per domain
+    #[serde(default)]
+    pub allowlist: Allowlist,
+
+    /// Suggested trusted senders for the allowlist
+    #[serde(default)]
+    pub trusted_learning: TrustedLearning,
+
+    /// Notes of users about source IPs and reports with the acknowledged failures
+    #[serde(default)]
+    pub notes: Arc<Notes>,
+
+    /// Mails that failed the extraction of their XML files
+    #[serde(default)]
+    pub quarantine: Arc<Quarantine>,
+
+    /// Incremented on every change of the reports, used for the ETag of API responses
+    #[serde(skip)]
+    pub revision: u64,
+
+    /// Set after the first successful update cycle since the start of the process
+    #[serde(skip)]
+    pub ready: bool,
+
+    /// Incremented whenever reports are purged or parsed again,
+    /// update cycles started before apply their results to the changed reports
+    #[serde(skip)]
+    pub maintenance: u64,
+}
+
+impl AppState {
+    /// Load a state previously written with `save` from a JSON file
+    pub fn load(path: &str) -> Result<Self> {
+        let json = fs::read(path).context("Failed to read state file")?;
+        Self::from_json(&json)
+    }
+
+    pub fn from_json(json: &[u8]) -> Result<Self> {
+        serde_json::from_slice(json).context("Failed to parse state")
+    }
+
+    /// Detaches a state exported by another instance from the mails of its mailbox.
+    /// The UIDs belong to a different mailbox and would drop the reports in the next update cycle
+    /// or mix them up with unrelated mails, so the reports are kept like reports ingested via HTTP.
+    pub fn imported(mut self) -> Self {
+        for report in Arc::make_mut(&mut self.reports) {
+            report.mail_uid = None;
+        }
+        for duplicate in Arc::make_mut(&mut self.duplicates) {
+            duplicate.mail_uid = None;
+        }
+        self.mails = Arc::default();
+        self.evicted_uids = Arc::default();
+        self.xml_errors = Arc::default();
+        self.quarantine = Arc::default();
+        self
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).context("Failed to serialize state")
+    }
+
+    /// Number of mails without the skipped copies of other mails
+    pub fn unique_mail_count(&self) -> usize {
+        self.mails
+            .values()
+            .filter(|m| m.duplicate_of.is_none())
+            .count()
+    }
+
+    /// Recalculates the summary after reports were added outside of an update cycle.
+    /// Compliance scores are updated in the next update cycle.
+    pub fn refresh_summary(&mut self, timestamp: u64) {
+        self.last_update = timestamp;
+        self.revision += 1;
+        let compliance = std::mem::take(&mut self.summary.compliance);
+        self.summary = Summary::new(
+            self.unique_mail_count(),
+            self.xml_files,
+            &self.reports,
+            self.duplicates.len(),
+            timestamp,
+        );
+        self.summary.compliance = compliance;
+    }
+}
+
+/// Number of attempts of a concurrent update before it waits for other updates
+const CONCURRENT_ATTEMPTS: usize = 3;
+
+/// App state shared between the background task and the servers.
+/// Readers get a snapshot of the current state without waiting for running updates.
+/// Updates are applied to a copy of the state that replaces the current one when done.
+/// The large collections are shared between the copies until an update changes them.
+pub struct SharedState {
+    current: ArcSwap<AppState>,
+
+    /// Current state as served to readers, with pseudonyms if anonymization is enabled
+    published: ArcSwap<AppState>,
+
+    anonymizer: Option<Anonymizer>,
+
+    /// Serializes updates, so no update is lost when two of them start from the same state.
+    /// A panicking update never published its copy, so the lock is still usable after it.
+    /// Counts the stored states to publish their anonymized versions in order.
+    writer: Mutex<u64>,
+
+    /// Number of the stored state whose anonymized version is published.
+    /// Anonymizing takes long for many reports and runs after the writer lock was released.
+    anonymized: Mutex<u64>,
+
+    /// Progress of the background task, only relevant for the running process.
+    /// Kept outside of the snapshots since it changes with every processed mail.
+    status: Mutex<BackgroundStatus>,
+
+    /// Metrics of the last update cycles of the running process
+    cycle_history: Mutex<CycleHistory>,
+}
+
+impl SharedState {
+    pub fn new(state: AppState) -> Self {
+        Self::with_anonymizer(state, None)
+    }
+
+    /// Readers only get states with pseudonyms if an anonymizer is given
+    pub fn with_anonymizer(state: AppState, anonymizer: Option<Anonymizer>) -> Self {
+        let shared = Self {
+            current: ArcSwap::from_pointee(AppState::default()),
+            published: ArcSwap::from_pointee(AppState::default()),
+            anonymizer,
+            writer: Mutex::new(0),
+            anonymized: Mutex::new(0),
+            status: Mutex::new(BackgroundStatus::default()),
+            cycle_history: Mutex::new(CycleHistory::default()),
+        };
+        shared.replace(state);
+        shared
+    }
+
+    /// Current state as served to readers, which does not change while it is used
+    pub fn snapshot(&self) -> Arc<AppState> {
+        self.published.load_full()
+    }
+
+    /// Current state without pseudonyms, for the background task that builds on the previous results
+    pub fn original(&self) -> Arc<AppState> {
+        self.current.load_full()
+    }
+
+    /// Applies the change to a copy of the current state and publishes it afterwards
+    pub fn update<T>(&self, change: impl FnOnce(&mut AppState) -> T) -> T {
+        let (result, state, number) = {
+            let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut state = AppState::clone(&self.current.load());
+            let result = change(&mut state);
+            let state = Arc::new(state);
+            let number = self.store(&mut writer, state.clone());
+            (result, state, number)
+        };
+        self.publish_anonymized(&state, number);
+        result
+    }
+
+    /// Applies a long running change without blocking other updates while it runs.
+    /// The change is computed on a copy of the current state without holding the writer lock
+    /// and computed again if another update was published in the meantime.
+    /// After repeated conflicts it is applied like `update` to not starve.
+    pub fn update_concurrently<T>(&self, mut change: impl FnMut(&mut AppState) -> T) -> T {
+        for _ in 0..CONCURRENT_ATTEMPTS {
+            let base = self.current.load_full();
+            let mut state = AppState::clone(&base);
+            let result = change(&mut state);
+            let state = Arc::new(state);
+            let number = {
+                let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+                if !Arc::ptr_eq(&self.current.load(), &base) {
+                    continue;
+                }
+                self.store(&mut writer, state.clone())
+            };
+            self.publish_anonymized(&state, number);
+            return result;
+        }
+        self.update(change)
+    }
+
+    /// Replaces the complete state, for example with the one loaded from a replica
+    pub fn replace(&self, state: AppState) {
+        let state = Arc::new(state);
+        let number = {
+            let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+            self.store(&mut writer, state.clone())
+        };
+        self.publish_anonymized(&state, number);
+    }
+
+    /// Stores the state while holding the writer lock and returns its number.
+    /// Without anonymization it is published right away.
+    fn store(&self, stored: &mut u64, state: Arc<AppState>) -> u64 {
+        *stored += 1;
+        if self.anonymizer.is_none() {
+            self.published.store(state.clone());
+        }
+        self.current.store(state);
+        *stored
+    }
+
+    /// Publishes the anonymized version of the stored state with the number,
+    /// unless the one of a newer state was already published
+    fn publish_anonymized(&self, state: &AppState, number: u64) {
+        let Some(anonymizer) = &self.anonymizer else {
+            return;
+        };
+        let anonymized = Arc::new(anonymizer.state(state));
+        let mut published = self
+            .anonymized
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if number > *published {
+            self.published.store(anonymized);
+            *published = number;
+        }
+    }
+
+    pub fn status(&self) -> MutexGuard<'_, BackgroundStatus> {
+        // The status stays usable after a panic caught in the background task
+        self.status.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn cycle_history(&self) -> MutexGuard<'_, CycleHistory> {
+        // The history stays usable after a panic caught in the background task
+        self.cycle_history
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_stay_unchanged() {
+        let state = SharedState::new(AppState::default());
+        let before = state.snapshot();
+        let revision = state.update(|state| {
+            state.revision += 1;
+            state.revision
+        });
+        assert_eq!(revision, 1);
+        assert_eq!(before.revision, 0);
+        assert_eq!(state.snapshot().revision, 1);
+
+        state.replace(AppState {
+            ready: true,
+            ..Default::default()
+        });
+        assert!(state.snapshot().ready);
+        assert_eq!(state.snapshot().revision, 0);
+
+        // A concurrent update is computed again after a conflicting update
+        let mut attempts = 0;
+        let revision = state.update_concurrently(|locked| {
+            attempts += 1;
+            if attempts == 1 {
+                state.update(|other| other.revision += 10);
+            }
+            locked.revision += 1;
+            locked.revision
+        });
+        assert_eq!(attempts, 2);
+        assert_eq!(revision, 11);
+        assert_eq!(state.snapshot().revision, 11);
+    }
+
+    #[test]
+    fn publish_anonymized_states_in_order() {
+        use crate::config::Configuration;
+        use clap::Parser;
+
+        let config = Configuration::parse_from([
+            "test",
+            "--demo",
+            "--http-server-password=password",
+            "--anonymize-key=key",
+        ]);
+        let state = SharedState::with_anonymizer(AppState::default(), Anonymizer::new(&config));
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = crate::parser::parse_xml_file(&xml).unwrap();
+        state.update(|state| Arc::make_mut(&mut state.reports).push(report));
+        let domain = |state: &AppState| state.reports[0].policy_published.domain.clone();
+        assert_eq!(domain(&state.original()), "example.com");
+        let anonymized = domain(&state.snapshot());
+        assert_ne!(anonymized, "example.com");
+
+        // The anonymized version of an older state never replaces the one of a newer state
+        state.publish_anonymized(&AppState::default(), 1);
+        assert_eq!(domain(&state.snapshot()), anonymized);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::summary::CycleMetrics;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+/// Number of finished update cycles kept in the history
+const HISTORY_SIZE: usize = 100;
+
+/// Current step of the background update cycle.
+/// The steps overlap, this is the first one that is not finished yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    #[default]
+    Idle,
+    Fetching,
+    Extracting,
+    Parsing,
+    Saving,
+}
+
+/// Progress and outcome of the background task to explain stale data
+#[derive(Clone, Debug, Default, Serialize, ToSchema)]
+pub struct BackgroundStatus {
+    pub phase: Phase,
+
+    /// ID of the running or last update cycle
+    pub cycle_id: u64,
+
+    /// Start of the running or last update cycle as Unix timestamp
+    pub cycle_started: Option<u64>,
+
+    /// Mails to process in the running cycle and how many of them are done
+    pub mails_total: usize,
+    pub mails_processed: usize,
+
+    /// XML files to parse in the running cycle and how many of them are done
+    pub xml_files_total: usize,
+    pub xml_files_parsed: usize,
+
+    /// Last successful cycle as Unix timestamp
+    pub last_success: Option<u64>,
+
+    /// Error of the last failed cycle and when it happened
+    pub last_error: Option<String>,
+    pub last_error_time: Option<u64>,
+
+    /// Set if the last finished cycle failed.
+    /// The data of the last successful cycle is kept and served until the next cycle succeeds.
+    pub stale: bool,
+
+    /// Next scheduled cycle as Unix timestamp
+    pub next_run: Option<u64>,
+
+    /// Time spent in the steps of the running or last cycle in milliseconds,
+    /// summed over all parallel workers for extracting and parsing.
+    /// The steps overlap, mails are extracted and parsed while the next batch is downloaded.
+    pub fetch_ms: u64,
+    pub extract_ms: u64,
+    pub parse_ms: u64,
+
+    /// Mails without extractable XML files and XML files that are no valid reports
+    /// in the running or last cycle
+    pub extract_errors: usize,
+    pub parse_errors: usize,
+
+    /// Copies of already processed mails skipped in the running or last cycle
+    pub duplicate_mails: usize,
+
+    /// Panics caught in the running or last cycle and the last one since the start
+    pub panics: usize,
+    pub last_panic: Option<TaskPanic>,
+
+    /// Progress of the backfill after the start, empty if not enabled
+    pub backfill: Option<BackfillProgress>,
+}
+
+/// Mails processed by the backfill over all its cycles
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct BackfillProgress {
+    /// Mails processed so far including the ones known before the start
+    pub processed: usize,
+
+    /// Mails in the mailbox when the last cycle started
+    pub total: usize,
+
+    /// Set once a cycle finished without new mails left
+    pub finished: bool,
+}
+
+/// Panic caught in the background task instead of stopping it
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct TaskPanic {
+    /// Step of the update cycle that panicked
+    pub phase: Phase,
+
+    /// Mail processed by the panicking worker, it is skipped in the following cycles
+    pub mail_uid: Option<u32>,
+    pub message: String,
+
+    /// Time of the panic as Unix timestamp
+    pub time: u64,
+}
+
+impl BackgroundStatus {
+    /// Resets the progress for a new update cycle
+    pub fn start_cycle(&mut self, cycle_id: u64) {
+        self.cycle_id = cycle_id;
+        self.cycle_started = Some(unix_time());
+        self.mails_total = 0;
+        self.mails_processed = 0;
+        self.xml_files_total = 0;
+        self.xml_files_parsed = 0;
+        self.next_run = None;
+        self.fetch_ms = 0;
+        self.extract_ms = 0;
+        self.parse_ms = 0;
+        self.extract_errors = 0;
+        self.parse_errors = 0;
+        self.duplicate_mails = 0;
+        self.panics = 0;
+    }
+
+    pub fn record_panic(&mut self, panic: TaskPanic) {
+        self.panics += 1;
+        self.last_panic = Some(panic);
+    }
+
+    pub fn finish_cycle(&mut self, error: Option<String>, next_run: u64) {
+        self.phase = Phase::Idle;
+        match error {
+            Some(error) => {
+                self.last_error = Some(error);
+                self.last_error_time = Some(unix_time());
+                self.stale = true;
+            }
+            None => {
+                self.last_success = Some(unix_time());
+                self.stale = false;
+            }
+        }
+        self.next_run = Some(next_run);
+    }
+
+    /// Metrics of the finished cycle with its error and the number of mails and reports after it
+    pub fn cycle_metrics(
+        &self,
+        duration: Duration,
+        error: Option<String>,
+        mails: usize,
+        reports: usize,
+    ) -> CycleMetrics {
+        CycleMetrics {
+            cycle_id: self.cycle_id,
+            started: self.cycle_started.unwrap_or_default(),
+            duration_ms: duration.as_millis() as u64,
+            fetch_ms: self.fetch_ms,
+            extract_ms: self.extract_ms,
+            parse_ms: self.parse_ms,
+            mails,
+            mails_delta: 0,
+            reports,
+            reports_delta: 0,
+            extract_errors: self.extract_errors,
+            parse_errors: self.parse_errors,
+            duplicate_mails: self.duplicate_mails,
+            panics: self.panics,
+            error,
+        }
+    }
+}
+
+/// Metrics of the last finished update cycles, oldest first
+#[derive(Default)]
+pub struct CycleHistory {
+    cycles: VecDeque<CycleMetrics>,
+}
+
+impl CycleHistory {
+    /// Adds the cycle with the changes since the previous one, the oldest cycle is dropped if full
+    pub fn push(&mut self, mut metrics: CycleMetrics) {
+        if let Some(previous) = self.cycles.back() {
+            metrics.mails_delta = metrics.mails as i64 - previous.mails as i64;
+            metrics.reports_delta = metrics.reports as i64 - previous.reports as i64;
+        }
+        if self.cycles.len() >= HISTORY_SIZE {
+            self.cycles.pop_front();
+        }
+        self.cycles.push_back(metrics);
+    }
+
+    pub fn last(&self) -> Option<CycleMetrics> {
+        self.cycles.back().cloned()
+    }
+
+    pub fn list(&self) -> Vec<CycleMetrics> {
+        self.cycles.iter().cloned().collect()
+    }
+}
+
+/// Current time as Unix timestamp
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_history() {
+        let mut status = BackgroundStatus::default();
+        let mut history = CycleHistory::default();
+        for (cycle_id, mails, reports) in [(1, 10, 8), (2, 12, 7)] {
+            status.start_cycle(cycle_id);
+            status.parse_errors = 1;
+            history.push(status.cycle_metrics(Duration::from_millis(1500), None, mails, reports));
+        }
+        for cycle_id in 3..=HISTORY_SIZE as u64 + 1 {
+            status.start_cycle(cycle_id);
+            let error = Some(String::from("Connection refused"));
+            history.push(status.cycle_metrics(Duration::ZERO, error, 12, 7));
+        }
+        let cycles = history.list();
+        assert_eq!(cycles.len(), HISTORY_SIZE);
+        assert_eq!(cycles[0].cycle_id, 2);
+        assert_eq!(cycles[0].duration_ms, 1500);
+        assert_eq!(cycles[0].mails_delta, 2);
+        assert_eq!(cycles[0].reports_delta, -1);
+        assert_eq!(cycles[0].parse_errors, 1);
+        assert_eq!(cycles[1].mails_delta, 0);
+        assert_eq!(cycles[1].parse_errors, 0);
+        assert!(cycles[1].error.is_some());
+    }
+}
@@ -0,0 +1,457 @@
+use crate::config::Configuration;
+use crate::parser::hash_data;
+use crate::report::Report;
+use crate::state::AppState;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard};
+use tokio::task::spawn_blocking;
+use tokio_postgres::config::Host;
+use tokio_postgres::{Client, Config};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tracing::{info, warn};
+
+/// Backend persisting the parsed application state.
+/// Allows multiple instances to share the same state,
+/// where one primary instance saves it and read replicas load it.
+#[async_trait]
+pub trait StateStore: Send + Sync {
+    /// Description of the storage location for logging
+    fn name(&self) -> String;
+
+    /// Returns nothing if no state was saved yet
+    async fn load(&self) -> Result<Option<AppState>>;
+
+    async fn save(&self, state: Arc<AppState>) -> Result<()>;
+
+    /// Value that changes whenever the saved state changes,
+    /// used by read replicas to skip loading an unchanged state
+    async fn version(&self) -> Result<Option<String>>;
+}
+
+/// Create the configured state storage, if any
+pub fn state_store(config: &Configuration) -> Option<Arc<dyn StateStore>> {
+    if let Some(database) = &config.state_database_url {
+        return Some(Arc::new(PostgresStore::new(database.clone())));
+    }
+    config
+        .state_file
+        .as_ref()
+        .map(|path| Arc::new(FileStore::new(path)) as Arc<dyn StateStore>)
+}
+
+/// Stores the state as JSON file on a local or shared file system
+pub struct FileStore {
+    path: String,
+}
+
+impl FileStore {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl StateStore for FileStore {
+    fn name(&self) -> String {
+        format!("file {}", self.path)
+    }
+
+    async fn load(&self) -> Result<Option<AppState>> {
+        let path = self.path.clone();
+        spawn_blocking(move || {
+            if !Path::new(&path).exists() {
+                return Ok(None);
+            }
+            AppState::load(&path).map(Some)
+        })
+        .await
+        .context("Failed to wait for loading state file")?
+    }
+
+    /// Uses a temporary file and a rename to make sure
+    /// readers never see a partially written file.
+    async fn save(&self, state: Arc<AppState>) -> Result<()> {
+        let path = self.path.clone();
+        spawn_blocking(move || {
+            let json = state.to_json()?;
+            let tmp_path = format!("{path}.tmp");
+            fs::write(&tmp_path, json).context("Failed to write temporary state file")?;
+            fs::rename(&tmp_path, &path).context("Failed to replace state file")
+        })
+        .await
+        .context("Failed to wait for writing state file")?
+    }
+
+    async fn version(&self) -> Result<Option<String>> {
+        let path = self.path.clone();
+        let metadata = match spawn_blocking(move || fs::metadata(path))
+            .await
+            .context("Failed to wait for metadata of state file")?
+        {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context("Failed to get metadata of state file"),
+        };
+        let modified = metadata
+            .modified()
+            .context("Failed to get modification time of state file")?;
+        let nanos = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(Some(format!("{}-{}", nanos.as_nanos(), metadata.len())))
+    }
+}
+
+/// Schema changes applied in order, the applied ones are recorded in `dmarc_schema_migrations`.
+/// Existing migrations must never be changed, only new ones appended.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE dmarc_state (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        data BYTEA NOT NULL,
+        version BIGINT NOT NULL,
+        saved_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    )",
+    "CREATE TABLE dmarc_reports (
+        id TEXT PRIMARY KEY,
+        org_name TEXT NOT NULL,
+        report_id TEXT NOT NULL,
+        domain TEXT NOT NULL,
+        date_begin TIMESTAMPTZ NOT NULL,
+        date_end TIMESTAMPTZ NOT NULL,
+        policy TEXT NOT NULL,
+        data JSONB NOT NULL
+    );
+    CREATE INDEX dmarc_reports_domain_date ON dmarc_reports (domain, date_begin);
+    CREATE TABLE dmarc_records (
+        report TEXT NOT NULL REFERENCES dmarc_reports (id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        source_ip INET NOT NULL,
+        count BIGINT NOT NULL,
+        disposition TEXT NOT NULL,
+        dkim TEXT,
+        spf TEXT,
+        header_from TEXT NOT NULL,
+        envelope_from TEXT,
+        PRIMARY KEY (report, position)
+    );
+    CREATE INDEX dmarc_records_source_ip ON dmarc_records (source_ip);",
+    // Hash of the stored report data to detect changed reports, like re-parsed ones
+    "ALTER TABLE dmarc_reports ADD COLUMN hash TEXT",
+];
+
+/// Key of the advisory lock held while migrating, so instances starting together wait for each other
+const MIGRATION_LOCK: i64 = 0x444d_4152_4353_5441;
+
+/// Stores the reports in the tables `dmarc_reports` and `dmarc_records` of a PostgreSQL database,
+/// where other tools can query them, and the rest of the state as JSON in a single row.
+/// Multiple instances can share the state without a shared file system.
+/// The connection is opened on first use and opened again after it was lost.
+pub struct PostgresStore {
+    config: Config,
+    client: Mutex<Option<Client>>,
+}
+
+impl PostgresStore {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            client: Mutex::new(None),
+        }
+    }
+
+    async fn client(&self) -> Result<MappedMutexGuard<'_, Client>> {
+        let mut slot = self.client.lock().await;
+        let client = match slot.take() {
+            Some(client) if !client.is_closed() => client,
+            _ => self.connect().await?,
+        };
+        Ok(MutexGuard::map(slot, |slot| slot.insert(client)))
+    }
+
+    /// Connects with TLS if the server supports it, verified with the webpki roots.
+    /// Use `sslmode=disable` in the URL for servers with self-signed certificates.
+    async fn connect(&self) -> Result<Client> {
+        let mut root_cert_store = RootCertStore::empty();
+        root_cert_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let tls_config = ClientConfig::builder()
+            .with_root_certificates(root_cert_store)
+            .with_no_client_auth();
+        let (mut client, connection) = self
+            .config
+            .connect(MakeRustlsConnect::new(tls_config))
+            .await
+            .context("Failed to connect to state database")?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                warn!("Lost connection to state database: {err}");
+            }
+        });
+        migrate(&mut client).await?;
+        Ok(client)
+    }
+}
+
+/// Applies the migrations that were not applied yet in a single transaction
+async fn migrate(client: &mut Client) -> Result<()> {
+    let transaction = client
+        .transaction()
+        .await
+        .context("Failed to start migration transaction")?;
+    transaction
+        .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+        .await
+        .context("Failed to lock state database for migration")?;
+    transaction
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS dmarc_schema_migrations (
+                version INTEGER PRIMARY KEY,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )",
+        )
+        .await
+        .context("Failed to create migrations table")?;
+    let applied: i32 = transaction
+        .query_one(
+            "SELECT COALESCE(MAX(version), 0) FROM dmarc_schema_migrations",
+            &[],
+        )
+        .await
+        .context("Failed to get applied migrations")?
+        .get(0);
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        let version = index as i32 + 1;
+        transaction
+            .batch_execute(migration)
+            .await
+            .with_context(|| format!("Failed to apply migration {version}"))?;
+        transaction
+            .execute(
+                "INSERT INTO dmarc_schema_migrations (version) VALUES ($1)",
+                &[&version],
+            )
+            .await
+            .context("Failed to record migration")?;
+        info!("Applied migration {version} to state database");
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit migrations")
+}
+
+#[async_trait]
+impl StateStore for PostgresStore {
+    fn name(&self) -> String {
+        let hosts: Vec<String> = self
+            .config
+            .get_hosts()
+            .iter()
+            .map(|host| match host {
+                Host::Tcp(host) => host.clone(),
+                #[cfg(unix)]
+                Host::Unix(path) => path.display().to_string(),
+            })
+            .collect();
+        format!(
+            "database {} on {}",
+            self.config.get_dbname().unwrap_or_default(),
+            hosts.join(", ")
+        )
+    }
+
+    /// Reports of states saved before the reports table existed are still part of the JSON
+    async fn load(&self) -> Result<Option<AppState>> {
+        let client = self.client().await?;
+        let row = client
+            .query_opt("SELECT data FROM dmarc_state WHERE id = 1", &[])
+            .await
+            .context("Failed to query state")?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let json: Vec<u8> = row.get(0);
+        let reports: Vec<String> = client
+            .query(
+                "SELECT data::TEXT FROM dmarc_reports ORDER BY date_end, id",
+                &[],
+            )
+            .await
+            .context("Failed to query reports")?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        drop(client);
+        spawn_blocking(move || {
+            let mut state = AppState::from_json(&json)?;
+            if state.reports.is_empty() {
+                let reports = reports
+                    .iter()
+                    .map(|json| serde_json::from_str(json))
+                    .collect::<Result<Vec<Report>, _>>()
+                    .context("Failed to parse stored report")?;
+                state.reports = Arc::new(reports);
+            }
+            // The flags are not stored with the reports, they change with the allowlist and notes
+            let reports = Arc::make_mut(&mut state.reports);
+            state.allowlist.mark(reports);
+            state.notes.mark(reports);
+            Ok(Some(state))
+        })
+        .await
+        .context("Failed to wait for parsing state")?
+    }
+
+    /// Only reports that are new or changed since the last save, like re-parsed ones, are written
+    /// and removed ones deleted. Changes are detected with a hash of the serialized report.
+    /// The reports, the rest of the state and its version, which is incremented with every save,
+    /// are changed in a single transaction.
+    async fn save(&self, state: Arc<AppState>) -> Result<()> {
+        let (json, mut reports) = spawn_blocking(move || -> Result<_> {
+            let rest = AppState {
+                reports: Arc::default(),
+                ..AppState::clone(&state)
+            };
+            let mut reports = HashMap::new();
+            for report in state.reports.iter() {
+                let mut report = report.clone();
+                for record in &mut report.record {
+                    record.unexpected = false;
+                    record.acknowledged = false;
+                }
+                let data = serde_json::to_string(&report).context("Failed to serialize report")?;
+                let hash = hash_data(data.as_bytes());
+                reports.insert(report.stable_id(), (hash, data, report));
+            }
+            Ok((rest.to_json()?, reports))
+        })
+        .await
+        .context("Failed to wait for serializing state")??;
+
+        let mut client = self.client().await?;
+        let transaction = client
+            .transaction()
+            .await
+            .context("Failed to start transaction")?;
+        let stored: Vec<(String, Option<String>)> = transaction
+            .query("SELECT id, hash FROM dmarc_reports", &[])
+            .await
+            .context("Failed to query stored reports")?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        let mut removed = Vec::new();
+        for (id, stored_hash) in stored {
+            match reports.get(&id) {
+                None => removed.push(id),
+                Some((hash, ..)) if stored_hash.as_ref() == Some(hash) => {
+                    reports.remove(&id);
+                }
+                Some(..) => {}
+            }
+        }
+        transaction
+            .execute("DELETE FROM dmarc_reports WHERE id = ANY($1)", &[&removed])
+            .await
+            .context("Failed to delete removed reports")?;
+        // Changed reports get all their records written again
+        let changed: Vec<&String> = reports.keys().collect();
+        transaction
+            .execute(
+                "DELETE FROM dmarc_records WHERE report = ANY($1)",
+                &[&changed],
+            )
+            .await
+            .context("Failed to delete records of changed reports")?;
+
+        let upsert_report = transaction
+            .prepare(
+                "INSERT INTO dmarc_reports
+                 (id, org_name, report_id, domain, date_begin, date_end, policy, data, hash)
+                 VALUES ($1, $2, $3, $4, to_timestamp($5::BIGINT), to_timestamp($6::BIGINT), $7, $8::TEXT::JSONB, $9)
+                 ON CONFLICT (id) DO UPDATE
+                 SET org_name = EXCLUDED.org_name, report_id = EXCLUDED.report_id,
+                     domain = EXCLUDED.domain, date_begin = EXCLUDED.date_begin,
+                     date_end = EXCLUDED.date_end, policy = EXCLUDED.policy,
+                     data = EXCLUDED.data, hash = EXCLUDED.hash",
+            )
+            .await
+            .context("Failed to prepare report upsert")?;
+        let insert_record = transaction
+            .prepare(
+                "INSERT INTO dmarc_records
+                 (report, position, source_ip, count, disposition, dkim, spf, header_from, envelope_from)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            )
+            .await
+            .context("Failed to prepare record insert")?;
+        for (id, (hash, data, report)) in &reports {
+            let metadata = &report.report_metadata;
+            transaction
+                .execute(
+                    &upsert_report,
+                    &[
+                        id,
+                        &metadata.org_name,
+                        &metadata.report_id,
+                        &report.policy_published.domain,
+                        &(metadata.date_range.begin as i64),
+                        &(metadata.date_range.end as i64),
+                        &String::from(report.policy_published.p.clone()),
+                        data,
+                        hash,
+                    ],
+                )
+                .await
+                .context("Failed to write report")?;
+            for (position, record) in report.record.iter().enumerate() {
+                let evaluated = &record.row.policy_evaluated;
+                transaction
+                    .execute(
+                        &insert_record,
+                        &[
+                            id,
+                            &(position as i32),
+                            &record.row.source_ip,
+                            &(record.row.count as i64),
+                            &String::from(evaluated.disposition.clone()),
+                            &evaluated.dkim.clone().map(String::from),
+                            &evaluated.spf.clone().map(String::from),
+                            &record.identifiers.header_from,
+                            &record.identifiers.envelope_from,
+                        ],
+                    )
+                    .await
+                    .context("Failed to insert record")?;
+            }
+        }
+
+        transaction
+            .execute(
+                "INSERT INTO dmarc_state (id, data, version) VALUES (1, $1, 1)
+                 ON CONFLICT (id) DO UPDATE
+                 SET data = EXCLUDED.data, version = dmarc_state.version + 1, saved_at = now()",
+                &[&json],
+            )
+            .await
+            .context("Failed to save state")?;
+        transaction.commit().await.context("Failed to commit state")
+    }
+
+    async fn version(&self) -> Result<Option<String>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt("SELECT version FROM dmarc_state WHERE id = 1", &[])
+            .await
+            .context("Failed to query state version")?;
+        Ok(row.map(|row| row.get::<_, i64>(0).to_string()))
+    }
+}
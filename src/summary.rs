@@ -1,105 +1,313 @@
-use crate::report::{DkimResultType, DmarcResultType, Report, SpfResultType};
-use serde::Serialize;
-use std::collections::HashMap;
-
-#[derive(Serialize, Default, Clone)]
-pub struct Summary {
-    /// Number of mails from IMAP inbox
-    pub mails: usize,
-
-    /// Number of XML files found in mails from IMAPinbox
-    pub xml_files: usize,
-
-    /// Number of successfully parsed DMARC reports XML files found in IMAP inbox
-    pub reports: usize,
-
-    /// Unix timestamp with time of last update
-    pub last_update: u64,
-
-    /// Map of organizations with number of corresponding reports
-    orgs: HashMap<String, usize>,
-
-    /// Map of domains with number of corresponding reports
-    domains: HashMap<String, usize>,
-
-    /// Map of SPF policy evaluation results
-    spf_policy_results: HashMap<DmarcResultType, usize>,
-
-    /// Map of DKIM policy evaluation results
-    dkim_policy_results: HashMap<DmarcResultType, usize>,
-
-    /// Map of SPF auth results
-    spf_auth_results: HashMap<SpfResultType, usize>,
-
-    /// Map of DKIM auth results
-    dkim_auth_results: HashMap<DkimResultType, usize>,
-}
-
-impl Summary {
-    pub fn new(mails: usize, xml_files: usize, reports: &[Report], last_update: u64) -> Self {
-        let mut orgs: HashMap<String, usize> = HashMap::new();
-        let mut domains = HashMap::new();
-        let mut spf_policy_results: HashMap<DmarcResultType, usize> = HashMap::new();
-        let mut dkim_policy_results: HashMap<DmarcResultType, usize> = HashMap::new();
-        let mut spf_auth_results: HashMap<SpfResultType, usize> = HashMap::new();
-        let mut dkim_auth_results: HashMap<DkimResultType, usize> = HashMap::new();
-        for report in reports {
-            for record in &report.record {
-                for r in &record.auth_results.spf {
-                    if let Some(entry) = spf_auth_results.get_mut(&r.result) {
-                        *entry += 1;
-                    } else {
-                        spf_auth_results.insert(r.result.clone(), 1);
-                    }
-                }
-                if let Some(vec) = &record.auth_results.dkim {
-                    for r in vec {
-                        if let Some(entry) = dkim_auth_results.get_mut(&r.result) {
-                            *entry += 1;
-                        } else {
-                            dkim_auth_results.insert(r.result.clone(), 1);
-                        }
-                    }
-                }
-                if let Some(result) = &record.row.policy_evaluated.spf {
-                    if let Some(entry) = spf_policy_results.get_mut(result) {
-                        *entry += 1;
-                    } else {
-                        spf_policy_results.insert(result.clone(), 1);
-                    }
-                }
-                if let Some(result) = &record.row.policy_evaluated.dkim {
-                    if let Some(entry) = dkim_policy_results.get_mut(result) {
-                        *entry += 1;
-                    } else {
-                        dkim_policy_results.insert(result.clone(), 1);
-                    }
-                }
-            }
-            let org = report.report_metadata.org_name.clone();
-            if let Some(entry) = orgs.get_mut(&org) {
-                *entry += 1;
-            } else {
-                orgs.insert(org, 1);
-            }
-            let domain = report.policy_published.domain.clone();
-            if let Some(entry) = domains.get_mut(&domain) {
-                *entry += 1;
-            } else {
-                domains.insert(domain, 1);
-            }
-        }
-        Self {
-            mails,
-            xml_files,
-            last_update,
-            reports: reports.len(),
-            orgs,
-            domains,
-            spf_policy_results,
-            dkim_policy_results,
-            spf_auth_results,
-            dkim_auth_results,
-        }
-    }
-}
+use crate::compliance::ComplianceScore;
+use crate::filter::RecordFilter;
+use crate::report::{DkimResultType, DmarcResultType, Report, SpfResultType};
+use crate::timeseries::{Interval, Timezone, DAY};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+/// Time window of a summary ending now, like `24h`, `7d` or `all`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SummaryWindow {
+    /// Rolling window of hours
+    Hours(u64),
+    /// Whole local days up to and including today
+    Days(u64),
+    All,
+}
+
+impl SummaryWindow {
+    /// Start of the window as Unix timestamp, nothing for all reports
+    pub fn since(&self, now: u64, timezone: Timezone) -> Option<u64> {
+        match self {
+            SummaryWindow::Hours(hours) => Some(now.saturating_sub(hours.saturating_mul(60 * 60))),
+            SummaryWindow::Days(days) => {
+                let today = Interval::Daily.bucket_start(now, timezone);
+                Some(today.saturating_sub(days.saturating_sub(1).saturating_mul(DAY)))
+            }
+            SummaryWindow::All => None,
+        }
+    }
+}
+
+impl FromStr for SummaryWindow {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_lowercase();
+        if value == "all" {
+            return Ok(SummaryWindow::All);
+        }
+        let invalid = || format!("Invalid summary window '{value}', expected e.g. 24h, 7d or all");
+        let (count, unit, window): (_, _, fn(u64) -> Self) =
+            if let Some(count) = value.strip_suffix('h') {
+                (count, 60 * 60, SummaryWindow::Hours)
+            } else if let Some(count) = value.strip_suffix('d') {
+                (count, DAY, SummaryWindow::Days)
+            } else {
+                return Err(invalid());
+            };
+        let count: u64 = count.parse().map_err(|_| invalid())?;
+        // The window must be expressible in seconds
+        if count == 0 || count.checked_mul(unit).is_none() {
+            return Err(invalid());
+        }
+        Ok(window(count))
+    }
+}
+
+impl Display for SummaryWindow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SummaryWindow::Hours(hours) => write!(f, "{hours}h"),
+            SummaryWindow::Days(days) => write!(f, "{days}d"),
+            SummaryWindow::All => write!(f, "all"),
+        }
+    }
+}
+
+/// Summary of one of the configured windows
+#[derive(Serialize, ToSchema)]
+pub struct WindowSummary {
+    /// Window as configured, like `7d`
+    pub window: String,
+    pub summary: Summary,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, ToSchema)]
+pub struct Summary {
+    /// Number of mails from IMAP inbox, copies of the same mail are counted once
+    pub mails: usize,
+
+    /// Number of XML files found in mails from IMAPinbox
+    pub xml_files: usize,
+
+    /// Number of successfully parsed DMARC reports XML files found in IMAP inbox
+    pub reports: usize,
+
+    /// Number of skipped reports with already known organization and report ID
+    #[serde(default)]
+    pub duplicates: usize,
+
+    /// Number of records from sources that are not on the allowlist of their domain
+    #[serde(default)]
+    pub unexpected: usize,
+
+    /// Unix timestamp with time of last update
+    pub last_update: u64,
+
+    /// Time window of the aggregated reports as Unix timestamps, unlimited if not set.
+    /// The counts of mails, XML files and duplicates always cover all reports.
+    #[serde(default)]
+    pub since: Option<u64>,
+    #[serde(default)]
+    pub until: Option<u64>,
+
+    /// Rolling compliance score per domain
+    #[serde(default)]
+    pub compliance: Vec<ComplianceScore>,
+
+    /// Durations and counts of the last finished update cycle
+    #[serde(default)]
+    pub last_cycle: Option<CycleMetrics>,
+
+    /// Map of organizations with number of corresponding reports
+    pub orgs: HashMap<String, usize>,
+
+    /// Map of domains with number of corresponding reports
+    pub domains: HashMap<String, usize>,
+
+    /// Map of envelope to domains with number of corresponding records
+    #[serde(default)]
+    envelope_to: HashMap<String, usize>,
+
+    /// Map of SPF policy evaluation results
+    spf_policy_results: HashMap<DmarcResultType, usize>,
+
+    /// Map of DKIM policy evaluation results
+    dkim_policy_results: HashMap<DmarcResultType, usize>,
+
+    /// Map of SPF auth results
+    spf_auth_results: HashMap<SpfResultType, usize>,
+
+    /// Map of DKIM auth results
+    dkim_auth_results: HashMap<DkimResultType, usize>,
+}
+
+/// Durations and counts of a finished update cycle
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct CycleMetrics {
+    pub cycle_id: u64,
+
+    /// Start of the cycle as Unix timestamp
+    pub started: u64,
+    pub duration_ms: u64,
+
+    pub fetch_ms: u64,
+    pub extract_ms: u64,
+    pub parse_ms: u64,
+
+    /// Mails and reports after the cycle and the change since the previous cycle
+    pub mails: usize,
+    pub mails_delta: i64,
+    pub reports: usize,
+    pub reports_delta: i64,
+
+    pub extract_errors: usize,
+    pub parse_errors: usize,
+    #[serde(default)]
+    pub duplicate_mails: usize,
+    #[serde(default)]
+    pub panics: usize,
+
+    /// Error that made the cycle fail
+    pub error: Option<String>,
+}
+
+impl Summary {
+    pub fn new(
+        mails: usize,
+        xml_files: usize,
+        reports: &[Report],
+        duplicates: usize,
+        last_update: u64,
+    ) -> Self {
+        let mut summary = Self::aggregate(reports, &RecordFilter::default());
+        summary.mails = mails;
+        summary.xml_files = xml_files;
+        summary.duplicates = duplicates;
+        summary.last_update = last_update;
+        summary
+    }
+
+    /// Summary of reports that were not fetched from a mailbox, like the ones of a library user
+    pub fn from_reports(reports: &[Report]) -> Self {
+        Self::aggregate(reports, &RecordFilter::default())
+    }
+
+    /// Same summary but only aggregating matching reports and records
+    pub fn filtered(&self, reports: &[Report], filter: &RecordFilter) -> Self {
+        let mut summary = Self::aggregate(reports, filter);
+        summary.mails = self.mails;
+        summary.xml_files = self.xml_files;
+        summary.duplicates = self.duplicates;
+        summary.last_update = self.last_update;
+        summary.compliance = self.compliance.clone();
+        summary.since = filter.since;
+        summary.until = filter.until;
+        summary
+    }
+
+    fn aggregate(reports: &[Report], filter: &RecordFilter) -> Self {
+        let mut count = 0;
+        let mut unexpected = 0;
+        let mut orgs: HashMap<String, usize> = HashMap::new();
+        let mut domains = HashMap::new();
+        let mut spf_policy_results: HashMap<DmarcResultType, usize> = HashMap::new();
+        let mut dkim_policy_results: HashMap<DmarcResultType, usize> = HashMap::new();
+        let mut spf_auth_results: HashMap<SpfResultType, usize> = HashMap::new();
+        let mut dkim_auth_results: HashMap<DkimResultType, usize> = HashMap::new();
+        let mut envelope_to: HashMap<String, usize> = HashMap::new();
+        for report in reports.iter().filter(|r| filter.matches_report_records(r)) {
+            count += 1;
+            for record in report.record.iter().filter(|r| filter.matches_record(r)) {
+                if record.unexpected {
+                    unexpected += 1;
+                }
+                if let Some(domain) = &record.identifiers.envelope_to {
+                    *envelope_to.entry(domain.to_lowercase()).or_default() += 1;
+                }
+                for r in &record.auth_results.spf {
+                    if let Some(entry) = spf_auth_results.get_mut(&r.result) {
+                        *entry += 1;
+                    } else {
+                        spf_auth_results.insert(r.result.clone(), 1);
+                    }
+                }
+                if let Some(vec) = &record.auth_results.dkim {
+                    for r in vec {
+                        if let Some(entry) = dkim_auth_results.get_mut(&r.result) {
+                            *entry += 1;
+                        } else {
+                            dkim_auth_results.insert(r.result.clone(), 1);
+                        }
+                    }
+                }
+                if let Some(result) = &record.row.policy_evaluated.spf {
+                    if let Some(entry) = spf_policy_results.get_mut(result) {
+                        *entry += 1;
+                    } else {
+                        spf_policy_results.insert(result.clone(), 1);
+                    }
+                }
+                if let Some(result) = &record.row.policy_evaluated.dkim {
+                    if let Some(entry) = dkim_policy_results.get_mut(result) {
+                        *entry += 1;
+                    } else {
+                        dkim_policy_results.insert(result.clone(), 1);
+                    }
+                }
+            }
+            let org = report.report_metadata.org_name.clone();
+            if let Some(entry) = orgs.get_mut(&org) {
+                *entry += 1;
+            } else {
+                orgs.insert(org, 1);
+            }
+            let domain = report.policy_published.domain.clone();
+            if let Some(entry) = domains.get_mut(&domain) {
+                *entry += 1;
+            } else {
+                domains.insert(domain, 1);
+            }
+        }
+        Self {
+            reports: count,
+            unexpected,
+            orgs,
+            domains,
+            spf_policy_results,
+            dkim_policy_results,
+            spf_auth_results,
+            dkim_auth_results,
+            envelope_to,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_windows() {
+        let windows: Vec<SummaryWindow> = ["24h", "7D", " all"]
+            .iter()
+            .map(|w| w.parse().unwrap())
+            .collect();
+        assert_eq!(
+            windows,
+            [
+                SummaryWindow::Hours(24),
+                SummaryWindow::Days(7),
+                SummaryWindow::All
+            ]
+        );
+        assert_eq!(windows[1].to_string(), "7d");
+        assert!("0d".parse::<SummaryWindow>().is_err());
+        assert!("7w".parse::<SummaryWindow>().is_err());
+        assert!("".parse::<SummaryWindow>().is_err());
+        assert!("7ä".parse::<SummaryWindow>().is_err());
+        assert!("ä".parse::<SummaryWindow>().is_err());
+        assert!(format!("{}h", u64::MAX).parse::<SummaryWindow>().is_err());
+
+        let now = 10 * DAY + 60;
+        let utc = Timezone::default();
+        assert_eq!(windows[0].since(now, utc), Some(9 * DAY + 60));
+        assert_eq!(windows[1].since(now, utc), Some(4 * DAY));
+        assert_eq!(windows[2].since(now, utc), None);
+    }
+}
@@ -0,0 +1,195 @@
+use crate::config::Configuration;
+use crate::events::{Event, Events};
+use crate::report::Report;
+use crate::state::{AppState, SharedState};
+use crate::status::unix_time;
+use crate::timeseries::DAY;
+use anyhow::{Context, Result};
+use reqwest::Client;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Path of the sync endpoint below the base URL of an instance
+pub const SYNC_PATH: &str = "/api/sync";
+
+/// Reports often arrive days after the end of their date range,
+/// so every sync fetches the reports of the last days again and skips the known ones
+const SYNC_OVERLAP: u64 = 7 * DAY;
+
+/// Timeout for requests to other instances in seconds
+const TIMEOUT: u64 = 60;
+
+/// Reports of an instance with a date range ending at or after `since`, to be pulled by other instances.
+/// The mail UIDs and archived files only have a meaning for this instance and are removed.
+pub fn sync_reports(reports: &[Report], since: u64) -> Vec<Report> {
+    reports
+        .iter()
+        .filter(|r| r.report_metadata.date_range.end >= since)
+        .map(|r| Report {
+            mail_uid: None,
+            xml_hash: None,
+            attachment_name: None,
+            ..r.clone()
+        })
+        .collect()
+}
+
+/// Add the reports pulled from another instance, skipping the ones with a known report ID.
+/// Like reports ingested via HTTP they have no mail and are kept by the update cycles.
+/// The hashes of archived files are removed as well, they name files in the archive of the other instance.
+/// Returns the number of added reports.
+pub fn merge_reports(state: &mut AppState, mut reports: Vec<Report>, timestamp: u64) -> usize {
+    let mut known: HashSet<(String, String)> = state
+        .reports
+        .iter()
+        .map(|r| (r.key().0.to_owned(), r.key().1.to_owned()))
+        .collect();
+    reports.retain(|r| known.insert((r.key().0.to_owned(), r.key().1.to_owned())));
+    if reports.is_empty() {
+        return 0;
+    }
+    let count = reports.len();
+    for report in &mut reports {
+        report.mail_uid = None;
+        report.xml_hash = None;
+        report.attachment_name = None;
+    }
+    state.allowlist.mark(&mut reports);
+    state.notes.mark(&mut reports);
+    Arc::make_mut(&mut state.reports).append(&mut reports);
+    state.xml_files += count;
+    state.refresh_summary(timestamp);
+    count
+}
+
+/// Start the task pulling new reports from the configured instances.
+/// Returns nothing if no instances are configured.
+pub fn start_sync_task(
+    config: &Configuration,
+    state: Arc<SharedState>,
+    events: Arc<Events>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Option<JoinHandle<()>> {
+    if config.sync_peers.is_empty() {
+        return None;
+    }
+    let peers = config.sync_peers.clone();
+    let token = config.sync_token.clone();
+    let interval = Duration::from_secs(config.sync_interval);
+    Some(tokio::spawn(async move {
+        let client = match Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT))
+            .build()
+        {
+            Ok(client) => client,
+            Err(err) => {
+                error!("Failed to create HTTP client for sync: {err:#}");
+                return;
+            }
+        };
+        info!(
+            "Started sync task for {} instances with interval of {} secs",
+            peers.len(),
+            interval.as_secs()
+        );
+
+        // End of the newest report pulled per instance, starts with a full sync after every restart
+        let mut newest: HashMap<String, u64> = HashMap::new();
+        loop {
+            for peer in &peers {
+                let since = newest
+                    .get(peer)
+                    .map_or(0, |end| end.saturating_sub(SYNC_OVERLAP));
+                match pull_reports(&client, peer, token.as_deref(), since).await {
+                    Ok(reports) => {
+                        if let Some(end) = reports
+                            .iter()
+                            .map(|r| r.report_metadata.date_range.end)
+                            .max()
+                        {
+                            let entry = newest.entry(peer.clone()).or_default();
+                            *entry = end.max(*entry);
+                        }
+                        let timestamp = unix_time();
+                        let count = state.update(|s| merge_reports(s, reports, timestamp));
+                        info!("Pulled {count} new reports from {peer}");
+                        if count > 0 {
+                            events.send(Event::NewReports { count });
+                        }
+                    }
+                    Err(err) => warn!("Failed to pull reports from {peer}: {err:#}"),
+                }
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {},
+                _ = shutdown.wait_for(|stop| *stop) => {
+                    info!("Stopped sync task");
+                    return;
+                },
+            }
+        }
+    }))
+}
+
+async fn pull_reports(
+    client: &Client,
+    peer: &str,
+    token: Option<&str>,
+    since: u64,
+) -> Result<Vec<Report>> {
+    let url = format!("{}{SYNC_PATH}", peer.trim_end_matches('/'));
+    let mut request = client.get(&url).query(&[("since", since)]);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    request
+        .send()
+        .await
+        .with_context(|| format!("Failed to request {url}"))?
+        .error_for_status()
+        .context("Instance rejected sync request")?
+        .json()
+        .await
+        .context("Failed to parse reports")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn merge_by_report_id() {
+        let mut edge = Vec::new();
+        for name in ["acme", "aol", "google"] {
+            let xml = fs::read(format!("testdata/dmarc-reports/{name}.xml")).unwrap();
+            let mut report = parse_xml_file(&xml).unwrap();
+            report.mail_uid = Some(42);
+            report.xml_hash = Some("../../etc/passwd".to_owned());
+            edge.push(report);
+        }
+        let since = edge[1].report_metadata.date_range.end;
+        let pulled = sync_reports(&edge, since);
+        assert!(pulled.iter().all(|r| r.mail_uid.is_none()));
+        assert!(pulled
+            .iter()
+            .all(|r| r.report_metadata.date_range.end >= since));
+
+        let mut central = AppState::default();
+        assert_eq!(merge_reports(&mut central, sync_reports(&edge, 0), 1), 3);
+        assert_eq!(merge_reports(&mut central, pulled, 2), 0);
+        assert_eq!(central.reports.len(), 3);
+        assert_eq!(central.summary.reports, 3);
+        assert!(central.reports.iter().all(|r| r.mail_uid.is_none()));
+
+        // Reports of older instances may still contain the hashes
+        let mut central = AppState::default();
+        assert_eq!(merge_reports(&mut central, edge, 1), 3);
+        assert!(central.reports.iter().all(|r| r.xml_hash.is_none()));
+    }
+}
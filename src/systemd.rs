@@ -0,0 +1,134 @@
+use crate::events::{Event, Events};
+use crate::state::SharedState;
+use axum_server::Handle;
+use std::env;
+use std::ffi::OsStr;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+/// Services started with `Type=notify` get the socket of the service manager in this variable
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+
+/// Whether the service manager expects notifications
+pub fn enabled() -> bool {
+    env::var_os(NOTIFY_SOCKET).is_some()
+}
+
+/// Send a state like `READY=1` to the service manager, nothing happens without `NOTIFY_SOCKET`
+pub fn notify(state: &str) {
+    let Some(socket) = env::var_os(NOTIFY_SOCKET) else {
+        return;
+    };
+    if let Err(err) = send(&socket, state) {
+        warn!("Failed to send {state} to systemd: {err:#}");
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    // Names starting with @ are sockets in the abstract namespace of Linux
+    #[cfg(target_os = "linux")]
+    if let Some(name) = socket.as_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let addr = SocketAddr::from_abstract_name(name)?;
+        datagram.send_to_addr(state.as_bytes(), &addr)?;
+        return Ok(());
+    }
+    datagram.send_to(state.as_bytes(), socket)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &OsStr, _state: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// Interval of the watchdog keepalives, half of the timeout requested by the service manager.
+/// Nothing if the watchdog is disabled or meant for another process.
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(std::process::id())) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// Run the future and send watchdog keepalives while it runs, if the service manager asks for them.
+/// The keepalives stop when the task running the future is blocked, so systemd restarts the service.
+pub async fn with_watchdog<F: Future>(future: F) -> F::Output {
+    let usec = env::var("WATCHDOG_USEC").ok();
+    let pid = env::var("WATCHDOG_PID").ok();
+    let Some(interval) = watchdog_interval(usec.as_deref(), pid.as_deref()) else {
+        return future.await;
+    };
+    info!(
+        "Sending systemd watchdog keepalives every {} ms",
+        interval.as_millis()
+    );
+    let keepalive = async {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            notify("WATCHDOG=1");
+        }
+    };
+    tokio::select! {
+        output = future => output,
+        _ = keepalive => unreachable!("Watchdog keepalives never stop"),
+    }
+}
+
+/// Send `READY=1` once the HTTP server listens and the first update cycle finished,
+/// successfully or not. Restored or already updated state is ready right away.
+pub async fn notify_ready(handle: Handle, state: Arc<SharedState>, events: Arc<Events>) {
+    let mut events = events.subscribe();
+    if handle.listening().await.is_none() {
+        return;
+    }
+    let finished = {
+        let status = state.status();
+        status.last_success.is_some() || status.last_error.is_some()
+    };
+    if !finished && !state.snapshot().ready {
+        loop {
+            match events.recv().await {
+                Ok(Event::CycleFinished { .. }) => break,
+                Ok(..) | Err(RecvError::Lagged(..)) => {}
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+    info!("Notifying systemd that the service is ready");
+    notify("READY=1");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_keepalive_interval() {
+        let own_pid = std::process::id().to_string();
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some(&own_pid)),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("1")), None);
+        assert_eq!(watchdog_interval(Some("0"), None), None);
+        assert_eq!(watchdog_interval(None, None), None);
+    }
+}
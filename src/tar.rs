@@ -0,0 +1,103 @@
+use anyhow::{bail, Context, Result};
+
+/// Size of headers and the alignment of file contents
+const BLOCK: usize = 512;
+
+/// Detects uncompressed TAR archives by the magic of POSIX and GNU headers
+pub fn is_tar(data: &[u8]) -> bool {
+    data.len() >= BLOCK && data[257..262] == *b"ustar"
+}
+
+/// Regular files of an uncompressed TAR archive with their names.
+/// Supports POSIX (ustar) headers and GNU long names,
+/// other special entries like directories or links are skipped.
+pub fn tar_files(data: &[u8]) -> Result<Vec<(String, &[u8])>> {
+    let mut files = Vec::new();
+    let mut long_name = None;
+    let mut offset = 0;
+    while offset + BLOCK <= data.len() {
+        let header = &data[offset..offset + BLOCK];
+        // The archive ends with empty blocks
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let size = parse_octal(&header[124..136]).context("Invalid file size in TAR header")?;
+        let start = offset + BLOCK;
+        let Some(content) = data.get(start..start + size) else {
+            bail!("TAR archive is truncated");
+        };
+        match header[156] {
+            b'0' | 0 => {
+                let name = long_name.take().unwrap_or_else(|| header_name(header));
+                files.push((name, content));
+            }
+            b'L' => long_name = Some(text(content)),
+            _ => long_name = None,
+        }
+        offset = start + size.div_ceil(BLOCK) * BLOCK;
+    }
+    Ok(files)
+}
+
+/// Name of the entry including the prefix of POSIX headers for long paths
+fn header_name(header: &[u8]) -> String {
+    let name = text(&header[0..100]);
+    let prefix = text(&header[345..500]);
+    if header[257..263] == *b"ustar\0" && !prefix.is_empty() {
+        format!("{prefix}/{name}")
+    } else {
+        name
+    }
+}
+
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn parse_octal(field: &[u8]) -> Result<usize> {
+    let digits = text(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    Ok(usize::from_str_radix(digits, 8)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, kind: u8, content: &[u8]) -> Vec<u8> {
+        let mut header = vec![0; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        let size = format!("{:011o}\0", content.len());
+        header[124..136].copy_from_slice(size.as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        let mut entry = header;
+        entry.extend_from_slice(content);
+        entry.resize(entry.len().div_ceil(BLOCK) * BLOCK, 0);
+        entry
+    }
+
+    #[test]
+    fn read_files() {
+        let long_name = format!("{}.xml", "a".repeat(120));
+        let mut archive = entry("reports/", b'5', b"");
+        archive.extend(entry("reports/first.xml", b'0', b"<feedback/>"));
+        archive.extend(entry("././@LongLink", b'L', long_name.as_bytes()));
+        archive.extend(entry("truncated", b'0', &[b'x'; 600]));
+        archive.extend(vec![0; 2 * BLOCK]);
+        assert!(is_tar(&archive));
+        let files = tar_files(&archive).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[0],
+            ("reports/first.xml".to_owned(), &b"<feedback/>"[..])
+        );
+        assert_eq!(files[1].0, long_name);
+        assert_eq!(files[1].1.len(), 600);
+        assert!(tar_files(&archive[..BLOCK * 4]).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::filter::TenantDomains;
+use anyhow::{ensure, Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use subtle::ConstantTimeEq;
+
+/// API endpoints available to tenant users, all others are reserved to operators.
+/// Endpoints outside of /api/ are available except for the ones listed in `OPERATOR_PATHS`.
+const TENANT_API_PATHS: &[&str] = &[
+    "/api/summary",
+    "/api/domains",
+    "/api/compliance",
+    "/api/reporters",
+    "/api/selectors",
+    "/api/forwarding",
+    "/api/overrides",
+    "/api/enforcement",
+    "/api/timeseries",
+    "/api/top-offenders",
+    "/api/charts",
+    "/api/sources",
+    "/api/policies",
+    "/api/reports",
+    "/api/records",
+    "/api/search",
+    "/api/export/csv",
+    "/api/export/xlsx",
+    "/api/dns/ptr",
+    "/api/dns/txt",
+    "/api/dns/health",
+    "/api/dns/spf",
+    "/api/rdap",
+    "/api/ips",
+    "/api/advice",
+    "/api/session",
+];
+
+/// Pages outside of /api/ with data of all domains
+const OPERATOR_PATHS: &[&str] = &["/graphql", "/mails", "/xml-errors"];
+
+/// Customer with the domains it may see and the users and API tokens assigned to it
+#[derive(Deserialize)]
+pub struct Tenant {
+    pub domains: Vec<String>,
+
+    /// Names of users from the users file or the authenticating reverse proxy
+    #[serde(default)]
+    pub users: Vec<String>,
+
+    /// Bearer tokens for read-only access to the /api/ endpoints of the tenant
+    #[serde(default)]
+    pub api_tokens: Vec<String>,
+}
+
+/// Tenants by name from the tenants file, users and tokens not assigned to a tenant see all domains
+#[derive(Default)]
+pub struct Tenants {
+    tenants: BTreeMap<String, Tenant>,
+}
+
+impl Tenants {
+    /// Reads the tenants from a JSON file with an object of tenants by name
+    pub fn load(path: &str) -> Result<Self> {
+        let json = fs::read(path).with_context(|| format!("Failed to read tenants file {path}"))?;
+        let tenants: BTreeMap<String, Tenant> = serde_json::from_slice(&json)
+            .with_context(|| format!("Failed to parse tenants file {path}"))?;
+        for (name, tenant) in &tenants {
+            ensure!(!tenant.domains.is_empty(), "Tenant {name} has no domains");
+        }
+        Ok(Self { tenants })
+    }
+
+    pub fn len(&self) -> usize {
+        self.tenants.len()
+    }
+
+    /// Domains of all tenants of the user, nothing if the user is not assigned to a tenant
+    pub fn user_domains(&self, name: &str) -> Option<TenantDomains> {
+        self.domains(|tenant| tenant.users.iter().any(|u| u == name))
+    }
+
+    /// Domains of all tenants with the API token, compared in constant time
+    pub fn token_domains(&self, token: &str) -> Option<TenantDomains> {
+        self.domains(|tenant| {
+            tenant.api_tokens.iter().fold(false, |valid, t| {
+                valid | bool::from(t.as_bytes().ct_eq(token.as_bytes()))
+            })
+        })
+    }
+
+    fn domains(&self, assigned: impl Fn(&Tenant) -> bool) -> Option<TenantDomains> {
+        let domains: HashSet<String> = self
+            .tenants
+            .values()
+            .filter(|tenant| assigned(tenant))
+            .flat_map(|tenant| tenant.domains.iter().map(|d| d.to_lowercase()))
+            .collect();
+        (!domains.is_empty()).then(|| TenantDomains::new(domains))
+    }
+}
+
+/// Checks if tenant users may request the path
+pub fn tenant_path(path: &str) -> bool {
+    let matches = |prefix: &&str| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    };
+    if path.starts_with("/api/") {
+        TENANT_API_PATHS.iter().any(matches)
+    } else {
+        !OPERATOR_PATHS.iter().any(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tenant_access() {
+        let tenants = Tenants {
+            tenants: serde_json::from_str(
+                r#"{
+                    "acme": {"domains": ["Acme.com"], "users": ["alice"], "api_tokens": ["t1"]},
+                    "globex": {"domains": ["globex.org"], "users": ["alice", "bob"]}
+                }"#,
+            )
+            .unwrap(),
+        };
+        let alice = tenants.user_domains("alice").unwrap();
+        assert!(alice.allows("acme.com"));
+        assert!(alice.allows("mail.ACME.com"));
+        assert!(alice.allows("globex.org"));
+        assert!(!alice.allows("notacme.com"));
+        let bob = tenants.user_domains("bob").unwrap();
+        assert!(!bob.allows("acme.com"));
+        assert!(tenants.user_domains("operator").is_none());
+        assert!(tenants.token_domains("t1").unwrap().allows("acme.com"));
+        assert!(tenants.token_domains("t2").is_none());
+
+        assert!(tenant_path("/api/reports/abc/xml"));
+        assert!(tenant_path("/summary"));
+        assert!(tenant_path("/index.html"));
+        assert!(!tenant_path("/api/reportsx"));
+        assert!(!tenant_path("/api/mails"));
+        assert!(!tenant_path("/mails"));
+        assert!(!tenant_path("/api/admin/settings"));
+        // Cycle status and events contain errors and counts of all domains
+        assert!(!tenant_path("/api/status"));
+        assert!(!tenant_path("/api/status/history"));
+        assert!(!tenant_path("/api/events"));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::filter::RecordFilter;
+use crate::state::AppState;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// Values of the state that can be checked after a single update cycle
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Metric {
+    /// XML files that could not be parsed
+    ParseErrors,
+    /// Reports within the summary window
+    Reports,
+    /// Messages failing DMARC within the summary window
+    DmarcFailures,
+    /// Records from sources not on the allowlist of their domain within the summary window
+    Unexpected,
+    /// Lowest compliance score of all domains in percent
+    Compliance,
+}
+
+const METRICS: &[(Metric, &str)] = &[
+    (Metric::ParseErrors, "parse-errors"),
+    (Metric::Reports, "reports"),
+    (Metric::DmarcFailures, "dmarc-failures"),
+    (Metric::Unexpected, "unexpected"),
+    (Metric::Compliance, "compliance"),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+/// Longer operators first, so `>=` is not parsed as `>`
+const COMPARISONS: &[(Comparison, &str)] = &[
+    (Comparison::GreaterOrEqual, ">="),
+    (Comparison::LessOrEqual, "<="),
+    (Comparison::Greater, ">"),
+    (Comparison::Less, "<"),
+];
+
+/// Condition like `dmarc-failures>100` that makes the single update cycle fail when met
+#[derive(Clone, Debug)]
+pub struct FailCondition {
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub threshold: f64,
+}
+
+impl FailCondition {
+    /// Current value of the metric, nothing if there is no value like the compliance without messages
+    fn value(&self, state: &AppState) -> Option<f64> {
+        let summary = &state.summary;
+        let filter = RecordFilter {
+            since: summary.since,
+            until: summary.until,
+            ..Default::default()
+        };
+        let value = match self.metric {
+            Metric::ParseErrors => state.xml_errors.len() as f64,
+            Metric::Reports => summary.reports as f64,
+            Metric::DmarcFailures => filter
+                .records(&state.reports)
+                .filter(|(_, record)| !record.is_dmarc_pass())
+                .map(|(_, record)| record.row.count)
+                .sum::<usize>() as f64,
+            Metric::Unexpected => summary.unexpected as f64,
+            Metric::Compliance => summary
+                .compliance
+                .iter()
+                .map(|score| score.compliance)
+                .min_by(f64::total_cmp)?,
+        };
+        Some(value)
+    }
+
+    fn met_by(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Greater => value > self.threshold,
+            Comparison::GreaterOrEqual => value >= self.threshold,
+            Comparison::Less => value < self.threshold,
+            Comparison::LessOrEqual => value <= self.threshold,
+        }
+    }
+}
+
+impl FromStr for FailCondition {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid condition '{value}', expected e.g. dmarc-failures>100 or compliance<95"
+            )
+        };
+        let (comparison, (name, threshold)) = COMPARISONS
+            .iter()
+            .find_map(|(comparison, op)| Some((*comparison, value.split_once(op)?)))
+            .ok_or_else(invalid)?;
+        let metric = METRICS
+            .iter()
+            .find(|(_, n)| n.eq_ignore_ascii_case(name.trim()))
+            .map(|(metric, _)| *metric)
+            .ok_or_else(|| {
+                let names: Vec<&str> = METRICS.iter().map(|(_, n)| *n).collect();
+                format!(
+                    "Unknown metric '{}', expected one of {}",
+                    name.trim(),
+                    names.join(", ")
+                )
+            })?;
+        let threshold = threshold.trim().parse().map_err(|_| invalid())?;
+        Ok(Self {
+            metric,
+            comparison,
+            threshold,
+        })
+    }
+}
+
+impl Display for FailCondition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = METRICS
+            .iter()
+            .find(|(m, _)| *m == self.metric)
+            .map_or("", |(_, n)| n);
+        let op = COMPARISONS
+            .iter()
+            .find(|(c, _)| *c == self.comparison)
+            .map_or("", |(_, op)| op);
+        write!(f, "{name}{op}{}", self.threshold)
+    }
+}
+
+/// Conditions met by the state, each with the current value
+pub fn failed_conditions(conditions: &[FailCondition], state: &AppState) -> Vec<String> {
+    conditions
+        .iter()
+        .filter_map(|condition| {
+            let value = condition.value(state)?;
+            condition
+                .met_by(value)
+                .then(|| format!("{condition} (current value {value})"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn fail_conditions() {
+        let condition: FailCondition = "dmarc-failures>=1".parse().unwrap();
+        assert_eq!(condition.metric, Metric::DmarcFailures);
+        assert_eq!(condition.comparison, Comparison::GreaterOrEqual);
+        assert_eq!(condition.to_string(), "dmarc-failures>=1");
+        assert!("failures>1".parse::<FailCondition>().is_err());
+        assert!("compliance=95".parse::<FailCondition>().is_err());
+        assert!("compliance<abc".parse::<FailCondition>().is_err());
+
+        let mut state = AppState::default();
+        for name in ["acme", "mailru"] {
+            let xml = fs::read(format!("testdata/dmarc-reports/{name}.xml")).unwrap();
+            Arc::make_mut(&mut state.reports).push(parse_xml_file(&xml).unwrap());
+        }
+        state.refresh_summary(1);
+        let conditions: Vec<FailCondition> = ["parse-errors>0", "dmarc-failures>=1", "reports<1"]
+            .iter()
+            .map(|c| c.parse().unwrap())
+            .collect();
+        let failed = failed_conditions(&conditions, &state);
+        assert_eq!(failed, vec!["dmarc-failures>=1 (current value 1)"]);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::notifications::{Alert, AlertKind};
+use crate::timeseries::DAY;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Minimum time between alerts of a kind about the same domain, like `failure_alert=6h`.
+/// Without kind the rule applies to all alerts except digests.
+#[derive(Clone, Debug)]
+pub struct ThrottleRule {
+    pub kind: Option<AlertKind>,
+
+    /// Window in seconds
+    pub window: u64,
+}
+
+impl FromStr for ThrottleRule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_lowercase();
+        let (kind, window) = match value.split_once('=') {
+            Some((kind, window)) => {
+                let kind = AlertKind::ALL
+                    .into_iter()
+                    .find(|k| k.name() == kind.trim())
+                    .ok_or_else(|| {
+                        let names: Vec<&str> = AlertKind::ALL.iter().map(|k| k.name()).collect();
+                        format!(
+                            "Unknown alert kind '{}', expected one of {}",
+                            kind.trim(),
+                            names.join(", ")
+                        )
+                    })?;
+                (Some(kind), window.trim())
+            }
+            None => (None, value.as_str()),
+        };
+        let invalid = || format!("Invalid throttle window '{window}', expected e.g. 6h or 1d");
+        let (count, unit) = window.split_at(window.len().saturating_sub(1));
+        let count: u64 = count.parse().map_err(|_| invalid())?;
+        let window = match unit {
+            _ if count == 0 => return Err(invalid()),
+            "h" => count * 60 * 60,
+            "d" => count * DAY,
+            _ => return Err(invalid()),
+        };
+        Ok(Self { kind, window })
+    }
+}
+
+impl Display for ThrottleRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(kind) = self.kind {
+            write!(f, "{}=", kind.name())?;
+        }
+        if self.window.is_multiple_of(DAY) {
+            write!(f, "{}d", self.window / DAY)
+        } else {
+            write!(f, "{}h", self.window / (60 * 60))
+        }
+    }
+}
+
+/// Field with the list of items of an alert, nothing for alerts that are never throttled
+fn items_field(kind: AlertKind) -> Option<&'static str> {
+    match kind {
+        AlertKind::FailureAlert => Some("records"),
+        AlertKind::ParseErrors => Some("errors"),
+        AlertKind::NewSources => Some("sources"),
+        AlertKind::PolicyChange => Some("changes"),
+        AlertKind::ComplianceDrop => Some("domains"),
+        AlertKind::ReporterGap => Some("gaps"),
+        AlertKind::TaskPanic => Some("panics"),
+        AlertKind::Digest => None,
+    }
+}
+
+/// Items are throttled per domain, items without domain like parse errors only if identical
+fn item_key(item: &Value) -> String {
+    match item.get("domain").and_then(Value::as_str) {
+        Some(domain) => domain.to_lowercase(),
+        None => item.to_string(),
+    }
+}
+
+/// Removes items from alerts that were already sent within the window of their rule
+pub struct Throttle {
+    rules: Vec<ThrottleRule>,
+
+    /// Last time an item of a kind was sent as Unix timestamp
+    sent: Mutex<HashMap<(AlertKind, String), u64>>,
+}
+
+impl Throttle {
+    pub fn new(rules: &[ThrottleRule]) -> Self {
+        Self {
+            rules: rules.to_vec(),
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Window of the kind, rules for a specific kind take precedence over rules for all kinds
+    fn window(&self, kind: AlertKind) -> Option<u64> {
+        let rule = self.rules.iter().find(|r| r.kind == Some(kind));
+        rule.or_else(|| self.rules.iter().find(|r| r.kind.is_none()))
+            .map(|r| r.window)
+    }
+
+    /// The alert with all items that were not sent within the window, nothing if no item is left.
+    /// The remaining items count as sent at the given time.
+    pub fn apply<'a>(&self, alert: &'a Alert, now: u64) -> Option<Cow<'a, Alert>> {
+        let (Some(field), Some(window)) = (items_field(alert.kind), self.window(alert.kind)) else {
+            return Some(Cow::Borrowed(alert));
+        };
+        let Some(items) = alert.data.get(field).and_then(Value::as_array) else {
+            return Some(Cow::Borrowed(alert));
+        };
+        let mut sent = self.sent.lock().expect("Failed to lock sent alerts");
+        sent.retain(|(kind, _), time| *kind != alert.kind || now < *time + window);
+        let remaining: Vec<Value> = items
+            .iter()
+            .filter(|item| !sent.contains_key(&(alert.kind, item_key(item))))
+            .cloned()
+            .collect();
+        for item in &remaining {
+            sent.insert((alert.kind, item_key(item)), now);
+        }
+        if remaining.is_empty() {
+            return None;
+        }
+        if remaining.len() == items.len() {
+            return Some(Cow::Borrowed(alert));
+        }
+
+        let mut data = alert.data.clone();
+        if data.get("total_count").is_some() {
+            let total: u64 = remaining
+                .iter()
+                .filter_map(|item| item.get("count").and_then(Value::as_u64))
+                .sum();
+            data["total_count"] = total.into();
+        }
+        data[field] = Value::Array(remaining);
+        Some(Cow::Owned(Alert {
+            kind: alert.kind,
+            data,
+            context: alert.context.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_xml_file;
+    use std::fs;
+
+    #[test]
+    fn throttle_per_domain() {
+        let rule: ThrottleRule = "failure_alert=6h".parse().unwrap();
+        assert_eq!(rule.to_string(), "failure_alert=6h");
+        assert!("digests=6h".parse::<ThrottleRule>().is_err());
+        assert!("0d".parse::<ThrottleRule>().is_err());
+        let throttle = Throttle::new(&[rule]);
+
+        let xml = fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let report = parse_xml_file(&xml).unwrap();
+        let alert = Alert::failures(std::slice::from_ref(&report), 1).unwrap();
+        assert!(throttle.apply(&alert, 1000).is_some());
+        assert!(throttle.apply(&alert, 2000).is_none());
+
+        // Only the new domain is sent within the window
+        let mut other = report.clone();
+        other.policy_published.domain = String::from("example.org");
+        let alert = Alert::failures(&[report.clone(), other], 1).unwrap();
+        let throttled = throttle.apply(&alert, 3000).unwrap();
+        assert_eq!(throttled.data["records"][0]["domain"], "example.org");
+        assert_eq!(throttled.data["total_count"], 2);
+        assert_eq!(throttled.data["records"].as_array().unwrap().len(), 1);
+
+        // After the window the domain is sent again
+        let alert = Alert::failures(&[report], 1).unwrap();
+        assert!(throttle.apply(&alert, 1000 + 6 * 60 * 60).is_some());
+    }
+}
@@ -0,0 +1,422 @@
+use crate::filter::RecordFilter;
+use crate::report::{DispositionType, Report};
+use chrono::DateTime;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+pub const DAY: u64 = 24 * 60 * 60;
+const WEEK: u64 = 7 * DAY;
+
+/// Upper limit for the number of buckets of a time series, more than a century of days
+const MAX_BUCKETS: usize = 40_000;
+
+/// Size of the time series buckets
+#[derive(Deserialize, ValueEnum, Clone, Copy, Default, PartialEq, Debug, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    #[default]
+    Daily,
+    Weekly,
+}
+
+impl Interval {
+    /// Unix timestamp of the day or week (starting Monday) in the timezone containing the timestamp
+    pub fn bucket_start(&self, timestamp: u64, timezone: Timezone) -> u64 {
+        let local = timestamp as i64 + timezone.offset;
+        let day = local.div_euclid(DAY as i64);
+        let start = match self {
+            Self::Daily => day,
+            // The Unix epoch was a Thursday
+            Self::Weekly => day - (day + 3).rem_euclid(7),
+        };
+        (start * DAY as i64 - timezone.offset).max(0) as u64
+    }
+
+    pub fn duration(&self) -> u64 {
+        match self {
+            Self::Daily => DAY,
+            Self::Weekly => WEEK,
+        }
+    }
+}
+
+/// Fixed offset from UTC used to align days and weeks with local reporting days.
+/// Parsed from `UTC` or offsets like `+02:00`, `-0530` or `+1`.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Timezone {
+    /// Offset in seconds east of UTC
+    offset: i64,
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let offset = value
+            .strip_prefix("UTC")
+            .or_else(|| value.strip_prefix("utc"))
+            .unwrap_or(value);
+        if offset.is_empty() || offset == "Z" {
+            return Ok(Self::default());
+        }
+        let invalid = || format!("Invalid timezone offset '{value}', expected e.g. UTC or +02:00");
+        let (sign, offset) = match offset.split_at(1) {
+            ("+", offset) => (1, offset),
+            ("-", offset) => (-1, offset),
+            _ => return Err(invalid()),
+        };
+        let (hours, minutes) = match offset.split_once(':') {
+            Some((hours, minutes)) => (hours, minutes),
+            None if offset.len() == 4 => offset.split_at(2),
+            None => (offset, "0"),
+        };
+        let (Ok(hours), Ok(minutes)) = (hours.parse::<i64>(), minutes.parse::<i64>()) else {
+            return Err(invalid());
+        };
+        if hours > 14 || minutes >= 60 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            offset: sign * (hours * 60 * 60 + minutes * 60),
+        })
+    }
+}
+
+impl Timezone {
+    /// Local date of the timestamp in ISO format
+    pub fn date(&self, timestamp: u64) -> String {
+        DateTime::from_timestamp(timestamp as i64 + self.offset, 0)
+            .unwrap_or_default()
+            .format("%Y-%m-%d")
+            .to_string()
+    }
+}
+
+impl Display for Timezone {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.offset == 0 {
+            return write!(f, "UTC");
+        }
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let minutes = self.offset.abs() / 60;
+        write!(f, "{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+    }
+}
+
+/// Message counts of all records in reports overlapping with a time bucket
+#[derive(Serialize, Default, Clone, PartialEq, Debug, ToSchema)]
+pub struct Bucket {
+    /// Unix timestamp of the start of the bucket
+    pub start: u64,
+    pub messages: usize,
+
+    /// Messages with passed DKIM or SPF policy evaluation
+    pub passed: usize,
+
+    /// Messages where DKIM and SPF policy evaluation did not pass
+    pub failed: usize,
+
+    pub disposition_none: usize,
+    pub disposition_quarantine: usize,
+    pub disposition_reject: usize,
+}
+
+/// Bucket all matching records by the report date range.
+/// Messages of reports spanning multiple buckets are distributed
+/// proportionally to the overlap of the date range with each bucket.
+/// Buckets without records between the first and last bucket are included
+/// with zero counts to allow rendering of continuous charts.
+pub fn time_series(
+    reports: &[Report],
+    filter: &RecordFilter,
+    interval: Interval,
+    timezone: Timezone,
+) -> Vec<Bucket> {
+    let mut buckets: BTreeMap<u64, Bucket> = BTreeMap::new();
+    for (report, record) in filter.records(reports) {
+        let range = &report.report_metadata.date_range;
+        let shares = split_range(range.begin, range.end, record.row.count, interval, timezone);
+        for (start, count) in shares {
+            let bucket = buckets.entry(start).or_insert_with(|| Bucket {
+                start,
+                ..Default::default()
+            });
+            bucket.messages += count;
+            if record.is_dmarc_pass() {
+                bucket.passed += count;
+            } else {
+                bucket.failed += count;
+            }
+            match record.row.policy_evaluated.disposition {
+                DispositionType::None => bucket.disposition_none += count,
+                DispositionType::Quarantine => bucket.disposition_quarantine += count,
+                DispositionType::Reject => bucket.disposition_reject += count,
+                DispositionType::Unknown(..) => {}
+            }
+        }
+    }
+
+    let (Some(first), Some(last)) = (
+        buckets.keys().next().copied(),
+        buckets.keys().last().copied(),
+    ) else {
+        return Vec::new();
+    };
+    let mut series = Vec::new();
+    let mut start = first;
+    while start <= last && series.len() < MAX_BUCKETS {
+        series.push(buckets.remove(&start).unwrap_or_else(|| Bucket {
+            start,
+            ..Default::default()
+        }));
+        let Some(next) = start.checked_add(interval.duration()) else {
+            break;
+        };
+        start = next;
+    }
+    series
+}
+
+/// Messages of a source IP or network within a time bucket
+#[derive(Serialize, Clone, PartialEq, Debug, ToSchema)]
+pub struct SourceActivity {
+    pub source: String,
+    pub messages: usize,
+    pub passed: usize,
+    pub failed: usize,
+
+    /// First bucket with messages from the source
+    pub new: bool,
+}
+
+/// Distinct sources sending within a time bucket
+#[derive(Serialize, Default, Clone, PartialEq, Debug, ToSchema)]
+pub struct SourceBucket {
+    /// Unix timestamp of the start of the bucket
+    pub start: u64,
+    pub sources: usize,
+
+    /// Sources with only passing messages
+    pub passing: usize,
+
+    /// Sources with only failing messages
+    pub failing: usize,
+
+    /// Sources with passing and failing messages
+    pub mixed: usize,
+
+    /// Sources without messages in any earlier bucket
+    pub new: usize,
+
+    /// Activity of each source, most messages first
+    pub activity: Vec<SourceActivity>,
+}
+
+/// Distinct sources per bucket for visualizing the churn of sending services.
+/// Records are split across buckets like for the message time series and a source
+/// only counts for the buckets that got a share of its messages.
+/// Buckets without records between the first and last bucket are included.
+pub fn source_timeline(
+    reports: &[Report],
+    filter: &RecordFilter,
+    interval: Interval,
+    timezone: Timezone,
+) -> Vec<SourceBucket> {
+    let mut buckets: BTreeMap<u64, BTreeMap<String, SourceActivity>> = BTreeMap::new();
+    for (report, record) in filter.records(reports) {
+        let range = &report.report_metadata.date_range;
+        let source = filter.group_source(record.row.source_ip);
+        let pass = record.is_dmarc_pass();
+        for (start, count) in
+            split_range(range.begin, range.end, record.row.count, interval, timezone)
+        {
+            let activity = buckets
+                .entry(start)
+                .or_default()
+                .entry(source.clone())
+                .or_insert_with(|| SourceActivity {
+                    source: source.clone(),
+                    messages: 0,
+                    passed: 0,
+                    failed: 0,
+                    new: false,
+                });
+            activity.messages += count;
+            if pass {
+                activity.passed += count;
+            } else {
+                activity.failed += count;
+            }
+        }
+    }
+
+    let (Some(first), Some(last)) = (
+        buckets.keys().next().copied(),
+        buckets.keys().last().copied(),
+    ) else {
+        return Vec::new();
+    };
+    let mut seen = HashSet::new();
+    let mut timeline = Vec::new();
+    let mut start = first;
+    while start <= last {
+        let mut bucket = SourceBucket {
+            start,
+            ..Default::default()
+        };
+        for (_, mut activity) in buckets.remove(&start).unwrap_or_default() {
+            activity.new = seen.insert(activity.source.clone());
+            bucket.sources += 1;
+            bucket.new += usize::from(activity.new);
+            match (activity.passed > 0, activity.failed > 0) {
+                (true, false) => bucket.passing += 1,
+                (false, true) => bucket.failing += 1,
+                _ => bucket.mixed += 1,
+            }
+            bucket.activity.push(activity);
+        }
+        bucket.activity.sort_by_key(|a| Reverse(a.messages));
+        timeline.push(bucket);
+        start += interval.duration();
+    }
+    timeline
+}
+
+/// Distribute the count over all buckets overlapping with the time range.
+/// Shares are rounded based on the cumulative overlap to keep the total count.
+/// Empty or inverted ranges are attributed completely to the bucket of the begin.
+fn split_range(
+    begin: u64,
+    end: u64,
+    count: usize,
+    interval: Interval,
+    timezone: Timezone,
+) -> Vec<(u64, usize)> {
+    let first = interval.bucket_start(begin, timezone);
+    if end <= begin {
+        return vec![(first, count)];
+    }
+    let total = u128::from(end - begin);
+    let mut shares = Vec::new();
+    let mut start = first;
+    let mut assigned = 0;
+    for _ in 0..MAX_BUCKETS {
+        if start >= end {
+            break;
+        }
+        let bucket_end = start.saturating_add(interval.duration());
+        let covered = u128::from(bucket_end.min(end).saturating_sub(begin));
+        let cumulative = (count as u128 * covered / total) as usize;
+        if cumulative > assigned {
+            shares.push((start, cumulative - assigned));
+            assigned = cumulative;
+        }
+        start = bucket_end;
+    }
+    shares
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_starts() {
+        // Wednesday, 2024-07-17 13:00:00 UTC
+        let timestamp = 1721221200;
+        let utc = Timezone::default();
+        assert_eq!(Interval::Daily.bucket_start(timestamp, utc), 1721174400);
+        // Monday, 2024-07-15 00:00:00 UTC
+        assert_eq!(Interval::Weekly.bucket_start(timestamp, utc), 1721001600);
+        assert_eq!(Interval::Weekly.bucket_start(1721001600, utc), 1721001600);
+
+        // Wednesday, 2024-07-17 00:00:00 in UTC-12 is 12:00:00 UTC
+        let timezone: Timezone = "-12:00".parse().unwrap();
+        assert_eq!(
+            Interval::Daily.bucket_start(timestamp, timezone),
+            1721217600
+        );
+        // Thursday, 2024-07-18 00:00:00 in UTC+12 is Wednesday 12:00:00 UTC
+        let timezone: Timezone = "+12".parse().unwrap();
+        assert_eq!(
+            Interval::Daily.bucket_start(timestamp, timezone),
+            1721217600
+        );
+        // Monday, 2024-07-15 00:00:00 in UTC+12
+        assert_eq!(
+            Interval::Weekly.bucket_start(timestamp, timezone),
+            1721001600 - 12 * 60 * 60
+        );
+    }
+
+    #[test]
+    fn split_multi_day_ranges() {
+        let utc = Timezone::default();
+        // Single day
+        assert_eq!(
+            split_range(DAY, 2 * DAY, 10, Interval::Daily, utc),
+            vec![(DAY, 10)]
+        );
+        // From noon to noon two days later
+        assert_eq!(
+            split_range(DAY / 2, 5 * DAY / 2, 10, Interval::Daily, utc),
+            vec![(0, 2), (DAY, 5), (2 * DAY, 3)]
+        );
+        // Noon to noon in the same week
+        assert_eq!(
+            split_range(4 * DAY + DAY / 2, 6 * DAY, 3, Interval::Weekly, utc),
+            vec![(Interval::Weekly.bucket_start(4 * DAY, utc), 3)]
+        );
+        assert_eq!(
+            split_range(DAY, DAY, 7, Interval::Daily, utc),
+            vec![(DAY, 7)]
+        );
+        // Implausible ranges stop after the maximum number of buckets without overflowing
+        let shares = split_range(0, u64::MAX, usize::MAX, Interval::Daily, utc);
+        assert!(shares.len() <= MAX_BUCKETS);
+    }
+
+    #[test]
+    fn distinct_sources() {
+        let xml = std::fs::read("testdata/dmarc-reports/acme.xml").unwrap();
+        let first = crate::parser::parse_xml_file(&xml).unwrap();
+        let mut same = first.clone();
+        same.report_metadata.date_range.begin += DAY;
+        same.report_metadata.date_range.end += DAY;
+        let mut other = same.clone();
+        other.record[0].row.source_ip = "192.0.2.1".parse().unwrap();
+        let timeline = source_timeline(
+            &[first, same, other],
+            &RecordFilter::default(),
+            Interval::Daily,
+            Timezone::default(),
+        );
+        assert_eq!(timeline.len(), 2);
+        assert_eq!((timeline[0].sources, timeline[0].new), (1, 1));
+        assert_eq!((timeline[1].sources, timeline[1].new), (2, 1));
+        assert_eq!(timeline[1].passing, 2);
+        let new: Vec<&str> = timeline[1]
+            .activity
+            .iter()
+            .filter(|a| a.new)
+            .map(|a| a.source.as_str())
+            .collect();
+        assert_eq!(new, vec!["192.0.2.1"]);
+    }
+
+    #[test]
+    fn parse_timezones() {
+        assert_eq!("UTC".parse::<Timezone>().unwrap(), Timezone::default());
+        assert_eq!("+02:00".parse::<Timezone>().unwrap().offset, 7200);
+        assert_eq!("-0530".parse::<Timezone>().unwrap().offset, -19800);
+        assert_eq!("UTC+1".parse::<Timezone>().unwrap().offset, 3600);
+        assert_eq!("-05:30".parse::<Timezone>().unwrap().to_string(), "-05:30");
+        assert!("Europe/Berlin".parse::<Timezone>().is_err());
+    }
+}
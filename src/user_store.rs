@@ -0,0 +1,312 @@
+use crate::password::hash_password;
+use crate::status::unix_time;
+use crate::users::Role;
+use anyhow::{anyhow, Context, Result};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::sync::Mutex;
+use subtle::ConstantTimeEq;
+use utoipa::ToSchema;
+
+/// Number of random bytes of generated API tokens
+const TOKEN_BYTES: usize = 32;
+
+/// User created with the admin API
+#[derive(Serialize, Deserialize, Clone)]
+struct StoredUser {
+    name: String,
+    role: Role,
+
+    /// Argon2 hash of the password
+    password: String,
+
+    #[serde(default)]
+    disabled: bool,
+}
+
+/// API token created with the admin API, only the SHA-256 hash of the token is stored
+#[derive(Serialize, Deserialize, Clone)]
+struct StoredToken {
+    name: String,
+    hash: String,
+
+    /// Creation or last rotation as Unix timestamp
+    created: u64,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct StoreData {
+    #[serde(default)]
+    users: Vec<StoredUser>,
+    #[serde(default)]
+    tokens: Vec<StoredToken>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserInfo {
+    pub name: String,
+    pub role: Role,
+    pub disabled: bool,
+
+    /// Created with the admin API, users from the configuration cannot be changed at runtime
+    pub managed: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TokenInfo {
+    pub name: String,
+    pub created: u64,
+}
+
+/// Changes of a managed user, fields that are not set stay unchanged
+#[derive(Deserialize, ToSchema)]
+pub struct UserUpdate {
+    pub role: Option<Role>,
+    pub password: Option<String>,
+    pub disabled: Option<bool>,
+}
+
+/// Users and API tokens managed at runtime, persisted in a JSON file after every change.
+/// They are kept apart from the `StateStore`, because changes must be written immediately,
+/// while the state is only saved by the primary instance after update cycles,
+/// and because the password hashes should not end up in exports and backups of the state.
+/// Instances sharing their users point to the same file on a shared file system.
+pub struct UserStore {
+    path: Option<String>,
+    data: Mutex<StoreData>,
+}
+
+impl UserStore {
+    /// Loads the store from the file, which is created with the first change.
+    /// Without file the store is disabled and stays empty.
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let data = match path.map(fs::read) {
+            Some(Ok(json)) => serde_json::from_slice(&json)
+                .with_context(|| format!("Failed to parse user store {}", path.unwrap_or("")))?,
+            Some(Err(err)) if err.kind() != ErrorKind::NotFound => {
+                return Err(err).context("Failed to read user store")
+            }
+            _ => StoreData::default(),
+        };
+        Ok(Self {
+            path: path.map(str::to_owned),
+            data: Mutex::new(data),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn count(&self) -> usize {
+        self.lock().users.len()
+    }
+
+    /// Password hash and role of an enabled user
+    pub fn credentials(&self, name: &str) -> Option<(String, Role)> {
+        self.lock()
+            .users
+            .iter()
+            .find(|u| u.name == name && !u.disabled)
+            .map(|u| (u.password.clone(), u.role))
+    }
+
+    /// Role of an enabled user
+    pub fn role(&self, name: &str) -> Option<Role> {
+        self.lock()
+            .users
+            .iter()
+            .find(|u| u.name == name && !u.disabled)
+            .map(|u| u.role)
+    }
+
+    /// Checks if the user exists but was disabled
+    pub fn disabled(&self, name: &str) -> bool {
+        self.lock()
+            .users
+            .iter()
+            .any(|u| u.name == name && u.disabled)
+    }
+
+    /// Check the token against the hashes of all tokens in constant time
+    pub fn valid_token(&self, token: &str) -> bool {
+        let hash = token_hash(token);
+        self.lock().tokens.iter().fold(false, |valid, t| {
+            valid | bool::from(t.hash.as_bytes().ct_eq(hash.as_bytes()))
+        })
+    }
+
+    pub fn users(&self) -> Vec<UserInfo> {
+        self.lock()
+            .users
+            .iter()
+            .map(|u| UserInfo {
+                name: u.name.clone(),
+                role: u.role,
+                disabled: u.disabled,
+                managed: true,
+            })
+            .collect()
+    }
+
+    pub fn tokens(&self) -> Vec<TokenInfo> {
+        self.lock()
+            .tokens
+            .iter()
+            .map(|t| TokenInfo {
+                name: t.name.clone(),
+                created: t.created,
+            })
+            .collect()
+    }
+
+    /// Returns false if a user with the name already exists
+    pub fn create_user(&self, name: &str, role: Role, password: &str) -> Result<bool> {
+        let password = hash_password(password)?;
+        self.update(|data| {
+            if data.users.iter().any(|u| u.name == name) {
+                return false;
+            }
+            data.users.push(StoredUser {
+                name: name.to_owned(),
+                role,
+                password,
+                disabled: false,
+            });
+            true
+        })
+    }
+
+    /// Returns false if there is no user with the name
+    pub fn update_user(&self, name: &str, update: UserUpdate) -> Result<bool> {
+        let password = update.password.as_deref().map(hash_password).transpose()?;
+        self.update(|data| {
+            let Some(user) = data.users.iter_mut().find(|u| u.name == name) else {
+                return false;
+            };
+            if let Some(role) = update.role {
+                user.role = role;
+            }
+            if let Some(password) = password {
+                user.password = password;
+            }
+            if let Some(disabled) = update.disabled {
+                user.disabled = disabled;
+            }
+            true
+        })
+    }
+
+    /// Returns false if there is no user with the name
+    pub fn delete_user(&self, name: &str) -> Result<bool> {
+        self.update(|data| {
+            let count = data.users.len();
+            data.users.retain(|u| u.name != name);
+            data.users.len() < count
+        })
+    }
+
+    /// Creates a new random token with the name, replacing an existing token with the same name.
+    /// The token is only returned here, the store keeps its hash.
+    pub fn rotate_token(&self, name: &str) -> Result<String> {
+        let mut bytes = [0; TOKEN_BYTES];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| anyhow!("Failed to generate token"))?;
+        let token = hex::encode(bytes);
+        let stored = StoredToken {
+            name: name.to_owned(),
+            hash: token_hash(&token),
+            created: unix_time(),
+        };
+        self.update(|data| {
+            data.tokens.retain(|t| t.name != name);
+            data.tokens.push(stored);
+        })?;
+        Ok(token)
+    }
+
+    /// Returns false if there is no token with the name
+    pub fn delete_token(&self, name: &str) -> Result<bool> {
+        self.update(|data| {
+            let count = data.tokens.len();
+            data.tokens.retain(|t| t.name != name);
+            data.tokens.len() < count
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StoreData> {
+        self.data.lock().expect("Failed to lock user store")
+    }
+
+    /// Applies the change to a copy and keeps it only if the copy could be saved
+    fn update<T>(&self, change: impl FnOnce(&mut StoreData) -> T) -> Result<T> {
+        let path = self.path.as_deref().context("User store is disabled")?;
+        let mut data = self.lock();
+        let mut changed = data.clone();
+        let result = change(&mut changed);
+        let json = serde_json::to_vec_pretty(&changed).context("Failed to serialize users")?;
+        let tmp_path = format!("{path}.tmp");
+        fs::write(&tmp_path, json).context("Failed to write temporary user store")?;
+        fs::rename(&tmp_path, path).context("Failed to replace user store")?;
+        *data = changed;
+        Ok(result)
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    hex::encode(digest(&SHA256, token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::password::verify_password;
+    use std::env;
+
+    fn authenticate(store: &UserStore, name: &str, password: &str) -> Option<Role> {
+        let (hash, role) = store.credentials(name)?;
+        verify_password(&hash, password).then_some(role)
+    }
+
+    #[test]
+    fn manage_users_and_tokens() {
+        let path = env::temp_dir().join(format!("dmarc-user-store-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let store = UserStore::load(Some(path)).unwrap();
+        assert!(store
+            .create_user("alice", Role::ReadOnly, "secret")
+            .unwrap());
+        assert!(!store.create_user("alice", Role::Admin, "other").unwrap());
+        assert_eq!(
+            authenticate(&store, "alice", "secret"),
+            Some(Role::ReadOnly)
+        );
+        assert_eq!(authenticate(&store, "alice", "other"), None);
+        let token = store.rotate_token("ci").unwrap();
+        assert!(store.valid_token(&token));
+
+        let store = UserStore::load(Some(path)).unwrap();
+        let update = UserUpdate {
+            role: Some(Role::Admin),
+            password: None,
+            disabled: Some(true),
+        };
+        assert!(store.update_user("alice", update).unwrap());
+        assert_eq!(authenticate(&store, "alice", "secret"), None);
+        assert!(store.disabled("alice"));
+        assert!(!store.disabled("bob"));
+        assert!(store.valid_token(&token));
+        let rotated = store.rotate_token("ci").unwrap();
+        assert!(!store.valid_token(&token));
+        assert!(store.valid_token(&rotated));
+        assert!(store.delete_user("alice").unwrap());
+        assert!(!store.delete_user("alice").unwrap());
+        fs::remove_file(path).unwrap();
+
+        assert!(UserStore::load(None).unwrap().rotate_token("ci").is_err());
+    }
+}
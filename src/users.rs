@@ -0,0 +1,301 @@
+use crate::config::Configuration;
+use crate::filter::TenantDomains;
+use crate::password::{verify_password, VerifiedPasswords, UNKNOWN_USER_HASH};
+use crate::tenants::Tenants;
+use crate::user_store::{UserInfo, UserStore};
+use anyhow::{bail, Context, Result};
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::IpAddr;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::task::spawn_blocking;
+use utoipa::ToSchema;
+
+/// Permissions of an authenticated HTTP user
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Can view everything and modify data and settings
+    Admin,
+    /// Can only view reports and other data
+    #[serde(alias = "readonly", alias = "read")]
+    ReadOnly,
+}
+
+impl Role {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "admin" => Ok(Self::Admin),
+            "read-only" | "readonly" | "read" => Ok(Self::ReadOnly),
+            _ => bail!("Unknown role '{value}', expected 'admin' or 'read-only'"),
+        }
+    }
+}
+
+struct User {
+    name: String,
+    password: String,
+    role: Role,
+}
+
+/// All users and API tokens that can access the HTTP server
+pub struct Users {
+    users: Vec<User>,
+    api_tokens: Vec<String>,
+    proxy_header: Option<String>,
+    trusted_proxies: Vec<IpAddr>,
+    tenants: Tenants,
+    store: UserStore,
+    verified: VerifiedPasswords,
+}
+
+impl Users {
+    /// Collects the basic auth user from the configuration (as admin),
+    /// all users from the optional users file, the API tokens and the managed users.
+    pub fn from_config(config: &Configuration) -> Result<Self> {
+        let mut users = Vec::new();
+        if !config.http_server_password.is_empty() {
+            users.push(User {
+                name: config.http_server_user.clone(),
+                password: config.http_server_password.clone(),
+                role: Role::Admin,
+            });
+        }
+        if let Some(path) = &config.http_users_file {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read users file {path}"))?;
+            users.extend(
+                parse_users(&content).with_context(|| format!("Invalid users file {path}"))?,
+            );
+        }
+        let api_tokens = config
+            .http_api_tokens
+            .iter()
+            .filter(|t| !t.is_empty())
+            .cloned()
+            .collect();
+        let tenants = match &config.http_tenants_file {
+            Some(path) => Tenants::load(path)?,
+            None => Tenants::default(),
+        };
+        Ok(Self {
+            users,
+            api_tokens,
+            proxy_header: config.http_proxy_auth_header.clone(),
+            trusted_proxies: config.http_trusted_proxies.clone(),
+            tenants,
+            store: UserStore::load(config.http_user_store.as_deref())?,
+            verified: VerifiedPasswords::default(),
+        })
+    }
+
+    /// Authentication is disabled if there is not a single user with password,
+    /// no authenticating reverse proxy is configured and users cannot be added at runtime
+    pub fn auth_disabled(&self) -> bool {
+        self.users.is_empty() && self.proxy_header.is_none() && !self.store.enabled()
+    }
+
+    pub fn count(&self) -> usize {
+        self.users.len() + self.store.count()
+    }
+
+    /// Users and API tokens managed at runtime
+    pub fn store(&self) -> &UserStore {
+        &self.store
+    }
+
+    /// Checks if the user is configured in the environment or the users file
+    pub fn is_configured(&self, name: &str) -> bool {
+        self.users.iter().any(|u| u.name == name)
+    }
+
+    /// Configured users followed by the managed users
+    pub fn list(&self) -> Vec<UserInfo> {
+        self.users
+            .iter()
+            .map(|u| UserInfo {
+                name: u.name.clone(),
+                role: u.role,
+                disabled: false,
+                managed: false,
+            })
+            .chain(self.store.users())
+            .collect()
+    }
+
+    pub fn tenant_count(&self) -> usize {
+        self.tenants.len()
+    }
+
+    /// Domains the user is restricted to, nothing for operators assigned to no tenant
+    pub fn user_domains(&self, name: &str) -> Option<TenantDomains> {
+        self.tenants.user_domains(name)
+    }
+
+    /// Domains of the tenants with the API token, nothing if it is not a tenant token
+    pub fn token_domains(&self, token: &str) -> Option<TenantDomains> {
+        self.tenants.token_domains(token)
+    }
+
+    /// Returns the role of the user if the credentials are valid.
+    /// Hashes are verified on a blocking thread to keep them off the async runtime.
+    pub async fn authenticate(self: &Arc<Self>, name: &str, password: &str) -> Option<Role> {
+        let users = self.clone();
+        let (name, password) = (name.to_owned(), password.to_owned());
+        spawn_blocking(move || users.authenticate_blocking(&name, &password))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Same as `authenticate` for callers that cannot await.
+    /// Unknown users are verified against a dummy hash,
+    /// so the response time does not reveal which user names exist.
+    pub fn authenticate_blocking(&self, name: &str, password: &str) -> Option<Role> {
+        let credentials = match self.users.iter().find(|u| u.name == name) {
+            Some(user) => Some((user.password.clone(), user.role)),
+            None => self.store.credentials(name),
+        };
+        let Some((hash, role)) = credentials else {
+            // Takes as long as for a configured user, the result is ignored
+            let dummy = self
+                .users
+                .first()
+                .map_or_else(|| UNKNOWN_USER_HASH.as_str(), |u| u.password.as_str());
+            verify_password(dummy, password);
+            return None;
+        };
+        self.verified.verify(&hash, password).then_some(role)
+    }
+
+    /// Role of a configured or enabled managed user
+    pub fn role(&self, name: &str) -> Option<Role> {
+        self.users
+            .iter()
+            .find(|u| u.name == name)
+            .map(|u| u.role)
+            .or_else(|| self.store.role(name))
+    }
+
+    /// Returns the user name and role provided by the header of a trusted reverse proxy.
+    /// Users unknown to this server get the read-only role, disabled managed users are denied.
+    pub fn proxy_user<'a>(&self, peer: IpAddr, headers: &'a HeaderMap) -> Option<(&'a str, Role)> {
+        let header = self.proxy_header.as_deref()?;
+        if !self.trusted_proxies.contains(&peer.to_canonical()) {
+            return None;
+        }
+        let name = headers.get(header)?.to_str().ok()?.trim();
+        if name.is_empty() || self.store.disabled(name) {
+            return None;
+        }
+        let role = self
+            .users
+            .iter()
+            .find(|u| u.name == name)
+            .map(|u| u.role)
+            .or_else(|| self.store.role(name))
+            .unwrap_or(Role::ReadOnly);
+        Some((name, role))
+    }
+
+    /// Check bearer token against all configured and managed API tokens in constant time
+    pub fn valid_token(&self, token: &str) -> bool {
+        let configured = self.api_tokens.iter().fold(false, |valid, t| {
+            valid | bool::from(t.as_bytes().ct_eq(token.as_bytes()))
+        });
+        configured | self.store.valid_token(token)
+    }
+}
+
+/// Parses lines with the format `<name>:<role>:<password>`.
+/// Empty lines and lines starting with `#` are ignored.
+fn parse_users(content: &str) -> Result<Vec<User>> {
+    let mut users = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(3, ':');
+        let (Some(name), Some(role), Some(password)) = (parts.next(), parts.next(), parts.next())
+        else {
+            bail!(
+                "Line {} does not match the format <name>:<role>:<password>",
+                index + 1
+            );
+        };
+        if name.is_empty() || password.is_empty() {
+            bail!("Line {} has an empty name or password", index + 1);
+        }
+        let role =
+            Role::parse(role).with_context(|| format!("Invalid role in line {}", index + 1))?;
+        users.push(User {
+            name: name.to_owned(),
+            password: password.to_owned(),
+            role,
+        });
+    }
+    Ok(users)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_users_file() {
+        let content = "# comment\n\nalice:admin:secret\nbob:read-only:$2b$04$abc:def\n";
+        let users = parse_users(content).unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].name, "alice");
+        assert_eq!(users[0].role, Role::Admin);
+        assert_eq!(users[1].role, Role::ReadOnly);
+        assert_eq!(users[1].password, "$2b$04$abc:def");
+
+        assert!(parse_users("alice:owner:secret").is_err());
+        assert!(parse_users("alice:secret").is_err());
+    }
+
+    #[test]
+    fn deny_disabled_proxy_users() {
+        use crate::user_store::UserUpdate;
+        use clap::Parser;
+
+        let path = std::env::temp_dir().join(format!("dmarc-proxy-users-{}", std::process::id()));
+        let config = Configuration::parse_from([
+            "test",
+            "--demo",
+            "--http-server-password=password",
+            "--http-proxy-auth-header=x-remote-user",
+            "--http-trusted-proxies=127.0.0.1",
+            &format!("--http-user-store={}", path.display()),
+        ]);
+        let users = Users::from_config(&config).unwrap();
+        assert!(users
+            .store()
+            .create_user("alice", Role::Admin, "secret")
+            .unwrap());
+        let proxy: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-remote-user", "alice".parse().unwrap());
+        assert_eq!(
+            users.proxy_user(proxy, &headers),
+            Some(("alice", Role::Admin))
+        );
+        let update = UserUpdate {
+            role: None,
+            password: None,
+            disabled: Some(true),
+        };
+        assert!(users.store().update_user("alice", update).unwrap());
+        assert_eq!(users.proxy_user(proxy, &headers), None);
+        headers.insert("x-remote-user", "bob".parse().unwrap());
+        assert_eq!(
+            users.proxy_user(proxy, &headers),
+            Some(("bob", Role::ReadOnly))
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
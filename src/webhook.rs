@@ -0,0 +1,40 @@
+use crate::notifications::{Alert, NotificationChannel, Templates};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+
+/// Posts alerts as JSON to a configured URL, optionally rendered from a custom template
+pub struct WebhookSender {
+    client: Client,
+    url: String,
+}
+
+impl WebhookSender {
+    pub fn new(client: Client, url: &str) -> Self {
+        Self {
+            client,
+            url: url.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl NotificationChannel for WebhookSender {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn send(&self, templates: &Templates, alert: &Alert) -> Result<()> {
+        let request = match templates.render_payload(alert)? {
+            Some(payload) => self.client.post(&self.url).json(&payload),
+            None => self.client.post(&self.url).json(alert),
+        };
+        request
+            .send()
+            .await
+            .context("Failed to send webhook request")?
+            .error_for_status()
+            .context("Webhook request failed")?;
+        Ok(())
+    }
+}
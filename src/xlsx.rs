@@ -0,0 +1,103 @@
+use crate::domains::DomainStats;
+use anyhow::{Context, Result};
+use rust_xlsxwriter::{Format, Workbook, Worksheet};
+use std::collections::HashSet;
+
+/// Number of sources listed per domain sheet
+const TOP_SOURCES: usize = 20;
+
+/// Excel limits the length of sheet names
+const MAX_SHEET_NAME: usize = 31;
+
+/// Creates an Excel workbook with one sheet for each domain
+pub fn domains_workbook(domains: &[DomainStats]) -> Result<Vec<u8>> {
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+    let mut names = HashSet::new();
+    for stats in domains {
+        let name = sheet_name(&stats.domain, &names);
+        names.insert(name.to_lowercase());
+        let sheet = workbook.add_worksheet();
+        sheet
+            .set_name(&name)
+            .with_context(|| format!("Failed to set sheet name {name}"))?;
+        write_domain_sheet(sheet, stats, &bold)
+            .with_context(|| format!("Failed to write sheet for domain {}", stats.domain))?;
+    }
+    workbook
+        .save_to_buffer()
+        .context("Failed to create XLSX file")
+}
+
+fn write_domain_sheet(sheet: &mut Worksheet, stats: &DomainStats, bold: &Format) -> Result<()> {
+    sheet.set_column_width(0, 40)?;
+    sheet.set_column_width(1, 15)?;
+    sheet.set_column_width(2, 15)?;
+    sheet.set_column_width(3, 15)?;
+
+    sheet.write_string_with_format(0, 0, "Domain", bold)?;
+    sheet.write_string(0, 1, &stats.domain)?;
+    let totals = [
+        ("Messages", stats.messages),
+        ("DMARC Passed", stats.passed),
+        ("DMARC Failed", stats.failed),
+        ("DKIM Failed", stats.dkim_failed),
+        ("SPF Failed", stats.spf_failed),
+    ];
+    for (row, (label, value)) in (1..).zip(totals) {
+        sheet.write_string_with_format(row, 0, label, bold)?;
+        sheet.write_number(row, 1, value as f64)?;
+    }
+    sheet.write_string_with_format(6, 0, "Pass Rate (%)", bold)?;
+    sheet.write_number(6, 1, (stats.pass_rate() * 100.0).round() / 100.0)?;
+
+    sheet.write_string_with_format(8, 0, "Disposition", bold)?;
+    sheet.write_string_with_format(8, 1, "Messages", bold)?;
+    let dispositions = [
+        ("none", stats.disposition_none),
+        ("quarantine", stats.disposition_quarantine),
+        ("reject", stats.disposition_reject),
+    ];
+    for (row, (label, value)) in (9..).zip(dispositions) {
+        sheet.write_string(row, 0, label)?;
+        sheet.write_number(row, 1, value as f64)?;
+    }
+
+    sheet.write_string_with_format(13, 0, "Top Sources", bold)?;
+    sheet.write_string_with_format(13, 1, "Messages", bold)?;
+    sheet.write_string_with_format(13, 2, "Passed", bold)?;
+    sheet.write_string_with_format(13, 3, "Failed", bold)?;
+    for (row, (ip, source)) in (14..).zip(stats.top_sources(TOP_SOURCES)) {
+        sheet.write_string(row, 0, ip.to_string())?;
+        sheet.write_number(row, 1, source.messages as f64)?;
+        sheet.write_number(row, 2, source.passed as f64)?;
+        sheet.write_number(row, 3, source.failed as f64)?;
+    }
+    Ok(())
+}
+
+/// Create a valid sheet name from a domain that is not in the set of existing lower case names
+fn sheet_name(domain: &str, existing: &HashSet<String>) -> String {
+    let base: String = domain
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' | '\'' => '_',
+            c => c,
+        })
+        .take(MAX_SHEET_NAME)
+        .collect();
+    let base = if base.is_empty() {
+        String::from("unknown")
+    } else {
+        base
+    };
+    let mut name = base.clone();
+    let mut counter = 2;
+    while existing.contains(&name.to_lowercase()) {
+        let suffix = format!("~{counter}");
+        let prefix: String = base.chars().take(MAX_SHEET_NAME - suffix.len()).collect();
+        name = format!("{prefix}{suffix}");
+        counter += 1;
+    }
+    name
+}
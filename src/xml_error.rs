@@ -1,8 +1,71 @@
-use serde::Serialize;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 pub struct XmlError {
     pub mail_uid: u32,
     pub error: String,
     pub xml: String,
+
+    #[serde(default)]
+    pub kind: XmlErrorKind,
+
+    /// SHA256 hash of the XML file, also used as name in the archive
+    #[serde(default)]
+    pub hash: String,
+
+    /// File name of the mail attachment containing the XML file
+    #[serde(default)]
+    pub attachment_name: Option<String>,
+
+    /// Relative archive path of the original attachment
+    #[serde(default)]
+    pub attachment_path: Option<String>,
+}
+
+/// Reason why an XML file could not be parsed as DMARC report
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum XmlErrorKind {
+    /// The file is not valid UTF-8
+    Encoding,
+    /// The file is not well-formed XML
+    Syntax,
+    /// The file is XML, but not a DMARC aggregate report
+    NotDmarc,
+    /// The file looks like a DMARC report, but does not match the expected schema
+    Schema,
+    /// Errors from older versions without classification
+    #[default]
+    Unknown,
+}
+
+impl XmlErrorKind {
+    /// Classifies a file that already failed to parse as DMARC report
+    pub fn classify(data: &[u8]) -> Self {
+        let Ok(text) = std::str::from_utf8(data) else {
+            return Self::Encoding;
+        };
+        let mut reader = Reader::from_str(text);
+        let mut root = None;
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(start)) | Ok(Event::Empty(start)) => {
+                    if root.is_none() {
+                        root = Some(start.local_name().as_ref() == b"feedback");
+                    }
+                }
+                Ok(Event::Eof) => break,
+                Ok(..) => {}
+                Err(..) => return Self::Syntax,
+            }
+        }
+        match root {
+            Some(true) => Self::Schema,
+            Some(false) => Self::NotDmarc,
+            None => Self::Syntax,
+        }
+    }
 }
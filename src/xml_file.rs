@@ -2,4 +2,10 @@ pub struct XmlFile {
     pub mail_uid: u32,
     pub data: Vec<u8>,
     pub hash: String,
+
+    /// File name of the mail attachment containing the XML file
+    pub attachment_name: Option<String>,
+
+    /// Relative archive path of the original attachment
+    pub attachment_path: Option<String>,
 }
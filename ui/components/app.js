@@ -10,12 +10,21 @@ export class App extends LitElement {
         a {
             color: rgb(14, 117, 212);
         }
+
+        .stale {
+            border: 1px solid rgb(230, 160, 0);
+            border-radius: 3px;
+            background-color: rgb(255, 243, 205);
+            padding: 5px 10px;
+        }
     `;
 
     static get properties() {
         return {
             component: { type: String },
             reportId: { type: String },
+            sessionUser: { type: String },
+            status: { type: Object },
         };
     }
 
@@ -23,8 +32,45 @@ export class App extends LitElement {
         super();
         this.component = "dashboard";
         this.reportId = null;
+        this.sessionUser = null;
+        this.status = null;
         window.onhashchange = () => this.onHashChange();
         this.onHashChange();
+
+        // Reload the current view when the server reports new data
+        const events = new EventSource("api/events");
+        events.addEventListener("new_reports", () => this.reload());
+        events.addEventListener("parse_errors", () => this.reload());
+        events.addEventListener("cycle_finished", () => this.updateStatus());
+        this.updateStatus();
+
+        // Offer a logout when logged in with the login form instead of basic auth
+        fetch("api/session")
+            .then((response) => (response.ok ? response.json() : null))
+            .then((session) => (this.sessionUser = session?.user ?? null));
+    }
+
+    // Warn when the shown data is outdated because the last update cycle failed
+    async updateStatus() {
+        const response = await fetch("api/status");
+        this.status = response.ok ? await response.json() : null;
+    }
+
+    formatTime(timestamp) {
+        return new Date(timestamp * 1000).toLocaleString();
+    }
+
+    async logout(event) {
+        event.preventDefault();
+        await fetch("api/logout", { method: "POST" });
+        document.location = "login.html";
+    }
+
+    async reload() {
+        const component = this.component;
+        this.component = null;
+        await this.updateComplete;
+        this.component = component;
     }
 
     async onHashChange() {
@@ -45,7 +91,9 @@ export class App extends LitElement {
 
     render() {
         let component;
-        if (this.component == "reports") {
+        if (this.component == null) {
+            component = html``;
+        } else if (this.component == "reports") {
             component = html`<dmarc-reports></dmarc-reports>`;
         } else if (this.component == "report") {
             component = html`<dmarc-report id="${this.reportId}"></dmarc-report>`;
@@ -62,7 +110,18 @@ export class App extends LitElement {
                 <a href="#/reports">Reports</a> |
                 <a href="#/mails">Mails</a> |
                 <a href="#/problems">Problems</a>
+                ${this.sessionUser
+                    ? html` | <a href="login.html" @click=${this.logout}>Logout ${this.sessionUser}</a>`
+                    : ""}
             </p>
+            ${this.status?.stale
+                ? html`<p class="stale" title="${this.status.last_error}">
+                      Last update failed at ${this.formatTime(this.status.last_error_time)}.
+                      ${this.status.last_success
+                          ? `Showing data of the last successful update at ${this.formatTime(this.status.last_success)}.`
+                          : "Showing previously saved data."}
+                  </p>`
+                : ""}
             ${component}
         `;
     }
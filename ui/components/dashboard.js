@@ -25,6 +25,14 @@ export class Dashboard extends LitElement {
             margin-bottom: 10px;
         }
 
+        .compliance table {
+            margin: auto;
+        }
+
+        .compliance td {
+            padding: 2px 10px;
+        }
+
         .stats span {
             margin-left: 15px;
             margin-right: 15px;
@@ -35,7 +43,11 @@ export class Dashboard extends LitElement {
         mails: { type: Number },
         xmlFiles: { type: Number },
         reports: { type: Number },
+        duplicates: { type: Number },
         lastUpdate: { type: Number },
+        days: { type: String },
+        since: { type: Number },
+        compliance: { type: Array },
     };
 
     constructor() {
@@ -44,17 +56,34 @@ export class Dashboard extends LitElement {
         this.mails = 0;
         this.xmlFiles = 0;
         this.reports = 0;
+        this.duplicates = 0;
         this.lastUpdate = 0;
+        this.days = "";
+        this.since = null;
+        this.compliance = [];
+        this.charts = [];
     }
 
     async firstUpdated() {
-        const response = await fetch("summary");
+        await this.loadSummary();
+    }
+
+    async loadSummary() {
+        // Without days parameter the configured window of the server is used
+        const query = this.days === "" ? "" : "?days=" + this.days;
+        const response = await fetch("summary" + query);
         const summary = await response.json();
 
+        this.charts.forEach(chart => chart.destroy());
+        this.charts = [];
+        this.since = summary.since;
+
         this.mails = summary.mails;
         this.xmlFiles = summary.xml_files;
         this.reports = summary.reports;
+        this.duplicates = summary.duplicates;
         this.lastUpdate = summary.last_update;
+        this.compliance = summary.compliance || [];
 
         this.createPieChart("orgs_chart", summary.orgs);
         this.createPieChart("domains_chart", summary.domains);
@@ -62,19 +91,62 @@ export class Dashboard extends LitElement {
         this.createPieChart("dkim_policy_chart", summary.dkim_policy_results);
         this.createPieChart("spf_auth_chart", summary.spf_auth_results);
         this.createPieChart("dkim_auth_chart", summary.dkim_auth_results);
+        this.createPieChart("envelope_to_chart", summary.envelope_to || {});
+        await this.loadCharts();
+    }
+
+    // Aggregated on the server to avoid downloading all reports
+    async loadCharts() {
+        const query = this.since ? "?since=" + this.since : "";
+        const [daily, dispositions, topIps] = await Promise.all(
+            ["daily", "dispositions", "top-ips"].map(async chart => {
+                const response = await fetch("api/charts/" + chart + query);
+                return await response.json();
+            })
+        );
+        this.createStackedBarChart("daily_chart", daily);
+        this.createChart("disposition_chart", "pie", dispositions);
+        this.createStackedBarChart("top_ips_chart", topIps);
+    }
+
+    createChart(canvasId, type, data, options = {}) {
+        const element = this.renderRoot.querySelector("." + canvasId);
+        this.charts.push(new Chart(element, { type, data, options }));
+    }
+
+    createStackedBarChart(canvasId, data) {
+        const colors = { Passed: "#4caf50", Failed: "#e53935" };
+        data.datasets.forEach(d => d.backgroundColor = colors[d.label]);
+        this.createChart(canvasId, "bar", data, {
+            scales: { x: { stacked: true }, y: { stacked: true } }
+        });
     }
 
     async createPieChart(canvasId, dataMap) {
         const element = this.renderRoot.querySelector("." + canvasId);
         const labels = Object.keys(dataMap);
         const data = labels.map(k => dataMap[k]);
-        new Chart(element, {
+        this.charts.push(new Chart(element, {
             type: "pie",
             data: {
                 labels,
                 datasets: [{ data }]
             }
-        });
+        }));
+    }
+
+    trendSymbol(trend) {
+        switch (trend) {
+            case "up": return "\u2191";
+            case "down": return "\u2193";
+            case "stable": return "\u2192";
+            default: return "";
+        }
+    }
+
+    changeWindow(event) {
+        this.days = event.target.value;
+        this.loadSummary();
     }
 
     render() {
@@ -83,7 +155,20 @@ export class Dashboard extends LitElement {
                 <span>Mails: <b>${this.mails}</b></span>
                 <span>XML Files: <b>${this.xmlFiles}</b></span>
                 <span>DMARC Reports: <b>${this.reports}</b></span>
+                <span>Duplicates: <b>${this.duplicates}</b></span>
                 <span>Last Update: <b>${new Date(this.lastUpdate * 1000).toLocaleString()}</b></span>
+                <span>
+                    Reports since:
+                    <b>${this.since ? new Date(this.since * 1000).toLocaleDateString() : "Beginning"}</b>
+                    <select @change="${this.changeWindow}">
+                        <option value="" ?selected="${this.days === ""}">Default</option>
+                        <option value="7">Last 7 days</option>
+                        <option value="30">Last 30 days</option>
+                        <option value="90">Last 90 days</option>
+                        <option value="365">Last year</option>
+                        <option value="0">All</option>
+                    </select>
+                </span>
             </div>
 
             <div class="container">
@@ -116,6 +201,39 @@ export class Dashboard extends LitElement {
                     <h2>DKIM Auth Results</h2>
                     <canvas class="dkim_auth_chart"></canvas>
                 </div>
+
+                <div class="module" style="grid-column: 1; grid-row: 3;">
+                    <h2>Envelope To</h2>
+                    <canvas class="envelope_to_chart"></canvas>
+                </div>
+
+                <div class="module" style="grid-column: 1 / 3; grid-row: 4;">
+                    <h2>Daily Messages</h2>
+                    <canvas class="daily_chart"></canvas>
+                </div>
+
+                <div class="module" style="grid-column: 3; grid-row: 4;">
+                    <h2>Dispositions</h2>
+                    <canvas class="disposition_chart"></canvas>
+                </div>
+
+                <div class="module" style="grid-column: 1 / 4; grid-row: 5;">
+                    <h2>Top Source IPs</h2>
+                    <canvas class="top_ips_chart"></canvas>
+                </div>
+
+                <div class="module compliance" style="grid-column: 2 / 4; grid-row: 3;">
+                    <h2>Compliance</h2>
+                    <table>
+                        ${this.compliance.map(c => html`
+                            <tr>
+                                <td>${c.domain}</td>
+                                <td><b>${c.compliance.toFixed(1)}%</b> ${this.trendSymbol(c.trend)}</td>
+                                <td>${c.passed} of ${c.messages} messages</td>
+                            </tr>
+                        `)}
+                    </table>
+                </div>
             </div>
         `;
     }
@@ -1,24 +1,83 @@
 import { LitElement, html, css } from "lit";
 
+const PAGE_SIZE = 100;
+
 export class Mails extends LitElement {
+    static styles = css`
+        .filters {
+            margin-bottom: 10px;
+        }
+
+        .filters > * {
+            margin-right: 10px;
+        }
+    `;
+
     static properties = {
         mails: { type: Array },
+        total: { type: Number },
+        offset: { type: Number },
+        senderDomain: { type: String },
+        hasErrors: { type: String },
     };
 
     constructor() {
         super();
         this.mails = [];
+        this.total = 0;
+        this.offset = 0;
+        this.senderDomain = "";
+        this.hasErrors = "";
         this.updateMails();
     }
 
     async updateMails() {
-        const mailsResponse = await fetch("mails");
-        this.mails = await mailsResponse.json();
-        this.mails.sort((a, b) => b.date - a.date);
+        const params = new URLSearchParams({ offset: this.offset, limit: PAGE_SIZE });
+        if (this.senderDomain) {
+            params.set("sender_domain", this.senderDomain);
+        }
+        if (this.hasErrors) {
+            params.set("has_errors", this.hasErrors);
+        }
+        const mailsResponse = await fetch("api/mails?" + params);
+        const page = await mailsResponse.json();
+        this.mails = page.mails;
+        this.total = page.total;
+    }
+
+    changeSenderDomain(event) {
+        this.senderDomain = event.target.value.trim();
+        this.offset = 0;
+        this.updateMails();
+    }
+
+    changeHasErrors(event) {
+        this.hasErrors = event.target.value;
+        this.offset = 0;
+        this.updateMails();
+    }
+
+    changePage(offset) {
+        this.offset = Math.max(0, offset);
+        this.updateMails();
     }
 
     render() {
-        return html`<dmarc-mail-table .mails="${this.mails}"></dmarc-mail-table>`;
+        const last = Math.min(this.offset + PAGE_SIZE, this.total);
+        return html`
+            <div class="filters">
+                <input placeholder="Sender domain" @change="${this.changeSenderDomain}">
+                <select @change="${this.changeHasErrors}">
+                    <option value="">All mails</option>
+                    <option value="true">With XML errors</option>
+                    <option value="false">Without XML errors</option>
+                </select>
+                <span>${this.total === 0 ? 0 : this.offset + 1}-${last} of ${this.total}</span>
+                <button ?disabled="${this.offset === 0}" @click="${() => this.changePage(this.offset - PAGE_SIZE)}">Previous</button>
+                <button ?disabled="${last >= this.total}" @click="${() => this.changePage(this.offset + PAGE_SIZE)}">Next</button>
+            </div>
+            <dmarc-mail-table .mails="${this.mails}"></dmarc-mail-table>
+        `;
     }
 }
 
@@ -41,6 +41,9 @@ export class MailTable extends LitElement {
                     <th>Date</th>
                     <th>Size</th>
                     <th>Subject</th>
+                    <th>Attachments</th>
+                    <th>Reports</th>
+                    <th>Result</th>
                 </tr>
                 ${this.mails.map((mail) =>
                     html`<tr>
@@ -49,6 +52,9 @@ export class MailTable extends LitElement {
                         <td>${new Date(mail.date * 1000).toLocaleString()}</td>
                         <td>${mail.size}</td>
                         <td>${mail.subject.length < 90 ? mail.subject : mail.subject.substring(0, 90) + "..."}</td>
+                        <td>${mail.attachments ?? ""}</td>
+                        <td>${mail.reports ?? ""}</td>
+                        <td>${mail.result ?? ""}</td>
                     </tr>`
                 )}
             </table>
@@ -16,17 +16,33 @@ export class Problems extends LitElement {
         .problem {
             margin-bottom: 50px;
         }
+
+        th {
+            text-align: left;
+            background-color: #efefef;
+        }
+
+        td, th {
+            padding-left: 10px;
+            padding-right: 10px;
+        }
+
+        .failed {
+            color: rgb(200, 30, 30);
+        }
     `;
 
     static properties = {
         xmlErrors: { type: Array },
         oversizedMails: { type: Array },
+        cycles: { type: Array },
     };
 
     constructor() {
         super();
         this.xmlErrors = [];
         this.oversizedMails = [];
+        this.cycles = [];
         this.updateProblems();
     }
 
@@ -36,10 +52,44 @@ export class Problems extends LitElement {
         const mailsResponse = await fetch("mails");
         const mails = await mailsResponse.json();
         this.oversizedMails = mails.filter((m) => m.oversized);
+        const historyResponse = await fetch("api/status/history");
+        this.cycles = (await historyResponse.json()).reverse();
+    }
+
+    delta(value) {
+        return value > 0 ? `+${value}` : `${value}`;
     }
 
     render() {
         return html`
+            <h1>Update Cycles</h1>
+            ${this.cycles.length == 0 ?
+                html`<p class="problem">No finished update cycles yet.</p>` :
+                html`<div class="problem"><table>
+                    <tr>
+                        <th>Cycle</th>
+                        <th>Started</th>
+                        <th>Duration</th>
+                        <th>Fetch</th>
+                        <th>Extract</th>
+                        <th>Parse</th>
+                        <th>Mails</th>
+                        <th>Reports</th>
+                        <th>Errors</th>
+                    </tr>
+                    ${this.cycles.map((c) => html`<tr class="${c.error ? "failed" : ""}" title="${c.error ?? ""}">
+                        <td>${c.cycle_id}</td>
+                        <td>${new Date(c.started * 1000).toLocaleString()}</td>
+                        <td>${c.duration_ms} ms</td>
+                        <td>${c.fetch_ms} ms</td>
+                        <td>${c.extract_ms} ms</td>
+                        <td>${c.parse_ms} ms</td>
+                        <td>${c.mails} (${this.delta(c.mails_delta)})</td>
+                        <td>${c.reports} (${this.delta(c.reports_delta)})</td>
+                        <td>${c.extract_errors + c.parse_errors}${c.error ? " (failed)" : ""}</td>
+                    </tr>`)}
+                </table></div>`}
+
             <h1>Oversized Mails</h1>
             ${this.oversizedMails.length == 0 ?
                 html`<p class="problem">No oversized mails found.</p>` :
@@ -50,7 +100,11 @@ export class Problems extends LitElement {
             ${this.xmlErrors.map((e) =>
             html`
                 <div class="problem">
-                    ${e.error}
+                    <b>${e.kind}</b>: ${e.error}
+                    ${e.attachment_name ? html`<br>Attachment: ${e.attachment_name}` : html``}
+                    ${e.hash ? html`<br>Download:
+                        <a href="api/xml-errors/${e.hash}/xml">XML</a>
+                        ${e.attachment_path ? html`| <a href="api/xml-errors/${e.hash}/attachment">Attachment</a>` : html``}` : html``}
                     <pre>${e.xml}</pre>
                 </div>`
             )}
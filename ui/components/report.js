@@ -135,6 +135,27 @@ export class Report extends LitElement {
                     <th>Errors</th>
                     <td>${this.renderOptional(errors)}</td>
                 </tr>
+                <tr>
+                    <th>Original XML</th>
+                    <td>${this.report.xml_hash ?
+                        html`<a href="api/reports/${encodeURIComponent(this.id)}/xml">Download</a>` :
+                        html`<span class="na">n/a</span>`}</td>
+                </tr>
+                <tr>
+                    <th>Source Mail</th>
+                    <td>${this.report.source?.subject ?
+                        html`${this.report.source.subject} from ${this.report.source.sender}
+                            (${new Date(this.report.source.date * 1000).toLocaleString()})` :
+                        html`<span class="na">n/a</span>`}</td>
+                </tr>
+                <tr>
+                    <th>Message-ID</th>
+                    <td>${this.renderOptional(this.report.source?.message_id)}</td>
+                </tr>
+                <tr>
+                    <th>Attachment</th>
+                    <td>${this.renderOptional(this.report.source?.attachment_name)}</td>
+                </tr>
                 <tr>
                     <th>Version</th>
                     <td>${this.renderOptional(this.report.version)}</td>
@@ -179,7 +200,10 @@ export class Report extends LitElement {
                     </tr>
                     <tr>
                         <th>Source IP</th>
-                        <td>${record.row.source_ip}</td>
+                        <td>
+                            ${record.row.source_ip}
+                            ${record.unexpected ? html`<span class="result negative" title="Source is not on the allowlist of the domain">unexpected</span>` : null}
+                        </td>
                     </tr>
                     <tr>
                         <th>Count</th>
@@ -258,6 +282,40 @@ export class Report extends LitElement {
                             <td>${this.renderOptional(result.human_result)}</td>
                         </tr>
                     `)}
+                    ${(record.auth_results.arc ?
+                        record.auth_results.arc : []).map((result) => html`
+                        <tr>
+                            <th colspan="2">ARC Auth Result</th>
+                        </tr>
+                        <tr>
+                            <th>Domain</th>
+                            <td>${this.renderOptional(result.domain)}</td>
+                        </tr>
+                        <tr>
+                            <th>Selector</th>
+                            <td>${this.renderOptional(result.selector)}</td>
+                        </tr>
+                        <tr>
+                            <th>Result</th>
+                            <td>${this.renderResultBadge(result.result)}</td>
+                        </tr>
+                    `)}
+                    ${(record.auth_results.other ?
+                        record.auth_results.other : []).map((result) => html`
+                        <tr>
+                            <th colspan="2">${result.method} Auth Result</th>
+                        </tr>
+                        ${Object.entries(result.details).filter(([name]) => name !== "result").map(([name, value]) => html`
+                            <tr>
+                                <th>${name}</th>
+                                <td>${value}</td>
+                            </tr>
+                        `)}
+                        <tr>
+                            <th>Result</th>
+                            <td>${this.renderResultBadge(result.result)}</td>
+                        </tr>
+                    `)}
                 `)}
             </table>
         `;
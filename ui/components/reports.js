@@ -38,28 +38,33 @@ export class Reports extends LitElement {
     }
 
     async updateReports() {
-        const response = await fetch("reports");
+        const response = await fetch("reports?sort=date&order=desc");
         this.reports = await response.json();
-        this.reports.sort((a, b) => b.date_begin - a.date_begin);
     }
 
     render() {
         return html`
+            <p>
+                <a href="api/export/csv">Download all records as CSV</a> |
+                <a href="api/export/xlsx">Download domain statistics as Excel workbook</a>
+            </p>
             <table>
                 <tr>
                     <th>ID</th>
                     <th>Organization</th>
                     <th>Domain</th>
                     <th>Records</th>
+                    <th>Failures</th>
                     <th>Begin</th>
                     <th>End</th>
                 </tr>
                 ${this.reports.map((report) =>
                     html`<tr>
-                        <td><a href="#/reports/${report.id}">${report.id}</a></td>
+                        <td><a href="#/reports/${report.stable_id}">${report.id}</a></td>
                         <td>${report.org}</td>
                         <td>${report.domain}</td>
                         <td>${report.records}</td>
+                        <td>${report.failures}</td>
                         <td>${new Date(report.date_begin * 1000).toLocaleString()}</td>
                         <td>${new Date(report.date_end * 1000).toLocaleString()}</td>
                     </tr>`